@@ -82,3 +82,305 @@ pub fn entry_point(_attr: TokenStream, mut item: TokenStream) -> TokenStream {
     item.extend(entry);
     item
 }
+
+/// Derives `cosmwasm_schema::QueryResponses` for a query message enum and generates a
+/// `{Name}MockHandler` to answer its queries in unit tests of other contracts.
+///
+/// Every variant needs a `#[returns(T)]` attribute naming the type of its response.
+/// Variant names are converted to snake case, matching `#[serde(rename_all = "snake_case")]`.
+/// ```ignore
+/// #[derive(Serialize, Deserialize, JsonSchema, QueryResponses)]
+/// #[serde(rename_all = "snake_case")]
+/// pub enum QueryMsg {
+///     #[returns(CountResponse)]
+///     GetCount {},
+///     #[returns(OwnerResponse)]
+///     Owner { at: Option<u64> },
+/// }
+///
+/// let handler = QueryMsg::mock_handler()
+///     .on_get_count(|| Ok(CountResponse { count: 7 }))
+///     .on_owner(|at| Ok(OwnerResponse { owner: format!("owner at {:?}", at) }));
+/// deps.querier.update_wasm(handler.into_wasm_handler("counter"));
+/// ```
+///
+/// The handler of a variant gets its fields in declaration order and returns the
+/// response. Queries of variants without a handler fail with
+/// `SystemError::UnsupportedRequest`, messages that do not deserialize with
+/// `SystemError::InvalidRequest`. The mock handler is not generated for `wasm32`.
+#[proc_macro_derive(QueryResponses, attributes(returns))]
+pub fn query_responses(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    match query_responses_impl(&input) {
+        Ok(code) => TokenStream::from_str(&code).unwrap(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct QueryVariant {
+    ident: String,
+    /// The variant name as it appears in JSON
+    name: String,
+    response: String,
+    /// The field names of struct variants, `None` for tuple and unit variants
+    field_names: Option<Vec<String>>,
+    field_types: Vec<String>,
+}
+
+impl QueryVariant {
+    /// A pattern binding the fields to `field0`, `field1`, … for `msg`
+    fn pattern(&self, msg: &str) -> String {
+        let bindings = (0..self.field_types.len()).map(|i| format!("field{}", i));
+        match &self.field_names {
+            Some(names) => {
+                let fields: Vec<String> = names
+                    .iter()
+                    .zip(bindings)
+                    .map(|(name, binding)| format!("{}: {}", name, binding))
+                    .collect();
+                format!("{}::{} {{ {} }}", msg, self.ident, fields.join(", "))
+            }
+            None if self.field_types.is_empty() => format!("{}::{}", msg, self.ident),
+            None => format!(
+                "{}::{}({})",
+                msg,
+                self.ident,
+                bindings.collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    fn handler_type(&self) -> String {
+        format!(
+            "dyn Fn({}) -> cosmwasm_std::StdResult<{}>",
+            self.field_types.join(", "),
+            self.response
+        )
+    }
+}
+
+fn query_responses_impl(input: &syn::DeriveInput) -> syn::Result<String> {
+    let data = match &input.data {
+        syn::Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "QueryResponses can only be derived for enums",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "QueryResponses cannot be derived for generic enums",
+        ));
+    }
+
+    let variants = data
+        .variants
+        .iter()
+        .map(query_variant)
+        .collect::<syn::Result<Vec<_>>>()?;
+    let msg = input.ident.to_string();
+    let handler = format!("{}MockHandler", msg);
+
+    let schemas: String = variants
+        .iter()
+        .map(|v| {
+            format!(
+                "(\"{}\", ::cosmwasm_schema::schema_for!({})),",
+                v.name, v.response
+            )
+        })
+        .collect();
+    let fields: String = variants
+        .iter()
+        .map(|v| format!("on_{}: Option<Box<{}>>,", v.name, v.handler_type()))
+        .collect();
+    let defaults: String = variants
+        .iter()
+        .map(|v| format!("on_{}: None,", v.name))
+        .collect();
+    let setters: String = variants
+        .iter()
+        .map(|v| {
+            format!(
+                r##"
+                /// Answers `{msg}::{ident}` queries with `handler`
+                pub fn on_{name}(mut self, handler: impl Fn({args}) -> cosmwasm_std::StdResult<{response}> + 'static) -> Self {{
+                    self.on_{name} = Some(Box::new(handler));
+                    self
+                }}
+                "##,
+                msg = msg,
+                ident = v.ident,
+                name = v.name,
+                args = v.field_types.join(", "),
+                response = v.response,
+            )
+        })
+        .collect();
+    let arms: String = variants
+        .iter()
+        .map(|v| {
+            let args: Vec<String> = (0..v.field_types.len())
+                .map(|i| format!("field{}", i))
+                .collect();
+            format!(
+                r##"
+                {pattern} => match &self.on_{name} {{
+                    Some(handler) => handler({args}).and_then(|response| cosmwasm_std::to_binary(&response)),
+                    None => return cosmwasm_std::SystemResult::Err(cosmwasm_std::SystemError::UnsupportedRequest {{
+                        kind: "{msg}::{ident}".to_string(),
+                    }}),
+                }},
+                "##,
+                pattern = v.pattern(&msg),
+                name = v.name,
+                args = args.join(", "),
+                msg = msg,
+                ident = v.ident,
+            )
+        })
+        .collect();
+
+    Ok(format!(
+        r##"
+        impl ::cosmwasm_schema::QueryResponses for {msg} {{
+            fn response_schemas() -> Vec<(&'static str, ::cosmwasm_schema::RootSchema)> {{
+                vec![{schemas}]
+            }}
+        }}
+
+        #[cfg(not(target_arch = "wasm32"))]
+        impl {msg} {{
+            /// Creates a handler answering queries of this contract in tests of other contracts
+            pub fn mock_handler() -> {handler} {{
+                {handler}::default()
+            }}
+        }}
+
+        /// Answers `{msg}` queries with the handlers registered for its variants,
+        /// see `{msg}::mock_handler`.
+        #[cfg(not(target_arch = "wasm32"))]
+        #[allow(clippy::type_complexity)]
+        pub struct {handler} {{
+            {fields}
+        }}
+
+        #[cfg(not(target_arch = "wasm32"))]
+        impl Default for {handler} {{
+            fn default() -> Self {{
+                {handler} {{ {defaults} }}
+            }}
+        }}
+
+        #[cfg(not(target_arch = "wasm32"))]
+        impl {handler} {{
+            {setters}
+
+            /// Answers a raw `{msg}` with the handler of its variant
+            pub fn handle(&self, msg: &[u8]) -> cosmwasm_std::QuerierResult {{
+                let parsed: {msg} = match cosmwasm_std::from_slice(msg) {{
+                    Ok(parsed) => parsed,
+                    Err(err) => {{
+                        return cosmwasm_std::SystemResult::Err(cosmwasm_std::SystemError::InvalidRequest {{
+                            error: err.to_string(),
+                            request: msg.into(),
+                        }})
+                    }}
+                }};
+                let response = match parsed {{
+                    {arms}
+                }};
+                cosmwasm_std::SystemResult::Ok(response.into())
+            }}
+
+            /// Turns this into a handler for `MockQuerier::update_wasm`, which answers
+            /// smart queries of the contract at `contract_addr`.
+            pub fn into_wasm_handler(
+                self,
+                contract_addr: impl Into<String>,
+            ) -> impl Fn(&cosmwasm_std::WasmQuery) -> cosmwasm_std::QuerierResult {{
+                let contract_addr = contract_addr.into();
+                move |request| match request {{
+                    cosmwasm_std::WasmQuery::Smart {{ contract_addr: addr, msg }} if *addr == contract_addr => {{
+                        self.handle(msg)
+                    }}
+                    cosmwasm_std::WasmQuery::Smart {{ contract_addr: addr, .. }}
+                    | cosmwasm_std::WasmQuery::Raw {{ contract_addr: addr, .. }}
+                    | cosmwasm_std::WasmQuery::ContractInfo {{ contract_addr: addr, .. }} => {{
+                        cosmwasm_std::SystemResult::Err(cosmwasm_std::SystemError::NoSuchContract {{
+                            addr: addr.clone(),
+                        }})
+                    }}
+                    _ => cosmwasm_std::SystemResult::Err(cosmwasm_std::SystemError::UnsupportedRequest {{
+                        kind: "wasm".to_string(),
+                    }}),
+                }}
+            }}
+        }}
+    "##,
+        msg = msg,
+        handler = handler,
+        schemas = schemas,
+        fields = fields,
+        defaults = defaults,
+        setters = setters,
+        arms = arms,
+    ))
+}
+
+fn query_variant(variant: &syn::Variant) -> syn::Result<QueryVariant> {
+    let returns = variant
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("returns"))
+        .ok_or_else(|| {
+            syn::Error::new_spanned(
+                variant,
+                "Missing #[returns(...)] attribute naming the response type",
+            )
+        })?;
+    let response: syn::Type = returns.parse_args()?;
+
+    let field_names = match &variant.fields {
+        syn::Fields::Named(fields) => Some(
+            fields
+                .named
+                .iter()
+                .map(|field| field.ident.as_ref().unwrap().to_string())
+                .collect(),
+        ),
+        _ => None,
+    };
+    let field_types = variant
+        .fields
+        .iter()
+        .map(|field| type_to_string(&field.ty))
+        .collect();
+
+    let ident = variant.ident.to_string();
+    Ok(QueryVariant {
+        name: to_snake_case(&ident),
+        ident,
+        response: type_to_string(&response),
+        field_names,
+        field_types,
+    })
+}
+
+fn type_to_string(ty: &syn::Type) -> String {
+    quote::ToTokens::to_token_stream(ty).to_string()
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (index, ch) in name.char_indices() {
+        if index != 0 && ch.is_uppercase() {
+            out.push('_');
+        }
+        out.push(ch.to_ascii_lowercase());
+    }
+    out
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use crate::clock::Clock;
+use crate::code_blocks::{BlockId, BlockStore};
+use crate::measure::Measurements;
+use crate::operators::OperatorSymbol;
+
+/// Summary statistics of a set of costs, in the unit of the clock they were measured with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// The 95th percentile using the nearest-rank method, i.e. the smallest sample
+    /// that is at least as large as 95% of all samples
+    pub p95: u128,
+    /// The population standard deviation
+    pub stddev: f64,
+    pub min: u128,
+    pub max: u128,
+}
+
+impl Summary {
+    /// Returns `None` if there are no samples.
+    pub fn from_samples(samples: impl IntoIterator<Item = u128>) -> Option<Self> {
+        let mut samples: Vec<u128> = samples.into_iter().collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+
+        let count = samples.len();
+        let mean = samples.iter().map(|s| *s as f64).sum::<f64>() / count as f64;
+        let median = if count % 2 == 1 {
+            samples[count / 2] as f64
+        } else {
+            (samples[count / 2 - 1] as f64 + samples[count / 2] as f64) / 2.0
+        };
+        // The rank is ceil(0.95 * count), counting from 1
+        let p95_index = (count * 95 - 1) / 100;
+        let variance = samples
+            .iter()
+            .map(|s| (*s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / count as f64;
+
+        Some(Summary {
+            count,
+            mean,
+            median,
+            p95: samples[p95_index],
+            stddev: variance.sqrt(),
+            min: samples[0],
+            max: samples[count - 1],
+        })
+    }
+}
+
+/// The cost the instrumentation adds to every measurement, in the unit of the clock it
+/// was measured with, see [`calibrate`](crate::calibration::calibrate).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Overhead {
+    pub per_measurement: u128,
+}
+
+impl Overhead {
+    /// The cost of a measurement without the overhead. Measurements cheaper than the
+    /// overhead cost 0.
+    pub fn subtract_from(&self, cost: u128) -> u128 {
+        cost.saturating_sub(self.per_measurement)
+    }
+}
+
+/// The statistics of one measured block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockStats {
+    /// The function index the block was first measured at
+    pub fn_index: u32,
+    /// The local block id the block was first measured at
+    pub local_block_id: u32,
+    pub block_id: BlockId,
+    pub summary: Summary,
+}
+
+/// The summary of every measured block, sorted by location.
+///
+/// Blocks with the same code share a `BlockId` and are aggregated, even if they
+/// are part of different functions. They are listed under the first location
+/// they were measured at.
+pub fn block_stats<C: Clock>(measurements: &Measurements<C>) -> Vec<BlockStats> {
+    block_stats_corrected(measurements, Overhead::default())
+}
+
+/// Like [`block_stats`], with `overhead` subtracted from every measurement.
+pub fn block_stats_corrected<C: Clock>(
+    measurements: &Measurements<C>,
+    overhead: Overhead,
+) -> Vec<BlockStats> {
+    let mut stats: Vec<BlockStats> = measurements
+        .taken
+        .iter()
+        .filter_map(|(block_id, timings)| {
+            let summary = Summary::from_samples(
+                timings
+                    .iter()
+                    .map(|t| overhead.subtract_from(C::to_units(*t))),
+            )?;
+            let (fn_index, local_block_id) = measurements.block_locations[block_id];
+            Some(BlockStats {
+                fn_index,
+                local_block_id,
+                block_id: *block_id,
+                summary,
+            })
+        })
+        .collect();
+    stats.sort_by_key(|stats| (stats.fn_index, stats.local_block_id, stats.block_id));
+    stats
+}
+
+/// The cost of all executions of one kind of operator.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OperatorStats {
+    /// How often the operator was executed
+    pub executions: u64,
+    /// The cost attributed to the operator. The cost of every block execution is
+    /// split evenly between its operators.
+    pub cost: f64,
+}
+
+impl OperatorStats {
+    /// The average cost attributed to one execution of the operator.
+    pub fn mean(&self) -> f64 {
+        if self.executions == 0 {
+            0.0
+        } else {
+            self.cost / self.executions as f64
+        }
+    }
+}
+
+/// Rolls the costs of all measured blocks up per operator.
+///
+/// Blocks that are not in `block_store` are skipped.
+pub fn operator_stats<C: Clock>(
+    measurements: &Measurements<C>,
+    block_store: &BlockStore,
+) -> HashMap<OperatorSymbol, OperatorStats> {
+    operator_stats_corrected(measurements, block_store, Overhead::default())
+}
+
+/// Like [`operator_stats`], with `overhead` subtracted from every measurement before
+/// it is split between the operators of the block.
+pub fn operator_stats_corrected<C: Clock>(
+    measurements: &Measurements<C>,
+    block_store: &BlockStore,
+    overhead: Overhead,
+) -> HashMap<OperatorSymbol, OperatorStats> {
+    let mut stats: HashMap<OperatorSymbol, OperatorStats> = HashMap::new();
+    for (block_id, timings) in &measurements.taken {
+        let operators = match block_store.get_block(*block_id) {
+            Some(block) if !block.operators().is_empty() => block.operators(),
+            _ => continue,
+        };
+        let total: u128 = timings
+            .iter()
+            .map(|t| overhead.subtract_from(C::to_units(*t)))
+            .sum();
+        let cost_per_operator = total as f64 / operators.len() as f64;
+        for operator in operators {
+            let entry = stats.entry(*operator).or_default();
+            entry.executions += timings.len() as u64;
+            entry.cost += cost_per_operator;
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::CodeBlock;
+
+    #[test]
+    fn summary_from_samples_works() {
+        assert_eq!(Summary::from_samples(Vec::new()), None);
+
+        let summary = Summary::from_samples(vec![2, 4, 4, 4, 5, 5, 7, 9]).unwrap();
+        assert_eq!(summary.count, 8);
+        assert_eq!(summary.mean, 5.0);
+        assert_eq!(summary.median, 4.5);
+        assert_eq!(summary.p95, 9);
+        assert_eq!(summary.stddev, 2.0);
+        assert_eq!((summary.min, summary.max), (2, 9));
+
+        let summary = Summary::from_samples(1..=100).unwrap();
+        assert_eq!(summary.median, 50.5);
+        assert_eq!(summary.p95, 95);
+
+        let summary = Summary::from_samples(vec![3]).unwrap();
+        assert_eq!((summary.median, summary.p95, summary.stddev), (3.0, 3, 0.0));
+    }
+
+    #[test]
+    fn block_stats_works() {
+        let measurements = Measurements::from_samples(&[
+            (BlockId(1), (2, 0), &[10, 20]),
+            (BlockId(2), (0, 3), &[5]),
+        ]);
+        let stats = block_stats(&measurements);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            (
+                stats[0].fn_index,
+                stats[0].local_block_id,
+                stats[0].block_id
+            ),
+            (0, 3, BlockId(2))
+        );
+        assert_eq!(stats[0].summary.mean, 5.0);
+        assert_eq!(stats[1].summary.mean, 15.0);
+        assert_eq!(stats[1].summary.count, 2);
+    }
+
+    #[test]
+    fn operator_stats_works() {
+        let mut block_store = BlockStore::new();
+        let first = block_store
+            .register_block(CodeBlock::from(vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Add,
+                OperatorSymbol::LocalGet,
+            ]))
+            .unwrap();
+        let second = block_store
+            .register_block(CodeBlock::from(vec![OperatorSymbol::I32Add]))
+            .unwrap();
+        let measurements = Measurements::from_samples(&[
+            (first, (0, 0), &[8, 12]),
+            (second, (0, 1), &[3]),
+            (BlockId(77), (0, 2), &[1000]),
+        ]);
+
+        let stats = operator_stats(&measurements, &block_store);
+        assert_eq!(stats.len(), 3);
+        assert_eq!(
+            stats[&OperatorSymbol::LocalGet],
+            OperatorStats {
+                executions: 4,
+                cost: 10.0,
+            }
+        );
+        assert_eq!(stats[&OperatorSymbol::I32Add].executions, 3);
+        assert_eq!(stats[&OperatorSymbol::I32Add].cost, 8.0);
+        assert_eq!(stats[&OperatorSymbol::I32Const].mean(), 2.5);
+    }
+
+    #[test]
+    fn overhead_is_subtracted() {
+        let overhead = Overhead { per_measurement: 6 };
+        assert_eq!(overhead.subtract_from(10), 4);
+        assert_eq!(overhead.subtract_from(3), 0);
+
+        let block_measurements = Measurements::from_samples(&[(BlockId(1), (0, 0), &[10, 20, 4])]);
+        let stats = block_stats_corrected(&block_measurements, overhead);
+        assert_eq!(stats[0].summary.mean, 6.0);
+        assert_eq!((stats[0].summary.min, stats[0].summary.max), (0, 14));
+        assert_eq!(
+            block_stats_corrected(&block_measurements, Overhead::default()),
+            block_stats(&block_measurements)
+        );
+
+        let mut block_store = BlockStore::new();
+        let block = block_store
+            .register_block(CodeBlock::from(vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Add,
+            ]))
+            .unwrap();
+        let block_measurements = Measurements::from_samples(&[(block, (0, 0), &[10, 20, 4])]);
+        let stats = operator_stats_corrected(&block_measurements, &block_store, overhead);
+        // (10 - 6) + (20 - 6) + 0, split between two operators
+        assert_eq!(stats[&OperatorSymbol::LocalGet].cost, 9.0);
+        assert_eq!(stats[&OperatorSymbol::I32Add].executions, 3);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::code_blocks::BlockId;
+use crate::measure::Measurements;
+
+/// A sign that the clock misbehaved while a block was measured, which makes its
+/// costs unreliable. Found by [`detect_clock_anomalies`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClockAnomaly {
+    /// The clock went backwards before `count` executions of the block started,
+    /// see [`Measurements::clock_regressions`].
+    NonMonotonic { count: u64 },
+    /// The median cost of the block changed between the first and the second half
+    /// of its executions, as happens when the CPU frequency changes mid-run.
+    FrequencyShift { before: u64, after: u64 },
+    /// The costs of the block form two separate clusters. `low` and `high` are the
+    /// mean costs of the clusters, `high_percent` is the share of executions in the
+    /// expensive one.
+    Bimodal {
+        low: u64,
+        high: u64,
+        high_percent: u8,
+    },
+}
+
+impl fmt::Display for ClockAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockAnomaly::NonMonotonic { count } => {
+                write!(f, "clock went backwards {} times", count)
+            }
+            ClockAnomaly::FrequencyShift { before, after } => {
+                write!(f, "median cost shifted from {} to {}", before, after)
+            }
+            ClockAnomaly::Bimodal {
+                low,
+                high,
+                high_percent,
+            } => write!(
+                f,
+                "costs are bimodal around {} and {} ({}% of executions)",
+                low, high, high_percent
+            ),
+        }
+    }
+}
+
+/// When [`detect_clock_anomalies`] considers the costs of a block suspicious.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyThresholds {
+    /// Blocks executed less often are only checked for non-monotonic readings
+    pub min_samples: usize,
+    /// The minimum factor between two levels of cost, both for frequency shifts
+    /// and the clusters of bimodal costs
+    pub min_ratio: f64,
+    /// How many standard deviations within the clusters the cluster means have to be
+    /// apart for the costs to be bimodal
+    pub min_separation: f64,
+    /// The minimum share of executions in the smaller cluster, between 0 and 0.5
+    pub min_cluster_share: f64,
+}
+
+impl Default for AnomalyThresholds {
+    fn default() -> Self {
+        AnomalyThresholds {
+            min_samples: 30,
+            min_ratio: 1.5,
+            min_separation: 3.0,
+            min_cluster_share: 0.1,
+        }
+    }
+}
+
+/// Checks the measurements of every block for signs of clock issues.
+///
+/// Frequency shifts explain bimodal costs, so blocks with a frequency shift are not
+/// checked for bimodality. Blocks without anomalies are not part of the result.
+pub fn detect_clock_anomalies<C: Clock>(
+    measurements: &Measurements<C>,
+    thresholds: &AnomalyThresholds,
+) -> BTreeMap<BlockId, Vec<ClockAnomaly>> {
+    let mut anomalies: BTreeMap<BlockId, Vec<ClockAnomaly>> = BTreeMap::new();
+    for (block_id, count) in &measurements.clock_regressions {
+        anomalies
+            .entry(*block_id)
+            .or_default()
+            .push(ClockAnomaly::NonMonotonic { count: *count });
+    }
+    for (block_id, timings) in &measurements.taken {
+        if timings.len() < thresholds.min_samples {
+            continue;
+        }
+        let costs: Vec<u128> = timings.iter().map(|t| C::to_units(*t)).collect();
+        let anomaly =
+            frequency_shift(&costs, thresholds).or_else(|| bimodality(&costs, thresholds));
+        if let Some(anomaly) = anomaly {
+            anomalies.entry(*block_id).or_default().push(anomaly);
+        }
+    }
+    anomalies
+}
+
+/// Compares the median of the first and the second half of `costs`, which are in
+/// the order they were measured.
+fn frequency_shift(costs: &[u128], thresholds: &AnomalyThresholds) -> Option<ClockAnomaly> {
+    let (first, second) = costs.split_at(costs.len() / 2);
+    let (before, after) = (median(first), median(second));
+    let (low, high) = if before < after {
+        (before, after)
+    } else {
+        (after, before)
+    };
+    if high > 0 && high as f64 >= low as f64 * thresholds.min_ratio {
+        Some(ClockAnomaly::FrequencyShift { before, after })
+    } else {
+        None
+    }
+}
+
+fn median(costs: &[u128]) -> u64 {
+    let mut costs = costs.to_vec();
+    costs.sort_unstable();
+    costs[costs.len() / 2] as u64
+}
+
+/// Splits the sorted costs where the variance within the two parts is smallest and
+/// checks whether the parts are far enough apart.
+fn bimodality(costs: &[u128], thresholds: &AnomalyThresholds) -> Option<ClockAnomaly> {
+    let mut sorted: Vec<f64> = costs.iter().map(|cost| *cost as f64).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let count = sorted.len();
+    let min_size = ((count as f64 * thresholds.min_cluster_share).ceil() as usize).max(1);
+    if count < 2 * min_size {
+        return None;
+    }
+
+    // Prefix sums of the costs and their squares
+    let mut sums = vec![(0.0, 0.0); count + 1];
+    for (index, cost) in sorted.iter().enumerate() {
+        let (sum, squares) = sums[index];
+        sums[index + 1] = (sum + cost, squares + cost * cost);
+    }
+    let (total, total_squares) = sums[count];
+    // The sum of squared deviations from the mean of `len` costs
+    let deviations = |sum: f64, squares: f64, len: usize| squares - sum * sum / len as f64;
+
+    let (split, within) = (min_size..=count - min_size)
+        .map(|split| {
+            let (sum, squares) = sums[split];
+            let within = deviations(sum, squares, split)
+                + deviations(total - sum, total_squares - squares, count - split);
+            (split, within)
+        })
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())?;
+
+    let low = sums[split].0 / split as f64;
+    let high = (total - sums[split].0) / (count - split) as f64;
+    let stddev = (within.max(0.0) / count as f64).sqrt();
+    if high >= low * thresholds.min_ratio && high - low >= thresholds.min_separation * stddev {
+        Some(ClockAnomaly::Bimodal {
+            low: low.round() as u64,
+            high: high.round() as u64,
+            high_percent: ((count - split) * 100 / count) as u8,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Slightly noisy costs around `level`
+    fn noisy(level: u64, count: usize) -> Vec<u64> {
+        (0..count).map(|i| level + (i % 5) as u64).collect()
+    }
+
+    #[test]
+    fn detect_clock_anomalies_ignores_stable_costs() {
+        let measurements = Measurements::from_samples(&[(BlockId(1), (0, 0), &noisy(100, 100))]);
+        let anomalies = detect_clock_anomalies(&measurements, &AnomalyThresholds::default());
+        assert!(anomalies.is_empty());
+    }
+
+    #[test]
+    fn detect_clock_anomalies_finds_frequency_shifts() {
+        let costs = [noisy(100, 50), noisy(200, 50)].concat();
+        let measurements = Measurements::from_samples(&[(BlockId(1), (0, 0), &costs)]);
+        let anomalies = detect_clock_anomalies(&measurements, &AnomalyThresholds::default());
+        assert_eq!(
+            anomalies[&BlockId(1)],
+            [ClockAnomaly::FrequencyShift {
+                before: 102,
+                after: 202
+            }]
+        );
+    }
+
+    #[test]
+    fn detect_clock_anomalies_finds_bimodal_costs() {
+        // Every fourth execution is slow
+        let costs: Vec<u64> = (0..100)
+            .map(|i| if i % 4 == 0 { 400 + i % 3 } else { 100 + i % 3 })
+            .collect();
+        let measurements = Measurements::from_samples(&[(BlockId(1), (0, 0), &costs)]);
+        let anomalies = detect_clock_anomalies(&measurements, &AnomalyThresholds::default());
+        match anomalies[&BlockId(1)].as_slice() {
+            [ClockAnomaly::Bimodal {
+                low,
+                high,
+                high_percent,
+            }] => {
+                assert_eq!((*low, *high, *high_percent), (101, 401, 25));
+            }
+            other => panic!("expected bimodal costs, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detect_clock_anomalies_ignores_rare_outliers_and_few_samples() {
+        let mut costs = noisy(100, 100);
+        costs[50] = 10_000;
+        let measurements = Measurements::from_samples(&[
+            (BlockId(1), (0, 0), &costs),
+            (BlockId(2), (0, 0), &[100, 100, 900, 900]),
+        ]);
+        let anomalies = detect_clock_anomalies(&measurements, &AnomalyThresholds::default());
+        assert!(anomalies.is_empty());
+    }
+
+    #[test]
+    fn detect_clock_anomalies_reports_non_monotonic_readings() {
+        let mut measurements = Measurements::from_samples(&[(BlockId(1), (0, 0), &[100])]);
+        measurements.clock_regressions.insert(BlockId(1), 2);
+        let anomalies = detect_clock_anomalies(&measurements, &AnomalyThresholds::default());
+        assert_eq!(
+            anomalies[&BlockId(1)],
+            [ClockAnomaly::NonMonotonic { count: 2 }]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use thiserror::Error;
+use wasmer::{Instance, Val};
+
+use crate::analysis::Summary;
+use crate::clock::Clock;
+use crate::code_blocks::BlockId;
+use crate::measure::Measurements;
+
+/// How often [`bench`] calls the export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Calls before measuring, to warm up caches and allocations. Their measurements
+    /// are discarded.
+    pub warmup: usize,
+    /// Measured calls
+    pub iterations: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            warmup: 10,
+            iterations: 100,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BenchError {
+    #[error("The instance does not export a function {name}")]
+    UnknownExport { name: String },
+    /// `iteration` counts all calls from 0, starting with the warmup.
+    #[error("Calling {name} failed in iteration {iteration}: {msg}")]
+    CallErr {
+        name: String,
+        iteration: usize,
+        msg: String,
+    },
+}
+
+/// The costs of one block over all measured iterations of a benchmark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDistribution {
+    /// The function index the block was first measured at
+    pub fn_index: u32,
+    /// The local block id the block was first measured at
+    pub local_block_id: u32,
+    /// The number of executions in all measured iterations
+    pub executions: u64,
+    /// The cost of every timed execution in the order they happened, in the unit of
+    /// the clock. Fewer than `executions` with
+    /// [`Measurements::with_early_termination`].
+    pub samples: Vec<u128>,
+}
+
+impl BlockDistribution {
+    pub fn summary(&self) -> Summary {
+        Summary::from_samples(self.samples.iter().copied())
+            .expect("every measured block has a sample")
+    }
+}
+
+/// The result of [`bench`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchResult {
+    /// The unit of all costs, see [`Clock::UNIT`]
+    pub unit: &'static str,
+    pub iterations: usize,
+    pub blocks: BTreeMap<BlockId, BlockDistribution>,
+}
+
+impl BenchResult {
+    /// How often the block is executed by one call on average
+    pub fn executions_per_iteration(&self, block_id: BlockId) -> f64 {
+        match self.blocks.get(&block_id) {
+            Some(block) if self.iterations > 0 => block.executions as f64 / self.iterations as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Calls `export` of an instrumented `instance` with `args`, first `options.warmup`
+/// times and then `options.iterations` times while measuring, and returns the
+/// distribution of the costs of every block. Single measurements are too noisy to
+/// calibrate costs with.
+///
+/// `measurements` must be the ones the profiling imports of `instance` record into,
+/// see [`add_measuring_imports`](crate::instrumentation::add_measuring_imports).
+/// Everything measured before is discarded, and afterwards they contain the measured
+/// iterations. Every call is ended with [`Measurements::end_invocation`].
+///
+/// The remaining points of a metering middleware are not reset between calls, so
+/// instantiate with a limit that suffices for all of them.
+pub fn bench<C: Clock>(
+    instance: &Instance,
+    measurements: &Arc<Mutex<Measurements<C>>>,
+    export: &str,
+    args: &[Val],
+    options: BenchOptions,
+) -> Result<BenchResult, BenchError> {
+    let function =
+        instance
+            .exports
+            .get_function(export)
+            .map_err(|_| BenchError::UnknownExport {
+                name: export.to_string(),
+            })?;
+    let call = |iteration: usize| -> Result<(), BenchError> {
+        let result = function.call(args);
+        measurements.lock().unwrap().end_invocation();
+        result
+            .map(|_| ())
+            .map_err(|err| call_err(export, iteration, err))
+    };
+
+    for iteration in 0..options.warmup {
+        call(iteration)?;
+    }
+    measurements.lock().unwrap().clear();
+    for iteration in options.warmup..options.warmup + options.iterations {
+        call(iteration)?;
+    }
+
+    let measurements = measurements.lock().unwrap();
+    let blocks = measurements
+        .taken
+        .iter()
+        .map(|(block_id, timings)| {
+            let (fn_index, local_block_id) = measurements.block_locations[block_id];
+            let distribution = BlockDistribution {
+                fn_index,
+                local_block_id,
+                executions: measurements.executions_of(*block_id),
+                samples: timings.iter().map(|t| C::to_units(*t)).collect(),
+            };
+            (*block_id, distribution)
+        })
+        .collect();
+    Ok(BenchResult {
+        unit: C::UNIT,
+        iterations: options.iterations,
+        blocks,
+    })
+}
+
+fn call_err(export: &str, iteration: usize, err: impl ToString) -> BenchError {
+    BenchError::CallErr {
+        name: export.to_string(),
+        iteration,
+        msg: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::{wat2wasm, Exports, ImportObject, ModuleMiddleware};
+
+    use crate::code_blocks::BlockStore;
+    use crate::instrumentation::{
+        add_measuring_imports, instrument_wasm, Granularity, Profiling, GAS_LIMIT,
+    };
+
+    /// Sums up the numbers from 1 to the argument
+    const WAT: &str = r#"(module
+        (func (export "sum") (param i32) (result i32) (local i32)
+            (block
+                (loop
+                    local.get 0
+                    i32.eqz
+                    br_if 1
+                    local.get 1
+                    local.get 0
+                    i32.add
+                    local.set 1
+                    local.get 0
+                    i32.const 1
+                    i32.sub
+                    local.set 0
+                    br 0))
+            local.get 1))"#;
+
+    fn instance() -> (Instance, Arc<Mutex<Measurements>>) {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store, Granularity::BasicBlock));
+        let wasm = instrument_wasm(&wat2wasm(WAT.as_bytes()).unwrap(), &profiling).unwrap();
+        let middleware: Arc<dyn ModuleMiddleware> = profiling.clone();
+        let module = cosmwasm_vm::internals::compile(&wasm, None, &[middleware]).unwrap();
+
+        let measurements = Arc::new(Mutex::new(Measurements::new()));
+        let mut exports = Exports::new();
+        add_measuring_imports(
+            &profiling,
+            module.store(),
+            measurements.clone(),
+            &mut exports,
+        );
+        let mut imports = ImportObject::new();
+        imports.register(profiling.import_module(), exports);
+        let instance = Instance::new(&module, &imports).unwrap();
+        wasmer_middlewares::metering::set_remaining_points(&instance, GAS_LIMIT);
+        (instance, measurements)
+    }
+
+    #[test]
+    fn bench_works() {
+        let (instance, measurements) = instance();
+        let options = BenchOptions {
+            warmup: 3,
+            iterations: 5,
+        };
+        let result = bench(&instance, &measurements, "sum", &[Val::I32(4)], options).unwrap();
+
+        assert_eq!(result.unit, "ns");
+        assert_eq!(result.iterations, 5);
+        // Per call, the loop condition is checked 5 times, the loop body runs 4 times
+        // and the function returns once
+        let executions: Vec<u64> = result.blocks.values().map(|b| b.executions).collect();
+        assert!(executions.contains(&25));
+        assert!(executions.contains(&20));
+        assert!(executions.contains(&5));
+        for (block_id, block) in &result.blocks {
+            assert_eq!(block.samples.len() as u64, block.executions);
+            assert_eq!(block.summary().count, block.samples.len());
+            assert_eq!(
+                result.executions_per_iteration(*block_id),
+                block.executions as f64 / 5.0
+            );
+        }
+        assert_eq!(result.executions_per_iteration(BlockId(0)), 0.0);
+
+        // Only the measured iterations are kept
+        let measurements = measurements.lock().unwrap();
+        let taken: usize = measurements.taken.values().map(|t| t.len()).sum();
+        let total: u64 = result.blocks.values().map(|b| b.executions).sum();
+        assert_eq!(taken as u64, total);
+    }
+
+    #[test]
+    fn bench_fails_for_bad_calls() {
+        let (instance, measurements) = instance();
+        let options = BenchOptions::default();
+        match bench(&instance, &measurements, "product", &[], options).unwrap_err() {
+            BenchError::UnknownExport { name } => assert_eq!(name, "product"),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        match bench(&instance, &measurements, "sum", &[], options).unwrap_err() {
+            BenchError::CallErr {
+                name, iteration, ..
+            } => {
+                assert_eq!(name, "sum");
+                assert_eq!(iteration, 0);
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::{Arc, Mutex};
+
+use cosmwasm_std::{to_vec, Coin};
+use cosmwasm_vm::{
+    call_execute_raw, call_instantiate_raw, call_migrate_raw, call_query_raw, call_sudo_raw,
+    testing::{mock_env, mock_info, MockApi, MockQuerier, MockStorage},
+    Instance, VmResult,
+};
+
+use cosmwasm_profiler::{
+    callgraph::CallGraph,
+    clock::{Clock, WallClock},
+    code_blocks::BlockStore,
+    floats::FloatReport,
+    instrumentation::{FunctionFilter, Granularity, Module, Profiling},
+    measure::Measurements,
+    symbols::Symbols,
+};
+
+type MockInstance = Instance<MockApi, MockStorage, MockQuerier>;
+
+/// Usage: `cosmwasm-profile run <contract.wasm> --entry instantiate|execute|migrate|sudo|query
+///   [--msg <path>] [--instantiate-msg <path>] [--sender <address>] [--funds <coins>]
+///   [--granularity function|block] [--iterations <n>]`
+///
+/// Instruments the contract, calls the entry point with the JSON message stored at
+/// `--msg` (`{}` by default) and writes the cost of every function to stdout, most
+/// expensive first. The result of every call is written to stderr.
+///
+/// Only the functions that can be called from the entry point are instrumented. All calls
+/// use the mock environment and backend of `cosmwasm-vm`, measured with the wall clock. `--instantiate-msg` instantiates the contract before profiling, which most
+/// entry points need to find their state. `--sender` and `--funds` (e.g. `100ucosm,5uatom`)
+/// form the message info of `instantiate` and `execute`. `--iterations` calls the entry point
+/// several times, all of which are part of the report.
+///
+/// Usage: `cosmwasm-profile floats <contract.wasm>`
+///
+/// Writes every basic block using floating-point operators to stdout and fails if there
+/// are any, see `FloatReport`. The contract is not executed.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let command = args.get(1).map(String::as_str);
+    let wasm_path = match command {
+        Some("run") | Some("floats") => args.get(2).cloned().unwrap_or_else(|| {
+            eprintln!("Missing contract, expected: cosmwasm-profile run|floats <contract.wasm>");
+            std::process::exit(2);
+        }),
+        _ => {
+            eprintln!("Unsupported command, expected: cosmwasm-profile run|floats <contract.wasm>");
+            std::process::exit(2);
+        }
+    };
+    if command == Some("floats") {
+        report_floats(&read_file(&wasm_path));
+        return;
+    }
+    let entry = match arg_value(&args, "--entry") {
+        Some(entry) => Entry::from_name(entry).unwrap_or_else(|| {
+            eprintln!("Unsupported entry point: {}", entry);
+            std::process::exit(2);
+        }),
+        None => {
+            eprintln!("Missing --entry");
+            std::process::exit(2);
+        }
+    };
+    let granularity = match arg_value(&args, "--granularity").unwrap_or("function") {
+        "block" => Granularity::BasicBlock,
+        "function" => Granularity::Function,
+        other => {
+            eprintln!("Unsupported granularity: {}", other);
+            std::process::exit(2);
+        }
+    };
+    let options = Options {
+        entry,
+        msg: arg_value(&args, "--msg")
+            .map(read_file)
+            .unwrap_or_else(|| b"{}".to_vec()),
+        instantiate_msg: arg_value(&args, "--instantiate-msg").map(read_file),
+        sender: arg_value(&args, "--sender")
+            .unwrap_or("creator")
+            .to_string(),
+        funds: arg_value(&args, "--funds").map(funds).unwrap_or_default(),
+        granularity,
+        iterations: arg_value(&args, "--iterations").map_or(1, |value| {
+            value.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid number for --iterations: {}", value);
+                std::process::exit(2);
+            })
+        }),
+    };
+
+    run(&read_file(&wasm_path), &options);
+}
+
+fn report_floats(wasm: &[u8]) {
+    let report = FloatReport::from_wasm(wasm).unwrap_or_else(|err| {
+        eprintln!("Cannot read the contract: {}", err);
+        std::process::exit(2);
+    });
+    let symbols = Symbols::from_wasm(wasm).unwrap_or_default();
+    report.write_csv(&symbols, std::io::stdout());
+    if !report.is_empty() {
+        eprintln!(
+            "Found floating-point operators in {} blocks",
+            report.blocks.len()
+        );
+        std::process::exit(1);
+    }
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|arg| arg == name)?;
+    Some(args.get(pos + 1).map(String::as_str).unwrap_or_else(|| {
+        eprintln!("Missing value for {}", name);
+        std::process::exit(2);
+    }))
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|err| {
+        eprintln!("Cannot read {}: {}", path, err);
+        std::process::exit(2);
+    })
+}
+
+/// Parses coins like `100ucosm,5uatom`
+fn funds(coins: &str) -> Vec<Coin> {
+    coins
+        .split(',')
+        .map(|coin| {
+            let split = coin.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+            let (amount, denom) = coin.split_at(split);
+            match amount.parse::<u128>() {
+                Ok(amount) => Coin::new(amount, denom),
+                Err(_) => {
+                    eprintln!("Invalid funds, expected e.g. 100ucosm: {}", coin);
+                    std::process::exit(2);
+                }
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    Instantiate,
+    Execute,
+    Migrate,
+    Sudo,
+    Query,
+}
+
+impl Entry {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "instantiate" => Some(Entry::Instantiate),
+            "execute" => Some(Entry::Execute),
+            "migrate" => Some(Entry::Migrate),
+            "sudo" => Some(Entry::Sudo),
+            "query" => Some(Entry::Query),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Entry::Instantiate => "instantiate",
+            Entry::Execute => "execute",
+            Entry::Migrate => "migrate",
+            Entry::Sudo => "sudo",
+            Entry::Query => "query",
+        }
+    }
+
+    fn call(
+        &self,
+        instance: &mut MockInstance,
+        options: &Options,
+        msg: &[u8],
+    ) -> VmResult<Vec<u8>> {
+        let env = to_vec(&mock_env()).unwrap();
+        let info = to_vec(&mock_info(&options.sender, &options.funds)).unwrap();
+        match self {
+            Entry::Instantiate => call_instantiate_raw(instance, &env, &info, msg),
+            Entry::Execute => call_execute_raw(instance, &env, &info, msg),
+            Entry::Migrate => call_migrate_raw(instance, &env, msg),
+            Entry::Sudo => call_sudo_raw(instance, &env, msg),
+            Entry::Query => call_query_raw(instance, &env, msg),
+        }
+    }
+}
+
+struct Options {
+    entry: Entry,
+    msg: Vec<u8>,
+    instantiate_msg: Option<Vec<u8>>,
+    sender: String,
+    funds: Vec<Coin>,
+    granularity: Granularity,
+    iterations: u32,
+}
+
+/// Calls `entry` and writes the result to stderr. Exits if the VM fails, errors
+/// returned by the contract are part of the result.
+fn call_and_print(instance: &mut MockInstance, entry: Entry, options: &Options, msg: &[u8]) {
+    match entry.call(instance, options, msg) {
+        Ok(result) => eprintln!("{:?}: {}", entry, String::from_utf8_lossy(&result)),
+        Err(err) => {
+            eprintln!("{:?} failed: {}", entry, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(wasm: &[u8], options: &Options) {
+    let measurements = Arc::new(Mutex::new(Measurements::new().with_event_recording()));
+    let block_store = Arc::new(Mutex::new(BlockStore::new()));
+    let profiling = Profiling::new(block_store, options.granularity).with_filter(
+        FunctionFilter::ReachableFrom(vec![options.entry.name().to_string()]),
+    );
+    let mut instance =
+        Module::from_bytes(wasm).instrument_measuring(Arc::new(profiling), measurements.clone());
+
+    if let Some(msg) = &options.instantiate_msg {
+        call_and_print(instance.vm_instance(), Entry::Instantiate, options, msg);
+        measurements.lock().unwrap().clear();
+    }
+
+    for _ in 0..options.iterations {
+        call_and_print(instance.vm_instance(), options.entry, options, &options.msg);
+        measurements.lock().unwrap().end_invocation();
+    }
+
+    let measurements = measurements.lock().unwrap();
+    let events = measurements.events.as_deref().unwrap_or_default();
+    CallGraph::from_events(events, options.granularity).write_csv(
+        WallClock::UNIT,
+        instance.symbols(),
+        &measurements.metadata,
+        std::io::stdout(),
+    );
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::code_blocks::BlockStore;
+use crate::instrumentation::{prepare_module, InstrumentationError, PreparedModule, Profiling};
+
+/// Stores Wasm instrumented by [`instrument_wasm`](crate::instrumentation::instrument_wasm)
+/// in a directory, so that repeated profiling runs of an unchanged contract skip
+/// parsing and rewriting the Wasm, which dominates the startup time for large contracts.
+///
+/// Entries are keyed by the SHA-256 checksum of the original Wasm, the options of the
+/// `Profiling` that change the instrumented Wasm and the version of this crate. Every
+/// entry consists of `{key}.wasm`, `{key}.json` with the sizes of the functions and,
+/// once saved with [`InstrumentationCache::save_blocks`], `{key}.blocks.json`.
+/// Unreadable entries are instrumented and written again.
+#[derive(Debug, Clone)]
+pub struct InstrumentationCache {
+    dir: PathBuf,
+}
+
+impl InstrumentationCache {
+    /// Uses `dir` for the cache, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, InstrumentationError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(cache_err)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The key of the entry of `wasm` instrumented for `profiling`, as a hex string.
+    pub fn key(&self, wasm: &[u8], profiling: &Profiling) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(Sha256::digest(wasm));
+        hasher.update(profiling.preparation_key().as_bytes());
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hex(&hasher.finalize())
+    }
+
+    /// Like [`instrument_wasm`](crate::instrumentation::instrument_wasm), but reuses the
+    /// instrumented Wasm of an earlier call for the same Wasm and configuration.
+    pub fn instrument_wasm(
+        &self,
+        wasm: &[u8],
+        profiling: &Profiling,
+    ) -> Result<Vec<u8>, InstrumentationError> {
+        let key = self.key(wasm, profiling);
+        let prepared = match self.load(&key)? {
+            Some(prepared) => prepared,
+            None => {
+                let prepared = prepare_module(wasm, profiling)?;
+                fs::write(
+                    self.path(&key, "json"),
+                    serde_json::to_vec(&prepared).unwrap(),
+                )
+                .map_err(cache_err)?;
+                fs::write(self.path(&key, "wasm"), &prepared.wasm).map_err(cache_err)?;
+                prepared
+            }
+        };
+        profiling.expect_module(&prepared);
+        Ok(prepared.wasm)
+    }
+
+    /// Whether instrumenting `wasm` for `profiling` is served from the cache.
+    pub fn contains(&self, wasm: &[u8], profiling: &Profiling) -> bool {
+        let key = self.key(wasm, profiling);
+        self.path(&key, "json").exists() && self.path(&key, "wasm").exists()
+    }
+
+    /// Stores the blocks registered when compiling the instrumented `wasm` with the
+    /// entry, e.g. the `BlockStore` of `profiling` right after compilation. Later runs
+    /// get them from [`InstrumentationCache::load_blocks`], even before compiling.
+    pub fn save_blocks(
+        &self,
+        wasm: &[u8],
+        profiling: &Profiling,
+        block_store: &BlockStore,
+    ) -> Result<(), InstrumentationError> {
+        let path = self.path(&self.key(wasm, profiling), "blocks.json");
+        block_store.save(path).map_err(cache_err)
+    }
+
+    /// The blocks stored with [`InstrumentationCache::save_blocks`], if any. Merge them
+    /// into a store with [`BlockStore::merge`].
+    pub fn load_blocks(
+        &self,
+        wasm: &[u8],
+        profiling: &Profiling,
+    ) -> Result<Option<BlockStore>, InstrumentationError> {
+        let path = self.path(&self.key(wasm, profiling), "blocks.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        BlockStore::load(path).map(Some).map_err(cache_err)
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, extension))
+    }
+
+    fn load(&self, key: &str) -> Result<Option<PreparedModule>, InstrumentationError> {
+        let (json, wasm) = match (
+            read_if_exists(&self.path(key, "json"))?,
+            read_if_exists(&self.path(key, "wasm"))?,
+        ) {
+            (Some(json), Some(wasm)) => (json, wasm),
+            _ => return Ok(None),
+        };
+        Ok(serde_json::from_slice::<PreparedModule>(&json)
+            .ok()
+            .filter(|_| wasmer::wasmparser::validate(&wasm).is_ok())
+            .map(|mut prepared| {
+                prepared.wasm = wasm;
+                prepared
+            }))
+    }
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>, InstrumentationError> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(cache_err(err)),
+    }
+}
+
+fn cache_err(err: impl ToString) -> InstrumentationError {
+    InstrumentationError::CacheErr {
+        msg: err.to_string(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::instrumentation::{instrument_wasm, FunctionFilter, Granularity};
+
+    static HACKATOM: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "instrumentation_cache_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn profiling() -> Profiling {
+        Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::Function,
+        )
+    }
+
+    #[test]
+    fn instrument_wasm_reuses_entries() {
+        let dir = cache_dir("reuse");
+        let cache = InstrumentationCache::new(&dir).unwrap();
+        let profiling = profiling();
+
+        assert!(!cache.contains(HACKATOM, &profiling));
+        let instrumented = cache.instrument_wasm(HACKATOM, &profiling).unwrap();
+        assert_eq!(instrumented, instrument_wasm(HACKATOM, &profiling).unwrap());
+        assert!(cache.contains(HACKATOM, &profiling));
+
+        // Served from the cache, even by a new cache on the same directory
+        let cache = InstrumentationCache::new(&dir).unwrap();
+        let key = cache.key(HACKATOM, &profiling);
+        fs::write(cache.path(&key, "wasm"), HACKATOM).unwrap();
+        assert_eq!(
+            cache.instrument_wasm(HACKATOM, &profiling).unwrap(),
+            HACKATOM
+        );
+
+        // Broken entries are written again
+        fs::write(cache.path(&key, "json"), b"{").unwrap();
+        assert_eq!(
+            cache.instrument_wasm(HACKATOM, &profiling).unwrap(),
+            instrumented
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn key_depends_on_wasm_and_configuration() {
+        let dir = cache_dir("key");
+        let cache = InstrumentationCache::new(&dir).unwrap();
+        let profiling = profiling();
+
+        let key = cache.key(HACKATOM, &profiling);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache.key(HACKATOM, &self::profiling()));
+        assert_ne!(key, cache.key(&HACKATOM[1..], &profiling));
+        assert_ne!(
+            key,
+            cache.key(HACKATOM, &self::profiling().with_loop_counting())
+        );
+        assert_ne!(
+            key,
+            cache.key(
+                HACKATOM,
+                &self::profiling()
+                    .with_filter(FunctionFilter::ReachableFrom(vec!["execute".to_string()]))
+            )
+        );
+        // Options only affecting compilation share the entry
+        assert_eq!(
+            key,
+            cache.key(HACKATOM, &self::profiling().with_gas_tracking())
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_and_load_blocks_work() {
+        let dir = cache_dir("blocks");
+        let cache = InstrumentationCache::new(&dir).unwrap();
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::Function));
+
+        assert!(cache.load_blocks(HACKATOM, &profiling).unwrap().is_none());
+        let wasm = cache.instrument_wasm(HACKATOM, &profiling).unwrap();
+        cosmwasm_vm::internals::compile(
+            &wasm,
+            None,
+            &[profiling.clone() as Arc<dyn wasmer::ModuleMiddleware>],
+        )
+        .unwrap();
+        cache
+            .save_blocks(HACKATOM, &profiling, &block_store.lock().unwrap())
+            .unwrap();
+
+        let loaded = cache.load_blocks(HACKATOM, &profiling).unwrap().unwrap();
+        let store = block_store.lock().unwrap();
+        assert_eq!(loaded.len(), store.len());
+        let mut locations: Vec<_> = loaded.locations().collect();
+        locations.sort_unstable();
+        let mut expected: Vec<_> = store.locations().collect();
+        expected.sort_unstable();
+        assert_eq!(locations, expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
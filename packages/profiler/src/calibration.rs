@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+
+use wasmer::{imports, Function, Instance, ModuleMiddleware};
+
+use crate::analysis::{Overhead, Summary};
+use crate::clock::Clock;
+use crate::code_blocks::BlockStore;
+use crate::instrumentation::{
+    instrument_wasm, start_measurement, take_measurement, Granularity, MeasurementsEnv, Profiling,
+    GAS_LIMIT, START_MEASUREMENT, TAKE_MEASUREMENT,
+};
+use crate::measure::Measurements;
+
+/// A function with a single block that does next to nothing. A block of just `nop`
+/// would not survive instrumentation, `nop`s are removed.
+const CALIBRATION_WAT: &[u8] = br#"
+(module
+  (func (export "calibrate")
+    i32.const 0
+    drop))
+"#;
+
+/// Measures the cost the instrumentation adds to every measurement on this machine.
+///
+/// The injected `start_measurement` and `take_measurement` calls are part of what gets
+/// measured: the host calls themselves, the transitions between Wasm and the host and the
+/// bookkeeping of [`Measurements`]. This instruments a block that does next to nothing,
+/// executes it `samples` times and returns the median cost. Subtract it from measured
+/// costs using [`block_stats_corrected`](crate::analysis::block_stats_corrected) or
+/// [`operator_stats_corrected`](crate::analysis::operator_stats_corrected).
+///
+/// The overhead depends on the clock, the machine and its load, so calibrate with the
+/// clock used for profiling, right before profiling.
+///
+/// Panics if `samples` is 0.
+pub fn calibrate<C: Clock>(clock: C, samples: usize) -> Overhead {
+    assert!(samples > 0, "calibrate: samples must not be 0");
+
+    let block_store = Arc::new(Mutex::new(BlockStore::new()));
+    let profiling = Arc::new(Profiling::new(block_store, Granularity::BasicBlock));
+    let wasm = wasmer::wat2wasm(CALIBRATION_WAT).unwrap();
+    let wasm = instrument_wasm(&wasm, &profiling).unwrap();
+    let middleware: Arc<dyn ModuleMiddleware> = profiling.clone();
+    let module = cosmwasm_vm::internals::compile(&wasm, None, &[middleware]).unwrap();
+
+    let measurements: MeasurementsEnv<C> = Arc::new(Mutex::new(Measurements::with_clock(clock)));
+    let store = module.store();
+    let import_object = imports! {
+        profiling.import_module() => {
+            START_MEASUREMENT => Function::new_native_with_env(store, measurements.clone(), start_measurement::<C>),
+            TAKE_MEASUREMENT => Function::new_native_with_env(store, measurements.clone(), take_measurement::<C>),
+        }
+    };
+    let instance = Instance::new(&module, &import_object).unwrap();
+    wasmer_middlewares::metering::set_remaining_points(&instance, GAS_LIMIT);
+    let calibrate = instance.exports.get_function("calibrate").unwrap();
+
+    // Warm up caches and the allocations of the measurement queues
+    for _ in 0..samples.min(100) {
+        calibrate.call(&[]).unwrap();
+    }
+    measurements.lock().unwrap().clear();
+    for _ in 0..samples {
+        calibrate.call(&[]).unwrap();
+    }
+
+    let measurements = measurements.lock().unwrap();
+    let costs = measurements
+        .taken
+        .values()
+        .flatten()
+        .map(|elapsed| C::to_units(*elapsed));
+    let summary = Summary::from_samples(costs).expect("calibration block was not measured");
+    Overhead {
+        per_measurement: summary.median.round() as u128,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::clock::Clock;
+
+    /// Every reading advances the time by one unit
+    #[derive(Debug, Default, Clone)]
+    struct TickingClock {
+        ticks: Arc<Mutex<u64>>,
+    }
+
+    impl Clock for TickingClock {
+        type Reading = u64;
+        type Elapsed = Duration;
+
+        const UNIT: &'static str = "ticks";
+
+        fn now(&self) -> u64 {
+            let mut ticks = self.ticks.lock().unwrap();
+            *ticks += 1;
+            *ticks
+        }
+
+        fn elapsed(&self, start: u64) -> Duration {
+            Duration::from_nanos(self.now() - start)
+        }
+
+        fn to_units(elapsed: Duration) -> u128 {
+            elapsed.as_nanos()
+        }
+    }
+
+    #[test]
+    fn calibrate_measures_empty_block() {
+        // One reading when the block starts and one when it ends
+        let overhead = calibrate(TickingClock::default(), 10);
+        assert_eq!(overhead.per_measurement, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "samples must not be 0")]
+    fn calibrate_panics_for_no_samples() {
+        calibrate(TickingClock::default(), 0);
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+
+use crate::instrumentation::Granularity;
+use crate::measure::{MeasurementEvent, Metadata};
+use crate::symbols::Symbols;
+use crate::utils::{with_metadata_keys, with_metadata_values};
+
+/// The aggregated cost of a function over all of its invocations.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    /// Cost including all callees, in the unit of the clock used for measuring.
+    /// Recursive invocations are only counted once.
+    pub inclusive: u128,
+    /// Cost of the function's own code.
+    pub exclusive: u128,
+    /// Cost of the calls to imported host functions made by the function itself.
+    /// This is part of `inclusive`, but not of `exclusive`.
+    pub host: u128,
+}
+
+/// The aggregated cost of the calls from one function to another.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CallStats {
+    pub count: u64,
+    /// The number of calls made through a table with `call_indirect`, part of `count`.
+    /// Only known with
+    /// [`Profiling::with_indirect_call_tracking`](crate::instrumentation::Profiling::with_indirect_call_tracking).
+    pub indirect: u64,
+    /// Cost of the callee including its own callees.
+    pub inclusive: u128,
+}
+
+/// A weighted call graph reconstructed from the ordered events recorded by
+/// [`Measurements`](crate::measure::Measurements).
+///
+/// Functions are identified by their local function index.
+///
+/// With [`Granularity::Function`], the measurements of a function enclose the
+/// ones of its callees, so the graph is exact. With [`Granularity::BasicBlock`],
+/// calls are inferred from the function the next block belongs to: a block of a
+/// function that is already on the call stack is considered a return to it,
+/// any other function a call. Direct recursion cannot be told apart from a
+/// function continuing to execute and is not part of the graph in this case.
+///
+/// A call recorded by [`MeasurementEvent::IndirectCall`] is attributed to the next
+/// function that is entered, which is the callee unless the callee is not instrumented.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CallGraph {
+    pub functions: BTreeMap<u32, FunctionStats>,
+    /// Keyed by caller and callee
+    pub calls: BTreeMap<(u32, u32), CallStats>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    fn_index: u32,
+    exclusive: u128,
+    children: u128,
+    host: u128,
+}
+
+impl CallGraph {
+    pub fn from_events(events: &[MeasurementEvent], granularity: Granularity) -> Self {
+        let mut graph = CallGraph::default();
+        let mut stack: Vec<Frame> = Vec::new();
+        // Whether the current function just called through a table
+        let mut indirect = false;
+
+        for event in events {
+            let called_indirectly = std::mem::take(&mut indirect);
+            match (*event, granularity) {
+                (MeasurementEvent::Start { fn_index }, Granularity::Function) => {
+                    graph.enter(&mut stack, fn_index, called_indirectly);
+                }
+                (MeasurementEvent::Take { fn_index, cost, .. }, Granularity::Function) => {
+                    // Pop frames that were never finished, e.g. due to a trap.
+                    while stack.len() > 1 && stack.last().unwrap().fn_index != fn_index {
+                        graph.leave(&mut stack, None);
+                    }
+                    if !stack.is_empty() {
+                        graph.leave(&mut stack, Some(cost));
+                    }
+                }
+                (MeasurementEvent::Start { fn_index }, Granularity::BasicBlock) => {
+                    match stack.iter().rposition(|frame| frame.fn_index == fn_index) {
+                        Some(pos) => {
+                            while stack.len() > pos + 1 {
+                                graph.leave(&mut stack, None);
+                            }
+                        }
+                        None => graph.enter(&mut stack, fn_index, called_indirectly),
+                    }
+                }
+                (MeasurementEvent::Take { fn_index, cost, .. }, Granularity::BasicBlock) => {
+                    if stack.last().map(|frame| frame.fn_index) != Some(fn_index) {
+                        graph.enter(&mut stack, fn_index, false);
+                    }
+                    stack.last_mut().unwrap().exclusive += cost;
+                }
+                (MeasurementEvent::HostCall { fn_index, cost, .. }, _) => {
+                    // Host calls of functions whose blocks are not measured are dropped.
+                    if let Some(frame) = stack.last_mut().filter(|f| f.fn_index == fn_index) {
+                        frame.children += cost;
+                        frame.host += cost;
+                    }
+                }
+                (MeasurementEvent::IndirectCall { fn_index, .. }, _) => {
+                    indirect = stack.last().map(|frame| frame.fn_index) == Some(fn_index);
+                }
+                (MeasurementEvent::InvocationEnd, _) => {
+                    while !stack.is_empty() {
+                        graph.leave(&mut stack, None);
+                    }
+                }
+            }
+        }
+        while !stack.is_empty() {
+            graph.leave(&mut stack, None);
+        }
+
+        graph
+    }
+
+    fn enter(&mut self, stack: &mut Vec<Frame>, fn_index: u32, indirect: bool) {
+        if let Some(caller) = stack.last() {
+            let call = self.calls.entry((caller.fn_index, fn_index)).or_default();
+            call.count += 1;
+            if indirect {
+                call.indirect += 1;
+            }
+        }
+        self.functions.entry(fn_index).or_default().calls += 1;
+        stack.push(Frame {
+            fn_index,
+            exclusive: 0,
+            children: 0,
+            host: 0,
+        });
+    }
+
+    /// Pops the top frame. `inclusive` is the measured cost of the whole invocation if
+    /// known. Otherwise it is the sum of the function's own blocks and its callees.
+    fn leave(&mut self, stack: &mut Vec<Frame>, inclusive: Option<u128>) {
+        let frame = stack.pop().unwrap();
+        let (inclusive, exclusive) = match inclusive {
+            Some(inclusive) => (inclusive, inclusive.saturating_sub(frame.children)),
+            None => (frame.exclusive + frame.children, frame.exclusive),
+        };
+
+        let recursive = stack.iter().any(|f| f.fn_index == frame.fn_index);
+        let stats = self.functions.entry(frame.fn_index).or_default();
+        stats.exclusive += exclusive;
+        stats.host += frame.host;
+        if !recursive {
+            stats.inclusive += inclusive;
+        }
+
+        if let Some(caller) = stack.last_mut() {
+            caller.children += inclusive;
+            self.calls
+                .entry((caller.fn_index, frame.fn_index))
+                .or_default()
+                .inclusive += inclusive;
+        }
+    }
+
+    /// Functions that were called from `fn_index`, with the stats of those calls.
+    pub fn callees(&self, fn_index: u32) -> impl Iterator<Item = (u32, &CallStats)> {
+        self.calls
+            .iter()
+            .filter(move |((caller, _), _)| *caller == fn_index)
+            .map(|((_, callee), stats)| (*callee, stats))
+    }
+
+    /// Functions that called `fn_index`, with the stats of those calls.
+    pub fn callers(&self, fn_index: u32) -> impl Iterator<Item = (u32, &CallStats)> {
+        self.calls
+            .iter()
+            .filter(move |((_, callee), _)| *callee == fn_index)
+            .map(|((caller, _), stats)| (*caller, stats))
+    }
+
+    /// Functions that were never called by another instrumented function, i.e.
+    /// the entry points that were executed.
+    pub fn roots(&self) -> impl Iterator<Item = (u32, &FunctionStats)> {
+        self.functions
+            .iter()
+            .filter(move |(fn_index, _)| self.callers(**fn_index).next().is_none())
+            .map(|(fn_index, stats)| (*fn_index, stats))
+    }
+
+    /// Writes the stats of all functions, most expensive (inclusive) first.
+    /// The `metadata` is added as extra columns to every row.
+    pub fn write_csv(
+        &self,
+        unit: &str,
+        symbols: &Symbols,
+        metadata: &Metadata,
+        sink: impl std::io::Write,
+    ) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        let header = [
+            "function".to_string(),
+            "calls".to_string(),
+            format!("inclusive in {}", unit),
+            format!("exclusive in {}", unit),
+            format!("host in {}", unit),
+        ];
+        wtr.write_record(with_metadata_keys(header, metadata))
+            .unwrap();
+
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|(a_index, a), (b_index, b)| {
+            b.inclusive.cmp(&a.inclusive).then(a_index.cmp(b_index))
+        });
+        for (fn_index, stats) in functions {
+            let row = [
+                symbols.describe_function(*fn_index),
+                stats.calls.to_string(),
+                stats.inclusive.to_string(),
+                stats.exclusive.to_string(),
+                stats.host.to_string(),
+            ];
+            wtr.write_record(with_metadata_values(row, metadata))
+                .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::BlockId;
+
+    fn start(fn_index: u32) -> MeasurementEvent {
+        MeasurementEvent::Start { fn_index }
+    }
+
+    fn take(fn_index: u32, cost: u128) -> MeasurementEvent {
+        MeasurementEvent::Take {
+            fn_index,
+            block_id: BlockId(0),
+            cost,
+        }
+    }
+
+    #[test]
+    fn basic_block_events_are_reconstructed() {
+        // 0 calls 1, which calls 2 twice, then 0 calls 2
+        let events = [
+            start(0),
+            take(0, 10),
+            start(1),
+            take(1, 5),
+            start(2),
+            take(2, 1),
+            start(1),
+            take(1, 5),
+            start(2),
+            take(2, 1),
+            start(1),
+            take(1, 5),
+            start(0),
+            take(0, 10),
+            start(2),
+            take(2, 1),
+            start(0),
+            take(0, 10),
+        ];
+
+        let graph = CallGraph::from_events(&events, Granularity::BasicBlock);
+        assert_eq!(
+            graph.functions[&0],
+            FunctionStats {
+                calls: 1,
+                inclusive: 48,
+                exclusive: 30,
+                host: 0,
+            }
+        );
+        assert_eq!(
+            graph.functions[&1],
+            FunctionStats {
+                calls: 1,
+                inclusive: 17,
+                exclusive: 15,
+                host: 0,
+            }
+        );
+        assert_eq!(
+            graph.functions[&2],
+            FunctionStats {
+                calls: 3,
+                inclusive: 3,
+                exclusive: 3,
+                host: 0,
+            }
+        );
+        assert_eq!(
+            graph.calls[&(1, 2)],
+            CallStats {
+                count: 2,
+                indirect: 0,
+                inclusive: 2,
+            }
+        );
+        assert_eq!(
+            graph.calls[&(0, 2)],
+            CallStats {
+                count: 1,
+                indirect: 0,
+                inclusive: 1,
+            }
+        );
+
+        let callees: Vec<u32> = graph.callees(0).map(|(callee, _)| callee).collect();
+        assert_eq!(callees, [1, 2]);
+        let callers: Vec<u32> = graph.callers(2).map(|(caller, _)| caller).collect();
+        assert_eq!(callers, [0, 1]);
+        let roots: Vec<u32> = graph.roots().map(|(root, _)| root).collect();
+        assert_eq!(roots, [0]);
+    }
+
+    #[test]
+    fn invocation_end_separates_entry_points() {
+        let events = [
+            start(0),
+            take(0, 10),
+            MeasurementEvent::InvocationEnd,
+            start(1),
+            take(1, 5),
+        ];
+
+        let graph = CallGraph::from_events(&events, Granularity::BasicBlock);
+        assert!(graph.calls.is_empty());
+        let roots: Vec<u32> = graph.roots().map(|(root, _)| root).collect();
+        assert_eq!(roots, [0, 1]);
+    }
+
+    #[test]
+    fn function_events_are_reconstructed() {
+        // 0 calls 1, which calls itself
+        let events = [
+            start(0),
+            start(1),
+            start(1),
+            take(1, 3),
+            take(1, 10),
+            take(0, 15),
+        ];
+
+        let graph = CallGraph::from_events(&events, Granularity::Function);
+        assert_eq!(
+            graph.functions[&0],
+            FunctionStats {
+                calls: 1,
+                inclusive: 15,
+                exclusive: 5,
+                host: 0,
+            }
+        );
+        // The recursive invocation is only part of the inclusive cost once.
+        assert_eq!(
+            graph.functions[&1],
+            FunctionStats {
+                calls: 2,
+                inclusive: 10,
+                exclusive: 10,
+                host: 0,
+            }
+        );
+        assert_eq!(
+            graph.calls[&(1, 1)],
+            CallStats {
+                count: 1,
+                indirect: 0,
+                inclusive: 3,
+            }
+        );
+        assert_eq!(
+            graph.calls[&(0, 1)],
+            CallStats {
+                count: 1,
+                indirect: 0,
+                inclusive: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn host_calls_are_separated() {
+        fn host_call(fn_index: u32, cost: u128) -> MeasurementEvent {
+            MeasurementEvent::HostCall {
+                fn_index,
+                import_index: 0,
+                cost,
+            }
+        }
+
+        let events = [start(0), host_call(0, 4), take(0, 10)];
+        let graph = CallGraph::from_events(&events, Granularity::Function);
+        assert_eq!(
+            graph.functions[&0],
+            FunctionStats {
+                calls: 1,
+                inclusive: 10,
+                exclusive: 6,
+                host: 4,
+            }
+        );
+
+        let events = [start(0), take(0, 3), host_call(0, 4), start(0), take(0, 2)];
+        let graph = CallGraph::from_events(&events, Granularity::BasicBlock);
+        assert_eq!(
+            graph.functions[&0],
+            FunctionStats {
+                calls: 1,
+                inclusive: 9,
+                exclusive: 5,
+                host: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn indirect_calls_are_marked() {
+        let indirect_call = |fn_index| MeasurementEvent::IndirectCall {
+            fn_index,
+            table_index: 0,
+            element_index: 3,
+        };
+        let events = [
+            start(0),
+            take(0, 10),
+            indirect_call(0),
+            start(1),
+            take(1, 5),
+            start(0),
+            take(0, 10),
+            start(1),
+            take(1, 5),
+            start(0),
+            take(0, 10),
+            // The callee is not instrumented
+            indirect_call(0),
+            start(0),
+            take(0, 10),
+        ];
+        let graph = CallGraph::from_events(&events, Granularity::BasicBlock);
+        assert_eq!(
+            graph.calls[&(0, 1)],
+            CallStats {
+                count: 2,
+                indirect: 1,
+                inclusive: 10,
+            }
+        );
+
+        // In function granularity: 0 calls 1 indirectly, then 2 directly
+        let events = [
+            start(0),
+            indirect_call(0),
+            start(1),
+            take(1, 5),
+            start(2),
+            take(2, 1),
+            take(0, 10),
+        ];
+        let graph = CallGraph::from_events(&events, Granularity::Function);
+        assert_eq!(graph.calls[&(0, 1)].indirect, 1);
+        assert_eq!(graph.calls[&(0, 2)].indirect, 0);
+    }
+
+    #[test]
+    fn write_csv_works() {
+        let events = [start(0), start(1), take(1, 3), take(0, 5)];
+        let graph = CallGraph::from_events(&events, Granularity::Function);
+
+        let mut csv = Vec::new();
+        graph.write_csv("ns", &Symbols::default(), &Metadata::new(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,calls,inclusive in ns,exclusive in ns,host in ns\r\nfn 0,1,5,2,0\r\nfn 1,1,3,3,0\r\n"
+        );
+
+        let metadata: Metadata = vec![("msg".to_string(), "transfer".to_string())]
+            .into_iter()
+            .collect();
+        let mut csv = Vec::new();
+        graph.write_csv("ns", &Symbols::default(), &metadata, &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,calls,inclusive in ns,exclusive in ns,host in ns,msg\r\nfn 0,1,5,2,0,transfer\r\nfn 1,1,3,3,0,transfer\r\n"
+        );
+    }
+}
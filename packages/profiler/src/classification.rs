@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::clock::Clock;
+use crate::code_blocks::{BlockId, BlockStore, CodeBlock};
+use crate::instrumentation::Granularity;
+use crate::measure::{MeasurementEvent, Measurements};
+use crate::operators::OperatorCategory;
+
+/// What a [`BlockClassifier`] knows about a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockFeatures<'a> {
+    pub block: &'a CodeBlock,
+    /// The number of operators of the block in every category
+    pub categories: BTreeMap<OperatorCategory, u32>,
+    pub executions: u64,
+    /// The number of calls to host functions during all executions, see
+    /// [`host_calls_per_block`]
+    pub host_calls: u64,
+}
+
+impl<'a> BlockFeatures<'a> {
+    pub fn new(block: &'a CodeBlock, executions: u64, host_calls: u64) -> Self {
+        let mut categories = BTreeMap::new();
+        for (operator, count) in block.operator_counts() {
+            *categories.entry(operator.category()).or_default() += count;
+        }
+        BlockFeatures {
+            block,
+            categories,
+            executions,
+            host_calls,
+        }
+    }
+
+    /// The number of operators of the block in `category`
+    pub fn count(&self, category: OperatorCategory) -> u32 {
+        self.categories.get(&category).copied().unwrap_or_default()
+    }
+}
+
+/// Labels blocks, e.g. to tell whether a contract spends its time computing or
+/// accessing storage. Blocks with the same label are aggregated in reports, see
+/// [`Report::with_classes`](crate::report::Report::with_classes).
+pub trait BlockClassifier {
+    fn classify(&self, features: &BlockFeatures) -> String;
+}
+
+/// Labels every block that calls host functions as `host-bound`, since storage,
+/// queries and crypto are host functions. Other blocks are labeled by the category
+/// with the most operators: `memory-bound`, `compute-bound` or `control-bound`, in
+/// this order of precedence on ties. Blocks only accessing variables are `other`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DominantCategoryClassifier;
+
+impl BlockClassifier for DominantCategoryClassifier {
+    fn classify(&self, features: &BlockFeatures) -> String {
+        if features.host_calls > 0 {
+            return "host-bound".to_string();
+        }
+        let classes = [
+            (OperatorCategory::Memory, "memory-bound"),
+            (OperatorCategory::Arithmetic, "compute-bound"),
+            (OperatorCategory::Control, "control-bound"),
+        ];
+        let mut class = "other";
+        let mut max = 0;
+        for (category, label) in classes {
+            let count = features.count(category);
+            if count > max {
+                max = count;
+                class = label;
+            }
+        }
+        class.to_string()
+    }
+}
+
+/// The class of every measured block.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub classes: HashMap<BlockId, String>,
+}
+
+impl Classification {
+    /// Classifies all blocks of `measurements` that are in `block_store`. Host calls
+    /// are only known if the measurements were taken with
+    /// [`Profiling::with_host_call_timing`](crate::instrumentation::Profiling::with_host_call_timing)
+    /// and event recording.
+    pub fn new<C: Clock>(
+        measurements: &Measurements<C>,
+        block_store: &BlockStore,
+        granularity: Granularity,
+        classifier: &dyn BlockClassifier,
+    ) -> Self {
+        let events = measurements.events.as_deref().unwrap_or_default();
+        let host_calls = host_calls_per_block(events, granularity);
+        let classes = measurements
+            .taken
+            .iter()
+            .filter_map(|(block_id, timings)| {
+                let block = block_store.get_block(*block_id)?;
+                let host_calls = host_calls.get(block_id).copied().unwrap_or_default();
+                let features = BlockFeatures::new(block, timings.len() as u64, host_calls);
+                Some((*block_id, classifier.classify(&features)))
+            })
+            .collect();
+        Classification { classes }
+    }
+
+    pub fn class(&self, block_id: BlockId) -> Option<&str> {
+        self.classes.get(&block_id).map(String::as_str)
+    }
+}
+
+/// Attributes every host call to the block it was made from.
+///
+/// In basic block granularity, calls end blocks, so a host call belongs to the block
+/// of the same function that was taken right before it. In function granularity, it
+/// belongs to the innermost function that was started.
+pub fn host_calls_per_block(
+    events: &[MeasurementEvent],
+    granularity: Granularity,
+) -> HashMap<BlockId, u64> {
+    let mut host_calls: HashMap<BlockId, u64> = HashMap::new();
+    // The last taken block in basic block granularity
+    let mut last_taken: Option<(u32, BlockId)> = None;
+    // The started functions and their host calls so far in function granularity
+    let mut stack: Vec<(u32, u64)> = Vec::new();
+
+    for event in events {
+        match (*event, granularity) {
+            (MeasurementEvent::Start { fn_index }, Granularity::Function) => {
+                stack.push((fn_index, 0));
+            }
+            (
+                MeasurementEvent::Take {
+                    fn_index, block_id, ..
+                },
+                Granularity::Function,
+            ) => {
+                // Drop frames that were never finished, e.g. due to a trap.
+                while stack.len() > 1 && stack.last().unwrap().0 != fn_index {
+                    stack.pop();
+                }
+                if let Some((_, calls)) = stack.pop() {
+                    if calls > 0 {
+                        *host_calls.entry(block_id).or_default() += calls;
+                    }
+                }
+            }
+            (
+                MeasurementEvent::Take {
+                    fn_index, block_id, ..
+                },
+                Granularity::BasicBlock,
+            ) => last_taken = Some((fn_index, block_id)),
+            (MeasurementEvent::Start { .. }, Granularity::BasicBlock) => {}
+            (MeasurementEvent::HostCall { fn_index, .. }, Granularity::Function) => {
+                if let Some((_, calls)) = stack.last_mut().filter(|(f, _)| *f == fn_index) {
+                    *calls += 1;
+                }
+            }
+            (MeasurementEvent::HostCall { fn_index, .. }, Granularity::BasicBlock) => {
+                if let Some((_, block_id)) = last_taken.filter(|(f, _)| *f == fn_index) {
+                    *host_calls.entry(block_id).or_default() += 1;
+                }
+            }
+            (MeasurementEvent::IndirectCall { .. }, _) => {}
+            (MeasurementEvent::InvocationEnd, _) => {
+                last_taken = None;
+                stack.clear();
+            }
+        }
+    }
+    host_calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::operators::OperatorSymbol;
+
+    fn block(operators: &[OperatorSymbol]) -> CodeBlock {
+        CodeBlock::from(operators.to_vec())
+    }
+
+    fn classify(operators: &[OperatorSymbol], host_calls: u64) -> String {
+        let block = block(operators);
+        DominantCategoryClassifier.classify(&BlockFeatures::new(&block, 1, host_calls))
+    }
+
+    #[test]
+    fn dominant_category_classifier_works() {
+        use OperatorSymbol::*;
+
+        assert_eq!(
+            classify(&[LocalGet, I64Load, I64Load, I64Add], 0),
+            "memory-bound"
+        );
+        assert_eq!(classify(&[I64Load, I64Mul, I64Add], 0), "compute-bound");
+        assert_eq!(classify(&[Block, Loop, I32Eqz], 0), "control-bound");
+        // Ties go to memory before arithmetic before control
+        assert_eq!(classify(&[I32Load, I32Add, Block], 0), "memory-bound");
+        assert_eq!(classify(&[LocalGet, LocalSet], 0), "other");
+        assert_eq!(classify(&[I64Mul, I64Mul], 2), "host-bound");
+    }
+
+    #[test]
+    fn host_calls_per_block_works_in_basic_block_granularity() {
+        let take = |fn_index, block_id| MeasurementEvent::Take {
+            fn_index,
+            block_id: BlockId(block_id),
+            cost: 1,
+        };
+        let host_call = |fn_index| MeasurementEvent::HostCall {
+            fn_index,
+            import_index: 0,
+            cost: 1,
+        };
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            take(0, 10),
+            host_call(0),
+            MeasurementEvent::Start { fn_index: 0 },
+            take(0, 11),
+            MeasurementEvent::Start { fn_index: 0 },
+            take(0, 10),
+            host_call(0),
+            // From a function whose blocks are not measured
+            host_call(1),
+            MeasurementEvent::InvocationEnd,
+            host_call(0),
+        ];
+        let host_calls = host_calls_per_block(&events, Granularity::BasicBlock);
+        assert_eq!(
+            host_calls,
+            vec![(BlockId(10), 2)]
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn host_calls_per_block_works_in_function_granularity() {
+        let take = |fn_index, block_id| MeasurementEvent::Take {
+            fn_index,
+            block_id: BlockId(block_id),
+            cost: 1,
+        };
+        let host_call = |fn_index| MeasurementEvent::HostCall {
+            fn_index,
+            import_index: 0,
+            cost: 1,
+        };
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            host_call(0),
+            MeasurementEvent::Start { fn_index: 1 },
+            host_call(1),
+            host_call(1),
+            take(1, 11),
+            take(0, 10),
+            MeasurementEvent::Start { fn_index: 2 },
+            take(2, 12),
+        ];
+        let host_calls = host_calls_per_block(&events, Granularity::Function);
+        assert_eq!(
+            host_calls,
+            vec![(BlockId(10), 1), (BlockId(11), 2)]
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn classification_works() {
+        use OperatorSymbol::*;
+
+        let mut store = BlockStore::new();
+        let memory = store.register_block(block(&[I64Load, I64Store])).unwrap();
+        let compute = store.register_block(block(&[I64Mul, I64Add])).unwrap();
+        let mut measurements = Measurements::new().with_event_recording();
+        measurements.start_measurement(0, 0);
+        measurements.take_measurement(0, 0, memory);
+        measurements.start_measurement(0, 1);
+        measurements.take_measurement(0, 1, compute);
+        measurements.start_host_call(0, 3);
+        measurements.end_host_call(0, 3);
+        // Not in the store
+        measurements.start_measurement(0, 2);
+        measurements.take_measurement(0, 2, 42);
+
+        let classification = Classification::new(
+            &measurements,
+            &store,
+            Granularity::BasicBlock,
+            &DominantCategoryClassifier,
+        );
+        assert_eq!(classification.class(memory), Some("memory-bound"));
+        assert_eq!(classification.class(compute), Some("host-bound"));
+        assert_eq!(classification.class(BlockId(42)), None);
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+/// A source of timestamps used to measure how long code blocks take.
+///
+/// The default is [`WallClock`], which measures wall time in nanoseconds.
+/// Wall time is noisy for the short basic blocks we instrument, so cycle
+/// counter based clocks are provided for the platforms that support them.
+pub trait Clock: Clone + Debug + Send + Sync + 'static {
+    /// A raw reading taken when a block starts executing. Later readings of a
+    /// well-behaved clock are never smaller.
+    type Reading: Copy + Debug + Ord + Send + Sync;
+    /// The cost of a single block execution.
+    type Elapsed: Copy + Debug + Ord + Send + Sync;
+
+    /// The unit costs are reported in, e.g. "ns" or "cycles".
+    const UNIT: &'static str;
+
+    fn now(&self) -> Self::Reading;
+
+    fn elapsed(&self, start: Self::Reading) -> Self::Elapsed;
+
+    /// Converts a cost to a plain number of `UNIT`s for aggregation and reporting.
+    fn to_units(elapsed: Self::Elapsed) -> u128;
+}
+
+/// Measures wall time using `std::time::Instant`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WallClock;
+
+impl Clock for WallClock {
+    type Reading = Instant;
+    type Elapsed = Duration;
+
+    const UNIT: &'static str = "ns";
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn elapsed(&self, start: Instant) -> Duration {
+        start.elapsed()
+    }
+
+    fn to_units(elapsed: Duration) -> u128 {
+        elapsed.as_nanos()
+    }
+}
+
+/// A number of CPU cycles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cycles(pub u64);
+
+/// Reads the time stamp counter using `rdtsc`.
+///
+/// On CPUs with an invariant TSC (see [`TscClock::is_invariant`]) the counter
+/// ticks at a constant rate regardless of frequency scaling, so readings are
+/// comparable across blocks but are not core clock cycles in the strict sense.
+/// The reads are fenced with `lfence` so that they are not reordered with the
+/// instructions of the measured block.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TscClock;
+
+#[cfg(target_arch = "x86_64")]
+impl TscClock {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Returns true if the CPU reports an invariant TSC.
+    // Newer compilers consider `__cpuid` safe to call.
+    #[allow(unused_unsafe)]
+    pub fn is_invariant() -> bool {
+        use std::arch::x86_64::__cpuid;
+
+        // SAFETY: `cpuid` is available on every x86_64 CPU.
+        let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+        if max_extended_leaf < 0x8000_0007 {
+            return false;
+        }
+        let edx = unsafe { __cpuid(0x8000_0007) }.edx;
+        edx & (1 << 8) != 0
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Clock for TscClock {
+    type Reading = u64;
+    type Elapsed = Cycles;
+
+    const UNIT: &'static str = "cycles";
+
+    fn now(&self) -> u64 {
+        use std::arch::x86_64::{_mm_lfence, _rdtsc};
+
+        // SAFETY: `lfence` and `rdtsc` are available on every x86_64 CPU.
+        unsafe {
+            _mm_lfence();
+            let tsc = _rdtsc();
+            _mm_lfence();
+            tsc
+        }
+    }
+
+    fn elapsed(&self, start: u64) -> Cycles {
+        Cycles(self.now().saturating_sub(start))
+    }
+
+    fn to_units(elapsed: Cycles) -> u128 {
+        elapsed.0 as u128
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "perf-event"))]
+pub use perf::PerfCycleClock;
+
+#[cfg(all(target_os = "linux", feature = "perf-event"))]
+mod perf {
+    use std::io;
+    use std::sync::Arc;
+
+    use super::{Clock, Cycles};
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    const PERF_ATTR_SIZE_VER0: u32 = 64;
+
+    const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+    const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+    /// The first version of `struct perf_event_attr` from `linux/perf_event.h`.
+    /// The kernel accepts older, shorter versions of the struct, so we don't need
+    /// to mirror the fields we never set.
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        type_: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    #[derive(Debug)]
+    struct Counter {
+        fd: libc::c_int,
+    }
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+
+    /// Counts user space CPU cycles of the current thread using `perf_event_open`.
+    ///
+    /// The counter is bound to the thread that created the clock, so the clock
+    /// must be created on the thread that executes the instrumented contract.
+    /// This requires `perf_event_paranoid` to allow unprivileged measurements
+    /// (a value of 2 or lower).
+    #[derive(Debug, Clone)]
+    pub struct PerfCycleClock {
+        counter: Arc<Counter>,
+    }
+
+    impl PerfCycleClock {
+        pub fn new() -> io::Result<Self> {
+            let attr = PerfEventAttr {
+                type_: PERF_TYPE_HARDWARE,
+                size: PERF_ATTR_SIZE_VER0,
+                config: PERF_COUNT_HW_CPU_CYCLES,
+                flags: FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+                ..Default::default()
+            };
+
+            // pid = 0 and cpu = -1 measures the calling thread on any CPU.
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0 as libc::pid_t,
+                    -1 as libc::c_int,
+                    -1 as libc::c_int,
+                    0 as libc::c_ulong,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self {
+                counter: Arc::new(Counter {
+                    fd: fd as libc::c_int,
+                }),
+            })
+        }
+    }
+
+    impl Clock for PerfCycleClock {
+        type Reading = u64;
+        type Elapsed = Cycles;
+
+        const UNIT: &'static str = "cycles";
+
+        fn now(&self) -> u64 {
+            let mut value: u64 = 0;
+            let read = unsafe {
+                libc::read(
+                    self.counter.fd,
+                    &mut value as *mut u64 as *mut libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+            if read != std::mem::size_of::<u64>() as isize {
+                panic!(
+                    "failed to read the cycle counter: {}",
+                    io::Error::last_os_error()
+                );
+            }
+            value
+        }
+
+        fn elapsed(&self, start: u64) -> Cycles {
+            Cycles(self.now().saturating_sub(start))
+        }
+
+        fn to_units(elapsed: Cycles) -> u128 {
+            elapsed.0 as u128
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_clock_works() {
+        let clock = WallClock;
+        let start = clock.now();
+        std::thread::sleep(Duration::from_millis(10));
+        let elapsed = clock.elapsed(start);
+        assert!(elapsed >= Duration::from_millis(10));
+        assert_eq!(WallClock::to_units(elapsed), elapsed.as_nanos());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn tsc_clock_is_monotonic() {
+        let clock = TscClock::new();
+        let start = clock.now();
+        std::thread::sleep(Duration::from_millis(10));
+        let elapsed = clock.elapsed(start);
+        assert!(elapsed > Cycles(0));
+        assert_eq!(TscClock::to_units(Cycles(42)), 42);
+    }
+}
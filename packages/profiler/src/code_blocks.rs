@@ -1,11 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::hash::Hash;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-use loupe::MemoryUsage;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::operators::OperatorSymbol;
+use crate::compat::MemoryUsage;
+use crate::operators::{Immediate, OperatorSymbol};
 
-#[derive(Debug, Hash, PartialEq, Eq, MemoryUsage, Copy, Clone)]
+#[derive(Error, Debug)]
+pub enum BlockStoreError {
+    #[error("Error accessing the block store file: {source}")]
+    IoErr {
+        #[from]
+        source: std::io::Error,
+    },
+    #[error("Error (de)serializing the block store: {source}")]
+    SerializationErr {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("The stores have different blocks at function {fn_index}, block {local_block_id}. Were they created from different Wasm?")]
+    LocationConflict { fn_index: u32, local_block_id: u32 },
+    #[error(
+        "The stores have different blocks with id {id}. Were they created from different Wasm?"
+    )]
+    BlockConflict { id: u64 },
+    #[error("Different blocks have the same id {id}. Use a stronger block hasher.")]
+    HashCollision { id: u64 },
+}
+
+#[derive(
+    Debug, Hash, PartialEq, Eq, PartialOrd, Ord, MemoryUsage, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(transparent)]
 pub struct BlockId(pub u64);
 
 impl BlockId {
@@ -26,55 +56,582 @@ impl PartialEq<u64> for BlockId {
     }
 }
 
+/// The byte offsets of the first and the last operator of a block in a Wasm module,
+/// relative to the start of the code section. This is how DWARF addresses Wasm code, so
+/// tools can map a block to source lines with the debug info of the same module.
+#[derive(Debug, PartialEq, Eq, MemoryUsage, Copy, Clone, Serialize, Deserialize)]
+pub struct WasmOffsets {
+    pub first: u32,
+    pub last: u32,
+}
+
 impl PartialEq<BlockId> for u64 {
     fn eq(&self, rhs: &BlockId) -> bool {
         rhs.0 == *self
     }
 }
 
+/// Identifies a block instrumented at a specific location by its content, so that
+/// measurements of different builds of the same contract can be matched.
+///
+/// Function indexes and local block ids shift whenever code is added or removed
+/// before a block. `id` is the hash of the block's code and `ordinal` counts the
+/// blocks with the same code at earlier locations, ordered by function index and
+/// local block id. Identical blocks therefore keep their ids as long as their
+/// relative order stays the same, see [`BlockStore::stable_ids`].
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Serialize, Deserialize)]
+pub struct StableBlockId {
+    pub id: BlockId,
+    pub ordinal: u32,
+}
+
+/// Formats as `{id:016x}#{ordinal}`, e.g. `00000000000004d2#1`.
+impl fmt::Display for StableBlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}#{}", self.id.as_u64(), self.ordinal)
+    }
+}
+
+/// Computes the id of a code block.
+///
+/// Ids are passed to the measurement functions as `i64` constants, so every hasher
+/// produces 64 bits. Different blocks with the same id are reported by
+/// [`BlockStore::register_block`].
+pub trait BlockHasher: fmt::Debug + Send + Sync {
+    fn block_id(&self, block: &CodeBlock) -> BlockId;
+}
+
+/// SipHash as implemented by the standard library, the same ids as
+/// [`CodeBlock::get_hash`]. The default hasher of a [`BlockStore`].
+///
+/// The standard library does not guarantee the algorithm stays the same, so ids
+/// may differ between stores created with different Rust versions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SipBlockHasher;
+
+impl BlockHasher for SipBlockHasher {
+    fn block_id(&self, block: &CodeBlock) -> BlockId {
+        block.get_hash()
+    }
+}
+
+/// The first 8 bytes of the SHA-256 hash of a block's serialization, read as a big endian
+/// number. Slower than [`SipBlockHasher`], but the ids are the same on every platform and
+/// Rust version.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Sha256BlockHasher;
+
+impl BlockHasher for Sha256BlockHasher {
+    fn block_id(&self, block: &CodeBlock) -> BlockId {
+        // Serializing a list of enums cannot fail. The byte length is left out, since
+        // it is not part of the identity of a block.
+        let serialized = serde_json::to_vec(&StoredCodeBlock::identity(block)).unwrap();
+        sha256_block_id(&serialized)
+    }
+}
+
+/// The first 8 bytes of the SHA-256 hash of `data`, read as a big endian number.
+pub(crate) fn sha256_block_id(data: &[u8]) -> BlockId {
+    use sha2::{Digest, Sha256};
+
+    let digest = Sha256::digest(data);
+    let mut truncated = [0u8; 8];
+    truncated.copy_from_slice(&digest[..8]);
+    BlockId(u64::from_be_bytes(truncated))
+}
+
 /// Stores non-branching Wasm code blocks so that the exact
 /// list of operators can be looked up by hash later.
-#[derive(Debug, MemoryUsage)]
+#[derive(Debug, Clone, MemoryUsage)]
 pub struct BlockStore {
     inner: HashMap<BlockId, CodeBlock>,
+    /// The block instrumented at every location, keyed by function index and
+    /// local block id.
+    locations: HashMap<(u32, u32), BlockId>,
+    #[loupe(skip)]
+    hasher: Arc<dyn BlockHasher>,
 }
 
 impl BlockStore {
     pub fn new() -> Self {
         Self {
             inner: HashMap::new(),
+            locations: HashMap::new(),
+            hasher: Arc::new(SipBlockHasher),
         }
     }
 
-    #[cfg(test)]
+    /// Uses `hasher` to compute the ids of blocks registered from now on.
+    pub fn with_hasher(mut self, hasher: impl BlockHasher + 'static) -> Self {
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    /// The id `block` is registered under by `register_block`.
+    pub fn block_id(&self, block: &CodeBlock) -> BlockId {
+        self.hasher.block_id(block)
+    }
+
+    /// The number of distinct code blocks registered.
     pub fn len(&self) -> usize {
         self.inner.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     /// Register a new code block in the store. Returns a hash that can be later
     /// used to get the code block.
-    pub fn register_block(&mut self, block: impl Into<CodeBlock>) -> BlockId {
+    ///
+    /// Fails if a different block is registered under the same hash.
+    pub fn register_block(
+        &mut self,
+        block: impl Into<CodeBlock>,
+    ) -> Result<BlockId, BlockStoreError> {
         let block = block.into();
-        let hash = block.get_hash();
+        let hash = self.block_id(&block);
+        self.insert(hash, block)?;
+        Ok(hash)
+    }
+
+    /// Register a code block under an id chosen by the caller rather than its hash.
+    ///
+    /// Fails if a different block is registered under the same id.
+    pub fn register_block_with_id(
+        &mut self,
+        id: BlockId,
+        block: impl Into<CodeBlock>,
+    ) -> Result<(), BlockStoreError> {
+        self.insert(id, block.into())
+    }
 
-        // let hash = calculate_hash(&v);
-        self.inner.insert(hash, block);
-        hash
+    fn insert(&mut self, id: BlockId, block: CodeBlock) -> Result<(), BlockStoreError> {
+        match self.inner.get_mut(&id) {
+            Some(existing) if *existing != block => {
+                Err(BlockStoreError::HashCollision { id: id.as_u64() })
+            }
+            Some(existing) => {
+                if existing.byte_len.is_none() {
+                    existing.byte_len = block.byte_len;
+                }
+                if existing.offsets.is_none() {
+                    existing.offsets = block.offsets;
+                }
+                Ok(())
+            }
+            None => {
+                self.inner.insert(id, block);
+                Ok(())
+            }
+        }
+    }
+
+    /// Records that a block was instrumented at a location. If several modules share
+    /// the store, their locations cannot be told apart.
+    pub fn register_location(&mut self, fn_index: u32, local_block_id: u32, id: BlockId) {
+        self.locations.insert((fn_index, local_block_id), id);
+    }
+
+    /// All locations registered with `register_location`, in no particular order.
+    pub fn locations(&self) -> impl Iterator<Item = ((u32, u32), BlockId)> + '_ {
+        self.locations.iter().map(|(location, id)| (*location, *id))
+    }
+
+    /// The stable id of the block at every registered location, see [`StableBlockId`].
+    pub fn stable_ids(&self) -> HashMap<(u32, u32), StableBlockId> {
+        let mut locations: Vec<_> = self.locations().collect();
+        locations.sort_unstable();
+
+        let mut ordinals: HashMap<BlockId, u32> = HashMap::new();
+        locations
+            .into_iter()
+            .map(|(location, id)| {
+                let ordinal = ordinals.entry(id).or_default();
+                let stable_id = StableBlockId {
+                    id,
+                    ordinal: *ordinal,
+                };
+                *ordinal += 1;
+                (location, stable_id)
+            })
+            .collect()
+    }
+
+    /// The location of the block with the stable id `stable_id`, e.g. to find a block
+    /// of another build in this one.
+    pub fn stable_location(&self, stable_id: StableBlockId) -> Option<(u32, u32)> {
+        let mut locations: Vec<(u32, u32)> = self
+            .locations
+            .iter()
+            .filter(|(_, id)| **id == stable_id.id)
+            .map(|(location, _)| *location)
+            .collect();
+        locations.sort_unstable();
+        locations.get(stable_id.ordinal as usize).copied()
+    }
+
+    /// How often every block was instrumented, see [`DedupStats`]. Blocks without a
+    /// registered location are left out.
+    pub fn dedup_stats(&self) -> DedupStats {
+        let mut functions: BTreeMap<BlockId, BTreeSet<u32>> = BTreeMap::new();
+        let mut blocks: BTreeMap<BlockId, BlockSharing> = BTreeMap::new();
+        for ((fn_index, _), id) in self.locations() {
+            functions.entry(id).or_default().insert(fn_index);
+            blocks.entry(id).or_default().sites += 1;
+        }
+        for (id, sharing) in &mut blocks {
+            sharing.functions = functions[id].len() as u32;
+        }
+        DedupStats { blocks }
     }
 
     /// Get a code block by hash.
     pub fn get_block(&self, hash: impl Into<BlockId>) -> Option<&CodeBlock> {
         self.inner.get(&hash.into())
     }
+
+    /// Adds all blocks and locations of `other`, e.g. of a store loaded from another
+    /// profiling run of the same Wasm. Fails without modifying the store if both
+    /// have different blocks under the same id or at the same location.
+    pub fn merge(&mut self, other: BlockStore) -> Result<(), BlockStoreError> {
+        for (id, block) in &other.inner {
+            if matches!(self.inner.get(id), Some(existing) if existing != block) {
+                return Err(BlockStoreError::BlockConflict { id: id.as_u64() });
+            }
+        }
+        for (location, id) in &other.locations {
+            if matches!(self.locations.get(location), Some(existing) if existing != id) {
+                return Err(BlockStoreError::LocationConflict {
+                    fn_index: location.0,
+                    local_block_id: location.1,
+                });
+            }
+        }
+
+        self.inner.extend(other.inner);
+        self.locations.extend(other.locations);
+        Ok(())
+    }
+
+    /// Writes the store to a JSON file, so measurements of separate processes can be
+    /// related to the same blocks.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BlockStoreError> {
+        let mut blocks: Vec<_> = self.inner.iter().collect();
+        blocks.sort_unstable_by_key(|(id, _)| **id);
+        let mut locations: Vec<_> = self.locations.iter().collect();
+        locations.sort_unstable();
+
+        let stored = StoredBlockStore { blocks, locations };
+        std::fs::write(path, serde_json::to_vec(&stored)?)?;
+        Ok(())
+    }
+
+    /// Reads a store written by `save`. The hasher is not part of the file, use
+    /// `with_hasher` to continue with the hasher the store was created with.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BlockStoreError> {
+        let data = std::fs::read(path)?;
+        let stored: StoredBlockStore<BlockId, CodeBlock, (u32, u32)> =
+            serde_json::from_slice(&data)?;
+        Ok(BlockStore {
+            inner: stored.blocks.into_iter().collect(),
+            locations: stored.locations.into_iter().collect(),
+            ..BlockStore::new()
+        })
+    }
+}
+
+/// How many locations share every block of a [`BlockStore`], e.g. to decide whether
+/// the cost of a block can be priced once for all its call sites.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DedupStats {
+    pub blocks: BTreeMap<BlockId, BlockSharing>,
+}
+
+/// The locations of one block, see [`DedupStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BlockSharing {
+    /// The number of locations the block was instrumented at
+    pub sites: u32,
+    /// The number of distinct functions containing these locations
+    pub functions: u32,
+}
+
+impl DedupStats {
+    /// The number of locations of all blocks.
+    pub fn sites(&self) -> u64 {
+        self.blocks
+            .values()
+            .map(|sharing| sharing.sites as u64)
+            .sum()
+    }
+
+    /// The number of distinct blocks.
+    pub fn distinct_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Locations per distinct block. 1.0 if no block occurs twice and for empty stats.
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.blocks.is_empty() {
+            return 1.0;
+        }
+        self.sites() as f64 / self.distinct_blocks() as f64
+    }
+
+    /// The blocks instrumented at more than one location, the most shared first.
+    pub fn shared_blocks(&self) -> Vec<(BlockId, BlockSharing)> {
+        let mut shared: Vec<_> = self
+            .blocks
+            .iter()
+            .filter(|(_, sharing)| sharing.sites > 1)
+            .map(|(id, sharing)| (*id, *sharing))
+            .collect();
+        shared.sort_by_key(|(id, sharing)| (std::cmp::Reverse(sharing.sites), *id));
+        shared
+    }
+
+    /// Writes the shared blocks as returned by [`DedupStats::shared_blocks`].
+    pub fn write_csv(&self, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(["block", "sites", "functions"]).unwrap();
+        for (id, sharing) in self.shared_blocks() {
+            wtr.write_record(&[
+                format!("{:016x}", id.as_u64()),
+                sharing.sites.to_string(),
+                sharing.functions.to_string(),
+            ])
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+}
+
+/// The file format of a `BlockStore`. JSON objects only support string keys, so the
+/// maps are stored as lists of pairs. The type parameters allow serializing references.
+#[derive(Serialize, Deserialize)]
+struct StoredBlockStore<I, B, L> {
+    blocks: Vec<(I, B)>,
+    locations: Vec<(L, I)>,
+}
+
+impl Default for BlockStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`BlockStore`] that can be filled from many threads at once, e.g. when the
+/// functions of a module are compiled in parallel, see
+/// [`Profiling::with_sharded_store`](crate::instrumentation::Profiling::with_sharded_store).
+/// Like in a `BlockStore`, the locations of different modules cannot be told apart.
+///
+/// Blocks are spread over several independently locked shards by id, locations by
+/// function index. Ids are computed before any shard is locked. Use
+/// [`ShardedBlockStore::to_block_store`] to analyze the registered blocks.
+#[derive(Debug)]
+pub struct ShardedBlockStore {
+    shards: Vec<Mutex<BlockStore>>,
+    hasher: Arc<dyn BlockHasher>,
+}
+
+impl ShardedBlockStore {
+    /// The number of shards of [`ShardedBlockStore::new`]
+    pub const DEFAULT_SHARDS: usize = 16;
+
+    pub fn new() -> Self {
+        Self::with_shards(Self::DEFAULT_SHARDS)
+    }
+
+    /// Panics if `shards` is 0.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "ShardedBlockStore: shards must not be 0");
+        Self {
+            shards: (0..shards).map(|_| Mutex::new(BlockStore::new())).collect(),
+            hasher: Arc::new(SipBlockHasher),
+        }
+    }
+
+    /// Uses `hasher` to compute the ids of blocks registered from now on.
+    pub fn with_hasher(mut self, hasher: impl BlockHasher + 'static) -> Self {
+        self.hasher = Arc::new(hasher);
+        self
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The id `block` is registered under by `register_block`.
+    pub fn block_id(&self, block: &CodeBlock) -> BlockId {
+        self.hasher.block_id(block)
+    }
+
+    /// The number of distinct code blocks registered.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn block_shard(&self, id: BlockId) -> &Mutex<BlockStore> {
+        &self.shards[(id.as_u64() % self.shards.len() as u64) as usize]
+    }
+
+    fn location_shard(&self, fn_index: u32) -> &Mutex<BlockStore> {
+        &self.shards[fn_index as usize % self.shards.len()]
+    }
+
+    /// Like [`BlockStore::register_block`], but only locks the shard of the block.
+    pub fn register_block(&self, block: impl Into<CodeBlock>) -> Result<BlockId, BlockStoreError> {
+        let block = block.into();
+        let id = self.block_id(&block);
+        self.register_block_with_id(id, block)?;
+        Ok(id)
+    }
+
+    /// Like [`BlockStore::register_block_with_id`], but only locks the shard of the block.
+    pub fn register_block_with_id(
+        &self,
+        id: BlockId,
+        block: impl Into<CodeBlock>,
+    ) -> Result<(), BlockStoreError> {
+        self.block_shard(id)
+            .lock()
+            .unwrap()
+            .register_block_with_id(id, block)
+    }
+
+    /// Like [`BlockStore::register_location`], but only locks the shard of the function.
+    pub fn register_location(&self, fn_index: u32, local_block_id: u32, id: BlockId) {
+        self.location_shard(fn_index)
+            .lock()
+            .unwrap()
+            .register_location(fn_index, local_block_id, id);
+    }
+
+    /// Get a copy of a code block by hash.
+    pub fn get_block(&self, hash: impl Into<BlockId>) -> Option<CodeBlock> {
+        let id = hash.into();
+        self.block_shard(id).lock().unwrap().get_block(id).cloned()
+    }
+
+    /// Copies all blocks and locations into one `BlockStore` with the same hasher.
+    pub fn to_block_store(&self) -> BlockStore {
+        let mut store = BlockStore {
+            hasher: self.hasher.clone(),
+            ..BlockStore::new()
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            store
+                .inner
+                .extend(shard.inner.iter().map(|(id, block)| (*id, block.clone())));
+            store.locations.extend(shard.locations.iter());
+        }
+        store
+    }
+}
+
+impl Default for ShardedBlockStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Represents a non-branching Wasm code block.
-#[derive(Debug, MemoryUsage, Hash, PartialEq)]
+///
+/// Blocks only consist of operator symbols unless created with
+/// [`CodeBlock::with_immediates`]. Blocks without immediates have the same id and
+/// serialization as before immediates existed, so stores and reports stay comparable.
+///
+/// The encoded byte length and the offsets are not part of the identity of a block.
+/// Blocks whose immediates are encoded in a different number of bytes share an id, as
+/// do blocks with the same code at different offsets. A store keeps the length and
+/// offsets of the block registered first.
+#[derive(MemoryUsage, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredCodeBlock", into = "StoredCodeBlock")]
 pub struct CodeBlock {
     inner: Vec<OperatorSymbol>,
+    /// The retained immediate of every operator, or empty
+    immediates: Vec<Immediate>,
+    /// The number of occurrences of every operator in `inner`
+    operator_counts: HashMap<OperatorSymbol, u32>,
+    /// The length of the block in the Wasm code section, if known
+    byte_len: Option<u32>,
+    /// Where the block is in the Wasm code section, if known
+    offsets: Option<WasmOffsets>,
 }
 
 impl CodeBlock {
+    /// A block whose id also depends on the `immediates` of its operators, see
+    /// [`RetainedImmediates`](crate::operators::RetainedImmediates).
+    ///
+    /// Panics if there is not one immediate per operator.
+    pub fn with_immediates(operators: Vec<OperatorSymbol>, immediates: Vec<Immediate>) -> Self {
+        assert_eq!(
+            operators.len(),
+            immediates.len(),
+            "CodeBlock::with_immediates: every operator needs an immediate"
+        );
+        Self {
+            operator_counts: count_operators(&operators),
+            inner: operators,
+            immediates,
+            byte_len: None,
+            offsets: None,
+        }
+    }
+
+    /// Sets the length of the block in the Wasm code section.
+    pub fn with_byte_len(mut self, byte_len: u32) -> Self {
+        self.byte_len = Some(byte_len);
+        self
+    }
+
+    /// Sets where the block is in the Wasm code section.
+    pub fn with_offsets(mut self, offsets: WasmOffsets) -> Self {
+        self.offsets = Some(offsets);
+        self
+    }
+
+    /// The operators of the block in order.
+    pub fn operators(&self) -> &[OperatorSymbol] {
+        &self.inner
+    }
+
+    /// The retained immediates of the operators in order. Empty if none were retained.
+    pub fn immediates(&self) -> &[Immediate] {
+        &self.immediates
+    }
+
+    /// How often every operator occurs in the block.
+    pub fn operator_counts(&self) -> &HashMap<OperatorSymbol, u32> {
+        &self.operator_counts
+    }
+
+    /// The length of the block in the Wasm code section in bytes. Only known for blocks
+    /// instrumented in Wasm prepared with
+    /// [`instrument_wasm`](crate::instrumentation::instrument_wasm).
+    pub fn byte_len(&self) -> Option<u32> {
+        self.byte_len
+    }
+
+    /// Where the block is in the Wasm code section, known like [`CodeBlock::byte_len`].
+    /// Of blocks with the same code, this is the first one registered in a store.
+    pub fn offsets(&self) -> Option<WasmOffsets> {
+        self.offsets
+    }
+
     pub fn get_hash(&self) -> BlockId {
         use std::hash::Hasher as _;
 
@@ -84,20 +641,131 @@ impl CodeBlock {
     }
 }
 
+fn count_operators(operators: &[OperatorSymbol]) -> HashMap<OperatorSymbol, u32> {
+    let mut counts = HashMap::new();
+    for operator in operators {
+        *counts.entry(*operator).or_default() += 1;
+    }
+    counts
+}
+
+/// Blocks are equal if they have the same id, i.e. the byte length is ignored.
+impl PartialEq for CodeBlock {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && self.immediates == other.immediates
+    }
+}
+
+impl Hash for CodeBlock {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state);
+        if !self.immediates.is_empty() {
+            self.immediates.hash(state);
+        }
+    }
+}
+
+impl fmt::Debug for CodeBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("CodeBlock");
+        debug.field("inner", &self.inner);
+        if !self.immediates.is_empty() {
+            debug.field("immediates", &self.immediates);
+        }
+        debug.finish()
+    }
+}
+
+/// The serialization of a `CodeBlock`: a list of operators, as it has always been,
+/// or an object if immediates were retained or the byte length or offsets are known. The
+/// operator counts are computed again when deserializing.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredCodeBlock {
+    Operators(Vec<OperatorSymbol>),
+    Detailed {
+        operators: Vec<OperatorSymbol>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        immediates: Vec<Immediate>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        byte_len: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offsets: Option<WasmOffsets>,
+    },
+}
+
+impl StoredCodeBlock {
+    /// The serialization of `block` without its byte length and offsets.
+    fn identity(block: &CodeBlock) -> Self {
+        if block.immediates.is_empty() {
+            StoredCodeBlock::Operators(block.inner.clone())
+        } else {
+            StoredCodeBlock::Detailed {
+                operators: block.inner.clone(),
+                immediates: block.immediates.clone(),
+                byte_len: None,
+                offsets: None,
+            }
+        }
+    }
+}
+
+impl From<StoredCodeBlock> for CodeBlock {
+    fn from(stored: StoredCodeBlock) -> Self {
+        match stored {
+            StoredCodeBlock::Operators(operators) => operators.into(),
+            StoredCodeBlock::Detailed {
+                operators,
+                immediates,
+                byte_len,
+                offsets,
+            } => Self {
+                operator_counts: count_operators(&operators),
+                inner: operators,
+                immediates,
+                byte_len,
+                offsets,
+            },
+        }
+    }
+}
+
+impl From<CodeBlock> for StoredCodeBlock {
+    fn from(block: CodeBlock) -> Self {
+        if block.immediates.is_empty() && block.byte_len.is_none() && block.offsets.is_none() {
+            StoredCodeBlock::Operators(block.inner)
+        } else {
+            StoredCodeBlock::Detailed {
+                operators: block.inner,
+                immediates: block.immediates,
+                byte_len: block.byte_len,
+                offsets: block.offsets,
+            }
+        }
+    }
+}
+
 impl<'b, Op> From<&'b [Op]> for CodeBlock
 where
     &'b Op: Into<OperatorSymbol>,
 {
     fn from(ops: &'b [Op]) -> Self {
-        Self {
-            inner: ops.iter().map(|item| item.into()).collect(),
-        }
+        ops.iter()
+            .map(|item| item.into())
+            .collect::<Vec<OperatorSymbol>>()
+            .into()
     }
 }
 
 impl From<Vec<OperatorSymbol>> for CodeBlock {
     fn from(ops: Vec<OperatorSymbol>) -> Self {
-        Self { inner: ops }
+        Self {
+            operator_counts: count_operators(&ops),
+            inner: ops,
+            immediates: Vec::new(),
+            byte_len: None,
+            offsets: None,
+        }
     }
 }
 
@@ -130,9 +798,9 @@ mod tests {
             Operator::GlobalSet { global_index: 333 },
         ];
 
-        let code_block1_hash = store.register_block(&code_block1[..]);
-        let code_block2_hash = store.register_block(&code_block2[..]);
-        let code_block1_another_hash = store.register_block(&code_block1[..]);
+        let code_block1_hash = store.register_block(&code_block1[..]).unwrap();
+        let code_block2_hash = store.register_block(&code_block2[..]).unwrap();
+        let code_block1_another_hash = store.register_block(&code_block1[..]).unwrap();
 
         assert_eq!(code_block1_hash, code_block1_another_hash);
         assert_ne!(code_block1_hash, code_block2_hash);
@@ -159,4 +827,350 @@ mod tests {
         assert_eq!(store.get_block(code_block2_hash), Some(&cb2_expected));
         assert_eq!(store.get_block(234), None);
     }
+
+    #[test]
+    fn immediates_are_part_of_the_id() {
+        let operators = vec![OperatorSymbol::I32Const, OperatorSymbol::MemoryCopy];
+        let small = CodeBlock::with_immediates(
+            operators.clone(),
+            vec![Immediate::Magnitude(4), Immediate::None],
+        );
+        let large = CodeBlock::with_immediates(
+            operators.clone(),
+            vec![Immediate::Magnitude(20), Immediate::None],
+        );
+        let plain = CodeBlock::from(operators.clone());
+        assert_ne!(small.get_hash(), large.get_hash());
+        assert_ne!(small.get_hash(), plain.get_hash());
+
+        // Blocks without immediates keep their id
+        let mut s = std::collections::hash_map::DefaultHasher::new();
+        operators.hash(&mut s);
+        assert_eq!(plain.get_hash(), BlockId(std::hash::Hasher::finish(&s)));
+    }
+
+    #[test]
+    fn code_block_serialization_works() {
+        let plain = CodeBlock::from(vec![OperatorSymbol::LocalGet]);
+        let json = serde_json::to_string(&plain).unwrap();
+        assert_eq!(json, r#"["LocalGet"]"#);
+        assert_eq!(serde_json::from_str::<CodeBlock>(&json).unwrap(), plain);
+
+        let block = CodeBlock::with_immediates(
+            vec![OperatorSymbol::I32Load],
+            vec![Immediate::Alignment(2)],
+        );
+        let json = serde_json::to_string(&block).unwrap();
+        assert_eq!(
+            json,
+            r#"{"operators":["I32Load"],"immediates":[{"Alignment":2}]}"#
+        );
+        assert_eq!(serde_json::from_str::<CodeBlock>(&json).unwrap(), block);
+
+        let block = CodeBlock::from(vec![OperatorSymbol::LocalGet]).with_byte_len(2);
+        let json = serde_json::to_string(&block).unwrap();
+        assert_eq!(json, r#"{"operators":["LocalGet"],"byte_len":2}"#);
+        let parsed = serde_json::from_str::<CodeBlock>(&json).unwrap();
+        assert_eq!(parsed.byte_len(), Some(2));
+        assert_eq!(parsed.operator_counts()[&OperatorSymbol::LocalGet], 1);
+
+        let offsets = WasmOffsets { first: 5, last: 9 };
+        let block = CodeBlock::from(vec![OperatorSymbol::LocalGet]).with_offsets(offsets);
+        let json = serde_json::to_string(&block).unwrap();
+        assert_eq!(
+            json,
+            r#"{"operators":["LocalGet"],"offsets":{"first":5,"last":9}}"#
+        );
+        let parsed = serde_json::from_str::<CodeBlock>(&json).unwrap();
+        assert_eq!(parsed.offsets(), Some(offsets));
+        assert_eq!(parsed.byte_len(), None);
+    }
+
+    #[test]
+    fn code_block_counts_operators() {
+        let block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Add,
+        ]);
+        assert_eq!(block.operator_counts().len(), 2);
+        assert_eq!(block.operator_counts()[&OperatorSymbol::LocalGet], 2);
+        assert_eq!(block.operator_counts()[&OperatorSymbol::I32Add], 1);
+        assert_eq!(block.byte_len(), None);
+    }
+
+    #[test]
+    fn byte_len_is_not_part_of_the_id() {
+        let plain = CodeBlock::from(vec![OperatorSymbol::I32Const]);
+        let short = plain.clone().with_byte_len(2);
+        let long = plain
+            .clone()
+            .with_byte_len(6)
+            .with_offsets(WasmOffsets { first: 1, last: 1 });
+        assert_eq!(short, long);
+        assert_eq!(short.get_hash(), plain.get_hash());
+        assert_eq!(
+            Sha256BlockHasher.block_id(&short),
+            Sha256BlockHasher.block_id(&plain)
+        );
+
+        // The store keeps the first known length
+        let mut store = BlockStore::new();
+        let id = store.register_block(plain).unwrap();
+        store.register_block(short).unwrap();
+        store.register_block(long).unwrap();
+        assert_eq!(store.get_block(id).unwrap().byte_len(), Some(2));
+        assert_eq!(
+            store.get_block(id).unwrap().offsets(),
+            Some(WasmOffsets { first: 1, last: 1 })
+        );
+    }
+
+    /// Maps every block to the same id
+    #[derive(Debug)]
+    struct ConstantHasher;
+
+    impl BlockHasher for ConstantHasher {
+        fn block_id(&self, _block: &CodeBlock) -> BlockId {
+            BlockId(7)
+        }
+    }
+
+    #[test]
+    fn register_block_detects_collisions() {
+        let mut store = BlockStore::new().with_hasher(ConstantHasher);
+        let id = store.register_block(vec![OperatorSymbol::I32Add]).unwrap();
+        assert_eq!(id, BlockId(7));
+        // The same block again is fine
+        store.register_block(vec![OperatorSymbol::I32Add]).unwrap();
+
+        match store.register_block(vec![OperatorSymbol::I32Sub]) {
+            Err(BlockStoreError::HashCollision { id }) => assert_eq!(id, 7),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match store.register_block_with_id(BlockId(7), vec![OperatorSymbol::I64Add]) {
+            Err(BlockStoreError::HashCollision { id }) => assert_eq!(id, 7),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // The first block is kept
+        assert_eq!(
+            store.get_block(7),
+            Some(&CodeBlock::from(vec![OperatorSymbol::I32Add]))
+        );
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn sha256_block_hasher_works() {
+        let mut store = BlockStore::new().with_hasher(Sha256BlockHasher);
+        let id = store
+            .register_block(vec![OperatorSymbol::LocalGet])
+            .unwrap();
+        assert_eq!(id, BlockId(2374727551429196339));
+
+        let other = store
+            .register_block(vec![OperatorSymbol::LocalGet, OperatorSymbol::Drop])
+            .unwrap();
+        assert_ne!(id, other);
+        let block = CodeBlock::from(vec![OperatorSymbol::LocalGet]);
+        assert_eq!(store.block_id(&block), id);
+        assert_ne!(SipBlockHasher.block_id(&block), id);
+    }
+
+    #[test]
+    fn sharded_block_store_works() {
+        let store = ShardedBlockStore::with_shards(4).with_hasher(Sha256BlockHasher);
+        assert_eq!(store.shard_count(), 4);
+        assert!(store.is_empty());
+
+        let blocks: Vec<_> = (1..=20)
+            .map(|len| CodeBlock::from(vec![OperatorSymbol::LocalGet; len]))
+            .collect();
+        let store = Arc::new(store);
+        let threads: Vec<_> = blocks
+            .chunks(5)
+            .enumerate()
+            .map(|(fn_index, chunk)| {
+                let store = store.clone();
+                let chunk = chunk.to_vec();
+                std::thread::spawn(move || {
+                    for (local_block_id, block) in chunk.into_iter().enumerate() {
+                        let id = store.register_block(block).unwrap();
+                        store.register_location(fn_index as u32, local_block_id as u32, id);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(store.len(), 20);
+        let id = store.block_id(&blocks[3]);
+        assert_eq!(store.get_block(id), Some(blocks[3].clone()));
+
+        let merged = store.to_block_store();
+        assert_eq!(merged.len(), 20);
+        assert_eq!(merged.locations().count(), 20);
+        assert_eq!(merged.get_block(id), Some(&blocks[3]));
+        // The hasher is kept
+        assert_eq!(merged.block_id(&blocks[3]), id);
+        assert!(merged
+            .locations()
+            .all(|((fn_index, local_block_id), id)| merged.get_block(id)
+                == Some(&blocks[(fn_index * 5 + local_block_id) as usize])));
+    }
+
+    #[test]
+    fn sharded_block_store_detects_collisions() {
+        let store = ShardedBlockStore::new().with_hasher(ConstantHasher);
+        store.register_block(vec![OperatorSymbol::I32Add]).unwrap();
+        store.register_block(vec![OperatorSymbol::I32Add]).unwrap();
+        match store.register_block(vec![OperatorSymbol::I32Sub]) {
+            Err(BlockStoreError::HashCollision { id }) => assert_eq!(id, 7),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(store.len(), 1);
+    }
+
+    fn store_with(blocks: &[(u32, Vec<OperatorSymbol>)]) -> BlockStore {
+        let mut store = BlockStore::new();
+        for (fn_index, block) in blocks {
+            let id = store.register_block(block.clone()).unwrap();
+            store.register_location(*fn_index, 0, id);
+        }
+        store
+    }
+
+    #[test]
+    fn save_and_load_work() {
+        let store = store_with(&[
+            (0, vec![OperatorSymbol::LocalGet, OperatorSymbol::I32Add]),
+            (3, vec![OperatorSymbol::Nop]),
+        ]);
+        let id = CodeBlock::from(vec![OperatorSymbol::Nop]).get_hash();
+
+        let path = std::env::temp_dir().join(format!("block_store_{}.json", std::process::id()));
+        store.save(&path).unwrap();
+        let loaded = BlockStore::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(
+            loaded.get_block(id),
+            Some(&CodeBlock::from(vec![OperatorSymbol::Nop]))
+        );
+        let mut locations: Vec<_> = loaded.locations().collect();
+        locations.sort_unstable();
+        let mut expected: Vec<_> = store.locations().collect();
+        expected.sort_unstable();
+        assert_eq!(locations, expected);
+
+        match BlockStore::load(std::env::temp_dir().join("does_not_exist.json")).unwrap_err() {
+            BlockStoreError::IoErr { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn merge_works() {
+        let mut store = store_with(&[(0, vec![OperatorSymbol::Nop])]);
+        let other = store_with(&[
+            (0, vec![OperatorSymbol::Nop]),
+            (1, vec![OperatorSymbol::Drop]),
+        ]);
+        store.merge(other).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.locations().count(), 2);
+
+        // A different block at the same location
+        let other = store_with(&[(1, vec![OperatorSymbol::Return])]);
+        match store.merge(other).unwrap_err() {
+            BlockStoreError::LocationConflict {
+                fn_index,
+                local_block_id,
+            } => assert_eq!((fn_index, local_block_id), (1, 0)),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn dedup_stats_work() {
+        let nop = vec![OperatorSymbol::Nop];
+        let drop = vec![OperatorSymbol::Drop];
+        let mut store = store_with(&[(0, nop.clone()), (1, drop.clone()), (2, nop.clone())]);
+        let nop_id = CodeBlock::from(nop).get_hash();
+        store.register_location(0, 1, nop_id);
+        // Registered without a location
+        store.register_block(vec![OperatorSymbol::Return]).unwrap();
+
+        let stats = store.dedup_stats();
+        assert_eq!(stats.sites(), 4);
+        assert_eq!(stats.distinct_blocks(), 2);
+        assert_eq!(stats.dedup_ratio(), 2.0);
+        assert_eq!(
+            stats.shared_blocks(),
+            vec![(
+                nop_id,
+                BlockSharing {
+                    sites: 3,
+                    functions: 2
+                }
+            )]
+        );
+
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!("block,sites,functions\r\n{:016x},3,2\r\n", nop_id.as_u64())
+        );
+        assert_eq!(BlockStore::new().dedup_stats().dedup_ratio(), 1.0);
+    }
+
+    #[test]
+    fn stable_ids_survive_shifted_locations() {
+        let nop = vec![OperatorSymbol::Nop];
+        let drop = vec![OperatorSymbol::Drop];
+        let old = store_with(&[(0, nop.clone()), (1, drop.clone()), (2, nop.clone())]);
+        // A function was added at the start, shifting all indexes
+        let new = store_with(&[
+            (0, vec![OperatorSymbol::Return]),
+            (1, nop.clone()),
+            (2, drop.clone()),
+            (3, nop.clone()),
+        ]);
+
+        let nop_id = CodeBlock::from(nop).get_hash();
+        let old_ids = old.stable_ids();
+        assert_eq!(
+            old_ids[&(2, 0)],
+            StableBlockId {
+                id: nop_id,
+                ordinal: 1
+            }
+        );
+        assert_eq!(old_ids[&(1, 0)].ordinal, 0);
+
+        let new_ids = new.stable_ids();
+        for (location, stable_id) in &old_ids {
+            let new_location = new.stable_location(*stable_id).unwrap();
+            assert_eq!(new_location, (location.0 + 1, location.1));
+            assert_eq!(new_ids[&new_location], *stable_id);
+        }
+
+        let missing = StableBlockId {
+            id: nop_id,
+            ordinal: 2,
+        };
+        assert_eq!(new.stable_location(missing), None);
+        assert_eq!(
+            StableBlockId {
+                id: BlockId(0x4d2),
+                ordinal: 1
+            }
+            .to_string(),
+            "00000000000004d2#1"
+        );
+    }
 }
@@ -0,0 +1,18 @@
+//! The parts of the Wasmer API the middleware depends on that changed after Wasmer 2,
+//! imported from here only, so that supporting another Wasmer version means changing
+//! this module instead of every user:
+//!
+//! - [`ModuleInfo`] moved from `wasmer_vm` to `wasmer_types` in Wasmer 3.
+//! - Wasmer 3 dropped `loupe`. Middlewares no longer need to implement
+//!   [`MemoryUsage`], so the derives become unnecessary there.
+//! - Wasmer 3 replaced `WasmerEnv` by `FunctionEnv`, which changes how the imports of
+//!   [`add_measuring_imports`](crate::instrumentation::add_measuring_imports) get
+//!   their measurements. `FunctionMiddleware` and `MiddlewareReaderState` stay the same.
+//!
+//! This is not a compatibility layer yet: there is no feature selecting another Wasmer
+//! version, and only Wasmer 2 is supported, since modules are compiled through
+//! `cosmwasm_vm`, which uses Wasmer 2.2. A Wasmer 3/4 backend needs a `cosmwasm_vm`
+//! on the same Wasmer version first.
+
+pub(crate) use loupe::MemoryUsage;
+pub(crate) use wasmer_vm::ModuleInfo;
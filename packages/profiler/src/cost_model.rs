@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::clock::Clock;
+use crate::code_blocks::{BlockStore, CodeBlock};
+use crate::measure::{Measurements, Metadata};
+use crate::operators::OperatorSymbol;
+use crate::utils::{with_metadata_keys, with_metadata_values};
+
+/// The z-score of a two-sided 95% confidence interval of a normal distribution.
+const Z_95: f64 = 1.96;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CostModelError {
+    #[error("Cannot fit {parameters} parameters from {observations} block executions")]
+    NotEnoughData {
+        observations: usize,
+        parameters: usize,
+    },
+    #[error("The cost of the operators cannot be separated, e.g. because some always occur together in the same blocks")]
+    Singular,
+}
+
+/// An estimated cost and its standard error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub cost: f64,
+    pub std_error: f64,
+}
+
+impl Estimate {
+    /// The 95% confidence interval, using the normal approximation. This is accurate
+    /// as long as there are many more block executions than operator kinds.
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        let margin = Z_95 * self.std_error;
+        (self.cost - margin, self.cost + margin)
+    }
+}
+
+/// A linear model of the cost of a block: a fixed overhead per block execution plus
+/// an estimated cost per executed operator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostModel {
+    /// The cost every block execution has regardless of its operators, e.g. the
+    /// overhead of the measurement itself
+    pub overhead: Estimate,
+    pub operators: HashMap<OperatorSymbol, Estimate>,
+    /// The number of block executions the model was fitted from
+    pub observations: usize,
+    /// The standard deviation of the measured costs around the predicted ones
+    pub residual_stddev: f64,
+}
+
+impl CostModel {
+    /// Fits the model with ordinary least squares: every execution of a block is an
+    /// observation, the operator counts of the block are the regressors and the
+    /// measured cost is the response.
+    ///
+    /// Blocks that are not in `block_store` are skipped.
+    pub fn fit<C: Clock>(
+        measurements: &Measurements<C>,
+        block_store: &BlockStore,
+    ) -> Result<Self, CostModelError> {
+        let mut samples: Vec<(&CodeBlock, Vec<f64>)> = measurements
+            .taken
+            .iter()
+            .filter(|(_, timings)| !timings.is_empty())
+            .filter_map(|(block_id, timings)| {
+                let block = block_store.get_block(*block_id)?;
+                let costs = timings.iter().map(|t| C::to_units(*t) as f64).collect();
+                Some((block, costs))
+            })
+            .collect();
+        // Sorting is not needed for the fit, but makes it deterministic
+        samples.sort_by_key(|(block, _)| block.get_hash());
+
+        let mut symbols: Vec<OperatorSymbol> = Vec::new();
+        let mut columns: HashMap<OperatorSymbol, usize> = HashMap::new();
+        for (block, _) in &samples {
+            for operator in block.operators() {
+                columns.entry(*operator).or_insert_with(|| {
+                    symbols.push(*operator);
+                    symbols.len()
+                });
+            }
+        }
+
+        // Column 0 is the overhead
+        let parameters = symbols.len() + 1;
+        let observations: usize = samples.iter().map(|(_, costs)| costs.len()).sum();
+        if observations <= parameters {
+            return Err(CostModelError::NotEnoughData {
+                observations,
+                parameters,
+            });
+        }
+
+        let regressors = |block: &CodeBlock| {
+            let mut x = vec![0.0; parameters];
+            x[0] = 1.0;
+            for operator in block.operators() {
+                x[columns[operator]] += 1.0;
+            }
+            x
+        };
+
+        // The normal equations X^T X b = X^T y. All executions of a block share the
+        // same regressors, so they are accumulated per block.
+        let mut xtx = vec![vec![0.0; parameters]; parameters];
+        let mut xty = vec![0.0; parameters];
+        for (block, costs) in &samples {
+            let x = regressors(block);
+            let n = costs.len() as f64;
+            let total: f64 = costs.iter().sum();
+            for i in 0..parameters {
+                xty[i] += x[i] * total;
+                for j in 0..parameters {
+                    xtx[i][j] += x[i] * x[j] * n;
+                }
+            }
+        }
+
+        let inverse = invert(xtx).ok_or(CostModelError::Singular)?;
+        let coefficients: Vec<f64> = inverse
+            .iter()
+            .map(|row| row.iter().zip(&xty).map(|(a, b)| a * b).sum())
+            .collect();
+
+        let mut residual_sum = 0.0;
+        for (block, costs) in &samples {
+            let x = regressors(block);
+            let predicted: f64 = x.iter().zip(&coefficients).map(|(a, b)| a * b).sum();
+            residual_sum += costs.iter().map(|c| (c - predicted).powi(2)).sum::<f64>();
+        }
+        let variance = residual_sum / (observations - parameters) as f64;
+
+        let estimate = |i: usize| Estimate {
+            cost: coefficients[i],
+            std_error: (variance * inverse[i][i]).max(0.0).sqrt(),
+        };
+        Ok(CostModel {
+            overhead: estimate(0),
+            operators: symbols
+                .iter()
+                .enumerate()
+                .map(|(i, symbol)| (*symbol, estimate(i + 1)))
+                .collect(),
+            observations,
+            residual_stddev: variance.sqrt(),
+        })
+    }
+
+    /// The predicted cost of one execution of `block`. Operators that were not part of
+    /// the fit are counted as free.
+    pub fn predict(&self, block: &CodeBlock) -> f64 {
+        self.overhead.cost
+            + block
+                .operators()
+                .iter()
+                .filter_map(|operator| self.operators.get(operator))
+                .map(|estimate| estimate.cost)
+                .sum::<f64>()
+    }
+
+    /// Writes the estimated cost per operator, most expensive first, followed by the
+    /// per-block overhead.
+    pub fn write_csv(&self, unit: &str, metadata: &Metadata, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        let cost = format!("cost in {}", unit);
+        wtr.write_record(with_metadata_keys(
+            vec!["operator", &cost, "std error", "95% low", "95% high"],
+            metadata,
+        ))
+        .unwrap();
+
+        let mut operators: Vec<(String, &Estimate)> = self
+            .operators
+            .iter()
+            .map(|(symbol, estimate)| (format!("{:?}", symbol), estimate))
+            .collect();
+        operators.sort_by(|(a_name, a), (b_name, b)| {
+            b.cost
+                .partial_cmp(&a.cost)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a_name.cmp(b_name))
+        });
+        operators.push(("(overhead)".to_string(), &self.overhead));
+
+        for (name, estimate) in operators {
+            let (low, high) = estimate.confidence_interval();
+            wtr.write_record(with_metadata_values(
+                vec![
+                    name,
+                    format!("{:.3}", estimate.cost),
+                    format!("{:.3}", estimate.std_error),
+                    format!("{:.3}", low),
+                    format!("{:.3}", high),
+                ],
+                metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+}
+
+/// Inverts a symmetric matrix with Gauss-Jordan elimination and partial pivoting.
+/// Returns `None` if the matrix is singular.
+fn invert(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let scale = matrix
+        .iter()
+        .flatten()
+        .fold(0.0_f64, |max, value| max.max(value.abs()));
+    let epsilon = scale * 1e-12;
+    let mut inverse: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+
+    for column in 0..n {
+        let pivot = (column..n).max_by(|a, b| {
+            matrix[*a][column]
+                .abs()
+                .partial_cmp(&matrix[*b][column].abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if matrix[pivot][column].abs() <= epsilon {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+
+        let divisor = matrix[column][column];
+        for j in 0..n {
+            matrix[column][j] /= divisor;
+            inverse[column][j] /= divisor;
+        }
+        for row in 0..n {
+            if row == column {
+                continue;
+            }
+            let factor = matrix[row][column];
+            if factor == 0.0 {
+                continue;
+            }
+            for j in 0..n {
+                matrix[row][j] -= factor * matrix[column][j];
+                inverse[row][j] -= factor * inverse[column][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use OperatorSymbol::{I32Add, I32Const, LocalGet};
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn fit_recovers_exact_costs() {
+        // overhead 5, local.get 2, i32.const 1, i32.add 4
+        let mut block_store = BlockStore::new();
+        let a = block_store
+            .register_block(CodeBlock::from(vec![LocalGet, I32Add]))
+            .unwrap();
+        let b = block_store
+            .register_block(CodeBlock::from(vec![I32Const, I32Const]))
+            .unwrap();
+        let c = block_store
+            .register_block(CodeBlock::from(vec![LocalGet, LocalGet, I32Const]))
+            .unwrap();
+        let d = block_store
+            .register_block(CodeBlock::from(vec![I32Add]))
+            .unwrap();
+        let measurements = Measurements::from_samples(&[
+            (a, (0, 0), &[11, 11]),
+            (b, (0, 0), &[7]),
+            (c, (0, 0), &[10]),
+            (d, (0, 0), &[9]),
+        ]);
+
+        let model = CostModel::fit(&measurements, &block_store).unwrap();
+        assert_eq!(model.observations, 5);
+        assert_close(model.overhead.cost, 5.0);
+        assert_close(model.operators[&LocalGet].cost, 2.0);
+        assert_close(model.operators[&I32Const].cost, 1.0);
+        assert_close(model.operators[&I32Add].cost, 4.0);
+        assert_close(model.residual_stddev, 0.0);
+        assert_close(model.operators[&I32Add].std_error, 0.0);
+        assert_close(
+            model.predict(&CodeBlock::from(vec![I32Add, I32Add, LocalGet])),
+            15.0,
+        );
+    }
+
+    #[test]
+    fn fit_reports_confidence_intervals() {
+        let mut block_store = BlockStore::new();
+        let a = block_store
+            .register_block(CodeBlock::from(vec![LocalGet]))
+            .unwrap();
+        let b = block_store
+            .register_block(CodeBlock::from(vec![LocalGet, LocalGet]))
+            .unwrap();
+        let measurements =
+            Measurements::from_samples(&[(a, (0, 0), &[9, 11]), (b, (0, 0), &[12, 14])]);
+
+        let model = CostModel::fit(&measurements, &block_store).unwrap();
+        let local_get = model.operators[&LocalGet];
+        assert_close(local_get.cost, 3.0);
+        assert_close(model.overhead.cost, 7.0);
+        assert!(local_get.std_error > 0.0);
+        let (low, high) = local_get.confidence_interval();
+        assert!(low < 3.0 && 3.0 < high);
+        assert_close(high - low, 2.0 * Z_95 * local_get.std_error);
+    }
+
+    #[test]
+    fn fit_fails_for_inseparable_operators() {
+        let mut block_store = BlockStore::new();
+        // local.get and i32.add always occur together
+        let a = block_store
+            .register_block(CodeBlock::from(vec![LocalGet, I32Add]))
+            .unwrap();
+        let b = block_store
+            .register_block(CodeBlock::from(vec![LocalGet, I32Add, I32Const]))
+            .unwrap();
+        let inseparable =
+            Measurements::from_samples(&[(a, (0, 0), &[3, 4, 3]), (b, (0, 0), &[5, 5])]);
+        assert_eq!(
+            CostModel::fit(&inseparable, &block_store),
+            Err(CostModelError::Singular)
+        );
+
+        let too_few = Measurements::from_samples(&[(a, (0, 0), &[3])]);
+        assert_eq!(
+            CostModel::fit(&too_few, &block_store),
+            Err(CostModelError::NotEnoughData {
+                observations: 1,
+                parameters: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn write_csv_works() {
+        let model = CostModel {
+            overhead: Estimate {
+                cost: 5.0,
+                std_error: 0.5,
+            },
+            operators: vec![
+                (
+                    LocalGet,
+                    Estimate {
+                        cost: 2.0,
+                        std_error: 0.0,
+                    },
+                ),
+                (
+                    I32Add,
+                    Estimate {
+                        cost: 4.0,
+                        std_error: 1.0,
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            observations: 10,
+            residual_stddev: 1.0,
+        };
+        let mut csv = Vec::new();
+        model.write_csv("ns", &Metadata::new(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "operator,cost in ns,std error,95% low,95% high\r\n\
+             I32Add,4.000,1.000,2.040,5.960\r\n\
+             LocalGet,2.000,0.000,2.000,2.000\r\n\
+             (overhead),5.000,0.500,4.020,5.980\r\n"
+        );
+    }
+}
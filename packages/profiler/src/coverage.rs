@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+use crate::code_blocks::BlockStore;
+use crate::instrumentation::BlockOffsets;
+use crate::symbols::{SourceLines, Symbols};
+
+/// Which of the instrumented blocks were executed, per function.
+///
+/// Only blocks registered in the `BlockStore` are known, so blocks skipped by
+/// sampling and functions excluded by a filter are not part of the report. In
+/// [`Granularity::Function`](crate::instrumentation::Granularity::Function), every
+/// function is a single block.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CoverageReport {
+    pub functions: BTreeMap<u32, FunctionCoverage>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FunctionCoverage {
+    /// The number of instrumented blocks of the function
+    pub blocks: usize,
+    /// The local block ids of the blocks that were never executed, sorted
+    pub unexecuted: Vec<u32>,
+}
+
+impl FunctionCoverage {
+    pub fn executed(&self) -> usize {
+        self.blocks - self.unexecuted.len()
+    }
+}
+
+impl CoverageReport {
+    /// Creates a report from the locations of all executed blocks, e.g.
+    /// [`Measurements::executed_blocks`](crate::measure::Measurements::executed_blocks).
+    pub fn new(block_store: &BlockStore, executed: &HashSet<(u32, u32)>) -> Self {
+        let mut functions: BTreeMap<u32, FunctionCoverage> = BTreeMap::new();
+        for ((fn_index, local_block_id), _) in block_store.locations() {
+            let function = functions.entry(fn_index).or_default();
+            function.blocks += 1;
+            if !executed.contains(&(fn_index, local_block_id)) {
+                function.unexecuted.push(local_block_id);
+            }
+        }
+        for function in functions.values_mut() {
+            function.unexecuted.sort_unstable();
+        }
+
+        CoverageReport { functions }
+    }
+
+    /// The total number of instrumented and of executed blocks.
+    pub fn totals(&self) -> (usize, usize) {
+        self.functions
+            .values()
+            .fold((0, 0), |(blocks, executed), function| {
+                (blocks + function.blocks, executed + function.executed())
+            })
+    }
+
+    /// Functions with at least one block that was never executed.
+    pub fn uncovered_functions(&self) -> impl Iterator<Item = (u32, &FunctionCoverage)> {
+        self.functions
+            .iter()
+            .filter(|(_, function)| !function.unexecuted.is_empty())
+            .map(|(fn_index, function)| (*fn_index, function))
+    }
+
+    /// Writes the coverage of all functions, sorted by function index. The ids of
+    /// unexecuted blocks are separated by spaces.
+    pub fn write_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(["function", "blocks", "executed", "unexecuted blocks"])
+            .unwrap();
+
+        for (fn_index, function) in &self.functions {
+            let unexecuted: Vec<String> = function
+                .unexecuted
+                .iter()
+                .map(|local_block_id| local_block_id.to_string())
+                .collect();
+            wtr.write_record(&[
+                symbols.describe_function(*fn_index),
+                function.blocks.to_string(),
+                function.executed().to_string(),
+                unexecuted.join(" "),
+            ])
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    /// Writes the coverage of the source lines of all blocks in the lcov format, so
+    /// that it can be viewed in standard coverage tools.
+    ///
+    /// The number of executions of a line is the largest one of the blocks containing
+    /// it, e.g. [`Measurements::location_executions`](crate::measure::Measurements::location_executions).
+    /// Blocks are found in `block_offsets` and their lines in `source_lines`, both
+    /// read from the Wasm with its debug info, see
+    /// [`block_offsets`](crate::instrumentation::block_offsets). Blocks without source
+    /// lines are left out.
+    pub fn write_lcov(
+        &self,
+        executions: &HashMap<(u32, u32), u64>,
+        block_offsets: &BlockOffsets,
+        source_lines: &SourceLines,
+        mut sink: impl Write,
+    ) -> std::io::Result<()> {
+        let executed = executions
+            .iter()
+            .filter(|((fn_index, _), _)| self.functions.contains_key(fn_index))
+            .map(|(location, count)| (*location, *count));
+        let unexecuted = self.functions.iter().flat_map(|(fn_index, function)| {
+            function
+                .unexecuted
+                .iter()
+                .map(move |local_block_id| ((*fn_index, *local_block_id), 0))
+        });
+
+        let mut files: BTreeMap<&str, BTreeMap<u64, u64>> = BTreeMap::new();
+        for (location, count) in executed.chain(unexecuted) {
+            let (first, last) = match block_offsets.get(&location) {
+                Some(offsets) => *offsets,
+                None => continue,
+            };
+            for (path, line) in source_lines.lines(first, last) {
+                let executions = files.entry(path).or_default().entry(line).or_default();
+                *executions = (*executions).max(count);
+            }
+        }
+
+        for (path, lines) in files {
+            writeln!(sink, "TN:")?;
+            writeln!(sink, "SF:{}", path)?;
+            for (line, count) in &lines {
+                writeln!(sink, "DA:{},{}", line, count)?;
+            }
+            writeln!(sink, "LF:{}", lines.len())?;
+            let hit = lines.values().filter(|count| **count > 0).count();
+            writeln!(sink, "LH:{}", hit)?;
+            writeln!(sink, "end_of_record")?;
+        }
+        sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::BlockId;
+    use crate::instrumentation::{
+        add_measuring_imports, block_offsets, instrument_wasm, Granularity, Profiling,
+    };
+    use crate::measure::Measurements;
+
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn new_works() {
+        let mut block_store = BlockStore::new();
+        for (fn_index, local_block_id) in [(0, 0), (0, 1), (0, 3), (1, 0), (2, 0)] {
+            block_store.register_location(fn_index, local_block_id, BlockId(7));
+        }
+        let executed = [(0, 1), (1, 0), (5, 0)].iter().copied().collect();
+
+        let report = CoverageReport::new(&block_store, &executed);
+        assert_eq!(
+            report.functions[&0],
+            FunctionCoverage {
+                blocks: 3,
+                unexecuted: vec![0, 3],
+            }
+        );
+        assert_eq!(report.functions[&1].executed(), 1);
+        assert_eq!(report.functions[&2].unexecuted, [0]);
+        assert_eq!(report.functions.len(), 3);
+        assert_eq!(report.totals(), (5, 2));
+
+        let uncovered: Vec<u32> = report.uncovered_functions().map(|(i, _)| i).collect();
+        assert_eq!(uncovered, [0, 2]);
+
+        let mut csv = Vec::new();
+        report.write_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,blocks,executed,unexecuted blocks\r\nfn 0,3,1,0 3\r\nfn 1,1,1,\r\nfn 2,1,0,0\r\n"
+        );
+    }
+
+    #[test]
+    fn write_lcov_works() {
+        let wasm = wasmer::wat2wasm(
+            br#"(module
+            (func (param i32) (result i32)
+                local.get 0
+                (if (result i32)
+                    (then i32.const 1)
+                    (else i32.const 2))
+                i32.const 3
+                i32.add))"#,
+        )
+        .unwrap();
+        let offsets = block_offsets(&wasm, Granularity::BasicBlock).unwrap();
+        let mut block_store = BlockStore::new();
+        for local_block_id in 0..3 {
+            block_store.register_location(0, local_block_id, BlockId(7));
+        }
+        let executions: HashMap<_, _> = vec![((0, 0), 4), ((0, 1), 3)].into_iter().collect();
+        let executed = executions.keys().copied().collect();
+        let report = CoverageReport::new(&block_store, &executed);
+
+        // Every block on its own line, the last two in another file
+        let row = |local_block_id, file, line| {
+            let (first, _) = offsets[&(0, local_block_id)];
+            (u64::from(first), Some((file, line)))
+        };
+        let source_lines = SourceLines::from_rows(
+            vec!["src/lib.rs".to_string(), "src/state.rs".to_string()],
+            vec![row(0, 0, 3), row(1, 1, 8), row(2, 1, 9)],
+        );
+
+        let mut lcov = Vec::new();
+        report
+            .write_lcov(&executions, &offsets, &source_lines, &mut lcov)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\nSF:src/lib.rs\nDA:3,4\nLF:1\nLH:1\nend_of_record\n\
+             TN:\nSF:src/state.rs\nDA:8,3\nDA:9,0\nLF:2\nLH:1\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn write_lcov_reads_dwarf_of_rust_build() {
+        use wasmer::CompilerConfig as _;
+
+        // Built by rustc from testdata/debug_info.rs, see there
+        let wasm = std::fs::read("testdata/debug_info.wasm").unwrap();
+        let source_lines = SourceLines::from_wasm(&wasm).unwrap();
+
+        let offsets = block_offsets(&wasm, Granularity::BasicBlock).unwrap();
+        let mut block_store = BlockStore::new();
+        for (fn_index, local_block_id) in offsets.keys() {
+            block_store.register_location(*fn_index, *local_block_id, BlockId(7));
+        }
+        // `add_one` called 3 times, `add_two` up to its first call
+        let executions: HashMap<_, _> = vec![((0, 0), 3), ((1, 0), 1)].into_iter().collect();
+        let executed = executions.keys().copied().collect();
+        let mut lcov = Vec::new();
+        CoverageReport::new(&block_store, &executed)
+            .write_lcov(&executions, &offsets, &source_lines, &mut lcov)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\nSF:/contract/debug_info.rs\n\
+             DA:36,3\nDA:37,3\nDA:41,1\nDA:42,1\nDA:43,0\nDA:44,0\n\
+             LF:6\nLH:4\nend_of_record\n"
+        );
+
+        // Profile `add_two` in the instrumented Wasm, whose functions are reordered
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::BasicBlock));
+        let prepared = instrument_wasm(&wasm, &profiling).unwrap();
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling.clone());
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &prepared).unwrap();
+        let measurements = Arc::new(Mutex::new(Measurements::new()));
+        let mut imports = wasmer::Exports::new();
+        add_measuring_imports(&profiling, &store, measurements.clone(), &mut imports);
+        let mut import_object = wasmer::ImportObject::new();
+        import_object.register(profiling.import_module(), imports);
+        let instance = wasmer::Instance::new(&module, &import_object).unwrap();
+        let add_two = instance.exports.get_function("add_two").unwrap();
+        assert_eq!(
+            add_two.call(&[wasmer::Val::I32(5)]).unwrap()[0],
+            wasmer::Val::I32(7)
+        );
+
+        let measurements = measurements.lock().unwrap();
+        let report =
+            CoverageReport::new(&block_store.lock().unwrap(), &measurements.executed_blocks);
+        let mut lcov = Vec::new();
+        report
+            .write_lcov(
+                &measurements.location_executions,
+                &profiling.block_offsets(),
+                &source_lines,
+                &mut lcov,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\nSF:/contract/debug_info.rs\n\
+             DA:36,2\nDA:37,2\nDA:41,1\nDA:42,1\nDA:43,1\nDA:44,1\n\
+             LF:6\nLH:6\nend_of_record\n"
+        );
+    }
+}
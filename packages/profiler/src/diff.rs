@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+
+use crate::code_blocks::BlockId;
+use crate::measure::Metadata;
+use crate::report::{BlockReport, FunctionReport, Report, Thresholds};
+use crate::utils::{with_metadata_keys, with_metadata_values};
+
+/// How a function changed between two builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Regression,
+    Improvement,
+    Unchanged,
+    /// Only part of the new build
+    Added,
+    /// Only part of the old build
+    Removed,
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::Regression => "regression",
+            Change::Improvement => "improvement",
+            Change::Unchanged => "unchanged",
+            Change::Added => "added",
+            Change::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDiff {
+    /// The name the functions were matched by
+    pub name: String,
+    pub old: Option<FunctionReport>,
+    pub new: Option<FunctionReport>,
+    pub change: Change,
+}
+
+/// How the measured blocks of two builds relate, matched by the hash of their code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDiffSummary {
+    /// Blocks measured in both builds
+    pub common: usize,
+    /// Blocks measured in both builds with a different number of executions
+    pub execution_changes: usize,
+    /// Blocks only measured in the new build
+    pub added: usize,
+    /// Blocks only measured in the old build
+    pub removed: usize,
+}
+
+/// The differences between the reports of two builds of a contract, e.g. before and
+/// after a change.
+///
+/// Function indexes usually differ between builds, so functions are matched by
+/// name; functions without a name in the report (see [`Report::with_symbols`]) are
+/// matched by index. Blocks are matched by the hash of their code, see
+/// [`Report::with_blocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportDiff {
+    pub unit: String,
+    /// Sorted by name
+    pub functions: Vec<FunctionDiff>,
+    pub blocks: BlockDiffSummary,
+}
+
+impl ReportDiff {
+    /// A function regressed if its inclusive cost or block count increased beyond
+    /// `thresholds`, and improved if either decreased by more than that without the
+    /// other regressing.
+    pub fn new(old: &Report, new: &Report, thresholds: &Thresholds) -> Self {
+        let old_functions = by_name(old);
+        let mut new_functions = by_name(new);
+
+        let mut functions: Vec<FunctionDiff> = old_functions
+            .into_iter()
+            .map(|(name, old)| {
+                let new = new_functions.remove(&name);
+                let change = match new {
+                    Some(new) => compare(old, new, thresholds),
+                    None => Change::Removed,
+                };
+                FunctionDiff {
+                    name,
+                    old: Some(old.clone()),
+                    new: new.cloned(),
+                    change,
+                }
+            })
+            .collect();
+        functions.extend(new_functions.into_iter().map(|(name, new)| FunctionDiff {
+            name,
+            old: None,
+            new: Some(new.clone()),
+            change: Change::Added,
+        }));
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        ReportDiff {
+            unit: new.unit.clone(),
+            functions,
+            blocks: compare_blocks(&old.blocks, &new.blocks),
+        }
+    }
+
+    pub fn regressions(&self) -> impl Iterator<Item = &FunctionDiff> {
+        self.functions
+            .iter()
+            .filter(|function| function.change == Change::Regression)
+    }
+
+    pub fn improvements(&self) -> impl Iterator<Item = &FunctionDiff> {
+        self.functions
+            .iter()
+            .filter(|function| function.change == Change::Improvement)
+    }
+
+    /// Writes all functions that are not unchanged. Costs of functions missing from
+    /// one of the builds are left empty.
+    pub fn write_csv(&self, metadata: &Metadata, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        let old_inclusive = format!("old inclusive in {}", self.unit);
+        let new_inclusive = format!("new inclusive in {}", self.unit);
+        wtr.write_record(with_metadata_keys(
+            vec![
+                "function",
+                "change",
+                &old_inclusive,
+                &new_inclusive,
+                "inclusive change in %",
+                "old blocks",
+                "new blocks",
+            ],
+            metadata,
+        ))
+        .unwrap();
+
+        let changed = self
+            .functions
+            .iter()
+            .filter(|function| function.change != Change::Unchanged);
+        for function in changed {
+            let inclusive = |report: &Option<FunctionReport>| {
+                report.as_ref().map(|report| report.inclusive.to_string())
+            };
+            let blocks = |report: &Option<FunctionReport>| {
+                report.as_ref().map(|report| report.blocks.to_string())
+            };
+            let percent = match (&function.old, &function.new) {
+                (Some(old), Some(new)) if old.inclusive > 0 => format!(
+                    "{:+.1}",
+                    (new.inclusive as f64 - old.inclusive as f64) / old.inclusive as f64 * 100.0
+                ),
+                _ => String::new(),
+            };
+            wtr.write_record(with_metadata_values(
+                vec![
+                    function.name.clone(),
+                    function.change.as_str().to_string(),
+                    inclusive(&function.old).unwrap_or_default(),
+                    inclusive(&function.new).unwrap_or_default(),
+                    percent,
+                    blocks(&function.old).unwrap_or_default(),
+                    blocks(&function.new).unwrap_or_default(),
+                ],
+                metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+}
+
+fn by_name(report: &Report) -> BTreeMap<String, &FunctionReport> {
+    report
+        .functions
+        .iter()
+        .map(|(fn_index, function)| {
+            let name = function
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("fn {}", fn_index));
+            (name, function)
+        })
+        .collect()
+}
+
+fn compare(old: &FunctionReport, new: &FunctionReport, thresholds: &Thresholds) -> Change {
+    let checks = [
+        (old.inclusive, new.inclusive, thresholds.time_percent),
+        (old.blocks, new.blocks, thresholds.blocks_percent),
+    ];
+    let regressed = checks
+        .iter()
+        .any(|(old, new, percent)| *new as f64 > *old as f64 * (1.0 + percent / 100.0));
+    let improved = checks
+        .iter()
+        .any(|(old, new, percent)| (*new as f64) < *old as f64 * (1.0 - percent / 100.0));
+    if regressed {
+        Change::Regression
+    } else if improved {
+        Change::Improvement
+    } else {
+        Change::Unchanged
+    }
+}
+
+fn compare_blocks(
+    old: &BTreeMap<BlockId, BlockReport>,
+    new: &BTreeMap<BlockId, BlockReport>,
+) -> BlockDiffSummary {
+    let mut summary = BlockDiffSummary::default();
+    for (block_id, old_block) in old {
+        match new.get(block_id) {
+            Some(new_block) => {
+                summary.common += 1;
+                if new_block.executions != old_block.executions {
+                    summary.execution_changes += 1;
+                }
+            }
+            None => summary.removed += 1,
+        }
+    }
+    summary.added = new.len() - summary.common;
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(functions: &[(u32, Option<&str>, u64, u64)], blocks: &[(u64, u64)]) -> Report {
+        Report {
+            unit: "ns".to_string(),
+            functions: functions
+                .iter()
+                .map(|(fn_index, name, inclusive, blocks)| {
+                    let report = FunctionReport {
+                        name: name.map(str::to_string),
+                        calls: 1,
+                        inclusive: *inclusive,
+                        exclusive: *inclusive,
+                        blocks: *blocks,
+                        host: 0,
+                    };
+                    (*fn_index, report)
+                })
+                .collect(),
+            blocks: blocks
+                .iter()
+                .map(|(block_id, executions)| {
+                    let block = BlockReport {
+                        executions: *executions,
+                        cost: *executions * 10,
+                    };
+                    (BlockId(*block_id), block)
+                })
+                .collect(),
+            clock_anomalies: BTreeMap::new(),
+            disassembly: BTreeMap::new(),
+            offsets: BTreeMap::new(),
+            classes: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            max_call_depths: BTreeMap::new(),
+            metadata: Metadata::new(),
+        }
+    }
+
+    #[test]
+    fn new_matches_functions_by_name() {
+        let old = report(
+            &[
+                (0, Some("execute"), 100, 10),
+                (1, Some("query"), 50, 5),
+                (2, Some("helper"), 30, 3),
+                (3, None, 20, 2),
+            ],
+            &[],
+        );
+        // The functions moved to other indexes
+        let new = report(
+            &[
+                (0, Some("query"), 50, 4),
+                (1, Some("execute"), 120, 10),
+                (2, Some("new_helper"), 10, 1),
+                (3, None, 20, 2),
+            ],
+            &[],
+        );
+
+        let diff = ReportDiff::new(&old, &new, &Thresholds::default());
+        let changes: Vec<(&str, Change)> = diff
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.change))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("execute", Change::Regression),
+                ("fn 3", Change::Unchanged),
+                ("helper", Change::Removed),
+                ("new_helper", Change::Added),
+                ("query", Change::Improvement),
+            ]
+        );
+        let regression = diff.regressions().next().unwrap();
+        assert_eq!(regression.old.as_ref().unwrap().inclusive, 100);
+        assert_eq!(regression.new.as_ref().unwrap().inclusive, 120);
+        assert_eq!(diff.improvements().count(), 1);
+    }
+
+    #[test]
+    fn new_compares_blocks_by_hash() {
+        let old = report(&[], &[(1, 5), (2, 5), (3, 1)]);
+        let new = report(&[], &[(1, 5), (2, 6), (4, 1), (5, 1)]);
+        let diff = ReportDiff::new(&old, &new, &Thresholds::default());
+        assert_eq!(
+            diff.blocks,
+            BlockDiffSummary {
+                common: 2,
+                execution_changes: 1,
+                added: 2,
+                removed: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn write_csv_works() {
+        let old = report(
+            &[(0, Some("execute"), 100, 10), (1, Some("query"), 50, 5)],
+            &[],
+        );
+        let new = report(
+            &[(0, Some("execute"), 150, 12), (2, Some("added"), 7, 1)],
+            &[],
+        );
+        let diff = ReportDiff::new(&old, &new, &Thresholds::default());
+
+        let mut csv = Vec::new();
+        diff.write_csv(&Metadata::new(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,change,old inclusive in ns,new inclusive in ns,inclusive change in %,old blocks,new blocks\r\n\
+             added,added,,7,,,1\r\n\
+             execute,regression,100,150,+50.0,10,12\r\n\
+             query,removed,50,,,5,\r\n"
+        );
+    }
+}
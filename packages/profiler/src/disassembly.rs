@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use wasmer::wasmparser::{Operator, Type, TypeOrFuncType};
+
+use crate::instrumentation::{ends_block, Granularity, InstrumentationError};
+use crate::operators::{memarg, OperatorSymbol};
+
+/// The instructions of every block of a Wasm module in the WebAssembly text format,
+/// to show what the measured blocks actually execute.
+///
+/// Blocks are found by walking the code like the
+/// [`Profiling`](crate::instrumentation::Profiling) middleware does, so create the
+/// disassembly from the Wasm returned by
+/// [`instrument_wasm`](crate::instrumentation::instrument_wasm) and with the same
+/// granularity. Then the locations match the ones of
+/// [`Measurements::block_locations`](crate::measure::Measurements::block_locations).
+///
+/// Unlike the operators of a [`CodeBlock`](crate::code_blocks::CodeBlock), the
+/// instructions include their immediates, e.g. `i32.const 42` or
+/// `i64.load offset=8 align=8`. Calls use function indexes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    /// The instructions of every block, keyed by function index and local block id
+    blocks: HashMap<(u32, u32), Vec<String>>,
+}
+
+impl Disassembly {
+    pub fn from_wasm(wasm: &[u8], granularity: Granularity) -> Result<Self, InstrumentationError> {
+        use wasmer::wasmparser::{Parser, Payload};
+
+        let parse_err =
+            |err: wasmer::wasmparser::BinaryReaderError| InstrumentationError::ParseErr {
+                msg: err.to_string(),
+            };
+
+        let mut blocks = HashMap::new();
+        let mut fn_index = 0;
+        for payload in Parser::new(0).parse_all(wasm) {
+            if let Payload::CodeSectionEntry(body) = payload.map_err(parse_err)? {
+                let mut reader = body.get_operators_reader().map_err(parse_err)?;
+                match granularity {
+                    Granularity::BasicBlock => {
+                        let mut local_block_id = 0;
+                        let mut current = Vec::new();
+                        while !reader.eof() {
+                            let operator = reader.read().map_err(parse_err)?;
+                            if !ends_block(&operator) {
+                                current.push(instruction(&operator));
+                            } else if !current.is_empty() {
+                                blocks.insert(
+                                    (fn_index, local_block_id),
+                                    std::mem::take(&mut current),
+                                );
+                                local_block_id += 1;
+                            }
+                        }
+                    }
+                    Granularity::Function => {
+                        let mut instructions = Vec::new();
+                        while !reader.eof() {
+                            instructions.push(instruction(&reader.read().map_err(parse_err)?));
+                        }
+                        blocks.insert((fn_index, 0), instructions);
+                    }
+                }
+                fn_index += 1;
+            }
+        }
+        Ok(Disassembly { blocks })
+    }
+
+    /// The instructions of the block at a location, one per entry.
+    pub fn block(&self, fn_index: u32, local_block_id: u32) -> Option<&[String]> {
+        self.blocks
+            .get(&(fn_index, local_block_id))
+            .map(|instructions| instructions.as_slice())
+    }
+
+    /// The instructions of the block at a location, one per line.
+    pub fn snippet(&self, fn_index: u32, local_block_id: u32) -> Option<String> {
+        self.block(fn_index, local_block_id)
+            .map(|instructions| instructions.join("\n"))
+    }
+}
+
+/// Formats an operator with its immediates in the WebAssembly text format.
+fn instruction(operator: &Operator) -> String {
+    let mnemonic = OperatorSymbol::from(operator).mnemonic();
+    let immediates = match operator {
+        Operator::I32Const { value } => value.to_string(),
+        Operator::I64Const { value } => value.to_string(),
+        Operator::F32Const { value } => f32::from_bits(value.bits()).to_string(),
+        Operator::F64Const { value } => f64::from_bits(value.bits()).to_string(),
+        Operator::LocalGet { local_index }
+        | Operator::LocalSet { local_index }
+        | Operator::LocalTee { local_index } => local_index.to_string(),
+        Operator::GlobalGet { global_index } | Operator::GlobalSet { global_index } => {
+            global_index.to_string()
+        }
+        Operator::Call { function_index } | Operator::RefFunc { function_index } => {
+            function_index.to_string()
+        }
+        Operator::CallIndirect { index, .. } => format!("(type {})", index),
+        Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+            relative_depth.to_string()
+        }
+        Operator::BrTable { table } => table
+            .targets()
+            .filter_map(|target| target.ok())
+            .map(|(relative_depth, _)| relative_depth.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        Operator::Block { ty } | Operator::Loop { ty } | Operator::If { ty } => block_type(ty),
+        _ => match memarg(operator) {
+            Some(memarg) if memarg.offset == 0 => format!("align={}", 1u32 << memarg.align),
+            Some(memarg) => format!("offset={} align={}", memarg.offset, 1u32 << memarg.align),
+            None => String::new(),
+        },
+    };
+    if immediates.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, immediates)
+    }
+}
+
+fn block_type(ty: &TypeOrFuncType) -> String {
+    match ty {
+        TypeOrFuncType::Type(Type::EmptyBlockType) => String::new(),
+        TypeOrFuncType::Type(ty) => {
+            let name = match ty {
+                Type::I32 => "i32",
+                Type::I64 => "i64",
+                Type::F32 => "f32",
+                Type::F64 => "f64",
+                Type::V128 => "v128",
+                Type::FuncRef => "funcref",
+                Type::ExternRef => "externref",
+                _ => "?",
+            };
+            format!("(result {})", name)
+        }
+        TypeOrFuncType::FuncType(index) => format!("(type {})", index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::wat2wasm;
+
+    const WAT: &[u8] = br#"
+    (module
+    (memory 1)
+    (func $load (param i32) (result i64)
+        local.get 0
+        i64.load offset=8
+        (block (result i64)
+            i64.const -3
+            local.get 0
+            br_if 0)
+        i64.add))
+    "#;
+
+    #[test]
+    fn from_wasm_works_in_basic_block_granularity() {
+        let wasm = wat2wasm(WAT).unwrap();
+        let disassembly = Disassembly::from_wasm(&wasm, Granularity::BasicBlock).unwrap();
+        assert_eq!(
+            disassembly.block(0, 0).unwrap(),
+            [
+                "local.get 0",
+                "i64.load offset=8 align=8",
+                "block (result i64)",
+                "i64.const -3",
+                "local.get 0",
+            ]
+        );
+        assert_eq!(disassembly.snippet(0, 1).unwrap(), "i64.add");
+        assert_eq!(disassembly.block(0, 2), None);
+    }
+
+    #[test]
+    fn from_wasm_works_in_function_granularity() {
+        let wasm = wat2wasm(WAT).unwrap();
+        let disassembly = Disassembly::from_wasm(&wasm, Granularity::Function).unwrap();
+        let function = disassembly.block(0, 0).unwrap();
+        assert_eq!(function.len(), 9);
+        assert_eq!(function[5], "br_if 0");
+        assert_eq!(function[8], "end");
+        assert_eq!(disassembly.block(0, 1), None);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::instrumentation::{ends_block, InstrumentationError};
+use crate::operators::OperatorSymbol;
+use crate::symbols::Symbols;
+
+/// The basic blocks of a contract that use floating-point operators, which make
+/// contract execution non-deterministic. Chains reject such contracts on upload,
+/// this report tells where the floats are.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FloatReport {
+    /// Sorted by location
+    pub blocks: Vec<FloatBlock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FloatBlock {
+    pub fn_index: u32,
+    pub local_block_id: u32,
+    /// The float operators of the block, each listed once in order of first use
+    pub operators: Vec<OperatorSymbol>,
+}
+
+impl FloatReport {
+    /// Finds the float operators of all functions of a Wasm module.
+    ///
+    /// Functions are split into basic blocks like the
+    /// [`Profiling`](crate::instrumentation::Profiling) middleware does in basic block
+    /// granularity, so function indexes and local block ids match the ones of
+    /// measurements. This does not compile the module, which fails for contracts
+    /// using floats.
+    pub fn from_wasm(wasm: &[u8]) -> Result<Self, InstrumentationError> {
+        use wasmer::wasmparser::{Parser, Payload};
+
+        let parse_err =
+            |err: wasmer::wasmparser::BinaryReaderError| InstrumentationError::ParseErr {
+                msg: err.to_string(),
+            };
+
+        let mut blocks = Vec::new();
+        let mut fn_index = 0;
+        for payload in Parser::new(0).parse_all(wasm) {
+            if let Payload::CodeSectionEntry(body) = payload.map_err(parse_err)? {
+                let mut blocks_seen = 0;
+                let mut block_len = 0;
+                let mut operators: Vec<OperatorSymbol> = Vec::new();
+                let mut reader = body.get_operators_reader().map_err(parse_err)?;
+                while !reader.eof() {
+                    let operator = reader.read().map_err(parse_err)?;
+                    if ends_block(&operator) {
+                        if block_len > 0 {
+                            if !operators.is_empty() {
+                                blocks.push(FloatBlock {
+                                    fn_index,
+                                    local_block_id: blocks_seen - 1,
+                                    operators: std::mem::take(&mut operators),
+                                });
+                            }
+                            block_len = 0;
+                        }
+                        continue;
+                    }
+
+                    if block_len == 0 {
+                        blocks_seen += 1;
+                    }
+                    block_len += 1;
+                    let symbol = OperatorSymbol::from(&operator);
+                    if symbol.is_float() && !operators.contains(&symbol) {
+                        operators.push(symbol);
+                    }
+                }
+                fn_index += 1;
+            }
+        }
+        Ok(FloatReport { blocks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Writes one row per block with the float operators separated by spaces.
+    pub fn write_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(["function", "block", "float operators"])
+            .unwrap();
+
+        for block in &self.blocks {
+            let operators: Vec<String> = block
+                .operators
+                .iter()
+                .map(|operator| format!("{:?}", operator))
+                .collect();
+            wtr.write_record(&[
+                symbols.describe_function(block.fn_index),
+                block.local_block_id.to_string(),
+                operators.join(" "),
+            ])
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::wat2wasm;
+
+    const WAT: &[u8] = br#"
+    (module
+    (func $ints (export "ints") (param i32) (result i32)
+        local.get 0
+        i32.const 1
+        i32.add)
+    (func $floats (export "floats") (param i32) (result i32)
+        local.get 0
+        call $ints
+        drop
+        local.get 0
+        f32.convert_i32_s
+        f32.const 1.5
+        f32.mul
+        f32.const 0.5
+        f32.add
+        i32.trunc_f32_s))
+    "#;
+
+    #[test]
+    fn from_wasm_finds_float_blocks() {
+        let report = FloatReport::from_wasm(&wat2wasm(WAT).unwrap()).unwrap();
+        assert_eq!(
+            report.blocks,
+            [FloatBlock {
+                fn_index: 1,
+                local_block_id: 1,
+                operators: vec![
+                    OperatorSymbol::F32ConvertI32S,
+                    OperatorSymbol::F32Const,
+                    OperatorSymbol::F32Mul,
+                    OperatorSymbol::F32Add,
+                    OperatorSymbol::I32TruncF32S,
+                ],
+            }]
+        );
+
+        let wasm = wat2wasm(br#"(module (func (export "ints") (result i32) i32.const 1))"#);
+        assert!(FloatReport::from_wasm(&wasm.unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_csv_works() {
+        let report = FloatReport::from_wasm(&wat2wasm(WAT).unwrap()).unwrap();
+        let mut csv = Vec::new();
+        report.write_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,block,float operators\r\nfn 1,1,F32ConvertI32S F32Const F32Mul F32Add I32TruncF32S\r\n"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasmer::wasmparser::Operator;
+
+use crate::cost_model::CostModel;
+use crate::operators::OperatorSymbol;
+
+/// The CosmWasm gas target of 1 Teragas per millisecond (see docs/GAS.md) in gas
+/// per nanosecond, i.e. the `gas_per_unit` for measurements of the wall clock.
+pub const GAS_PER_NANOSECOND: f64 = 1_000_000.0;
+
+/// The flat fee per operation `cosmwasm-vm` currently charges.
+pub const DEFAULT_OPERATOR_COST: u64 = 150_000;
+
+/// A gas cost per operator, as consumed by the metering middleware of `cosmwasm-vm`.
+///
+/// The schedule can be used directly as the cost function of the middleware via
+/// [`GasSchedule::cost`], stored as JSON, or written as the Rust source of a `cost`
+/// function with [`GasSchedule::write_rust`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasSchedule {
+    /// The cost of operators without an entry in `costs`
+    pub default_cost: u64,
+    pub costs: BTreeMap<OperatorSymbol, u64>,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        GasSchedule {
+            default_cost: DEFAULT_OPERATOR_COST,
+            costs: BTreeMap::new(),
+        }
+    }
+}
+
+impl GasSchedule {
+    /// Converts the fitted operator costs to gas. `gas_per_unit` is the gas that
+    /// one unit of the clock the model was measured with is worth, e.g.
+    /// [`GAS_PER_NANOSECOND`].
+    ///
+    /// The per-block overhead of the model is not part of the schedule, since it is
+    /// caused by the measurement. Every operator costs at least 1 gas, so estimates
+    /// that are zero or negative due to noise do not make an operator free.
+    pub fn from_cost_model(model: &CostModel, gas_per_unit: f64) -> Self {
+        let costs = model
+            .operators
+            .iter()
+            .map(|(symbol, estimate)| {
+                let gas = (estimate.cost * gas_per_unit).round().max(1.0);
+                (*symbol, gas as u64)
+            })
+            .collect();
+        GasSchedule {
+            costs,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the cost of operators that were not measured.
+    pub fn with_default_cost(mut self, default_cost: u64) -> Self {
+        self.default_cost = default_cost;
+        self
+    }
+
+    /// The gas cost of `operator`. This has the signature of the cost function of
+    /// `wasmer_middlewares::Metering`.
+    pub fn cost(&self, operator: &Operator) -> u64 {
+        self.costs
+            .get(&OperatorSymbol::from(operator))
+            .copied()
+            .unwrap_or(self.default_cost)
+    }
+
+    /// Writes a Rust function `cost(operator: &Operator) -> u64` implementing this
+    /// schedule, which can replace the flat fee in `cosmwasm-vm`.
+    pub fn write_rust(&self, mut sink: impl std::io::Write) -> std::io::Result<()> {
+        writeln!(
+            sink,
+            "// Generated by cosmwasm-profiler from measured operator costs"
+        )?;
+        writeln!(sink, "fn cost(operator: &Operator) -> u64 {{")?;
+        writeln!(sink, "    match operator {{")?;
+        for (symbol, cost) in &self.costs {
+            writeln!(sink, "        Operator::{:?} {{ .. }} => {},", symbol, cost)?;
+        }
+        writeln!(sink, "        _ => {},", self.default_cost)?;
+        writeln!(sink, "    }}")?;
+        writeln!(sink, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cost_model::Estimate;
+
+    fn schedule() -> GasSchedule {
+        let estimate = |cost| Estimate {
+            cost,
+            std_error: 0.1,
+        };
+        let model = CostModel {
+            overhead: estimate(20.0),
+            operators: vec![
+                (OperatorSymbol::I32Add, estimate(0.25)),
+                (OperatorSymbol::LocalGet, estimate(0.1234567)),
+                (OperatorSymbol::Nop, estimate(-0.01)),
+            ]
+            .into_iter()
+            .collect(),
+            observations: 100,
+            residual_stddev: 1.0,
+        };
+        GasSchedule::from_cost_model(&model, GAS_PER_NANOSECOND)
+    }
+
+    #[test]
+    fn from_cost_model_works() {
+        let schedule = schedule();
+        assert_eq!(schedule.default_cost, DEFAULT_OPERATOR_COST);
+        assert_eq!(
+            schedule.costs.into_iter().collect::<Vec<_>>(),
+            [
+                (OperatorSymbol::Nop, 1),
+                (OperatorSymbol::LocalGet, 123457),
+                (OperatorSymbol::I32Add, 250000),
+            ]
+        );
+    }
+
+    #[test]
+    fn cost_works() {
+        let schedule = schedule().with_default_cost(7);
+        assert_eq!(schedule.cost(&Operator::I32Add), 250000);
+        assert_eq!(
+            schedule.cost(&Operator::LocalGet { local_index: 3 }),
+            123457
+        );
+        assert_eq!(schedule.cost(&Operator::I64Add), 7);
+    }
+
+    #[test]
+    fn json_roundtrip_works() {
+        let schedule = schedule();
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert_eq!(
+            json,
+            r#"{"default_cost":150000,"costs":{"Nop":1,"LocalGet":123457,"I32Add":250000}}"#
+        );
+        let parsed: GasSchedule = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, schedule);
+    }
+
+    #[test]
+    fn write_rust_works() {
+        let mut source = Vec::new();
+        schedule().write_rust(&mut source).unwrap();
+        assert_eq!(
+            String::from_utf8(source).unwrap(),
+            "// Generated by cosmwasm-profiler from measured operator costs\n\
+             fn cost(operator: &Operator) -> u64 {\n    \
+                 match operator {\n        \
+                     Operator::Nop { .. } => 1,\n        \
+                     Operator::LocalGet { .. } => 123457,\n        \
+                     Operator::I32Add { .. } => 250000,\n        \
+                     _ => 150000,\n    \
+                 }\n\
+             }\n"
+        );
+    }
+
+    // The generated patterns are valid for operators with and without fields
+    fn generated_patterns_compile(operator: &Operator) -> u64 {
+        match operator {
+            Operator::Nop { .. } => 1,
+            Operator::LocalGet { .. } => 123457,
+            _ => 150000,
+        }
+    }
+
+    #[test]
+    fn generated_patterns_match() {
+        assert_eq!(generated_patterns_compile(&Operator::Nop), 1);
+        assert_eq!(
+            generated_patterns_compile(&Operator::LocalGet { local_index: 0 }),
+            123457
+        );
+    }
+}
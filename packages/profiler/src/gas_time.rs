@@ -0,0 +1,236 @@
+use crate::clock::Clock;
+use crate::code_blocks::BlockId;
+use crate::measure::{Measurements, Metadata};
+use crate::symbols::Symbols;
+use crate::utils::{with_metadata_keys, with_metadata_values};
+
+/// The gas charged for every block next to the time it took, to find blocks whose
+/// operators are mispriced by the gas schedule.
+///
+/// Needs measurements taken with
+/// [`Profiling::with_gas_tracking`](crate::instrumentation::Profiling::with_gas_tracking).
+/// Blocks are compared by their cost per gas relative to the median of all blocks: a
+/// block with a relative cost of 3 takes three times as long per gas as the typical
+/// block, so it is cheap for what it costs the node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GasTimeReport {
+    /// The unit of the cost, see [`Clock::UNIT`]
+    pub unit: String,
+    /// Sorted by relative cost, the most underpriced blocks first
+    pub blocks: Vec<GasTimeBlock>,
+    /// The median cost per gas of all blocks
+    pub median_cost_per_gas: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasTimeBlock {
+    pub block_id: BlockId,
+    pub fn_index: u32,
+    pub local_block_id: u32,
+    pub executions: u64,
+    /// The gas charged for all executions
+    pub gas: u64,
+    /// The cost of all executions in the unit of the clock
+    pub cost: u128,
+    /// The cost per gas divided by the median cost per gas of all blocks
+    pub relative_cost: f64,
+}
+
+impl GasTimeBlock {
+    pub fn cost_per_gas(&self) -> f64 {
+        self.cost as f64 / self.gas as f64
+    }
+}
+
+impl GasTimeReport {
+    /// Correlates the gas and the cost of all blocks that were charged any gas.
+    pub fn new<C: Clock>(measurements: &Measurements<C>) -> Self {
+        let mut blocks: Vec<GasTimeBlock> = measurements
+            .gas
+            .iter()
+            .filter(|(_, gas)| **gas > 0)
+            .filter_map(|(block_id, gas)| {
+                let timings = measurements.taken.get(block_id)?;
+                let (fn_index, local_block_id) = measurements.block_locations[block_id];
+                Some(GasTimeBlock {
+                    block_id: *block_id,
+                    fn_index,
+                    local_block_id,
+                    executions: timings.len() as u64,
+                    gas: *gas,
+                    cost: timings.iter().map(|t| C::to_units(*t)).sum(),
+                    relative_cost: 0.0,
+                })
+            })
+            .collect();
+
+        let mut costs_per_gas: Vec<f64> = blocks.iter().map(|b| b.cost_per_gas()).collect();
+        costs_per_gas.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median_cost_per_gas = match costs_per_gas.len() {
+            0 => 0.0,
+            len if len % 2 == 1 => costs_per_gas[len / 2],
+            len => (costs_per_gas[len / 2 - 1] + costs_per_gas[len / 2]) / 2.0,
+        };
+
+        if median_cost_per_gas > 0.0 {
+            for block in &mut blocks {
+                block.relative_cost = block.cost_per_gas() / median_cost_per_gas;
+            }
+        }
+        blocks.sort_by(|a, b| {
+            b.relative_cost
+                .partial_cmp(&a.relative_cost)
+                .unwrap()
+                .then(a.block_id.cmp(&b.block_id))
+        });
+
+        GasTimeReport {
+            unit: C::UNIT.to_string(),
+            blocks,
+            median_cost_per_gas,
+        }
+    }
+
+    /// The blocks whose cost per gas is more than `factor` times higher or lower than
+    /// the median.
+    pub fn mispriced(&self, factor: f64) -> impl Iterator<Item = &GasTimeBlock> {
+        self.blocks
+            .iter()
+            .filter(move |block| block.relative_cost > factor || block.relative_cost * factor < 1.0)
+    }
+
+    /// Writes one row per block in the order of [`GasTimeReport::blocks`].
+    pub fn write_csv(&self, symbols: &Symbols, metadata: &Metadata, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            [
+                "function".to_string(),
+                "block".to_string(),
+                "block id".to_string(),
+                "executions".to_string(),
+                "gas".to_string(),
+                format!("cost in {}", self.unit),
+                format!("{} per gas", self.unit),
+                "relative cost".to_string(),
+            ],
+            metadata,
+        ))
+        .unwrap();
+
+        for block in &self.blocks {
+            wtr.write_record(with_metadata_values(
+                [
+                    symbols.describe_function(block.fn_index),
+                    block.local_block_id.to_string(),
+                    block.block_id.as_u64().to_string(),
+                    block.executions.to_string(),
+                    block.gas.to_string(),
+                    block.cost.to_string(),
+                    format!("{:.6}", block.cost_per_gas()),
+                    format!("{:.2}", block.relative_cost),
+                ],
+                metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use cosmwasm_std::Empty;
+    use cosmwasm_vm::testing::{mock_backend, mock_env, mock_info};
+    use cosmwasm_vm::{call_instantiate, Instance};
+
+    use crate::code_blocks::BlockStore;
+    use crate::instrumentation::{Granularity, Profiling, GAS_LIMIT};
+    use crate::vm::VmProfiling;
+
+    static HACKATOM: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+    #[test]
+    fn new_works() {
+        let mut measurements = Measurements::from_samples(&[
+            (BlockId::from(1), (0, 0), &[50, 50]),
+            (BlockId::from(2), (0, 0), &[10]),
+            (BlockId::from(3), (0, 0), &[60, 40]),
+            (BlockId::from(4), (0, 0), &[20]),
+        ]);
+        // Block 4 is not charged
+        for (block_id, gas) in [(1, 100), (2, 100), (3, 10), (4, 0)] {
+            measurements.gas.insert(BlockId::from(block_id), gas);
+        }
+        let report = GasTimeReport::new(&measurements);
+        assert_eq!(report.unit, "ns");
+        assert_eq!(report.median_cost_per_gas, 1.0);
+
+        let blocks: Vec<_> = report
+            .blocks
+            .iter()
+            .map(|block| (block.block_id.as_u64(), block.relative_cost))
+            .collect();
+        assert_eq!(blocks, [(3, 10.0), (1, 1.0), (2, 0.1)]);
+        assert_eq!(report.blocks[0].executions, 2);
+        assert_eq!(report.blocks[0].cost, 100);
+
+        let mispriced: Vec<_> = report
+            .mispriced(2.0)
+            .map(|block| block.block_id.as_u64())
+            .collect();
+        assert_eq!(mispriced, [3, 2]);
+    }
+
+    #[test]
+    fn write_csv_works() {
+        let mut measurements = Measurements::from_samples(&[(BlockId::from(1), (0, 0), &[50, 50])]);
+        measurements.gas.insert(BlockId::from(1), 100);
+        let report = GasTimeReport::new(&measurements);
+        let mut csv = Vec::new();
+        report.write_csv(&Symbols::default(), &Metadata::new(), &mut csv);
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "function,block,block id,executions,gas,cost in ns,ns per gas,relative cost"
+        );
+        assert_eq!(lines.next().unwrap(), "fn 0,0,1,2,100,100,1.000000,1.00");
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn gas_tracking_records_metering_gas() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Profiling::new(block_store, Granularity::BasicBlock).with_gas_tracking();
+        let profiling = VmProfiling::new(profiling, Measurements::new());
+        let options = profiling.instance_options(GAS_LIMIT, false);
+        let mut instance = Instance::from_code(HACKATOM, mock_backend(&[]), options, None).unwrap();
+
+        let info = mock_info("creator", &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        let instantiated = profiling.profile(|| {
+            call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg).unwrap()
+        });
+        assert!(instantiated.result.into_result().is_ok());
+
+        let measurements = profiling.measurements().lock().unwrap();
+        assert!(!measurements.gas.is_empty());
+        for (block_id, gas) in &measurements.gas {
+            // Every execution is charged for the block and the four operators taking
+            // the measurement, which cost 150_000 each in cosmwasm-vm.
+            let executions = measurements.taken[block_id].len() as u64;
+            assert_eq!(gas % 150_000, 0);
+            assert!(*gas >= executions * 5 * 150_000);
+        }
+        let report = GasTimeReport::new(&measurements);
+        assert_eq!(report.blocks.len(), measurements.gas.len());
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use cosmwasm_std::{Coin, Empty};
+use cosmwasm_vm::testing::{mock_backend, mock_env, mock_info, MockApi, MockQuerier, MockStorage};
+use cosmwasm_vm::{call_execute, call_instantiate, call_migrate, call_query, Instance, VmResult};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::code_blocks::{BlockId, BlockStore};
+use crate::instrumentation::{Granularity, Profiling, GAS_LIMIT};
+use crate::measure::Measurements;
+use crate::vm::VmProfiling;
+
+/// Set to any value to make [`GasHarness::check`] write the golden file instead of
+/// comparing against it.
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+type MockInstance = Instance<MockApi, MockStorage, MockQuerier>;
+
+#[derive(Error, Debug)]
+pub enum GoldenError {
+    #[error("Error creating the instance: {msg}")]
+    InstanceErr { msg: String },
+    #[error("Fixture {fixture} failed: {msg}")]
+    FixtureErr { fixture: String, msg: String },
+    #[error("Error accessing the golden file: {source}")]
+    IoErr {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Error (de)serializing the golden file: {source}")]
+    SerializationErr {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error(
+        "The golden file {path} does not exist. Set {} to create it.",
+        UPDATE_GOLDEN_ENV
+    )]
+    Missing { path: String },
+    #[error(
+        "The gas costs drifted from the golden file. Set {} to accept them.\n{diff}",
+        UPDATE_GOLDEN_ENV
+    )]
+    Drift { diff: GoldenDiff },
+}
+
+/// Runs a contract against fixture messages and compares the gas charged for every
+/// block to a golden file, so that contract repos can catch gas regressions in CI:
+///
+/// ```no_run
+/// # use cosmwasm_profiler::golden::GasHarness;
+/// let wasm = std::fs::read("artifacts/hackatom.wasm").unwrap();
+/// GasHarness::new(wasm)
+///     .instantiate("instantiate", "creator", br#"{"verifier":"verifies","beneficiary":"benefits"}"#)
+///     .execute("release", "verifies", br#"{"release":{}}"#)
+///     .with_tolerance(2.0)
+///     .check("tests/gas.golden.json")
+///     .unwrap();
+/// ```
+///
+/// All fixtures run in the order they were added on one instance with a mock backend,
+/// so later fixtures see the state of earlier ones. Fixtures fail if the contract
+/// returns an error.
+///
+/// The contract is instrumented in basic block granularity with gas tracking, see
+/// [`Profiling::with_gas_tracking`]. The gas includes the cost of the instrumentation,
+/// so it is only comparable to other runs of the harness. Unlike times, gas is
+/// deterministic, so the default tolerance is 0.
+#[derive(Debug, Clone)]
+pub struct GasHarness {
+    wasm: Vec<u8>,
+    contract_balance: Vec<Coin>,
+    fixtures: Vec<Fixture>,
+    tolerance_percent: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Fixture {
+    name: String,
+    call: Call,
+    msg: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+enum Call {
+    Instantiate { sender: String },
+    Execute { sender: String },
+    Migrate,
+    Query,
+}
+
+impl Fixture {
+    fn run(&self, instance: &mut MockInstance) -> Result<(), String> {
+        let env = mock_env();
+        let result: VmResult<Result<(), String>> = match &self.call {
+            Call::Instantiate { sender } => call_instantiate::<_, _, _, Empty>(
+                instance,
+                &env,
+                &mock_info(sender, &[]),
+                &self.msg,
+            )
+            .map(|result| result.into_result().map(|_| ())),
+            Call::Execute { sender } => {
+                call_execute::<_, _, _, Empty>(instance, &env, &mock_info(sender, &[]), &self.msg)
+                    .map(|result| result.into_result().map(|_| ()))
+            }
+            Call::Migrate => call_migrate::<_, _, _, Empty>(instance, &env, &self.msg)
+                .map(|result| result.into_result().map(|_| ())),
+            Call::Query => {
+                call_query(instance, &env, &self.msg).map(|result| result.into_result().map(|_| ()))
+            }
+        };
+        result.map_err(|err| err.to_string())?
+    }
+}
+
+impl GasHarness {
+    pub fn new(wasm: impl Into<Vec<u8>>) -> Self {
+        GasHarness {
+            wasm: wasm.into(),
+            contract_balance: Vec::new(),
+            fixtures: Vec::new(),
+            tolerance_percent: 0.0,
+        }
+    }
+
+    /// The balance of the contract in the mock backend
+    pub fn with_contract_balance(mut self, balance: &[Coin]) -> Self {
+        self.contract_balance = balance.to_vec();
+        self
+    }
+
+    /// How much the gas of a fixture or block may differ from the golden file, in
+    /// percent of the golden value.
+    pub fn with_tolerance(mut self, percent: f64) -> Self {
+        self.tolerance_percent = percent;
+        self
+    }
+
+    pub fn instantiate(self, name: impl Into<String>, sender: &str, msg: &[u8]) -> Self {
+        let call = Call::Instantiate {
+            sender: sender.to_string(),
+        };
+        self.fixture(name, call, msg)
+    }
+
+    pub fn execute(self, name: impl Into<String>, sender: &str, msg: &[u8]) -> Self {
+        let call = Call::Execute {
+            sender: sender.to_string(),
+        };
+        self.fixture(name, call, msg)
+    }
+
+    pub fn migrate(self, name: impl Into<String>, msg: &[u8]) -> Self {
+        self.fixture(name, Call::Migrate, msg)
+    }
+
+    pub fn query(self, name: impl Into<String>, msg: &[u8]) -> Self {
+        self.fixture(name, Call::Query, msg)
+    }
+
+    fn fixture(mut self, name: impl Into<String>, call: Call, msg: &[u8]) -> Self {
+        self.fixtures.push(Fixture {
+            name: name.into(),
+            call,
+            msg: msg.to_vec(),
+        });
+        self
+    }
+
+    /// Runs all fixtures and returns their costs.
+    pub fn run(&self) -> Result<GoldenFile, GoldenError> {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Profiling::new(block_store, Granularity::BasicBlock).with_gas_tracking();
+        let profiling = VmProfiling::new(profiling, Measurements::new());
+        let options = profiling.instance_options(GAS_LIMIT, false);
+        let mut instance = Instance::from_code(
+            &self.wasm,
+            mock_backend(&self.contract_balance),
+            options,
+            None,
+        )
+        .map_err(|err| GoldenError::InstanceErr {
+            msg: err.to_string(),
+        })?;
+        let symbols = profiling.symbols();
+
+        let mut golden = GoldenFile::default();
+        for fixture in &self.fixtures {
+            profiling.measurements().lock().unwrap().clear();
+            let gas_before = instance.get_gas_left();
+            fixture
+                .run(&mut instance)
+                .map_err(|msg| GoldenError::FixtureErr {
+                    fixture: fixture.name.clone(),
+                    msg,
+                })?;
+            let gas_used = gas_before - instance.get_gas_left();
+
+            let measurements = profiling.measurements().lock().unwrap();
+            let blocks = measurements
+                .taken
+                .keys()
+                .map(|block_id| {
+                    let (fn_index, _) = measurements.block_locations[block_id];
+                    let costs = BlockCosts {
+                        function: symbols.describe_function(fn_index),
+                        executions: measurements.executions_of(*block_id),
+                        gas: measurements.gas.get(block_id).copied().unwrap_or_default(),
+                    };
+                    (*block_id, costs)
+                })
+                .collect();
+            golden
+                .fixtures
+                .insert(fixture.name.clone(), FixtureCosts { gas_used, blocks });
+        }
+        Ok(golden)
+    }
+
+    /// Runs all fixtures and compares their costs to the golden file at `path`. Writes
+    /// the golden file instead if the environment variable [`UPDATE_GOLDEN_ENV`] is set.
+    pub fn check(&self, path: impl AsRef<Path>) -> Result<(), GoldenError> {
+        let update = std::env::var_os(UPDATE_GOLDEN_ENV).is_some();
+        self.check_or_update(path, update)
+    }
+
+    /// Like [`GasHarness::check`], with an explicit choice whether to write the golden
+    /// file.
+    pub fn check_or_update(&self, path: impl AsRef<Path>, update: bool) -> Result<(), GoldenError> {
+        let path = path.as_ref();
+        let current = self.run()?;
+        if update {
+            fs::write(path, serde_json::to_vec_pretty(&current)?)?;
+            return Ok(());
+        }
+        if !path.exists() {
+            return Err(GoldenError::Missing {
+                path: path.display().to_string(),
+            });
+        }
+        let golden: GoldenFile = serde_json::from_slice(&fs::read(path)?)?;
+        let diff = golden.compare(&current, self.tolerance_percent);
+        if diff.changes.is_empty() {
+            Ok(())
+        } else {
+            Err(GoldenError::Drift { diff })
+        }
+    }
+}
+
+/// The costs of all fixtures of a [`GasHarness`], keyed by fixture name.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GoldenFile {
+    pub fixtures: BTreeMap<String, FixtureCosts>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct FixtureCosts {
+    /// The gas the VM charged for the call, including host functions
+    pub gas_used: u64,
+    /// The costs of every executed block. Blocks are identified by the hash of their
+    /// code, so they can be matched across builds.
+    pub blocks: BTreeMap<BlockId, BlockCosts>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BlockCosts {
+    /// The function the block was first executed in. It is informational only and
+    /// not compared.
+    pub function: String,
+    pub executions: u64,
+    /// The gas charged during all executions
+    pub gas: u64,
+}
+
+impl GoldenFile {
+    /// The gas of every fixture and block of `current` that differs from this golden
+    /// file by more than `tolerance_percent`. Fixtures and blocks missing on either
+    /// side count as 0 gas.
+    pub fn compare(&self, current: &GoldenFile, tolerance_percent: f64) -> GoldenDiff {
+        let mut changes = Vec::new();
+        let empty = FixtureCosts::default();
+        let mut names: Vec<&String> = self.fixtures.keys().collect();
+        names.extend(current.fixtures.keys());
+        names.sort_unstable();
+        names.dedup();
+
+        for name in names {
+            let golden = self.fixtures.get(name).unwrap_or(&empty);
+            let now = current.fixtures.get(name).unwrap_or(&empty);
+            let mut change = |block: Option<(BlockId, &str)>, golden: u64, current: u64| {
+                let allowed = golden as f64 * tolerance_percent / 100.0;
+                if (current as f64 - golden as f64).abs() > allowed {
+                    changes.push(GasChange {
+                        fixture: name.clone(),
+                        block: block.map(|(block_id, _)| block_id),
+                        function: block.map(|(_, function)| function.to_string()),
+                        golden,
+                        current,
+                    });
+                }
+            };
+            change(None, golden.gas_used, now.gas_used);
+
+            let mut block_ids: Vec<&BlockId> = golden.blocks.keys().collect();
+            block_ids.extend(now.blocks.keys());
+            block_ids.sort_unstable();
+            block_ids.dedup();
+            for block_id in block_ids {
+                let (golden, now) = (golden.blocks.get(block_id), now.blocks.get(block_id));
+                let function = now.or(golden).map_or("", |costs| costs.function.as_str());
+                change(
+                    Some((*block_id, function)),
+                    golden.map_or(0, |costs| costs.gas),
+                    now.map_or(0, |costs| costs.gas),
+                );
+            }
+        }
+        GoldenDiff { changes }
+    }
+}
+
+/// The differences found by [`GoldenFile::compare`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GoldenDiff {
+    pub changes: Vec<GasChange>,
+}
+
+/// The gas of a fixture, or of a block in it, that differs from the golden file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasChange {
+    pub fixture: String,
+    /// `None` for the gas used by the whole fixture
+    pub block: Option<BlockId>,
+    pub function: Option<String>,
+    pub golden: u64,
+    pub current: u64,
+}
+
+impl fmt::Display for GasChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.block, &self.function) {
+            (Some(block_id), Some(function)) => write!(
+                f,
+                "{}: block {} in {}",
+                self.fixture,
+                block_id.as_u64(),
+                function
+            )?,
+            _ => write!(f, "{}: gas used", self.fixture)?,
+        }
+        write!(f, ": {} -> {}", self.golden, self.current)?;
+        if self.golden > 0 {
+            let percent = (self.current as f64 - self.golden as f64) * 100.0 / self.golden as f64;
+            write!(f, " ({:+.1}%)", percent)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for GoldenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use cosmwasm_std::coins;
+
+    static HACKATOM: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+    fn harness() -> GasHarness {
+        GasHarness::new(HACKATOM)
+            .with_contract_balance(&coins(1000, "earth"))
+            .instantiate(
+                "instantiate",
+                "creator",
+                br#"{"verifier":"verifies","beneficiary":"benefits"}"#,
+            )
+            .query("verifier", br#"{"verifier":{}}"#)
+            .execute("release", "verifies", br#"{"release":{}}"#)
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("golden_{}_{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn run_is_deterministic() {
+        let harness = harness();
+        let golden = harness.run().unwrap();
+        assert_eq!(golden.fixtures.len(), 3);
+        for costs in golden.fixtures.values() {
+            assert!(costs.gas_used > 0);
+            assert!(!costs.blocks.is_empty());
+            assert!(costs.blocks.values().all(|block| block.executions > 0));
+        }
+        assert_eq!(harness.run().unwrap(), golden);
+    }
+
+    #[test]
+    fn run_fails_for_contract_errors() {
+        let harness = harness().execute("unauthorized", "anyone", br#"{"release":{}}"#);
+        match harness.run().unwrap_err() {
+            GoldenError::FixtureErr { fixture, .. } => assert_eq!(fixture, "unauthorized"),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn check_or_update_works() {
+        let path = golden_path("check");
+        let harness = harness();
+        match harness.check_or_update(&path, false).unwrap_err() {
+            GoldenError::Missing { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        harness.check_or_update(&path, true).unwrap();
+        harness.check_or_update(&path, false).unwrap();
+
+        // Make the release cheaper in the golden file
+        let mut golden: GoldenFile = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        let release = golden.fixtures.get_mut("release").unwrap();
+        release.gas_used = release.gas_used * 100 / 110;
+        fs::write(&path, serde_json::to_vec(&golden).unwrap()).unwrap();
+        match harness.check_or_update(&path, false).unwrap_err() {
+            GoldenError::Drift { diff } => {
+                assert_eq!(diff.changes.len(), 1);
+                assert_eq!(diff.changes[0].fixture, "release");
+                assert_eq!(diff.changes[0].block, None);
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        harness
+            .clone()
+            .with_tolerance(11.0)
+            .check_or_update(&path, false)
+            .unwrap();
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compare_reports_changed_blocks() {
+        let block = |gas| BlockCosts {
+            function: "execute".to_string(),
+            executions: 1,
+            gas,
+        };
+        let golden = GoldenFile {
+            fixtures: vec![(
+                "release".to_string(),
+                FixtureCosts {
+                    gas_used: 1000,
+                    blocks: vec![(BlockId(1), block(100)), (BlockId(2), block(50))]
+                        .into_iter()
+                        .collect(),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let mut current = golden.clone();
+        let release = current.fixtures.get_mut("release").unwrap();
+        release.gas_used = 1010;
+        release.blocks.remove(&BlockId(2));
+        release.blocks.insert(BlockId(1), block(120));
+
+        let diff = golden.compare(&current, 5.0);
+        assert_eq!(
+            diff.to_string(),
+            "release: block 1 in execute: 100 -> 120 (+20.0%)\n\
+             release: block 2 in execute: 50 -> 0 (-100.0%)\n"
+        );
+        assert!(golden.compare(&current, 100.0).changes.is_empty());
+
+        let diff = golden.compare(&GoldenFile::default(), 0.0);
+        assert_eq!(diff.changes.len(), 3);
+        assert_eq!(
+            diff.changes[0].to_string(),
+            "release: gas used: 1000 -> 0 (-100.0%)"
+        );
+    }
+}
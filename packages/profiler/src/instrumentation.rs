@@ -1,25 +1,175 @@
 use std::{
+    collections::{HashMap, HashSet},
     path::Path,
-    sync::{Arc, Mutex},
+    sync::{Arc, Condvar, Mutex, PoisonError},
 };
 
 use cosmwasm_vm::{
     testing::{MockApi, MockQuerier, MockStorage},
     Backend, Instance,
 };
-use loupe::MemoryUsage;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use wasmer::{
     internals::WithEnv, wasmparser::Operator, Exports, Function, FunctionMiddleware, HostFunction,
     LocalFunctionIndex, ModuleMiddleware, WasmerEnv,
 };
 use wasmer_types::{FunctionIndex, ImportIndex};
 
-use crate::{code_blocks::BlockStore, operators::OperatorSymbol};
+use crate::{
+    clock::Clock,
+    code_blocks::{
+        sha256_block_id, BlockId, BlockStore, BlockStoreError, CodeBlock, ShardedBlockStore,
+        WasmOffsets,
+    },
+    compat::{MemoryUsage, ModuleInfo},
+    measure::Measurements,
+    middlewares::METERING_REMAINING_POINTS,
+    operators::{Immediate, OperatorSymbol, RetainedImmediates},
+    symbols::{Symbols, SymbolsError},
+};
+
+/// The name of the import module the profiling functions are imported from
+/// unless configured otherwise with [`Profiling::with_import_module`].
+pub const DEFAULT_IMPORT_MODULE: &str = "profiling";
+/// The names of the profiling imports, see [`Module::instrument_with_imports`].
+pub const START_MEASUREMENT: &str = "start_measurement";
+pub const TAKE_MEASUREMENT: &str = "take_measurement";
+pub const COUNT_LOOP_ITERATION: &str = "count_loop_iteration";
+pub const RECORD_MEMORY_GROW: &str = "record_memory_grow";
+pub const START_HOST_CALL: &str = "start_host_call";
+pub const END_HOST_CALL: &str = "end_host_call";
+pub const RECORD_INDIRECT_CALL: &str = "record_indirect_call";
+pub const RECORD_BRANCH: &str = "record_branch";
+pub const ENTER_FUNCTION: &str = "enter_function";
+pub const ENTER_CALL: &str = "enter_call";
+pub const EXIT_CALL: &str = "exit_call";
+pub const FLUSH_MEASUREMENTS: &str = "flush_measurements";
+
+/// The size of a record written by [`Profiling::with_buffered_recording`]: the function
+/// index and local block id as little endian `u32`s followed by the block id as `u64`.
+pub const BUFFER_RECORD_SIZE: usize = 16;
+/// The largest capacity of [`Profiling::with_buffered_recording`], whose buffer still
+/// fits in 2 GiB. Addresses in the buffer are computed with `i32` arithmetic.
+pub const MAX_BUFFER_CAPACITY: u32 = i32::MAX as u32 / BUFFER_RECORD_SIZE as u32;
+/// The name prefix of the functions added by [`instrument_wasm`] for buffered recording.
+/// They are not instrumented themselves.
+const BUFFER_FUNCTION_PREFIX: &str = "__profiling_";
+const RECORD_BLOCK_FUNCTION: &str = "__profiling_record_block";
+
+/// The gas limit of instrumented instances, high enough not to interfere with
+/// profiling (~1000s at the gas target of 1 Teragas per millisecond).
+pub const GAS_LIMIT: u64 = 1_000_000_000_000_000_000;
+
+#[derive(Error, Debug)]
+pub enum InstrumentationError {
+    #[error("Error parsing Wasm: {msg}")]
+    ParseErr { msg: String },
+    #[error("The module already imports {module}.{name}, but with an unexpected type")]
+    ImportMismatch { module: String, name: String },
+    #[error("The instrumented Wasm is invalid: {msg}")]
+    ValidationErr { msg: String },
+    #[error("The module does not export a function {name}")]
+    UnknownExport { name: String },
+    #[error("Buffered recording requires the module to define a memory")]
+    MissingMemory,
+    #[error("Error reading symbols: {source}")]
+    SymbolsErr {
+        #[from]
+        source: SymbolsError,
+    },
+    #[error("Error accessing the instrumentation cache: {msg}")]
+    CacheErr { msg: String },
+}
+
+/// Injects the imports required by `profiling` into a Wasm module.
+///
+/// The returned bytes are validated and ready to be compiled with `profiling`
+/// attached as a middleware, e.g. using `wasmer::Module::new` or
+/// `cosmwasm_vm::internals::compile`. Instrumenting already instrumented Wasm
+/// is a no-op. Function names found in DWARF debug info only are added to the
+/// name section, since the debug info does not survive instrumentation. The
+/// [`WasmOffsets`] of the blocks are the ones in `wasm`, so they can be mapped to source
+/// lines with its debug info, see [`Profiling::block_offsets`].
+///
+/// Use an [`InstrumentationCache`](crate::cache::InstrumentationCache) to skip this
+/// for Wasm that was instrumented for the same configuration before.
+pub fn instrument_wasm(
+    wasm: &[u8],
+    profiling: &Profiling,
+) -> Result<Vec<u8>, InstrumentationError> {
+    let prepared = prepare_module(wasm, profiling)?;
+    profiling.expect_module(&prepared);
+    Ok(prepared.wasm)
+}
+
+/// Everything [`instrument_wasm`] computes for a module. The Wasm is stored on its own
+/// by the [`InstrumentationCache`](crate::cache::InstrumentationCache).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PreparedModule {
+    #[serde(skip)]
+    pub wasm: Vec<u8>,
+    function_sizes: Vec<FunctionSizes>,
+    /// The functions selected by [`FunctionFilter::ReachableFrom`], if used
+    reachable_functions: Option<Vec<u32>>,
+}
+
+/// Instruments `wasm` for `profiling` without changing the state of `profiling`.
+pub(crate) fn prepare_module(
+    wasm: &[u8],
+    profiling: &Profiling,
+) -> Result<PreparedModule, InstrumentationError> {
+    let parse_err = |err: wasmer::wasmparser::BinaryReaderError| InstrumentationError::ParseErr {
+        msg: err.to_string(),
+    };
+
+    let mut module =
+        walrus::Module::from_buffer(wasm).map_err(|err| InstrumentationError::ParseErr {
+            msg: err.to_string(),
+        })?;
+    let original_functions = local_function_ids(&module);
+    name_functions(&mut module, &Symbols::from_wasm(wasm)?);
+    add_imports(&mut module, profiling)?;
+    if let Some(capacity) = profiling.buffer_capacity {
+        add_record_buffer(&mut module, profiling.import_module(), capacity)?;
+    }
+    let emitted_functions = emitted_function_ids(&module);
+    let original_sizes = function_sizes(wasm).map_err(parse_err)?;
+    let wasm = module.emit_wasm();
+
+    wasmer::wasmparser::validate(&wasm).map_err(|err| InstrumentationError::ValidationErr {
+        msg: err.to_string(),
+    })?;
+
+    // Point the offsets into the original code, whose debug info is lost.
+    let mut function_sizes = function_sizes(&wasm).map_err(parse_err)?;
+    for (sizes, id) in function_sizes.iter_mut().zip(emitted_functions) {
+        let original = original_functions
+            .iter()
+            .position(|original| *original == id)
+            .map(|fn_index| &original_sizes[fn_index]);
+        sizes.block_offsets = original
+            .map(|original| original.block_offsets.clone())
+            .unwrap_or_default();
+        sizes.offsets = original.and_then(|original| original.offsets);
+    }
+    let reachable_functions = match &profiling.filter {
+        FunctionFilter::ReachableFrom(exports) => Some(reachable_functions(&wasm, exports)?),
+        _ => None,
+    };
+    Ok(PreparedModule {
+        wasm,
+        function_sizes,
+        reachable_functions,
+    })
+}
 
 pub enum Module<'d> {
     Path(&'d Path),
-    #[cfg(test)]
     Bytes(&'d [u8]),
+    /// Wasm that is already instrumented for the `Profiling` it is compiled with, e.g.
+    /// by an [`InstrumentationCache`](crate::cache::InstrumentationCache)
+    Prepared(&'d [u8]),
 }
 
 impl<'d> Module<'d> {
@@ -27,7 +177,6 @@ impl<'d> Module<'d> {
         Self::Path(path.as_ref())
     }
 
-    #[cfg(test)]
     pub fn from_bytes(bytes: &'d [u8]) -> Self {
         Self::Bytes(bytes)
     }
@@ -44,35 +193,183 @@ impl<'d> Module<'d> {
         F1: HostFunction<(u32, u32), (), WithEnv, Env>,
         F2: HostFunction<(u32, u32, u64), (), WithEnv, Env>,
     {
-        let profiling = Arc::new(Profiling::new(block_store));
-
-        // Create the module with our middleware.
-        // let mut compiler_config = Cranelift::default();
-        // compiler_config.push_middleware(profiling.clone());
-        // let store = Store::new(&Universal::new(compiler_config).engine());
-        let mut walrus_module = match self {
-            Module::Path(path) => walrus::Module::from_file(path).unwrap(),
-            #[cfg(test)]
-            Module::Bytes(bytes) => walrus::Module::from_buffer(bytes).unwrap(),
-        };
-        add_imports(&mut walrus_module);
-        let wasm = walrus_module.emit_wasm();
-        //let wasmer_module = wasmer::Module::new(&store, wasm).unwrap();
-
-        let wasmer_module =
-            cosmwasm_vm::internals::compile(&wasm, None, &[profiling.clone()]).unwrap();
-        let store = wasmer_module.store();
+        let profiling = Arc::new(Profiling::new(block_store, Granularity::default()));
+        self.instrument_with(profiling, env, start_measurement_fn, take_measurement_fn)
+    }
 
-        // Mock imports that do nothing.
-        let mut fns_to_import = Exports::new();
-        fns_to_import.insert(
-            "start_measurement",
-            Function::new_native_with_env(store, env.clone(), start_measurement_fn),
+    /// Like `instrument`, but uses an existing `Profiling` middleware. This way
+    /// several modules can share one middleware and register their code blocks
+    /// in the same `BlockStore`.
+    ///
+    /// Panics if `profiling` counts loop iterations. Use `instrument_counting_loops` then.
+    /// Panics if `profiling` tracks memory growth, host calls, indirect calls, branches or the
+    /// call depth or buffers measurements.
+    /// Use `instrument_with_imports` then.
+    pub fn instrument_with<Env, F1, F2>(
+        &self,
+        profiling: Arc<Profiling>,
+        env: Env,
+        start_measurement_fn: F1,
+        take_measurement_fn: F2,
+    ) -> InstrumentedInstance
+    where
+        Env: WasmerEnv + 'static,
+        F1: HostFunction<(u32, u32), (), WithEnv, Env>,
+        F2: HostFunction<(u32, u32, u64), (), WithEnv, Env>,
+    {
+        assert!(
+            !profiling.counts_loops(),
+            "Module::instrument_with: use instrument_counting_loops for a Profiling that counts loop iterations"
+        );
+        assert!(
+            !profiling.tracks_memory()
+                && !profiling.times_host_calls()
+                && !profiling.tracks_indirect_calls()
+                && !profiling.tracks_branches()
+                && !profiling.tracks_call_depth()
+                && !profiling.buffers_recording(),
+            "Module::instrument_with: use instrument_with_imports for a Profiling that tracks memory growth, host calls, indirect calls, branches or the call depth or buffers measurements"
         );
-        fns_to_import.insert(
-            "take_measurement",
-            Function::new_native_with_env(store, env, take_measurement_fn),
+        self.instantiate(profiling, env, |store, env, fns_to_import| {
+            fns_to_import.insert(
+                START_MEASUREMENT,
+                Function::new_native_with_env(store, env.clone(), start_measurement_fn),
+            );
+            fns_to_import.insert(
+                TAKE_MEASUREMENT,
+                Function::new_native_with_env(store, env, take_measurement_fn),
+            );
+        })
+    }
+
+    /// Like `instrument_with`, for a `Profiling` created with
+    /// [`Profiling::with_loop_counting`]. `count_loop_iteration_fn` is called with the
+    /// function index and the index of the loop within the function every time
+    /// a loop body starts executing.
+    pub fn instrument_counting_loops<Env, F1, F2, F3>(
+        &self,
+        profiling: Arc<Profiling>,
+        env: Env,
+        start_measurement_fn: F1,
+        take_measurement_fn: F2,
+        count_loop_iteration_fn: F3,
+    ) -> InstrumentedInstance
+    where
+        Env: WasmerEnv + 'static,
+        F1: HostFunction<(u32, u32), (), WithEnv, Env>,
+        F2: HostFunction<(u32, u32, u64), (), WithEnv, Env>,
+        F3: HostFunction<(u32, u32), (), WithEnv, Env>,
+    {
+        assert!(
+            profiling.counts_loops(),
+            "Module::instrument_counting_loops: the Profiling does not count loop iterations"
+        );
+        assert!(
+            !profiling.tracks_memory()
+                && !profiling.times_host_calls()
+                && !profiling.tracks_indirect_calls()
+                && !profiling.tracks_branches()
+                && !profiling.tracks_call_depth()
+                && !profiling.buffers_recording(),
+            "Module::instrument_counting_loops: use instrument_with_imports for a Profiling that tracks memory growth, host calls, indirect calls, branches or the call depth or buffers measurements"
+        );
+        self.instantiate(profiling, env, |store, env, fns_to_import| {
+            fns_to_import.insert(
+                START_MEASUREMENT,
+                Function::new_native_with_env(store, env.clone(), start_measurement_fn),
+            );
+            fns_to_import.insert(
+                TAKE_MEASUREMENT,
+                Function::new_native_with_env(store, env.clone(), take_measurement_fn),
+            );
+            fns_to_import.insert(
+                COUNT_LOOP_ITERATION,
+                Function::new_native_with_env(store, env, count_loop_iteration_fn),
+            );
+        })
+    }
+
+    /// Like `instrument_with`, for a `Profiling` with any combination of options.
+    /// `add_imports` has to insert all imports `profiling` needs under the names
+    /// [`START_MEASUREMENT`], [`TAKE_MEASUREMENT`] and, if enabled,
+    /// [`COUNT_LOOP_ITERATION`], [`RECORD_MEMORY_GROW`], [`START_HOST_CALL`],
+    /// [`END_HOST_CALL`], [`RECORD_INDIRECT_CALL`], [`RECORD_BRANCH`], [`ENTER_FUNCTION`],
+    /// [`ENTER_CALL`], [`EXIT_CALL`] and [`FLUSH_MEASUREMENTS`].
+    ///
+    /// The `record_memory_grow` import receives the result of `memory.grow` (the previous
+    /// number of pages or -1), the function index, the local block id and the current
+    /// number of pages. It must return the result of `memory.grow` unchanged.
+    ///
+    /// The host call imports receive the function index and the index of the called import.
+    ///
+    /// The `record_indirect_call` import receives the index of the called table element,
+    /// the function index and the table index. It must return the element index unchanged.
+    ///
+    /// The `record_branch` import receives the condition of a `br_if` or the index of a
+    /// `br_table`, the function index, the local block id and the largest outcome: 1 for
+    /// `br_if` and the number of targets for `br_table`, whose default target is the last
+    /// outcome. It must return the condition or index unchanged.
+    ///
+    /// The `enter_function` import receives the function index, `enter_call` and
+    /// `exit_call` receive nothing.
+    ///
+    /// The `flush_measurements` import receives the address of the buffer in the linear
+    /// memory and the number of [`BUFFER_RECORD_SIZE`] byte records in it, which can be
+    /// passed to [`Measurements::flush_buffer`].
+    pub fn instrument_with_imports<Env>(
+        &self,
+        profiling: Arc<Profiling>,
+        env: Env,
+        add_imports: impl FnOnce(&wasmer::Store, Env, &mut Exports),
+    ) -> InstrumentedInstance
+    where
+        Env: WasmerEnv + 'static,
+    {
+        self.instantiate(profiling, env, add_imports)
+    }
+
+    /// Like `instrument_with_imports`, with all imports `profiling` needs recording
+    /// into `measurements`.
+    pub fn instrument_measuring<C: Clock>(
+        &self,
+        profiling: Arc<Profiling>,
+        measurements: Arc<Mutex<Measurements<C>>>,
+    ) -> InstrumentedInstance {
+        let instance = self.instantiate(
+            profiling.clone(),
+            measurements.clone(),
+            |store, env, imports| add_measuring_imports(&profiling, store, env, imports),
         );
+        // The start function ran during instantiation
+        measurements.lock().unwrap().end_invocation();
+        instance
+    }
+
+    fn instantiate<Env>(
+        &self,
+        profiling: Arc<Profiling>,
+        env: Env,
+        add_imports: impl FnOnce(&wasmer::Store, Env, &mut Exports),
+    ) -> InstrumentedInstance
+    where
+        Env: WasmerEnv + 'static,
+    {
+        let wasm = match self {
+            Module::Path(path) => instrument_wasm(&std::fs::read(path).unwrap(), &profiling),
+            Module::Bytes(bytes) => instrument_wasm(bytes, &profiling),
+            Module::Prepared(wasm) => Ok(wasm.to_vec()),
+        }
+        .unwrap();
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+
+        let wasmer_module = {
+            let _compilation = profiling.compilation_guard();
+            cosmwasm_vm::internals::compile(&wasm, None, &[profiling.clone()]).unwrap()
+        };
+        let store = wasmer_module.store();
+
+        let mut fns_to_import = Exports::new();
+        add_imports(store, env, &mut fns_to_import);
 
         let backend = Backend {
             api: MockApi::default(),
@@ -82,149 +379,295 @@ impl<'d> Module<'d> {
         let instance = cosmwasm_vm::internals::instance_from_module(
             &wasmer_module,
             backend,
-            999999999,
+            GAS_LIMIT,
             false,
-            Some(vec![("profiling", fns_to_import)].into_iter().collect()),
+            Some(
+                vec![(profiling.import_module.as_str(), fns_to_import)]
+                    .into_iter()
+                    .collect(),
+            ),
         )
         .unwrap();
 
         InstrumentedInstance {
             profiling,
             instance,
+            symbols,
         }
     }
 }
 
-type MockInstance = Instance<MockApi, MockStorage, MockQuerier>;
+pub(crate) type MeasurementsEnv<C> = Arc<Mutex<Measurements<C>>>;
 
-pub struct InstrumentedInstance {
-    #[allow(dead_code)]
-    profiling: Arc<Profiling>,
-    instance: MockInstance,
+/// Inserts all imports `profiling` needs, recording into `env`. Register them under
+/// [`Profiling::import_module`] to instantiate a module compiled with `profiling`, e.g.
+/// for [`bench`](crate::bench::bench).
+pub fn add_measuring_imports<C: Clock>(
+    profiling: &Profiling,
+    store: &wasmer::Store,
+    env: Arc<Mutex<Measurements<C>>>,
+    imports: &mut Exports,
+) {
+    if profiling.tracks_gas() {
+        let env = GasEnv {
+            measurements: env.clone(),
+            remaining_points: wasmer::LazyInit::new(),
+        };
+        let start = Function::new_native_with_env(store, env.clone(), start_measurement_with_gas);
+        imports.insert(START_MEASUREMENT, start);
+        let take = Function::new_native_with_env(store, env, take_measurement_with_gas);
+        imports.insert(TAKE_MEASUREMENT, take);
+    } else {
+        let start = Function::new_native_with_env(store, env.clone(), start_measurement::<C>);
+        imports.insert(START_MEASUREMENT, start);
+        let take = Function::new_native_with_env(store, env.clone(), take_measurement::<C>);
+        imports.insert(TAKE_MEASUREMENT, take);
+    }
+    if profiling.counts_loops() {
+        let count = Function::new_native_with_env(store, env.clone(), count_loop_iteration::<C>);
+        imports.insert(COUNT_LOOP_ITERATION, count);
+    }
+    if profiling.tracks_memory() {
+        let record = Function::new_native_with_env(store, env.clone(), record_memory_grow::<C>);
+        imports.insert(RECORD_MEMORY_GROW, record);
+    }
+    if profiling.times_host_calls() {
+        let start = Function::new_native_with_env(store, env.clone(), start_host_call::<C>);
+        imports.insert(START_HOST_CALL, start);
+        let end = Function::new_native_with_env(store, env.clone(), end_host_call::<C>);
+        imports.insert(END_HOST_CALL, end);
+    }
+    if profiling.tracks_indirect_calls() {
+        let record = Function::new_native_with_env(store, env.clone(), record_indirect_call::<C>);
+        imports.insert(RECORD_INDIRECT_CALL, record);
+    }
+    if profiling.tracks_branches() {
+        let record = Function::new_native_with_env(store, env.clone(), record_branch::<C>);
+        imports.insert(RECORD_BRANCH, record);
+    }
+    if profiling.tracks_call_depth() {
+        let enter = Function::new_native_with_env(store, env.clone(), enter_function::<C>);
+        imports.insert(ENTER_FUNCTION, enter);
+        let enter = Function::new_native_with_env(store, env.clone(), enter_call::<C>);
+        imports.insert(ENTER_CALL, enter);
+        let exit = Function::new_native_with_env(store, env.clone(), exit_call::<C>);
+        imports.insert(EXIT_CALL, exit);
+    }
+    if profiling.buffers_recording() {
+        let env = BufferEnv {
+            measurements: env,
+            memory: wasmer::LazyInit::new(),
+        };
+        let flush = Function::new_native_with_env(store, env, flush_measurements::<C>);
+        imports.insert(FLUSH_MEASUREMENTS, flush);
+    }
 }
 
-impl InstrumentedInstance {
-    pub fn vm_instance(&mut self) -> &mut MockInstance {
-        &mut self.instance
+pub(crate) fn start_measurement<C: Clock>(
+    env: &MeasurementsEnv<C>,
+    fn_index: u32,
+    local_block_id: u32,
+) {
+    env.lock()
+        .unwrap()
+        .start_measurement(fn_index, local_block_id);
+}
+
+pub(crate) fn take_measurement<C: Clock>(
+    env: &MeasurementsEnv<C>,
+    fn_index: u32,
+    local_block_id: u32,
+    block_id: u64,
+) {
+    env.lock()
+        .unwrap()
+        .take_measurement(fn_index, local_block_id, BlockId::from(block_id));
+}
+
+/// The environment of the measuring imports with gas tracking, which need to read
+/// the remaining gas of the instance.
+#[derive(Clone)]
+struct GasEnv<C: Clock> {
+    measurements: MeasurementsEnv<C>,
+    remaining_points: wasmer::LazyInit<wasmer::Global>,
+}
+
+impl<C: Clock> WasmerEnv for GasEnv<C> {
+    fn init_with_instance(
+        &mut self,
+        instance: &wasmer::Instance,
+    ) -> Result<(), wasmer::HostEnvInitError> {
+        // A weak reference, since the instance owns the import holding this env
+        let remaining_points: wasmer::Global = instance
+            .exports
+            .get_with_generics_weak(METERING_REMAINING_POINTS)?;
+        self.remaining_points.initialize(remaining_points);
+        Ok(())
     }
 }
 
-/// Add the imports we need to make instrumentation work.
-/// Returns the ids for both fns.
-fn add_imports(module: &mut walrus::Module) -> (usize, usize) {
-    use walrus::ValType::*;
+impl<C: Clock> GasEnv<C> {
+    fn remaining_points(&self) -> u64 {
+        let global = self
+            .remaining_points
+            .get_ref()
+            .expect("the module does not export its remaining gas");
+        // The global holds an unsigned value
+        global.get().unwrap_i64() as u64
+    }
+}
 
-    let start_type = module.types.add(&[I32, I32], &[]);
-    let take_type = module.types.add(&[I32, I32, I64], &[]);
+fn start_measurement_with_gas<C: Clock>(env: &GasEnv<C>, fn_index: u32, local_block_id: u32) {
+    let remaining_gas = env.remaining_points();
+    env.measurements.lock().unwrap().start_measurement_with_gas(
+        fn_index,
+        local_block_id,
+        remaining_gas,
+    );
+}
 
-    let (fn1, _) = module.add_import_func("profiling", "start_measurement", start_type);
-    let (fn2, _) = module.add_import_func("profiling", "take_measurement", take_type);
+fn take_measurement_with_gas<C: Clock>(
+    env: &GasEnv<C>,
+    fn_index: u32,
+    local_block_id: u32,
+    block_id: u64,
+) {
+    let remaining_gas = env.remaining_points();
+    env.measurements.lock().unwrap().take_measurement_with_gas(
+        fn_index,
+        local_block_id,
+        BlockId::from(block_id),
+        remaining_gas,
+    );
+}
 
-    (fn1.index(), fn2.index())
+fn count_loop_iteration<C: Clock>(env: &MeasurementsEnv<C>, fn_index: u32, loop_index: u32) {
+    env.lock()
+        .unwrap()
+        .count_loop_iteration(fn_index, loop_index);
 }
 
-#[non_exhaustive]
-#[derive(Debug, MemoryUsage)]
-pub struct Profiling {
-    block_store: Arc<Mutex<BlockStore>>,
-    indexes: Mutex<Option<ProfilingIndexes>>,
+fn record_memory_grow<C: Clock>(
+    env: &MeasurementsEnv<C>,
+    previous_pages: i32,
+    fn_index: u32,
+    local_block_id: u32,
+    current_pages: u32,
+) -> i32 {
+    env.lock()
+        .unwrap()
+        .record_memory_grow(fn_index, local_block_id, previous_pages, current_pages);
+    previous_pages
 }
 
-impl Profiling {
-    pub fn new(block_store: Arc<Mutex<BlockStore>>) -> Self {
-        Self {
-            block_store,
-            indexes: Mutex::new(None),
-        }
-    }
+fn start_host_call<C: Clock>(env: &MeasurementsEnv<C>, fn_index: u32, import_index: u32) {
+    env.lock().unwrap().start_host_call(fn_index, import_index);
 }
 
-impl ModuleMiddleware for Profiling {
-    fn generate_function_middleware(
-        &self,
-        local_function_index: wasmer::LocalFunctionIndex,
-    ) -> Box<dyn wasmer::FunctionMiddleware> {
-        Box::new(FunctionProfiling::new(
-            self.block_store.clone(),
-            self.indexes.lock().unwrap().clone().unwrap(),
-            local_function_index,
-        ))
-    }
+fn end_host_call<C: Clock>(env: &MeasurementsEnv<C>, fn_index: u32, import_index: u32) {
+    env.lock().unwrap().end_host_call(fn_index, import_index);
+}
 
-    fn transform_module_info(&self, module_info: &mut wasmer_vm::ModuleInfo) {
-        let mut indexes = self.indexes.lock().unwrap();
+fn record_indirect_call<C: Clock>(
+    env: &MeasurementsEnv<C>,
+    element_index: u32,
+    fn_index: u32,
+    table_index: u32,
+) -> u32 {
+    env.lock()
+        .unwrap()
+        .record_indirect_call(fn_index, table_index, element_index);
+    element_index
+}
 
-        if indexes.is_some() {
-            panic!("Profiling::transform_module_info: Attempting to use a `Profiling` middleware from multiple modules.");
-        }
+fn record_branch<C: Clock>(
+    env: &MeasurementsEnv<C>,
+    value: u32,
+    fn_index: u32,
+    local_block_id: u32,
+    max_outcome: u32,
+) -> u32 {
+    env.lock().unwrap().record_branch(
+        fn_index,
+        local_block_id,
+        value.min(max_outcome),
+        max_outcome,
+    );
+    value
+}
 
-        let fn1 = module_info
-            .imports
-            .iter()
-            .find_map(|((module, field, _), index)| {
-                if (module.as_str(), field.as_str()) == ("profiling", "start_measurement") {
-                    if let ImportIndex::Function(fn_index) = index {
-                        return Some(fn_index);
-                    }
-                }
-                None
-            })
-            .unwrap();
+fn enter_function<C: Clock>(env: &MeasurementsEnv<C>, fn_index: u32) {
+    env.lock().unwrap().enter_function(fn_index);
+}
 
-        let fn2 = module_info
-            .imports
-            .iter()
-            .find_map(|((module, field, _), index)| {
-                if (module.as_str(), field.as_str()) == ("profiling", "take_measurement") {
-                    if let ImportIndex::Function(fn_index) = index {
-                        return Some(fn_index);
-                    }
-                }
-                None
-            })
-            .unwrap();
+fn enter_call<C: Clock>(env: &MeasurementsEnv<C>) {
+    env.lock().unwrap().enter_call();
+}
 
-        *indexes = Some(ProfilingIndexes {
-            start_measurement: *fn1,
-            take_measurement: *fn2,
-        });
+fn exit_call<C: Clock>(env: &MeasurementsEnv<C>) {
+    env.lock().unwrap().exit_call();
+}
+
+/// The environment of the `flush_measurements` import, which needs to read the buffer.
+#[derive(Clone)]
+struct BufferEnv<C: Clock> {
+    measurements: MeasurementsEnv<C>,
+    memory: wasmer::LazyInit<wasmer::Memory>,
+}
+
+impl<C: Clock> WasmerEnv for BufferEnv<C> {
+    fn init_with_instance(
+        &mut self,
+        instance: &wasmer::Instance,
+    ) -> Result<(), wasmer::HostEnvInitError> {
+        // A weak reference, since the instance owns the import holding this env
+        let memory: wasmer::Memory = instance.exports.get_with_generics_weak("memory")?;
+        self.memory.initialize(memory);
+        Ok(())
     }
 }
 
-#[derive(Debug)]
-struct FunctionProfiling {
-    block_store: Arc<Mutex<BlockStore>>,
-    accumulated_ops: Vec<OperatorSymbol>,
-    indexes: ProfilingIndexes,
-    block_count: u32,
-    fn_index: LocalFunctionIndex,
+fn flush_measurements<C: Clock>(env: &BufferEnv<C>, address: u32, len: u32) {
+    let memory = env
+        .memory
+        .get_ref()
+        .expect("flush_measurements: the module does not export its memory");
+    let start = address as usize;
+    let end = start + len as usize * BUFFER_RECORD_SIZE;
+    let records: Vec<u8> = memory.view::<u8>()[start..end]
+        .iter()
+        .map(|byte| byte.get())
+        .collect();
+    env.measurements.lock().unwrap().flush_buffer(&records);
 }
 
-impl FunctionProfiling {
-    fn new(
-        block_store: Arc<Mutex<BlockStore>>,
-        indexes: ProfilingIndexes,
-        fn_index: LocalFunctionIndex,
-    ) -> Self {
-        Self {
-            block_store,
-            accumulated_ops: Vec::new(),
-            indexes,
-            block_count: 0,
-            fn_index,
-        }
+type MockInstance = Instance<MockApi, MockStorage, MockQuerier>;
+
+pub struct InstrumentedInstance {
+    #[allow(dead_code)]
+    profiling: Arc<Profiling>,
+    instance: MockInstance,
+    symbols: Symbols,
+}
+
+impl InstrumentedInstance {
+    pub fn vm_instance(&mut self) -> &mut MockInstance {
+        &mut self.instance
+    }
+
+    /// The function names of the instrumented module. Function indexes match the
+    /// ones passed to the profiling imports.
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
     }
 }
 
-impl FunctionMiddleware for FunctionProfiling {
-    fn feed<'a>(
-        &mut self,
-        operator: wasmer::wasmparser::Operator<'a>,
-        state: &mut wasmer::MiddlewareReaderState<'a>,
-    ) -> Result<(), wasmer::MiddlewareError> {
-        // Possible sources and targets of a branch. Finalize the cost of the previous basic block and perform necessary checks.
-        match operator {
-            Operator::Loop { .. } // loop headers are branch targets
+/// Whether an operator is a possible source or target of a branch, which
+/// ends the current basic block. These operators are not part of any block.
+pub(crate) fn ends_block(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::Loop { .. } // loop headers are branch targets
             | Operator::End // block ends are branch targets
             | Operator::Else // "else" is the "end" of an if branch
             | Operator::Br { .. } // branch source
@@ -233,90 +676,1742 @@ impl FunctionMiddleware for FunctionProfiling {
             | Operator::Call { .. } // function call - branch source
             | Operator::CallIndirect { .. } // function call - branch source
             | Operator::Return // end of function - branch source
-            => {
-                if !self.accumulated_ops.is_empty() {
-                    let mut store = self.block_store.lock().unwrap();
-                    let block_id = store.register_block(std::mem::take(&mut self.accumulated_ops));
+    )
+}
 
-                    // We're at the end of a code block. Finalize the measurement.
-                    state.extend(&[
-                        Operator::I32Const { value: self.fn_index.as_u32() as i32 },
-                        Operator::I32Const { value: self.block_count as i32 },
-                        Operator::I64Const { value: block_id.as_u64() as i64 },
-                        Operator::Call{ function_index: self.indexes.take_measurement.as_u32() },
-                    ]);
+/// The sizes of a local function and its basic blocks.
+#[derive(Debug, Default, Clone, PartialEq, Eq, MemoryUsage, Serialize, Deserialize)]
+struct FunctionSizes {
+    /// The number of operators of every basic block, in the order `FunctionProfiling`
+    /// sees them
+    block_operators: Vec<usize>,
+    /// The encoded length of every basic block in bytes
+    block_bytes: Vec<u32>,
+    /// The encoded length of all operators of the function in bytes
+    bytes: u32,
+    /// Where every basic block is in the code section of the uninstrumented Wasm
+    #[serde(default)]
+    block_offsets: Vec<WasmOffsets>,
+    /// Where the operators of the function are in the code section of the uninstrumented Wasm
+    #[serde(default)]
+    offsets: Option<WasmOffsets>,
+    /// The id of the whole function in function granularity, derived from the encoded
+    /// operators, see [`Granularity::Function`]
+    block_id: u64,
+}
+
+/// The ids of all local functions in the order of the function index space.
+fn local_function_ids(module: &walrus::Module) -> Vec<walrus::FunctionId> {
+    module
+        .funcs
+        .iter()
+        .filter(|function| matches!(function.kind, walrus::FunctionKind::Local(_)))
+        .map(|function| function.id())
+        .collect()
+}
+
+/// The ids of all local functions in the order `emit_wasm` writes them: walrus sorts
+/// them by their number of instructions, the largest first.
+fn emitted_function_ids(module: &walrus::Module) -> Vec<walrus::FunctionId> {
+    let mut functions: Vec<_> = module
+        .funcs
+        .iter()
+        .filter_map(|function| match &function.kind {
+            walrus::FunctionKind::Local(local) => Some((function.id(), local.size())),
+            _ => None,
+        })
+        .collect();
+    functions.sort_by_key(|(id, size)| (std::cmp::Reverse(*size), *id));
+    functions.into_iter().map(|(id, _)| id).collect()
+}
+
+/// The sizes of every local function.
+fn function_sizes(
+    wasm: &[u8],
+) -> Result<Vec<FunctionSizes>, wasmer::wasmparser::BinaryReaderError> {
+    use wasmer::wasmparser::{Parser, Payload};
+
+    let mut functions = Vec::new();
+    let mut code_start = 0;
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload? {
+            Payload::CodeSectionStart { range, .. } => code_start = range.start,
+            Payload::CodeSectionEntry(body) => {
+                let mut sizes = FunctionSizes::default();
+                let mut current = 0;
+                let mut reader = body.get_operators_reader()?;
+                let function_start = reader.original_position();
+                let mut block_start = function_start;
+                let mut previous = function_start;
+                let offsets = |first: usize, last: usize| WasmOffsets {
+                    first: (first - code_start) as u32,
+                    last: (last - code_start) as u32,
+                };
+                while !reader.eof() {
+                    let (operator, offset) = reader.read_with_offset()?;
+                    if ends_block(&operator) {
+                        if current > 0 {
+                            sizes.block_operators.push(current);
+                            sizes.block_bytes.push((offset - block_start) as u32);
+                            sizes.block_offsets.push(offsets(block_start, previous));
+                        }
+                        current = 0;
+                    } else {
+                        if current == 0 {
+                            block_start = offset;
+                        }
+                        current += 1;
+                    }
+                    previous = offset;
                 }
+                sizes.bytes = (reader.original_position() - function_start) as u32;
+                sizes.offsets = Some(offsets(function_start, previous));
+                sizes.block_id =
+                    sha256_block_id(&wasm[function_start..reader.original_position()]).as_u64();
+                functions.push(sizes);
             }
-            _ => {
-                if self.accumulated_ops.is_empty() {
-                    // We know we're at the beginning of a code block.
-                    // Call start_measurement before executing it.
-                    state.extend(&[
-                        Operator::I32Const { value: self.fn_index.as_u32() as i32 },
-                        Operator::I32Const { value: self.block_count as i32 },
-                        Operator::Call{ function_index: self.indexes.start_measurement.as_u32() },
-                    ]);
+            _ => {}
+        }
+    }
+    Ok(functions)
+}
+
+/// The offsets of the first and last operator of blocks, keyed by function index and
+/// local block id, see [`block_offsets`] and [`Profiling::block_offsets`].
+pub type BlockOffsets = HashMap<(u32, u32), (u32, u32)>;
+
+/// Where every block of every local function is in the code section, as the offsets
+/// of its first and last operator relative to the start of the section, keyed by
+/// function index and local block id. Blocks are split like `FunctionProfiling` does
+/// with `granularity`, so the offsets can be looked up in
+/// [`SourceLines`](crate::symbols::SourceLines) of `wasm`.
+///
+/// [`instrument_wasm`] reorders the functions, so the locations measured in Wasm
+/// prepared by it are found in [`Profiling::block_offsets`] instead.
+pub fn block_offsets(
+    wasm: &[u8],
+    granularity: Granularity,
+) -> Result<BlockOffsets, InstrumentationError> {
+    use wasmer::wasmparser::{Parser, Payload};
+
+    let parse_err = |err: wasmer::wasmparser::BinaryReaderError| InstrumentationError::ParseErr {
+        msg: err.to_string(),
+    };
+
+    let mut offsets = HashMap::new();
+    let mut code_start = 0;
+    let mut fn_index = 0;
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.map_err(parse_err)? {
+            Payload::CodeSectionStart { range, .. } => code_start = range.start,
+            Payload::CodeSectionEntry(body) => {
+                let relative = |offset: usize| (offset - code_start) as u32;
+                let mut reader = body.get_operators_reader().map_err(parse_err)?;
+                match granularity {
+                    Granularity::BasicBlock => {
+                        let mut local_block_id = 0;
+                        let mut first = None;
+                        let mut previous = 0;
+                        while !reader.eof() {
+                            let (operator, offset) =
+                                reader.read_with_offset().map_err(parse_err)?;
+                            if !ends_block(&operator) {
+                                first.get_or_insert(offset);
+                            } else if let Some(first) = first.take() {
+                                offsets.insert(
+                                    (fn_index, local_block_id),
+                                    (relative(first), relative(previous)),
+                                );
+                                local_block_id += 1;
+                            }
+                            previous = offset;
+                        }
+                    }
+                    Granularity::Function => {
+                        let first = reader.original_position();
+                        let mut previous = first;
+                        while !reader.eof() {
+                            previous = reader.read_with_offset().map_err(parse_err)?.1;
+                        }
+                        offsets.insert((fn_index, 0), (relative(first), relative(previous)));
+                    }
                 }
-                self.accumulated_ops.push((&operator).into());
+                fn_index += 1;
             }
+            _ => {}
         }
-
-        state.push_operator(operator);
-        Ok(())
     }
+    Ok(offsets)
 }
 
-#[derive(Debug, MemoryUsage, Clone)]
-struct ProfilingIndexes {
-    start_measurement: FunctionIndex,
-    take_measurement: FunctionIndex,
-}
+/// The local function indexes of all functions that can be called from the exported
+/// functions named `exports`, including the exported functions themselves.
+///
+/// Which function an indirect call ends up in is not known statically, so a function
+/// calling through a table is assumed to call all functions in that table.
+fn reachable_functions(wasm: &[u8], exports: &[String]) -> Result<Vec<u32>, InstrumentationError> {
+    use walrus::ir::{dfs_in_order, Visitor};
+    use walrus::{ExportItem, FunctionId, FunctionKind, TableId};
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[derive(Default)]
+    struct References {
+        functions: Vec<FunctionId>,
+        tables: Vec<TableId>,
+    }
 
-    use crate::code_blocks::CodeBlock;
+    impl<'instr> Visitor<'instr> for References {
+        fn visit_function_id(&mut self, function: &FunctionId) {
+            self.functions.push(*function);
+        }
 
-    use std::sync::Arc;
-    use wasmer::{wat2wasm, WasmerEnv};
+        fn visit_table_id(&mut self, table: &TableId) {
+            self.tables.push(*table);
+        }
+    }
 
-    const WAT: &[u8] = br#"
-    (module
-    (type $t0 (func (param i32) (result i32)))
-    (func $add_one (export "add_one") (type $t0) (param $p0 i32) (result i32)
-        get_local $p0
-        i32.const 1
-        i32.add)
-    (func $multisub (export "multisub") (type $t0) (param $p0 i32) (result i32)
-        get_local $p0
-        i32.const 2
-        i32.mul
-        call $sub_one
-        i32.const 1
-        i32.sub)
-    (func $sub_one (type $t0) (param $p0 i32) (result i32)
-        get_local $p0
-        i32.const 1
-        i32.sub))
-    "#;
+    // Parse the Wasm again, since walrus may reorder the functions when emitting it
+    let module =
+        walrus::Module::from_buffer(wasm).map_err(|err| InstrumentationError::ParseErr {
+            msg: err.to_string(),
+        })?;
 
-    struct Fixture {
-        instance: InstrumentedInstance,
+    let mut pending = Vec::new();
+    for name in exports {
+        let export = module
+            .exports
+            .iter()
+            .find(|export| export.name == *name)
+            .and_then(|export| match export.item {
+                ExportItem::Function(function) => Some(function),
+                _ => None,
+            });
+        match export {
+            Some(function) => pending.push(function),
+            None => return Err(InstrumentationError::UnknownExport { name: name.clone() }),
+        }
     }
 
-    #[derive(Debug, Clone, WasmerEnv)]
-    struct FixtureEnv {
-        start_calls: Arc<Mutex<Vec<(u32, u32)>>>,
-        end_calls: Arc<Mutex<Vec<(u32, u32, u64)>>>,
+    let mut reachable = HashSet::new();
+    let mut visited_tables = HashSet::new();
+    while let Some(function) = pending.pop() {
+        if !reachable.insert(function) {
+            continue;
+        }
+        if let FunctionKind::Local(local) = &module.funcs.get(function).kind {
+            let mut references = References::default();
+            dfs_in_order(&mut references, local, local.entry_block());
+            pending.extend(references.functions);
+            for table in references.tables {
+                if visited_tables.insert(table) {
+                    let elements = &module.tables.get(table).elem_segments;
+                    pending.extend(elements.iter().flat_map(|element| {
+                        module.elements.get(*element).members.iter().flatten()
+                    }));
+                }
+            }
+        }
     }
 
-    impl FixtureEnv {
-        fn new() -> Self {
-            Self {
-                start_calls: Arc::new(Mutex::new(Vec::new())),
-                end_calls: Arc::new(Mutex::new(Vec::new())),
-            }
+    Ok(module
+        .funcs
+        .iter()
+        .filter(|function| matches!(function.kind, FunctionKind::Local(_)))
+        .enumerate()
+        .filter(|(_, function)| reachable.contains(&function.id()))
+        .map(|(fn_index, _)| fn_index as u32)
+        .collect())
+}
+
+/// Names the local functions without a name that `symbols` knows.
+fn name_functions(module: &mut walrus::Module, symbols: &Symbols) {
+    let local_functions = module
+        .funcs
+        .iter_mut()
+        .filter(|function| matches!(function.kind, walrus::FunctionKind::Local(_)));
+    for (fn_index, function) in local_functions.enumerate() {
+        if function.name.is_none() {
+            function.name = symbols.function_name(fn_index as u32).map(String::from);
+        }
+    }
+}
+
+/// Add the imports we need to make instrumentation work, unless they already exist.
+fn add_imports(
+    module: &mut walrus::Module,
+    profiling: &Profiling,
+) -> Result<(), InstrumentationError> {
+    use walrus::ValType::*;
+
+    let import_module = profiling.import_module();
+    add_import(module, import_module, START_MEASUREMENT, &[I32, I32], &[])?;
+    add_import(
+        module,
+        import_module,
+        TAKE_MEASUREMENT,
+        &[I32, I32, I64],
+        &[],
+    )?;
+    if profiling.counts_loops() {
+        add_import(
+            module,
+            import_module,
+            COUNT_LOOP_ITERATION,
+            &[I32, I32],
+            &[],
+        )?;
+    }
+    if profiling.tracks_memory() {
+        let params = [I32, I32, I32, I32];
+        add_import(module, import_module, RECORD_MEMORY_GROW, &params, &[I32])?;
+    }
+    if profiling.times_host_calls() {
+        add_import(module, import_module, START_HOST_CALL, &[I32, I32], &[])?;
+        add_import(module, import_module, END_HOST_CALL, &[I32, I32], &[])?;
+    }
+    if profiling.tracks_indirect_calls() {
+        let params = [I32, I32, I32];
+        add_import(module, import_module, RECORD_INDIRECT_CALL, &params, &[I32])?;
+    }
+    if profiling.tracks_branches() {
+        let params = [I32, I32, I32, I32];
+        add_import(module, import_module, RECORD_BRANCH, &params, &[I32])?;
+    }
+    if profiling.tracks_call_depth() {
+        add_import(module, import_module, ENTER_FUNCTION, &[I32], &[])?;
+        add_import(module, import_module, ENTER_CALL, &[], &[])?;
+        add_import(module, import_module, EXIT_CALL, &[], &[])?;
+    }
+    if profiling.buffers_recording() {
+        add_import(module, import_module, FLUSH_MEASUREMENTS, &[I32, I32], &[])?;
+    }
+    Ok(())
+}
+
+/// Adds the functions buffered recording needs, unless they already exist:
+///
+/// - `__profiling_record_block(fn_index, local_block_id, block_id)` appends a record to
+///   a buffer of `capacity` records, which is allocated with `memory.grow` when the first
+///   record is written. A full buffer is flushed before the record is appended.
+/// - A wrapper around every exported function that flushes the buffer after the export
+///   returns. The exports are redirected to the wrappers.
+///
+/// Flushing calls the `flush_measurements` import with the address of the buffer and the
+/// number of records in it, then starts over at the beginning of the buffer.
+fn add_record_buffer(
+    module: &mut walrus::Module,
+    import_module: &str,
+    capacity: u32,
+) -> Result<(), InstrumentationError> {
+    use walrus::ir::{BinaryOp, MemArg, StoreKind, UnaryOp, Value};
+    use walrus::{ExportItem, FunctionBuilder, FunctionKind, InitExpr, ValType::*};
+
+    let already_instrumented = module
+        .funcs
+        .iter()
+        .any(|function| function.name.as_deref() == Some(RECORD_BLOCK_FUNCTION));
+    if already_instrumented {
+        return Ok(());
+    }
+
+    let memory = module
+        .memories
+        .iter()
+        .next()
+        .ok_or(InstrumentationError::MissingMemory)?
+        .id();
+    let flush = module
+        .imports
+        .iter()
+        .find(|import| import.module == import_module && import.name == FLUSH_MEASUREMENTS)
+        .and_then(|import| match import.kind {
+            walrus::ImportKind::Function(function) => Some(function),
+            _ => None,
+        })
+        .expect("add_record_buffer: called before add_imports");
+
+    // 0 until the buffer is allocated
+    let base = module
+        .globals
+        .add_local(I32, true, InitExpr::Value(Value::I32(0)));
+    // The number of records in the buffer
+    let len = module
+        .globals
+        .add_local(I32, true, InitExpr::Value(Value::I32(0)));
+    let size = capacity as u64 * BUFFER_RECORD_SIZE as u64;
+    let pages = ((size + 0xFFFF) >> 16) as i32;
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[I32, I32, I64], &[]);
+    builder.name(RECORD_BLOCK_FUNCTION.to_string());
+    let fn_index = module.locals.add(I32);
+    let local_block_id = module.locals.add(I32);
+    let block_id = module.locals.add(I64);
+    let address = module.locals.add(I32);
+    let arg = |offset| MemArg { align: 4, offset };
+    builder
+        .func_body()
+        .global_get(base)
+        .unop(UnaryOp::I32Eqz)
+        .if_else(
+            None,
+            |allocate| {
+                // Records are dropped if the memory cannot grow
+                allocate
+                    .i32_const(pages)
+                    .memory_grow(memory)
+                    .local_tee(address)
+                    .i32_const(-1)
+                    .binop(BinaryOp::I32Eq)
+                    .if_else(
+                        None,
+                        |failed| {
+                            failed.return_();
+                        },
+                        |_| {},
+                    )
+                    .local_get(address)
+                    .i32_const(16)
+                    .binop(BinaryOp::I32Shl)
+                    .global_set(base);
+            },
+            |_| {},
+        )
+        .global_get(len)
+        .i32_const(capacity as i32)
+        .binop(BinaryOp::I32Eq)
+        .if_else(
+            None,
+            |full| {
+                full.global_get(base)
+                    .global_get(len)
+                    .call(flush)
+                    .i32_const(0)
+                    .global_set(len);
+            },
+            |_| {},
+        )
+        .global_get(base)
+        .global_get(len)
+        .i32_const(BUFFER_RECORD_SIZE as i32)
+        .binop(BinaryOp::I32Mul)
+        .binop(BinaryOp::I32Add)
+        .local_tee(address)
+        .local_get(fn_index)
+        .store(memory, StoreKind::I32 { atomic: false }, arg(0))
+        .local_get(address)
+        .local_get(local_block_id)
+        .store(memory, StoreKind::I32 { atomic: false }, arg(4))
+        .local_get(address)
+        .local_get(block_id)
+        .store(
+            memory,
+            StoreKind::I64 { atomic: false },
+            MemArg {
+                align: 8,
+                offset: 8,
+            },
+        )
+        .global_get(len)
+        .i32_const(1)
+        .binop(BinaryOp::I32Add)
+        .global_set(len);
+    builder.finish(vec![fn_index, local_block_id, block_id], &mut module.funcs);
+
+    let exports: Vec<_> = module
+        .exports
+        .iter()
+        .filter_map(|export| match export.item {
+            ExportItem::Function(function) => Some((export.id(), export.name.clone(), function)),
+            _ => None,
+        })
+        .filter(|(_, _, function)| {
+            matches!(module.funcs.get(*function).kind, FunctionKind::Local(_))
+        })
+        .collect();
+    for (export, name, function) in exports {
+        let ty = module.types.get(module.funcs.get(function).ty()).clone();
+        let mut builder = FunctionBuilder::new(&mut module.types, ty.params(), ty.results());
+        builder.name(format!("{}flush_{}", BUFFER_FUNCTION_PREFIX, name));
+        let args: Vec<_> = ty
+            .params()
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect();
+        let mut body = builder.func_body();
+        for arg in &args {
+            body.local_get(*arg);
+        }
+        body.call(function)
+            .global_get(base)
+            .global_get(len)
+            .call(flush)
+            .i32_const(0)
+            .global_set(len);
+        let wrapper = builder.finish(args, &mut module.funcs);
+        module.exports.get_mut(export).item = ExportItem::Function(wrapper);
+    }
+    Ok(())
+}
+
+fn add_import(
+    module: &mut walrus::Module,
+    import_module: &str,
+    name: &str,
+    params: &[walrus::ValType],
+    results: &[walrus::ValType],
+) -> Result<(), InstrumentationError> {
+    let existing = module
+        .imports
+        .iter()
+        .find(|import| import.module == import_module && import.name == name)
+        .map(|import| import.kind.clone());
+
+    match existing {
+        Some(walrus::ImportKind::Function(fn_id)) => {
+            let ty = module.types.get(module.funcs.get(fn_id).ty());
+            if ty.params() == params && ty.results() == results {
+                Ok(())
+            } else {
+                Err(InstrumentationError::ImportMismatch {
+                    module: import_module.to_string(),
+                    name: name.to_string(),
+                })
+            }
+        }
+        Some(_) => Err(InstrumentationError::ImportMismatch {
+            module: import_module.to_string(),
+            name: name.to_string(),
+        }),
+        None => {
+            let ty = module.types.add(params, results);
+            module.add_import_func(import_module, name, ty);
+            Ok(())
+        }
+    }
+}
+
+/// A middleware that instruments every code block of a module with calls to the
+/// `profiling` imports.
+///
+/// One `Profiling` instance can be attached to multiple modules. The code blocks
+/// of all of them are registered in the same `BlockStore`. Since wasmer does not
+/// tell the function middlewares which module they belong to, modules sharing a
+/// `Profiling` are instrumented one after another: compiling a module waits until
+/// every function of the module compiled before got its middleware.
+#[non_exhaustive]
+#[derive(Debug, MemoryUsage)]
+pub struct Profiling {
+    block_store: Arc<Mutex<BlockStore>>,
+    /// Replaces `block_store` for registering blocks if set.
+    #[loupe(skip)]
+    sharded_store: Option<Arc<ShardedBlockStore>>,
+    granularity: Granularity,
+    import_module: String,
+    count_loops: bool,
+    track_memory: bool,
+    time_host_calls: bool,
+    track_indirect_calls: bool,
+    track_branches: bool,
+    track_call_depth: bool,
+    track_gas: bool,
+    /// The number of records in the buffer of buffered recording, if enabled
+    buffer_capacity: Option<u32>,
+    sampling: Sampling,
+    filter: FunctionFilter,
+    immediates: RetainedImmediates,
+    /// The function sizes computed by `instrument_wasm` for the module that is compiled next.
+    pending_function_sizes: Mutex<Option<Vec<FunctionSizes>>>,
+    /// The functions selected by [`FunctionFilter::ReachableFrom`], computed by
+    /// `instrument_wasm` for the module that is compiled next.
+    pending_reachable_functions: Mutex<Option<Vec<u32>>>,
+    /// The offsets of the blocks of all compiled functions, see [`Profiling::block_offsets`].
+    block_offsets: Mutex<BlockOffsets>,
+    modules: Mutex<ModuleIndexes>,
+    /// Notified when the compiling module got all of its function middlewares.
+    #[loupe(skip)]
+    module_done: Condvar,
+}
+
+/// Ends the compilation of a module with a [`Profiling`] when dropped, see
+/// [`Profiling::compilation_guard`].
+#[derive(Debug)]
+pub struct CompilationGuard<'a> {
+    profiling: &'a Profiling,
+}
+
+impl Drop for CompilationGuard<'_> {
+    fn drop(&mut self) {
+        self.profiling.end_compilation();
+    }
+}
+
+/// Which local functions get instrumented. Functions that are not instrumented
+/// run at full speed, but their cost is not attributed to any block.
+#[derive(Debug, Clone, PartialEq, Eq, MemoryUsage)]
+pub enum FunctionFilter {
+    All,
+    /// Only instrument the selected functions
+    Allow(Vec<FunctionSelector>),
+    /// Instrument all but the selected functions
+    Deny(Vec<FunctionSelector>),
+    /// Only instrument the functions that can be called from the exported functions
+    /// with these names, e.g. `execute`.
+    ///
+    /// This requires the Wasm to be prepared with [`instrument_wasm`] right
+    /// before it is compiled, since the call graph is not known during compilation.
+    ReachableFrom(Vec<String>),
+}
+
+impl Default for FunctionFilter {
+    fn default() -> Self {
+        FunctionFilter::All
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, MemoryUsage)]
+pub enum FunctionSelector {
+    /// A local function index, i.e. not counting imported functions
+    Index(u32),
+    /// A function name from the name section of the Wasm. Functions without
+    /// a name cannot be selected this way.
+    Name(String),
+}
+
+impl FunctionSelector {
+    pub fn index(index: u32) -> Self {
+        FunctionSelector::Index(index)
+    }
+
+    pub fn name(name: impl Into<String>) -> Self {
+        FunctionSelector::Name(name.into())
+    }
+}
+
+impl FunctionFilter {
+    /// Resolves the selectors of the filter to local function indexes.
+    fn selected_functions(&self, module_info: &ModuleInfo) -> Vec<u32> {
+        let selectors = match self {
+            FunctionFilter::All | FunctionFilter::ReachableFrom(_) => return Vec::new(),
+            FunctionFilter::Allow(selectors) | FunctionFilter::Deny(selectors) => selectors,
+        };
+
+        let mut selected = Vec::new();
+        for selector in selectors {
+            match selector {
+                FunctionSelector::Index(index) => selected.push(*index),
+                FunctionSelector::Name(name) => {
+                    selected.extend(
+                        module_info
+                            .function_names
+                            .iter()
+                            .filter(|(_, function_name)| *function_name == name)
+                            .filter_map(|(index, _)| module_info.local_func_index(*index))
+                            .map(|index| index.as_u32()),
+                    );
+                }
+            }
+        }
+        selected
+    }
+
+    fn includes(&self, selected: &[u32], index: LocalFunctionIndex) -> bool {
+        match self {
+            FunctionFilter::All => true,
+            FunctionFilter::Allow(_) | FunctionFilter::ReachableFrom(_) => {
+                selected.contains(&index.as_u32())
+            }
+            FunctionFilter::Deny(_) => !selected.contains(&index.as_u32()),
+        }
+    }
+}
+
+/// Which basic blocks get instrumented. Blocks that are not instrumented don't
+/// show up in the measurements and don't slow down execution.
+///
+/// Only applies to [`Granularity::BasicBlock`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, MemoryUsage)]
+pub struct Sampling {
+    /// Only instrument the first and then every nth block of every function.
+    pub every_nth: u32,
+    /// Only instrument blocks with at least this many operators.
+    ///
+    /// This requires the Wasm to be prepared with [`instrument_wasm`] right
+    /// before it is compiled, since the size of a block is not known when it starts.
+    pub min_block_size: usize,
+}
+
+impl Default for Sampling {
+    /// Instrument all blocks.
+    fn default() -> Self {
+        Sampling {
+            every_nth: 1,
+            min_block_size: 0,
+        }
+    }
+}
+
+/// What the measurements injected by `Profiling` wrap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, MemoryUsage)]
+pub enum Granularity {
+    /// Measure every non-branching code block separately.
+    BasicBlock,
+    /// Measure whole local functions. This is much cheaper, but the time spent in
+    /// callees is included in the measurement of the caller.
+    ///
+    /// Every function is registered in the `BlockStore` as one `CodeBlock` with
+    /// all of its operators under the truncated SHA-256 hash of its encoded operators,
+    /// like [`Sha256BlockHasher`](crate::code_blocks::Sha256BlockHasher) computes it.
+    /// The ids are the same in every run, but this requires the Wasm to be prepared
+    /// with [`instrument_wasm`] right before it is compiled.
+    Function,
+}
+
+impl Default for Granularity {
+    fn default() -> Self {
+        Granularity::BasicBlock
+    }
+}
+
+impl Profiling {
+    pub fn new(block_store: Arc<Mutex<BlockStore>>, granularity: Granularity) -> Self {
+        Self {
+            block_store,
+            sharded_store: None,
+            granularity,
+            import_module: DEFAULT_IMPORT_MODULE.to_string(),
+            count_loops: false,
+            track_memory: false,
+            time_host_calls: false,
+            track_indirect_calls: false,
+            track_branches: false,
+            track_call_depth: false,
+            track_gas: false,
+            buffer_capacity: None,
+            sampling: Sampling::default(),
+            filter: FunctionFilter::default(),
+            immediates: RetainedImmediates::default(),
+            pending_function_sizes: Mutex::new(None),
+            pending_reachable_functions: Mutex::new(None),
+            block_offsets: Mutex::new(HashMap::new()),
+            modules: Mutex::new(ModuleIndexes::default()),
+            module_done: Condvar::new(),
+        }
+    }
+
+    /// Makes the middleware expect the profiling functions to be imported
+    /// from `import_module` rather than from [`DEFAULT_IMPORT_MODULE`].
+    pub fn with_import_module(mut self, import_module: impl Into<String>) -> Self {
+        self.import_module = import_module.into();
+        self
+    }
+
+    /// Makes the middleware call the `count_loop_iteration` import at the start of
+    /// every loop iteration, independent of the granularity. Loops are identified by
+    /// the function index and their index in the function in the order they appear.
+    pub fn with_loop_counting(mut self) -> Self {
+        self.count_loops = true;
+        self
+    }
+
+    /// Makes the middleware call the `record_memory_grow` import after every `memory.grow`
+    /// with the block it belongs to and the memory size before and after growing.
+    pub fn with_memory_tracking(mut self) -> Self {
+        self.track_memory = true;
+        self
+    }
+
+    /// Makes the middleware call the `start_host_call` and `end_host_call` imports
+    /// around every call to an imported function, so the time spent in the host can
+    /// be told apart from the time spent in Wasm. Calls through tables are not covered.
+    pub fn with_host_call_timing(mut self) -> Self {
+        self.time_host_calls = true;
+        self
+    }
+
+    /// Makes the middleware call the `record_indirect_call` import before every
+    /// `call_indirect` with the index of the called table element. Together with the
+    /// table contents known to [`Symbols::table_function`], this tells which function
+    /// was called, and the call graph marks the call as indirect, see
+    /// [`CallStats::indirect`](crate::callgraph::CallStats::indirect).
+    pub fn with_indirect_call_tracking(mut self) -> Self {
+        self.track_indirect_calls = true;
+        self
+    }
+
+    /// Makes the middleware call the `record_branch` import before every `br_if` and
+    /// `br_table` with its condition or index, so that [`Measurements::branches`] tells
+    /// how often each of their targets was taken. Blocks ending with a biased branch
+    /// explain bimodal costs of the blocks they branch to.
+    ///
+    /// Branches are recorded for the block they end. In function granularity, all
+    /// branches of a function are recorded for its only block. A branch right after
+    /// the end of a nested block does not end a block of its own and is recorded for
+    /// the block before it.
+    pub fn with_branch_tracking(mut self) -> Self {
+        self.track_branches = true;
+        self
+    }
+
+    /// Makes the middleware call the `enter_function` import at the start of every
+    /// function and the `enter_call` and `exit_call` imports around every call to
+    /// another Wasm function, so that [`Measurements::max_call_depths`] tells how deeply
+    /// the calls of every entry point nest. Chains can derive stack height limits
+    /// for contracts from this.
+    ///
+    /// Functions that are not instrumented, e.g. due to a filter, are not counted.
+    pub fn with_call_depth_tracking(mut self) -> Self {
+        self.track_call_depth = true;
+        self
+    }
+
+    /// Makes the `start_measurement` and `take_measurement` imports read the remaining
+    /// gas of the metering middleware, so the gas charged during every block is
+    /// recorded along with its time, see [`Measurements::gas`]. This does not change
+    /// the instrumentation, only the imports added by `add_measuring_imports`.
+    ///
+    /// The module must be compiled with the metering middleware of `cosmwasm-vm` or
+    /// `wasmer_middlewares::Metering` after this middleware, which is how
+    /// `cosmwasm-vm` and [`push_profiling_middlewares`](crate::middlewares::push_profiling_middlewares)
+    /// order them. Instantiating fails if the module does not export
+    /// the `wasmer_metering_remaining_points` global. Has no effect with buffered
+    /// recording, which does not call these imports.
+    pub fn with_gas_tracking(mut self) -> Self {
+        self.track_gas = true;
+        self
+    }
+
+    /// Makes the instrumented code append a record to a buffer in the Wasm linear memory
+    /// at the end of every block, instead of calling `start_measurement` and
+    /// `take_measurement`. Calling into the host twice per block is the main overhead of
+    /// profiling, while the buffer is only handed to the `flush_measurements` import when
+    /// an exported function returns and whenever `capacity` records have been written.
+    ///
+    /// Since Wasm has no clock, this counts block executions rather than timing them,
+    /// see [`Measurements::flush_buffer`]. The counts can be priced with a
+    /// [`CostModel`](crate::cost_model::CostModel). The buffer is allocated with
+    /// `memory.grow` when the first block is recorded, so the module must define a
+    /// memory and its size includes the buffer. Records still in the buffer when the
+    /// contract traps are flushed after the next export call.
+    ///
+    /// This requires the Wasm to be prepared with [`instrument_wasm`], which adds the
+    /// buffer. Panics if `capacity` is 0 or greater than [`MAX_BUFFER_CAPACITY`].
+    pub fn with_buffered_recording(mut self, capacity: u32) -> Self {
+        assert!(
+            capacity > 0,
+            "Profiling::with_buffered_recording: the capacity must not be 0"
+        );
+        assert!(
+            capacity <= MAX_BUFFER_CAPACITY,
+            "Profiling::with_buffered_recording: the capacity must not exceed {}",
+            MAX_BUFFER_CAPACITY
+        );
+        self.buffer_capacity = Some(capacity);
+        self
+    }
+
+    /// Registers the code blocks in `store` instead of the `BlockStore` passed to
+    /// [`Profiling::new`], which stays empty.
+    ///
+    /// The functions of a module are compiled in parallel by some compilers, e.g.
+    /// Cranelift, and each of them locks the `BlockStore` for every block. A
+    /// `ShardedBlockStore` only locks one of its shards, so that compilation of
+    /// large contracts does not contend on a single mutex.
+    pub fn with_sharded_store(mut self, store: Arc<ShardedBlockStore>) -> Self {
+        self.sharded_store = Some(store);
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Restricts the instrumentation to some functions.
+    pub fn with_filter(mut self, filter: FunctionFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Makes the registered code blocks keep some immediates of their operators, e.g.
+    /// to tell `i32.const 0` and `i32.const 1000000` apart. Blocks that only differ in
+    /// these immediates get different ids and are measured separately.
+    pub fn with_immediates(mut self, immediates: RetainedImmediates) -> Self {
+        self.immediates = immediates;
+        self
+    }
+
+    pub fn filter(&self) -> &FunctionFilter {
+        &self.filter
+    }
+
+    pub fn retained_immediates(&self) -> RetainedImmediates {
+        self.immediates
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    pub fn counts_loops(&self) -> bool {
+        self.count_loops
+    }
+
+    pub fn tracks_memory(&self) -> bool {
+        self.track_memory
+    }
+
+    pub fn times_host_calls(&self) -> bool {
+        self.time_host_calls
+    }
+
+    pub fn tracks_indirect_calls(&self) -> bool {
+        self.track_indirect_calls
+    }
+
+    pub fn tracks_branches(&self) -> bool {
+        self.track_branches
+    }
+
+    pub fn tracks_call_depth(&self) -> bool {
+        self.track_call_depth
+    }
+
+    pub fn tracks_gas(&self) -> bool {
+        self.track_gas
+    }
+
+    pub fn buffers_recording(&self) -> bool {
+        self.buffer_capacity.is_some()
+    }
+
+    pub fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    /// The name of the import module the profiling functions are imported from.
+    pub fn import_module(&self) -> &str {
+        &self.import_module
+    }
+
+    /// A copy of the blocks registered so far, in the store passed to [`Profiling::new`]
+    /// or in the one passed to [`Profiling::with_sharded_store`].
+    pub fn registered_blocks(&self) -> BlockStore {
+        match &self.sharded_store {
+            Some(store) => store.to_block_store(),
+            None => self.block_store.lock().unwrap().clone(),
+        }
+    }
+
+    /// The number of modules instrumented by this middleware so far.
+    pub fn module_count(&self) -> usize {
+        self.modules.lock().unwrap().instrumented
+    }
+
+    /// Returns a guard that calls [`Profiling::end_compilation`] when dropped. Create it
+    /// right before compiling a module with this middleware on the same thread.
+    pub fn compilation_guard(&self) -> CompilationGuard<'_> {
+        CompilationGuard { profiling: self }
+    }
+
+    /// Forgets the module compiled on the current thread. Call this once its compilation
+    /// returned, whether it succeeded or not. If compiling a function fails, the compiler
+    /// stops before all functions got their middleware, and compilations of other modules
+    /// with this middleware would wait for the rest of them forever.
+    pub fn end_compilation(&self) {
+        // Called from the guard's drop, so it must not panic while unwinding.
+        let mut modules = self.modules.lock().unwrap_or_else(PoisonError::into_inner);
+        let thread = std::thread::current().id();
+        if matches!(&modules.compiling, Some(compiling) if compiling.thread == thread) {
+            modules.compiling = None;
+            modules.pending_functions = 0;
+            self.module_done.notify_all();
+        }
+    }
+
+    /// Describes everything besides the Wasm that [`instrument_wasm`] depends on.
+    pub(crate) fn preparation_key(&self) -> String {
+        let reachable_from = match &self.filter {
+            FunctionFilter::ReachableFrom(exports) => Some(exports),
+            _ => None,
+        };
+        format!(
+            "{:?}",
+            (
+                &self.import_module,
+                self.count_loops,
+                self.track_memory,
+                self.time_host_calls,
+                self.track_indirect_calls,
+                self.track_branches,
+                self.track_call_depth,
+                self.buffer_capacity,
+                reachable_from,
+            )
+        )
+    }
+
+    /// Where the blocks of all functions compiled so far are in the code section of the
+    /// Wasm passed to [`instrument_wasm`], keyed by their location in the instrumented Wasm.
+    /// Look them up in the [`SourceLines`](crate::symbols::SourceLines) of the
+    /// uninstrumented Wasm, e.g. for [`CoverageReport::write_lcov`](crate::coverage::CoverageReport::write_lcov).
+    ///
+    /// Empty unless the modules were prepared with [`instrument_wasm`]. Like the block
+    /// locations, the offsets of different modules cannot be told apart.
+    pub fn block_offsets(&self) -> BlockOffsets {
+        self.block_offsets.lock().unwrap().clone()
+    }
+
+    /// Makes the module compiled next use what [`prepare_module`] computed for it.
+    pub(crate) fn expect_module(&self, prepared: &PreparedModule) {
+        *self.pending_function_sizes.lock().unwrap() = Some(prepared.function_sizes.clone());
+        if let Some(reachable) = &prepared.reachable_functions {
+            *self.pending_reachable_functions.lock().unwrap() = Some(reachable.clone());
+        }
+    }
+}
+
+impl ModuleMiddleware for Profiling {
+    fn generate_function_middleware(
+        &self,
+        local_function_index: wasmer::LocalFunctionIndex,
+    ) -> Box<dyn wasmer::FunctionMiddleware> {
+        let module = {
+            let mut modules = self.modules.lock().unwrap();
+            let module = modules.compiling.clone().expect(
+                "Profiling::generate_function_middleware: called before transform_module_info",
+            );
+            modules.pending_functions -= 1;
+            if modules.pending_functions == 0 {
+                // Every function got its middleware, so the next module can be compiled.
+                modules.compiling = None;
+                self.module_done.notify_all();
+            }
+            module
+        };
+
+        if !self
+            .filter
+            .includes(&module.selected_functions, local_function_index)
+            || module
+                .buffer_functions
+                .contains(&local_function_index.as_u32())
+        {
+            return Box::new(PassThrough);
+        }
+
+        let block_sink = match &self.sharded_store {
+            Some(store) => BlockSink::Sharded(store.clone()),
+            None => BlockSink::Shared(self.block_store.clone()),
+        };
+        let mut function_profiling =
+            FunctionProfiling::new(block_sink, module.indexes.clone(), local_function_index);
+        if self.granularity == Granularity::Function {
+            let sizes = module.function_sizes.as_ref().expect(
+                "Profiling::generate_function_middleware: function granularity requires the Wasm to be prepared with instrument_wasm",
+            );
+            function_profiling.function_block_id = Some(BlockId(
+                sizes[local_function_index.as_u32() as usize].block_id,
+            ));
+        }
+        function_profiling.sampling = self.sampling;
+        function_profiling.immediates = self.immediates;
+        match &module.function_sizes {
+            Some(sizes) => {
+                let sizes = &sizes[local_function_index.as_u32() as usize];
+                let fn_index = local_function_index.as_u32();
+                let mut block_offsets = self.block_offsets.lock().unwrap();
+                let offsets = match self.granularity {
+                    Granularity::BasicBlock => sizes.block_offsets.clone(),
+                    Granularity::Function => sizes.offsets.into_iter().collect(),
+                };
+                for (local_block_id, offsets) in offsets.into_iter().enumerate() {
+                    block_offsets.insert(
+                        (fn_index, local_block_id as u32),
+                        (offsets.first, offsets.last),
+                    );
+                }
+                function_profiling.sizes = Some(sizes.clone());
+            }
+            None if self.sampling.min_block_size > 0 => panic!(
+                "Profiling::generate_function_middleware: sampling by block size requires the Wasm to be prepared with instrument_wasm"
+            ),
+            None => {}
+        }
+        Box::new(function_profiling)
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        // The metering middleware adds this export, so it ran before us and the code we
+        // see contains its accounting.
+        if module_info.exports.contains_key(METERING_REMAINING_POINTS) {
+            panic!(
+                "Profiling::transform_module_info: Profiling must be pushed before the metering middleware, see push_profiling_middlewares."
+            );
+        }
+        let mut modules = self.modules.lock().unwrap();
+        let module_id = module_info.id.id();
+        let thread = std::thread::current().id();
+
+        while let Some(compiling) = &modules.compiling {
+            if compiling.id == module_id {
+                panic!(
+                    "Profiling::transform_module_info: Attempting to instrument the same module twice."
+                );
+            }
+            if compiling.thread == thread {
+                // The compilation of the previous module on this thread failed before
+                // all of its functions got their middleware.
+                modules.compiling = None;
+                break;
+            }
+            modules = self.module_done.wait(modules).unwrap();
+        }
+
+        let find_import = |name: &str| {
+            module_info
+                .imports
+                .iter()
+                .find_map(|((module, field, _), index)| {
+                    if (module.as_str(), field.as_str()) == (self.import_module(), name) {
+                        if let ImportIndex::Function(fn_index) = index {
+                            return Some(*fn_index);
+                        }
+                    }
+                    None
+                })
+        };
+
+        let buffer_functions: Vec<_> = module_info
+            .function_names
+            .iter()
+            .filter(|(_, name)| name.starts_with(BUFFER_FUNCTION_PREFIX))
+            .filter_map(|(index, name)| Some((module_info.local_func_index(*index)?, *index, name)))
+            .collect();
+        let record_block = if self.buffers_recording() {
+            let record_block = buffer_functions
+                .iter()
+                .find(|(_, _, name)| *name == RECORD_BLOCK_FUNCTION)
+                .map(|(_, index, _)| *index)
+                .expect("Profiling::transform_module_info: buffered recording requires the Wasm to be prepared with instrument_wasm");
+            find_import(FLUSH_MEASUREMENTS).unwrap();
+            Some(record_block)
+        } else {
+            None
+        };
+
+        let indexes = ProfilingIndexes {
+            start_measurement: find_import(START_MEASUREMENT).unwrap(),
+            take_measurement: find_import(TAKE_MEASUREMENT).unwrap(),
+            count_loop_iteration: if self.count_loops {
+                Some(find_import(COUNT_LOOP_ITERATION).unwrap())
+            } else {
+                None
+            },
+            record_memory_grow: if self.track_memory {
+                Some(find_import(RECORD_MEMORY_GROW).unwrap())
+            } else {
+                None
+            },
+            host_calls: if self.time_host_calls {
+                Some((
+                    find_import(START_HOST_CALL).unwrap(),
+                    find_import(END_HOST_CALL).unwrap(),
+                ))
+            } else {
+                None
+            },
+            record_indirect_call: if self.track_indirect_calls {
+                Some(find_import(RECORD_INDIRECT_CALL).unwrap())
+            } else {
+                None
+            },
+            record_branch: if self.track_branches {
+                Some(find_import(RECORD_BRANCH).unwrap())
+            } else {
+                None
+            },
+            call_depth: if self.track_call_depth {
+                Some((
+                    find_import(ENTER_FUNCTION).unwrap(),
+                    find_import(ENTER_CALL).unwrap(),
+                    find_import(EXIT_CALL).unwrap(),
+                ))
+            } else {
+                None
+            },
+            record_block,
+            imported_functions: module_info.num_imported_functions as u32,
+        };
+
+        let selected_functions = match self.filter {
+            FunctionFilter::ReachableFrom(_) => self
+                .pending_reachable_functions
+                .lock()
+                .unwrap()
+                .take()
+                .expect("Profiling::transform_module_info: filtering by reachability requires the Wasm to be prepared with instrument_wasm"),
+            _ => self.filter.selected_functions(module_info),
+        };
+        let module = CompilingModule {
+            id: module_id,
+            thread,
+            indexes,
+            function_sizes: self.pending_function_sizes.lock().unwrap().take(),
+            selected_functions,
+            buffer_functions: buffer_functions
+                .iter()
+                .map(|(index, _, _)| index.as_u32())
+                .collect(),
+        };
+        modules.instrumented += 1;
+        modules.pending_functions =
+            module_info.functions.len() - module_info.num_imported_functions;
+        if modules.pending_functions > 0 {
+            modules.compiling = Some(Arc::new(module));
+        }
+    }
+}
+
+#[derive(Debug, Default, MemoryUsage)]
+struct ModuleIndexes {
+    /// The module whose functions are being compiled, until all of them got their
+    /// middleware or its compilation ended, see [`Profiling::end_compilation`].
+    compiling: Option<Arc<CompilingModule>>,
+    /// The number of local functions of `compiling` that did not get their middleware yet
+    pending_functions: usize,
+    /// The number of modules instrumented so far
+    instrumented: usize,
+}
+
+/// What the function middlewares of a module need to know about it.
+#[derive(Debug, MemoryUsage)]
+struct CompilingModule {
+    /// The `ModuleId`
+    id: String,
+    #[loupe(skip)]
+    thread: std::thread::ThreadId,
+    indexes: ProfilingIndexes,
+    /// The sizes of every function. Only known for Wasm prepared with `instrument_wasm`.
+    function_sizes: Option<Vec<FunctionSizes>>,
+    /// The local function indexes matched by the filter
+    selected_functions: Vec<u32>,
+    /// The local function indexes of the functions added by [`add_record_buffer`].
+    /// They are never instrumented.
+    buffer_functions: Vec<u32>,
+}
+
+/// The middleware for functions that are not instrumented.
+#[derive(Debug)]
+struct PassThrough;
+
+impl FunctionMiddleware for PassThrough {}
+
+/// Fails instrumentation if a block cannot be registered, e.g. due to a hash collision.
+fn middleware_error(err: BlockStoreError) -> wasmer::MiddlewareError {
+    wasmer::MiddlewareError::new("Profiling", err.to_string())
+}
+
+/// Where a `FunctionProfiling` registers its blocks.
+#[derive(Debug)]
+enum BlockSink {
+    Shared(Arc<Mutex<BlockStore>>),
+    Sharded(Arc<ShardedBlockStore>),
+}
+
+impl BlockSink {
+    /// Registers `block` at a location, under `id` if given or under its hash otherwise.
+    fn register(
+        &self,
+        fn_index: u32,
+        local_block_id: u32,
+        id: Option<BlockId>,
+        block: CodeBlock,
+    ) -> Result<BlockId, BlockStoreError> {
+        match self {
+            BlockSink::Shared(store) => {
+                let mut store = store.lock().unwrap();
+                let id = match id {
+                    Some(id) => store.register_block_with_id(id, block).map(|_| id)?,
+                    None => store.register_block(block)?,
+                };
+                store.register_location(fn_index, local_block_id, id);
+                Ok(id)
+            }
+            BlockSink::Sharded(store) => {
+                let id = match id {
+                    Some(id) => store.register_block_with_id(id, block).map(|_| id)?,
+                    None => store.register_block(block)?,
+                };
+                store.register_location(fn_index, local_block_id, id);
+                Ok(id)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FunctionProfiling {
+    block_sink: BlockSink,
+    accumulated_ops: Vec<OperatorSymbol>,
+    /// The retained immediates of `accumulated_ops`, if any are retained.
+    accumulated_immediates: Vec<Immediate>,
+    immediates: RetainedImmediates,
+    indexes: ProfilingIndexes,
+    /// The index of the current basic block within the function. Always 0 in
+    /// function granularity.
+    block_index: u32,
+    fn_index: LocalFunctionIndex,
+    /// Set in function granularity only.
+    function_block_id: Option<BlockId>,
+    /// The nesting depth of blocks in the function. Only tracked in function granularity.
+    depth: u32,
+    /// The number of loops seen so far in the function.
+    loop_count: u32,
+    sampling: Sampling,
+    /// The sizes of the function and its basic blocks, if the Wasm was prepared with
+    /// `instrument_wasm`.
+    sizes: Option<FunctionSizes>,
+    /// The number of basic blocks seen so far in the function.
+    blocks_seen: u32,
+    /// The number of basic blocks to skip before the next one is sampled.
+    blocks_until_sample: u32,
+    /// Whether the current basic block is instrumented.
+    block_sampled: bool,
+    /// Whether the first operator of the function was fed.
+    started: bool,
+}
+
+impl FunctionProfiling {
+    fn new(block_sink: BlockSink, indexes: ProfilingIndexes, fn_index: LocalFunctionIndex) -> Self {
+        Self {
+            block_sink,
+            accumulated_ops: Vec::new(),
+            accumulated_immediates: Vec::new(),
+            immediates: RetainedImmediates::default(),
+            indexes,
+            block_index: 0,
+            fn_index,
+            function_block_id: None,
+            depth: 0,
+            loop_count: 0,
+            sampling: Sampling::default(),
+            sizes: None,
+            blocks_seen: 0,
+            blocks_until_sample: 0,
+            block_sampled: false,
+            started: false,
+        }
+    }
+
+    /// Decides whether the block that starts now gets instrumented.
+    fn sample_next_block(&mut self) -> bool {
+        let index = self.blocks_seen;
+        self.blocks_seen += 1;
+
+        if self.blocks_until_sample > 0 {
+            self.blocks_until_sample -= 1;
+            return false;
+        }
+        self.blocks_until_sample = self.sampling.every_nth.saturating_sub(1);
+
+        match &self.sizes {
+            Some(sizes) => {
+                sizes
+                    .block_operators
+                    .get(index as usize)
+                    .copied()
+                    .unwrap_or(0)
+                    >= self.sampling.min_block_size
+            }
+            None => true,
+        }
+    }
+
+    fn accumulate(&mut self, operator: &Operator) {
+        self.accumulated_ops.push(operator.into());
+        if self.immediates.any() {
+            self.accumulated_immediates
+                .push(self.immediates.immediate(operator));
+        }
+    }
+
+    /// Takes the accumulated operators as a code block.
+    fn take_block(&mut self) -> CodeBlock {
+        let operators = std::mem::take(&mut self.accumulated_ops);
+        let block = if self.immediates.any() {
+            let immediates = std::mem::take(&mut self.accumulated_immediates);
+            CodeBlock::with_immediates(operators, immediates)
+        } else {
+            CodeBlock::from(operators)
+        };
+
+        let byte_len = self.sizes.as_ref().and_then(|sizes| {
+            if self.function_block_id.is_some() {
+                Some(sizes.bytes)
+            } else {
+                sizes.block_bytes.get(self.block_index as usize).copied()
+            }
+        });
+        let block = match byte_len {
+            Some(byte_len) => block.with_byte_len(byte_len),
+            None => block,
+        };
+        let offsets = self.sizes.as_ref().and_then(|sizes| {
+            if self.function_block_id.is_some() {
+                sizes.offsets
+            } else {
+                sizes.block_offsets.get(self.block_index as usize).copied()
+            }
+        });
+        match offsets {
+            Some(offsets) => block.with_offsets(offsets),
+            None => block,
+        }
+    }
+
+    fn start_measurement_ops<'a>(&self) -> [Operator<'a>; 3] {
+        [
+            Operator::I32Const {
+                value: self.fn_index.as_u32() as i32,
+            },
+            Operator::I32Const {
+                value: self.block_index as i32,
+            },
+            Operator::Call {
+                function_index: self.indexes.start_measurement.as_u32(),
+            },
+        ]
+    }
+
+    fn take_measurement_ops<'a>(&self, block_id: BlockId) -> [Operator<'a>; 4] {
+        [
+            Operator::I32Const {
+                value: self.fn_index.as_u32() as i32,
+            },
+            Operator::I32Const {
+                value: self.block_index as i32,
+            },
+            Operator::I64Const {
+                value: block_id.as_u64() as i64,
+            },
+            Operator::Call {
+                function_index: self
+                    .indexes
+                    .record_block
+                    .unwrap_or(self.indexes.take_measurement)
+                    .as_u32(),
+            },
+        ]
+    }
+
+    fn feed_basic_block<'a>(
+        &mut self,
+        operator: &Operator<'a>,
+        state: &mut wasmer::MiddlewareReaderState<'a>,
+    ) -> Result<(), wasmer::MiddlewareError> {
+        if ends_block(operator) {
+            if !self.accumulated_ops.is_empty() {
+                let block = self.take_block();
+                if self.block_sampled {
+                    let block_id = self
+                        .block_sink
+                        .register(self.fn_index.as_u32(), self.block_index, None, block)
+                        .map_err(middleware_error)?;
+
+                    // We're at the end of a code block. Finalize the measurement.
+                    state.extend(&self.take_measurement_ops(block_id));
+                }
+            }
+        } else {
+            if self.accumulated_ops.is_empty() {
+                // We know we're at the beginning of a code block.
+                self.block_index = self.blocks_seen;
+                self.block_sampled = self.sample_next_block();
+                if self.block_sampled && self.indexes.record_block.is_none() {
+                    // Call start_measurement before executing it.
+                    state.extend(&self.start_measurement_ops());
+                }
+            }
+            self.accumulate(operator);
+        }
+        Ok(())
+    }
+
+    fn feed_function<'a>(
+        &mut self,
+        operator: &Operator<'a>,
+        state: &mut wasmer::MiddlewareReaderState<'a>,
+        block_id: BlockId,
+    ) -> Result<(), wasmer::MiddlewareError> {
+        if self.accumulated_ops.is_empty() && self.indexes.record_block.is_none() {
+            // The first operator of the function.
+            state.extend(&self.start_measurement_ops());
+        }
+        self.accumulate(operator);
+
+        match operator {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                self.depth += 1;
+            }
+            Operator::End if self.depth > 0 => {
+                self.depth -= 1;
+            }
+            Operator::End => {
+                // The end of the function.
+                let block = self.take_block();
+                self.block_sink
+                    .register(self.fn_index.as_u32(), 0, Some(block_id), block)
+                    .map_err(middleware_error)?;
+                state.extend(&self.take_measurement_ops(block_id));
+            }
+            Operator::Return => {
+                state.extend(&self.take_measurement_ops(block_id));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The import index if `operator` calls a host function and host calls are timed.
+    fn host_call(&self, operator: &Operator) -> Option<u32> {
+        match operator {
+            Operator::Call { function_index }
+                if self.indexes.host_calls.is_some()
+                    && *function_index < self.indexes.imported_functions =>
+            {
+                Some(*function_index)
+            }
+            _ => None,
+        }
+    }
+
+    fn host_call_ops<'a>(&self, import_index: u32, hook: FunctionIndex) -> [Operator<'a>; 3] {
+        [
+            Operator::I32Const {
+                value: self.fn_index.as_u32() as i32,
+            },
+            Operator::I32Const {
+                value: import_index as i32,
+            },
+            Operator::Call {
+                function_index: hook.as_u32(),
+            },
+        ]
+    }
+}
+
+impl FunctionMiddleware for FunctionProfiling {
+    fn feed<'a>(
+        &mut self,
+        operator: wasmer::wasmparser::Operator<'a>,
+        state: &mut wasmer::MiddlewareReaderState<'a>,
+    ) -> Result<(), wasmer::MiddlewareError> {
+        let is_loop = matches!(operator, Operator::Loop { .. });
+        let grown_memory = match operator {
+            Operator::MemoryGrow { mem, mem_byte } => Some((mem, mem_byte)),
+            _ => None,
+        };
+
+        let host_call = self.host_call(&operator);
+        let indirect_call_table = match operator {
+            Operator::CallIndirect { table_index, .. } => Some(table_index),
+            _ => None,
+        };
+        let max_branch_outcome = match &operator {
+            Operator::BrIf { .. } => Some(1),
+            Operator::BrTable { table } => Some(table.len() as u32),
+            _ => None,
+        };
+        let wasm_call = match operator {
+            Operator::Call { function_index } => function_index >= self.indexes.imported_functions,
+            Operator::CallIndirect { .. } => true,
+            _ => false,
+        };
+
+        // Before the measurement of the first block starts
+        if let (false, Some((enter_function, _, _))) = (self.started, self.indexes.call_depth) {
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.fn_index.as_u32() as i32,
+                },
+                Operator::Call {
+                    function_index: enter_function.as_u32(),
+                },
+            ]);
+        }
+        self.started = true;
+
+        match self.function_block_id {
+            Some(block_id) => self.feed_function(&operator, state, block_id)?,
+            None => self.feed_basic_block(&operator, state)?,
+        }
+
+        // The called element index is on top of the stack. The import gets the
+        // caller and table as well and passes the element index on.
+        if let (Some(table_index), Some(record_indirect_call)) =
+            (indirect_call_table, self.indexes.record_indirect_call)
+        {
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.fn_index.as_u32() as i32,
+                },
+                Operator::I32Const {
+                    value: table_index as i32,
+                },
+                Operator::Call {
+                    function_index: record_indirect_call.as_u32(),
+                },
+            ]);
+        }
+
+        // The condition or index is on top of the stack. The import gets the block
+        // the branch ends and passes the condition or index on.
+        if let (Some(max_outcome), Some(record_branch)) =
+            (max_branch_outcome, self.indexes.record_branch)
+        {
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.fn_index.as_u32() as i32,
+                },
+                Operator::I32Const {
+                    value: self.block_index as i32,
+                },
+                Operator::I32Const {
+                    value: max_outcome as i32,
+                },
+                Operator::Call {
+                    function_index: record_branch.as_u32(),
+                },
+            ]);
+        }
+
+        // In basic block granularity, the call ends the current block, so its
+        // measurement is already finished here.
+        match (host_call, self.indexes.host_calls, self.indexes.call_depth) {
+            (Some(import_index), Some((start_host_call, end_host_call)), _) => {
+                state.extend(&self.host_call_ops(import_index, start_host_call));
+                state.push_operator(operator);
+                state.extend(&self.host_call_ops(import_index, end_host_call));
+            }
+            (_, _, Some((_, enter_call, exit_call))) if wasm_call => {
+                state.push_operator(Operator::Call {
+                    function_index: enter_call.as_u32(),
+                });
+                state.push_operator(operator);
+                state.push_operator(Operator::Call {
+                    function_index: exit_call.as_u32(),
+                });
+            }
+            _ => state.push_operator(operator),
+        }
+
+        // The first instruction after the loop header is executed once per iteration.
+        if let (true, Some(count_loop_iteration)) = (is_loop, self.indexes.count_loop_iteration) {
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.fn_index.as_u32() as i32,
+                },
+                Operator::I32Const {
+                    value: self.loop_count as i32,
+                },
+                Operator::Call {
+                    function_index: count_loop_iteration.as_u32(),
+                },
+            ]);
+            self.loop_count += 1;
+        }
+
+        // `memory.grow` left the previous size on the stack. The import gets the
+        // current size as well and passes the previous size on.
+        if let (Some((mem, mem_byte)), Some(record_memory_grow)) =
+            (grown_memory, self.indexes.record_memory_grow)
+        {
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.fn_index.as_u32() as i32,
+                },
+                Operator::I32Const {
+                    value: self.block_index as i32,
+                },
+                Operator::MemorySize { mem, mem_byte },
+                Operator::Call {
+                    function_index: record_memory_grow.as_u32(),
+                },
+            ]);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, MemoryUsage, Clone)]
+struct ProfilingIndexes {
+    start_measurement: FunctionIndex,
+    take_measurement: FunctionIndex,
+    /// Only set when counting loop iterations.
+    count_loop_iteration: Option<FunctionIndex>,
+    /// Only set when tracking memory growth.
+    record_memory_grow: Option<FunctionIndex>,
+    /// The `start_host_call` and `end_host_call` imports. Only set when timing host calls.
+    host_calls: Option<(FunctionIndex, FunctionIndex)>,
+    /// Only set when tracking indirect calls.
+    record_indirect_call: Option<FunctionIndex>,
+    /// Only set when tracking branches.
+    record_branch: Option<FunctionIndex>,
+    /// The `enter_function`, `enter_call` and `exit_call` imports. Only set when tracking
+    /// the call depth.
+    call_depth: Option<(FunctionIndex, FunctionIndex, FunctionIndex)>,
+    /// The function appending to the buffer, which replaces `take_measurement`. Only
+    /// set for buffered recording.
+    record_block: Option<FunctionIndex>,
+    /// The number of imported functions including the profiling imports. Calls to
+    /// lower function indexes are host calls.
+    imported_functions: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::CodeBlock;
+
+    use std::sync::Arc;
+    use wasmer::{wat2wasm, WasmerEnv};
+
+    const WAT: &[u8] = br#"
+    (module
+    (type $t0 (func (param i32) (result i32)))
+    (func $add_one (export "add_one") (type $t0) (param $p0 i32) (result i32)
+        get_local $p0
+        i32.const 1
+        i32.add)
+    (func $multisub (export "multisub") (type $t0) (param $p0 i32) (result i32)
+        get_local $p0
+        i32.const 2
+        i32.mul
+        call $sub_one
+        i32.const 1
+        i32.sub)
+    (func $sub_one (type $t0) (param $p0 i32) (result i32)
+        get_local $p0
+        i32.const 1
+        i32.sub))
+    "#;
+
+    struct Fixture {
+        instance: InstrumentedInstance,
+    }
+
+    #[derive(Debug, Clone, WasmerEnv)]
+    struct FixtureEnv {
+        start_calls: Arc<Mutex<Vec<(u32, u32)>>>,
+        end_calls: Arc<Mutex<Vec<(u32, u32, u64)>>>,
+    }
+
+    impl FixtureEnv {
+        fn new() -> Self {
+            Self {
+                start_calls: Arc::new(Mutex::new(Vec::new())),
+                end_calls: Arc::new(Mutex::new(Vec::new())),
+            }
         }
     }
 
@@ -325,78 +2420,1298 @@ mod tests {
             let wasm = wat2wasm(WAT).unwrap();
             let module = Module::from_bytes(&wasm);
 
-            let env = FixtureEnv::new();
-            let start_measurement_fn = |env: &FixtureEnv, fun: u32, block: u32| {
-                env.start_calls.lock().unwrap().push((fun, block));
-            };
-            let take_measurement_fn = |env: &FixtureEnv, fun: u32, block: u32, hash: u64| {
-                env.end_calls.lock().unwrap().push((fun, block, hash));
-            };
+            let env = FixtureEnv::new();
+            let start_measurement_fn = |env: &FixtureEnv, fun: u32, block: u32| {
+                env.start_calls.lock().unwrap().push((fun, block));
+            };
+            let take_measurement_fn = |env: &FixtureEnv, fun: u32, block: u32, hash: u64| {
+                env.end_calls.lock().unwrap().push((fun, block, hash));
+            };
+
+            let block_store = Arc::new(Mutex::new(BlockStore::new()));
+
+            Self {
+                instance: module.instrument(
+                    block_store,
+                    env,
+                    start_measurement_fn,
+                    take_measurement_fn,
+                ),
+            }
+        }
+    }
+
+    // #[test]
+    // fn instrumentation_does_not_mess_up_local_fns() {
+    //     let fixture = Fixture::new();
+
+    //     let result = fixture.add_one().call(&[Value::I32(42)]).unwrap();
+    //     assert_eq!(result[0], Value::I32(43));
+
+    //     let result = fixture.multisub().call(&[Value::I32(4)]).unwrap();
+    //     assert_eq!(result[0], Value::I32(6));
+    // }
+
+    #[test]
+    fn instrumentation_registers_code_blocks() {
+        let fixture = Fixture::new();
+
+        let block_store = fixture.instance.profiling.block_store.lock().unwrap();
+        assert_eq!(block_store.len(), 4);
+        assert_eq!(block_store.locations().count(), 4);
+        println!("{:?}", block_store);
+
+        // The body of $add_one.
+        let expected_block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Add,
+        ]);
+        let block = block_store.get_block(expected_block.get_hash());
+        assert_eq!(block, Some(&expected_block));
+
+        // The body of $sub_one
+        let expected_block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Sub,
+        ]);
+        let block = block_store.get_block(expected_block.get_hash());
+        assert_eq!(block, Some(&expected_block));
+
+        // The body of $multisub until the `call` instruction.
+        let expected_block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Mul,
+        ]);
+        let block = block_store.get_block(expected_block.get_hash());
+        assert_eq!(block, Some(&expected_block));
+
+        // The body of $multisub after the `call` instruction.
+        let expected_block =
+            CodeBlock::from(vec![OperatorSymbol::I32Const, OperatorSymbol::I32Sub]);
+        let block = block_store.get_block(expected_block.get_hash());
+        assert_eq!(block, Some(&expected_block));
+
+        // i32.const 1: 2 bytes, i32.sub: 1 byte
+        let block = block.unwrap();
+        assert_eq!(block.byte_len(), Some(3));
+        // i32.sub starts after i32.const 1
+        let offsets = block.offsets().unwrap();
+        assert_eq!(offsets.last - offsets.first, 2);
+        assert_eq!(block.operator_counts()[&OperatorSymbol::I32Sub], 1);
+    }
+
+    #[test]
+    fn instrumented_instance_has_symbols() {
+        let fixture = Fixture::new();
+
+        // walrus may reorder the functions
+        let symbols = fixture.instance.symbols();
+        let mut names: Vec<String> = (0..3).map(|i| symbols.describe_function(i)).collect();
+        names.sort();
+        assert_eq!(names, ["add_one", "multisub", "sub_one"]);
+    }
+
+    #[test]
+    fn profiling_can_be_shared_between_modules() {
+        const OTHER_WAT: &[u8] = br#"
+        (module
+        (type $t0 (func (param i32) (result i32)))
+        (import "env" "debug" (func $debug (param i32)))
+        (func $double (export "double") (type $t0) (param $p0 i32) (result i32)
+            get_local $p0
+            i32.const 2
+            i32.shl))
+        "#;
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::BasicBlock));
+
+        let start_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32| {};
+        let take_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {};
+
+        let wasm = wat2wasm(WAT).unwrap();
+        let _first = Module::from_bytes(&wasm).instrument_with(
+            profiling.clone(),
+            FixtureEnv::new(),
+            start_measurement_fn,
+            take_measurement_fn,
+        );
+        let wasm = wat2wasm(OTHER_WAT).unwrap();
+        let _second = Module::from_bytes(&wasm).instrument_with(
+            profiling.clone(),
+            FixtureEnv::new(),
+            start_measurement_fn,
+            take_measurement_fn,
+        );
+
+        assert_eq!(profiling.module_count(), 2);
+
+        // The blocks of both modules end up in the same store.
+        let block_store = block_store.lock().unwrap();
+        assert_eq!(block_store.len(), 5);
+        let expected_block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Shl,
+        ]);
+        let block = block_store.get_block(expected_block.get_hash());
+        assert_eq!(block, Some(&expected_block));
+
+        // Nothing is kept about the modules once all their functions are compiled.
+        assert!(profiling.modules.lock().unwrap().compiling.is_none());
+    }
+
+    #[test]
+    fn profiling_can_be_shared_between_concurrent_compilations() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::BasicBlock));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let profiling = profiling.clone();
+                std::thread::spawn(move || {
+                    let start_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32| {};
+                    let take_measurement_fn =
+                        |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {};
+                    let wasm = wat2wasm(WAT).unwrap();
+                    Module::from_bytes(&wasm).instrument_with(
+                        profiling,
+                        FixtureEnv::new(),
+                        start_measurement_fn,
+                        take_measurement_fn,
+                    );
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(profiling.module_count(), 4);
+        assert_eq!(block_store.lock().unwrap().len(), 4);
+        assert!(profiling.modules.lock().unwrap().compiling.is_none());
+    }
+
+    /// Fails compiling every function containing `unreachable`.
+    #[derive(Debug, MemoryUsage)]
+    struct RejectUnreachable;
+
+    impl ModuleMiddleware for RejectUnreachable {
+        fn generate_function_middleware(
+            &self,
+            _local_function_index: LocalFunctionIndex,
+        ) -> Box<dyn FunctionMiddleware> {
+            Box::new(RejectUnreachable)
+        }
+    }
+
+    impl FunctionMiddleware for RejectUnreachable {
+        fn feed<'a>(
+            &mut self,
+            operator: Operator<'a>,
+            state: &mut wasmer::MiddlewareReaderState<'a>,
+        ) -> Result<(), wasmer::MiddlewareError> {
+            if let Operator::Unreachable = operator {
+                return Err(wasmer::MiddlewareError::new(
+                    "RejectUnreachable",
+                    "unreachable",
+                ));
+            }
+            state.push_operator(operator);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_compilations_do_not_block_other_modules() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store, Granularity::BasicBlock));
+
+        // The compiler stops at the first failing function, so the others never get
+        // their middleware.
+        let mut failing_wat = String::from("(module");
+        for _ in 0..1000 {
+            failing_wat.push_str(" (func unreachable)");
+        }
+        failing_wat.push(')');
+        let failing = prepare_module(&wat2wasm(failing_wat.as_bytes()).unwrap(), &profiling)
+            .unwrap()
+            .wasm;
+        let wasm = prepare_module(&wat2wasm(WAT).unwrap(), &profiling)
+            .unwrap()
+            .wasm;
+
+        let failing_thread = {
+            let profiling = profiling.clone();
+            std::thread::spawn(move || {
+                let _compilation = profiling.compilation_guard();
+                let middlewares: [Arc<dyn ModuleMiddleware>; 2] =
+                    [profiling.clone(), Arc::new(RejectUnreachable)];
+                cosmwasm_vm::internals::compile(&failing, None, &middlewares).is_err()
+            })
+        };
+        let (sender, receiver) = std::sync::mpsc::channel();
+        {
+            let profiling = profiling.clone();
+            std::thread::spawn(move || {
+                let _compilation = profiling.compilation_guard();
+                let middlewares: [Arc<dyn ModuleMiddleware>; 1] = [profiling.clone()];
+                let compiled = cosmwasm_vm::internals::compile(&wasm, None, &middlewares).is_ok();
+                sender.send(compiled).unwrap();
+            });
+        }
+
+        assert!(failing_thread.join().unwrap());
+        let compiled = receiver
+            .recv_timeout(std::time::Duration::from_secs(60))
+            .expect("the compilation waits for the failed one");
+        assert!(compiled);
+        assert_eq!(profiling.module_count(), 2);
+        assert!(profiling.modules.lock().unwrap().compiling.is_none());
+    }
+
+    #[test]
+    fn sharded_store_replaces_block_store() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let sharded_store = Arc::new(ShardedBlockStore::with_shards(2));
+        let profiling = Arc::new(
+            Profiling::new(block_store.clone(), Granularity::BasicBlock)
+                .with_sharded_store(sharded_store.clone()),
+        );
+
+        let start_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32| {};
+        let take_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {};
+
+        let wasm = wat2wasm(WAT).unwrap();
+        let _instance = Module::from_bytes(&wasm).instrument_with(
+            profiling,
+            FixtureEnv::new(),
+            start_measurement_fn,
+            take_measurement_fn,
+        );
+
+        assert!(block_store.lock().unwrap().is_empty());
+        assert_eq!(sharded_store.len(), 4);
+        let store = sharded_store.to_block_store();
+        assert_eq!(store.locations().count(), 4);
+        let expected_block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Sub,
+        ]);
+        let block = store.get_block(expected_block.get_hash());
+        assert_eq!(block, Some(&expected_block));
+    }
+
+    #[test]
+    fn function_granularity_registers_whole_functions() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::Function));
+
+        let start_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32| {};
+        let take_measurement_fn = |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {};
+
+        let wasm = wat2wasm(WAT).unwrap();
+        let _instance = Module::from_bytes(&wasm).instrument_with(
+            profiling.clone(),
+            FixtureEnv::new(),
+            start_measurement_fn,
+            take_measurement_fn,
+        );
+
+        // One block per local function
+        let block_store = block_store.lock().unwrap();
+        assert_eq!(block_store.len(), 3);
+
+        let expected_block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Mul,
+            OperatorSymbol::Call,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Sub,
+            OperatorSymbol::End,
+        ]);
+        // Re-encoding the module may reorder functions, so we don't know its index.
+        let found = block_store.locations().find_map(|(_, id)| {
+            block_store
+                .get_block(id)
+                .filter(|block| **block == expected_block)
+        });
+        // local.get: 2 bytes, i32.const 2: 2 bytes, i32.mul: 1 byte, call: 2 bytes,
+        // i32.const 1: 2 bytes, i32.sub: 1 byte, end: 1 byte
+        let found = found.unwrap();
+        assert_eq!(found.byte_len(), Some(11));
+        // The offsets of the first and the final end operator
+        let offsets = found.offsets().unwrap();
+        assert_eq!(offsets.last - offsets.first, 10);
+    }
+
+    #[test]
+    fn function_granularity_ids_are_stable() {
+        let wasm = wat2wasm(WAT).unwrap();
+        let locations = || {
+            let block_store = Arc::new(Mutex::new(BlockStore::new()));
+            let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::Function));
+            let _instance = Module::from_bytes(&wasm).instrument_with(
+                profiling,
+                FixtureEnv::new(),
+                |_env: &FixtureEnv, _fun: u32, _block: u32| {},
+                |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {},
+            );
+            let block_store = block_store.lock().unwrap();
+            let mut locations: Vec<_> = block_store.locations().collect();
+            locations.sort_unstable();
+            locations
+        };
+
+        // Compiling the same Wasm again gives a module with another ModuleInfo id
+        let first = locations();
+        assert_eq!(first.len(), 3);
+        assert_eq!(locations(), first);
+    }
+
+    #[test]
+    fn loop_counting_counts_iterations() {
+        const LOOP_WAT: &[u8] = br#"
+        (module
+        (func $count_down (export "count_down") (param $n i32)
+            (loop $continue
+                local.get $n
+                i32.const 1
+                i32.sub
+                local.tee $n
+                br_if $continue)))
+        "#;
+
+        #[derive(Debug, Clone, WasmerEnv)]
+        struct LoopEnv {
+            iterations: Arc<Mutex<Vec<(u32, u32)>>>,
+        }
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling =
+            Arc::new(Profiling::new(block_store, Granularity::BasicBlock).with_loop_counting());
+        let wasm = instrument_wasm(&wat2wasm(LOOP_WAT).unwrap(), &profiling).unwrap();
+        assert!(function_imports(&wasm)
+            .contains(&("profiling".to_string(), "count_loop_iteration".to_string())));
 
-            let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        use wasmer::CompilerConfig as _;
 
-            Self {
-                instance: module.instrument(
-                    block_store,
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling);
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        let env = LoopEnv {
+            iterations: Arc::new(Mutex::new(Vec::new())),
+        };
+        let imports = wasmer::imports! {
+            "profiling" => {
+                "start_measurement" => Function::new_native(&store, |_: u32, _: u32| {}),
+                "take_measurement" => Function::new_native(&store, |_: u32, _: u32, _: u64| {}),
+                "count_loop_iteration" => Function::new_native_with_env(
+                    &store,
+                    env.clone(),
+                    |env: &LoopEnv, fun: u32, index: u32| {
+                        env.iterations.lock().unwrap().push((fun, index));
+                    },
+                ),
+            }
+        };
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        instance
+            .exports
+            .get_function("count_down")
+            .unwrap()
+            .call(&[wasmer::Val::I32(3)])
+            .unwrap();
+
+        assert_eq!(*env.iterations.lock().unwrap(), [(0, 0), (0, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn buffered_recording_counts_blocks() {
+        const BUFFER_WAT: &[u8] = br#"
+        (module
+        (memory (export "memory") 1)
+        (func $add_one (param $p0 i32) (result i32)
+            local.get $p0
+            i32.const 1
+            i32.add)
+        (func $run (export "run") (param $n i32) (result i32)
+            (local $acc i32)
+            (loop $continue
+                local.get $acc
+                call $add_one
+                local.tee $acc
+                local.get $n
+                i32.lt_u
+                br_if $continue)
+            local.get $acc))
+        "#;
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(
+            Profiling::new(block_store.clone(), Granularity::BasicBlock).with_buffered_recording(2),
+        );
+        let wasm = instrument_wasm(&wat2wasm(BUFFER_WAT).unwrap(), &profiling).unwrap();
+        assert!(function_imports(&wasm)
+            .contains(&("profiling".to_string(), "flush_measurements".to_string())));
+        // Instrumenting twice does not add another buffer
+        let again = instrument_wasm(&wasm, &profiling).unwrap();
+        assert_eq!(again.len(), wasm.len());
+
+        use wasmer::CompilerConfig as _;
+
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling);
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        fn unexpected_start(_: u32, _: u32) {
+            panic!("start_measurement must not be called");
+        }
+        fn unexpected_take(_: u32, _: u32, _: u64) {
+            panic!("take_measurement must not be called");
+        }
+
+        let measurements = Arc::new(Mutex::new(Measurements::new()));
+        let env = BufferEnv {
+            measurements: measurements.clone(),
+            memory: wasmer::LazyInit::new(),
+        };
+        let imports = wasmer::imports! {
+            "profiling" => {
+                "start_measurement" => Function::new_native(&store, unexpected_start),
+                "take_measurement" => Function::new_native(&store, unexpected_take),
+                "flush_measurements" => Function::new_native_with_env(
+                    &store,
                     env,
-                    start_measurement_fn,
-                    take_measurement_fn,
+                    flush_measurements::<crate::clock::WallClock>,
                 ),
             }
+        };
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        let run = instance.exports.get_function("run").unwrap();
+
+        let add_one = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Add,
+        ])
+        .get_hash();
+        assert_eq!(
+            run.call(&[wasmer::Val::I32(5)]).unwrap()[0],
+            wasmer::Val::I32(5)
+        );
+        // The buffer is flushed when full and when the export returns.
+        assert_eq!(measurements.lock().unwrap().executions[&add_one], 5);
+        run.call(&[wasmer::Val::I32(3)]).unwrap();
+        assert_eq!(measurements.lock().unwrap().executions[&add_one], 8);
+        assert!(measurements.lock().unwrap().taken.is_empty());
+
+        // The buffer is allocated once, and the helper functions are not instrumented.
+        let memory = instance.exports.get_memory("memory").unwrap();
+        assert_eq!(memory.size(), wasmer::Pages(2));
+        assert_eq!(block_store.lock().unwrap().locations().count(), 4);
+    }
+
+    #[test]
+    fn buffered_recording_requires_memory() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        )
+        .with_buffered_recording(16);
+        let err = instrument_wasm(&wat2wasm(WAT).unwrap(), &profiling).unwrap_err();
+        assert!(matches!(err, InstrumentationError::MissingMemory));
+    }
+
+    #[test]
+    fn indirect_call_tracking_records_callees() {
+        const TABLE_WAT: &[u8] = br#"
+        (module
+        (type $unary (func (param i32) (result i32)))
+        (table 2 funcref)
+        (elem (i32.const 0) $double $square)
+        (func $double (type $unary)
+            local.get 0
+            i32.const 2
+            i32.mul)
+        (func $square (type $unary)
+            local.get 0
+            local.get 0
+            i32.mul)
+        (func $apply (export "apply") (param $x i32) (param $f i32) (result i32)
+            local.get $x
+            local.get $f
+            call_indirect (type $unary)))
+        "#;
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(
+            Profiling::new(block_store, Granularity::Function).with_indirect_call_tracking(),
+        );
+        let wasm = instrument_wasm(&wat2wasm(TABLE_WAT).unwrap(), &profiling).unwrap();
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+
+        use wasmer::CompilerConfig as _;
+
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling.clone());
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        let measurements = Arc::new(Mutex::new(Measurements::new().with_event_recording()));
+        let mut exports = Exports::new();
+        add_measuring_imports(&profiling, &store, measurements.clone(), &mut exports);
+        let mut imports = wasmer::ImportObject::new();
+        imports.register("profiling", exports);
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        let apply = instance.exports.get_function("apply").unwrap();
+
+        // The element index is passed through.
+        let args = [wasmer::Val::I32(3), wasmer::Val::I32(1)];
+        assert_eq!(apply.call(&args).unwrap()[0], wasmer::Val::I32(9));
+
+        let measurements = measurements.lock().unwrap();
+        assert_eq!(measurements.indirect_calls.len(), 1);
+        assert_eq!(measurements.indirect_calls[&(2, 0, 1)], 1);
+        assert_eq!(symbols.table_function(0, 1), Some(1));
+
+        let events = measurements.events.as_deref().unwrap();
+        let graph = crate::callgraph::CallGraph::from_events(events, Granularity::Function);
+        assert_eq!(graph.calls[&(2, 1)].count, 1);
+        assert_eq!(graph.calls[&(2, 1)].indirect, 1);
+    }
+
+    #[test]
+    fn call_depth_tracking_records_max_depths() {
+        const DEPTH_WAT: &[u8] = br#"
+        (module
+        (func $rec (param $n i32) (result i32)
+            local.get $n
+            i32.eqz
+            if
+                i32.const 0
+                return
+            end
+            local.get $n
+            i32.const 1
+            i32.sub
+            call $rec
+            i32.const 1
+            i32.add)
+        (func (export "run") (param i32) (result i32)
+            local.get 0
+            call $rec)
+        (func (export "leaf") (result i32)
+            i32.const 7))
+        "#;
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(
+            Profiling::new(block_store, Granularity::BasicBlock).with_call_depth_tracking(),
+        );
+        let wasm = instrument_wasm(&wat2wasm(DEPTH_WAT).unwrap(), &profiling).unwrap();
+        let imports = function_imports(&wasm);
+        for name in [ENTER_FUNCTION, ENTER_CALL, EXIT_CALL] {
+            assert!(imports.contains(&("profiling".to_string(), name.to_string())));
+        }
+
+        use wasmer::CompilerConfig as _;
+
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling.clone());
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        let measurements = Arc::new(Mutex::new(Measurements::new()));
+        let mut exports = Exports::new();
+        add_measuring_imports(&profiling, &store, measurements.clone(), &mut exports);
+        let mut imports = wasmer::ImportObject::new();
+        imports.register("profiling", exports);
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        let run = instance.exports.get_function("run").unwrap();
+        let leaf = instance.exports.get_function("leaf").unwrap();
+
+        for n in [1, 3, 2] {
+            let result = run.call(&[wasmer::Val::I32(n)]).unwrap();
+            assert_eq!(result[0], wasmer::Val::I32(n));
         }
+        leaf.call(&[]).unwrap();
+
+        // run calls rec(3), rec(2), rec(1) and rec(0)
+        let measurements = measurements.lock().unwrap();
+        let mut depths: Vec<_> = measurements.max_call_depths.iter().collect();
+        depths.sort_unstable();
+        assert_eq!(depths, [(&1, &5), (&2, &1)]);
     }
 
-    // #[test]
-    // fn instrumentation_does_not_mess_up_local_fns() {
-    //     let fixture = Fixture::new();
+    #[test]
+    fn branch_tracking_records_outcomes() {
+        const BRANCH_WAT: &[u8] = br#"
+        (module
+        (func $classify (export "classify") (param $x i32) (result i32)
+            (block $big
+                (block $two
+                    (block $one
+                        local.get $x
+                        br_table $one $two $big)
+                    i32.const 1
+                    return)
+                local.get $x
+                i32.const 2
+                i32.eq
+                br_if $big
+                i32.const 2
+                return)
+            i32.const 3))
+        "#;
 
-    //     let result = fixture.add_one().call(&[Value::I32(42)]).unwrap();
-    //     assert_eq!(result[0], Value::I32(43));
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(
+            Profiling::new(block_store.clone(), Granularity::BasicBlock).with_branch_tracking(),
+        );
+        let wasm = instrument_wasm(&wat2wasm(BRANCH_WAT).unwrap(), &profiling).unwrap();
+        assert!(function_imports(&wasm)
+            .contains(&("profiling".to_string(), "record_branch".to_string())));
 
-    //     let result = fixture.multisub().call(&[Value::I32(4)]).unwrap();
-    //     assert_eq!(result[0], Value::I32(6));
-    // }
+        use wasmer::CompilerConfig as _;
+
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling.clone());
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        let measurements = Arc::new(Mutex::new(Measurements::new()));
+        let mut exports = Exports::new();
+        add_measuring_imports(&profiling, &store, measurements.clone(), &mut exports);
+        let mut imports = wasmer::ImportObject::new();
+        imports.register("profiling", exports);
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        let classify = instance.exports.get_function("classify").unwrap();
+
+        // The condition and index are passed through.
+        for (x, expected) in [(0, 1), (1, 2), (2, 3), (7, 3), (0, 1)] {
+            let result = classify.call(&[wasmer::Val::I32(x)]).unwrap();
+            assert_eq!(result[0], wasmer::Val::I32(expected));
+        }
+
+        let measurements = measurements.lock().unwrap();
+        let mut branches: Vec<_> = measurements
+            .branches
+            .iter()
+            .map(|(location, stats)| (*location, stats.outcomes.clone()))
+            .collect();
+        branches.sort_unstable();
+        assert_eq!(branches, [((0, 0), vec![2, 1, 2]), ((0, 2), vec![1, 0])]);
+    }
 
     #[test]
-    fn instrumentation_registers_code_blocks() {
-        let fixture = Fixture::new();
+    fn memory_tracking_records_grows() {
+        const GROW_WAT: &[u8] = br#"
+        (module
+        (memory 1 3)
+        (func $grow (export "grow") (param $pages i32) (result i32)
+            local.get $pages
+            memory.grow))
+        "#;
 
-        let block_store = fixture.instance.profiling.block_store.lock().unwrap();
-        assert_eq!(block_store.len(), 4);
-        println!("{:?}", block_store);
+        /// The arguments of a `record_memory_grow` call
+        type Grow = (i32, u32, u32, u32);
 
-        // The body of $add_one.
-        let expected_block = CodeBlock::from(vec![
-            OperatorSymbol::LocalGet,
+        #[derive(Debug, Clone, WasmerEnv)]
+        struct GrowEnv {
+            grows: Arc<Mutex<Vec<Grow>>>,
+        }
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling =
+            Arc::new(Profiling::new(block_store, Granularity::BasicBlock).with_memory_tracking());
+        let wasm = instrument_wasm(&wat2wasm(GROW_WAT).unwrap(), &profiling).unwrap();
+        assert!(function_imports(&wasm)
+            .contains(&("profiling".to_string(), "record_memory_grow".to_string())));
+
+        use wasmer::CompilerConfig as _;
+
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling);
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        let env = GrowEnv {
+            grows: Arc::new(Mutex::new(Vec::new())),
+        };
+        let imports = wasmer::imports! {
+            "profiling" => {
+                "start_measurement" => Function::new_native(&store, |_: u32, _: u32| {}),
+                "take_measurement" => Function::new_native(&store, |_: u32, _: u32, _: u64| {}),
+                "record_memory_grow" => Function::new_native_with_env(
+                    &store,
+                    env.clone(),
+                    |env: &GrowEnv, previous: i32, fun: u32, block: u32, current: u32| {
+                        env.grows.lock().unwrap().push((previous, fun, block, current));
+                        previous
+                    },
+                ),
+            }
+        };
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        let grow = instance.exports.get_function("grow").unwrap();
+
+        // The result of memory.grow is passed through.
+        assert_eq!(
+            grow.call(&[wasmer::Val::I32(2)]).unwrap()[0],
+            wasmer::Val::I32(1)
+        );
+        assert_eq!(
+            grow.call(&[wasmer::Val::I32(1)]).unwrap()[0],
+            wasmer::Val::I32(-1)
+        );
+        assert_eq!(*env.grows.lock().unwrap(), [(1, 0, 0, 3), (-1, 0, 0, 3)]);
+    }
+
+    #[test]
+    fn host_call_timing_wraps_imported_calls() {
+        const HOST_WAT: &[u8] = br#"
+        (module
+        (import "env" "debug" (func $debug (param i32)))
+        (func $noop)
+        (func $log (export "log") (param $p0 i32)
+            call $noop
+            local.get $p0
+            call $debug))
+        "#;
+
+        #[derive(Debug, Clone, WasmerEnv)]
+        struct HostEnv {
+            calls: Arc<Mutex<Vec<(&'static str, u32)>>>,
+        }
+
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling =
+            Arc::new(Profiling::new(block_store, Granularity::Function).with_host_call_timing());
+        let wasm = instrument_wasm(&wat2wasm(HOST_WAT).unwrap(), &profiling).unwrap();
+
+        use wasmer::CompilerConfig as _;
+
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(profiling);
+        let store = wasmer::Store::new(&wasmer::Universal::new(compiler).engine());
+        let module = wasmer::Module::new(&store, &wasm).unwrap();
+
+        let env = HostEnv {
+            calls: Arc::new(Mutex::new(Vec::new())),
+        };
+        let start_host_call = |env: &HostEnv, _fun: u32, import: u32| {
+            env.calls.lock().unwrap().push(("start", import));
+        };
+        let end_host_call = |env: &HostEnv, _fun: u32, import: u32| {
+            env.calls.lock().unwrap().push(("end", import));
+        };
+        let imports = wasmer::imports! {
+            "env" => {
+                "debug" => Function::new_native_with_env(&store, env.clone(), |env: &HostEnv, _: i32| {
+                    env.calls.lock().unwrap().push(("debug", 0));
+                }),
+            },
+            "profiling" => {
+                "start_measurement" => Function::new_native(&store, |_: u32, _: u32| {}),
+                "take_measurement" => Function::new_native(&store, |_: u32, _: u32, _: u64| {}),
+                "start_host_call" => Function::new_native_with_env(&store, env.clone(), start_host_call),
+                "end_host_call" => Function::new_native_with_env(&store, env.clone(), end_host_call),
+            }
+        };
+        let instance = wasmer::Instance::new(&module, &imports).unwrap();
+        instance
+            .exports
+            .get_function("log")
+            .unwrap()
+            .call(&[wasmer::Val::I32(7)])
+            .unwrap();
+
+        // The call to the local $noop is not wrapped.
+        assert_eq!(
+            *env.calls.lock().unwrap(),
+            [("start", 0), ("debug", 0), ("end", 0)]
+        );
+    }
+
+    #[test]
+    fn with_buffered_recording_accepts_max_capacity() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Profiling::new(block_store, Granularity::BasicBlock)
+            .with_buffered_recording(MAX_BUFFER_CAPACITY);
+        assert_eq!(profiling.buffer_capacity, Some(MAX_BUFFER_CAPACITY));
+        // Addresses in the buffer fit in an i32
+        let size = MAX_BUFFER_CAPACITY as u64 * BUFFER_RECORD_SIZE as u64;
+        assert!(size <= i32::MAX as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "the capacity must not exceed")]
+    fn with_buffered_recording_panics_for_too_large_capacity() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let _ = Profiling::new(block_store, Granularity::BasicBlock)
+            .with_buffered_recording(MAX_BUFFER_CAPACITY + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "use instrument_with_imports")]
+    fn instrument_with_panics_for_memory_tracking() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling =
+            Arc::new(Profiling::new(block_store, Granularity::BasicBlock).with_memory_tracking());
+        let wasm = wat2wasm(WAT).unwrap();
+        Module::from_bytes(&wasm).instrument_with(
+            profiling,
+            FixtureEnv::new(),
+            |_env: &FixtureEnv, _fun: u32, _block: u32| {},
+            |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {},
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "use instrument_counting_loops")]
+    fn instrument_with_panics_for_loop_counting() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling =
+            Arc::new(Profiling::new(block_store, Granularity::BasicBlock).with_loop_counting());
+        let wasm = wat2wasm(WAT).unwrap();
+        Module::from_bytes(&wasm).instrument_with(
+            profiling,
+            FixtureEnv::new(),
+            |_env: &FixtureEnv, _fun: u32, _block: u32| {},
+            |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {},
+        );
+    }
+
+    fn sampled_blocks(sampling: Sampling) -> Vec<CodeBlock> {
+        instrumented_blocks(
+            Profiling::new(
+                Arc::new(Mutex::new(BlockStore::new())),
+                Granularity::BasicBlock,
+            )
+            .with_sampling(sampling),
+        )
+    }
+
+    /// Instruments `WAT` and returns which of its blocks were registered.
+    fn instrumented_blocks(profiling: Profiling) -> Vec<CodeBlock> {
+        let block_store = profiling.block_store.clone();
+        let profiling = Arc::new(profiling);
+
+        let wasm = wat2wasm(WAT).unwrap();
+        let _instance = Module::from_bytes(&wasm).instrument_with(
+            profiling,
+            FixtureEnv::new(),
+            |_env: &FixtureEnv, _fun: u32, _block: u32| {},
+            |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {},
+        );
+
+        let block_store = block_store.lock().unwrap();
+        vec![
+            vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Mul,
+            ],
+            vec![OperatorSymbol::I32Const, OperatorSymbol::I32Sub],
+            vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Add,
+            ],
+        ]
+        .into_iter()
+        .map(CodeBlock::from)
+        .filter(|block| block_store.get_block(block.get_hash()).is_some())
+        .collect()
+    }
+
+    #[test]
+    fn sampling_every_nth_block() {
+        let blocks = sampled_blocks(Sampling::default());
+        assert_eq!(blocks.len(), 3);
+
+        // The second block of $multisub is skipped
+        let blocks = sampled_blocks(Sampling {
+            every_nth: 2,
+            min_block_size: 0,
+        });
+        assert_eq!(blocks.len(), 2);
+        assert!(!blocks.contains(&CodeBlock::from(vec![
             OperatorSymbol::I32Const,
-            OperatorSymbol::I32Add,
-        ]);
-        let block = block_store.get_block(expected_block.get_hash());
-        assert_eq!(block, Some(&expected_block));
+            OperatorSymbol::I32Sub
+        ])));
+    }
 
-        // The body of $sub_one
-        let expected_block = CodeBlock::from(vec![
-            OperatorSymbol::LocalGet,
+    #[test]
+    fn sampling_by_block_size() {
+        let blocks = sampled_blocks(Sampling {
+            every_nth: 1,
+            min_block_size: 3,
+        });
+        assert_eq!(blocks.len(), 2);
+        assert!(!blocks.contains(&CodeBlock::from(vec![
             OperatorSymbol::I32Const,
-            OperatorSymbol::I32Sub,
-        ]);
-        let block = block_store.get_block(expected_block.get_hash());
-        assert_eq!(block, Some(&expected_block));
+            OperatorSymbol::I32Sub
+        ])));
 
-        // The body of $multisub until the `call` instruction.
-        let expected_block = CodeBlock::from(vec![
+        let blocks = sampled_blocks(Sampling {
+            every_nth: 1,
+            min_block_size: 4,
+        });
+        assert!(blocks.is_empty());
+    }
+
+    #[test]
+    fn filter_by_name() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        let blocks = instrumented_blocks(profiling.with_filter(FunctionFilter::Allow(vec![
+            FunctionSelector::name("add_one"),
+            FunctionSelector::name("does_not_exist"),
+        ])));
+        assert_eq!(
+            blocks,
+            [CodeBlock::from(vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Add,
+            ])]
+        );
+
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        let blocks = instrumented_blocks(profiling.with_filter(FunctionFilter::Deny(vec![
+            FunctionSelector::name("multisub"),
+        ])));
+        assert_eq!(
+            blocks,
+            [CodeBlock::from(vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Add,
+            ])]
+        );
+    }
+
+    #[test]
+    fn filter_by_index() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        let blocks = instrumented_blocks(profiling.with_filter(FunctionFilter::Deny(
+            (0..3).map(FunctionSelector::index).collect(),
+        )));
+        assert!(blocks.is_empty());
+
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        let blocks = instrumented_blocks(profiling.with_filter(FunctionFilter::Allow(
+            (0..3).map(FunctionSelector::index).collect(),
+        )));
+        assert_eq!(blocks.len(), 3);
+    }
+
+    #[test]
+    fn filter_by_reachability() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        let blocks = instrumented_blocks(
+            profiling.with_filter(FunctionFilter::ReachableFrom(vec!["add_one".to_string()])),
+        );
+        assert_eq!(
+            blocks,
+            [CodeBlock::from(vec![
+                OperatorSymbol::LocalGet,
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Add,
+            ])]
+        );
+
+        // $sub_one is called by $multisub
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Profiling::new(block_store.clone(), Granularity::BasicBlock)
+            .with_filter(FunctionFilter::ReachableFrom(vec!["multisub".to_string()]));
+        let blocks = instrumented_blocks(profiling);
+        assert_eq!(blocks.len(), 2);
+        assert!(!blocks.contains(&CodeBlock::from(vec![
             OperatorSymbol::LocalGet,
             OperatorSymbol::I32Const,
-            OperatorSymbol::I32Mul,
-        ]);
-        let block = block_store.get_block(expected_block.get_hash());
-        assert_eq!(block, Some(&expected_block));
+            OperatorSymbol::I32Add,
+        ])));
+        assert_eq!(block_store.lock().unwrap().len(), 3);
+    }
 
-        // The body of $multisub after the `call` instruction.
-        let expected_block =
-            CodeBlock::from(vec![OperatorSymbol::I32Const, OperatorSymbol::I32Sub]);
-        let block = block_store.get_block(expected_block.get_hash());
-        assert_eq!(block, Some(&expected_block));
+    #[test]
+    fn reachable_functions_works() {
+        let wasm = wat2wasm(
+            br#"(module
+            (import "env" "debug" (func $debug (param i32)))
+            (type $t0 (func))
+            (table 2 funcref)
+            (elem (i32.const 0) $indirect_a $indirect_b)
+            (func $execute (export "execute")
+                call $helper
+                i32.const 0
+                call_indirect (type $t0))
+            (func $helper
+                i32.const 1
+                call $debug)
+            (func $indirect_a nop)
+            (func $indirect_b nop)
+            (func $query (export "query")
+                call $unused)
+            (func $unused nop)
+            (func $instantiate (export "instantiate") nop))"#,
+        )
+        .unwrap();
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+        let names = |exports: &[&str]| {
+            let exports: Vec<String> = exports.iter().map(|name| name.to_string()).collect();
+            let mut names: Vec<String> = reachable_functions(&wasm, &exports)
+                .unwrap()
+                .into_iter()
+                .map(|fn_index| symbols.describe_function(fn_index))
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(
+            names(&["execute"]),
+            ["execute", "helper", "indirect_a", "indirect_b"]
+        );
+        assert_eq!(
+            names(&["query", "instantiate"]),
+            ["instantiate", "query", "unused"]
+        );
+        assert!(names(&[]).is_empty());
+
+        match reachable_functions(&wasm, &["migrate".to_string()]).unwrap_err() {
+            InstrumentationError::UnknownExport { name } => assert_eq!(name, "migrate"),
+            err => panic!("Unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn retained_immediates_split_blocks() {
+        const CONSTS_WAT: &[u8] = br#"
+        (module
+        (memory 1)
+        (func $small (export "small") (param $p0 i32) (result i32)
+            get_local $p0
+            i32.const 3
+            i32.add)
+        (func $large (export "large") (param $p0 i32) (result i32)
+            get_local $p0
+            i32.const 1000000
+            i32.add)
+        (func $load (export "load") (param $p0 i32) (result i32)
+            get_local $p0
+            i32.load align=1))
+        "#;
+        let blocks = |immediates: RetainedImmediates| {
+            let block_store = Arc::new(Mutex::new(BlockStore::new()));
+            let profiling = Profiling::new(block_store.clone(), Granularity::BasicBlock)
+                .with_immediates(immediates);
+            let wasm = wat2wasm(CONSTS_WAT).unwrap();
+            let _instance = Module::from_bytes(&wasm).instrument_with(
+                Arc::new(profiling),
+                FixtureEnv::new(),
+                |_env: &FixtureEnv, _fun: u32, _block: u32| {},
+                |_env: &FixtureEnv, _fun: u32, _block: u32, _hash: u64| {},
+            );
+            let block_store = block_store.lock().unwrap();
+            let mut immediates: Vec<Vec<Immediate>> = block_store
+                .locations()
+                .map(|(_, id)| block_store.get_block(id).unwrap().immediates().to_vec())
+                .collect();
+            immediates.sort();
+            (block_store.len(), immediates)
+        };
+
+        // Both constants are the same operator
+        assert_eq!(blocks(RetainedImmediates::default()).0, 2);
+
+        let (len, immediates) = blocks(RetainedImmediates {
+            alignment: false,
+            const_magnitude: true,
+        });
+        assert_eq!(len, 3);
+        assert_eq!(
+            immediates,
+            [
+                vec![Immediate::None, Immediate::None],
+                vec![Immediate::None, Immediate::Magnitude(2), Immediate::None],
+                vec![Immediate::None, Immediate::Magnitude(20), Immediate::None],
+            ]
+        );
+
+        let (_, immediates) = blocks(RetainedImmediates {
+            alignment: true,
+            const_magnitude: false,
+        });
+        assert!(immediates.contains(&vec![Immediate::None, Immediate::Alignment(0)]));
+    }
+
+    #[test]
+    fn function_sizes_works() {
+        let wasm = wat2wasm(WAT).unwrap();
+        let mut sizes: Vec<Vec<usize>> = function_sizes(&wasm)
+            .unwrap()
+            .into_iter()
+            .map(|sizes| sizes.block_operators)
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, [vec![3], vec![3], vec![3, 2]]);
+
+        let wasm = wat2wasm(
+            br#"(module
+              (func $f (param i32) (result i32)
+                i32.const 1000
+                local.get 0
+                local.get 0
+                br_if 0
+                drop
+                i32.const 1))"#,
+        )
+        .unwrap();
+        let sizes = function_sizes(&wasm).unwrap();
+        assert_eq!(
+            sizes,
+            [FunctionSizes {
+                block_operators: vec![3, 2],
+                // i32.const 1000: 3 bytes, local.get 0: 2 bytes each, drop: 1 byte,
+                // i32.const 1: 2 bytes
+                block_bytes: vec![7, 3],
+                // Both blocks, br_if 0: 2 bytes, end: 1 byte
+                bytes: 13,
+                // After the function count and the body size and local count of $f
+                block_offsets: vec![
+                    WasmOffsets { first: 3, last: 8 },
+                    WasmOffsets {
+                        first: 12,
+                        last: 13
+                    },
+                ],
+                offsets: Some(WasmOffsets { first: 3, last: 15 }),
+                // The hash of the operators above
+                block_id: sha256_block_id(&[
+                    0x41, 0xe8, 0x07, 0x20, 0x00, 0x20, 0x00, 0x0d, 0x00, 0x1a, 0x41, 0x01, 0x0b
+                ])
+                .as_u64(),
+            }]
+        );
+    }
+
+    #[test]
+    fn block_offsets_works() {
+        let wasm = wat2wasm(WAT).unwrap();
+        let offsets = block_offsets(&wasm, Granularity::BasicBlock).unwrap();
+        assert_eq!(offsets.len(), 4);
+        let mut sizes: Vec<_> = offsets.values().map(|(first, last)| last - first).collect();
+        sizes.sort_unstable();
+        // `local.get 0` and `i32.const 1` take two bytes each
+        assert_eq!(sizes, [2, 4, 4, 4]);
+        assert_eq!(offsets[&(1, 1)].0, offsets[&(1, 0)].1 + 3);
+
+        // Functions end with the `end` operator
+        let offsets = block_offsets(&wasm, Granularity::Function).unwrap();
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(offsets[&(0, 0)].1 - offsets[&(0, 0)].0, 5);
+    }
+
+    fn function_imports(wasm: &[u8]) -> Vec<(String, String)> {
+        let module = walrus::Module::from_buffer(wasm).unwrap();
+        module
+            .imports
+            .iter()
+            .map(|import| (import.module.clone(), import.name.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn instrument_wasm_injects_imports() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        let wasm = instrument_wasm(&wat2wasm(WAT).unwrap(), &profiling).unwrap();
+        assert_eq!(
+            function_imports(&wasm),
+            [
+                ("profiling".to_string(), "start_measurement".to_string()),
+                ("profiling".to_string(), "take_measurement".to_string()),
+            ]
+        );
+
+        // Instrumenting twice doesn't add the imports again.
+        let wasm = instrument_wasm(&wasm, &profiling).unwrap();
+        assert_eq!(function_imports(&wasm).len(), 2);
+    }
+
+    #[test]
+    fn instrument_wasm_uses_configured_import_module() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        )
+        .with_import_module("prof");
+        let wasm = instrument_wasm(&wat2wasm(WAT).unwrap(), &profiling).unwrap();
+        assert_eq!(
+            function_imports(&wasm),
+            [
+                ("prof".to_string(), "start_measurement".to_string()),
+                ("prof".to_string(), "take_measurement".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn instrument_wasm_errors_for_mismatching_imports() {
+        let wasm =
+            wat2wasm(br#"(module (import "profiling" "take_measurement" (func (param i32 i32))))"#)
+                .unwrap();
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        match instrument_wasm(&wasm, &profiling).unwrap_err() {
+            InstrumentationError::ImportMismatch { module, name } => {
+                assert_eq!(module, "profiling");
+                assert_eq!(name, "take_measurement");
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn instrument_wasm_errors_for_invalid_wasm() {
+        let profiling = Profiling::new(
+            Arc::new(Mutex::new(BlockStore::new())),
+            Granularity::BasicBlock,
+        );
+        match instrument_wasm(b"not wasm", &profiling).unwrap_err() {
+            InstrumentationError::ParseErr { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
     }
 
     // #[test]
@@ -0,0 +1,30 @@
+pub mod analysis;
+pub mod anomalies;
+pub mod bench;
+pub mod cache;
+pub mod calibration;
+pub mod callgraph;
+pub mod classification;
+pub mod clock;
+pub mod code_blocks;
+mod compat;
+pub mod cost_model;
+pub mod coverage;
+pub mod diff;
+pub mod disassembly;
+pub mod floats;
+pub mod gas_schedule;
+pub mod gas_time;
+pub mod golden;
+pub mod instrumentation;
+pub mod measure;
+pub mod metrics;
+pub mod microbench;
+pub mod middlewares;
+pub mod operators;
+pub mod report;
+pub mod session;
+pub mod symbols;
+pub mod vm;
+// mod profiling;
+mod utils;
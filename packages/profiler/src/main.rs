@@ -1,10 +1,4 @@
-mod code_blocks;
-mod instrumentation;
-mod measure;
-mod operators;
-// mod profiling;
-mod utils;
-
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use cosmwasm_std::{coins, Response};
@@ -13,46 +7,374 @@ use cosmwasm_vm::{
     Instance,
 };
 
-use crate::{
-    code_blocks::{BlockId, BlockStore},
-    instrumentation::Module,
-    measure::Measurements,
+use cosmwasm_profiler::{
+    anomalies::{detect_clock_anomalies, AnomalyThresholds},
+    cache::InstrumentationCache,
+    calibration::calibrate,
+    callgraph::CallGraph,
+    classification::{Classification, DominantCategoryClassifier},
+    clock::{self, Clock, WallClock},
+    code_blocks::{BlockStore, Sha256BlockHasher},
+    cost_model::CostModel,
+    coverage::CoverageReport,
+    diff::ReportDiff,
+    gas_schedule::{GasSchedule, GAS_PER_NANOSECOND},
+    gas_time::GasTimeReport,
+    instrumentation::{FunctionFilter, FunctionSelector, Granularity, Module, Profiling, Sampling},
+    measure::{EarlyTermination, Measurements, Metadata},
+    metrics::{MetricsSink, ProfileMetrics, PushGateway, Statsd, DEFAULT_BUCKETS},
+    operators::RetainedImmediates,
+    report::{
+        Aggregates, ChromeTraceExporter, CsvExporter, Exporter, GeckoProfileExporter, HtmlExporter,
+        JsonExporter, PprofExporter, Report, Thresholds,
+    },
+    symbols::SourceLines,
 };
 
-type Env = Arc<Mutex<Measurements>>;
 type MockInstance = Instance<MockApi, MockStorage, MockQuerier>;
 
+/// Usage: `cosmwasm-profiler [--clock wall|tsc|perf] [--granularity block|function] [--count-loops] [--track-memory] [--host-calls] [--indirect-calls] [--branches] [--call-depth] [--gas] [--classify] [--callgraph] [--coverage] [--dedup] [--cost-model] [--calibrate]
+///   [--save-report <path>] [--check-against <path>] [--diff-against <path>] [--chrome-trace <path>] [--pprof <path>] [--gecko-profile <path>] [--html <path>] [--lcov <path>] [--csv <path>] [--json <path>] [--push-gateway <host:port>] [--statsd <host:port>] [--save-blocks <path>] [--instrumentation-cache <dir>] [--gas-schedule <path>] [--sample-every <n>] [--min-block-size <n>]
+///   [--early-termination <relative error>] [--only <fn names>] [--exclude <fn names>] [--reachable-from <export names>] [--immediates <kinds>] [--block-hasher sip|sha256] [--metadata <key=value,...>]`
+///
+/// Function names are comma separated and looked up in the name section of the Wasm.
+/// `--reachable-from` only instruments the functions that can be called from the given exports.
+/// `--immediates alignment,magnitude` tells blocks apart by the alignment of memory accesses
+/// and the magnitude of constants, see `RetainedImmediates`.
+/// `--block-hasher sha256` computes block ids with SHA-256 instead of SipHash.
+/// The `--metadata` pairs are added to all CSV output and the report.
+///
+/// With `--count-loops`, the iteration counts of all loops are written to stderr.
+/// With `--track-memory`, the memory growth caused by every block is written to stderr.
+/// With `--host-calls`, the time spent in every imported host function is written to stderr
+/// and excluded from the exclusive cost of the calling functions.
+/// With `--indirect-calls`, the calls through tables are written to stderr with the called functions.
+/// With `--branches`, the outcomes of all branches are written to stderr and added to the saved
+/// report, see `BranchStats`.
+/// With `--call-depth`, the deepest nesting of Wasm calls in every entry point is written to stderr
+/// and added to the saved report.
+/// With `--gas`, the gas charged for every block is written to stderr next to its cost, the most
+/// underpriced blocks first, see `GasTimeReport`.
+/// With `--classify`, the cost of every class of blocks is written to stderr and added to the
+/// saved report, see `DominantCategoryClassifier`. This times host calls like `--host-calls`.
+/// With `--callgraph`, the inclusive and exclusive cost of all functions is written to stderr.
+/// With `--coverage`, the blocks of every function that were never executed are written to stderr.
+/// `--lcov <path>` stores the executions of the source lines of all blocks in the lcov format,
+/// using the DWARF debug info of the contract, see `CoverageReport::write_lcov`.
+/// With `--dedup`, the blocks instrumented at several locations are written to stderr, see `DedupStats`.
+/// With `--cost-model`, the cost per operator estimated from all measured blocks is written to stderr.
+/// With `--calibrate`, the cost the instrumentation adds to every measurement is measured before
+/// profiling and written to stderr, see `calibrate`.
+/// `--gas-schedule <path>` stores the estimated costs converted to gas, as the Rust source of a
+/// metering cost function if the path ends with `.rs` and as JSON otherwise. This needs `--clock wall`.
+/// `--save-report <path>` stores a per-function report as JSON. `--check-against <path>`
+/// compares the run to such a report and fails if any function regressed.
+/// `--diff-against <path>` compares the run to a report of another build of the contract,
+/// matching functions by name, writes all changed functions to stderr and fails on regressions.
+/// `--chrome-trace <path>` stores all measurements as a Chrome trace, see `ChromeTraceExporter`.
+/// `--pprof <path>` stores the cost of all call stacks as a pprof profile, see `PprofExporter`.
+/// `--gecko-profile <path>` stores all measurements for the Firefox Profiler, see `GeckoProfileExporter`.
+/// `--html <path>` stores a self-contained HTML page with tables, a treemap and operator
+/// histograms, see `HtmlExporter`.
+/// With `--early-termination 0.01`, no more timings of a block are kept once the mean cost of the
+/// block is known within ±1% with 95% confidence, see `EarlyTermination`. Later executions are
+/// only counted.
+/// `--csv <path>` stores the cost of every block as CSV, see `CsvExporter`.
+/// `--json <path>` stores the cost of every function and block as JSON, see `JsonExporter`.
+/// `--push-gateway <host:port>` pushes histograms of the block costs per function and other
+/// aggregates to a Prometheus push gateway, `--statsd <host:port>` sends them to a statsd server,
+/// see `ProfileMetrics`.
+/// `--save-blocks <path>` stores the instrumented code blocks as JSON, see `BlockStore::load`.
+/// `--instrumentation-cache <dir>` reuses the instrumented Wasm of earlier runs with the same
+/// options, see `InstrumentationCache`.
+///
+/// Blocks whose measurements show signs of clock issues are written to stderr and marked in
+/// the saved report, see `detect_clock_anomalies`.
 fn main() {
-    fn start_measurement(env: &Env, fn_index: u32, local_block_id: u32) {
-        env.lock()
-            .unwrap()
-            .start_measurement(fn_index, local_block_id);
+    let args: Vec<String> = std::env::args().collect();
+    let clock = arg_value(&args, "--clock").unwrap_or("wall");
+    let granularity = match arg_value(&args, "--granularity").unwrap_or("block") {
+        "block" => Granularity::BasicBlock,
+        "function" => Granularity::Function,
+        other => {
+            eprintln!("Unsupported granularity: {}", other);
+            std::process::exit(2);
+        }
+    };
+    let options = Options {
+        granularity,
+        count_loops: args.iter().any(|arg| arg == "--count-loops"),
+        track_memory: args.iter().any(|arg| arg == "--track-memory"),
+        host_calls: args.iter().any(|arg| arg == "--host-calls"),
+        indirect_calls: args.iter().any(|arg| arg == "--indirect-calls"),
+        branches: args.iter().any(|arg| arg == "--branches"),
+        call_depth: args.iter().any(|arg| arg == "--call-depth"),
+        gas: args.iter().any(|arg| arg == "--gas"),
+        classify: args.iter().any(|arg| arg == "--classify"),
+        callgraph: args.iter().any(|arg| arg == "--callgraph"),
+        coverage: args.iter().any(|arg| arg == "--coverage"),
+        dedup: args.iter().any(|arg| arg == "--dedup"),
+        cost_model: args.iter().any(|arg| arg == "--cost-model"),
+        calibrate: args.iter().any(|arg| arg == "--calibrate"),
+        save_report: arg_value(&args, "--save-report").map(PathBuf::from),
+        check_against: arg_value(&args, "--check-against").map(PathBuf::from),
+        diff_against: arg_value(&args, "--diff-against").map(PathBuf::from),
+        chrome_trace: arg_value(&args, "--chrome-trace").map(PathBuf::from),
+        pprof: arg_value(&args, "--pprof").map(PathBuf::from),
+        gecko_profile: arg_value(&args, "--gecko-profile").map(PathBuf::from),
+        html: arg_value(&args, "--html").map(PathBuf::from),
+        lcov: arg_value(&args, "--lcov").map(PathBuf::from),
+        csv: arg_value(&args, "--csv").map(PathBuf::from),
+        json: arg_value(&args, "--json").map(PathBuf::from),
+        push_gateway: arg_value(&args, "--push-gateway").map(String::from),
+        statsd: arg_value(&args, "--statsd").map(String::from),
+        save_blocks: arg_value(&args, "--save-blocks").map(PathBuf::from),
+        instrumentation_cache: arg_value(&args, "--instrumentation-cache").map(PathBuf::from),
+        gas_schedule: arg_value(&args, "--gas-schedule").map(PathBuf::from),
+        block_hasher: match arg_value(&args, "--block-hasher").unwrap_or("sip") {
+            "sip" => BlockHasherKind::Sip,
+            "sha256" => BlockHasherKind::Sha256,
+            other => {
+                eprintln!("Unsupported block hasher: {}", other);
+                std::process::exit(2);
+            }
+        },
+        immediates: arg_value(&args, "--immediates")
+            .map(immediates)
+            .unwrap_or_default(),
+        filter: match (
+            arg_value(&args, "--only"),
+            arg_value(&args, "--exclude"),
+            arg_value(&args, "--reachable-from"),
+        ) {
+            (Some(names), None, None) => FunctionFilter::Allow(selectors(names)),
+            (None, Some(names), None) => FunctionFilter::Deny(selectors(names)),
+            (None, None, Some(exports)) => {
+                FunctionFilter::ReachableFrom(exports.split(',').map(String::from).collect())
+            }
+            (None, None, None) => FunctionFilter::All,
+            _ => {
+                eprintln!("--only, --exclude and --reachable-from cannot be combined");
+                std::process::exit(2);
+            }
+        },
+        sampling: Sampling {
+            every_nth: number_arg(&args, "--sample-every").unwrap_or(1),
+            min_block_size: number_arg(&args, "--min-block-size").unwrap_or(0),
+        },
+        early_termination: number_arg(&args, "--early-termination").map(|relative_error| {
+            EarlyTermination {
+                relative_error,
+                ..EarlyTermination::default()
+            }
+        }),
+        metadata: arg_value(&args, "--metadata")
+            .map(metadata)
+            .unwrap_or_default(),
+    };
+
+    match clock {
+        "wall" => run(WallClock, options),
+        #[cfg(target_arch = "x86_64")]
+        "tsc" => {
+            if !clock::TscClock::is_invariant() {
+                eprintln!("Warning: this CPU does not report an invariant TSC");
+            }
+            run(clock::TscClock::new(), options)
+        }
+        #[cfg(all(target_os = "linux", feature = "perf-event"))]
+        "perf" => match clock::PerfCycleClock::new() {
+            Ok(clock) => run(clock, options),
+            Err(err) => {
+                eprintln!("Failed to open the cycle counter: {}", err);
+                std::process::exit(1);
+            }
+        },
+        other => {
+            eprintln!("Unsupported clock: {}", other);
+            std::process::exit(2);
+        }
     }
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|arg| arg == name)?;
+    Some(args.get(pos + 1).map(String::as_str).unwrap_or_else(|| {
+        eprintln!("Missing value for {}", name);
+        std::process::exit(2);
+    }))
+}
+
+#[derive(Clone, Copy)]
+enum BlockHasherKind {
+    Sip,
+    Sha256,
+}
 
-    fn take_measurement(
-        env: &Env,
-        fn_index: u32,
-        local_block_id: u32,
-        block_id: impl Into<BlockId>,
-    ) {
-        env.lock()
-            .unwrap()
-            .take_measurement(fn_index, local_block_id, block_id);
+struct Options {
+    granularity: Granularity,
+    count_loops: bool,
+    track_memory: bool,
+    host_calls: bool,
+    indirect_calls: bool,
+    branches: bool,
+    call_depth: bool,
+    gas: bool,
+    classify: bool,
+    callgraph: bool,
+    coverage: bool,
+    dedup: bool,
+    cost_model: bool,
+    calibrate: bool,
+    save_report: Option<PathBuf>,
+    check_against: Option<PathBuf>,
+    diff_against: Option<PathBuf>,
+    chrome_trace: Option<PathBuf>,
+    pprof: Option<PathBuf>,
+    gecko_profile: Option<PathBuf>,
+    html: Option<PathBuf>,
+    lcov: Option<PathBuf>,
+    csv: Option<PathBuf>,
+    json: Option<PathBuf>,
+    push_gateway: Option<String>,
+    statsd: Option<String>,
+    save_blocks: Option<PathBuf>,
+    instrumentation_cache: Option<PathBuf>,
+    gas_schedule: Option<PathBuf>,
+    immediates: RetainedImmediates,
+    block_hasher: BlockHasherKind,
+    filter: FunctionFilter,
+    sampling: Sampling,
+    early_termination: Option<EarlyTermination>,
+    metadata: Metadata,
+}
+
+impl Options {
+    fn needs_events(&self) -> bool {
+        self.callgraph
+            || self.classify
+            || self.save_report.is_some()
+            || self.check_against.is_some()
+            || self.diff_against.is_some()
+            || self.chrome_trace.is_some()
+            || self.pprof.is_some()
+            || self.gecko_profile.is_some()
+            || self.html.is_some()
+            || self.csv.is_some()
+            || self.json.is_some()
+            || self.push_gateway.is_some()
+            || self.statsd.is_some()
     }
+}
 
-    let measurements = Arc::new(Mutex::new(Measurements::new()));
-    let block_store = Arc::new(Mutex::new(BlockStore::new()));
+fn selectors(names: &str) -> Vec<FunctionSelector> {
+    names.split(',').map(FunctionSelector::name).collect()
+}
 
-    let mut instance = Module::from_path("testdata/hackatom.wasm").instrument(
-        block_store.clone(),
-        measurements.clone(),
-        start_measurement,
-        take_measurement,
-    );
+fn immediates(kinds: &str) -> RetainedImmediates {
+    let mut immediates = RetainedImmediates::default();
+    for kind in kinds.split(',') {
+        match kind {
+            "alignment" => immediates.alignment = true,
+            "magnitude" => immediates.const_magnitude = true,
+            other => {
+                eprintln!("Unsupported immediate: {}", other);
+                std::process::exit(2);
+            }
+        }
+    }
+    immediates
+}
+
+fn metadata(pairs: &str) -> Metadata {
+    pairs
+        .split(',')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => {
+                eprintln!("Invalid metadata, expected key=value: {}", pair);
+                std::process::exit(2);
+            }
+        })
+        .collect()
+}
+
+fn number_arg<T: std::str::FromStr>(args: &[String], name: &str) -> Option<T> {
+    arg_value(args, name).map(|value| {
+        value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid number for {}: {}", name, value);
+            std::process::exit(2);
+        })
+    })
+}
+
+fn run<C: Clock>(clock: C, options: Options) {
+    if options.calibrate {
+        let overhead = calibrate(clock.clone(), 10_000);
+        eprintln!(
+            "Instrumentation overhead: {} {} per measurement",
+            overhead.per_measurement,
+            C::UNIT
+        );
+    }
+    let mut measurements = Measurements::with_clock(clock);
+    measurements.metadata = options.metadata.clone();
+    if options.needs_events() {
+        measurements = measurements.with_event_recording();
+    }
+    if let Some(early_termination) = options.early_termination {
+        measurements = measurements.with_early_termination(early_termination);
+    }
+    let measurements = Arc::new(Mutex::new(measurements));
+    let end_invocation = || measurements.lock().unwrap().end_invocation();
+    let block_store = Arc::new(Mutex::new(match options.block_hasher {
+        BlockHasherKind::Sip => BlockStore::new(),
+        BlockHasherKind::Sha256 => BlockStore::new().with_hasher(Sha256BlockHasher),
+    }));
+
+    let mut profiling = Profiling::new(block_store.clone(), options.granularity)
+        .with_sampling(options.sampling)
+        .with_filter(options.filter)
+        .with_immediates(options.immediates);
+    if options.count_loops {
+        profiling = profiling.with_loop_counting();
+    }
+    if options.track_memory {
+        profiling = profiling.with_memory_tracking();
+    }
+    if options.host_calls || options.classify {
+        profiling = profiling.with_host_call_timing();
+    }
+    if options.indirect_calls {
+        profiling = profiling.with_indirect_call_tracking();
+    }
+    if options.branches {
+        profiling = profiling.with_branch_tracking();
+    }
+    if options.call_depth {
+        profiling = profiling.with_call_depth_tracking();
+    }
+    if options.gas {
+        profiling = profiling.with_gas_tracking();
+    }
+
+    let wasm_path = Path::new("testdata/hackatom.wasm");
+    let profiling = Arc::new(profiling);
+    let mut instance = match &options.instrumentation_cache {
+        Some(dir) => {
+            let cache = InstrumentationCache::new(dir).unwrap();
+            let wasm = cache
+                .instrument_wasm(&std::fs::read(wasm_path).unwrap(), &profiling)
+                .unwrap();
+            Module::Prepared(&wasm).instrument_measuring(profiling.clone(), measurements.clone())
+        }
+        None => Module::from_path(wasm_path)
+            .instrument_measuring(profiling.clone(), measurements.clone()),
+    };
 
     eprintln!("Warm-up round: 10 executions...");
     for _ in 1..10 {
-        call_things(instance.vm_instance());
+        call_things(instance.vm_instance(), &end_invocation);
     }
 
     {
@@ -63,15 +385,246 @@ fn main() {
     eprintln!("Profiling 10 executions...");
     // This could probably be multi-threaded.
     for _ in 1..10 {
-        call_things(instance.vm_instance());
+        call_things(instance.vm_instance(), &end_invocation);
     }
 
+    let symbols = instance.symbols();
     let measurements = measurements.lock().unwrap();
-    measurements.compile_csv(block_store, std::io::stdout());
+    measurements.compile_csv(block_store.clone(), symbols, std::io::stdout());
+    if let Some(path) = &options.save_blocks {
+        block_store.lock().unwrap().save(path).unwrap();
+    }
+    if options.count_loops {
+        measurements.compile_loop_csv(symbols, std::io::stderr());
+    }
+    if options.track_memory {
+        measurements.compile_memory_csv(symbols, std::io::stderr());
+    }
+    if options.host_calls {
+        measurements.compile_host_csv(symbols, std::io::stderr());
+    }
+    if options.indirect_calls {
+        measurements.compile_indirect_csv(symbols, std::io::stderr());
+    }
+    if options.branches {
+        measurements.compile_branch_csv(symbols, std::io::stderr());
+    }
+    if options.call_depth {
+        measurements.compile_call_depth_csv(symbols, std::io::stderr());
+    }
+    if options.gas {
+        GasTimeReport::new(&measurements).write_csv(
+            symbols,
+            &measurements.metadata,
+            std::io::stderr(),
+        );
+    }
+    if options.coverage {
+        let coverage =
+            CoverageReport::new(&block_store.lock().unwrap(), &measurements.executed_blocks);
+        let (blocks, executed) = coverage.totals();
+        eprintln!("Executed {} of {} instrumented blocks", executed, blocks);
+        coverage.write_csv(symbols, std::io::stderr());
+    }
+    if let Some(path) = &options.lcov {
+        let mut file = std::fs::File::create(path).unwrap();
+        let coverage =
+            CoverageReport::new(&block_store.lock().unwrap(), &measurements.executed_blocks);
+        let source_lines = SourceLines::from_wasm(&std::fs::read(wasm_path).unwrap()).unwrap();
+        if source_lines.is_empty() {
+            eprintln!("The Wasm has no DWARF line tables, the lcov report is empty");
+        }
+        coverage
+            .write_lcov(
+                &measurements.location_executions,
+                &profiling.block_offsets(),
+                &source_lines,
+                &mut file,
+            )
+            .unwrap();
+    }
+    if options.dedup {
+        let stats = block_store.lock().unwrap().dedup_stats();
+        eprintln!(
+            "{} locations share {} distinct blocks, {:.2} locations per block",
+            stats.sites(),
+            stats.distinct_blocks(),
+            stats.dedup_ratio()
+        );
+        stats.write_csv(std::io::stderr());
+    }
+    if options.cost_model || options.gas_schedule.is_some() {
+        match CostModel::fit(&measurements, &block_store.lock().unwrap()) {
+            Ok(model) => {
+                if options.cost_model {
+                    model.write_csv(C::UNIT, &measurements.metadata, std::io::stderr());
+                }
+                if let Some(path) = &options.gas_schedule {
+                    save_gas_schedule::<C>(&model, path);
+                }
+            }
+            Err(err) => eprintln!("Cannot fit a cost model: {}", err),
+        }
+    }
+    let anomalies = detect_clock_anomalies(&measurements, &AnomalyThresholds::default());
+    for (block_id, block_anomalies) in &anomalies {
+        let (fn_index, local_block_id) = measurements.block_locations[block_id];
+        for anomaly in block_anomalies {
+            let block = symbols.describe_block(fn_index, local_block_id);
+            eprintln!("Clock anomaly in {}: {}", block, anomaly);
+        }
+    }
+    let events = measurements.events.as_deref().unwrap_or_default();
+    if options.callgraph {
+        CallGraph::from_events(events, options.granularity).write_csv(
+            C::UNIT,
+            symbols,
+            &measurements.metadata,
+            std::io::stderr(),
+        );
+    }
+
+    if let Some(path) = &options.chrome_trace {
+        let mut file = std::fs::File::create(path).unwrap();
+        ChromeTraceExporter
+            .export(&Aggregates::new(&measurements, symbols), &mut file)
+            .unwrap();
+    }
+    if let Some(path) = &options.pprof {
+        let mut file = std::fs::File::create(path).unwrap();
+        PprofExporter
+            .export(&Aggregates::new(&measurements, symbols), &mut file)
+            .unwrap();
+    }
+    if let Some(path) = &options.gecko_profile {
+        let mut file = std::fs::File::create(path).unwrap();
+        GeckoProfileExporter
+            .export(&Aggregates::new(&measurements, symbols), &mut file)
+            .unwrap();
+    }
+    if let Some(path) = &options.html {
+        let mut file = std::fs::File::create(path).unwrap();
+        let block_store = block_store.lock().unwrap();
+        HtmlExporter::new(options.granularity)
+            .with_blocks(&block_store)
+            .export(&Aggregates::new(&measurements, symbols), &mut file)
+            .unwrap();
+    }
+    if let Some(path) = &options.csv {
+        let mut file = std::fs::File::create(path).unwrap();
+        CsvExporter
+            .export(&Aggregates::new(&measurements, symbols), &mut file)
+            .unwrap();
+    }
+    if let Some(path) = &options.json {
+        let mut file = std::fs::File::create(path).unwrap();
+        JsonExporter::new(options.granularity)
+            .export(&Aggregates::new(&measurements, symbols), &mut file)
+            .unwrap();
+    }
+    if options.push_gateway.is_some() || options.statsd.is_some() {
+        let metrics = ProfileMetrics::new(
+            &measurements,
+            symbols,
+            options.granularity,
+            &DEFAULT_BUCKETS,
+        );
+        if let Some(address) = &options.push_gateway {
+            push_metrics(
+                &mut PushGateway::new(address, "cosmwasm-profiler"),
+                &metrics,
+            );
+        }
+        if let Some(address) = &options.statsd {
+            match Statsd::new(address.as_str()) {
+                Ok(mut statsd) => push_metrics(&mut statsd, &metrics),
+                Err(err) => eprintln!("Cannot connect to statsd at {}: {}", address, err),
+            }
+        }
+    }
+
+    let mut report = Report::from_events(C::UNIT, events, options.granularity)
+        .with_symbols(symbols)
+        .with_blocks(&measurements)
+        .with_offsets(&block_store.lock().unwrap())
+        .with_clock_anomalies(anomalies)
+        .with_metadata(&measurements.metadata);
+    if options.branches {
+        report = report.with_branches(&measurements, &block_store.lock().unwrap());
+    }
+    if options.call_depth {
+        report = report.with_call_depths(&measurements);
+    }
+    if options.classify {
+        let classification = Classification::new(
+            &measurements,
+            &block_store.lock().unwrap(),
+            options.granularity,
+            &DominantCategoryClassifier,
+        );
+        report = report.with_classes(&classification);
+        for (class, class_report) in &report.classes {
+            eprintln!(
+                "{}: {} blocks, {} executions, {} {}",
+                class,
+                class_report.blocks,
+                class_report.executions,
+                class_report.cost,
+                C::UNIT
+            );
+        }
+    }
+    if let Some(path) = &options.save_report {
+        std::fs::write(path, serde_json::to_vec_pretty(&report).unwrap()).unwrap();
+    }
+    if let Some(path) = &options.check_against {
+        let baseline: Report = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        if let Err(violations) = report.check_against(&baseline, &Thresholds::default()) {
+            for violation in violations {
+                let function = symbols.describe_function(violation.fn_index);
+                eprintln!("Regression in {}: {}", function, violation);
+            }
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &options.diff_against {
+        let old: Report = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        let diff = ReportDiff::new(&old, &report, &Thresholds::default());
+        eprintln!(
+            "Blocks: {} in both builds ({} executed a different number of times), {} added, {} removed",
+            diff.blocks.common, diff.blocks.execution_changes, diff.blocks.added, diff.blocks.removed
+        );
+        diff.write_csv(&measurements.metadata, std::io::stderr());
+        if diff.regressions().next().is_some() {
+            std::process::exit(1);
+        }
+    }
+}
+
+fn push_metrics(sink: &mut dyn MetricsSink, metrics: &ProfileMetrics) {
+    if let Err(err) = sink.push(metrics) {
+        eprintln!("Cannot push metrics: {}", err);
+    }
+}
+
+fn save_gas_schedule<C: Clock>(model: &CostModel, path: &Path) {
+    if C::UNIT != WallClock::UNIT {
+        eprintln!("A gas schedule can only be created from wall clock measurements");
+        std::process::exit(2);
+    }
+    let schedule = GasSchedule::from_cost_model(model, GAS_PER_NANOSECOND);
+    if path.extension().and_then(|extension| extension.to_str()) == Some("rs") {
+        schedule
+            .write_rust(std::fs::File::create(path).unwrap())
+            .unwrap();
+    } else {
+        std::fs::write(path, serde_json::to_vec_pretty(&schedule).unwrap()).unwrap();
+    }
 }
 
 // Pretty much stolen from `/contracts/hackatom/tests/integration.rs`
-fn call_things(deps: &mut MockInstance) {
+/// `end_invocation` is called after every call into the contract.
+fn call_things(deps: &mut MockInstance, end_invocation: &dyn Fn()) {
     use hackatom::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
 
     let verifier = String::from("verifies");
@@ -83,14 +636,17 @@ fn call_things(deps: &mut MockInstance) {
     };
     let info = mock_info(&creator, &coins(1000, "earth"));
     let res: Response = instantiate(deps, mock_env(), info, msg).unwrap();
+    end_invocation();
     assert_eq!(0, res.messages.len());
 
     // now let's query
     let query_response = query(deps, mock_env(), QueryMsg::Verifier {}).unwrap();
+    end_invocation();
     assert_eq!(query_response.as_slice(), b"{\"verifier\":\"verifies\"}");
 
     // bad query returns parse error (pass wrong type - this connection is not enforced)
     let qres = query(deps, mock_env(), ExecuteMsg::Release {});
+    end_invocation();
     let msg = qres.unwrap_err();
     assert!(msg.contains("Error parsing"));
 }
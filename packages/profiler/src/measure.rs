@@ -1,26 +1,338 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
 use std::sync::{Arc, Mutex};
-use std::time::{self, Duration};
 
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, WallClock};
 use crate::code_blocks::{BlockId, BlockStore};
-use crate::utils::InsertPush as _;
+use crate::symbols::Symbols;
+use crate::utils::{with_metadata_keys, with_metadata_values, InsertPush as _};
+
+/// Key-value pairs describing a profiling session, e.g. the contract address or
+/// the message type, see [`Measurements::with_metadata`].
+pub type Metadata = BTreeMap<String, String>;
 
-#[derive(wasmer::WasmerEnv, Default, Debug, Clone)]
-pub struct Measurements {
-    started: HashMap<(u32, u32), VecDeque<time::Instant>>,
-    pub taken: HashMap<BlockId, VecDeque<time::Duration>>,
+/// A measurement as it happened, see [`Measurements::with_event_recording`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasurementEvent {
+    Start {
+        fn_index: u32,
+    },
+    Take {
+        fn_index: u32,
+        block_id: BlockId,
+        /// The cost in the unit of the clock
+        cost: u128,
+    },
+    /// A call from a function to an imported host function, see
+    /// [`Measurements::end_host_call`].
+    HostCall {
+        fn_index: u32,
+        import_index: u32,
+        /// The cost in the unit of the clock
+        cost: u128,
+    },
+    /// A call through a table, right before the callee starts, see
+    /// [`Measurements::record_indirect_call`].
+    IndirectCall {
+        fn_index: u32,
+        table_index: u32,
+        element_index: u32,
+    },
+    /// Marks the end of a call into the contract, see [`Measurements::end_invocation`].
+    InvocationEnd,
+}
+
+/// How much the memory grew in one block, see [`Measurements::record_memory_grow`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryGrowth {
+    /// The number of executed `memory.grow` instructions, including failed ones
+    pub grows: u64,
+    /// The number of `memory.grow` instructions that failed
+    pub failed: u64,
+    /// The number of Wasm pages (64 KiB each) the memory grew by
+    pub pages: u64,
+}
+
+/// How often the branch ending a block took each of its targets, see
+/// [`Measurements::record_branch`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchStats {
+    /// The number of executions per outcome. For `br_if`, outcome 0 is falling through
+    /// and outcome 1 is taking the branch. For `br_table`, outcomes are the indexes of
+    /// the targets with the default target last.
+    pub outcomes: Vec<u64>,
+}
+
+impl BranchStats {
+    pub fn executions(&self) -> u64 {
+        self.outcomes.iter().sum()
+    }
+
+    /// The share of executions taking the most common outcome, from 0 to 1. Branches
+    /// with a bias close to 1 almost always go the same way.
+    pub fn bias(&self) -> f64 {
+        match self.executions() {
+            0 => 0.0,
+            executions => *self.outcomes.iter().max().unwrap() as f64 / executions as f64,
+        }
+    }
+
+    /// Adds the executions of `other` per outcome.
+    pub fn merge(&mut self, other: &BranchStats) {
+        if self.outcomes.len() < other.outcomes.len() {
+            self.outcomes.resize(other.outcomes.len(), 0);
+        }
+        for (count, other) in self.outcomes.iter_mut().zip(&other.outcomes) {
+            *count += other;
+        }
+    }
+}
+
+/// When to stop keeping the timings of a block, see
+/// [`Measurements::with_early_termination`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EarlyTermination {
+    /// The number of timings kept for every block at least. Never less than 2.
+    pub min_samples: u64,
+    /// The half width of the 95% confidence interval of the mean cost of a block,
+    /// relative to the mean, below which no more timings of the block are kept
+    pub relative_error: f64,
+}
+
+impl Default for EarlyTermination {
+    /// The mean of every block is known within ±1% after at least 30 timings.
+    fn default() -> Self {
+        EarlyTermination {
+            min_samples: 30,
+            relative_error: 0.01,
+        }
+    }
+}
+
+impl EarlyTermination {
+    /// Whether `stats` are precise enough to stop keeping timings.
+    fn is_reached(&self, stats: &RunningStats) -> bool {
+        if stats.count < self.min_samples.max(2) || stats.mean <= 0.0 {
+            return false;
+        }
+        let variance = stats.m2 / (stats.count - 1) as f64;
+        let half_width = 1.96 * (variance / stats.count as f64).sqrt();
+        half_width <= self.relative_error * stats.mean
+    }
+}
+
+/// The mean and the sum of squared deviations of the timings of a block so far,
+/// updated with Welford's algorithm.
+#[derive(Debug, Default, Clone, Copy)]
+struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+}
+
+/// The location of a started block, the clock reading at its start, whether the clock
+/// went backwards before it started and the remaining gas, which is only known with gas
+/// tracking.
+type StartedMeasurement<R> = ((u32, u32), R, bool, Option<u64>);
+
+#[derive(Debug, Clone)]
+pub struct Measurements<C: Clock = WallClock> {
+    clock: C,
+    /// The measurements that were started but not taken yet with their function index
+    /// and local block id, innermost last.
+    started: Vec<StartedMeasurement<C::Reading>>,
+    /// The most recent reading at the start of a measurement
+    last_reading: Option<C::Reading>,
+    pub taken: HashMap<BlockId, VecDeque<C::Elapsed>>,
+    /// The number of executions of every block that were not added to `taken` since
+    /// its timings were precise enough, see [`Measurements::with_early_termination`].
+    pub untimed: HashMap<BlockId, u64>,
+    early_termination: Option<EarlyTermination>,
+    /// The statistics of the timings of every block in `taken` that is not in
+    /// `untimed` yet. Only kept with early termination.
+    sample_stats: HashMap<BlockId, RunningStats>,
+    /// The function index and local block id a block was first measured at. Blocks
+    /// with the same code share a `BlockId`, even across functions.
+    pub block_locations: HashMap<BlockId, (u32, u32)>,
+    /// The function index and local block id of every block that was executed.
+    pub executed_blocks: HashSet<(u32, u32)>,
+    /// The number of executions of every block, keyed by function index and local block id.
+    pub location_executions: HashMap<(u32, u32), u64>,
+    /// The number of executions of every block recorded by [`Measurements::flush_buffer`].
+    /// Blocks are not timed in buffered recording.
+    pub executions: HashMap<BlockId, u64>,
+    /// The number of executions of every block that started at an earlier clock reading
+    /// than the measurement started before, see
+    /// [`detect_clock_anomalies`](crate::anomalies::detect_clock_anomalies).
+    pub clock_regressions: HashMap<BlockId, u64>,
+    /// The number of iterations of every loop, keyed by function index and the
+    /// index of the loop within the function.
+    pub loop_iterations: HashMap<(u32, u32), u64>,
+    /// The memory growth caused by every block, keyed by function index and local block id.
+    pub memory_growth: HashMap<(u32, u32), MemoryGrowth>,
+    /// The gas charged by the metering middleware during all executions of every block,
+    /// recorded by [`Measurements::take_measurement_with_gas`]. The metering middleware
+    /// also charges for the operators that take the measurement, so this includes the
+    /// cost of four operators per execution.
+    pub gas: HashMap<BlockId, u64>,
+    host_started: Vec<(u32, C::Reading)>,
+    /// The timings of all calls to imported host functions, keyed by import index.
+    pub host_calls: HashMap<u32, VecDeque<C::Elapsed>>,
+    /// The number of calls through tables, keyed by the function index of the caller,
+    /// the table index and the index of the called element in the table.
+    pub indirect_calls: HashMap<(u32, u32, u32), u64>,
+    /// The outcomes of the branch ending every block, keyed by function index and
+    /// local block id.
+    pub branches: HashMap<(u32, u32), BranchStats>,
+    /// The deepest nesting of Wasm calls in every entry point, keyed by the function
+    /// index of the entry point, see [`Measurements::enter_function`]. The entry point
+    /// itself has depth 1.
+    pub max_call_depths: HashMap<u32, u32>,
+    /// The entry point of the current invocation and the number of calls it made
+    /// that did not return yet.
+    call_stack: Option<(u32, u32)>,
+    /// All measurements in the order they happened. Only recorded if enabled,
+    /// since this grows with the number of executed blocks.
+    pub events: Option<Vec<MeasurementEvent>>,
+    /// Added to all exports of this collector: as extra columns of every CSV row
+    /// and as the `metadata` of reports.
+    pub metadata: Metadata,
+}
+
+impl<C: Clock> wasmer::WasmerEnv for Measurements<C> {}
+
+impl Default for Measurements {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Measurements {
     pub fn new() -> Self {
-        Self::default()
+        Self::with_clock(WallClock)
+    }
+}
+
+impl<C: Clock> Measurements<C> {
+    /// Creates an empty collector that times blocks using the given clock.
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            clock,
+            started: Vec::new(),
+            last_reading: None,
+            taken: HashMap::new(),
+            untimed: HashMap::new(),
+            early_termination: None,
+            sample_stats: HashMap::new(),
+            block_locations: HashMap::new(),
+            executed_blocks: HashSet::new(),
+            location_executions: HashMap::new(),
+            executions: HashMap::new(),
+            clock_regressions: HashMap::new(),
+            loop_iterations: HashMap::new(),
+            memory_growth: HashMap::new(),
+            gas: HashMap::new(),
+            host_started: Vec::new(),
+            host_calls: HashMap::new(),
+            indirect_calls: HashMap::new(),
+            branches: HashMap::new(),
+            max_call_depths: HashMap::new(),
+            call_stack: None,
+            events: None,
+            metadata: Metadata::new(),
+        }
+    }
+
+    /// Makes the collector keep an ordered log of all measurements, which is
+    /// needed to reconstruct a [`CallGraph`](crate::callgraph::CallGraph).
+    pub fn with_event_recording(mut self) -> Self {
+        self.events = Some(Vec::new());
+        self
+    }
+
+    /// Stops adding the timings of a block to `taken` once the confidence interval of
+    /// its mean cost is tight enough, counting its executions in `untimed` instead. This
+    /// bounds the memory of long runs like soak tests, while the timings kept are still
+    /// enough to estimate the mean. Blocks are still timed, and recorded events still
+    /// contain every execution.
+    pub fn with_early_termination(mut self, early_termination: EarlyTermination) -> Self {
+        self.early_termination = Some(early_termination);
+        self
+    }
+
+    /// The number of executions of a block, including the ones in `untimed`.
+    pub fn executions_of(&self, block_id: BlockId) -> u64 {
+        let timed = self.taken.get(&block_id).map_or(0, |t| t.len() as u64);
+        timed + self.untimed.get(&block_id).copied().unwrap_or_default()
+    }
+
+    /// Annotates the session, e.g. with `("contract", "wasm1abc...")` or `("msg", "transfer")`.
+    /// The metadata is kept by `clear`.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Marks the end of an execution of an entry point. Calls to the contract
+    /// cannot be told apart from calls between its functions otherwise.
+    ///
+    /// Measurements that are still started at this point were unwound by a trap
+    /// and are discarded.
+    pub fn end_invocation(&mut self) {
+        self.started.clear();
+        self.host_started.clear();
+        self.call_stack = None;
+        if let Some(events) = &mut self.events {
+            events.push(MeasurementEvent::InvocationEnd);
+        }
     }
 
     pub fn start_measurement(&mut self, fn_index: u32, local_block_id: u32) {
+        self.start(fn_index, local_block_id, None);
+    }
+
+    /// Like [`Measurements::start_measurement`], remembering the gas that remains
+    /// when the block starts.
+    pub fn start_measurement_with_gas(
+        &mut self,
+        fn_index: u32,
+        local_block_id: u32,
+        remaining_gas: u64,
+    ) {
+        self.start(fn_index, local_block_id, Some(remaining_gas));
+    }
+
+    fn start(&mut self, fn_index: u32, local_block_id: u32, remaining_gas: Option<u64>) {
+        if let Some(events) = &mut self.events {
+            events.push(MeasurementEvent::Start { fn_index });
+        }
+        self.executed_blocks.insert((fn_index, local_block_id));
+        *self
+            .location_executions
+            .entry((fn_index, local_block_id))
+            .or_default() += 1;
+        let now = self.clock.now();
+        let regressed = matches!(self.last_reading, Some(last) if now < last);
+        self.last_reading = Some(now);
         self.started
-            .insert_push((fn_index, local_block_id), time::Instant::now());
+            .push(((fn_index, local_block_id), now, regressed, remaining_gas));
     }
 
+    /// Finalizes the innermost started measurement of the block at `fn_index` and
+    /// `local_block_id`. Pairing starts and takes like a call stack attributes nested
+    /// executions of the same block correctly, e.g. of a recursive function in
+    /// function granularity.
     // TODO: Error handling? This will be called from Wasm code probably.
     pub fn take_measurement(
         &mut self,
@@ -28,18 +340,430 @@ impl Measurements {
         local_block_id: u32,
         block_id: impl Into<BlockId>,
     ) {
-        match self.started.get_mut(&(fn_index, local_block_id)) {
-            Some(q) => {
-                let start = q
-                    .pop_front()
-                    .expect("trying to finalize a measurement that was never started");
-                self.taken.insert_push(block_id.into(), start.elapsed());
+        self.take(fn_index, local_block_id, block_id.into(), None);
+    }
+
+    /// Like [`Measurements::take_measurement`], adding the gas charged since the block
+    /// started to [`Measurements::gas`] if it was started with
+    /// [`Measurements::start_measurement_with_gas`].
+    ///
+    /// In basic block granularity, the gas charged by host functions is not part of any
+    /// block, since calls end blocks. In function granularity, it is part of the caller.
+    pub fn take_measurement_with_gas(
+        &mut self,
+        fn_index: u32,
+        local_block_id: u32,
+        block_id: impl Into<BlockId>,
+        remaining_gas: u64,
+    ) {
+        self.take(
+            fn_index,
+            local_block_id,
+            block_id.into(),
+            Some(remaining_gas),
+        );
+    }
+
+    fn take(
+        &mut self,
+        fn_index: u32,
+        local_block_id: u32,
+        block_id: BlockId,
+        remaining_gas: Option<u64>,
+    ) {
+        let location = (fn_index, local_block_id);
+        match self
+            .started
+            .iter()
+            .rposition(|(started, _, _, _)| *started == location)
+        {
+            Some(index) => {
+                let (_, start, regressed, started_gas) = self.started.remove(index);
+                let elapsed = self.clock.elapsed(start);
+                if let Some(events) = &mut self.events {
+                    events.push(MeasurementEvent::Take {
+                        fn_index,
+                        block_id,
+                        cost: C::to_units(elapsed),
+                    });
+                }
+                self.block_locations
+                    .entry(block_id)
+                    .or_insert((fn_index, local_block_id));
+                self.add_timing(block_id, elapsed);
+                if regressed {
+                    *self.clock_regressions.entry(block_id).or_default() += 1;
+                }
+                if let (Some(started_gas), Some(remaining_gas)) = (started_gas, remaining_gas) {
+                    *self.gas.entry(block_id).or_default() +=
+                        started_gas.saturating_sub(remaining_gas);
+                }
             }
             None => panic!("trying to finalize a measurement that was never started"),
         }
     }
 
-    pub fn compile_csv(&self, block_store: Arc<Mutex<BlockStore>>, sink: impl std::io::Write) {
+    fn add_timing(&mut self, block_id: BlockId, elapsed: C::Elapsed) {
+        if let Some(untimed) = self.untimed.get_mut(&block_id) {
+            *untimed += 1;
+            return;
+        }
+        self.taken.insert_push(block_id, elapsed);
+        if let Some(early_termination) = &self.early_termination {
+            let stats = self.sample_stats.entry(block_id).or_default();
+            stats.add(C::to_units(elapsed) as f64);
+            if early_termination.is_reached(stats) {
+                self.sample_stats.remove(&block_id);
+                self.untimed.insert(block_id, 0);
+            }
+        }
+    }
+
+    /// Counts the block executions written by
+    /// [`Profiling::with_buffered_recording`](crate::instrumentation::Profiling::with_buffered_recording),
+    /// one record of [`BUFFER_RECORD_SIZE`](crate::instrumentation::BUFFER_RECORD_SIZE)
+    /// bytes per execution. A trailing partial record is ignored.
+    pub fn flush_buffer(&mut self, records: &[u8]) {
+        for record in records.chunks_exact(crate::instrumentation::BUFFER_RECORD_SIZE) {
+            let fn_index = u32::from_le_bytes(record[0..4].try_into().unwrap());
+            let local_block_id = u32::from_le_bytes(record[4..8].try_into().unwrap());
+            let block_id = BlockId::from(u64::from_le_bytes(record[8..16].try_into().unwrap()));
+            self.executed_blocks.insert((fn_index, local_block_id));
+            *self
+                .location_executions
+                .entry((fn_index, local_block_id))
+                .or_default() += 1;
+            self.block_locations
+                .entry(block_id)
+                .or_insert((fn_index, local_block_id));
+            *self.executions.entry(block_id).or_default() += 1;
+        }
+    }
+
+    pub fn count_loop_iteration(&mut self, fn_index: u32, loop_index: u32) {
+        *self
+            .loop_iterations
+            .entry((fn_index, loop_index))
+            .or_default() += 1;
+    }
+
+    /// Writes the iteration counts of all loops that were executed, sorted by
+    /// function and loop index.
+    pub fn compile_loop_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            ["function", "loop", "iterations"],
+            &self.metadata,
+        ))
+        .unwrap();
+
+        let mut loops: Vec<_> = self.loop_iterations.iter().collect();
+        loops.sort_unstable();
+        for ((fn_index, loop_index), iterations) in loops {
+            wtr.write_record(with_metadata_values(
+                [
+                    symbols.describe_function(*fn_index),
+                    loop_index.to_string(),
+                    iterations.to_string(),
+                ],
+                &self.metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    pub fn start_host_call(&mut self, _fn_index: u32, import_index: u32) {
+        self.host_started.push((import_index, self.clock.now()));
+    }
+
+    /// Finalizes the timing of a call to an import. Host calls are not part of any
+    /// block, so their time is not included in the timings of blocks.
+    pub fn end_host_call(&mut self, fn_index: u32, import_index: u32) {
+        let (started_import, start) = self
+            .host_started
+            .pop()
+            .expect("trying to finalize a host call that was never started");
+        assert_eq!(
+            started_import, import_index,
+            "host calls finished in the wrong order"
+        );
+        let elapsed = self.clock.elapsed(start);
+        if let Some(events) = &mut self.events {
+            events.push(MeasurementEvent::HostCall {
+                fn_index,
+                import_index,
+                cost: C::to_units(elapsed),
+            });
+        }
+        self.host_calls.insert_push(import_index, elapsed);
+    }
+
+    /// Writes the timings of all host functions that were called, sorted by import index.
+    pub fn compile_host_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            [
+                "import".to_string(),
+                "calls".to_string(),
+                format!("total in {}", C::UNIT),
+                format!("avg in {}", C::UNIT),
+                format!("min in {}", C::UNIT),
+                format!("max in {}", C::UNIT),
+            ],
+            &self.metadata,
+        ))
+        .unwrap();
+
+        let mut imports: Vec<_> = self.host_calls.iter().collect();
+        imports.sort_unstable_by_key(|(import_index, _)| **import_index);
+        for (import_index, timings) in imports {
+            let total = timings.iter().map(|t| C::to_units(*t)).sum::<u128>();
+            wtr.write_record(with_metadata_values(
+                [
+                    symbols.describe_import(*import_index),
+                    timings.len().to_string(),
+                    total.to_string(),
+                    (total / timings.len() as u128).to_string(),
+                    C::to_units(*timings.iter().min().unwrap()).to_string(),
+                    C::to_units(*timings.iter().max().unwrap()).to_string(),
+                ],
+                &self.metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    /// Records the result of a `memory.grow` instruction. `previous_pages` is its
+    /// result, i.e. the previous memory size or -1 if growing failed.
+    pub fn record_memory_grow(
+        &mut self,
+        fn_index: u32,
+        local_block_id: u32,
+        previous_pages: i32,
+        current_pages: u32,
+    ) {
+        let growth = self
+            .memory_growth
+            .entry((fn_index, local_block_id))
+            .or_default();
+        growth.grows += 1;
+        if previous_pages < 0 {
+            growth.failed += 1;
+        } else {
+            growth.pages += u64::from(current_pages.saturating_sub(previous_pages as u32));
+        }
+    }
+
+    /// Writes the memory growth of all blocks that grew the memory, sorted by
+    /// function and block index.
+    pub fn compile_memory_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            ["location", "grows", "failed", "pages"],
+            &self.metadata,
+        ))
+        .unwrap();
+
+        let mut blocks: Vec<_> = self.memory_growth.iter().collect();
+        blocks.sort_unstable_by_key(|(location, _)| **location);
+        for ((fn_index, local_block_id), growth) in blocks {
+            wtr.write_record(with_metadata_values(
+                [
+                    symbols.describe_block(*fn_index, *local_block_id),
+                    growth.grows.to_string(),
+                    growth.failed.to_string(),
+                    growth.pages.to_string(),
+                ],
+                &self.metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    /// Records a `call_indirect` of the element at `element_index` in a table, right
+    /// before the callee starts.
+    pub fn record_indirect_call(&mut self, fn_index: u32, table_index: u32, element_index: u32) {
+        if let Some(events) = &mut self.events {
+            events.push(MeasurementEvent::IndirectCall {
+                fn_index,
+                table_index,
+                element_index,
+            });
+        }
+        *self
+            .indirect_calls
+            .entry((fn_index, table_index, element_index))
+            .or_default() += 1;
+    }
+
+    /// Writes the number of calls through tables per call site and callee, sorted by
+    /// caller and element. Callees are resolved with the table contents known to
+    /// `symbols`.
+    pub fn compile_indirect_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            ["caller", "table", "element", "callee", "calls"],
+            &self.metadata,
+        ))
+        .unwrap();
+
+        let mut calls: Vec<_> = self.indirect_calls.iter().collect();
+        calls.sort_unstable_by_key(|(call, _)| **call);
+        for ((fn_index, table_index, element_index), count) in calls {
+            let callee = match symbols.table_function(*table_index, *element_index) {
+                Some(callee) => symbols.describe_function(callee),
+                None => String::new(),
+            };
+            wtr.write_record(with_metadata_values(
+                [
+                    symbols.describe_function(*fn_index),
+                    table_index.to_string(),
+                    element_index.to_string(),
+                    callee,
+                    count.to_string(),
+                ],
+                &self.metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    /// Records an execution of the `br_if` or `br_table` ending a block. `outcome` is at
+    /// most `max_outcome`, which is 1 for `br_if` and the number of targets for
+    /// `br_table`, see [`BranchStats::outcomes`].
+    pub fn record_branch(
+        &mut self,
+        fn_index: u32,
+        local_block_id: u32,
+        outcome: u32,
+        max_outcome: u32,
+    ) {
+        let outcomes = &mut self
+            .branches
+            .entry((fn_index, local_block_id))
+            .or_default()
+            .outcomes;
+        let len = max_outcome.max(outcome) as usize + 1;
+        if outcomes.len() < len {
+            outcomes.resize(len, 0);
+        }
+        outcomes[outcome as usize] += 1;
+    }
+
+    /// Records the start of a function. A function entered while no calls are pending
+    /// was called by the host and starts a new entry point, e.g. `allocate` before
+    /// `execute`.
+    ///
+    /// Calls that were unwound by a trap stay pending until
+    /// [`Measurements::end_invocation`].
+    pub fn enter_function(&mut self, fn_index: u32) {
+        let (entry_point, pending_calls) = match self.call_stack {
+            Some((entry_point, pending_calls)) if pending_calls > 0 => (entry_point, pending_calls),
+            _ => (fn_index, 0),
+        };
+        self.call_stack = Some((entry_point, pending_calls));
+        let max_depth = self.max_call_depths.entry(entry_point).or_default();
+        *max_depth = (*max_depth).max(pending_calls + 1);
+    }
+
+    /// Records a call from one Wasm function to another, right before it happens.
+    pub fn enter_call(&mut self) {
+        if let Some((_, pending_calls)) = &mut self.call_stack {
+            *pending_calls += 1;
+        }
+    }
+
+    /// Records the return of a call recorded by [`Measurements::enter_call`].
+    pub fn exit_call(&mut self) {
+        if let Some((_, pending_calls)) = &mut self.call_stack {
+            *pending_calls = pending_calls.saturating_sub(1);
+        }
+    }
+
+    /// Writes the maximum call depth of every entry point, sorted by function index.
+    pub fn compile_call_depth_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            ["entry point", "max call depth"],
+            &self.metadata,
+        ))
+        .unwrap();
+
+        let mut entry_points: Vec<_> = self.max_call_depths.iter().collect();
+        entry_points.sort_unstable();
+        for (fn_index, max_depth) in entry_points {
+            wtr.write_record(with_metadata_values(
+                [symbols.describe_function(*fn_index), max_depth.to_string()],
+                &self.metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    /// Writes the outcomes of all executed branches, sorted by function and block index.
+    /// The outcomes are separated by spaces.
+    pub fn compile_branch_csv(&self, symbols: &Symbols, sink: impl std::io::Write) {
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(sink);
+
+        wtr.write_record(with_metadata_keys(
+            ["location", "executions", "bias", "outcomes"],
+            &self.metadata,
+        ))
+        .unwrap();
+
+        let mut blocks: Vec<_> = self.branches.iter().collect();
+        blocks.sort_unstable_by_key(|(location, _)| **location);
+        for ((fn_index, local_block_id), stats) in blocks {
+            let outcomes: Vec<_> = stats.outcomes.iter().map(u64::to_string).collect();
+            wtr.write_record(with_metadata_values(
+                [
+                    symbols.describe_block(*fn_index, *local_block_id),
+                    stats.executions().to_string(),
+                    format!("{:.3}", stats.bias()),
+                    outcomes.join(" "),
+                ],
+                &self.metadata,
+            ))
+            .unwrap();
+        }
+
+        wtr.flush().unwrap();
+    }
+
+    /// Writes the timings of all blocks. Their locations are described using `symbols`.
+    pub fn compile_csv(
+        &self,
+        block_store: Arc<Mutex<BlockStore>>,
+        symbols: &Symbols,
+        sink: impl std::io::Write,
+    ) {
         let block_store = block_store.lock().unwrap();
         let mut wtr = csv::WriterBuilder::new()
             .terminator(csv::Terminator::CRLF)
@@ -47,23 +771,39 @@ impl Measurements {
             .from_writer(sink);
 
         // Header row
-        wtr.write_record(&["block", "executions", "avg in ns", "min in ns", "max in ns"])
-            .unwrap();
+        wtr.write_record(with_metadata_keys(
+            [
+                "location".to_string(),
+                "block".to_string(),
+                "executions".to_string(),
+                format!("avg in {}", C::UNIT),
+                format!("min in {}", C::UNIT),
+                format!("max in {}", C::UNIT),
+            ],
+            &self.metadata,
+        ))
+        .unwrap();
 
         for (block_id, timings) in &self.taken {
-            let avg = timings.iter().sum::<Duration>().as_nanos() / timings.len() as u128;
-            let min = timings.iter().min().unwrap().as_nanos();
-            let max = timings.iter().max().unwrap().as_nanos();
-            let executions = timings.len();
+            let avg = timings.iter().map(|t| C::to_units(*t)).sum::<u128>() / timings.len() as u128;
+            let min = C::to_units(*timings.iter().min().unwrap());
+            let max = C::to_units(*timings.iter().max().unwrap());
+            let executions = self.executions_of(*block_id);
 
+            let (fn_index, local_block_id) = self.block_locations[block_id];
+            let location = symbols.describe_block(fn_index, local_block_id);
             let block = format!("{:?}", block_store.get_block(*block_id).unwrap());
-            wtr.write_record(&[
-                block,
-                executions.to_string(),
-                avg.to_string(),
-                min.to_string(),
-                max.to_string(),
-            ])
+            wtr.write_record(with_metadata_values(
+                [
+                    location,
+                    block,
+                    executions.to_string(),
+                    avg.to_string(),
+                    min.to_string(),
+                    max.to_string(),
+                ],
+                &self.metadata,
+            ))
             .unwrap();
 
             // wtr.write_record(timings.iter().map(|d| d.as_nanos().to_string()))
@@ -73,9 +813,68 @@ impl Measurements {
         wtr.flush().unwrap();
     }
 
+    /// Moves everything measured so far into the returned collector and continues with
+    /// an empty one, like [`Measurements::clear`] but keeping the cleared data.
+    ///
+    /// Measurements that are started but not taken yet stay with this collector, so
+    /// this can also be called in the middle of a call into the contract. Their cost
+    /// is attributed to the new period when they are taken. Both collectors keep the
+    /// clock, the metadata, whether events are recorded and the early termination.
+    pub fn split_off(&mut self) -> Self {
+        let mut rest = Self::with_clock(self.clock.clone());
+        rest.metadata = self.metadata.clone();
+        rest.events = self.events.as_ref().map(|_| Vec::new());
+        rest.early_termination = self.early_termination;
+        rest.started = std::mem::take(&mut self.started);
+        rest.last_reading = self.last_reading;
+        rest.host_started = std::mem::take(&mut self.host_started);
+        std::mem::replace(self, rest)
+    }
+
     pub fn clear(&mut self) {
-        self.started = HashMap::new();
+        self.started = Vec::new();
+        self.last_reading = None;
         self.taken = HashMap::new();
+        self.untimed = HashMap::new();
+        self.sample_stats = HashMap::new();
+        self.block_locations = HashMap::new();
+        self.executed_blocks = HashSet::new();
+        self.location_executions = HashMap::new();
+        self.executions = HashMap::new();
+        self.clock_regressions = HashMap::new();
+        self.loop_iterations = HashMap::new();
+        self.memory_growth = HashMap::new();
+        self.gas = HashMap::new();
+        self.host_started = Vec::new();
+        self.host_calls = HashMap::new();
+        self.indirect_calls = HashMap::new();
+        self.branches = HashMap::new();
+        self.max_call_depths = HashMap::new();
+        self.call_stack = None;
+        if let Some(events) = &mut self.events {
+            events.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+impl Measurements {
+    /// Creates measurements in which every block at the given location, i.e. function
+    /// index and local block id, took the given numbers of nanoseconds. For tests of
+    /// the code analyzing measurements.
+    pub(crate) fn from_samples(blocks: &[(BlockId, (u32, u32), &[u64])]) -> Self {
+        let mut measurements = Measurements::new();
+        for (block_id, location, timings) in blocks {
+            measurements.block_locations.insert(*block_id, *location);
+            measurements.taken.insert(
+                *block_id,
+                timings
+                    .iter()
+                    .map(|t| std::time::Duration::from_nanos(*t))
+                    .collect(),
+            );
+        }
+        measurements
     }
 }
 
@@ -83,6 +882,8 @@ impl Measurements {
 mod tests {
     use super::*;
 
+    use std::time;
+
     #[test]
     fn take_measurements_of_different_blocks() {
         // TODO: This is probably very confusing. What's a good way to refactor?
@@ -99,15 +900,21 @@ mod tests {
         measure.take_measurement(0, 0, 0);
         measure.take_measurement(1, 0, 1);
 
-        assert_eq!(measure.started[&(0, 0)].len(), 0);
-        assert_eq!(measure.started[&(0, 1)].len(), 1);
-        assert_eq!(measure.started[&(1, 0)].len(), 0);
+        let started: Vec<_> = measure
+            .started
+            .iter()
+            .map(|(location, _, _, _)| *location)
+            .collect();
+        assert_eq!(started, [(0, 1)]);
+        assert_eq!(measure.location_executions[&(0, 0)], 2);
+        assert_eq!(measure.location_executions[&(0, 1)], 1);
 
         let ms0 = &measure.taken[&BlockId(0)];
         let ms1 = &measure.taken[&BlockId(1)];
 
-        assert!(ms0[0] > time::Duration::from_millis(100));
-        assert!(ms0[1] < time::Duration::from_millis(25));
+        // The innermost measurement is taken first
+        assert!(ms0[0] < time::Duration::from_millis(25));
+        assert!(ms0[1] > time::Duration::from_millis(100));
         assert!(ms1[0] < time::Duration::from_millis(25));
     }
 
@@ -132,9 +939,440 @@ mod tests {
 
         let ms = &measure.taken[&BlockId(0)];
 
-        assert!(ms[0] < time::Duration::from_millis(60));
-        assert!(ms[1] < time::Duration::from_millis(20));
-        assert!(ms[2] > time::Duration::from_millis(100));
-        assert!(ms[3] < time::Duration::from_millis(60));
+        assert!(ms[0] < time::Duration::from_millis(20));
+        assert!(ms[1] > time::Duration::from_millis(50));
+        assert!(ms[1] < time::Duration::from_millis(70));
+        assert!(ms[2] > time::Duration::from_millis(50));
+        assert!(ms[2] < time::Duration::from_millis(70));
+        assert!(ms[3] > time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn take_measurements_of_nested_executions() {
+        let mut measure = Measurements::new();
+
+        // A recursive function measured as a whole: f(0) -> g -> f(0)
+        measure.start_measurement(0, 0);
+        std::thread::sleep(time::Duration::from_millis(30));
+        measure.start_measurement(1, 0);
+        measure.start_measurement(0, 0);
+        measure.take_measurement(0, 0, 7);
+        measure.take_measurement(1, 0, 8);
+        measure.take_measurement(0, 0, 7);
+
+        let ms = &measure.taken[&BlockId(7)];
+        assert!(ms[0] < time::Duration::from_millis(20));
+        assert!(ms[1] > time::Duration::from_millis(30));
+        assert!(measure.taken[&BlockId(8)][0] < time::Duration::from_millis(20));
+        assert!(measure.started.is_empty());
+
+        // Measurements unwound by a trap don't pair with the next invocation
+        measure.start_measurement(0, 0);
+        std::thread::sleep(time::Duration::from_millis(30));
+        measure.start_measurement(0, 1);
+        measure.end_invocation();
+        assert!(measure.started.is_empty());
+        measure.start_measurement(0, 0);
+        measure.take_measurement(0, 0, 7);
+        assert!(measure.taken[&BlockId(7)][2] < time::Duration::from_millis(20));
+    }
+
+    /// Returns the queued readings in order
+    #[derive(Debug, Clone)]
+    struct ScriptedClock {
+        readings: Arc<Mutex<VecDeque<u64>>>,
+    }
+
+    impl Clock for ScriptedClock {
+        type Reading = u64;
+        type Elapsed = time::Duration;
+
+        const UNIT: &'static str = "ticks";
+
+        fn now(&self) -> u64 {
+            self.readings.lock().unwrap().pop_front().unwrap()
+        }
+
+        fn elapsed(&self, start: u64) -> time::Duration {
+            time::Duration::from_nanos(self.now().saturating_sub(start))
+        }
+
+        fn to_units(elapsed: time::Duration) -> u128 {
+            elapsed.as_nanos()
+        }
+    }
+
+    #[test]
+    fn take_measurement_counts_clock_regressions() {
+        let clock = ScriptedClock {
+            readings: Arc::new(Mutex::new(vec![100, 110, 50, 60, 70, 80].into())),
+        };
+        let mut measure = Measurements::with_clock(clock);
+
+        measure.start_measurement(0, 0);
+        measure.take_measurement(0, 0, 1);
+        // The clock jumps back between the blocks
+        measure.start_measurement(0, 1);
+        measure.take_measurement(0, 1, 2);
+        measure.start_measurement(0, 1);
+        measure.take_measurement(0, 1, 2);
+
+        assert_eq!(measure.clock_regressions.get(&BlockId(1)), None);
+        assert_eq!(measure.clock_regressions[&BlockId(2)], 1);
+
+        measure.clear();
+        assert!(measure.clock_regressions.is_empty());
+    }
+
+    #[test]
+    fn early_termination_works() {
+        // Block 1 always costs 10, block 2 alternates between 1 and 100
+        let mut readings = Vec::new();
+        let mut now = 0;
+        for i in 0..10 {
+            readings.extend([now, now + 10, now + 20, now + 20 + [1, 100][i % 2]]);
+            now += 200;
+        }
+        let clock = ScriptedClock {
+            readings: Arc::new(Mutex::new(readings.into())),
+        };
+        let early_termination = EarlyTermination {
+            min_samples: 3,
+            relative_error: 0.05,
+        };
+        let mut measure = Measurements::with_clock(clock)
+            .with_early_termination(early_termination)
+            .with_event_recording();
+        for _ in 0..10 {
+            measure.start_measurement(0, 0);
+            measure.take_measurement(0, 0, 1);
+            measure.start_measurement(0, 1);
+            measure.take_measurement(0, 1, 2);
+        }
+
+        assert_eq!(measure.taken[&BlockId(1)].len(), 3);
+        assert_eq!(measure.untimed[&BlockId(1)], 7);
+        assert_eq!(measure.taken[&BlockId(2)].len(), 10);
+        assert_eq!(measure.untimed.get(&BlockId(2)), None);
+        assert_eq!(measure.executions_of(BlockId(1)), 10);
+        assert_eq!(measure.executions_of(BlockId(2)), 10);
+        // Events still contain every execution
+        assert_eq!(measure.events.as_ref().unwrap().len(), 40);
+
+        let rest = measure.split_off();
+        assert_eq!(rest.untimed[&BlockId(1)], 7);
+        assert!(measure.untimed.is_empty());
+        assert_eq!(measure.early_termination, Some(early_termination));
+    }
+
+    #[test]
+    fn take_measurement_with_gas_works() {
+        let mut measure = Measurements::new();
+
+        measure.start_measurement_with_gas(0, 0, 1000);
+        measure.start_measurement_with_gas(1, 0, 900);
+        measure.take_measurement_with_gas(1, 0, 2, 850);
+        measure.take_measurement_with_gas(0, 0, 1, 700);
+        measure.start_measurement_with_gas(1, 0, 700);
+        measure.take_measurement_with_gas(1, 0, 2, 680);
+        // Blocks started without the remaining gas are not charged
+        measure.start_measurement(0, 1);
+        measure.take_measurement_with_gas(0, 1, 3, 600);
+
+        assert_eq!(measure.gas[&BlockId(1)], 300);
+        assert_eq!(measure.gas[&BlockId(2)], 70);
+        assert_eq!(measure.gas.get(&BlockId(3)), None);
+        assert_eq!(measure.taken[&BlockId(3)].len(), 1);
+
+        measure.clear();
+        assert!(measure.gas.is_empty());
+    }
+
+    #[test]
+    fn count_loop_iterations() {
+        let mut measure = Measurements::new();
+
+        measure.count_loop_iteration(1, 0);
+        measure.count_loop_iteration(0, 2);
+        measure.count_loop_iteration(1, 0);
+
+        assert_eq!(measure.loop_iterations[&(1, 0)], 2);
+        assert_eq!(measure.loop_iterations[&(0, 2)], 1);
+
+        let mut csv = Vec::new();
+        measure.compile_loop_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,loop,iterations\r\nfn 0,2,1\r\nfn 1,0,2\r\n"
+        );
+
+        measure.clear();
+        assert!(measure.loop_iterations.is_empty());
+
+        // Metadata is added to every row.
+        let mut measure = Measurements::new()
+            .with_metadata("msg", "transfer")
+            .with_metadata("contract", "wasm1abc");
+        measure.count_loop_iteration(0, 1);
+        let mut csv = Vec::new();
+        measure.compile_loop_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "function,loop,iterations,contract,msg\r\nfn 0,1,1,wasm1abc,transfer\r\n"
+        );
+        measure.clear();
+        assert_eq!(measure.metadata.len(), 2);
+    }
+
+    #[test]
+    fn record_indirect_call_works() {
+        let mut measure = Measurements::new().with_event_recording();
+        measure.record_indirect_call(1, 0, 3);
+        measure.record_indirect_call(1, 0, 3);
+        measure.record_indirect_call(2, 0, 4);
+
+        assert_eq!(measure.indirect_calls[&(1, 0, 3)], 2);
+        assert_eq!(measure.indirect_calls[&(2, 0, 4)], 1);
+        assert_eq!(
+            measure.events.as_ref().unwrap()[2],
+            MeasurementEvent::IndirectCall {
+                fn_index: 2,
+                table_index: 0,
+                element_index: 4,
+            }
+        );
+
+        let mut csv = Vec::new();
+        measure.compile_indirect_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "caller,table,element,callee,calls\r\nfn 1,0,3,,2\r\nfn 2,0,4,,1\r\n"
+        );
+
+        measure.clear();
+        assert!(measure.indirect_calls.is_empty());
+    }
+
+    #[test]
+    fn record_branch_works() {
+        let mut measure = Measurements::new();
+        measure.record_branch(1, 2, 1, 1);
+        measure.record_branch(1, 2, 1, 1);
+        measure.record_branch(1, 2, 0, 1);
+        measure.record_branch(0, 4, 3, 3);
+
+        let stats = &measure.branches[&(1, 2)];
+        assert_eq!(stats.outcomes, [1, 2]);
+        assert_eq!(stats.executions(), 3);
+        assert!((stats.bias() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(measure.branches[&(0, 4)].outcomes, [0, 0, 0, 1]);
+        assert_eq!(BranchStats::default().bias(), 0.0);
+
+        let mut csv = Vec::new();
+        measure.compile_branch_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "location,executions,bias,outcomes\r\n\"fn 0, block 4\",1,1.000,0 0 0 1\r\n\"fn 1, block 2\",3,0.667,1 2\r\n"
+        );
+
+        measure.clear();
+        assert!(measure.branches.is_empty());
+    }
+
+    #[test]
+    fn call_depths_work() {
+        let mut measure = Measurements::new();
+        // allocate, called by the host
+        measure.enter_function(7);
+        // execute calls 3, which calls 4 twice
+        measure.enter_function(1);
+        measure.enter_call();
+        measure.enter_function(3);
+        for _ in 0..2 {
+            measure.enter_call();
+            measure.enter_function(4);
+            measure.exit_call();
+        }
+        measure.exit_call();
+        // A trap left a call pending
+        measure.enter_call();
+        measure.end_invocation();
+        measure.enter_function(7);
+
+        let mut depths: Vec<_> = measure.max_call_depths.iter().collect();
+        depths.sort_unstable();
+        assert_eq!(depths, [(&1, &3), (&7, &1)]);
+
+        let mut csv = Vec::new();
+        measure.compile_call_depth_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "entry point,max call depth\r\nfn 1,3\r\nfn 7,1\r\n"
+        );
+
+        measure.clear();
+        assert!(measure.max_call_depths.is_empty());
+    }
+
+    #[test]
+    fn branch_stats_merge() {
+        let mut stats = BranchStats {
+            outcomes: vec![1, 2],
+        };
+        stats.merge(&BranchStats {
+            outcomes: vec![3, 0, 4],
+        });
+        assert_eq!(stats.outcomes, [4, 2, 4]);
+    }
+
+    #[test]
+    fn record_memory_grows() {
+        let mut measure = Measurements::new();
+
+        measure.record_memory_grow(1, 3, 17, 19);
+        measure.record_memory_grow(0, 0, 2, 3);
+        measure.record_memory_grow(1, 3, -1, 19);
+        measure.record_memory_grow(1, 3, 19, 19);
+
+        assert_eq!(
+            measure.memory_growth[&(1, 3)],
+            MemoryGrowth {
+                grows: 3,
+                failed: 1,
+                pages: 2,
+            }
+        );
+
+        let mut csv = Vec::new();
+        measure.compile_memory_csv(&Symbols::default(), &mut csv);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "location,grows,failed,pages\r\n\"fn 0, block 0\",1,0,1\r\n\"fn 1, block 3\",3,1,2\r\n"
+        );
+
+        measure.clear();
+        assert!(measure.memory_growth.is_empty());
+    }
+
+    #[test]
+    fn flush_buffer_counts_executions() {
+        let mut measure = Measurements::new();
+
+        let record = |fn_index: u32, local_block_id: u32, block_id: u64| {
+            let mut record = fn_index.to_le_bytes().to_vec();
+            record.extend_from_slice(&local_block_id.to_le_bytes());
+            record.extend_from_slice(&block_id.to_le_bytes());
+            record
+        };
+        let mut records = [record(1, 2, 42), record(0, 0, 7), record(1, 2, 42)].concat();
+        measure.flush_buffer(&records);
+        // Partial records are ignored
+        records.truncate(20);
+        measure.flush_buffer(&records);
+
+        assert_eq!(measure.executions[&BlockId(42)], 3);
+        assert_eq!(measure.executions[&BlockId(7)], 1);
+        assert_eq!(measure.block_locations[&BlockId(42)], (1, 2));
+        assert_eq!(measure.executed_blocks.len(), 2);
+        assert_eq!(measure.location_executions[&(1, 2)], 3);
+        assert!(measure.taken.is_empty());
+
+        measure.clear();
+        assert!(measure.executions.is_empty());
+    }
+
+    #[test]
+    fn time_host_calls() {
+        let mut measure = Measurements::new().with_event_recording();
+
+        measure.start_host_call(0, 2);
+        measure.end_host_call(0, 2);
+        measure.start_host_call(1, 2);
+        measure.end_host_call(1, 2);
+        measure.start_host_call(1, 0);
+        measure.end_host_call(1, 0);
+
+        assert_eq!(measure.host_calls[&2].len(), 2);
+        assert_eq!(measure.host_calls[&0].len(), 1);
+        let events = measure.events.as_ref().unwrap();
+        assert!(matches!(
+            events[1],
+            MeasurementEvent::HostCall {
+                fn_index: 1,
+                import_index: 2,
+                ..
+            }
+        ));
+
+        let mut csv = Vec::new();
+        measure.compile_host_csv(&Symbols::default(), &mut csv);
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "import,calls,total in ns,avg in ns,min in ns,max in ns"
+        );
+        assert!(lines[1].starts_with("import 0,1,"));
+        assert!(lines[2].starts_with("import 2,2,"));
+
+        measure.clear();
+        assert!(measure.host_calls.is_empty());
+    }
+
+    #[test]
+    fn record_events() {
+        let mut measure = Measurements::new();
+        measure.start_measurement(0, 0);
+        measure.take_measurement(0, 0, 7);
+        assert_eq!(measure.events, None);
+
+        let mut measure = Measurements::new().with_event_recording();
+        measure.start_measurement(0, 0);
+        measure.start_measurement(1, 0);
+        measure.take_measurement(1, 0, 7);
+        measure.take_measurement(0, 0, 8);
+        measure.end_invocation();
+
+        let events = measure.events.as_ref().unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0], MeasurementEvent::Start { fn_index: 0 });
+        assert_eq!(events[1], MeasurementEvent::Start { fn_index: 1 });
+        assert!(matches!(
+            events[2],
+            MeasurementEvent::Take {
+                fn_index: 1,
+                block_id: BlockId(7),
+                ..
+            }
+        ));
+        assert_eq!(events[4], MeasurementEvent::InvocationEnd);
+
+        measure.clear();
+        assert_eq!(measure.events, Some(vec![]));
+    }
+
+    #[test]
+    fn split_off_keeps_started_measurements() {
+        let mut measure = Measurements::new()
+            .with_event_recording()
+            .with_metadata("msg", "transfer");
+        measure.start_measurement(0, 0);
+        measure.take_measurement(0, 0, 7);
+        measure.start_measurement(1, 0);
+
+        let first = measure.split_off();
+        assert_eq!(first.taken[&BlockId(7)].len(), 1);
+        assert!(first.started.is_empty());
+        assert_eq!(first.events.as_ref().unwrap().len(), 3);
+
+        assert!(measure.taken.is_empty());
+        assert_eq!(measure.events, Some(vec![]));
+        assert_eq!(measure.metadata["msg"], "transfer");
+
+        // The measurement started before is taken in the new period
+        measure.take_measurement(1, 0, 8);
+        assert_eq!(measure.taken[&BlockId(8)].len(), 1);
+        assert_eq!(measure.block_locations[&BlockId(8)], (1, 0));
     }
 }
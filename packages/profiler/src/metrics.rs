@@ -0,0 +1,635 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+
+use crate::callgraph::CallGraph;
+use crate::clock::Clock;
+use crate::instrumentation::Granularity;
+use crate::measure::{Measurements, Metadata};
+use crate::symbols::Symbols;
+
+/// The upper bounds of the default histogram buckets, powers of four from 16 to about
+/// 4 million units of the clock.
+pub const DEFAULT_BUCKETS: [u64; 10] = [
+    16, 64, 256, 1024, 4096, 16384, 65536, 262144, 1048576, 4194304,
+];
+
+/// The largest statsd datagram sent, small enough to not be fragmented on common
+/// networks.
+const MAX_DATAGRAM: usize = 1432;
+
+/// Counts values in buckets, like a Prometheus histogram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// The inclusive upper bounds of all buckets but the last one, which is unbounded
+    pub bounds: Vec<u64>,
+    /// The number of values in every bucket, one more than `bounds`
+    pub counts: Vec<u64>,
+    pub sum: u128,
+    pub max: u64,
+}
+
+impl Histogram {
+    /// `bounds` must be sorted in increasing order.
+    pub fn new(bounds: &[u64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: u64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[bucket] += 1;
+        self.sum += value as u128;
+        self.max = self.max.max(value);
+    }
+
+    /// The number of observed values
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionMetrics {
+    /// The cost of every execution of a block of the function
+    pub block_costs: Histogram,
+    /// The number of executions of every block by local block id
+    pub block_executions: BTreeMap<u32, u64>,
+    /// The number of calls, only known with event recording
+    pub calls: u64,
+    /// The cost including callees, only known with event recording
+    pub inclusive: u128,
+    /// The cost of the function's own code, only known with event recording
+    pub exclusive: u128,
+}
+
+impl FunctionMetrics {
+    fn new(bounds: &[u64]) -> Self {
+        FunctionMetrics {
+            block_costs: Histogram::new(bounds),
+            block_executions: BTreeMap::new(),
+            calls: 0,
+            inclusive: 0,
+            exclusive: 0,
+        }
+    }
+}
+
+/// The per-function and per-block aggregates of a profiling session in a form that
+/// can be pushed to a monitoring system with a [`MetricsSink`], e.g. to profile
+/// contracts continuously on a staging node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileMetrics {
+    /// The unit of all costs, see [`Clock::UNIT`]
+    pub unit: String,
+    /// Keyed by the function name, see [`Symbols::describe_function`]. Functions
+    /// with the same name are merged.
+    pub functions: BTreeMap<String, FunctionMetrics>,
+    /// Added as labels to all Prometheus samples
+    pub metadata: Metadata,
+}
+
+impl ProfileMetrics {
+    /// Aggregates all blocks of `measurements` in histograms with the given bucket
+    /// bounds. The calls and costs of functions are taken from the call graph if
+    /// events were recorded.
+    pub fn new<C: Clock>(
+        measurements: &Measurements<C>,
+        symbols: &Symbols,
+        granularity: Granularity,
+        bounds: &[u64],
+    ) -> Self {
+        let mut functions: BTreeMap<String, FunctionMetrics> = BTreeMap::new();
+        for (block_id, timings) in &measurements.taken {
+            let (fn_index, local_block_id) = measurements.block_locations[block_id];
+            let function = functions
+                .entry(symbols.describe_function(fn_index))
+                .or_insert_with(|| FunctionMetrics::new(bounds));
+            for timing in timings {
+                function.block_costs.observe(C::to_units(*timing) as u64);
+            }
+            *function.block_executions.entry(local_block_id).or_default() += timings.len() as u64;
+        }
+
+        if let Some(events) = &measurements.events {
+            let graph = CallGraph::from_events(events, granularity);
+            for (fn_index, stats) in &graph.functions {
+                let function = functions
+                    .entry(symbols.describe_function(*fn_index))
+                    .or_insert_with(|| FunctionMetrics::new(bounds));
+                function.calls += stats.calls;
+                function.inclusive += stats.inclusive;
+                function.exclusive += stats.exclusive;
+            }
+        }
+
+        ProfileMetrics {
+            unit: C::UNIT.to_string(),
+            functions,
+            metadata: measurements.metadata.clone(),
+        }
+    }
+
+    /// Writes all metrics in the Prometheus text exposition format. Metric names
+    /// start with `prefix` and costs end with the unit, e.g.
+    /// `cosmwasm_block_cost_ns_bucket{function="...",le="64"}`.
+    pub fn write_prometheus(&self, prefix: &str, mut sink: impl Write) -> io::Result<()> {
+        let unit = sanitize(&self.unit);
+        let block_cost = format!("{}_block_cost_{}", prefix, unit);
+        writeln!(sink, "# TYPE {} histogram", block_cost)?;
+        for (name, function) in &self.functions {
+            let histogram = &function.block_costs;
+            let mut cumulative = 0;
+            for (index, count) in histogram.counts.iter().enumerate() {
+                cumulative += count;
+                let bound = match histogram.bounds.get(index) {
+                    Some(bound) => bound.to_string(),
+                    None => "+Inf".to_string(),
+                };
+                let labels = self.labels(name, &[("le", &bound)]);
+                writeln!(sink, "{}_bucket{{{}}} {}", block_cost, labels, cumulative)?;
+            }
+            let labels = self.labels(name, &[]);
+            writeln!(sink, "{}_sum{{{}}} {}", block_cost, labels, histogram.sum)?;
+            writeln!(sink, "{}_count{{{}}} {}", block_cost, labels, cumulative)?;
+        }
+
+        let block_executions = format!("{}_block_executions_total", prefix);
+        writeln!(sink, "# TYPE {} counter", block_executions)?;
+        for (name, function) in &self.functions {
+            for (local_block_id, executions) in &function.block_executions {
+                let labels = self.labels(name, &[("block", &local_block_id.to_string())]);
+                writeln!(sink, "{}{{{}}} {}", block_executions, labels, executions)?;
+            }
+        }
+
+        let calls = format!("{}_function_calls_total", prefix);
+        writeln!(sink, "# TYPE {} counter", calls)?;
+        for (name, function) in &self.functions {
+            let labels = self.labels(name, &[]);
+            writeln!(sink, "{}{{{}}} {}", calls, labels, function.calls)?;
+        }
+
+        for (kind, inclusive) in [("inclusive", true), ("exclusive", false)] {
+            let metric = format!("{}_function_{}_cost_{}", prefix, kind, unit);
+            writeln!(sink, "# TYPE {} counter", metric)?;
+            for (name, function) in &self.functions {
+                let labels = self.labels(name, &[]);
+                let cost = if inclusive {
+                    function.inclusive
+                } else {
+                    function.exclusive
+                };
+                writeln!(sink, "{}{{{}}} {}", metric, labels, cost)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The metrics as statsd lines with names starting with `prefix`, e.g.
+    /// `cosmwasm.block_cost.<function>:64|h|@0.5`.
+    ///
+    /// Statsd has no way to send bucketed values, so every non-empty bucket is sent
+    /// once as its upper bound with a sample rate of one over its count, which the
+    /// server scales back up. Values above the last bound are sent as the maximum.
+    /// Function names are sanitized to only contain letters, digits, `_` and `-`.
+    pub fn statsd_lines(&self, prefix: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (name, function) in &self.functions {
+            let name = sanitize(name);
+            let histogram = &function.block_costs;
+            for (index, count) in histogram.counts.iter().enumerate() {
+                if *count == 0 {
+                    continue;
+                }
+                let value = histogram
+                    .bounds
+                    .get(index)
+                    .copied()
+                    .unwrap_or(histogram.max);
+                let rate = if *count == 1 {
+                    String::new()
+                } else {
+                    format!("|@{}", 1.0 / *count as f64)
+                };
+                lines.push(format!(
+                    "{}.block_cost.{}:{}|h{}",
+                    prefix, name, value, rate
+                ));
+            }
+            for (local_block_id, executions) in &function.block_executions {
+                lines.push(format!(
+                    "{}.block_executions.{}.{}:{}|c",
+                    prefix, name, local_block_id, executions
+                ));
+            }
+            if function.calls > 0 {
+                lines.push(format!(
+                    "{}.function_calls.{}:{}|c",
+                    prefix, name, function.calls
+                ));
+                lines.push(format!(
+                    "{}.function_inclusive_cost.{}:{}|g",
+                    prefix, name, function.inclusive
+                ));
+                lines.push(format!(
+                    "{}.function_exclusive_cost.{}:{}|g",
+                    prefix, name, function.exclusive
+                ));
+            }
+        }
+        lines
+    }
+
+    fn labels(&self, function: &str, extra: &[(&str, &str)]) -> String {
+        let mut labels = vec![format!("function=\"{}\"", escape_label(function))];
+        for (key, value) in &self.metadata {
+            labels.push(format!("{}=\"{}\"", sanitize(key), escape_label(value)));
+        }
+        for (key, value) in extra {
+            labels.push(format!("{}=\"{}\"", key, escape_label(value)));
+        }
+        labels.join(",")
+    }
+}
+
+/// Pushes [`ProfileMetrics`] to a monitoring system.
+pub trait MetricsSink {
+    fn push(&mut self, metrics: &ProfileMetrics) -> io::Result<()>;
+}
+
+/// Pushes metrics to a [Prometheus push gateway](https://github.com/prometheus/pushgateway)
+/// over plain HTTP. Every push replaces the metrics of the job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushGateway {
+    /// `host:port` of the gateway
+    address: String,
+    job: String,
+    prefix: String,
+}
+
+impl PushGateway {
+    pub fn new(address: impl Into<String>, job: impl Into<String>) -> Self {
+        PushGateway {
+            address: address.into(),
+            job: job.into(),
+            prefix: "cosmwasm".to_string(),
+        }
+    }
+
+    /// The start of all metric names, `cosmwasm` by default
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+impl MetricsSink for PushGateway {
+    fn push(&mut self, metrics: &ProfileMetrics) -> io::Result<()> {
+        let mut body = Vec::new();
+        metrics.write_prometheus(&self.prefix, &mut body)?;
+
+        let mut stream = TcpStream::connect(&self.address)?;
+        write!(
+            stream,
+            "PUT /metrics/job/{} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            percent_encode(&self.job),
+            self.address,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status_line = response.lines().next().unwrap_or_default();
+        match status_line.split(' ').nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Push gateway responded with: {}", status_line),
+            )),
+        }
+    }
+}
+
+/// Sends metrics to a statsd server over UDP, see [`ProfileMetrics::statsd_lines`].
+#[derive(Debug)]
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl Statsd {
+    /// Connects to the statsd server at `address`, e.g. `127.0.0.1:8125`.
+    pub fn new(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address for statsd"))?;
+        let socket = if address.is_ipv4() {
+            UdpSocket::bind("0.0.0.0:0")?
+        } else {
+            UdpSocket::bind("[::]:0")?
+        };
+        socket.connect(address)?;
+        Ok(Statsd {
+            socket,
+            prefix: "cosmwasm".to_string(),
+        })
+    }
+
+    /// The start of all metric names, `cosmwasm` by default
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+impl MetricsSink for Statsd {
+    /// Sends as many lines per datagram as fit.
+    fn push(&mut self, metrics: &ProfileMetrics) -> io::Result<()> {
+        let mut datagram = String::new();
+        for line in metrics.statsd_lines(&self.prefix) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.socket.send(datagram.as_bytes())?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces everything but ASCII letters, digits, `_` and `-` by `_`.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use crate::code_blocks::BlockId;
+    use crate::measure::MeasurementEvent;
+
+    fn measurements() -> Measurements {
+        let mut measurements = Measurements::from_samples(&[
+            (BlockId::from(1), (0, 0), &[10, 20, 100]),
+            (BlockId::from(2), (0, 1), &[5000]),
+            (BlockId::from(3), (1, 0), &[1]),
+        ]);
+        measurements
+            .metadata
+            .insert("commit".to_string(), "abc".to_string());
+        measurements
+    }
+
+    #[test]
+    fn histogram_works() {
+        let mut histogram = Histogram::new(&[10, 100]);
+        for value in [0, 10, 11, 100, 101, 5000] {
+            histogram.observe(value);
+        }
+        assert_eq!(histogram.counts, [2, 2, 2]);
+        assert_eq!(histogram.sum, 5222);
+        assert_eq!(histogram.max, 5000);
+        assert_eq!(histogram.count(), 6);
+    }
+
+    #[test]
+    fn new_works() {
+        let mut measurements = measurements().with_event_recording();
+        measurements.events.as_mut().unwrap().extend([
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::Take {
+                fn_index: 1,
+                block_id: BlockId::from(3),
+                cost: 7,
+            },
+        ]);
+        let metrics = ProfileMetrics::new(
+            &measurements,
+            &Symbols::default(),
+            Granularity::Function,
+            &[16, 256],
+        );
+        assert_eq!(metrics.unit, "ns");
+        assert_eq!(metrics.metadata["commit"], "abc");
+
+        let first = &metrics.functions["fn 0"];
+        assert_eq!(first.block_costs.counts, [1, 2, 1]);
+        assert_eq!(first.block_costs.sum, 5130);
+        assert_eq!(
+            first.block_executions,
+            vec![(0, 3), (1, 1)].into_iter().collect::<BTreeMap<_, _>>()
+        );
+        assert_eq!(first.calls, 0);
+
+        let second = &metrics.functions["fn 1"];
+        assert_eq!(second.block_costs.counts, [1, 0, 0]);
+        assert_eq!(second.calls, 1);
+        assert_eq!(second.inclusive, 7);
+        assert_eq!(second.exclusive, 7);
+    }
+
+    #[test]
+    fn write_prometheus_works() {
+        let metrics = ProfileMetrics::new(
+            &measurements(),
+            &Symbols::default(),
+            Granularity::BasicBlock,
+            &[16, 256],
+        );
+        let mut text = Vec::new();
+        metrics.write_prometheus("cosmwasm", &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let expected = r#"# TYPE cosmwasm_block_cost_ns histogram
+cosmwasm_block_cost_ns_bucket{function="fn 0",commit="abc",le="16"} 1
+cosmwasm_block_cost_ns_bucket{function="fn 0",commit="abc",le="256"} 3
+cosmwasm_block_cost_ns_bucket{function="fn 0",commit="abc",le="+Inf"} 4
+cosmwasm_block_cost_ns_sum{function="fn 0",commit="abc"} 5130
+cosmwasm_block_cost_ns_count{function="fn 0",commit="abc"} 4
+cosmwasm_block_cost_ns_bucket{function="fn 1",commit="abc",le="16"} 1
+cosmwasm_block_cost_ns_bucket{function="fn 1",commit="abc",le="256"} 1
+cosmwasm_block_cost_ns_bucket{function="fn 1",commit="abc",le="+Inf"} 1
+cosmwasm_block_cost_ns_sum{function="fn 1",commit="abc"} 1
+cosmwasm_block_cost_ns_count{function="fn 1",commit="abc"} 1
+# TYPE cosmwasm_block_executions_total counter
+cosmwasm_block_executions_total{function="fn 0",commit="abc",block="0"} 3
+cosmwasm_block_executions_total{function="fn 0",commit="abc",block="1"} 1
+cosmwasm_block_executions_total{function="fn 1",commit="abc",block="0"} 1
+# TYPE cosmwasm_function_calls_total counter
+cosmwasm_function_calls_total{function="fn 0",commit="abc"} 0
+cosmwasm_function_calls_total{function="fn 1",commit="abc"} 0
+# TYPE cosmwasm_function_inclusive_cost_ns counter
+cosmwasm_function_inclusive_cost_ns{function="fn 0",commit="abc"} 0
+cosmwasm_function_inclusive_cost_ns{function="fn 1",commit="abc"} 0
+# TYPE cosmwasm_function_exclusive_cost_ns counter
+cosmwasm_function_exclusive_cost_ns{function="fn 0",commit="abc"} 0
+cosmwasm_function_exclusive_cost_ns{function="fn 1",commit="abc"} 0
+"#;
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn escape_label_works() {
+        assert_eq!(escape_label(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn statsd_lines_works() {
+        let metrics = ProfileMetrics::new(
+            &measurements(),
+            &Symbols::default(),
+            Granularity::BasicBlock,
+            &[16, 256],
+        );
+        assert_eq!(
+            metrics.statsd_lines("cw"),
+            [
+                "cw.block_cost.fn_0:16|h",
+                "cw.block_cost.fn_0:256|h|@0.5",
+                "cw.block_cost.fn_0:5000|h",
+                "cw.block_executions.fn_0.0:3|c",
+                "cw.block_executions.fn_0.1:1|c",
+                "cw.block_cost.fn_1:16|h",
+                "cw.block_executions.fn_1.0:1|c",
+            ]
+        );
+    }
+
+    #[test]
+    fn statsd_push_works() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let metrics = ProfileMetrics::new(
+            &measurements(),
+            &Symbols::default(),
+            Granularity::BasicBlock,
+            &DEFAULT_BUCKETS,
+        );
+        let mut statsd = Statsd::new(server.local_addr().unwrap()).unwrap();
+        statsd.push(&metrics).unwrap();
+
+        let mut buffer = [0; MAX_DATAGRAM];
+        let len = server.recv(&mut buffer).unwrap();
+        let datagram = std::str::from_utf8(&buffer[..len]).unwrap();
+        assert_eq!(
+            datagram.lines().collect::<Vec<_>>(),
+            metrics.statsd_lines("cosmwasm")
+        );
+    }
+
+    fn serve_once(status: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // Read until the announced body is complete
+            loop {
+                let len = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..len]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn push_gateway_works() {
+        let metrics = ProfileMetrics::new(
+            &measurements(),
+            &Symbols::default(),
+            Granularity::BasicBlock,
+            &DEFAULT_BUCKETS,
+        );
+        let (address, server) = serve_once("200 OK");
+        PushGateway::new(&address, "staging node")
+            .push(&metrics)
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("PUT /metrics/job/staging%20node HTTP/1.1\r\n"));
+        let mut body = Vec::new();
+        metrics.write_prometheus("cosmwasm", &mut body).unwrap();
+        assert!(request.ends_with(&String::from_utf8(body).unwrap()));
+    }
+
+    #[test]
+    fn push_gateway_fails_on_error_status() {
+        let metrics = ProfileMetrics::new(
+            &measurements(),
+            &Symbols::default(),
+            Granularity::BasicBlock,
+            &DEFAULT_BUCKETS,
+        );
+        let (address, server) = serve_once("400 Bad Request");
+        let err = PushGateway::new(&address, "job")
+            .push(&metrics)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Push gateway responded with: HTTP/1.1 400 Bad Request"
+        );
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,477 @@
+use std::sync::{Arc, Mutex};
+
+use thiserror::Error;
+use wasmer::{Exports, ImportObject, Instance, ModuleMiddleware, Val};
+
+use crate::bench::{bench, BenchError, BenchOptions, BenchResult};
+use crate::clock::Clock;
+use crate::code_blocks::BlockStore;
+use crate::cost_model::CostModel;
+use crate::instrumentation::{
+    add_measuring_imports, instrument_wasm, Granularity, Profiling, GAS_LIMIT,
+};
+use crate::measure::Measurements;
+use crate::operators::OperatorSymbol;
+
+/// The values passed to the benchmarked operators. None of them is 0, so divisions
+/// don't trap.
+const OPERANDS: [i64; 3] = [7, 3, 1];
+
+/// The address loads and stores access
+const ADDRESS: i32 = 16;
+
+const WAT_HEADER: &str = r#"(module
+  (memory (export "memory") 1)
+  (global $g (mut i32) (i32.const 0))
+  (func (export "run") (param $n i32) (local $l i32)
+    (loop $loop
+"#;
+
+/// Counts down `$n` and repeats the loop until it is 0
+const WAT_FOOTER: &str = r#"      local.get $n
+      i32.const 1
+      i32.sub
+      local.tee $n
+      br_if $loop)))
+"#;
+
+#[derive(Error, Debug)]
+pub enum MicroBenchError {
+    #[error("No micro-benchmark can be generated for {symbol:?}")]
+    Unsupported { symbol: OperatorSymbol },
+    #[error("Error compiling the micro-benchmark: {msg}")]
+    CompileErr { msg: String },
+    #[error(transparent)]
+    Bench(#[from] BenchError),
+    #[error("The loop of the micro-benchmark was not measured")]
+    LoopNotMeasured,
+}
+
+/// How the micro-benchmarks are generated and executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MicroBenchOptions {
+    /// How often the operators are repeated in the body of the loop. Repetitions make
+    /// the measured block long enough for the clock to resolve the operators.
+    pub unroll: u32,
+    /// Loop iterations per call
+    pub loop_iterations: u32,
+    pub bench: BenchOptions,
+}
+
+impl Default for MicroBenchOptions {
+    fn default() -> Self {
+        MicroBenchOptions {
+            unroll: 16,
+            loop_iterations: 100,
+            bench: BenchOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+        }
+    }
+
+    fn const_symbol(self) -> OperatorSymbol {
+        match self {
+            ValType::I32 => OperatorSymbol::I32Const,
+            ValType::I64 => OperatorSymbol::I64Const,
+        }
+    }
+}
+
+/// An operator together with the types it pops and pushes
+struct Kernel {
+    symbol: OperatorSymbol,
+    inputs: Vec<ValType>,
+    /// The operator in the text format, including its immediates
+    instruction: String,
+    outputs: Vec<ValType>,
+}
+
+impl Kernel {
+    fn new(symbol: OperatorSymbol) -> Result<Self, MicroBenchError> {
+        use ValType::*;
+
+        let unsupported = || MicroBenchError::Unsupported { symbol };
+        let (inputs, instruction, outputs) = match symbol {
+            OperatorSymbol::Drop => (vec![I32], "drop".to_string(), vec![]),
+            OperatorSymbol::Select => (vec![I32, I32, I32], "select".to_string(), vec![I32]),
+            OperatorSymbol::LocalGet => (vec![], "local.get $l".to_string(), vec![I32]),
+            OperatorSymbol::LocalSet => (vec![I32], "local.set $l".to_string(), vec![]),
+            OperatorSymbol::LocalTee => (vec![I32], "local.tee $l".to_string(), vec![I32]),
+            OperatorSymbol::GlobalGet => (vec![], "global.get $g".to_string(), vec![I32]),
+            OperatorSymbol::GlobalSet => (vec![I32], "global.set $g".to_string(), vec![]),
+            _ if symbol.is_float() => return Err(unsupported()),
+            _ => {
+                let mnemonic = symbol.mnemonic();
+                let (prefix, name) = mnemonic.split_once('.').ok_or_else(unsupported)?;
+                let t = match prefix {
+                    "i32" => I32,
+                    "i64" => I64,
+                    _ => return Err(unsupported()),
+                };
+                let (inputs, outputs) = match name {
+                    "const" => (vec![], vec![t]),
+                    "eqz" => (vec![t], vec![I32]),
+                    "clz" | "ctz" | "popcnt" | "extend8_s" | "extend16_s" | "extend32_s" => {
+                        (vec![t], vec![t])
+                    }
+                    "add" | "sub" | "mul" | "div_s" | "div_u" | "rem_s" | "rem_u" | "and"
+                    | "or" | "xor" | "shl" | "shr_s" | "shr_u" | "rotl" | "rotr" => {
+                        (vec![t, t], vec![t])
+                    }
+                    "eq" | "ne" | "lt_s" | "lt_u" | "gt_s" | "gt_u" | "le_s" | "le_u" | "ge_s"
+                    | "ge_u" => (vec![t, t], vec![I32]),
+                    "wrap_i64" => (vec![I64], vec![I32]),
+                    "extend_i32_s" | "extend_i32_u" => (vec![I32], vec![I64]),
+                    _ if name.starts_with("load") => (vec![I32], vec![t]),
+                    _ if name.starts_with("store") => (vec![I32, t], vec![]),
+                    _ => return Err(unsupported()),
+                };
+                let instruction = match name {
+                    "const" => format!("{} {}", mnemonic, OPERANDS[0]),
+                    _ => mnemonic,
+                };
+                (inputs, instruction, outputs)
+            }
+        };
+        Ok(Kernel {
+            symbol,
+            inputs,
+            instruction,
+            outputs,
+        })
+    }
+
+    /// Pushes the operands, executes the operator and drops its results
+    fn write_wat(&self, wat: &mut String) {
+        let is_memory_access =
+            self.instruction.contains(".load") || self.instruction.contains(".store");
+        for (index, input) in self.inputs.iter().enumerate() {
+            let value = match index {
+                0 if is_memory_access => i64::from(ADDRESS),
+                _ => OPERANDS[index],
+            };
+            wat.push_str(&format!("      {}.const {}\n", input.name(), value));
+        }
+        wat.push_str(&format!("      {}\n", self.instruction));
+        for _ in &self.outputs {
+            wat.push_str("      drop\n");
+        }
+    }
+
+    /// The operators [`Kernel::write_wat`] writes
+    fn operators(&self) -> Vec<OperatorSymbol> {
+        let mut operators: Vec<OperatorSymbol> = self
+            .inputs
+            .iter()
+            .map(|input| input.const_symbol())
+            .collect();
+        operators.push(self.symbol);
+        operators.extend(self.outputs.iter().map(|_| OperatorSymbol::Drop));
+        operators
+    }
+}
+
+/// Generates a module exporting `run(n: i32)`, which executes the operators `unroll`
+/// times in each of `n` loop iterations. Every operator gets its operands from
+/// constants and its results are dropped, so several operators, e.g. a pair, are
+/// independent of each other.
+///
+/// Returns the module in the text format and the operators of one repetition,
+/// including the constants and drops.
+pub fn generate_wat(
+    operators: &[OperatorSymbol],
+    unroll: u32,
+) -> Result<(String, Vec<OperatorSymbol>), MicroBenchError> {
+    let kernels = operators
+        .iter()
+        .map(|symbol| Kernel::new(*symbol))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut wat = WAT_HEADER.to_string();
+    for _ in 0..unroll {
+        for kernel in &kernels {
+            kernel.write_wat(&mut wat);
+        }
+    }
+    wat.push_str(WAT_FOOTER);
+    let repetition = kernels
+        .iter()
+        .flat_map(|kernel| kernel.operators())
+        .collect();
+    Ok((wat, repetition))
+}
+
+/// The measured cost of a micro-benchmark
+#[derive(Debug, Clone, PartialEq)]
+pub struct MicroBenchResult {
+    /// The benchmarked operators
+    pub operators: Vec<OperatorSymbol>,
+    /// All operators executed per repetition, see [`generate_wat`]
+    pub repetition: Vec<OperatorSymbol>,
+    /// The unit of `cost`, see [`Clock::UNIT`]
+    pub unit: &'static str,
+    /// The median cost of one repetition, without the cost of the loop itself and of
+    /// the measurement
+    pub cost: f64,
+}
+
+impl MicroBenchResult {
+    /// The cost of one repetition according to `model`. Operators that were not part of
+    /// the fit are counted as free, like in [`CostModel::predict`].
+    pub fn predicted(&self, model: &CostModel) -> f64 {
+        self.repetition
+            .iter()
+            .filter_map(|operator| model.operators.get(operator))
+            .map(|estimate| estimate.cost)
+            .sum()
+    }
+
+    /// How much the measured cost deviates from the one predicted by `model`, relative
+    /// to the measured cost.
+    pub fn relative_error(&self, model: &CostModel) -> f64 {
+        (self.predicted(model) - self.cost) / self.cost
+    }
+}
+
+/// Measures the cost of executing `operators` in a tight loop, see [`generate_wat`].
+/// Single operators give the ground truth to validate the costs fitted by
+/// [`CostModel::fit`] from real contracts with, and pairs of operators show whether
+/// their costs add up.
+///
+/// The loop is benchmarked once with the operators and once empty. Their difference
+/// is the cost of the repetitions, since both include the loop counter and the
+/// instrumentation.
+pub fn run_microbench<C: Clock>(
+    clock: C,
+    operators: &[OperatorSymbol],
+    options: MicroBenchOptions,
+) -> Result<MicroBenchResult, MicroBenchError> {
+    let (wat, repetition) = generate_wat(operators, options.unroll)?;
+    let (empty_wat, _) = generate_wat(&[], 0)?;
+
+    let cost = measure_loop(clock.clone(), &wat, options)?;
+    let empty_cost = measure_loop(clock, &empty_wat, options)?;
+    Ok(MicroBenchResult {
+        operators: operators.to_vec(),
+        repetition,
+        unit: C::UNIT,
+        cost: (cost - empty_cost) / f64::from(options.unroll.max(1)),
+    })
+}
+
+/// Runs [`run_microbench`] for every single operator that a micro-benchmark can be
+/// generated for, in the order of `symbols`. Unsupported operators are skipped.
+pub fn run_microbenches<C: Clock>(
+    clock: C,
+    symbols: &[OperatorSymbol],
+    options: MicroBenchOptions,
+) -> Result<Vec<MicroBenchResult>, MicroBenchError> {
+    let mut results = Vec::new();
+    for symbol in symbols {
+        match run_microbench(clock.clone(), &[*symbol], options) {
+            Ok(result) => results.push(result),
+            Err(MicroBenchError::Unsupported { .. }) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(results)
+}
+
+/// The median cost of one execution of the loop body of `wat`
+fn measure_loop<C: Clock>(
+    clock: C,
+    wat: &str,
+    options: MicroBenchOptions,
+) -> Result<f64, MicroBenchError> {
+    let compile_err = |err: &dyn ToString| MicroBenchError::CompileErr {
+        msg: err.to_string(),
+    };
+    let block_store = Arc::new(Mutex::new(BlockStore::new()));
+    let profiling = Arc::new(Profiling::new(block_store, Granularity::BasicBlock));
+    let wasm = wasmer::wat2wasm(wat.as_bytes()).map_err(|e| compile_err(&e))?;
+    let wasm = instrument_wasm(&wasm, &profiling).map_err(|e| compile_err(&e))?;
+    let middleware: Arc<dyn ModuleMiddleware> = profiling.clone();
+    let module =
+        cosmwasm_vm::internals::compile(&wasm, None, &[middleware]).map_err(|e| compile_err(&e))?;
+
+    let measurements = Arc::new(Mutex::new(Measurements::with_clock(clock)));
+    let mut exports = Exports::new();
+    add_measuring_imports(
+        &profiling,
+        module.store(),
+        measurements.clone(),
+        &mut exports,
+    );
+    let mut imports = ImportObject::new();
+    imports.register(profiling.import_module(), exports);
+    let instance = Instance::new(&module, &imports).map_err(|e| compile_err(&e))?;
+    wasmer_middlewares::metering::set_remaining_points(&instance, GAS_LIMIT);
+
+    let args = [Val::I32(options.loop_iterations.max(1) as i32)];
+    let result = bench(&instance, &measurements, "run", &args, options.bench)?;
+    loop_body_cost(&result, options.loop_iterations.max(1))
+}
+
+/// The loop body is the only block executed once per loop iteration
+fn loop_body_cost(result: &BenchResult, loop_iterations: u32) -> Result<f64, MicroBenchError> {
+    result
+        .blocks
+        .iter()
+        .find(|(block_id, _)| {
+            result.executions_per_iteration(**block_id) == f64::from(loop_iterations)
+        })
+        .map(|(_, block)| block.summary().median)
+        .ok_or(MicroBenchError::LoopNotMeasured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::WallClock;
+    use crate::cost_model::Estimate;
+
+    const OPTIONS: MicroBenchOptions = MicroBenchOptions {
+        unroll: 4,
+        loop_iterations: 10,
+        bench: BenchOptions {
+            warmup: 1,
+            iterations: 3,
+        },
+    };
+
+    #[test]
+    fn generate_wat_works() {
+        let (wat, repetition) = generate_wat(&[OperatorSymbol::I64DivU], 2).unwrap();
+        assert_eq!(wat.matches("i64.div_u").count(), 2);
+        assert_eq!(
+            repetition,
+            [
+                OperatorSymbol::I64Const,
+                OperatorSymbol::I64Const,
+                OperatorSymbol::I64DivU,
+                OperatorSymbol::Drop,
+            ]
+        );
+        wasmer::wat2wasm(wat.as_bytes()).unwrap();
+
+        // Pairs are independent of each other
+        let (wat, repetition) =
+            generate_wat(&[OperatorSymbol::I32Load8U, OperatorSymbol::I64Store], 1).unwrap();
+        assert!(wat.contains("i32.const 16\n      i32.load8_u\n      drop"));
+        assert!(wat.contains("i32.const 16\n      i64.const 3\n      i64.store\n"));
+        assert_eq!(repetition.len(), 6);
+        wasmer::wat2wasm(wat.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn generate_wat_works_for_all_supported_operators() {
+        let symbols = [
+            OperatorSymbol::Drop,
+            OperatorSymbol::Select,
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::LocalSet,
+            OperatorSymbol::LocalTee,
+            OperatorSymbol::GlobalGet,
+            OperatorSymbol::GlobalSet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I64Eqz,
+            OperatorSymbol::I32Popcnt,
+            OperatorSymbol::I64Extend32S,
+            OperatorSymbol::I32RemS,
+            OperatorSymbol::I64Rotr,
+            OperatorSymbol::I32GeU,
+            OperatorSymbol::I32WrapI64,
+            OperatorSymbol::I64ExtendI32U,
+            OperatorSymbol::I64Load32S,
+            OperatorSymbol::I32Store16,
+        ];
+        let (wat, repetition) = generate_wat(&symbols, 1).unwrap();
+        wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        for symbol in &symbols {
+            assert!(repetition.contains(symbol));
+        }
+    }
+
+    #[test]
+    fn generate_wat_fails_for_unsupported_operators() {
+        for symbol in [
+            OperatorSymbol::F64Add,
+            OperatorSymbol::Br,
+            OperatorSymbol::Call,
+            OperatorSymbol::I32AtomicLoad,
+            OperatorSymbol::I32TruncF32S,
+        ] {
+            match generate_wat(&[symbol], 1).unwrap_err() {
+                MicroBenchError::Unsupported { symbol: s } => assert_eq!(s, symbol),
+                err => panic!("Unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn run_microbench_works() {
+        let result = run_microbench(WallClock, &[OperatorSymbol::I32Add], OPTIONS).unwrap();
+        assert_eq!(result.operators, [OperatorSymbol::I32Add]);
+        assert_eq!(result.repetition.len(), 4);
+        assert_eq!(result.unit, "ns");
+        assert!(result.cost.is_finite());
+
+        let pair = [OperatorSymbol::I64Mul, OperatorSymbol::I32Eqz];
+        let result = run_microbench(WallClock, &pair, OPTIONS).unwrap();
+        assert_eq!(result.operators, pair);
+    }
+
+    #[test]
+    fn run_microbenches_skips_unsupported_operators() {
+        let symbols = [OperatorSymbol::I32Xor, OperatorSymbol::F32Add];
+        let results = run_microbenches(WallClock, &symbols, OPTIONS).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].operators, [OperatorSymbol::I32Xor]);
+    }
+
+    #[test]
+    fn predicted_works() {
+        let result = MicroBenchResult {
+            operators: vec![OperatorSymbol::I32Eqz],
+            repetition: vec![
+                OperatorSymbol::I32Const,
+                OperatorSymbol::I32Eqz,
+                OperatorSymbol::Drop,
+            ],
+            unit: "ns",
+            cost: 4.0,
+        };
+        let estimate = |cost| Estimate {
+            cost,
+            std_error: 0.1,
+        };
+        let model = CostModel {
+            overhead: estimate(20.0),
+            operators: vec![
+                (OperatorSymbol::I32Const, estimate(1.0)),
+                (OperatorSymbol::I32Eqz, estimate(2.0)),
+            ]
+            .into_iter()
+            .collect(),
+            observations: 100,
+            residual_stddev: 0.5,
+        };
+        // Drop was not fitted
+        assert_eq!(result.predicted(&model), 3.0);
+        assert_eq!(result.relative_error(&model), -0.25);
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use wasmer::wasmparser::Operator;
+use wasmer::CompilerConfig;
+use wasmer_middlewares::Metering;
+
+use crate::instrumentation::{Profiling, GAS_LIMIT};
+
+/// The name of the global the metering middleware exports the remaining points in.
+/// [`Profiling`] checks for it to detect that it was pushed after the metering
+/// middleware.
+pub const METERING_REMAINING_POINTS: &str = "wasmer_metering_remaining_points";
+
+/// The cost of every operator in `cosmwasm-vm`, see `GAS.md`.
+pub const FLAT_OPERATOR_COST: u64 = 150_000;
+
+/// Charges [`FLAT_OPERATOR_COST`] for every operator like `cosmwasm-vm` does.
+pub fn flat_cost(_operator: &Operator) -> u64 {
+    FLAT_OPERATOR_COST
+}
+
+/// How [`push_profiling_middlewares`] configures the metering middleware.
+#[derive(Debug, Clone, Copy)]
+pub struct MiddlewareOptions {
+    /// The points instances start with. They can be changed with
+    /// `wasmer_middlewares::metering::set_remaining_points`.
+    pub gas_limit: u64,
+    /// The cost of every operator
+    pub cost: fn(&Operator) -> u64,
+}
+
+impl Default for MiddlewareOptions {
+    /// The flat cost of `cosmwasm-vm` and a limit high enough not to interfere with
+    /// profiling.
+    fn default() -> Self {
+        MiddlewareOptions {
+            gas_limit: GAS_LIMIT,
+            cost: flat_cost,
+        }
+    }
+}
+
+/// Pushes `profiling` followed by a metering middleware to `config`.
+///
+/// Middlewares see the operators emitted by the ones pushed before them, so the order
+/// matters:
+///
+/// - `Profiling` first makes metering charge for the instrumentation like for any other
+///   code. Since metering charges before every call, the calls to the profiling imports
+///   align the charges with the measured blocks, which is what
+///   [`Profiling::with_gas_tracking`] relies on.
+/// - Metering first would make `Profiling` register the operators injected for metering
+///   as part of the code blocks and split blocks at its branches, so that block ids no
+///   longer match the contract code.
+///
+/// This is the order of `cosmwasm-vm`, which profiles with the same middleware order
+/// through [`VmProfiling`](crate::vm::VmProfiling). [`Profiling`] panics when it is
+/// compiled after a metering middleware.
+pub fn push_profiling_middlewares(
+    config: &mut dyn CompilerConfig,
+    profiling: Arc<Profiling>,
+    options: MiddlewareOptions,
+) {
+    config.push_middleware(profiling);
+    config.push_middleware(Arc::new(Metering::new(options.gas_limit, options.cost)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use wasmer::{wat2wasm, Cranelift, Exports, ImportObject, Instance, Module, Store, Universal};
+    use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
+
+    use crate::code_blocks::BlockStore;
+    use crate::instrumentation::{add_measuring_imports, instrument_wasm, Granularity};
+    use crate::measure::Measurements;
+
+    const WAT: &str = r#"(module
+        (func (export "add") (param i32 i32) (result i32)
+            local.get 0
+            local.get 1
+            i32.add))"#;
+
+    #[test]
+    fn push_profiling_middlewares_works() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store.clone(), Granularity::BasicBlock));
+        let wasm = instrument_wasm(&wat2wasm(WAT.as_bytes()).unwrap(), &profiling).unwrap();
+
+        let mut config = Cranelift::default();
+        let options = MiddlewareOptions {
+            gas_limit: 1_000,
+            cost: |_| 1,
+        };
+        push_profiling_middlewares(&mut config, profiling.clone(), options);
+        let store = Store::new(&Universal::new(config).engine());
+        let module = Module::new(&store, &wasm).unwrap();
+
+        let measurements = Arc::new(Mutex::new(Measurements::new()));
+        let mut exports = Exports::new();
+        add_measuring_imports(&profiling, &store, measurements.clone(), &mut exports);
+        let mut imports = ImportObject::new();
+        imports.register(profiling.import_module(), exports);
+        let instance = Instance::new(&module, &imports).unwrap();
+        let add = instance.exports.get_function("add").unwrap();
+        assert_eq!(add.call(&[1.into(), 2.into()]).unwrap()[0], 3.into());
+
+        let measurements = measurements.lock().unwrap();
+        assert_eq!(
+            measurements.taken.values().map(|t| t.len()).sum::<usize>(),
+            1
+        );
+        assert_eq!(block_store.lock().unwrap().len(), 1);
+        // The three operators of the function and the ones calling the imports
+        match get_remaining_points(&instance) {
+            MeteringPoints::Remaining(points) => assert!(points < 1_000 - 3),
+            MeteringPoints::Exhausted => panic!("Unexpectedly exhausted"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "must be pushed before the metering middleware")]
+    fn profiling_after_metering_panics() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store, Granularity::BasicBlock));
+        let wasm = instrument_wasm(&wat2wasm(WAT.as_bytes()).unwrap(), &profiling).unwrap();
+
+        let mut config = Cranelift::default();
+        config.push_middleware(Arc::new(Metering::new(1_000, flat_cost)));
+        config.push_middleware(profiling);
+        let store = Store::new(&Universal::new(config).engine());
+        let _ = Module::new(&store, &wasm);
+    }
+}
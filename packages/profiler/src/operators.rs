@@ -1,9 +1,13 @@
 use std::hash::Hash;
 
-use loupe::MemoryUsage;
-use wasmer::wasmparser::Operator;
+use serde::{Deserialize, Serialize};
+use wasmer::wasmparser::{MemoryImmediate, Operator};
 
-#[derive(Debug, Clone, Hash, PartialEq, MemoryUsage)]
+use crate::compat::MemoryUsage;
+
+#[derive(
+    Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, MemoryUsage, Serialize, Deserialize,
+)]
 pub enum OperatorSymbol {
     Unreachable,
     Nop,
@@ -1066,6 +1070,583 @@ impl From<&Operator<'_>> for OperatorSymbol {
 
 impl From<Operator<'_>> for OperatorSymbol {
     fn from(op: Operator<'_>) -> Self {
-        op.into()
+        (&op).into()
+    }
+}
+
+impl OperatorSymbol {
+    /// The name of the instruction in the WebAssembly text format, e.g. `i32.const` or
+    /// `br_if`.
+    pub fn mnemonic(&self) -> String {
+        const PREFIXES: [&str; 18] = [
+            "I8x16", "I16x8", "I32x4", "I64x2", "F32x4", "F64x2", "V128", "I32", "I64", "F32",
+            "F64", "Local", "Global", "Memory", "Table", "Ref", "Data", "Elem",
+        ];
+
+        match self {
+            OperatorSymbol::TypedSelect => return "select".to_string(),
+            OperatorSymbol::AtomicFence => return "atomic.fence".to_string(),
+            _ => {}
+        }
+
+        let name = format!("{:?}", self);
+        let prefix = PREFIXES.iter().find(|prefix| {
+            matches!(name.strip_prefix(*prefix), Some(rest) if rest.starts_with(char::is_uppercase))
+        });
+        match prefix {
+            Some(prefix) => {
+                let instruction = snake_case(&name[prefix.len()..]);
+                // Atomic instructions have more dots, e.g. `i32.atomic.rmw8.add_u`
+                let instruction = match instruction.strip_prefix("atomic_") {
+                    Some(rest) => match rest.find('_') {
+                        Some(end) if rest.starts_with("rmw") => {
+                            format!("atomic.{}.{}", &rest[..end], &rest[end + 1..])
+                        }
+                        _ => format!("atomic.{}", rest),
+                    },
+                    None => instruction,
+                };
+                format!("{}.{}", prefix.to_lowercase(), instruction)
+            }
+            None => snake_case(&name),
+        }
+    }
+
+    /// Whether the operator loads, stores, computes or converts floating-point values,
+    /// including SIMD lanes. Results of float operations are not deterministic across
+    /// platforms, which is why contracts must not use them.
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            OperatorSymbol::F32Load
+                | OperatorSymbol::F64Load
+                | OperatorSymbol::F32Store
+                | OperatorSymbol::F64Store
+                | OperatorSymbol::F32Const
+                | OperatorSymbol::F64Const
+                | OperatorSymbol::F32Eq
+                | OperatorSymbol::F32Ne
+                | OperatorSymbol::F32Lt
+                | OperatorSymbol::F32Gt
+                | OperatorSymbol::F32Le
+                | OperatorSymbol::F32Ge
+                | OperatorSymbol::F64Eq
+                | OperatorSymbol::F64Ne
+                | OperatorSymbol::F64Lt
+                | OperatorSymbol::F64Gt
+                | OperatorSymbol::F64Le
+                | OperatorSymbol::F64Ge
+                | OperatorSymbol::F32Abs
+                | OperatorSymbol::F32Neg
+                | OperatorSymbol::F32Ceil
+                | OperatorSymbol::F32Floor
+                | OperatorSymbol::F32Trunc
+                | OperatorSymbol::F32Nearest
+                | OperatorSymbol::F32Sqrt
+                | OperatorSymbol::F32Add
+                | OperatorSymbol::F32Sub
+                | OperatorSymbol::F32Mul
+                | OperatorSymbol::F32Div
+                | OperatorSymbol::F32Min
+                | OperatorSymbol::F32Max
+                | OperatorSymbol::F32Copysign
+                | OperatorSymbol::F64Abs
+                | OperatorSymbol::F64Neg
+                | OperatorSymbol::F64Ceil
+                | OperatorSymbol::F64Floor
+                | OperatorSymbol::F64Trunc
+                | OperatorSymbol::F64Nearest
+                | OperatorSymbol::F64Sqrt
+                | OperatorSymbol::F64Add
+                | OperatorSymbol::F64Sub
+                | OperatorSymbol::F64Mul
+                | OperatorSymbol::F64Div
+                | OperatorSymbol::F64Min
+                | OperatorSymbol::F64Max
+                | OperatorSymbol::F64Copysign
+                | OperatorSymbol::I32TruncF32S
+                | OperatorSymbol::I32TruncF32U
+                | OperatorSymbol::I32TruncF64S
+                | OperatorSymbol::I32TruncF64U
+                | OperatorSymbol::I64TruncF32S
+                | OperatorSymbol::I64TruncF32U
+                | OperatorSymbol::I64TruncF64S
+                | OperatorSymbol::I64TruncF64U
+                | OperatorSymbol::F32ConvertI32S
+                | OperatorSymbol::F32ConvertI32U
+                | OperatorSymbol::F32ConvertI64S
+                | OperatorSymbol::F32ConvertI64U
+                | OperatorSymbol::F32DemoteF64
+                | OperatorSymbol::F64ConvertI32S
+                | OperatorSymbol::F64ConvertI32U
+                | OperatorSymbol::F64ConvertI64S
+                | OperatorSymbol::F64ConvertI64U
+                | OperatorSymbol::F64PromoteF32
+                | OperatorSymbol::I32ReinterpretF32
+                | OperatorSymbol::I64ReinterpretF64
+                | OperatorSymbol::F32ReinterpretI32
+                | OperatorSymbol::F64ReinterpretI64
+                | OperatorSymbol::I32TruncSatF32S
+                | OperatorSymbol::I32TruncSatF32U
+                | OperatorSymbol::I32TruncSatF64S
+                | OperatorSymbol::I32TruncSatF64U
+                | OperatorSymbol::I64TruncSatF32S
+                | OperatorSymbol::I64TruncSatF32U
+                | OperatorSymbol::I64TruncSatF64S
+                | OperatorSymbol::I64TruncSatF64U
+                | OperatorSymbol::F32x4ExtractLane
+                | OperatorSymbol::F32x4ReplaceLane
+                | OperatorSymbol::F64x2ExtractLane
+                | OperatorSymbol::F64x2ReplaceLane
+                | OperatorSymbol::F32x4Splat
+                | OperatorSymbol::F64x2Splat
+                | OperatorSymbol::F32x4Eq
+                | OperatorSymbol::F32x4Ne
+                | OperatorSymbol::F32x4Lt
+                | OperatorSymbol::F32x4Gt
+                | OperatorSymbol::F32x4Le
+                | OperatorSymbol::F32x4Ge
+                | OperatorSymbol::F64x2Eq
+                | OperatorSymbol::F64x2Ne
+                | OperatorSymbol::F64x2Lt
+                | OperatorSymbol::F64x2Gt
+                | OperatorSymbol::F64x2Le
+                | OperatorSymbol::F64x2Ge
+                | OperatorSymbol::F32x4Ceil
+                | OperatorSymbol::F32x4Floor
+                | OperatorSymbol::F32x4Trunc
+                | OperatorSymbol::F32x4Nearest
+                | OperatorSymbol::F32x4Abs
+                | OperatorSymbol::F32x4Neg
+                | OperatorSymbol::F32x4Sqrt
+                | OperatorSymbol::F32x4Add
+                | OperatorSymbol::F32x4Sub
+                | OperatorSymbol::F32x4Mul
+                | OperatorSymbol::F32x4Div
+                | OperatorSymbol::F32x4Min
+                | OperatorSymbol::F32x4Max
+                | OperatorSymbol::F32x4PMin
+                | OperatorSymbol::F32x4PMax
+                | OperatorSymbol::F64x2Ceil
+                | OperatorSymbol::F64x2Floor
+                | OperatorSymbol::F64x2Trunc
+                | OperatorSymbol::F64x2Nearest
+                | OperatorSymbol::F64x2Abs
+                | OperatorSymbol::F64x2Neg
+                | OperatorSymbol::F64x2Sqrt
+                | OperatorSymbol::F64x2Add
+                | OperatorSymbol::F64x2Sub
+                | OperatorSymbol::F64x2Mul
+                | OperatorSymbol::F64x2Div
+                | OperatorSymbol::F64x2Min
+                | OperatorSymbol::F64x2Max
+                | OperatorSymbol::F64x2PMin
+                | OperatorSymbol::F64x2PMax
+                | OperatorSymbol::I32x4TruncSatF32x4S
+                | OperatorSymbol::I32x4TruncSatF32x4U
+                | OperatorSymbol::F32x4ConvertI32x4S
+                | OperatorSymbol::F32x4ConvertI32x4U
+                | OperatorSymbol::I32x4TruncSatF64x2SZero
+                | OperatorSymbol::I32x4TruncSatF64x2UZero
+                | OperatorSymbol::F64x2ConvertLowI32x4S
+                | OperatorSymbol::F64x2ConvertLowI32x4U
+                | OperatorSymbol::F32x4DemoteF64x2Zero
+                | OperatorSymbol::F64x2PromoteLowF32x4
+        )
+    }
+
+    /// The kind of work the operator does. Memory accesses include bulk memory and
+    /// atomic operators, arithmetic includes constants, comparisons, conversions and
+    /// SIMD lanes.
+    pub fn category(&self) -> OperatorCategory {
+        use OperatorSymbol::*;
+
+        match self {
+            Unreachable | Nop | Block | Loop | If | Else | Try | Catch | Throw | Rethrow
+            | Unwind | End | Br | BrIf | BrTable | Return | Call | CallIndirect | ReturnCall
+            | ReturnCallIndirect | Delegate | CatchAll => OperatorCategory::Control,
+            LocalGet | LocalSet | LocalTee | GlobalGet | GlobalSet => OperatorCategory::Variable,
+            _ => {
+                let mnemonic = self.mnemonic();
+                if mnemonic.contains(".load")
+                    || mnemonic.contains(".store")
+                    || mnemonic.contains("atomic.")
+                    || mnemonic.starts_with("memory.")
+                    || mnemonic.starts_with("data.")
+                {
+                    OperatorCategory::Memory
+                } else if mnemonic.starts_with("table.")
+                    || mnemonic.starts_with("elem.")
+                    || mnemonic.starts_with("ref.")
+                    || !mnemonic.contains('.')
+                {
+                    OperatorCategory::Other
+                } else {
+                    OperatorCategory::Arithmetic
+                }
+            }
+        }
+    }
+}
+
+/// What an operator does, see [`OperatorSymbol::category`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum OperatorCategory {
+    /// Accesses of the linear memory
+    Memory,
+    Arithmetic,
+    /// Branches, calls and the structure of the code
+    Control,
+    /// Accesses of locals and globals
+    Variable,
+    /// E.g. `drop`, `select` and table operators
+    Other,
+}
+
+/// The immediates of operators that [`CodeBlock`](crate::code_blocks::CodeBlock)s keep
+/// in addition to the operator symbols. All are off by default, since blocks that only
+/// differ in retained immediates are measured separately, which needs more samples.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, MemoryUsage)]
+pub struct RetainedImmediates {
+    /// The alignment of memory accesses
+    pub alignment: bool,
+    /// The magnitude of integer constants, see [`Immediate::Magnitude`]
+    pub const_magnitude: bool,
+}
+
+impl RetainedImmediates {
+    pub fn any(&self) -> bool {
+        self.alignment || self.const_magnitude
+    }
+
+    /// The retained immediate of `operator`, or [`Immediate::None`] if it has none.
+    pub fn immediate(&self, operator: &Operator) -> Immediate {
+        match operator {
+            Operator::I32Const { value } if self.const_magnitude => {
+                Immediate::magnitude(value.unsigned_abs() as u64)
+            }
+            Operator::I64Const { value } if self.const_magnitude => {
+                Immediate::magnitude(value.unsigned_abs())
+            }
+            _ if self.alignment => match memarg(operator) {
+                Some(memarg) => Immediate::Alignment(memarg.align),
+                None => Immediate::None,
+            },
+            _ => Immediate::None,
+        }
+    }
+}
+
+/// An immediate of an operator that can influence its cost, see [`RetainedImmediates`].
+#[derive(
+    Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, MemoryUsage, Serialize, Deserialize,
+)]
+pub enum Immediate {
+    None,
+    /// The alignment of a memory access as the exponent of a power of 2
+    Alignment(u8),
+    /// The number of bits needed for the absolute value of a constant. This buckets
+    /// constants by powers of 2, e.g. the lengths passed to `memory.copy`.
+    Magnitude(u8),
+}
+
+impl Immediate {
+    fn magnitude(value: u64) -> Self {
+        Immediate::Magnitude((u64::BITS - value.leading_zeros()) as u8)
+    }
+}
+
+/// Converts `CamelCase` to `snake_case`. Digits belong to the word before them.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// The memory immediate of loads and stores, including those of SIMD. Atomics are not
+/// supported by CosmWasm.
+pub(crate) fn memarg<'a>(operator: &'a Operator) -> Option<&'a MemoryImmediate> {
+    match operator {
+        Operator::I32Load { memarg }
+        | Operator::I64Load { memarg }
+        | Operator::F32Load { memarg }
+        | Operator::F64Load { memarg }
+        | Operator::I32Load8S { memarg }
+        | Operator::I32Load8U { memarg }
+        | Operator::I32Load16S { memarg }
+        | Operator::I32Load16U { memarg }
+        | Operator::I64Load8S { memarg }
+        | Operator::I64Load8U { memarg }
+        | Operator::I64Load16S { memarg }
+        | Operator::I64Load16U { memarg }
+        | Operator::I64Load32S { memarg }
+        | Operator::I64Load32U { memarg }
+        | Operator::I32Store { memarg }
+        | Operator::I64Store { memarg }
+        | Operator::F32Store { memarg }
+        | Operator::F64Store { memarg }
+        | Operator::I32Store8 { memarg }
+        | Operator::I32Store16 { memarg }
+        | Operator::I64Store8 { memarg }
+        | Operator::I64Store16 { memarg }
+        | Operator::I64Store32 { memarg }
+        | Operator::V128Load { memarg }
+        | Operator::V128Load8x8S { memarg }
+        | Operator::V128Load8x8U { memarg }
+        | Operator::V128Load16x4S { memarg }
+        | Operator::V128Load16x4U { memarg }
+        | Operator::V128Load32x2S { memarg }
+        | Operator::V128Load32x2U { memarg }
+        | Operator::V128Load8Splat { memarg }
+        | Operator::V128Load16Splat { memarg }
+        | Operator::V128Load32Splat { memarg }
+        | Operator::V128Load64Splat { memarg }
+        | Operator::V128Load32Zero { memarg }
+        | Operator::V128Load64Zero { memarg }
+        | Operator::V128Store { memarg }
+        | Operator::V128Load8Lane { memarg, .. }
+        | Operator::V128Load16Lane { memarg, .. }
+        | Operator::V128Load32Lane { memarg, .. }
+        | Operator::V128Load64Lane { memarg, .. }
+        | Operator::V128Store8Lane { memarg, .. }
+        | Operator::V128Store16Lane { memarg, .. }
+        | Operator::V128Store32Lane { memarg, .. }
+        | Operator::V128Store64Lane { memarg, .. } => Some(memarg),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::wasmparser::{Parser, Payload};
+
+    /// The symbols of the operators of all function bodies of `wat`
+    fn symbols(wat: &str) -> Vec<OperatorSymbol> {
+        let wasm = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        let mut symbols = Vec::new();
+        for payload in Parser::new(0).parse_all(&wasm) {
+            if let Payload::CodeSectionEntry(body) = payload.unwrap() {
+                let mut reader = body.get_operators_reader().unwrap();
+                while !reader.eof() {
+                    symbols.push(reader.read().unwrap().into());
+                }
+            }
+        }
+        symbols
+    }
+
+    #[test]
+    fn mnemonic_works() {
+        use OperatorSymbol::*;
+
+        let cases = [
+            (I32Const, "i32.const"),
+            (LocalGet, "local.get"),
+            (BrIf, "br_if"),
+            (CallIndirect, "call_indirect"),
+            (I64Load32U, "i64.load32_u"),
+            (I32TruncSatF64U, "i32.trunc_sat_f64_u"),
+            (I64ExtendI32S, "i64.extend_i32_s"),
+            (MemoryGrow, "memory.grow"),
+            (RefIsNull, "ref.is_null"),
+            (I8x16ExtractLaneS, "i8x16.extract_lane_s"),
+            (V128Load8x8S, "v128.load8x8_s"),
+            (F32x4ConvertI32x4S, "f32x4.convert_i32x4_s"),
+            (I32AtomicRmw8AddU, "i32.atomic.rmw8.add_u"),
+            (I64AtomicLoad, "i64.atomic.load"),
+            (TypedSelect, "select"),
+            (Unreachable, "unreachable"),
+        ];
+        for (symbol, mnemonic) in cases {
+            assert_eq!(symbol.mnemonic(), mnemonic);
+        }
+    }
+
+    #[test]
+    fn category_works() {
+        use OperatorSymbol::*;
+
+        let cases = [
+            (I64Load32U, OperatorCategory::Memory),
+            (V128Store, OperatorCategory::Memory),
+            (MemoryGrow, OperatorCategory::Memory),
+            (MemoryCopy, OperatorCategory::Memory),
+            (I32AtomicRmw8AddU, OperatorCategory::Memory),
+            (AtomicFence, OperatorCategory::Memory),
+            (I32Const, OperatorCategory::Arithmetic),
+            (I64Mul, OperatorCategory::Arithmetic),
+            (F32x4Sqrt, OperatorCategory::Arithmetic),
+            (I64ExtendI32S, OperatorCategory::Arithmetic),
+            (BrIf, OperatorCategory::Control),
+            (Call, OperatorCategory::Control),
+            (End, OperatorCategory::Control),
+            (LocalTee, OperatorCategory::Variable),
+            (GlobalGet, OperatorCategory::Variable),
+            (Drop, OperatorCategory::Other),
+            (TypedSelect, OperatorCategory::Other),
+            (TableGet, OperatorCategory::Other),
+            (RefFunc, OperatorCategory::Other),
+        ];
+        for (symbol, category) in cases {
+            assert_eq!(symbol.category(), category, "{:?}", symbol);
+        }
+    }
+
+    #[test]
+    fn is_float_works() {
+        let symbols = symbols(
+            r#"(module
+              (func (param i32) (result i32)
+                local.get 0
+                f64.convert_i32_u
+                f64.const 2
+                f64.mul
+                i64.reinterpret_f64
+                i32.wrap_i64
+                v128.const i32x4 0 0 0 0
+                f32x4.sqrt
+                i32x4.extract_lane 0
+                i32.add))"#,
+        );
+        let floats: Vec<bool> = symbols.iter().map(OperatorSymbol::is_float).collect();
+        assert_eq!(
+            floats,
+            [false, true, true, true, true, false, false, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn bulk_memory_operators_have_symbols() {
+        let symbols = symbols(
+            r#"(module
+              (memory 1)
+              (data $d "abc")
+              (func
+                i32.const 0 i32.const 8 i32.const 16 memory.copy
+                i32.const 0 i32.const 0 i32.const 16 memory.fill
+                i32.const 0 i32.const 0 i32.const 3 memory.init $d
+                data.drop $d))"#,
+        );
+        use OperatorSymbol::*;
+        assert_eq!(
+            symbols,
+            [
+                I32Const, I32Const, I32Const, MemoryCopy, I32Const, I32Const, I32Const, MemoryFill,
+                I32Const, I32Const, I32Const, MemoryInit, DataDrop, End,
+            ]
+        );
+    }
+
+    #[test]
+    fn reference_types_operators_have_symbols() {
+        let symbols = symbols(
+            r#"(module
+              (table $t 1 funcref)
+              (elem declare func $f)
+              (func $f
+                ref.null func
+                ref.is_null
+                drop
+                ref.func $f
+                i32.const 1
+                table.grow $t
+                table.get $t
+                ref.null func
+                i32.const 0
+                select (result funcref)
+                drop))"#,
+        );
+        use OperatorSymbol::*;
+        assert_eq!(
+            symbols,
+            [
+                RefNull,
+                RefIsNull,
+                Drop,
+                RefFunc,
+                I32Const,
+                TableGrow,
+                TableGet,
+                RefNull,
+                I32Const,
+                TypedSelect,
+                Drop,
+                End,
+            ]
+        );
+    }
+
+    #[test]
+    fn simd_operators_have_symbols() {
+        let symbols = symbols(
+            r#"(module
+              (memory 1)
+              (func
+                i32.const 0
+                v128.load
+                v128.const i32x4 1 2 3 4
+                i32x4.add
+                i32.const 0
+                v128.load32_zero
+                i8x16.shuffle 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
+                i32.const 0
+                v128.load8_lane 3
+                i32x4.extract_lane 1
+                drop))"#,
+        );
+        use OperatorSymbol::*;
+        assert_eq!(
+            symbols,
+            [
+                I32Const,
+                V128Load,
+                V128Const,
+                I32x4Add,
+                I32Const,
+                V128Load32Zero,
+                I8x16Shuffle,
+                I32Const,
+                V128Load8Lane,
+                I32x4ExtractLane,
+                Drop,
+                End,
+            ]
+        );
+    }
+
+    #[test]
+    fn alignment_is_retained_for_simd() {
+        let immediates = RetainedImmediates {
+            alignment: true,
+            const_magnitude: false,
+        };
+        let memarg = MemoryImmediate {
+            align: 3,
+            offset: 0,
+            memory: 0,
+        };
+        assert_eq!(
+            immediates.immediate(&Operator::V128Load64Splat { memarg }),
+            Immediate::Alignment(3)
+        );
+        assert_eq!(
+            immediates.immediate(&Operator::V128Store16Lane { memarg, lane: 1 }),
+            Immediate::Alignment(3)
+        );
+        assert_eq!(
+            immediates.immediate(&Operator::I8x16Swizzle),
+            Immediate::None
+        );
     }
 }
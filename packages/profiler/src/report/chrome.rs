@@ -0,0 +1,295 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::measure::{MeasurementEvent, Metadata};
+use crate::symbols::Symbols;
+
+use super::exporter::{Aggregates, Exporter};
+
+/// Exports the measurements in the Chrome trace event format, which can be viewed in
+/// `chrome://tracing` or [Perfetto](https://ui.perfetto.dev).
+///
+/// Every measurement becomes a pair of begin and end events named after the function,
+/// with the block id as an argument of the end event. Calls to host functions become
+/// complete events named after the import.
+///
+/// Measurements only contain costs, not points in time, so the timeline is synthetic:
+/// it starts at 0 and only advances by the measured costs. Nesting is preserved, but
+/// code between measurements takes no time. Costs in nanoseconds are converted to the
+/// microseconds of the format, any other unit is used as is.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChromeTraceExporter;
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: u32,
+    tid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: Vec<TraceEvent>,
+    display_time_unit: &'static str,
+    other_data: &'a Metadata,
+}
+
+/// Builds the synthetic timeline, see [`ChromeTraceExporter`].
+struct Timeline<'a> {
+    symbols: &'a Symbols,
+    units_per_microsecond: f64,
+    now: u128,
+    /// The function index and start time of all open measurements
+    open: Vec<(u32, u128)>,
+    events: Vec<TraceEvent>,
+}
+
+impl<'a> Timeline<'a> {
+    fn event(&self, name: String, cat: &'static str, ph: &'static str, ts: u128) -> TraceEvent {
+        TraceEvent {
+            name,
+            cat,
+            ph,
+            ts: ts as f64 / self.units_per_microsecond,
+            dur: None,
+            pid: 1,
+            tid: 1,
+            args: None,
+        }
+    }
+
+    fn begin(&mut self, fn_index: u32) {
+        let event = self.event(
+            self.symbols.describe_function(fn_index),
+            "wasm",
+            "B",
+            self.now,
+        );
+        self.events.push(event);
+        self.open.push((fn_index, self.now));
+    }
+
+    /// Ends the innermost open measurement. A measurement ends after its cost, but
+    /// never before the measurements nested in it.
+    fn end(&mut self, cost: Option<u128>, args: Option<Value>) {
+        let (fn_index, start) = self.open.pop().unwrap();
+        if let Some(cost) = cost {
+            self.now = self.now.max(start + cost);
+        }
+        let mut event = self.event(
+            self.symbols.describe_function(fn_index),
+            "wasm",
+            "E",
+            self.now,
+        );
+        event.args = args;
+        self.events.push(event);
+    }
+
+    fn complete(&mut self, name: String, cat: &'static str, cost: u128, args: Option<Value>) {
+        let mut event = self.event(name, cat, "X", self.now);
+        event.dur = Some(cost as f64 / self.units_per_microsecond);
+        event.args = args;
+        self.events.push(event);
+        self.now += cost;
+    }
+
+    fn close_all(&mut self) {
+        while !self.open.is_empty() {
+            self.end(None, None);
+        }
+    }
+}
+
+impl Exporter for ChromeTraceExporter {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()> {
+        let (units_per_microsecond, display_time_unit) = match aggregates.unit {
+            "ns" => (1000.0, "ns"),
+            _ => (1.0, "ms"),
+        };
+        let mut timeline = Timeline {
+            symbols: aggregates.symbols,
+            units_per_microsecond,
+            now: 0,
+            open: Vec::new(),
+            events: Vec::new(),
+        };
+
+        for event in aggregates.events {
+            match *event {
+                MeasurementEvent::Start { fn_index } => timeline.begin(fn_index),
+                MeasurementEvent::Take {
+                    fn_index,
+                    block_id,
+                    cost,
+                } => {
+                    let args = Some(json!({ "block": block_id.as_u64() }));
+                    match timeline.open.iter().rposition(|(f, _)| *f == fn_index) {
+                        Some(pos) => {
+                            // Measurements that were never finished, e.g. due to a trap
+                            while timeline.open.len() > pos + 1 {
+                                timeline.end(None, None);
+                            }
+                            timeline.end(Some(cost), args);
+                        }
+                        None => {
+                            let name = timeline.symbols.describe_function(fn_index);
+                            timeline.complete(name, "wasm", cost, args);
+                        }
+                    }
+                }
+                MeasurementEvent::HostCall {
+                    fn_index,
+                    import_index,
+                    cost,
+                } => {
+                    let name = timeline.symbols.describe_import(import_index);
+                    let caller = timeline.symbols.describe_function(fn_index);
+                    timeline.complete(name, "host", cost, Some(json!({ "caller": caller })));
+                }
+                MeasurementEvent::IndirectCall { .. } => {}
+                MeasurementEvent::InvocationEnd => timeline.close_all(),
+            }
+        }
+        timeline.close_all();
+
+        let trace = Trace {
+            trace_events: timeline.events,
+            display_time_unit,
+            other_data: aggregates.metadata,
+        };
+        serde_json::to_writer(&mut *sink, &trace)?;
+        sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::BlockId;
+
+    fn export(events: &[MeasurementEvent], unit: &str) -> Value {
+        let symbols = Symbols::default();
+        let metadata: Metadata = vec![("msg".to_string(), "transfer".to_string())]
+            .into_iter()
+            .collect();
+        let aggregates = Aggregates {
+            unit,
+            events,
+            symbols: &symbols,
+            metadata: &metadata,
+        };
+        let mut json = Vec::new();
+        ChromeTraceExporter.export(&aggregates, &mut json).unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    /// Name, phase and timestamp of every trace event
+    fn timeline(trace: &Value) -> Vec<(String, String, f64)> {
+        trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap().to_string(),
+                    event["ph"].as_str().unwrap().to_string(),
+                    event["ts"].as_f64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn owned(events: &[(&str, &str, f64)]) -> Vec<(String, String, f64)> {
+        events
+            .iter()
+            .map(|(name, ph, ts)| (name.to_string(), ph.to_string(), *ts))
+            .collect()
+    }
+
+    #[test]
+    fn export_nests_measurements() {
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::HostCall {
+                fn_index: 1,
+                import_index: 0,
+                cost: 500,
+            },
+            MeasurementEvent::Take {
+                fn_index: 1,
+                block_id: BlockId(7),
+                cost: 2000,
+            },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(8),
+                cost: 5000,
+            },
+            MeasurementEvent::InvocationEnd,
+        ];
+        let trace = export(&events, "ns");
+        assert_eq!(
+            timeline(&trace),
+            owned(&[
+                ("fn 0", "B", 0.0),
+                ("fn 1", "B", 0.0),
+                ("import 0", "X", 0.0),
+                ("fn 1", "E", 2.0),
+                ("fn 0", "E", 5.0),
+            ])
+        );
+        assert_eq!(trace["traceEvents"][2]["dur"], 0.5);
+        assert_eq!(trace["traceEvents"][2]["cat"], "host");
+        assert_eq!(trace["traceEvents"][3]["args"]["block"], 7);
+        assert_eq!(trace["displayTimeUnit"], "ns");
+        assert_eq!(trace["otherData"]["msg"], "transfer");
+    }
+
+    #[test]
+    fn export_closes_unfinished_measurements() {
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(1),
+                cost: 10,
+            },
+            MeasurementEvent::Start { fn_index: 2 },
+            MeasurementEvent::InvocationEnd,
+            // Without a matching start
+            MeasurementEvent::Take {
+                fn_index: 3,
+                block_id: BlockId(2),
+                cost: 4,
+            },
+        ];
+        let trace = export(&events, "cycles");
+        assert_eq!(
+            timeline(&trace),
+            owned(&[
+                ("fn 0", "B", 0.0),
+                ("fn 1", "B", 0.0),
+                ("fn 1", "E", 0.0),
+                ("fn 0", "E", 10.0),
+                ("fn 2", "B", 10.0),
+                ("fn 2", "E", 10.0),
+                ("fn 3", "X", 10.0),
+            ])
+        );
+        assert_eq!(trace["traceEvents"][6]["dur"], 4.0);
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::{self, Write};
+
+use crate::clock::Clock;
+use crate::measure::{MeasurementEvent, Measurements, Metadata};
+use crate::symbols::Symbols;
+
+/// The results of a profiling run an [`Exporter`] works on.
+#[derive(Debug, Clone, Copy)]
+pub struct Aggregates<'a> {
+    /// The unit of all costs, see [`Clock::UNIT`]
+    pub unit: &'a str,
+    /// The ordered measurements, see
+    /// [`Measurements::with_event_recording`](crate::measure::Measurements::with_event_recording)
+    pub events: &'a [MeasurementEvent],
+    pub symbols: &'a Symbols,
+    pub metadata: &'a Metadata,
+}
+
+impl<'a> Aggregates<'a> {
+    /// Borrows the results collected by `measurements`. Without event recording,
+    /// `events` is empty.
+    pub fn new<C: Clock>(measurements: &'a Measurements<C>, symbols: &'a Symbols) -> Self {
+        Aggregates {
+            unit: C::UNIT,
+            events: measurements.events.as_deref().unwrap_or_default(),
+            symbols,
+            metadata: &measurements.metadata,
+        }
+    }
+}
+
+/// Writes the results of a profiling run in a format other tools understand.
+pub trait Exporter {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()>;
+}
@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use crate::measure::MeasurementEvent;
+use crate::symbols::Symbols;
+
+use super::exporter::{Aggregates, Exporter};
+
+/// The version of the Gecko profile format that is written. The Firefox Profiler
+/// upgrades older versions when loading a profile.
+const GECKO_PROFILE_VERSION: u32 = 24;
+
+/// The categories of frames and markers, indexes into `meta.categories`
+const WASM_CATEGORY: usize = 0;
+const HOST_CATEGORY: usize = 1;
+
+/// Exports the measurements in the Gecko profile format of Firefox, which can be
+/// opened in the [Firefox Profiler](https://profiler.firefox.com).
+///
+/// The timeline is synthetic like for [`ChromeTraceExporter`](super::ChromeTraceExporter).
+/// The reconstructed call stack is sampled whenever it changes, so the duration of
+/// a sample is the exclusive cost of the function on top. Calls to host functions are
+/// frames on top of the calling function and also `HostCall` markers named after the
+/// import. Costs in nanoseconds are converted to the milliseconds of the format, any
+/// other unit is used as is.
+///
+/// The metadata of the session is stored as `meta.cosmwasm`, which is kept in the
+/// file but not shown by the Firefox Profiler.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GeckoProfileExporter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Frame {
+    Function(u32),
+    Import(u32),
+}
+
+/// Builds the tables of the profile's only thread.
+struct Thread<'a> {
+    symbols: &'a Symbols,
+    units_per_millisecond: f64,
+    now: u128,
+    /// The function index and start time of all open measurements
+    open: Vec<(u32, u128)>,
+    strings: Vec<String>,
+    string_indexes: HashMap<String, usize>,
+    /// The location string and category of every frame
+    frames: Vec<(usize, usize)>,
+    frame_indexes: HashMap<Frame, usize>,
+    /// The prefix and frame of every stack
+    stacks: Vec<(Option<usize>, usize)>,
+    stack_indexes: HashMap<(Option<usize>, usize), usize>,
+    /// The stack and time of every sample
+    samples: Vec<(Option<usize>, u128)>,
+    markers: Vec<Value>,
+}
+
+impl<'a> Thread<'a> {
+    fn new(symbols: &'a Symbols, units_per_millisecond: f64) -> Self {
+        Thread {
+            symbols,
+            units_per_millisecond,
+            now: 0,
+            open: Vec::new(),
+            strings: Vec::new(),
+            string_indexes: HashMap::new(),
+            frames: Vec::new(),
+            frame_indexes: HashMap::new(),
+            stacks: Vec::new(),
+            stack_indexes: HashMap::new(),
+            samples: Vec::new(),
+            markers: Vec::new(),
+        }
+    }
+
+    fn millis(&self, time: u128) -> f64 {
+        time as f64 / self.units_per_millisecond
+    }
+
+    fn string(&mut self, string: String) -> usize {
+        if let Some(index) = self.string_indexes.get(&string) {
+            return *index;
+        }
+        let index = self.strings.len();
+        self.strings.push(string.clone());
+        self.string_indexes.insert(string, index);
+        index
+    }
+
+    fn frame(&mut self, frame: Frame) -> usize {
+        if let Some(index) = self.frame_indexes.get(&frame) {
+            return *index;
+        }
+        let (name, category) = match frame {
+            Frame::Function(fn_index) => (self.symbols.describe_function(fn_index), WASM_CATEGORY),
+            Frame::Import(import_index) => {
+                (self.symbols.describe_import(import_index), HOST_CATEGORY)
+            }
+        };
+        let location = self.string(name);
+        let index = self.frames.len();
+        self.frames.push((location, category));
+        self.frame_indexes.insert(frame, index);
+        index
+    }
+
+    fn stack(&mut self, frames: &[Frame]) -> Option<usize> {
+        let mut prefix = None;
+        for frame in frames {
+            let key = (prefix, self.frame(*frame));
+            let index = match self.stack_indexes.get(&key) {
+                Some(index) => *index,
+                None => {
+                    let index = self.stacks.len();
+                    self.stacks.push(key);
+                    self.stack_indexes.insert(key, index);
+                    index
+                }
+            };
+            prefix = Some(index);
+        }
+        prefix
+    }
+
+    fn open_frames(&self) -> Vec<Frame> {
+        self.open
+            .iter()
+            .map(|(fn_index, _)| Frame::Function(*fn_index))
+            .collect()
+    }
+
+    /// Samples `frames` at the current time. A sample at the same time as the
+    /// previous one replaces it, since the previous stack took no time.
+    fn sample(&mut self, frames: &[Frame]) {
+        let stack = self.stack(frames);
+        if let Some(last) = self.samples.last_mut() {
+            if last.1 == self.now {
+                *last = (stack, self.now);
+                return;
+            }
+            if last.0 == stack {
+                return;
+            }
+        }
+        self.samples.push((stack, self.now));
+    }
+
+    fn sample_open(&mut self) {
+        let frames = self.open_frames();
+        self.sample(&frames);
+    }
+
+    fn begin(&mut self, fn_index: u32) {
+        self.open.push((fn_index, self.now));
+        self.sample_open();
+    }
+
+    /// Ends the innermost open measurement. A measurement ends after its cost, but
+    /// never before the measurements nested in it.
+    fn end(&mut self, cost: Option<u128>) {
+        let (_, start) = self.open.pop().unwrap();
+        if let Some(cost) = cost {
+            self.now = self.now.max(start + cost);
+        }
+        self.sample_open();
+    }
+
+    /// Runs `top` on top of the open measurements for `cost`.
+    fn complete(&mut self, top: &[Frame], cost: u128) {
+        let mut frames = self.open_frames();
+        frames.extend_from_slice(top);
+        self.sample(&frames);
+        self.now += cost;
+        self.sample_open();
+    }
+
+    fn take(&mut self, fn_index: u32, cost: u128) {
+        match self.open.iter().rposition(|(f, _)| *f == fn_index) {
+            Some(pos) => {
+                // Measurements that were never finished, e.g. due to a trap
+                while self.open.len() > pos + 1 {
+                    self.end(None);
+                }
+                self.end(Some(cost));
+            }
+            None => self.complete(&[Frame::Function(fn_index)], cost),
+        }
+    }
+
+    fn host_call(&mut self, fn_index: u32, import_index: u32, cost: u128) {
+        let mut top = Vec::new();
+        if self.open.last().map(|(f, _)| *f) != Some(fn_index) {
+            top.push(Frame::Function(fn_index));
+        }
+        top.push(Frame::Import(import_index));
+
+        let name = self.string(self.symbols.describe_import(import_index));
+        let caller = self.symbols.describe_function(fn_index);
+        // name, startTime, endTime, phase (interval), category, data
+        self.markers.push(json!([
+            name,
+            self.millis(self.now),
+            self.millis(self.now + cost),
+            1,
+            HOST_CATEGORY,
+            { "type": "HostCall", "caller": caller },
+        ]));
+        self.complete(&top, cost);
+    }
+
+    fn close_all(&mut self) {
+        while !self.open.is_empty() {
+            self.end(None);
+        }
+    }
+
+    fn into_json(self) -> Value {
+        let samples: Vec<Value> = self
+            .samples
+            .iter()
+            .map(|(stack, time)| json!([stack, self.millis(*time), 0]))
+            .collect();
+        let frames: Vec<Value> = self
+            .frames
+            .iter()
+            .map(|(location, category)| {
+                json!([location, false, 0, null, null, null, null, category, 0])
+            })
+            .collect();
+        let stacks: Vec<Value> = self
+            .stacks
+            .iter()
+            .map(|(prefix, frame)| json!([prefix, frame]))
+            .collect();
+        json!({
+            "name": "GeckoMain",
+            "processType": "default",
+            "pid": 1,
+            "tid": 1,
+            "registerTime": 0,
+            "unregisterTime": null,
+            "samples": {
+                "schema": { "stack": 0, "time": 1, "eventDelay": 2 },
+                "data": samples,
+            },
+            "markers": {
+                "schema": { "name": 0, "startTime": 1, "endTime": 2, "phase": 3, "category": 4, "data": 5 },
+                "data": self.markers,
+            },
+            "frameTable": {
+                "schema": {
+                    "location": 0,
+                    "relevantForJS": 1,
+                    "innerWindowID": 2,
+                    "implementation": 3,
+                    "optimizations": 4,
+                    "line": 5,
+                    "column": 6,
+                    "category": 7,
+                    "subcategory": 8,
+                },
+                "data": frames,
+            },
+            "stackTable": {
+                "schema": { "prefix": 0, "frame": 1 },
+                "data": stacks,
+            },
+            "stringTable": self.strings,
+        })
+    }
+}
+
+impl Exporter for GeckoProfileExporter {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()> {
+        let units_per_millisecond = match aggregates.unit {
+            "ns" => 1_000_000.0,
+            _ => 1.0,
+        };
+        let mut thread = Thread::new(aggregates.symbols, units_per_millisecond);
+
+        for event in aggregates.events {
+            match *event {
+                MeasurementEvent::Start { fn_index } => thread.begin(fn_index),
+                MeasurementEvent::Take { fn_index, cost, .. } => thread.take(fn_index, cost),
+                MeasurementEvent::HostCall {
+                    fn_index,
+                    import_index,
+                    cost,
+                } => thread.host_call(fn_index, import_index, cost),
+                MeasurementEvent::IndirectCall { .. } => {}
+                MeasurementEvent::InvocationEnd => thread.close_all(),
+            }
+        }
+        thread.close_all();
+
+        let meta = json!({
+            "version": GECKO_PROFILE_VERSION,
+            "interval": 1.0 / units_per_millisecond,
+            "startTime": 0,
+            "shutdownTime": null,
+            "processType": 0,
+            "product": "cosmwasm-profiler",
+            "stackwalk": 0,
+            "debug": 0,
+            "gcpoison": 0,
+            "asyncstack": 0,
+            "categories": [
+                { "name": "Wasm", "color": "blue", "subcategories": ["Other"] },
+                { "name": "Host", "color": "orange", "subcategories": ["Other"] },
+            ],
+            "markerSchema": [{
+                "name": "HostCall",
+                "display": ["marker-chart", "marker-table", "timeline-overview"],
+                "data": [{ "key": "caller", "label": "Caller", "format": "string" }],
+            }],
+            "cosmwasm": aggregates.metadata,
+        });
+        let profile = json!({
+            "meta": meta,
+            "libs": [],
+            "threads": [thread.into_json()],
+            "processes": [],
+            "pausedRanges": [],
+        });
+        serde_json::to_writer(&mut *sink, &profile)?;
+        sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::BlockId;
+    use crate::measure::Metadata;
+
+    fn export(events: &[MeasurementEvent], unit: &str) -> Value {
+        let symbols = Symbols::default();
+        let metadata: Metadata = vec![("msg".to_string(), "transfer".to_string())]
+            .into_iter()
+            .collect();
+        let aggregates = Aggregates {
+            unit,
+            events,
+            symbols: &symbols,
+            metadata: &metadata,
+        };
+        let mut json = Vec::new();
+        GeckoProfileExporter.export(&aggregates, &mut json).unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    /// The frame names of the stack of every sample, outermost first, and its time
+    fn samples(profile: &Value) -> Vec<(Vec<String>, f64)> {
+        let thread = &profile["threads"][0];
+        let strings = thread["stringTable"].as_array().unwrap();
+        let frames = thread["frameTable"]["data"].as_array().unwrap();
+        let stacks = thread["stackTable"]["data"].as_array().unwrap();
+        thread["samples"]["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|sample| {
+                let mut names = Vec::new();
+                let mut stack = sample[0].as_u64();
+                while let Some(index) = stack {
+                    let frame = &frames[stacks[index as usize][1].as_u64().unwrap() as usize];
+                    let location = frame[0].as_u64().unwrap() as usize;
+                    names.insert(0, strings[location].as_str().unwrap().to_string());
+                    stack = stacks[index as usize][0].as_u64();
+                }
+                (names, sample[1].as_f64().unwrap())
+            })
+            .collect()
+    }
+
+    fn owned(samples: &[(&[&str], f64)]) -> Vec<(Vec<String>, f64)> {
+        samples
+            .iter()
+            .map(|(names, time)| (names.iter().map(|n| n.to_string()).collect(), *time))
+            .collect()
+    }
+
+    #[test]
+    fn export_samples_stacks() {
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::HostCall {
+                fn_index: 1,
+                import_index: 0,
+                cost: 500_000,
+            },
+            MeasurementEvent::Take {
+                fn_index: 1,
+                block_id: BlockId(7),
+                cost: 2_000_000,
+            },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(8),
+                cost: 5_000_000,
+            },
+            MeasurementEvent::InvocationEnd,
+        ];
+        let profile = export(&events, "ns");
+        assert_eq!(
+            samples(&profile),
+            owned(&[
+                (&["fn 0", "fn 1", "import 0"], 0.0),
+                (&["fn 0", "fn 1"], 0.5),
+                (&["fn 0"], 2.0),
+                (&[], 5.0),
+            ])
+        );
+
+        let markers = &profile["threads"][0]["markers"]["data"];
+        assert_eq!(markers.as_array().unwrap().len(), 1);
+        let name = markers[0][0].as_u64().unwrap() as usize;
+        assert_eq!(profile["threads"][0]["stringTable"][name], "import 0");
+        assert_eq!(markers[0][1], 0.0);
+        assert_eq!(markers[0][2], 0.5);
+        assert_eq!(markers[0][5]["type"], "HostCall");
+        assert_eq!(markers[0][5]["caller"], "fn 1");
+
+        // Imports are in the host category
+        let frames = &profile["threads"][0]["frameTable"]["data"];
+        let categories: Vec<u64> = frames
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| frame[7].as_u64().unwrap())
+            .collect();
+        assert_eq!(categories, [0, 0, 1]);
+
+        assert_eq!(profile["meta"]["version"], GECKO_PROFILE_VERSION);
+        assert_eq!(profile["meta"]["cosmwasm"]["msg"], "transfer");
+    }
+
+    #[test]
+    fn export_closes_unfinished_measurements() {
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(1),
+                cost: 10,
+            },
+            MeasurementEvent::Start { fn_index: 2 },
+            MeasurementEvent::InvocationEnd,
+            // Without a matching start
+            MeasurementEvent::Take {
+                fn_index: 3,
+                block_id: BlockId(2),
+                cost: 4,
+            },
+        ];
+        let profile = export(&events, "cycles");
+        assert_eq!(
+            samples(&profile),
+            owned(&[(&["fn 0"], 0.0), (&["fn 3"], 10.0), (&[], 14.0),])
+        );
+        assert_eq!(profile["meta"]["interval"], 1.0);
+    }
+}
@@ -0,0 +1,438 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::callgraph::CallGraph;
+use crate::code_blocks::{BlockId, BlockStore};
+use crate::instrumentation::Granularity;
+use crate::measure::MeasurementEvent;
+use crate::operators::OperatorSymbol;
+
+use super::exporter::{Aggregates, Exporter};
+
+/// Exports the measurements as a single self-contained HTML page for contract
+/// developers, with no external resources.
+///
+/// The page contains a table of all functions and a table of all measured blocks,
+/// which both can be sorted by clicking a column header, and a treemap of the
+/// exclusive cost of every function, divided into its blocks. With
+/// [`HtmlExporter::with_blocks`], it also contains a histogram of the executed
+/// operators.
+///
+/// Function costs are computed like in [`Report::from_events`](super::Report::from_events),
+/// so the granularity has to match the one the contract was instrumented with.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HtmlExporter<'a> {
+    granularity: Granularity,
+    blocks: Option<&'a BlockStore>,
+}
+
+/// The measurements of a block
+#[derive(Debug, Default, Clone, Copy)]
+struct BlockStats {
+    fn_index: u32,
+    executions: u64,
+    cost: u128,
+}
+
+/// The maximum number of blocks per function shown in the treemap. Cheaper blocks
+/// are merged into one area.
+const TREEMAP_BLOCKS: usize = 20;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { padding: 0.2em 0.8em; border-bottom: 1px solid #ddd; text-align: right; }
+th { cursor: pointer; background: #f4f4f4; user-select: none; }
+td:first-child, th:first-child { text-align: left; }
+.treemap { position: relative; width: 100%; height: 480px; margin-bottom: 2em; }
+.treemap div { position: absolute; box-sizing: border-box; overflow: hidden; font-size: 11px; }
+.function { border: 2px solid #fff; background: #4a78b5; color: #fff; }
+.block { border: 1px solid #fff; background: rgba(255, 255, 255, 0.15); padding: 2px; }
+.bar { background: #4a78b5; height: 0.9em; }
+"#;
+
+/// Sorts the rows of a table by the `data-value` of the clicked column, or by its
+/// text if there is none. Clicking the same column again reverses the order.
+const SCRIPT: &str = r#"
+document.querySelectorAll("table.sortable th").forEach(function (th) {
+  th.addEventListener("click", function () {
+    var body = th.closest("table").tBodies[0];
+    var column = th.cellIndex;
+    var descending = th.dataset.order !== "desc";
+    th.closest("tr").querySelectorAll("th").forEach(function (other) { delete other.dataset.order; });
+    th.dataset.order = descending ? "desc" : "asc";
+    var key = function (row) {
+      var cell = row.cells[column];
+      return cell.dataset.value !== undefined ? parseFloat(cell.dataset.value) : cell.textContent;
+    };
+    Array.from(body.rows)
+      .sort(function (a, b) {
+        var x = key(a), y = key(b);
+        var order = x < y ? -1 : x > y ? 1 : 0;
+        return descending ? -order : order;
+      })
+      .forEach(function (row) { body.appendChild(row); });
+  });
+});
+"#;
+
+impl<'a> HtmlExporter<'a> {
+    pub fn new(granularity: Granularity) -> Self {
+        HtmlExporter {
+            granularity,
+            blocks: None,
+        }
+    }
+
+    /// Adds the histogram of executed operators, using the code of the measured blocks
+    /// registered in `blocks`.
+    pub fn with_blocks(mut self, blocks: &'a BlockStore) -> Self {
+        self.blocks = Some(blocks);
+        self
+    }
+
+    fn functions(&self, html: &mut String, aggregates: &Aggregates, graph: &CallGraph) {
+        let unit = escape(aggregates.unit);
+        html.push_str("<h2>Functions</h2>\n<table class=\"sortable\">\n<thead><tr>");
+        let _ = write!(
+            html,
+            "<th>function</th><th>calls</th><th>inclusive ({0})</th><th>exclusive ({0})</th><th>host ({0})</th>",
+            unit
+        );
+        html.push_str("</tr></thead>\n<tbody>\n");
+        let mut functions: Vec<_> = graph.functions.iter().collect();
+        functions.sort_by_key(|(_, stats)| Reverse(stats.exclusive));
+        for (fn_index, stats) in functions {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td>{}{}{}{}</tr>",
+                escape(&aggregates.symbols.describe_function(*fn_index)),
+                number(stats.calls as u128),
+                number(stats.inclusive),
+                number(stats.exclusive),
+                number(stats.host),
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+
+    fn blocks(&self, html: &mut String, aggregates: &Aggregates, blocks: &[(BlockId, BlockStats)]) {
+        let unit = escape(aggregates.unit);
+        html.push_str("<h2>Blocks</h2>\n<table class=\"sortable\">\n<thead><tr>");
+        let _ = write!(
+            html,
+            "<th>block</th><th>function</th><th>executions</th><th>cost ({0})</th><th>mean cost ({0})</th>",
+            unit
+        );
+        if self.blocks.is_some() {
+            html.push_str("<th>operators</th>");
+        }
+        html.push_str("</tr></thead>\n<tbody>\n");
+        for (block_id, stats) in blocks {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td>{}{}{}",
+                block_id.as_u64(),
+                escape(&aggregates.symbols.describe_function(stats.fn_index)),
+                number(stats.executions as u128),
+                number(stats.cost),
+                number(stats.cost / stats.executions.max(1) as u128),
+            );
+            if let Some(store) = self.blocks {
+                let operators = store
+                    .get_block(*block_id)
+                    .map(|block| block.operators().len() as u128);
+                match operators {
+                    Some(operators) => html.push_str(&number(operators)),
+                    None => html.push_str("<td data-value=\"0\"></td>"),
+                }
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+
+    /// Lays out the functions from left to right and their blocks from top to bottom,
+    /// with areas proportional to their cost.
+    fn treemap(
+        &self,
+        html: &mut String,
+        aggregates: &Aggregates,
+        graph: &CallGraph,
+        blocks: &[(BlockId, BlockStats)],
+    ) {
+        html.push_str("<h2>Cost treemap</h2>\n<div class=\"treemap\">\n");
+        let mut functions: Vec<(u32, u128)> = graph
+            .functions
+            .iter()
+            .map(|(fn_index, stats)| (*fn_index, stats.exclusive))
+            .filter(|(_, exclusive)| *exclusive > 0)
+            .collect();
+        functions.sort_by_key(|(_, exclusive)| Reverse(*exclusive));
+        let total: u128 = functions.iter().map(|(_, exclusive)| exclusive).sum();
+
+        let mut left = 0.0;
+        for (fn_index, exclusive) in functions {
+            let width = exclusive as f64 * 100.0 / total as f64;
+            let name = escape(&aggregates.symbols.describe_function(fn_index));
+            let _ = writeln!(
+                html,
+                "<div class=\"function\" style=\"left: {:.3}%; top: 0; width: {:.3}%; height: 100%\" title=\"{} ({} {})\">",
+                left, width, name, exclusive, escape(aggregates.unit)
+            );
+
+            // `blocks` is sorted by cost
+            let own: Vec<(BlockId, u128)> = blocks
+                .iter()
+                .filter(|(_, stats)| stats.fn_index == fn_index && stats.cost > 0)
+                .map(|(block_id, stats)| (*block_id, stats.cost))
+                .collect();
+            let own_total: u128 = own.iter().map(|(_, cost)| cost).sum();
+            let mut top = 0.0;
+            for (index, (block_id, cost)) in own.iter().enumerate() {
+                let (label, cost) = if index + 1 == TREEMAP_BLOCKS && own.len() > TREEMAP_BLOCKS {
+                    let rest: u128 = own[index..].iter().map(|(_, cost)| cost).sum();
+                    (format!("{} other blocks", own.len() - index), rest)
+                } else {
+                    (format!("block {}", block_id.as_u64()), *cost)
+                };
+                let height = cost as f64 * 100.0 / own_total as f64;
+                let _ = writeln!(
+                    html,
+                    "<div class=\"block\" style=\"left: 0; top: {:.3}%; width: 100%; height: {:.3}%\" title=\"{}: {} in {}\">{}</div>",
+                    top, height, label, cost, name, label
+                );
+                top += height;
+                if index + 1 == TREEMAP_BLOCKS {
+                    break;
+                }
+            }
+            let _ = writeln!(
+                html,
+                "<div style=\"bottom: 0; padding: 2px\">{}</div>",
+                name
+            );
+            html.push_str("</div>\n");
+            left += width;
+        }
+        html.push_str("</div>\n");
+    }
+
+    /// How often every operator was executed, i.e. its count in a block times the
+    /// executions of the block
+    fn operators(&self, html: &mut String, blocks: &[(BlockId, BlockStats)]) {
+        let store = match self.blocks {
+            Some(store) => store,
+            None => return,
+        };
+        let mut executed: HashMap<OperatorSymbol, u128> = HashMap::new();
+        for (block_id, stats) in blocks {
+            if let Some(block) = store.get_block(*block_id) {
+                for (operator, count) in block.operator_counts() {
+                    *executed.entry(*operator).or_default() +=
+                        *count as u128 * stats.executions as u128;
+                }
+            }
+        }
+        let mut executed: Vec<(String, u128)> = executed
+            .into_iter()
+            .map(|(operator, count)| (operator.mnemonic(), count))
+            .collect();
+        executed.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let max = executed
+            .first()
+            .map(|(_, count)| *count)
+            .unwrap_or_default();
+
+        html.push_str("<h2>Executed operators</h2>\n<table class=\"sortable\">\n<thead><tr>");
+        html.push_str("<th>operator</th><th>executions</th><th></th></tr></thead>\n<tbody>\n");
+        for (operator, count) in executed {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td>{}<td style=\"width: 300px\"><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>",
+                operator,
+                number(count),
+                count as f64 * 100.0 / max as f64,
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+}
+
+impl<'a> Exporter for HtmlExporter<'a> {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()> {
+        let graph = CallGraph::from_events(aggregates.events, self.granularity);
+        let mut blocks: BTreeMap<BlockId, BlockStats> = BTreeMap::new();
+        for event in aggregates.events {
+            if let MeasurementEvent::Take {
+                fn_index,
+                block_id,
+                cost,
+            } = *event
+            {
+                let stats = blocks.entry(block_id).or_default();
+                stats.fn_index = fn_index;
+                stats.executions += 1;
+                stats.cost += cost;
+            }
+        }
+        let mut blocks: Vec<(BlockId, BlockStats)> = blocks.into_iter().collect();
+        blocks.sort_by_key(|(_, stats)| Reverse(stats.cost));
+
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str("<title>Contract profile</title>\n<style>");
+        html.push_str(STYLE);
+        html.push_str("</style>\n</head>\n<body>\n<h1>Contract profile</h1>\n");
+        if !aggregates.metadata.is_empty() {
+            html.push_str("<table>\n");
+            for (key, value) in aggregates.metadata {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    escape(key),
+                    escape(value)
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        self.functions(&mut html, aggregates, &graph);
+        self.treemap(&mut html, aggregates, &graph, &blocks);
+        self.blocks(&mut html, aggregates, &blocks);
+        self.operators(&mut html, &blocks);
+
+        html.push_str("<script>");
+        html.push_str(SCRIPT);
+        html.push_str("</script>\n</body>\n</html>\n");
+        sink.write_all(html.as_bytes())?;
+        sink.flush()
+    }
+}
+
+/// A table cell with a number, sortable by its value
+fn number(value: u128) -> String {
+    format!("<td data-value=\"{0}\">{0}</td>", value)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::code_blocks::CodeBlock;
+    use crate::measure::Metadata;
+    use crate::symbols::Symbols;
+
+    fn events() -> Vec<MeasurementEvent> {
+        vec![
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(7),
+                cost: 30,
+            },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(7),
+                cost: 50,
+            },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(8),
+                cost: 20,
+            },
+            MeasurementEvent::InvocationEnd,
+        ]
+    }
+
+    fn export(exporter: HtmlExporter, events: &[MeasurementEvent]) -> String {
+        let symbols = Symbols::default();
+        let metadata: Metadata = vec![("msg".to_string(), "<transfer>".to_string())]
+            .into_iter()
+            .collect();
+        let aggregates = Aggregates {
+            unit: "ns",
+            events,
+            symbols: &symbols,
+            metadata: &metadata,
+        };
+        let mut html = Vec::new();
+        exporter.export(&aggregates, &mut html).unwrap();
+        String::from_utf8(html).unwrap()
+    }
+
+    #[test]
+    fn export_works() {
+        let html = export(HtmlExporter::new(Granularity::BasicBlock), &events());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        // Self-contained
+        assert!(!html.contains("src="));
+        assert!(!html.contains("href="));
+        assert!(html.contains("<tr><td>msg</td><td>&lt;transfer&gt;</td></tr>"));
+
+        // Function table
+        assert!(html.contains(
+            "<tr><td>fn 0</td><td data-value=\"1\">1</td><td data-value=\"100\">100</td><td data-value=\"100\">100</td><td data-value=\"0\">0</td></tr>"
+        ));
+        // Block table, sorted by cost
+        let block7 = "<tr><td>7</td><td>fn 0</td><td data-value=\"2\">2</td><td data-value=\"80\">80</td><td data-value=\"40\">40</td></tr>";
+        let block8 = "<tr><td>8</td><td>fn 0</td>";
+        assert!(html.find(block7).unwrap() < html.find(block8).unwrap());
+        // Treemap
+        assert!(html.contains("width: 100.000%; height: 100%\" title=\"fn 0 (100 ns)\""));
+        assert!(html.contains(
+            "top: 80.000%; width: 100%; height: 20.000%\" title=\"block 8: 20 in fn 0\""
+        ));
+        // Operators need the code blocks
+        assert!(!html.contains("Executed operators"));
+    }
+
+    #[test]
+    fn export_with_blocks_adds_operators() {
+        let mut store = BlockStore::new();
+        let block = CodeBlock::from(vec![
+            OperatorSymbol::LocalGet,
+            OperatorSymbol::I32Const,
+            OperatorSymbol::I32Add,
+            OperatorSymbol::LocalGet,
+        ]);
+        store.register_block_with_id(BlockId(7), block).unwrap();
+
+        let exporter = HtmlExporter::new(Granularity::BasicBlock).with_blocks(&store);
+        let html = export(exporter, &events());
+        assert!(html.contains("Executed operators"));
+        // Block 7 was executed twice
+        assert!(html.contains(
+            "<tr><td>local.get</td><td data-value=\"4\">4</td><td style=\"width: 300px\"><div class=\"bar\" style=\"width: 100.0%\"></div></td></tr>"
+        ));
+        assert!(html.contains("<tr><td>i32.add</td><td data-value=\"2\">2</td>"));
+        // Operator counts of blocks, unknown ones are empty
+        assert!(html.contains("<td data-value=\"40\">40</td><td data-value=\"4\">4</td></tr>"));
+        assert!(html.contains("<td data-value=\"20\">20</td><td data-value=\"0\"></td></tr>"));
+    }
+
+    #[test]
+    fn escape_works() {
+        assert_eq!(
+            escape("<Vec<u8> as Clone>::clone & \"'"),
+            "&lt;Vec&lt;u8&gt; as Clone&gt;::clone &amp; &quot;&#39;"
+        );
+    }
+}
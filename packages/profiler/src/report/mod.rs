@@ -0,0 +1,715 @@
+mod chrome;
+mod exporter;
+mod gecko;
+mod html;
+mod pprof;
+mod summary;
+
+pub use chrome::ChromeTraceExporter;
+pub use exporter::{Aggregates, Exporter};
+pub use gecko::GeckoProfileExporter;
+pub use html::HtmlExporter;
+pub use pprof::PprofExporter;
+pub use summary::{CsvExporter, JsonExporter};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::anomalies::ClockAnomaly;
+use crate::callgraph::CallGraph;
+use crate::classification::Classification;
+use crate::clock::Clock;
+use crate::code_blocks::{BlockId, BlockStore, WasmOffsets};
+use crate::disassembly::Disassembly;
+use crate::instrumentation::Granularity;
+use crate::measure::{BranchStats, MeasurementEvent, Measurements, Metadata};
+use crate::symbols::Symbols;
+
+/// A summary of a profiling run per function that can be stored and compared
+/// against later runs.
+///
+/// Functions are identified by their local function index, so only reports of
+/// the same contract build are comparable with [`Report::check_against`]. Use
+/// [`ReportDiff`](crate::diff::ReportDiff) to compare different builds.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The unit of all costs, see [`Clock::UNIT`](crate::clock::Clock::UNIT)
+    pub unit: String,
+    pub functions: BTreeMap<u32, FunctionReport>,
+    /// The cost of every measured block, see [`Report::with_blocks`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub blocks: BTreeMap<BlockId, BlockReport>,
+    /// The blocks whose costs are unreliable, see [`Report::with_clock_anomalies`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub clock_anomalies: BTreeMap<BlockId, Vec<ClockAnomaly>>,
+    /// The instructions of the measured blocks, see [`Report::with_disassembly`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub disassembly: BTreeMap<BlockId, Vec<String>>,
+    /// Where the measured blocks are in the Wasm code section, see [`Report::with_offsets`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub offsets: BTreeMap<BlockId, WasmOffsets>,
+    /// The cost of every class of blocks, see [`Report::with_classes`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub classes: BTreeMap<String, ClassReport>,
+    /// The outcomes of the branches ending the measured blocks, see [`Report::with_branches`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub branches: BTreeMap<BlockId, BranchStats>,
+    /// The deepest nesting of calls in every entry point, keyed by function index, see
+    /// [`Report::with_call_depths`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_call_depths: BTreeMap<u32, u32>,
+    /// The metadata of the profiling session, see [`Report::with_metadata`]
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct FunctionReport {
+    /// The demangled name of the function, see [`Report::with_symbols`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub calls: u64,
+    /// Cost including callees
+    pub inclusive: u64,
+    /// Cost of the function's own code
+    pub exclusive: u64,
+    /// Number of measurements taken in the function, i.e. executed blocks
+    pub blocks: u64,
+    /// Cost of the host functions called by the function, part of `inclusive`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub host: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockReport {
+    pub executions: u64,
+    /// Cost of all executions
+    pub cost: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClassReport {
+    /// Number of distinct blocks in the class
+    pub blocks: u64,
+    pub executions: u64,
+    /// Cost of all executions of all blocks in the class
+    pub cost: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// How much a run may regress compared to a baseline, in percent.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// Maximum increase of the inclusive cost of a function
+    pub time_percent: f64,
+    /// Maximum increase of the number of executed blocks of a function
+    pub blocks_percent: f64,
+}
+
+impl Default for Thresholds {
+    /// Timings are noisy, block counts are deterministic.
+    fn default() -> Self {
+        Thresholds {
+            time_percent: 10.0,
+            blocks_percent: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Time,
+    Blocks,
+}
+
+/// A function that regressed beyond the configured thresholds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    pub fn_index: u32,
+    pub metric: Metric,
+    pub baseline: u64,
+    pub current: u64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metric = match self.metric {
+            Metric::Time => "time",
+            Metric::Blocks => "block count",
+        };
+        write!(
+            f,
+            "function {}: {} regressed from {} to {}",
+            self.fn_index, metric, self.baseline, self.current
+        )
+    }
+}
+
+impl Report {
+    /// Creates a report from measurements recorded with
+    /// [`Measurements::with_event_recording`](crate::measure::Measurements::with_event_recording).
+    pub fn from_events(
+        unit: impl Into<String>,
+        events: &[MeasurementEvent],
+        granularity: Granularity,
+    ) -> Self {
+        let graph = CallGraph::from_events(events, granularity);
+        let mut functions: BTreeMap<u32, FunctionReport> = graph
+            .functions
+            .iter()
+            .map(|(fn_index, stats)| {
+                let report = FunctionReport {
+                    name: None,
+                    calls: stats.calls,
+                    inclusive: stats.inclusive as u64,
+                    exclusive: stats.exclusive as u64,
+                    blocks: 0,
+                    host: stats.host as u64,
+                };
+                (*fn_index, report)
+            })
+            .collect();
+        for event in events {
+            if let MeasurementEvent::Take { fn_index, .. } = event {
+                functions.entry(*fn_index).or_default().blocks += 1;
+            }
+        }
+
+        Report {
+            unit: unit.into(),
+            functions,
+            blocks: BTreeMap::new(),
+            clock_anomalies: BTreeMap::new(),
+            disassembly: BTreeMap::new(),
+            offsets: BTreeMap::new(),
+            classes: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            max_call_depths: BTreeMap::new(),
+            metadata: Metadata::new(),
+        }
+    }
+
+    /// Adds the names of all functions known to `symbols`. Names are informational
+    /// only, functions are still compared by index.
+    pub fn with_symbols(mut self, symbols: &Symbols) -> Self {
+        for (fn_index, function) in self.functions.iter_mut() {
+            if symbols.function_name(*fn_index).is_some() {
+                function.name = Some(symbols.describe_function(*fn_index));
+            }
+        }
+        self
+    }
+
+    /// Adds the cost of every measured block. Blocks are identified by the hash of
+    /// their code, which makes them comparable across builds.
+    ///
+    /// Executions that were not timed due to
+    /// [`Measurements::with_early_termination`](crate::measure::Measurements::with_early_termination)
+    /// are counted at the mean cost of the timed ones.
+    pub fn with_blocks<C: Clock>(mut self, measurements: &Measurements<C>) -> Self {
+        for (block_id, timings) in &measurements.taken {
+            let cost = timings.iter().map(|t| C::to_units(*t)).sum::<u128>() as u64;
+            let untimed = measurements
+                .untimed
+                .get(block_id)
+                .copied()
+                .unwrap_or_default();
+            let block = self.blocks.entry(*block_id).or_default();
+            block.executions += timings.len() as u64 + untimed;
+            block.cost += cost + cost / timings.len() as u64 * untimed;
+        }
+        self
+    }
+
+    /// Adds the instructions of every block added by [`Report::with_blocks`], so that
+    /// the expensive blocks can be read. The disassembly must be created from the Wasm
+    /// that produced `measurements`. It is informational only and not compared.
+    pub fn with_disassembly<C: Clock>(
+        mut self,
+        measurements: &Measurements<C>,
+        disassembly: &Disassembly,
+    ) -> Self {
+        for block_id in self.blocks.keys() {
+            let instructions = measurements.block_locations.get(block_id).and_then(
+                |(fn_index, local_block_id)| disassembly.block(*fn_index, *local_block_id),
+            );
+            if let Some(instructions) = instructions {
+                self.disassembly.insert(*block_id, instructions.to_vec());
+            }
+        }
+        self
+    }
+
+    /// Adds where every block added by [`Report::with_blocks`] is in the Wasm code
+    /// section, as far as `block_store` knows, so that the expensive blocks can be mapped
+    /// to source lines using the DWARF debug info of the instrumented Wasm. The offsets are
+    /// informational only and not compared.
+    pub fn with_offsets(mut self, block_store: &BlockStore) -> Self {
+        for block_id in self.blocks.keys() {
+            let offsets = block_store
+                .get_block(*block_id)
+                .and_then(|block| block.offsets());
+            if let Some(offsets) = offsets {
+                self.offsets.insert(*block_id, offsets);
+            }
+        }
+        self
+    }
+
+    /// Sums up the cost of the blocks added by [`Report::with_blocks`] per class, e.g. to
+    /// see whether a contract spends more time computing or accessing storage. Blocks
+    /// without a class are left out. The classes are informational only and not compared.
+    pub fn with_classes(mut self, classification: &Classification) -> Self {
+        for (block_id, block) in &self.blocks {
+            if let Some(class) = classification.class(*block_id) {
+                let report = self.classes.entry(class.to_string()).or_default();
+                report.blocks += 1;
+                report.executions += block.executions;
+                report.cost += block.cost;
+            }
+        }
+        self
+    }
+
+    /// Adds the outcomes of the branches recorded with
+    /// [`Profiling::with_branch_tracking`](crate::instrumentation::Profiling::with_branch_tracking),
+    /// so the bias of a branch can be read next to the cost of the block it ends. Branches
+    /// are looked up by location in `block_store`, and blocks sharing an id add up their
+    /// outcomes like their costs. The branches are informational only and not compared.
+    pub fn with_branches<C: Clock>(
+        mut self,
+        measurements: &Measurements<C>,
+        block_store: &BlockStore,
+    ) -> Self {
+        let block_ids: BTreeMap<_, _> = block_store.locations().collect();
+        for (location, stats) in &measurements.branches {
+            if let Some(block_id) = block_ids.get(location) {
+                self.branches.entry(*block_id).or_default().merge(stats);
+            }
+        }
+        self
+    }
+
+    /// Adds the call depths recorded with
+    /// [`Profiling::with_call_depth_tracking`](crate::instrumentation::Profiling::with_call_depth_tracking).
+    /// They are informational only and not compared.
+    pub fn with_call_depths<C: Clock>(mut self, measurements: &Measurements<C>) -> Self {
+        self.max_call_depths
+            .extend(measurements.max_call_depths.iter().map(|(k, v)| (*k, *v)));
+        self
+    }
+
+    /// Marks the blocks whose measurements show signs of clock issues, as found by
+    /// [`detect_clock_anomalies`](crate::anomalies::detect_clock_anomalies).
+    /// They are informational only and not compared.
+    pub fn with_clock_anomalies(mut self, anomalies: BTreeMap<BlockId, Vec<ClockAnomaly>>) -> Self {
+        self.clock_anomalies.extend(anomalies);
+        self
+    }
+
+    /// Adds the metadata of a session, e.g. [`Measurements::metadata`](crate::measure::Measurements::metadata).
+    /// It is informational only and not compared.
+    pub fn with_metadata(mut self, metadata: &Metadata) -> Self {
+        self.metadata.extend(metadata.clone());
+        self
+    }
+
+    /// Compares this report to a baseline. Returns all functions whose time or
+    /// block count increased by more than the thresholds allow.
+    ///
+    /// Functions that are not part of the baseline are not checked.
+    pub fn check_against(
+        &self,
+        baseline: &Report,
+        thresholds: &Thresholds,
+    ) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        for (fn_index, current) in &self.functions {
+            let base = match baseline.functions.get(fn_index) {
+                Some(base) => base,
+                None => continue,
+            };
+            let checks = [
+                (
+                    Metric::Time,
+                    base.inclusive,
+                    current.inclusive,
+                    thresholds.time_percent,
+                ),
+                (
+                    Metric::Blocks,
+                    base.blocks,
+                    current.blocks,
+                    thresholds.blocks_percent,
+                ),
+            ];
+            for (metric, base, current, percent) in checks {
+                if current as f64 > base as f64 * (1.0 + percent / 100.0) {
+                    violations.push(Violation {
+                        fn_index: *fn_index,
+                        metric,
+                        baseline: base,
+                        current,
+                    });
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::code_blocks::CodeBlock;
+    use crate::operators::OperatorSymbol;
+
+    fn report(functions: &[(u32, u64, u64)]) -> Report {
+        Report {
+            unit: "ns".to_string(),
+            functions: functions
+                .iter()
+                .map(|(fn_index, inclusive, blocks)| {
+                    let report = FunctionReport {
+                        name: None,
+                        calls: 1,
+                        inclusive: *inclusive,
+                        exclusive: *inclusive,
+                        blocks: *blocks,
+                        host: 0,
+                    };
+                    (*fn_index, report)
+                })
+                .collect(),
+            blocks: BTreeMap::new(),
+            clock_anomalies: BTreeMap::new(),
+            disassembly: BTreeMap::new(),
+            offsets: BTreeMap::new(),
+            classes: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            max_call_depths: BTreeMap::new(),
+            metadata: Metadata::new(),
+        }
+    }
+
+    #[test]
+    fn from_events_works() {
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(1),
+                cost: 10,
+            },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::Take {
+                fn_index: 1,
+                block_id: BlockId(2),
+                cost: 3,
+            },
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(3),
+                cost: 2,
+            },
+        ];
+        let report = Report::from_events("ns", &events, Granularity::BasicBlock);
+        assert_eq!(report.unit, "ns");
+        assert_eq!(
+            report.functions[&0],
+            FunctionReport {
+                name: None,
+                calls: 1,
+                inclusive: 15,
+                exclusive: 12,
+                blocks: 2,
+                host: 0,
+            }
+        );
+        assert_eq!(
+            report.functions[&1],
+            FunctionReport {
+                name: None,
+                calls: 1,
+                inclusive: 3,
+                exclusive: 3,
+                blocks: 1,
+                host: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn check_against_works() {
+        let baseline = report(&[(0, 100, 10), (1, 50, 5)]);
+        let thresholds = Thresholds::default();
+
+        // Within the thresholds
+        let current = report(&[(0, 110, 10), (1, 20, 4), (2, 1000, 100)]);
+        assert_eq!(current.check_against(&baseline, &thresholds), Ok(()));
+
+        let current = report(&[(0, 111, 10), (1, 50, 6)]);
+        assert_eq!(
+            current.check_against(&baseline, &thresholds),
+            Err(vec![
+                Violation {
+                    fn_index: 0,
+                    metric: Metric::Time,
+                    baseline: 100,
+                    current: 111,
+                },
+                Violation {
+                    fn_index: 1,
+                    metric: Metric::Blocks,
+                    baseline: 5,
+                    current: 6,
+                },
+            ])
+        );
+
+        let thresholds = Thresholds {
+            time_percent: 50.0,
+            blocks_percent: 20.0,
+        };
+        assert_eq!(current.check_against(&baseline, &thresholds), Ok(()));
+    }
+
+    #[test]
+    fn report_serializes() {
+        let report = report(&[(3, 100, 10)]);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"unit":"ns","functions":{"3":{"calls":1,"inclusive":100,"exclusive":100,"blocks":10}}}"#
+        );
+        let parsed: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn with_symbols_adds_names() {
+        let wasm = wasmer::wat2wasm(br#"(module (func $first nop) (func nop))"#).unwrap();
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+
+        let report = report(&[(0, 100, 10), (1, 50, 5)]).with_symbols(&symbols);
+        assert_eq!(report.functions[&0].name.as_deref(), Some("first"));
+        assert_eq!(report.functions[&1].name, None);
+
+        let json = serde_json::to_string(&report.functions[&0]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"first","calls":1,"inclusive":100,"exclusive":100,"blocks":10}"#
+        );
+    }
+
+    #[test]
+    fn with_blocks_works() {
+        let mut measurements = Measurements::new();
+        measurements.taken.insert(
+            BlockId(7),
+            vec![Duration::from_nanos(3), Duration::from_nanos(5)].into(),
+        );
+        let report = report(&[(3, 100, 10)]).with_blocks(&measurements);
+        assert_eq!(
+            report.blocks[&BlockId(7)],
+            BlockReport {
+                executions: 2,
+                cost: 8,
+            }
+        );
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"unit":"ns","functions":{"3":{"calls":1,"inclusive":100,"exclusive":100,"blocks":10}},"blocks":{"7":{"executions":2,"cost":8}}}"#
+        );
+        let parsed: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, report);
+
+        // Untimed executions are counted at the mean cost
+        measurements.untimed.insert(BlockId(7), 3);
+        let report = self::report(&[]).with_blocks(&measurements);
+        assert_eq!(
+            report.blocks[&BlockId(7)],
+            BlockReport {
+                executions: 5,
+                cost: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn with_branches_works() {
+        let mut block_store = BlockStore::new();
+        block_store.register_location(0, 1, BlockId(7));
+        block_store.register_location(2, 3, BlockId(7));
+        let mut measurements = Measurements::new();
+        measurements.record_branch(0, 1, 1, 1);
+        measurements.record_branch(2, 3, 0, 1);
+        measurements.record_branch(2, 3, 1, 1);
+        // Not registered, e.g. not sampled
+        measurements.record_branch(4, 0, 0, 1);
+
+        let report = report(&[]).with_branches(&measurements, &block_store);
+        assert_eq!(report.branches.len(), 1);
+        assert_eq!(report.branches[&BlockId(7)].outcomes, [1, 2]);
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"unit":"ns","functions":{},"branches":{"7":{"outcomes":[1,2]}}}"#
+        );
+    }
+
+    #[test]
+    fn with_offsets_works() {
+        let mut block_store = BlockStore::new();
+        let offsets = WasmOffsets { first: 4, last: 10 };
+        let block = CodeBlock::from(vec![OperatorSymbol::I32Add]).with_offsets(offsets);
+        let with_offsets = block_store.register_block(block).unwrap();
+        let without_offsets = block_store
+            .register_block(vec![OperatorSymbol::I32Sub])
+            .unwrap();
+
+        let mut report = report(&[]);
+        report.blocks.insert(with_offsets, BlockReport::default());
+        report
+            .blocks
+            .insert(without_offsets, BlockReport::default());
+        let report = report.with_offsets(&block_store);
+        assert_eq!(report.offsets.len(), 1);
+        assert_eq!(report.offsets[&with_offsets], offsets);
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn with_call_depths_works() {
+        let mut measurements = Measurements::new();
+        measurements.enter_function(3);
+        measurements.enter_call();
+        measurements.enter_function(5);
+
+        let report = report(&[]).with_call_depths(&measurements);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"unit":"ns","functions":{},"max_call_depths":{"3":2}}"#
+        );
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn with_disassembly_works() {
+        let wasm = wasmer::wat2wasm(
+            br#"(module (func (param i32) (result i32) local.get 0 i32.const 2 i32.mul))"#,
+        )
+        .unwrap();
+        let disassembly = Disassembly::from_wasm(&wasm, Granularity::BasicBlock).unwrap();
+
+        let mut measurements = Measurements::new();
+        measurements.block_locations.insert(BlockId(7), (0, 0));
+        measurements.block_locations.insert(BlockId(8), (0, 1));
+        measurements
+            .taken
+            .insert(BlockId(7), vec![Duration::from_nanos(3)].into());
+        let report = report(&[(0, 100, 10)])
+            .with_blocks(&measurements)
+            .with_disassembly(&measurements, &disassembly);
+        // Only measured blocks are disassembled
+        assert_eq!(report.disassembly.len(), 1);
+        assert_eq!(
+            report.disassembly[&BlockId(7)],
+            ["local.get 0", "i32.const 2", "i32.mul"]
+        );
+    }
+
+    #[test]
+    fn with_classes_works() {
+        let mut measurements = Measurements::new();
+        for (block_id, timings) in [(7, vec![3, 4]), (8, vec![5]), (9, vec![1])] {
+            measurements.taken.insert(
+                BlockId(block_id),
+                timings.into_iter().map(Duration::from_nanos).collect(),
+            );
+        }
+        let classification = Classification {
+            classes: vec![
+                (BlockId(7), "compute-bound".to_string()),
+                (BlockId(8), "compute-bound".to_string()),
+                (BlockId(9), "host-bound".to_string()),
+                // Not measured
+                (BlockId(10), "host-bound".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let report = report(&[(0, 100, 10)])
+            .with_blocks(&measurements)
+            .with_classes(&classification);
+        assert_eq!(report.classes.len(), 2);
+        assert_eq!(
+            report.classes["compute-bound"],
+            ClassReport {
+                blocks: 2,
+                executions: 3,
+                cost: 12,
+            }
+        );
+        assert_eq!(
+            report.classes["host-bound"],
+            ClassReport {
+                blocks: 1,
+                executions: 1,
+                cost: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn with_metadata_is_serialized() {
+        let metadata: Metadata = vec![
+            ("contract".to_string(), "wasm1abc".to_string()),
+            ("msg".to_string(), "transfer".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let report = report(&[(3, 100, 10)]).with_metadata(&metadata);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"unit":"ns","functions":{"3":{"calls":1,"inclusive":100,"exclusive":100,"blocks":10}},"metadata":{"contract":"wasm1abc","msg":"transfer"}}"#
+        );
+        let parsed: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn violation_displays() {
+        let violation = Violation {
+            fn_index: 4,
+            metric: Metric::Blocks,
+            baseline: 5,
+            current: 6,
+        };
+        assert_eq!(
+            violation.to_string(),
+            "function 4: block count regressed from 5 to 6"
+        );
+    }
+}
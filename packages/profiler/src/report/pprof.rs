@@ -0,0 +1,573 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use crate::measure::MeasurementEvent;
+use crate::symbols::Symbols;
+
+use super::exporter::{Aggregates, Exporter};
+
+/// Exports the measurements as a [pprof](https://github.com/google/pprof) profile
+/// (`profile.proto`), which can be opened with `go tool pprof` or
+/// [Speedscope](https://www.speedscope.app).
+///
+/// The call stacks are reconstructed like for [`ChromeTraceExporter`](super::ChromeTraceExporter).
+/// Every stack becomes one sample with two values: the number of measurements taken
+/// with this stack and their exclusive cost. Calls to host functions are samples
+/// with the import on top of the calling function. Functions are named like in all
+/// other output, with the name as it appears in the module as the system name.
+///
+/// The profile is written uncompressed. Both tools also accept it gzipped.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PprofExporter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Frame {
+    Function(u32),
+    Import(u32),
+}
+
+/// A measurement that was started but not taken yet
+struct Open {
+    fn_index: u32,
+    /// The cost of the measurements and host calls nested in it
+    children: u128,
+}
+
+#[derive(Default)]
+struct Stacks {
+    open: Vec<Open>,
+    /// The number of measurements and their exclusive cost, keyed by the stack
+    /// from the outermost frame to the innermost
+    samples: BTreeMap<Vec<Frame>, (u64, u128)>,
+}
+
+impl Stacks {
+    fn frames(&self) -> Vec<Frame> {
+        self.open
+            .iter()
+            .map(|open| Frame::Function(open.fn_index))
+            .collect()
+    }
+
+    fn record(&mut self, stack: Vec<Frame>, cost: u128) {
+        let sample = self.samples.entry(stack).or_default();
+        sample.0 += 1;
+        sample.1 += cost;
+    }
+
+    /// Adds `cost` to the measurement enclosing the current one
+    fn charge_parent(&mut self, cost: u128) {
+        if let Some(parent) = self.open.last_mut() {
+            parent.children += cost;
+        }
+    }
+
+    /// Drops the innermost measurement without a sample, e.g. due to a trap. The
+    /// cost of its children is still part of the enclosing measurement.
+    fn abandon(&mut self) {
+        let open = self.open.pop().unwrap();
+        self.charge_parent(open.children);
+    }
+
+    fn take(&mut self, fn_index: u32, cost: u128) {
+        match self.open.iter().rposition(|open| open.fn_index == fn_index) {
+            Some(pos) => {
+                while self.open.len() > pos + 1 {
+                    self.abandon();
+                }
+                let stack = self.frames();
+                let open = self.open.pop().unwrap();
+                self.record(stack, cost.saturating_sub(open.children));
+            }
+            None => {
+                let mut stack = self.frames();
+                stack.push(Frame::Function(fn_index));
+                self.record(stack, cost);
+            }
+        }
+        self.charge_parent(cost);
+    }
+
+    fn host_call(&mut self, fn_index: u32, import_index: u32, cost: u128) {
+        let mut stack = self.frames();
+        if self.open.last().map(|open| open.fn_index) != Some(fn_index) {
+            stack.push(Frame::Function(fn_index));
+        }
+        stack.push(Frame::Import(import_index));
+        self.record(stack, cost);
+        self.charge_parent(cost);
+    }
+}
+
+/// The string table of a profile. Index 0 is always the empty string.
+struct Strings {
+    indexes: HashMap<String, u64>,
+    table: Vec<String>,
+}
+
+impl Strings {
+    fn new() -> Self {
+        Strings {
+            indexes: vec![(String::new(), 0)].into_iter().collect(),
+            table: vec![String::new()],
+        }
+    }
+
+    fn index(&mut self, string: &str) -> u64 {
+        if let Some(index) = self.indexes.get(string) {
+            return *index;
+        }
+        let index = self.table.len() as u64;
+        self.indexes.insert(string.to_string(), index);
+        self.table.push(string.to_string());
+        index
+    }
+}
+
+/// An encoded protobuf message. Only the wire types needed for a profile are supported.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.varint((field as u64) << 3);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u32, data: &[u8]) {
+        self.varint((field as u64) << 3 | 2);
+        self.varint(data.len() as u64);
+        self.0.extend_from_slice(data);
+    }
+
+    fn message(&mut self, field: u32, message: Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed(&mut self, field: u32, values: impl IntoIterator<Item = u64>) {
+        let mut packed = Message::default();
+        for value in values {
+            packed.varint(value);
+        }
+        self.message(field, packed);
+    }
+}
+
+/// A `ValueType` of `profile.proto`
+fn value_type(strings: &mut Strings, kind: &str, unit: &str) -> Message {
+    let mut message = Message::default();
+    message.uint(1, strings.index(kind));
+    message.uint(2, strings.index(unit));
+    message
+}
+
+/// The units pprof knows are spelled out
+fn pprof_unit(unit: &str) -> &str {
+    match unit {
+        "ns" => "nanoseconds",
+        other => other,
+    }
+}
+
+fn frame_names(frame: Frame, symbols: &Symbols) -> (String, String) {
+    match frame {
+        Frame::Function(fn_index) => {
+            let name = symbols.describe_function(fn_index);
+            let system_name = symbols
+                .function_name(fn_index)
+                .map_or_else(|| name.clone(), str::to_string);
+            (name, system_name)
+        }
+        Frame::Import(import_index) => {
+            let name = symbols.describe_import(import_index);
+            (name.clone(), name)
+        }
+    }
+}
+
+impl Exporter for PprofExporter {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()> {
+        let mut stacks = Stacks::default();
+        for event in aggregates.events {
+            match *event {
+                MeasurementEvent::Start { fn_index } => stacks.open.push(Open {
+                    fn_index,
+                    children: 0,
+                }),
+                MeasurementEvent::Take { fn_index, cost, .. } => stacks.take(fn_index, cost),
+                MeasurementEvent::HostCall {
+                    fn_index,
+                    import_index,
+                    cost,
+                } => stacks.host_call(fn_index, import_index, cost),
+                MeasurementEvent::IndirectCall { .. } => {}
+                MeasurementEvent::InvocationEnd => {
+                    while !stacks.open.is_empty() {
+                        stacks.abandon();
+                    }
+                }
+            }
+        }
+
+        let mut strings = Strings::new();
+        let mut profile = Message::default();
+        profile.message(1, value_type(&mut strings, "samples", "count"));
+        profile.message(
+            1,
+            value_type(&mut strings, "cost", pprof_unit(aggregates.unit)),
+        );
+
+        // Every frame has one location with the same id as its function
+        let mut ids: BTreeMap<Frame, u64> = BTreeMap::new();
+        for (stack, (count, cost)) in &stacks.samples {
+            for frame in stack {
+                let next_id = ids.len() as u64 + 1;
+                ids.entry(*frame).or_insert(next_id);
+            }
+            let mut sample = Message::default();
+            // Locations are listed from the innermost frame to the outermost
+            sample.packed(1, stack.iter().rev().map(|frame| ids[frame]));
+            let cost = i64::try_from(*cost).unwrap_or(i64::MAX) as u64;
+            sample.packed(2, vec![*count, cost]);
+            profile.message(2, sample);
+        }
+        for id in ids.values() {
+            let mut line = Message::default();
+            line.uint(1, *id);
+            let mut location = Message::default();
+            location.uint(1, *id);
+            location.message(4, line);
+            profile.message(4, location);
+        }
+        for (frame, id) in &ids {
+            let (name, system_name) = frame_names(*frame, aggregates.symbols);
+            let mut function = Message::default();
+            function.uint(1, *id);
+            function.uint(2, strings.index(&name));
+            function.uint(3, strings.index(&system_name));
+            profile.message(5, function);
+        }
+
+        let comments: Vec<u64> = aggregates
+            .metadata
+            .iter()
+            .map(|(key, value)| strings.index(&format!("{}={}", key, value)))
+            .collect();
+        let default_sample_type = strings.index("cost");
+        for string in &strings.table {
+            profile.bytes(6, string.as_bytes());
+        }
+        profile.packed(13, comments);
+        profile.uint(14, default_sample_type);
+
+        sink.write_all(&profile.0)?;
+        sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::wat2wasm;
+
+    use crate::code_blocks::BlockId;
+    use crate::measure::Metadata;
+
+    enum Value {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    impl Value {
+        fn varint(&self) -> u64 {
+            match self {
+                Value::Varint(value) => *value,
+                Value::Bytes(_) => panic!("Not a varint"),
+            }
+        }
+
+        fn bytes(&self) -> &[u8] {
+            match self {
+                Value::Varint(_) => panic!("Not length delimited"),
+                Value::Bytes(bytes) => bytes,
+            }
+        }
+
+        fn packed(&self) -> Vec<u64> {
+            let mut data = self.bytes();
+            let mut values = Vec::new();
+            while !data.is_empty() {
+                values.push(read_varint(&mut data));
+            }
+            values
+        }
+    }
+
+    fn read_varint(data: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[0];
+            *data = &data[1..];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Decodes the fields of a protobuf message
+    fn fields(mut data: &[u8]) -> Vec<(u32, Value)> {
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let key = read_varint(&mut data);
+            let value = match key & 7 {
+                0 => Value::Varint(read_varint(&mut data)),
+                2 => {
+                    let len = read_varint(&mut data) as usize;
+                    let (bytes, rest) = data.split_at(len);
+                    data = rest;
+                    Value::Bytes(bytes.to_vec())
+                }
+                wire_type => panic!("Unexpected wire type {}", wire_type),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    fn field(fields: &[(u32, Value)], number: u32) -> &Value {
+        &fields.iter().find(|(n, _)| *n == number).unwrap().1
+    }
+
+    fn repeated(fields: &[(u32, Value)], number: u32) -> impl Iterator<Item = &Value> {
+        fields
+            .iter()
+            .filter(move |(n, _)| *n == number)
+            .map(|(_, value)| value)
+    }
+
+    /// The parts of a profile the exporter writes, with all references resolved
+    #[derive(Debug, PartialEq)]
+    struct Decoded {
+        sample_types: Vec<(String, String)>,
+        /// The function names from the innermost to the outermost frame, and the values
+        samples: Vec<(Vec<String>, Vec<u64>)>,
+        /// The name and system name of all functions
+        functions: Vec<(String, String)>,
+        comments: Vec<String>,
+        default_sample_type: String,
+    }
+
+    fn export(events: &[MeasurementEvent], unit: &str, symbols: &Symbols) -> Decoded {
+        let metadata: Metadata = vec![("msg".to_string(), "transfer".to_string())]
+            .into_iter()
+            .collect();
+        let aggregates = Aggregates {
+            unit,
+            events,
+            symbols,
+            metadata: &metadata,
+        };
+        let mut profile = Vec::new();
+        PprofExporter.export(&aggregates, &mut profile).unwrap();
+
+        let profile = fields(&profile);
+        let strings: Vec<String> = repeated(&profile, 6)
+            .map(|value| String::from_utf8(value.bytes().to_vec()).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        let string = |value: &Value| strings[value.varint() as usize].clone();
+
+        let functions: HashMap<u64, (String, String)> = repeated(&profile, 5)
+            .map(|value| {
+                let function = fields(value.bytes());
+                let names = (string(field(&function, 2)), string(field(&function, 3)));
+                (field(&function, 1).varint(), names)
+            })
+            .collect();
+        let locations: HashMap<u64, u64> = repeated(&profile, 4)
+            .map(|value| {
+                let location = fields(value.bytes());
+                let line = fields(field(&location, 4).bytes());
+                (field(&location, 1).varint(), field(&line, 1).varint())
+            })
+            .collect();
+
+        let mut function_names: Vec<(String, String)> = functions.values().cloned().collect();
+        function_names.sort();
+        Decoded {
+            sample_types: repeated(&profile, 1)
+                .map(|value| {
+                    let value_type = fields(value.bytes());
+                    (string(field(&value_type, 1)), string(field(&value_type, 2)))
+                })
+                .collect(),
+            samples: repeated(&profile, 2)
+                .map(|value| {
+                    let sample = fields(value.bytes());
+                    let stack = field(&sample, 1)
+                        .packed()
+                        .iter()
+                        .map(|location_id| functions[&locations[location_id]].0.clone())
+                        .collect();
+                    (stack, field(&sample, 2).packed())
+                })
+                .collect(),
+            functions: function_names,
+            comments: field(&profile, 13)
+                .packed()
+                .iter()
+                .map(|index| strings[*index as usize].clone())
+                .collect(),
+            default_sample_type: string(field(&profile, 14)),
+        }
+    }
+
+    fn owned(samples: &[(&[&str], &[u64])]) -> Vec<(Vec<String>, Vec<u64>)> {
+        samples
+            .iter()
+            .map(|(stack, values)| {
+                let stack = stack.iter().map(|name| name.to_string()).collect();
+                (stack, values.to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn export_aggregates_stacks() {
+        let invocation = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::HostCall {
+                fn_index: 1,
+                import_index: 0,
+                cost: 500,
+            },
+            MeasurementEvent::Take {
+                fn_index: 1,
+                block_id: BlockId(7),
+                cost: 2000,
+            },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(8),
+                cost: 5000,
+            },
+            MeasurementEvent::InvocationEnd,
+        ];
+        let events: Vec<MeasurementEvent> = invocation.iter().chain(&invocation).copied().collect();
+        let profile = export(&events, "ns", &Symbols::default());
+
+        assert_eq!(
+            profile.sample_types,
+            [
+                ("samples".to_string(), "count".to_string()),
+                ("cost".to_string(), "nanoseconds".to_string())
+            ]
+        );
+        assert_eq!(
+            profile.samples,
+            owned(&[
+                (&["fn 0"], &[2, 6000]),
+                (&["fn 1", "fn 0"], &[2, 3000]),
+                (&["import 0", "fn 1", "fn 0"], &[2, 1000]),
+            ])
+        );
+        assert_eq!(
+            profile.functions,
+            [
+                ("fn 0".to_string(), "fn 0".to_string()),
+                ("fn 1".to_string(), "fn 1".to_string()),
+                ("import 0".to_string(), "import 0".to_string()),
+            ]
+        );
+        assert_eq!(profile.comments, ["msg=transfer"]);
+        assert_eq!(profile.default_sample_type, "cost");
+    }
+
+    #[test]
+    fn export_handles_unfinished_measurements() {
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::Start { fn_index: 1 },
+            MeasurementEvent::Start { fn_index: 2 },
+            MeasurementEvent::Take {
+                fn_index: 2,
+                block_id: BlockId(1),
+                cost: 3,
+            },
+            // fn 1 never finishes, but its callee is part of fn 0
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(2),
+                cost: 10,
+            },
+            MeasurementEvent::Start { fn_index: 3 },
+            MeasurementEvent::InvocationEnd,
+            // Without a matching start
+            MeasurementEvent::Take {
+                fn_index: 4,
+                block_id: BlockId(3),
+                cost: 4,
+            },
+        ];
+        let profile = export(&events, "cycles", &Symbols::default());
+
+        assert_eq!(profile.sample_types[1].1, "cycles");
+        assert_eq!(
+            profile.samples,
+            owned(&[
+                (&["fn 0"], &[1, 7]),
+                (&["fn 2", "fn 1", "fn 0"], &[1, 3]),
+                (&["fn 4"], &[1, 4]),
+            ])
+        );
+    }
+
+    #[test]
+    fn export_uses_function_names() {
+        let wasm = wat2wasm(
+            br#"(module
+            (import "env" "db_read" (func (param i32) (result i32)))
+            (func $_ZN8hackatom8contract7execute17h0123456789abcdefE nop))"#,
+        )
+        .unwrap();
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+        let events = [
+            MeasurementEvent::Start { fn_index: 0 },
+            MeasurementEvent::HostCall {
+                fn_index: 0,
+                import_index: 0,
+                cost: 1,
+            },
+            MeasurementEvent::Take {
+                fn_index: 0,
+                block_id: BlockId(1),
+                cost: 2,
+            },
+        ];
+        let profile = export(&events, "ns", &symbols);
+        assert_eq!(
+            profile.functions,
+            [
+                ("env.db_read".to_string(), "env.db_read".to_string()),
+                (
+                    "hackatom::contract::execute".to_string(),
+                    "_ZN8hackatom8contract7execute17h0123456789abcdefE".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::code_blocks::BlockId;
+use crate::instrumentation::Granularity;
+use crate::measure::MeasurementEvent;
+use crate::utils::{with_metadata_keys, with_metadata_values};
+
+use super::exporter::{Aggregates, Exporter};
+use super::Report;
+
+/// Exports the cost of every measured block as CSV, one row per block and function,
+/// the most expensive blocks first. Like all other CSV output, the metadata is
+/// appended to every row.
+///
+/// Unlike [`Measurements::compile_csv`](crate::measure::Measurements::compile_csv),
+/// this only needs the recorded events, not the block store.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CsvExporter;
+
+/// The measurements of a block in one function
+#[derive(Debug, Clone, Copy)]
+struct BlockCosts {
+    executions: u64,
+    total: u128,
+    min: u128,
+    max: u128,
+}
+
+impl Exporter for CsvExporter {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()> {
+        let mut blocks: BTreeMap<(u32, BlockId), BlockCosts> = BTreeMap::new();
+        for event in aggregates.events {
+            if let MeasurementEvent::Take {
+                fn_index,
+                block_id,
+                cost,
+            } = *event
+            {
+                let costs = blocks.entry((fn_index, block_id)).or_insert(BlockCosts {
+                    executions: 0,
+                    total: 0,
+                    min: cost,
+                    max: cost,
+                });
+                costs.executions += 1;
+                costs.total += cost;
+                costs.min = costs.min.min(cost);
+                costs.max = costs.max.max(cost);
+            }
+        }
+        let mut blocks: Vec<_> = blocks.into_iter().collect();
+        blocks.sort_by_key(|(location, costs)| (Reverse(costs.total), *location));
+
+        let mut wtr = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(&mut *sink);
+        let unit = aggregates.unit;
+        let header = [
+            "function".to_string(),
+            "block".to_string(),
+            "executions".to_string(),
+            format!("total in {}", unit),
+            format!("avg in {}", unit),
+            format!("min in {}", unit),
+            format!("max in {}", unit),
+        ];
+        wtr.write_record(with_metadata_keys(header, aggregates.metadata))?;
+        for ((fn_index, block_id), costs) in blocks {
+            let row = [
+                aggregates.symbols.describe_function(fn_index),
+                block_id.as_u64().to_string(),
+                costs.executions.to_string(),
+                costs.total.to_string(),
+                (costs.total / costs.executions as u128).to_string(),
+                costs.min.to_string(),
+                costs.max.to_string(),
+            ];
+            wtr.write_record(with_metadata_values(row, aggregates.metadata))?;
+        }
+        wtr.flush()
+    }
+}
+
+/// Exports a [`Report`] with the cost of every function and block, the names of all
+/// functions and the metadata as pretty-printed JSON. It can be loaded again with
+/// `serde_json` and compared with [`Report::check_against`].
+///
+/// Function costs are computed like in [`Report::from_events`], so the granularity has
+/// to match the one the contract was instrumented with.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonExporter {
+    granularity: Granularity,
+}
+
+impl JsonExporter {
+    pub fn new(granularity: Granularity) -> Self {
+        JsonExporter { granularity }
+    }
+
+    /// The report written by [`Exporter::export`]
+    pub fn report(&self, aggregates: &Aggregates) -> Report {
+        let mut report = Report::from_events(aggregates.unit, aggregates.events, self.granularity)
+            .with_symbols(aggregates.symbols)
+            .with_metadata(aggregates.metadata);
+        for event in aggregates.events {
+            if let MeasurementEvent::Take { block_id, cost, .. } = *event {
+                let block = report.blocks.entry(block_id).or_default();
+                block.executions += 1;
+                block.cost += cost as u64;
+            }
+        }
+        report
+    }
+}
+
+impl Exporter for JsonExporter {
+    fn export(&self, aggregates: &Aggregates, sink: &mut impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *sink, &self.report(aggregates))?;
+        sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::measure::Metadata;
+    use crate::report::BlockReport;
+    use crate::symbols::Symbols;
+
+    const EVENTS: &[MeasurementEvent] = &[
+        MeasurementEvent::Start { fn_index: 0 },
+        MeasurementEvent::Take {
+            fn_index: 0,
+            block_id: BlockId(1),
+            cost: 10,
+        },
+        MeasurementEvent::Start { fn_index: 1 },
+        MeasurementEvent::Take {
+            fn_index: 1,
+            block_id: BlockId(2),
+            cost: 3,
+        },
+        MeasurementEvent::Start { fn_index: 1 },
+        MeasurementEvent::Take {
+            fn_index: 1,
+            block_id: BlockId(2),
+            cost: 7,
+        },
+        MeasurementEvent::InvocationEnd,
+    ];
+
+    fn export(exporter: impl Exporter) -> String {
+        let symbols = Symbols::default();
+        let metadata: Metadata = vec![("msg".to_string(), "transfer".to_string())]
+            .into_iter()
+            .collect();
+        let aggregates = Aggregates {
+            unit: "ns",
+            events: EVENTS,
+            symbols: &symbols,
+            metadata: &metadata,
+        };
+        let mut out = Vec::new();
+        exporter.export(&aggregates, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_exporter_works() {
+        assert_eq!(
+            export(CsvExporter),
+            "function,block,executions,total in ns,avg in ns,min in ns,max in ns,msg\r\n\
+             fn 0,1,1,10,10,10,10,transfer\r\n\
+             fn 1,2,2,10,5,3,7,transfer\r\n"
+        );
+    }
+
+    #[test]
+    fn json_exporter_works() {
+        let json = export(JsonExporter::new(Granularity::Function));
+        let report: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(report.functions[&1].calls, 2);
+        assert_eq!(report.functions[&1].inclusive, 10);
+        assert_eq!(
+            report.blocks[&BlockId(2)],
+            BlockReport {
+                executions: 2,
+                cost: 10
+            }
+        );
+        assert_eq!(report.metadata["msg"], "transfer");
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, WallClock};
+use crate::code_blocks::{BlockId, BlockStore, BlockStoreError};
+use crate::measure::Measurements;
+use crate::report::{BlockReport, Report};
+use crate::vm::VmProfiling;
+
+/// Delimits the measurements of a long-lived instance, e.g. to get the measurements
+/// of every call to `execute` separately without recompiling the module.
+///
+/// A session shares the measurements with the profiling imports, see
+/// [`add_measuring_imports`](crate::instrumentation::add_measuring_imports). Clones
+/// share the same measurements.
+pub struct Session<C: Clock = WallClock> {
+    measurements: Arc<Mutex<Measurements<C>>>,
+}
+
+impl<C: Clock> Clone for Session<C> {
+    fn clone(&self) -> Self {
+        Self {
+            measurements: self.measurements.clone(),
+        }
+    }
+}
+
+impl<C: Clock> Session<C> {
+    pub fn new(measurements: Measurements<C>) -> Self {
+        Self::from_shared(Arc::new(Mutex::new(measurements)))
+    }
+
+    /// A session of measurements that are already shared with the profiling imports.
+    pub fn from_shared(measurements: Arc<Mutex<Measurements<C>>>) -> Self {
+        Self { measurements }
+    }
+
+    /// The measurements to pass to the profiling imports
+    pub fn measurements(&self) -> &Arc<Mutex<Measurements<C>>> {
+        &self.measurements
+    }
+
+    /// A copy of everything measured since the session started or was reset last.
+    pub fn snapshot(&self) -> Measurements<C> {
+        self.measurements.lock().unwrap().clone()
+    }
+
+    /// Returns everything measured since the session started or was reset last and
+    /// continues with empty measurements, see [`Measurements::split_off`]. Nothing
+    /// measured in between is lost, even if the contract is running.
+    ///
+    /// Call [`Measurements::end_invocation`] first if the measurements are reset after
+    /// a call into the contract, so that the call graph of the returned measurements
+    /// is complete.
+    pub fn reset(&self) -> Measurements<C> {
+        self.measurements.lock().unwrap().split_off()
+    }
+}
+
+/// Profiles several contracts interacting in one transaction, e.g. a factory and the
+/// contracts it instantiates or both ends of an IBC channel.
+///
+/// Every contract is instrumented by its own [`VmProfiling`] under a label, since
+/// function indexes and block locations only make sense within one contract. The
+/// session clears and reports all of them at once.
+pub struct ProfilingSession<C: Clock = WallClock> {
+    contracts: BTreeMap<String, VmProfiling<C>>,
+}
+
+impl<C: Clock> Default for ProfilingSession<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of a transaction profiled with [`ProfilingSession::profile`].
+#[derive(Debug, Clone)]
+pub struct SessionProfiled<T> {
+    pub result: T,
+    pub report: SessionReport,
+}
+
+impl<C: Clock> ProfilingSession<C> {
+    pub fn new() -> Self {
+        Self {
+            contracts: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the contract instrumented by `profiling` under `label` and returns the
+    /// profiling to create its instance with, see [`VmProfiling::instance_options`].
+    /// A contract that was added under the same label before is replaced.
+    pub fn add_contract(
+        &mut self,
+        label: impl Into<String>,
+        profiling: VmProfiling<C>,
+    ) -> VmProfiling<C> {
+        self.contracts.insert(label.into(), profiling.clone());
+        profiling
+    }
+
+    pub fn contract(&self, label: &str) -> Option<&VmProfiling<C>> {
+        self.contracts.get(label)
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.contracts.keys().map(String::as_str)
+    }
+
+    /// Runs `call` and reports the measurements all contracts took during it, e.g. of
+    /// a closure executing a message and dispatching its submessages to the other
+    /// contracts. The measurements of earlier calls are discarded.
+    pub fn profile<T>(&self, call: impl FnOnce() -> T) -> SessionProfiled<T> {
+        for profiling in self.contracts.values() {
+            profiling.measurements().lock().unwrap().clear();
+        }
+        let result = call();
+        for profiling in self.contracts.values() {
+            profiling.measurements().lock().unwrap().end_invocation();
+        }
+        SessionProfiled {
+            result,
+            report: self.report(),
+        }
+    }
+
+    /// Reports the measurements of all contracts taken so far.
+    pub fn report(&self) -> SessionReport {
+        SessionReport {
+            unit: C::UNIT.to_string(),
+            contracts: self
+                .contracts
+                .iter()
+                .map(|(label, profiling)| {
+                    let report = profiling.report(&profiling.measurements().lock().unwrap());
+                    (label.clone(), report)
+                })
+                .collect(),
+        }
+    }
+
+    /// The blocks of all contracts in one store, labeled with the contracts they
+    /// belong to. Fails if two contracts registered different blocks under one id.
+    pub fn merged_blocks(&self) -> Result<MergedBlocks, BlockStoreError> {
+        let mut merged = MergedBlocks {
+            store: BlockStore::new(),
+            contracts: BTreeMap::new(),
+            locations: BTreeMap::new(),
+        };
+        for (label, profiling) in &self.contracts {
+            let blocks = profiling.profiling().registered_blocks();
+            for ((fn_index, local_block_id), id) in blocks.locations() {
+                let block = blocks.get_block(id).unwrap().clone();
+                merged.store.register_block_with_id(id, block)?;
+                merged
+                    .contracts
+                    .entry(id)
+                    .or_default()
+                    .insert(label.clone());
+                merged
+                    .locations
+                    .insert((label.clone(), fn_index, local_block_id), id);
+            }
+        }
+        Ok(merged)
+    }
+}
+
+/// The blocks of all contracts of a [`ProfilingSession`].
+#[derive(Debug)]
+pub struct MergedBlocks {
+    /// The blocks of all contracts without locations. Blocks with the same code share
+    /// an id across contracts, e.g. code of a common dependency.
+    pub store: BlockStore,
+    /// The labels of the contracts containing every block
+    pub contracts: BTreeMap<BlockId, BTreeSet<String>>,
+    /// The block at every location, keyed by label, function index and local block id
+    pub locations: BTreeMap<(String, u32, u32), BlockId>,
+}
+
+/// A report of every contract of a [`ProfilingSession`].
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct SessionReport {
+    /// The unit of all costs, see [`Clock::UNIT`]
+    pub unit: String,
+    /// The report of every contract, keyed by label
+    pub contracts: BTreeMap<String, Report>,
+}
+
+impl SessionReport {
+    /// The cost of the code and the host calls of every contract, keyed by label.
+    ///
+    /// Queries to other contracts run inside a host call of the querying contract, so
+    /// their cost is part of the totals of both contracts.
+    pub fn totals(&self) -> BTreeMap<String, u64> {
+        self.contracts
+            .iter()
+            .map(|(label, report)| {
+                let total = report
+                    .functions
+                    .values()
+                    .map(|function| function.exclusive + function.host)
+                    .sum();
+                (label.clone(), total)
+            })
+            .collect()
+    }
+
+    /// The blocks measured in more than one contract with their cost in every contract.
+    pub fn shared_blocks(&self) -> BTreeMap<BlockId, BTreeMap<String, BlockReport>> {
+        let mut blocks: BTreeMap<BlockId, BTreeMap<String, BlockReport>> = BTreeMap::new();
+        for (label, report) in &self.contracts {
+            for (block_id, block) in &report.blocks {
+                blocks
+                    .entry(*block_id)
+                    .or_default()
+                    .insert(label.clone(), *block);
+            }
+        }
+        blocks.retain(|_, contracts| contracts.len() > 1);
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::{
+        wat2wasm, CompilerConfig, Cranelift, Exports, ImportObject, Instance, Module, Store,
+        Universal,
+    };
+
+    use cosmwasm_std::Empty;
+    use cosmwasm_vm::{
+        call_instantiate,
+        testing::{mock_backend, mock_env, mock_info},
+    };
+
+    use crate::instrumentation::{
+        add_measuring_imports, instrument_wasm, Granularity, Profiling, GAS_LIMIT,
+    };
+
+    #[test]
+    fn reset_delimits_calls() {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Arc::new(Profiling::new(block_store, Granularity::Function));
+        let wasm = wat2wasm(
+            br#"(module
+                (func (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add))"#,
+        )
+        .unwrap();
+        let wasm = instrument_wasm(&wasm, &profiling).unwrap();
+
+        let mut config = Cranelift::default();
+        config.push_middleware(profiling.clone());
+        let store = Store::new(&Universal::new(config).engine());
+        let module = Module::new(&store, &wasm).unwrap();
+
+        let session = Session::new(Measurements::new().with_metadata("contract", "adder"));
+        let mut exports = Exports::new();
+        add_measuring_imports(
+            &profiling,
+            &store,
+            session.measurements().clone(),
+            &mut exports,
+        );
+        let mut imports = ImportObject::new();
+        imports.register(profiling.import_module(), exports);
+        let instance = Instance::new(&module, &imports).unwrap();
+        let add = instance.exports.get_function("add").unwrap();
+        let executions = |measurements: &Measurements| -> usize {
+            measurements.taken.values().map(|taken| taken.len()).sum()
+        };
+
+        add.call(&[1.into(), 2.into()]).unwrap();
+        add.call(&[3.into(), 4.into()]).unwrap();
+        assert_eq!(executions(&session.snapshot()), 2);
+
+        let first = session.reset();
+        assert_eq!(executions(&first), 2);
+        assert_eq!(executions(&session.snapshot()), 0);
+
+        add.call(&[5.into(), 6.into()]).unwrap();
+        let second = session.reset();
+        assert_eq!(executions(&second), 1);
+        assert_eq!(second.metadata["contract"], "adder");
+
+        // Clones share the measurements
+        add.call(&[7.into(), 8.into()]).unwrap();
+        assert_eq!(executions(&session.clone().reset()), 1);
+    }
+
+    #[test]
+    fn profiling_session_reports_all_contracts() {
+        static HACKATOM: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+        let vm_profiling = || {
+            let block_store = Arc::new(Mutex::new(BlockStore::new()));
+            let profiling = Profiling::new(block_store, Granularity::BasicBlock);
+            VmProfiling::new(profiling, Measurements::new())
+        };
+        let mut session = ProfilingSession::new();
+        let factory = session.add_contract("factory", vm_profiling());
+        let child = session.add_contract("child", vm_profiling());
+        let idle = session.add_contract("idle", vm_profiling());
+        assert_eq!(
+            session.labels().collect::<Vec<_>>(),
+            ["child", "factory", "idle"]
+        );
+
+        let instance = |profiling: VmProfiling| {
+            let options = profiling.instance_options(GAS_LIMIT, false);
+            cosmwasm_vm::Instance::from_code(HACKATOM, mock_backend(&[]), options, None).unwrap()
+        };
+        let mut factory = instance(factory);
+        let mut child = instance(child);
+        let _idle = instance(idle);
+
+        let info = mock_info("creator", &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        let profiled = session.profile(|| {
+            call_instantiate::<_, _, _, Empty>(&mut factory, &mock_env(), &info, msg).unwrap();
+            call_instantiate::<_, _, _, Empty>(&mut child, &mock_env(), &info, msg).unwrap();
+        });
+
+        let report = profiled.report;
+        assert_eq!(report.unit, "ns");
+        assert_eq!(report.contracts.len(), 3);
+        assert!(!report.contracts["factory"].functions.is_empty());
+        assert!(report.contracts["idle"].functions.is_empty());
+        let totals = report.totals();
+        assert!(totals["factory"] > 0);
+        assert!(totals["child"] > 0);
+        assert_eq!(totals["idle"], 0);
+        // Both run the same code
+        let shared = report.shared_blocks();
+        assert!(!shared.is_empty());
+        for contracts in shared.values() {
+            assert_eq!(contracts.keys().collect::<Vec<_>>(), ["child", "factory"]);
+        }
+
+        let merged = session.merged_blocks().unwrap();
+        assert!(!merged.store.is_empty());
+        for (block_id, contracts) in &merged.contracts {
+            assert!(merged.store.get_block(*block_id).is_some());
+            assert_eq!(contracts.len(), 3);
+        }
+        let factory_locations = merged
+            .locations
+            .keys()
+            .filter(|(label, _, _)| label == "factory")
+            .count();
+        assert_eq!(merged.locations.len(), 3 * factory_locations);
+
+        // A new transaction starts from scratch
+        let empty = session.profile(|| ());
+        assert!(empty.report.totals().values().all(|total| *total == 0));
+        assert_eq!(
+            serde_json::from_str::<SessionReport>(&serde_json::to_string(&report).unwrap())
+                .unwrap(),
+            report
+        );
+    }
+}
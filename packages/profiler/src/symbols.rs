@@ -0,0 +1,588 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use thiserror::Error;
+use wasmer::wasmparser::{
+    ElementItem, ElementKind, ImportSectionEntryType, Name, NameSectionReader, Operator, Parser,
+    Payload,
+};
+
+#[derive(Error, Debug)]
+pub enum SymbolsError {
+    #[error("Error parsing Wasm: {msg}")]
+    ParseErr { msg: String },
+    #[error("Error parsing DWARF: {msg}")]
+    DwarfErr { msg: String },
+}
+
+impl From<wasmer::wasmparser::BinaryReaderError> for SymbolsError {
+    fn from(err: wasmer::wasmparser::BinaryReaderError) -> Self {
+        SymbolsError::ParseErr {
+            msg: err.to_string(),
+        }
+    }
+}
+
+impl From<gimli::Error> for SymbolsError {
+    fn from(err: gimli::Error) -> Self {
+        SymbolsError::DwarfErr {
+            msg: err.to_string(),
+        }
+    }
+}
+
+/// The names of the functions of a Wasm module, keyed by local function index
+/// like everything the profiler reports.
+///
+/// Names are taken from the `name` custom section. Functions missing there are
+/// looked up in the DWARF debug info if the module has any. Names are stored as
+/// they appear in the module and demangled when describing code.
+///
+/// Imported functions are known by their module and field name, keyed by function
+/// index. Since imports come first, this is the same as the import index.
+///
+/// The local functions in tables are known from the element segments at constant
+/// offsets, so that calls through tables can be resolved, see
+/// [`Symbols::table_function`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Symbols {
+    functions: BTreeMap<u32, String>,
+    imports: BTreeMap<u32, String>,
+    /// The local function index of every table element, keyed by table and element index
+    table_functions: BTreeMap<(u32, u32), u32>,
+    /// The local function index of the start function
+    start_function: Option<u32>,
+}
+
+impl Symbols {
+    pub fn from_wasm(wasm: &[u8]) -> Result<Self, SymbolsError> {
+        let mut imported_functions = 0;
+        let mut code_start = 0;
+        // The end of every local function body relative to the start of the code
+        // section, which is how DWARF addresses code in Wasm.
+        let mut body_ends = Vec::new();
+        let mut names = BTreeMap::new();
+        let mut debug_sections = BTreeMap::new();
+        let mut imports = BTreeMap::new();
+        // Keyed by table and element index, with function indexes including imports
+        let mut elements = BTreeMap::new();
+        let mut start = None;
+
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import?;
+                        if let ImportSectionEntryType::Function(_) = import.ty {
+                            let name =
+                                format!("{}.{}", import.module, import.field.unwrap_or_default());
+                            imports.insert(imported_functions, name);
+                            imported_functions += 1;
+                        }
+                    }
+                }
+                Payload::StartSection { func, .. } => start = Some(func),
+                Payload::ElementSection(reader) => {
+                    for element in reader {
+                        let element = element?;
+                        let (table_index, init_expr) = match element.kind {
+                            ElementKind::Active {
+                                table_index,
+                                init_expr,
+                            } => (table_index, init_expr),
+                            _ => continue,
+                        };
+                        // Offsets read from imported globals are not known before instantiation
+                        let offset = match init_expr.get_operators_reader().read()? {
+                            Operator::I32Const { value } => value as u32,
+                            _ => continue,
+                        };
+                        let items = element.items.get_items_reader()?;
+                        for (position, item) in (0..).zip(items) {
+                            if let ElementItem::Func(function) = item? {
+                                elements.insert((table_index, offset + position), function);
+                            }
+                        }
+                    }
+                }
+                Payload::CodeSectionStart { range, .. } => code_start = range.start,
+                Payload::CodeSectionEntry(body) => body_ends.push(body.range().end - code_start),
+                Payload::CustomSection {
+                    name: "name",
+                    data,
+                    data_offset,
+                    ..
+                } => {
+                    for name in NameSectionReader::new(data, data_offset)? {
+                        if let Name::Function(function_names) = name? {
+                            let mut map = function_names.get_map()?;
+                            for _ in 0..map.get_count() {
+                                let naming = map.read()?;
+                                names.insert(naming.index, naming.name.to_string());
+                            }
+                        }
+                    }
+                }
+                Payload::CustomSection { name, data, .. } if name.starts_with(".debug_") => {
+                    debug_sections.insert(name, data);
+                }
+                _ => {}
+            }
+        }
+
+        let mut functions: BTreeMap<u32, String> = names
+            .into_iter()
+            .filter_map(|(index, name)| Some((index.checked_sub(imported_functions)?, name)))
+            .collect();
+        if !debug_sections.is_empty() {
+            for (fn_index, name) in dwarf_names(&debug_sections, &body_ends)? {
+                functions.entry(fn_index).or_insert(name);
+            }
+        }
+
+        let table_functions = elements
+            .into_iter()
+            .filter_map(|(element, function)| {
+                Some((element, function.checked_sub(imported_functions)?))
+            })
+            .collect();
+        let start_function = start.and_then(|start| start.checked_sub(imported_functions));
+
+        Ok(Symbols {
+            functions,
+            imports,
+            table_functions,
+            start_function,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// The name of a local function as it appears in the module, i.e. mangled.
+    pub fn function_name(&self, fn_index: u32) -> Option<&str> {
+        self.functions.get(&fn_index).map(String::as_str)
+    }
+
+    /// A human readable description of a function, e.g. `hackatom::contract::execute`,
+    /// or `fn 17` for functions without a name.
+    pub fn describe_function(&self, fn_index: u32) -> String {
+        match self.function_name(fn_index) {
+            // The alternate format omits the hash suffix of Rust symbols.
+            Some(name) => format!("{:#}", rustc_demangle::demangle(name)),
+            None => format!("fn {}", fn_index),
+        }
+    }
+
+    /// The module and field name of an imported function, e.g. `env.db_read`.
+    pub fn import_name(&self, import_index: u32) -> Option<&str> {
+        self.imports.get(&import_index).map(String::as_str)
+    }
+
+    /// Like `import_name`, but `import 3` for unknown imports.
+    pub fn describe_import(&self, import_index: u32) -> String {
+        match self.import_name(import_index) {
+            Some(name) => name.to_string(),
+            None => format!("import {}", import_index),
+        }
+    }
+
+    /// The local function at `element_index` in a table as initialized by the module.
+    /// `None` for imported functions, empty elements and elements written at runtime.
+    pub fn table_function(&self, table_index: u32, element_index: u32) -> Option<u32> {
+        self.table_functions
+            .get(&(table_index, element_index))
+            .copied()
+    }
+
+    /// The local function the module runs when it is instantiated, if it has one.
+    /// Its measurements are taken before the first call into the module.
+    pub fn start_function(&self) -> Option<u32> {
+        self.start_function
+    }
+
+    /// A human readable description of a basic block, e.g. `hackatom::contract::execute, block 3`.
+    pub fn describe_block(&self, fn_index: u32, local_block_id: u32) -> String {
+        format!(
+            "{}, block {}",
+            self.describe_function(fn_index),
+            local_block_id
+        )
+    }
+}
+
+/// The source lines of the code of a Wasm module, read from the line tables of its
+/// DWARF debug info. Addresses are relative to the start of the code section like
+/// the ones of [`block_offsets`](crate::instrumentation::block_offsets). Modules
+/// without debug info have no lines.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceLines {
+    /// The paths of all source files, indexed by the rows
+    files: Vec<String>,
+    /// The file index and line starting at every address, sorted by address. Ends of
+    /// sequences have no line.
+    rows: Vec<(u64, Option<(usize, u64)>)>,
+}
+
+impl SourceLines {
+    pub fn from_wasm(wasm: &[u8]) -> Result<Self, SymbolsError> {
+        let mut debug_sections = BTreeMap::new();
+        for payload in Parser::new(0).parse_all(wasm) {
+            if let Payload::CustomSection { name, data, .. } = payload? {
+                if name.starts_with(".debug_") {
+                    debug_sections.insert(name, data);
+                }
+            }
+        }
+        if debug_sections.is_empty() {
+            return Ok(SourceLines::default());
+        }
+
+        let dwarf = load_dwarf(&debug_sections)?;
+        let mut files = Vec::new();
+        let mut file_indexes = HashMap::new();
+        let mut rows = Vec::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match unit.line_program.clone() {
+                Some(program) => program,
+                None => continue,
+            };
+            let mut program_rows = program.rows();
+            while let Some((header, row)) = program_rows.next_row()? {
+                if row.end_sequence() {
+                    rows.push((row.address(), None));
+                    continue;
+                }
+                let (line, file) = match (row.line(), row.file(header)) {
+                    (Some(line), Some(file)) => (line.get(), file),
+                    _ => continue,
+                };
+                let mut path = dwarf
+                    .attr_string(&unit, file.path_name())?
+                    .to_string_lossy()
+                    .into_owned();
+                if !path.starts_with('/') {
+                    if let Some(directory) = file.directory(header) {
+                        let directory = dwarf.attr_string(&unit, directory)?;
+                        path = format!("{}/{}", directory.to_string_lossy(), path);
+                    }
+                }
+                let file_index = *file_indexes.entry(path.clone()).or_insert_with(|| {
+                    files.push(path);
+                    files.len() - 1
+                });
+                rows.push((row.address(), Some((file_index, line))));
+            }
+        }
+        // A sequence may start where another one ends.
+        rows.sort_by_key(|(address, location)| (*address, location.is_some()));
+
+        Ok(SourceLines { files, rows })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_rows(files: Vec<String>, rows: Vec<(u64, Option<(usize, u64)>)>) -> Self {
+        SourceLines { files, rows }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The file paths and lines of the code from the `first` to the `last` operator
+    /// of a block, sorted by path and line and without duplicates.
+    pub fn lines(&self, first: u32, last: u32) -> Vec<(&str, u64)> {
+        let first = u64::from(first);
+        let last = u64::from(last);
+        // The row in effect at the first operator and all rows starting within the block
+        let start = self
+            .rows
+            .partition_point(|(address, _)| *address <= first)
+            .saturating_sub(1);
+        let lines: BTreeSet<_> = self.rows[start..]
+            .iter()
+            .take_while(|(address, _)| *address <= last)
+            .filter_map(|(_, location)| *location)
+            .map(|(file_index, line)| (self.files[file_index].as_str(), line))
+            .collect();
+        lines.into_iter().collect()
+    }
+}
+
+fn load_dwarf<'a>(
+    sections: &BTreeMap<&str, &'a [u8]>,
+) -> Result<gimli::Dwarf<gimli::EndianSlice<'a, gimli::LittleEndian>>, SymbolsError> {
+    use gimli::{EndianSlice, LittleEndian};
+
+    let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+        let data = sections.get(id.name()).copied().unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    })?;
+    Ok(dwarf)
+}
+
+/// Finds the names of the subprograms in the DWARF debug info and maps them to the
+/// local functions whose code contains their address.
+fn dwarf_names(
+    sections: &BTreeMap<&str, &[u8]>,
+    body_ends: &[usize],
+) -> Result<Vec<(u32, String)>, SymbolsError> {
+    use gimli::AttributeValue;
+
+    let dwarf = load_dwarf(sections)?;
+
+    let mut names = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+            let address = match entry.attr_value(gimli::DW_AT_low_pc)? {
+                // Address 0 is used for functions removed by the linker.
+                Some(AttributeValue::Addr(address)) if address > 0 => address as usize,
+                _ => continue,
+            };
+            let name = match entry.attr_value(gimli::DW_AT_linkage_name)? {
+                Some(name) => Some(name),
+                None => entry.attr_value(gimli::DW_AT_name)?,
+            };
+            let name = match name {
+                Some(name) => dwarf.attr_string(&unit, name)?,
+                None => continue,
+            };
+            // The first function whose code ends after the address contains it.
+            let fn_index = body_ends.iter().position(|end| address < *end);
+            if let Some(fn_index) = fn_index {
+                names.push((fn_index as u32, name.to_string_lossy().into_owned()));
+            }
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::wat2wasm;
+
+    const TRANSFER: &str = "_ZN8hackatom7execute8transfer17h0123456789abcdefE";
+
+    #[test]
+    fn from_wasm_reads_name_section() {
+        let wat = format!(
+            r#"(module
+            (import "env" "abort" (func $abort))
+            (func ${} nop)
+            (func nop)
+            (func $plain nop))"#,
+            TRANSFER
+        );
+        let wasm = wat2wasm(wat.as_bytes()).unwrap();
+
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.function_name(0), Some(TRANSFER));
+        assert_eq!(symbols.function_name(1), None);
+        assert_eq!(symbols.describe_function(0), "hackatom::execute::transfer");
+        assert_eq!(symbols.describe_function(1), "fn 1");
+        assert_eq!(symbols.describe_function(2), "plain");
+        assert_eq!(
+            symbols.describe_block(0, 3),
+            "hackatom::execute::transfer, block 3"
+        );
+        assert_eq!(symbols.import_name(0), Some("env.abort"));
+        assert_eq!(symbols.describe_import(1), "import 1");
+    }
+
+    #[test]
+    fn from_wasm_reads_tables_and_start() {
+        let wasm = wat2wasm(
+            br#"(module
+            (import "env" "abort" (func $abort))
+            (import "env" "base" (global $base i32))
+            (table 8 funcref)
+            (func $a nop)
+            (func $b nop)
+            (func $init nop)
+            (elem (i32.const 2) $b $abort $a)
+            (elem (global.get $base) $init)
+            (start $init))"#,
+        )
+        .unwrap();
+
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+        assert_eq!(symbols.table_function(0, 2), Some(1));
+        assert_eq!(symbols.table_function(0, 3), None);
+        assert_eq!(symbols.table_function(0, 4), Some(0));
+        assert_eq!(symbols.table_function(0, 5), None);
+        assert_eq!(symbols.table_function(1, 2), None);
+        assert_eq!(symbols.start_function(), Some(2));
+    }
+
+    /// Appends a custom section to a Wasm module.
+    fn append_custom_section(wasm: &mut Vec<u8>, name: &str, data: &[u8]) {
+        fn leb128(mut value: usize, out: &mut Vec<u8>) {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    out.push(byte);
+                    return;
+                }
+                out.push(byte | 0x80);
+            }
+        }
+
+        let mut payload = Vec::new();
+        leb128(name.len(), &mut payload);
+        payload.extend_from_slice(name.as_bytes());
+        payload.extend_from_slice(data);
+        wasm.push(0);
+        leb128(payload.len(), wasm);
+        wasm.extend(payload);
+    }
+
+    #[test]
+    fn from_wasm_reads_dwarf() {
+        use gimli::write::{Address, AttributeValue, DwarfUnit, EndianVec, Sections};
+
+        let mut wasm = wat2wasm(
+            br#"(module
+            (func $named nop)
+            (func nop)
+            (func i32.const 1 drop))"#,
+        )
+        .unwrap()
+        .into_owned();
+
+        // The offsets of the function bodies within the code section
+        let mut code_start = 0;
+        let mut bodies = Vec::new();
+        for payload in Parser::new(0).parse_all(&wasm) {
+            match payload.unwrap() {
+                Payload::CodeSectionStart { range, .. } => code_start = range.start,
+                Payload::CodeSectionEntry(body) => bodies.push(body.range().start - code_start),
+                _ => {}
+            }
+        }
+
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 4,
+        };
+        let mut dwarf = DwarfUnit::new(encoding);
+        let root = dwarf.unit.root();
+        for (body, name, attribute) in [
+            (bodies[0], "ignored", gimli::DW_AT_linkage_name),
+            (bodies[1], TRANSFER, gimli::DW_AT_linkage_name),
+            (bodies[2], "helper", gimli::DW_AT_name),
+        ] {
+            let subprogram = dwarf.unit.add(root, gimli::DW_TAG_subprogram);
+            let entry = dwarf.unit.get_mut(subprogram);
+            entry.set(
+                gimli::DW_AT_low_pc,
+                AttributeValue::Address(Address::Constant(body as u64)),
+            );
+            entry.set(attribute, AttributeValue::String(name.as_bytes().to_vec()));
+        }
+        let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        sections
+            .for_each(|id, data| -> Result<(), ()> {
+                if !data.slice().is_empty() {
+                    append_custom_section(&mut wasm, id.name(), data.slice());
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let symbols = Symbols::from_wasm(&wasm).unwrap();
+        // The name section takes precedence.
+        assert_eq!(symbols.function_name(0), Some("named"));
+        assert_eq!(symbols.describe_function(1), "hackatom::execute::transfer");
+        assert_eq!(symbols.describe_function(2), "helper");
+    }
+
+    #[test]
+    fn source_lines_from_wasm_reads_line_tables() {
+        use gimli::write::{
+            Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections,
+        };
+
+        let mut wasm = wat2wasm(br#"(module (func i32.const 1 drop))"#)
+            .unwrap()
+            .into_owned();
+        assert!(SourceLines::from_wasm(&wasm).unwrap().is_empty());
+
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 4,
+        };
+        let mut dwarf = DwarfUnit::new(encoding);
+        let mut program = LineProgram::new(
+            encoding,
+            gimli::LineEncoding::default(),
+            LineString::String(b"/contract".to_vec()),
+            LineString::String(b"src/lib.rs".to_vec()),
+            None,
+        );
+        let directory = program.default_directory();
+        let lib = program.add_file(LineString::String(b"src/lib.rs".to_vec()), directory, None);
+        let absolute =
+            program.add_file(LineString::String(b"/std/mem.rs".to_vec()), directory, None);
+        program.begin_sequence(Some(Address::Constant(10)));
+        for (address_offset, file, line) in [(0, lib, 3), (4, absolute, 7), (8, lib, 5)] {
+            program.row().address_offset = address_offset;
+            program.row().file = file;
+            program.row().line = line;
+            program.generate_row();
+        }
+        program.end_sequence(12);
+        dwarf.unit.line_program = program;
+        // Readers take the default directory from the compilation unit.
+        let root = dwarf.unit.root();
+        dwarf.unit.get_mut(root).set(
+            gimli::DW_AT_comp_dir,
+            AttributeValue::String(b"/contract".to_vec()),
+        );
+
+        let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        sections
+            .for_each(|id, data| -> Result<(), ()> {
+                if !data.slice().is_empty() {
+                    append_custom_section(&mut wasm, id.name(), data.slice());
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let lines = SourceLines::from_wasm(&wasm).unwrap();
+        // Before the start of the sequence
+        assert_eq!(lines.lines(0, 9), []);
+        assert_eq!(lines.lines(10, 11), [("/contract/src/lib.rs", 3)]);
+        assert_eq!(
+            lines.lines(12, 19),
+            [
+                ("/contract/src/lib.rs", 3),
+                ("/contract/src/lib.rs", 5),
+                ("/std/mem.rs", 7)
+            ]
+        );
+        assert_eq!(lines.lines(18, 20), [("/contract/src/lib.rs", 5)]);
+        // After the end of the sequence
+        assert_eq!(lines.lines(22, 30), []);
+    }
+}
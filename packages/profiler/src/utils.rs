@@ -1,4 +1,4 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::hash::Hash;
 
 /// For KV stores that keep vectors/vecdeques as their values, provides a way
@@ -29,3 +29,27 @@ impl<K: Eq + Hash + Clone, I> InsertPush<K, I> for HashMap<K, VecDeque<I>> {
         q.push_back(item);
     }
 }
+
+/// Appends one column per metadata entry to the header of a CSV export.
+pub fn with_metadata_keys<S: AsRef<str>>(
+    header: impl IntoIterator<Item = S>,
+    metadata: &BTreeMap<String, String>,
+) -> Vec<String> {
+    header
+        .into_iter()
+        .map(|field| field.as_ref().to_string())
+        .chain(metadata.keys().cloned())
+        .collect()
+}
+
+/// Appends the values of all metadata entries to a row of a CSV export, matching
+/// the header created by `with_metadata_keys`.
+pub fn with_metadata_values<S: AsRef<str>>(
+    row: impl IntoIterator<Item = S>,
+    metadata: &BTreeMap<String, String>,
+) -> Vec<String> {
+    row.into_iter()
+        .map(|field| field.as_ref().to_string())
+        .chain(metadata.values().cloned())
+        .collect()
+}
@@ -0,0 +1,294 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use cosmwasm_vm::{InstanceOptions, Instrumentation};
+use wasmer::{Exports, ModuleMiddleware, Store};
+
+use crate::{
+    cache::InstrumentationCache,
+    clock::{Clock, WallClock},
+    instrumentation::{add_measuring_imports, instrument_wasm, Profiling},
+    measure::Measurements,
+    metrics::{MetricsSink, ProfileMetrics, DEFAULT_BUCKETS},
+    report::Report,
+    session::Session,
+    symbols::Symbols,
+};
+
+/// Profiles contracts running in a regular `cosmwasm_vm::Instance`, called through
+/// `call_instantiate`, `call_execute`, `call_query` etc.
+///
+/// Create the instance with [`VmProfiling::instance_options`], which compiles the
+/// contract with the `Profiling` middleware and adds the profiling imports to the
+/// standard imports. Then wrap every call in [`VmProfiling::profile`] to get a report
+/// along with its result.
+pub struct VmProfiling<C: Clock = WallClock> {
+    profiling: Arc<Profiling>,
+    measurements: Arc<Mutex<Measurements<C>>>,
+    /// The symbols of the most recently instrumented contract
+    symbols: Arc<Mutex<Symbols>>,
+    cache: Option<InstrumentationCache>,
+}
+
+impl<C: Clock> Clone for VmProfiling<C> {
+    fn clone(&self) -> Self {
+        Self {
+            profiling: self.profiling.clone(),
+            measurements: self.measurements.clone(),
+            symbols: self.symbols.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+/// The result of a call profiled with [`VmProfiling::profile`].
+#[derive(Debug, Clone)]
+pub struct Profiled<T> {
+    pub result: T,
+    pub report: Report,
+}
+
+impl<C: Clock> VmProfiling<C> {
+    /// Event recording is enabled on `measurements` if needed, since reports
+    /// are built from the call graph.
+    pub fn new(profiling: Profiling, measurements: Measurements<C>) -> Self {
+        let measurements = if measurements.events.is_some() {
+            measurements
+        } else {
+            measurements.with_event_recording()
+        };
+        Self {
+            profiling: Arc::new(profiling),
+            measurements: Arc::new(Mutex::new(measurements)),
+            symbols: Arc::new(Mutex::new(Symbols::default())),
+            cache: None,
+        }
+    }
+
+    /// Reuses contracts instrumented by earlier instances or runs from `cache`.
+    pub fn with_instrumentation_cache(mut self, cache: InstrumentationCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Options for `Instance::from_code` that instrument the contract for this profiling.
+    pub fn instance_options(&self, gas_limit: u64, print_debug: bool) -> InstanceOptions {
+        InstanceOptions {
+            gas_limit,
+            print_debug,
+            instrumentation: Some(Arc::new(self.clone())),
+            wasm_costs: None,
+        }
+    }
+
+    pub fn profiling(&self) -> &Profiling {
+        &self.profiling
+    }
+
+    /// All measurements taken by instances created with [`VmProfiling::instance_options`]
+    pub fn measurements(&self) -> &Arc<Mutex<Measurements<C>>> {
+        &self.measurements
+    }
+
+    /// The function names of the most recently instrumented contract
+    pub fn symbols(&self) -> Symbols {
+        self.symbols.lock().unwrap().clone()
+    }
+
+    /// Runs `call` and reports the measurements taken during it, e.g. of
+    /// `|| call_execute(&mut instance, &env, &info, msg)`. The measurements of
+    /// earlier calls are discarded, the metadata is kept.
+    pub fn profile<T>(&self, call: impl FnOnce() -> T) -> Profiled<T> {
+        self.measurements.lock().unwrap().clear();
+        let result = call();
+
+        let mut measurements = self.measurements.lock().unwrap();
+        measurements.end_invocation();
+        let report = self.report(&measurements);
+        Profiled { result, report }
+    }
+
+    /// A session on the measurements of this profiling, to delimit calls that are not
+    /// wrapped in [`VmProfiling::profile`], e.g. calls made by a long-lived VM.
+    /// Pass what [`Session::reset`] returns to [`VmProfiling::report`].
+    pub fn session(&self) -> Session<C> {
+        Session::from_shared(self.measurements.clone())
+    }
+
+    /// Reports `measurements` taken by instances of this profiling, e.g. a snapshot of
+    /// its [`VmProfiling::session`]. Event recording is enabled by [`VmProfiling::new`].
+    pub fn report(&self, measurements: &Measurements<C>) -> Report {
+        let events = measurements.events.as_deref().unwrap_or_default();
+        Report::from_events(C::UNIT, events, self.profiling.granularity())
+            .with_blocks(measurements)
+            .with_symbols(&self.symbols.lock().unwrap())
+            .with_metadata(&measurements.metadata)
+    }
+
+    /// Pushes the aggregates of the most recently profiled call to `sink`, see
+    /// [`ProfileMetrics`]. Call this after every [`VmProfiling::profile`] to monitor
+    /// the contracts running on a node continuously.
+    pub fn push_metrics(&self, sink: &mut dyn MetricsSink) -> io::Result<()> {
+        let metrics = ProfileMetrics::new(
+            &self.measurements.lock().unwrap(),
+            &self.symbols.lock().unwrap(),
+            self.profiling.granularity(),
+            &DEFAULT_BUCKETS,
+        );
+        sink.push(&metrics)
+    }
+}
+
+impl<C: Clock> Instrumentation for VmProfiling<C> {
+    fn prepare_wasm(&self, wasm: &[u8]) -> Result<Vec<u8>, String> {
+        let wasm = match &self.cache {
+            Some(cache) => cache.instrument_wasm(wasm, &self.profiling),
+            None => instrument_wasm(wasm, &self.profiling),
+        }
+        .map_err(|err| err.to_string())?;
+        *self.symbols.lock().unwrap() = Symbols::from_wasm(&wasm).map_err(|err| err.to_string())?;
+        Ok(wasm)
+    }
+
+    fn middleware(&self) -> Arc<dyn ModuleMiddleware> {
+        self.profiling.clone()
+    }
+
+    fn end_compilation(&self) {
+        self.profiling.end_compilation();
+    }
+
+    fn import_module(&self) -> &str {
+        self.profiling.import_module()
+    }
+
+    fn imports(&self, store: &Store) -> Exports {
+        let mut imports = Exports::new();
+        add_measuring_imports(
+            &self.profiling,
+            store,
+            self.measurements.clone(),
+            &mut imports,
+        );
+        imports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cosmwasm_std::Empty;
+    use cosmwasm_vm::{
+        call_instantiate, call_query,
+        testing::{mock_backend, mock_env, mock_info},
+        Instance,
+    };
+
+    use crate::{
+        code_blocks::BlockStore,
+        instrumentation::{Granularity, GAS_LIMIT},
+    };
+
+    static HACKATOM: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+    fn vm_profiling() -> VmProfiling {
+        let block_store = Arc::new(Mutex::new(BlockStore::new()));
+        let profiling = Profiling::new(block_store, Granularity::Function);
+        VmProfiling::new(profiling, Measurements::new())
+    }
+
+    #[test]
+    fn profile_reports_entry_point_calls() {
+        let profiling = vm_profiling();
+        let options = profiling.instance_options(GAS_LIMIT, false);
+        let mut instance = Instance::from_code(HACKATOM, mock_backend(&[]), options, None).unwrap();
+
+        let info = mock_info("creator", &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        let instantiated = profiling.profile(|| {
+            call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg).unwrap()
+        });
+        assert!(instantiated.result.into_result().is_ok());
+        assert!(!instantiated.report.functions.is_empty());
+        assert_eq!(instantiated.report.unit, "ns");
+
+        let queried = profiling
+            .profile(|| call_query(&mut instance, &mock_env(), br#"{"verifier":{}}"#).unwrap());
+        assert!(queried.result.into_result().is_ok());
+        assert!(!queried.report.functions.is_empty());
+        assert_ne!(queried.report, instantiated.report);
+    }
+
+    #[test]
+    fn profile_only_reports_the_profiled_call() {
+        let profiling = vm_profiling();
+        let options = profiling.instance_options(GAS_LIMIT, false);
+        let mut instance = Instance::from_code(HACKATOM, mock_backend(&[]), options, None).unwrap();
+
+        let msg = br#"{"verifier":{}}"#;
+        let queried = profiling.profile(|| call_query(&mut instance, &mock_env(), msg).unwrap());
+        assert!(!queried.report.functions.is_empty());
+
+        let idle = profiling.profile(|| ());
+        assert!(idle.report.functions.is_empty());
+        assert!(idle.report.blocks.is_empty());
+    }
+
+    #[test]
+    fn session_delimits_unwrapped_calls() {
+        let profiling = vm_profiling();
+        let options = profiling.instance_options(GAS_LIMIT, false);
+        let mut instance = Instance::from_code(HACKATOM, mock_backend(&[]), options, None).unwrap();
+        let session = profiling.session();
+
+        let info = mock_info("creator", &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg).unwrap();
+        session.measurements().lock().unwrap().end_invocation();
+        let instantiated = profiling.report(&session.reset());
+
+        let msg = br#"{"verifier":{}}"#;
+        call_query(&mut instance, &mock_env(), msg).unwrap();
+        session.measurements().lock().unwrap().end_invocation();
+        let snapshot = profiling.report(&session.snapshot());
+        let queried = profiling.report(&session.reset());
+
+        assert!(!instantiated.functions.is_empty());
+        assert!(!queried.functions.is_empty());
+        assert_ne!(instantiated, queried);
+        assert_eq!(snapshot, queried);
+        // Same as profiling the call
+        let profiled = profiling.profile(|| call_query(&mut instance, &mock_env(), msg).unwrap());
+        for (fn_index, function) in &queried.functions {
+            assert_eq!(profiled.report.functions[fn_index].calls, function.calls);
+        }
+    }
+
+    #[test]
+    fn push_metrics_works() {
+        struct Collect(Vec<ProfileMetrics>);
+        impl MetricsSink for Collect {
+            fn push(&mut self, metrics: &ProfileMetrics) -> io::Result<()> {
+                self.0.push(metrics.clone());
+                Ok(())
+            }
+        }
+
+        let profiling = vm_profiling();
+        let options = profiling.instance_options(GAS_LIMIT, false);
+        let mut instance = Instance::from_code(HACKATOM, mock_backend(&[]), options, None).unwrap();
+
+        let msg = br#"{"verifier":{}}"#;
+        let queried = profiling.profile(|| call_query(&mut instance, &mock_env(), msg).unwrap());
+        let mut sink = Collect(Vec::new());
+        profiling.push_metrics(&mut sink).unwrap();
+
+        let metrics = &sink.0[0];
+        assert_eq!(metrics.unit, "ns");
+        for (fn_index, function) in &queried.report.functions {
+            let name = profiling.symbols().describe_function(*fn_index);
+            assert_eq!(metrics.functions[&name].calls, function.calls);
+        }
+    }
+}
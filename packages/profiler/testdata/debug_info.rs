@@ -0,0 +1,44 @@
+// Source of debug_info.wasm, a tiny module with DWARF debug info for testing.
+// It does not use core, so that it builds without the standard library for
+// wasm32. Build with:
+//
+// rustc +nightly --target wasm32-unknown-unknown --crate-type cdylib \
+//   -C debuginfo=2 -C opt-level=0 -C overflow-checks=off -C panic=abort \
+//   --remap-path-prefix=$(pwd)=/contract -A internal_features \
+//   debug_info.rs -o debug_info.wasm
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+impl Copy for i32 {}
+
+#[lang = "add"]
+pub trait Add<Rhs = Self> {
+    type Output;
+    fn add(self, rhs: Rhs) -> Self::Output;
+}
+
+impl Add for i32 {
+    type Output = i32;
+    fn add(self, rhs: i32) -> i32 {
+        self + rhs
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn add_one(x: i32) -> i32 {
+    x + 1
+}
+
+#[no_mangle]
+pub extern "C" fn add_two(x: i32) -> i32 {
+    let y = add_one(x);
+    add_one(y)
+}
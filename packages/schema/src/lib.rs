@@ -1,9 +1,18 @@
+// Makes the code generated by the QueryResponses derive work in the tests of this crate
+extern crate self as cosmwasm_schema;
+
 mod casing;
 mod export;
+mod openapi;
+mod query_responses;
 mod remove;
 
 pub use export::{export_schema, export_schema_with_title};
+pub use openapi::{export_openapi, openapi_for_queries, ConflictingComponent};
+pub use query_responses::QueryResponses;
 pub use remove::remove_schemas;
 
 // Re-exports
+pub use cosmwasm_derive::QueryResponses;
+pub use schemars::schema::RootSchema;
 pub use schemars::schema_for;
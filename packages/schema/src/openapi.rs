@@ -0,0 +1,375 @@
+//! Export the query interface of a contract as an OpenAPI document
+
+use std::fmt;
+use std::fs::write;
+use std::path::Path;
+
+use schemars::schema::RootSchema;
+use serde_json::{json, Map, Value};
+
+use crate::casing::to_snake_case;
+
+const DEFINITIONS_PREFIX: &str = "#/definitions/";
+const COMPONENTS_PREFIX: &str = "#/components/schemas/";
+
+/// Two different schemas with the same name were added to `components/schemas`,
+/// e.g. two response types with the same name from different modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingComponent {
+    pub name: String,
+}
+
+impl fmt::Display for ConflictingComponent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Different schemas use the component name {}. Rename one of the types or set a different schema title.",
+            self.name
+        )
+    }
+}
+
+impl std::error::Error for ConflictingComponent {}
+
+/// Creates an OpenAPI 3.1 document describing the queries of a contract.
+///
+/// Every variant of the query message becomes a `POST /{variant}` endpoint whose
+/// JSON request body is the full query message, e.g. `{"get_count":{}}`.
+/// `responses` maps variant names (in snake case, as they appear in JSON) to the
+/// schema of their response. Variants without an entry are documented without
+/// a response schema.
+///
+/// All definitions of the given schemas are moved to `components/schemas` and
+/// references are rewritten accordingly. Components are named after the definition
+/// or the schema title. If two different schemas share a name, an error is returned
+/// instead of one of them overwriting the other.
+pub fn openapi_for_queries(
+    title: &str,
+    version: &str,
+    query_msg: &RootSchema,
+    responses: &[(&str, RootSchema)],
+) -> Result<Value, ConflictingComponent> {
+    let mut components = Map::new();
+    let query_msg = into_component_schema(query_msg, &mut components)?;
+
+    let mut paths = Map::new();
+    for (variant, request_schema) in query_variants(&query_msg) {
+        let description = request_schema
+            .get("description")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        let response = match responses.iter().find(|(name, _)| *name == variant) {
+            Some((_, response_schema)) => {
+                let response_ref = add_response_component(response_schema, &mut components)?;
+                json!({
+                    "description": "The query response",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": response_ref }
+                        }
+                    }
+                })
+            }
+            None => json!({ "description": "The query response" }),
+        };
+
+        let mut operation = Map::new();
+        operation.insert("operationId".to_string(), json!(variant));
+        if let Some(description) = description {
+            operation.insert("description".to_string(), json!(description));
+        }
+        operation.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": request_schema
+                    }
+                }
+            }),
+        );
+        operation.insert("responses".to_string(), json!({ "200": response }));
+
+        paths.insert(format!("/{}", variant), json!({ "post": operation }));
+    }
+
+    Ok(json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": title,
+            "version": version,
+        },
+        "paths": paths,
+        "components": {
+            "schemas": components,
+        },
+    }))
+}
+
+/// Writes the OpenAPI document created by [`openapi_for_queries`] to
+/// `{out_dir}/{title}_openapi.json`. Overwrites an existing file.
+/// Panics on conflicting component names and on any error writing out the document.
+pub fn export_openapi(
+    title: &str,
+    version: &str,
+    query_msg: &RootSchema,
+    responses: &[(&str, RootSchema)],
+    out_dir: &Path,
+) {
+    let document = openapi_for_queries(title, version, query_msg, responses).unwrap();
+    let path = out_dir.join(format!("{}_openapi.json", to_snake_case(title)));
+    let json = serde_json::to_string_pretty(&document).unwrap();
+    write(&path, json + "\n").unwrap();
+    println!("Created {}", path.to_str().unwrap());
+}
+
+/// Moves the definitions of a root schema into `components` and returns the
+/// main schema with rewritten references.
+fn into_component_schema(
+    schema: &RootSchema,
+    components: &mut Map<String, Value>,
+) -> Result<Value, ConflictingComponent> {
+    for (name, definition) in &schema.definitions {
+        let mut definition = serde_json::to_value(definition).unwrap();
+        rewrite_refs(&mut definition);
+        insert_component(components, name, definition)?;
+    }
+    let mut main = serde_json::to_value(&schema.schema).unwrap();
+    rewrite_refs(&mut main);
+    Ok(main)
+}
+
+/// Adds a response schema to `components` and returns a reference to it.
+fn add_response_component(
+    schema: &RootSchema,
+    components: &mut Map<String, Value>,
+) -> Result<String, ConflictingComponent> {
+    let main = into_component_schema(schema, components)?;
+    let title = main
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or("untitled")
+        .to_string();
+    insert_component(components, &title, main)?;
+    Ok(format!("{}{}", COMPONENTS_PREFIX, title))
+}
+
+/// Adds a schema to `components`. The same type is usually added several times,
+/// e.g. as a definition of multiple responses, so only a different schema with
+/// an existing name is an error.
+fn insert_component(
+    components: &mut Map<String, Value>,
+    name: &str,
+    schema: Value,
+) -> Result<(), ConflictingComponent> {
+    match components.get(name) {
+        Some(existing) if *existing != schema => Err(ConflictingComponent {
+            name: name.to_string(),
+        }),
+        _ => {
+            components.insert(name.to_string(), schema);
+            Ok(())
+        }
+    }
+}
+
+/// Returns the name and schema of every variant of a query message schema created
+/// by schemars. Struct and tuple variants are objects with a single required property.
+/// Unit variants are string enums.
+fn query_variants(query_msg: &Value) -> Vec<(String, Value)> {
+    let alternatives = match query_msg.get("oneOf").or_else(|| query_msg.get("anyOf")) {
+        Some(Value::Array(alternatives)) => alternatives.clone(),
+        _ => vec![query_msg.clone()],
+    };
+
+    let mut variants = Vec::new();
+    for alternative in alternatives {
+        if let Some(Value::Array(values)) = alternative.get("enum") {
+            for value in values.iter().filter_map(Value::as_str) {
+                let mut schema = alternative.clone();
+                schema["enum"] = json!([value]);
+                variants.push((value.to_string(), schema));
+            }
+        } else if let Some(Value::Array(required)) = alternative.get("required") {
+            if let [Value::String(name)] = required.as_slice() {
+                variants.push((name.clone(), alternative.clone()));
+            }
+        }
+    }
+    variants
+}
+
+fn rewrite_refs(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, inner) in map.iter_mut() {
+                match inner {
+                    Value::String(reference) if key == "$ref" => {
+                        if let Some(name) = reference.strip_prefix(DEFINITIONS_PREFIX) {
+                            *reference = format!("{}{}", COMPONENTS_PREFIX, name);
+                        }
+                    }
+                    _ => rewrite_refs(inner),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use schemars::{schema_for, JsonSchema};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    #[allow(dead_code)]
+    enum QueryMsg {
+        /// Returns the current count
+        GetCount {},
+        Owner {
+            at: Option<Height>,
+        },
+        Version,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    struct Height {
+        height: u64,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    struct CountResponse {
+        count: Height,
+    }
+
+    #[test]
+    fn openapi_for_queries_works() {
+        let document = openapi_for_queries(
+            "Counter",
+            "1.2.3",
+            &schema_for!(QueryMsg),
+            &[("get_count", schema_for!(CountResponse))],
+        )
+        .unwrap();
+
+        assert_eq!(document["openapi"], "3.1.0");
+        assert_eq!(document["info"]["title"], "Counter");
+        assert_eq!(document["info"]["version"], "1.2.3");
+
+        let paths = document["paths"].as_object().unwrap();
+        let mut names: Vec<&String> = paths.keys().collect();
+        names.sort();
+        assert_eq!(names, ["/get_count", "/owner", "/version"]);
+
+        let get_count = &paths["/get_count"]["post"];
+        assert_eq!(get_count["operationId"], "get_count");
+        assert_eq!(get_count["description"], "Returns the current count");
+        assert_eq!(
+            get_count["requestBody"]["content"]["application/json"]["schema"]["required"],
+            json!(["get_count"])
+        );
+        assert_eq!(
+            get_count["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/CountResponse"
+        );
+
+        // Variants without a response
+        let owner = &paths["/owner"]["post"];
+        assert_eq!(
+            owner["responses"]["200"],
+            json!({ "description": "The query response" })
+        );
+        // References point to components
+        assert_eq!(
+            owner["requestBody"]["content"]["application/json"]["schema"]["properties"]["owner"]
+                ["properties"]["at"]["anyOf"][0]["$ref"],
+            "#/components/schemas/Height"
+        );
+
+        // Unit variants
+        let version = &paths["/version"]["post"];
+        assert_eq!(
+            version["requestBody"]["content"]["application/json"]["schema"]["enum"],
+            json!(["version"])
+        );
+
+        let components = document["components"]["schemas"].as_object().unwrap();
+        let mut names: Vec<&String> = components.keys().collect();
+        names.sort();
+        assert_eq!(names, ["CountResponse", "Height"]);
+        assert_eq!(
+            components["CountResponse"]["properties"]["count"]["$ref"],
+            "#/components/schemas/Height"
+        );
+    }
+
+    mod other {
+        use super::*;
+
+        #[derive(Serialize, Deserialize, JsonSchema)]
+        pub struct Height {
+            pub revision: u64,
+        }
+
+        #[derive(Serialize, Deserialize, JsonSchema)]
+        pub struct CountResponse {
+            pub count: Height,
+        }
+    }
+
+    #[test]
+    fn openapi_for_queries_fails_for_conflicting_components() {
+        // The response contains a different type named Height than the query message
+        let err = openapi_for_queries(
+            "Counter",
+            "1.2.3",
+            &schema_for!(QueryMsg),
+            &[("get_count", schema_for!(other::CountResponse))],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ConflictingComponent {
+                name: "Height".to_string()
+            }
+        );
+
+        // The same type in multiple responses is fine
+        openapi_for_queries(
+            "Counter",
+            "1.2.3",
+            &schema_for!(QueryMsg),
+            &[
+                ("get_count", schema_for!(CountResponse)),
+                ("owner", schema_for!(CountResponse)),
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rewrite_refs_works() {
+        let mut value = json!({
+            "$ref": "#/definitions/Foo",
+            "items": [{ "$ref": "#/definitions/Bar" }, { "$ref": "http://example.com/x" }],
+        });
+        rewrite_refs(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "$ref": "#/components/schemas/Foo",
+                "items": [
+                    { "$ref": "#/components/schemas/Bar" },
+                    { "$ref": "http://example.com/x" }
+                ],
+            })
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! The responses of contract queries
+
+use schemars::schema::RootSchema;
+
+/// Maps the variants of a query message to the schema of their responses.
+///
+/// Derive this with `#[derive(QueryResponses)]` and annotate every variant with
+/// `#[returns(T)]`. The derive also generates a mock handler answering the queries
+/// in unit tests of other contracts, see [`QueryResponses`](derive@crate::QueryResponses).
+pub trait QueryResponses {
+    /// The response schema of every variant, keyed by the variant name in snake case
+    /// as it appears in JSON. This can be passed to [`openapi_for_queries`](crate::openapi_for_queries).
+    fn response_schemas() -> Vec<(&'static str, RootSchema)>;
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::MockQuerier;
+    use cosmwasm_std::{
+        from_binary, to_binary, Binary, ContractResult, Empty, QuerierWrapper, QueryRequest,
+        StdError, SystemError, SystemResult, WasmQuery,
+    };
+    use schemars::{schema_for, JsonSchema};
+    use serde::{Deserialize, Serialize};
+
+    use crate::QueryResponses;
+
+    #[derive(Serialize, Deserialize, JsonSchema, QueryResponses)]
+    #[serde(rename_all = "snake_case")]
+    enum QueryMsg {
+        #[returns(CountResponse)]
+        GetCount {},
+        #[returns(OwnerResponse)]
+        Owner { at: Option<u64>, verbose: bool },
+        #[returns(Vec<String>)]
+        Members(u32, u32),
+        #[returns(String)]
+        Version,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    struct CountResponse {
+        count: u64,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    struct OwnerResponse {
+        owner: String,
+    }
+
+    fn handler() -> QueryMsgMockHandler {
+        QueryMsg::mock_handler()
+            .on_get_count(|| Ok(CountResponse { count: 7 }))
+            .on_owner(|at, verbose| {
+                Ok(OwnerResponse {
+                    owner: format!("owner at {:?}, verbose: {}", at, verbose),
+                })
+            })
+            .on_members(|start, limit| {
+                Ok((start..start + limit)
+                    .map(|i| format!("member{}", i))
+                    .collect())
+            })
+    }
+
+    #[test]
+    fn response_schemas_works() {
+        let schemas = QueryMsg::response_schemas();
+        let names: Vec<&str> = schemas.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["get_count", "owner", "members", "version"]);
+        assert_eq!(schemas[0].1, schema_for!(CountResponse));
+        assert_eq!(schemas[2].1, schema_for!(Vec<String>));
+    }
+
+    #[test]
+    fn mock_handler_handles_all_kinds_of_variants() {
+        let handler = handler();
+
+        let raw = handler
+            .handle(&to_binary(&QueryMsg::GetCount {}).unwrap())
+            .unwrap()
+            .unwrap();
+        let count: CountResponse = from_binary(&raw).unwrap();
+        assert_eq!(count, CountResponse { count: 7 });
+
+        let raw = handler
+            .handle(br#"{"owner":{"at":5,"verbose":true}}"#)
+            .unwrap()
+            .unwrap();
+        let owner: OwnerResponse = from_binary(&raw).unwrap();
+        assert_eq!(owner.owner, "owner at Some(5), verbose: true");
+
+        let raw = handler
+            .handle(&to_binary(&QueryMsg::Members(3, 2)).unwrap())
+            .unwrap()
+            .unwrap();
+        let members: Vec<String> = from_binary(&raw).unwrap();
+        assert_eq!(members, ["member3", "member4"]);
+    }
+
+    #[test]
+    fn mock_handler_reports_errors() {
+        let handler = handler().on_get_count(|| Err(StdError::generic_err("broken")));
+
+        // Errors of the handler are contract errors
+        match handler.handle(br#"{"get_count":{}}"#) {
+            SystemResult::Ok(ContractResult::Err(err)) => {
+                assert_eq!(err, "Generic error: broken")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Variants without a handler
+        match handler.handle(br#""version""#) {
+            SystemResult::Err(SystemError::UnsupportedRequest { kind }) => {
+                assert_eq!(kind, "QueryMsg::Version")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Messages of other contracts
+        match handler.handle(br#"{"balance":{}}"#) {
+            SystemResult::Err(SystemError::InvalidRequest { request, .. }) => {
+                assert_eq!(request, Binary::from(br#"{"balance":{}}"#))
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn into_wasm_handler_works() {
+        let mut querier: MockQuerier = MockQuerier::new(&[]);
+        querier.update_wasm(handler().into_wasm_handler("counter"));
+        let wrapper = QuerierWrapper::<Empty>::new(&querier);
+
+        let count: CountResponse = wrapper
+            .query_wasm_smart("counter", &QueryMsg::GetCount {})
+            .unwrap();
+        assert_eq!(count, CountResponse { count: 7 });
+
+        let request = QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: "other".to_string(),
+            msg: to_binary(&QueryMsg::GetCount {}).unwrap(),
+        });
+        match querier.handle_query(&request) {
+            SystemResult::Err(SystemError::NoSuchContract { addr }) => assert_eq!(addr, "other"),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+}
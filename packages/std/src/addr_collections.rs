@@ -0,0 +1,345 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::{btree_map, btree_set, BTreeMap, BTreeSet};
+use std::iter::FromIterator;
+
+use crate::addresses::Addr;
+
+/// A set of addresses in canonical order, e.g. for whitelists.
+///
+/// Membership checks take logarithmic time and every address is contained at most
+/// once. The set is serialized as a JSON array of addresses sorted by their string
+/// representation. Deserializing an array that contains an address twice fails, so
+/// that duplicates are noticed rather than silently dropped.
+///
+/// ```
+/// # use cosmwasm_std::{from_slice, Addr, AddrSet};
+/// let set: AddrSet = from_slice(br#"["bob","alice"]"#).unwrap();
+/// assert!(set.contains(&Addr::unchecked("alice")));
+/// assert_eq!(set.iter().next().unwrap().as_str(), "alice");
+///
+/// assert!(from_slice::<AddrSet>(br#"["bob","bob"]"#).is_err());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AddrSet(BTreeSet<Addr>);
+
+impl AddrSet {
+    pub fn new() -> Self {
+        AddrSet(BTreeSet::new())
+    }
+
+    /// Adds an address. Returns false if it was in the set already.
+    pub fn insert(&mut self, addr: Addr) -> bool {
+        self.0.insert(addr)
+    }
+
+    /// Removes an address. Returns false if it was not in the set.
+    pub fn remove(&mut self, addr: &Addr) -> bool {
+        self.0.remove(addr)
+    }
+
+    pub fn contains(&self, addr: &Addr) -> bool {
+        self.0.contains(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over the addresses in canonical order.
+    pub fn iter(&self) -> btree_set::Iter<'_, Addr> {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<Addr> for AddrSet {
+    fn from_iter<I: IntoIterator<Item = Addr>>(iter: I) -> Self {
+        AddrSet(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for AddrSet {
+    type Item = Addr;
+    type IntoIter = btree_set::IntoIter<Addr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a AddrSet {
+    type Item = &'a Addr;
+    type IntoIter = btree_set::Iter<'a, Addr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl Serialize for AddrSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for AddrSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = BTreeSet::new();
+        for addr in Vec::<Addr>::deserialize(deserializer)? {
+            if set.contains(&addr) {
+                return Err(de::Error::custom(format!("Duplicate address: {}", addr)));
+            }
+            set.insert(addr);
+        }
+        Ok(AddrSet(set))
+    }
+}
+
+impl JsonSchema for AddrSet {
+    fn schema_name() -> String {
+        "AddrSet".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        BTreeSet::<Addr>::json_schema(gen)
+    }
+}
+
+/// A map from addresses to values in canonical order, e.g. for operator registries.
+///
+/// Lookups take logarithmic time. Since maps cannot be represented in the JSON
+/// format used by contracts, it is serialized as an array of `[address, value]` pairs
+/// sorted by address. Deserializing an array that contains an address twice fails.
+///
+/// ```
+/// # use cosmwasm_std::{from_slice, to_vec, Addr, AddrMap};
+/// let mut operators = AddrMap::new();
+/// operators.insert(Addr::unchecked("bob"), 2u32);
+/// operators.insert(Addr::unchecked("alice"), 1u32);
+/// assert_eq!(operators.get(&Addr::unchecked("bob")), Some(&2));
+/// assert_eq!(to_vec(&operators).unwrap(), br#"[["alice",1],["bob",2]]"#);
+///
+/// assert!(from_slice::<AddrMap<u32>>(br#"[["bob",1],["bob",2]]"#).is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AddrMap<V>(BTreeMap<Addr, V>);
+
+impl<V> AddrMap<V> {
+    pub fn new() -> Self {
+        AddrMap(BTreeMap::new())
+    }
+
+    /// Sets the value of an address and returns its previous value.
+    pub fn insert(&mut self, addr: Addr, value: V) -> Option<V> {
+        self.0.insert(addr, value)
+    }
+
+    /// Removes an address and returns its value.
+    pub fn remove(&mut self, addr: &Addr) -> Option<V> {
+        self.0.remove(addr)
+    }
+
+    pub fn get(&self, addr: &Addr) -> Option<&V> {
+        self.0.get(addr)
+    }
+
+    pub fn get_mut(&mut self, addr: &Addr) -> Option<&mut V> {
+        self.0.get_mut(addr)
+    }
+
+    pub fn contains_key(&self, addr: &Addr) -> bool {
+        self.0.contains_key(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over the entries in canonical order of the addresses.
+    pub fn iter(&self) -> btree_map::Iter<'_, Addr, V> {
+        self.0.iter()
+    }
+
+    pub fn keys(&self) -> btree_map::Keys<'_, Addr, V> {
+        self.0.keys()
+    }
+
+    pub fn values(&self) -> btree_map::Values<'_, Addr, V> {
+        self.0.values()
+    }
+}
+
+// Implemented by hand since the derive would require `V: Default`
+impl<V> Default for AddrMap<V> {
+    fn default() -> Self {
+        AddrMap::new()
+    }
+}
+
+impl<V> FromIterator<(Addr, V)> for AddrMap<V> {
+    fn from_iter<I: IntoIterator<Item = (Addr, V)>>(iter: I) -> Self {
+        AddrMap(iter.into_iter().collect())
+    }
+}
+
+impl<V> IntoIterator for AddrMap<V> {
+    type Item = (Addr, V);
+    type IntoIter = btree_map::IntoIter<Addr, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a AddrMap<V> {
+    type Item = (&'a Addr, &'a V);
+    type IntoIter = btree_map::Iter<'a, Addr, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<V: Serialize> Serialize for AddrMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for AddrMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut map = BTreeMap::new();
+        for (addr, value) in Vec::<(Addr, V)>::deserialize(deserializer)? {
+            if map.contains_key(&addr) {
+                return Err(de::Error::custom(format!("Duplicate address: {}", addr)));
+            }
+            map.insert(addr, value);
+        }
+        Ok(AddrMap(map))
+    }
+}
+
+impl<V: JsonSchema> JsonSchema for AddrMap<V> {
+    fn schema_name() -> String {
+        format!("AddrMap_for_{}", V::schema_name())
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        Vec::<(Addr, V)>::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{from_slice, to_vec, Uint128};
+
+    fn addrs(names: &[&str]) -> Vec<Addr> {
+        names.iter().map(|name| Addr::unchecked(*name)).collect()
+    }
+
+    #[test]
+    fn addr_set_works() {
+        let mut set = AddrSet::new();
+        assert!(set.is_empty());
+        assert!(set.insert(Addr::unchecked("bob")));
+        assert!(set.insert(Addr::unchecked("alice")));
+        assert!(!set.insert(Addr::unchecked("bob")));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&Addr::unchecked("alice")));
+        assert!(!set.contains(&Addr::unchecked("carol")));
+
+        let sorted: Vec<Addr> = set.iter().cloned().collect();
+        assert_eq!(sorted, addrs(&["alice", "bob"]));
+
+        assert!(set.remove(&Addr::unchecked("alice")));
+        assert!(!set.remove(&Addr::unchecked("alice")));
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), addrs(&["bob"]));
+    }
+
+    #[test]
+    fn addr_set_serializes_in_canonical_order() {
+        let set: AddrSet = addrs(&["carol", "alice", "bob"]).into_iter().collect();
+        let json = to_vec(&set).unwrap();
+        assert_eq!(String::from_utf8_lossy(&json), r#"["alice","bob","carol"]"#);
+        assert_eq!(from_slice::<AddrSet>(&json).unwrap(), set);
+
+        // Any order is accepted
+        let unsorted: AddrSet = from_slice(br#"["carol","alice","bob"]"#).unwrap();
+        assert_eq!(unsorted, set);
+
+        let err = from_slice::<AddrSet>(br#"["alice","bob","alice"]"#).unwrap_err();
+        assert!(err.to_string().contains("Duplicate address: alice"));
+    }
+
+    #[test]
+    fn addr_map_works() {
+        let mut map = AddrMap::new();
+        assert!(map.is_empty());
+        assert_eq!(map.insert(Addr::unchecked("bob"), 2), None);
+        assert_eq!(map.insert(Addr::unchecked("alice"), 1), None);
+        assert_eq!(map.insert(Addr::unchecked("bob"), 3), Some(2));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&Addr::unchecked("bob")), Some(&3));
+        assert!(!map.contains_key(&Addr::unchecked("carol")));
+
+        *map.get_mut(&Addr::unchecked("alice")).unwrap() += 10;
+        assert_eq!(
+            map.keys().cloned().collect::<Vec<_>>(),
+            addrs(&["alice", "bob"])
+        );
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), [11, 3]);
+
+        assert_eq!(map.remove(&Addr::unchecked("alice")), Some(11));
+        assert_eq!(map.remove(&Addr::unchecked("alice")), None);
+        assert_eq!(
+            map.into_iter().collect::<Vec<_>>(),
+            [(Addr::unchecked("bob"), 3)]
+        );
+    }
+
+    #[test]
+    fn addr_map_serializes_in_canonical_order() {
+        let map: AddrMap<Uint128> = vec![
+            (Addr::unchecked("bob"), Uint128::new(2)),
+            (Addr::unchecked("alice"), Uint128::new(1)),
+        ]
+        .into_iter()
+        .collect();
+        let json = to_vec(&map).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&json),
+            r#"[["alice","1"],["bob","2"]]"#
+        );
+        assert_eq!(from_slice::<AddrMap<Uint128>>(&json).unwrap(), map);
+
+        let err = from_slice::<AddrMap<Uint128>>(br#"[["bob","1"],["bob","2"]]"#).unwrap_err();
+        assert!(err.to_string().contains("Duplicate address: bob"));
+    }
+
+    #[test]
+    fn schema_works() {
+        let schema = schemars::schema_for!(AddrSet);
+        let array = schema.schema.array.unwrap();
+        assert_eq!(array.unique_items, Some(true));
+        assert!(schema.definitions.contains_key("Addr"));
+
+        let schema = schemars::schema_for!(AddrMap<Uint128>);
+        let metadata = schema.schema.metadata.unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("AddrMap_for_Uint128"));
+        assert!(schema.schema.array.is_some());
+        assert!(schema.definitions.contains_key("Uint128"));
+    }
+}
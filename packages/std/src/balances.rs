@@ -0,0 +1,260 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::coins::Coin;
+use crate::errors::StdResult;
+use crate::math::Uint128;
+use crate::query::CustomQuery;
+use crate::traits::QuerierWrapper;
+
+/// The balances of an account at a given point in time, as reported by the bank module.
+///
+/// This can be used to find out how much an account actually received or sent over
+/// the course of an operation, e.g. when a token charges a fee on transfer. Take a
+/// snapshot at the beginning (it can be stored in state if the operation finishes in
+/// a reply) and compare it to the balances at the end using [`BalanceSnapshot::diff`].
+///
+/// ```
+/// # use cosmwasm_std::{coins, BalanceSnapshot, Uint128};
+/// # use cosmwasm_std::testing::{mock_dependencies_with_balance, MOCK_CONTRACT_ADDR};
+/// let mut deps = mock_dependencies_with_balance(&coins(100, "ucosm"));
+/// let before = BalanceSnapshot::take(&deps.as_ref().querier, MOCK_CONTRACT_ADDR, &["ucosm"]).unwrap();
+///
+/// // ... some messages get executed ...
+/// deps.querier.update_balance(MOCK_CONTRACT_ADDR, coins(142, "ucosm"));
+///
+/// let diff = before.diff(&deps.as_ref().querier).unwrap();
+/// assert_eq!(diff.received("ucosm"), Uint128::new(42));
+/// assert_eq!(diff.sent("ucosm"), Uint128::zero());
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BalanceSnapshot {
+    pub address: String,
+    /// The denoms that were queried. `None` means all balances of the account were queried.
+    pub denoms: Option<Vec<String>>,
+    /// The balances, sorted by denom. Denoms with a zero balance are omitted.
+    pub balances: Vec<Coin>,
+}
+
+impl BalanceSnapshot {
+    /// Queries the balances of `address` in the given denoms.
+    pub fn take<C: CustomQuery>(
+        querier: &QuerierWrapper<C>,
+        address: impl Into<String>,
+        denoms: &[&str],
+    ) -> StdResult<Self> {
+        let address = address.into();
+        let mut denoms: Vec<String> = denoms.iter().map(|denom| denom.to_string()).collect();
+        denoms.sort();
+        denoms.dedup();
+
+        let mut balances = Vec::with_capacity(denoms.len());
+        for denom in &denoms {
+            balances.push(querier.query_balance(address.clone(), denom.clone())?);
+        }
+
+        Ok(Self::new(address, Some(denoms), balances))
+    }
+
+    /// Queries all balances of `address`.
+    pub fn take_all<C: CustomQuery>(
+        querier: &QuerierWrapper<C>,
+        address: impl Into<String>,
+    ) -> StdResult<Self> {
+        let address = address.into();
+        let balances = querier.query_all_balances(address.clone())?;
+        Ok(Self::new(address, None, balances))
+    }
+
+    fn new(address: String, denoms: Option<Vec<String>>, balances: Vec<Coin>) -> Self {
+        let mut balances: Vec<Coin> = balances
+            .into_iter()
+            .filter(|coin| !coin.amount.is_zero())
+            .collect();
+        balances.sort_by(|a, b| a.denom.cmp(&b.denom));
+        BalanceSnapshot {
+            address,
+            denoms,
+            balances,
+        }
+    }
+
+    /// Returns the amount of `denom` held at the time of the snapshot.
+    pub fn amount_of(&self, denom: &str) -> Uint128 {
+        self.balances
+            .iter()
+            .find(|coin| coin.denom == denom)
+            .map(|coin| coin.amount)
+            .unwrap_or_default()
+    }
+
+    /// Takes a new snapshot of the same address and denoms and compares it to this one.
+    pub fn diff<C: CustomQuery>(&self, querier: &QuerierWrapper<C>) -> StdResult<BalanceDiff> {
+        let current = match &self.denoms {
+            Some(denoms) => {
+                let denoms: Vec<&str> = denoms.iter().map(String::as_str).collect();
+                Self::take(querier, self.address.clone(), &denoms)?
+            }
+            None => Self::take_all(querier, self.address.clone())?,
+        };
+        Ok(self.diff_to(&current))
+    }
+
+    /// Compares this snapshot to a later one.
+    pub fn diff_to(&self, later: &BalanceSnapshot) -> BalanceDiff {
+        let mut amounts: BTreeMap<&str, (Uint128, Uint128)> = BTreeMap::new();
+        for coin in &self.balances {
+            amounts.entry(&coin.denom).or_default().0 = coin.amount;
+        }
+        for coin in &later.balances {
+            amounts.entry(&coin.denom).or_default().1 = coin.amount;
+        }
+
+        let mut diff = BalanceDiff::default();
+        for (denom, (before, after)) in amounts {
+            if after > before {
+                diff.received.push(Coin {
+                    denom: denom.to_string(),
+                    amount: after - before,
+                });
+            } else if before > after {
+                diff.sent.push(Coin {
+                    denom: denom.to_string(),
+                    amount: before - after,
+                });
+            }
+        }
+        diff
+    }
+}
+
+/// The change between two balance snapshots, grouped by direction.
+///
+/// Both lists are sorted by denom and never contain zero amounts.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct BalanceDiff {
+    /// Denoms with a higher balance in the later snapshot and by how much.
+    pub received: Vec<Coin>,
+    /// Denoms with a lower balance in the later snapshot and by how much.
+    pub sent: Vec<Coin>,
+}
+
+impl BalanceDiff {
+    /// Returns the amount of `denom` the account gained. Zero if it did not gain any.
+    pub fn received(&self, denom: &str) -> Uint128 {
+        find_amount(&self.received, denom)
+    }
+
+    /// Returns the amount of `denom` the account lost. Zero if it did not lose any.
+    pub fn sent(&self, denom: &str) -> Uint128 {
+        find_amount(&self.sent, denom)
+    }
+
+    /// Returns true if no balance changed.
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty() && self.sent.is_empty()
+    }
+}
+
+fn find_amount(coins: &[Coin], denom: &str) -> Uint128 {
+    coins
+        .iter()
+        .find(|coin| coin.denom == denom)
+        .map(|coin| coin.amount)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::coins::coin;
+    use crate::testing::{mock_dependencies_with_balance, MOCK_CONTRACT_ADDR};
+
+    #[test]
+    fn take_works() {
+        let deps = mock_dependencies_with_balance(&[coin(123, "ucosm"), coin(7, "uatom")]);
+        let querier = deps.as_ref().querier;
+
+        let snapshot =
+            BalanceSnapshot::take(&querier, MOCK_CONTRACT_ADDR, &["ucosm", "uxyz", "ucosm"])
+                .unwrap();
+        assert_eq!(
+            snapshot.denoms,
+            Some(vec!["ucosm".to_string(), "uxyz".to_string()])
+        );
+        assert_eq!(snapshot.balances, vec![coin(123, "ucosm")]);
+        assert_eq!(snapshot.amount_of("ucosm"), Uint128::new(123));
+        assert_eq!(snapshot.amount_of("uatom"), Uint128::zero());
+
+        let snapshot = BalanceSnapshot::take_all(&querier, MOCK_CONTRACT_ADDR).unwrap();
+        assert_eq!(snapshot.denoms, None);
+        assert_eq!(
+            snapshot.balances,
+            vec![coin(7, "uatom"), coin(123, "ucosm")]
+        );
+    }
+
+    #[test]
+    fn diff_works() {
+        let mut deps = mock_dependencies_with_balance(&[coin(100, "ucosm"), coin(50, "uatom")]);
+
+        let before = BalanceSnapshot::take_all(&deps.as_ref().querier, MOCK_CONTRACT_ADDR).unwrap();
+        let diff = before.diff(&deps.as_ref().querier).unwrap();
+        assert!(diff.is_empty());
+
+        deps.querier.update_balance(
+            MOCK_CONTRACT_ADDR,
+            vec![coin(142, "ucosm"), coin(1, "uosmo")],
+        );
+        let diff = before.diff(&deps.as_ref().querier).unwrap();
+        assert_eq!(
+            diff,
+            BalanceDiff {
+                received: vec![coin(42, "ucosm"), coin(1, "uosmo")],
+                sent: vec![coin(50, "uatom")],
+            }
+        );
+        assert_eq!(diff.received("ucosm"), Uint128::new(42));
+        assert_eq!(diff.received("uatom"), Uint128::zero());
+        assert_eq!(diff.sent("uatom"), Uint128::new(50));
+        assert_eq!(diff.sent("ucosm"), Uint128::zero());
+    }
+
+    #[test]
+    fn diff_only_considers_snapshot_denoms() {
+        let mut deps = mock_dependencies_with_balance(&[coin(100, "ucosm")]);
+
+        let before =
+            BalanceSnapshot::take(&deps.as_ref().querier, MOCK_CONTRACT_ADDR, &["ucosm"]).unwrap();
+        deps.querier.update_balance(
+            MOCK_CONTRACT_ADDR,
+            vec![coin(90, "ucosm"), coin(1, "uosmo")],
+        );
+        let diff = before.diff(&deps.as_ref().querier).unwrap();
+        assert_eq!(
+            diff,
+            BalanceDiff {
+                received: vec![],
+                sent: vec![coin(10, "ucosm")],
+            }
+        );
+    }
+
+    #[test]
+    fn snapshot_serializes() {
+        let snapshot = BalanceSnapshot {
+            address: "contract".to_string(),
+            denoms: Some(vec!["ucosm".to_string()]),
+            balances: vec![coin(5, "ucosm")],
+        };
+        let json = crate::to_vec(&snapshot).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&json),
+            r#"{"address":"contract","denoms":["ucosm"],"balances":[{"denom":"ucosm","amount":"5"}]}"#
+        );
+        let parsed: BalanceSnapshot = crate::from_slice(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+}
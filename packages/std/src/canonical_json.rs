@@ -0,0 +1,370 @@
+use serde::Serialize;
+use std::any::type_name;
+
+use crate::errors::{StdError, StdResult};
+use crate::serde::to_vec;
+
+/// Serializes `data` to a canonical JSON representation, e.g. for signing payloads
+/// that have to be reproduced byte by byte by someone else.
+///
+/// The result is the same as [`to_vec`](crate::to_vec) except that the members of all
+/// objects are sorted by their names, compared as UTF-16 code units like in the
+/// [Canonical JSON](https://gibson042.github.io/canonicaljson-spec/) spec. Like
+/// `to_vec`, it contains no whitespace and only escapes `"`, `\` and the control
+/// characters, using the short escapes `\b`, `\t`, `\n`, `\f` and `\r` where possible
+/// and `\u00XX` with lowercase hex digits otherwise. All other characters are
+/// written as UTF-8. Numbers are integers, 128 bit integers are strings.
+///
+/// Fails if an object has two members with the same name, e.g. due to `#[serde(rename)]`.
+///
+/// ```
+/// # use cosmwasm_std::to_canonical_json;
+/// # use serde::Serialize;
+/// #[derive(Serialize)]
+/// struct SignDoc {
+///     sequence: u64,
+///     chain_id: String,
+///     memo: String,
+/// }
+///
+/// let doc = SignDoc {
+///     sequence: 7,
+///     chain_id: "testing".to_string(),
+///     memo: "Thanks\n".to_string(),
+/// };
+/// let json = to_canonical_json(&doc).unwrap();
+/// assert_eq!(json, br#"{"chain_id":"testing","memo":"Thanks\n","sequence":7}"#);
+/// ```
+pub fn to_canonical_json<T>(data: &T) -> StdResult<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    let json = to_vec(data)?;
+    let mut parser = Parser {
+        json: &json,
+        pos: 0,
+    };
+    let value = parser
+        .value()
+        .map_err(|msg| StdError::serialize_err(type_name::<T>(), msg))?;
+    let mut out = Vec::with_capacity(json.len());
+    value
+        .write(&mut out)
+        .map_err(|msg| StdError::serialize_err(type_name::<T>(), msg))?;
+    Ok(out)
+}
+
+/// A JSON value as written by `to_vec`. Strings and other scalars are kept as their
+/// raw bytes, including quotes and escapes.
+pub(crate) enum Value<'a> {
+    Scalar(&'a [u8]),
+    Array(Vec<Value<'a>>),
+    Object(Vec<(&'a [u8], Value<'a>)>),
+}
+
+impl Value<'_> {
+    /// Writes the value in canonical form, see [`to_canonical_json`].
+    pub(crate) fn write(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Value::Scalar(raw) => out.extend_from_slice(raw),
+            Value::Array(elements) => {
+                out.push(b'[');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    element.write(out)?;
+                }
+                out.push(b']');
+            }
+            Value::Object(members) => {
+                let mut sorted: Vec<(Vec<u16>, &[u8], &Value)> = members
+                    .iter()
+                    .map(|(name, value)| Ok((utf16_name(name)?, *name, value)))
+                    .collect::<Result<_, String>>()?;
+                sorted.sort_by(|a, b| a.0.cmp(&b.0));
+                if let Some(pair) = sorted.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                    return Err(format!(
+                        "Duplicate object member {}",
+                        String::from_utf8_lossy(pair[0].1)
+                    ));
+                }
+
+                out.push(b'{');
+                for (i, (_, name, value)) in sorted.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    out.extend_from_slice(name);
+                    out.push(b':');
+                    value.write(out)?;
+                }
+                out.push(b'}');
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The UTF-16 code units of a raw, quoted member name, for sorting.
+fn utf16_name(raw: &[u8]) -> Result<Vec<u16>, String> {
+    let escaped = std::str::from_utf8(&raw[1..raw.len() - 1]).map_err(|e| e.to_string())?;
+    let mut name = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            name.push(c);
+            continue;
+        }
+        let unescaped = match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{0008}',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('f') => '\u{000C}',
+            Some('r') => '\r',
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("Unsupported escape \\u{}", hex))?
+            }
+            other => return Err(format!("Unsupported escape {:?}", other)),
+        };
+        name.push(unescaped);
+    }
+    Ok(name.encode_utf16().collect())
+}
+
+/// Parses JSON without whitespace as written by `to_vec`.
+pub(crate) fn parse(json: &[u8]) -> Result<Value<'_>, String> {
+    let mut parser = Parser { json, pos: 0 };
+    let value = parser.value()?;
+    if parser.pos != json.len() {
+        return Err(format!("Unexpected data at position {}", parser.pos));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    json: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Result<u8, String> {
+        self.json
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| "Unexpected end of JSON".to_string())
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek()? != byte {
+            return Err(format!(
+                "Expected '{}' at position {}",
+                byte as char, self.pos
+            ));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, String> {
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self.string().map(Value::Scalar),
+            _ => {
+                let start = self.pos;
+                while self.pos < self.json.len() && !b",]}".contains(&self.json[self.pos]) {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err(format!("Expected a value at position {}", start));
+                }
+                Ok(Value::Scalar(&self.json[start..self.pos]))
+            }
+        }
+    }
+
+    /// A string including its quotes
+    fn string(&mut self) -> Result<&'a [u8], String> {
+        let start = self.pos;
+        self.expect(b'"')?;
+        loop {
+            match self.peek()? {
+                b'"' => break,
+                b'\\' => self.pos += 2,
+                _ => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        Ok(&self.json[start..self.pos])
+    }
+
+    fn array(&mut self) -> Result<Value<'a>, String> {
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        if self.peek()? != b']' {
+            loop {
+                elements.push(self.value()?);
+                if self.peek()? != b',' {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.expect(b']')?;
+        Ok(Value::Array(elements))
+    }
+
+    fn object(&mut self) -> Result<Value<'a>, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        if self.peek()? != b'}' {
+            loop {
+                let name = self.string()?;
+                self.expect(b':')?;
+                members.push((name, self.value()?));
+                if self.peek()? != b',' {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.expect(b'}')?;
+        Ok(Value::Object(members))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::serde::from_slice;
+    use crate::{coins, BankMsg, Binary, CosmosMsg, Uint128};
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Inner {
+        zeta: Vec<u32>,
+        alpha: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Msg {
+        Transfer {
+            recipient: String,
+            amount: Uint128,
+            inner: Inner,
+        },
+        Burn {},
+        Pause,
+    }
+
+    #[test]
+    fn to_canonical_json_sorts_members() {
+        let msg = Msg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(300),
+            inner: Inner {
+                zeta: vec![3, 1, 2],
+                alpha: None,
+            },
+        };
+        let json = to_canonical_json(&msg).unwrap();
+        assert_eq!(
+            String::from_utf8(json.clone()).unwrap(),
+            r#"{"transfer":{"amount":"300","inner":{"alpha":null,"zeta":[3,1,2]},"recipient":"bob"}}"#
+        );
+        assert_eq!(from_slice::<Msg>(&json).unwrap(), msg);
+
+        assert_eq!(to_canonical_json(&Msg::Burn {}).unwrap(), br#"{"burn":{}}"#);
+        assert_eq!(to_canonical_json(&Msg::Pause).unwrap(), br#""pause""#);
+        let empty: Vec<Inner> = vec![];
+        assert_eq!(to_canonical_json(&empty).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn to_canonical_json_is_deterministic_for_std_types() {
+        let msg: CosmosMsg = BankMsg::Send {
+            to_address: "alice".to_string(),
+            amount: coins(12, "ucosm"),
+        }
+        .into();
+        assert_eq!(
+            to_canonical_json(&msg).unwrap(),
+            br#"{"bank":{"send":{"amount":[{"amount":"12","denom":"ucosm"}],"to_address":"alice"}}}"#
+        );
+        assert_eq!(
+            to_canonical_json(&Binary::from(b"hi")).unwrap(),
+            br#""aGk=""#
+        );
+    }
+
+    #[test]
+    fn to_canonical_json_escapes_strings() {
+        let inner = Inner {
+            zeta: vec![],
+            alpha: Some("quote\" backslash\\ tab\t bell\u{7} {b:[1,2]} é".to_string()),
+        };
+        let json = to_canonical_json(&inner).unwrap();
+        assert_eq!(
+            String::from_utf8(json.clone()).unwrap(),
+            r#"{"alpha":"quote\" backslash\\ tab\t bell\u0007 {b:[1,2]} é","zeta":[]}"#
+        );
+        assert_eq!(from_slice::<Inner>(&json).unwrap(), inner);
+    }
+
+    #[test]
+    fn to_canonical_json_sorts_by_utf16() {
+        #[derive(Serialize)]
+        struct Names {
+            #[serde(rename = "\u{FB01}")]
+            ligature: u8,
+            #[serde(rename = "\u{1F600}")]
+            emoji: u8,
+            #[serde(rename = "\u{E9}")]
+            e_acute: u8,
+            a: u8,
+        }
+
+        // In UTF-8 the ligature would come before the emoji
+        let json = to_canonical_json(&Names {
+            ligature: 1,
+            emoji: 2,
+            e_acute: 3,
+            a: 4,
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"a\":4,\"\u{E9}\":3,\"\u{1F600}\":2,\"\u{FB01}\":1}"
+        );
+    }
+
+    #[test]
+    fn to_canonical_json_rejects_duplicate_members() {
+        #[derive(Serialize)]
+        struct Duplicate {
+            a: u8,
+            #[serde(rename = "a")]
+            b: u8,
+        }
+
+        match to_canonical_json(&Duplicate { a: 1, b: 2 }).unwrap_err() {
+            StdError::SerializeErr {
+                source_type, msg, ..
+            } => {
+                assert!(source_type.ends_with("Duplicate"));
+                assert_eq!(msg, r#"Duplicate object member "a""#);
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+}
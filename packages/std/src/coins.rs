@@ -1,7 +1,10 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::str::FromStr;
 
+use crate::errors::{StdError, StdResult};
 use crate::math::Uint128;
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
@@ -29,6 +32,59 @@ impl fmt::Display for Coin {
     }
 }
 
+impl FromStr for Coin {
+    type Err = StdError;
+
+    /// Parses a coin in the format of the Cosmos SDK, e.g. "123456uatom" or
+    /// "1000ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2",
+    /// which is the format produced by [`Coin`]'s `Display` implementation.
+    ///
+    /// The amount is an integer without sign, decimal point or separators and can be
+    /// followed by spaces. The denom has to be valid in the Cosmos SDK: 3 to 128
+    /// characters starting with a letter, followed by letters, digits and `/:._-`,
+    /// like `reDnmString` in the SDK's `types/coin.go`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let denom_start = input
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| StdError::parse_err("Coin", "missing denom"))?;
+        let (amount, denom) = input.split_at(denom_start);
+        if amount.is_empty() {
+            return Err(StdError::parse_err("Coin", "missing amount"));
+        }
+        let amount = amount
+            .parse::<u128>()
+            .map_err(|err| StdError::parse_err("Coin", err))?;
+        let denom = denom.trim_start();
+        validate_denom(denom)?;
+
+        Ok(Coin::new(amount, denom))
+    }
+}
+
+fn validate_denom(denom: &str) -> StdResult<()> {
+    if denom.len() < 3 || denom.len() > 128 {
+        return Err(StdError::parse_err(
+            "Coin",
+            format!("denom must be 3 to 128 characters long, got '{}'", denom),
+        ));
+    }
+    let mut chars = denom.chars();
+    // Length checked above
+    if !chars.next().unwrap().is_ascii_alphabetic() {
+        return Err(StdError::parse_err(
+            "Coin",
+            format!("denom must start with a letter, got '{}'", denom),
+        ));
+    }
+    if !chars.all(|c| c.is_ascii_alphanumeric() || "/:._-".contains(c)) {
+        return Err(StdError::parse_err(
+            "Coin",
+            format!("invalid character in denom '{}'", denom),
+        ));
+    }
+    Ok(())
+}
+
 /// A shortcut constructor for a set of one denomination of coins
 ///
 /// # Examples
@@ -74,6 +130,20 @@ pub fn coin(amount: u128, denom: impl Into<String>) -> Coin {
     Coin::new(amount, denom)
 }
 
+/// Brings coins into the form the bank module expects: coins of the same denom are
+/// merged, zero amounts are removed and the rest is sorted by denom.
+pub(crate) fn normalize_coins(coins: impl IntoIterator<Item = Coin>) -> Vec<Coin> {
+    let mut amounts: BTreeMap<String, Uint128> = BTreeMap::new();
+    for coin in coins {
+        *amounts.entry(coin.denom).or_default() += coin.amount;
+    }
+    amounts
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(denom, amount)| Coin { denom, amount })
+        .collect()
+}
+
 /// has_coins returns true if the list of coins has at least the required amount
 pub fn has_coins(coins: &[Coin], required: &Coin) -> bool {
     coins
@@ -99,6 +169,72 @@ mod tests {
         assert_eq!(a.to_string(), "123ucosm");
     }
 
+    #[test]
+    fn coin_from_str_works() {
+        assert_eq!(
+            "123456uatom".parse::<Coin>().unwrap(),
+            coin(123456, "uatom")
+        );
+        assert_eq!("0ucosm".parse::<Coin>().unwrap(), coin(0, "ucosm"));
+        assert_eq!("007ucosm".parse::<Coin>().unwrap(), coin(7, "ucosm"));
+        assert_eq!("12 ucosm".parse::<Coin>().unwrap(), coin(12, "ucosm"));
+        assert_eq!(
+            "340282366920938463463374607431768211455ucosm"
+                .parse::<Coin>()
+                .unwrap(),
+            coin(u128::MAX, "ucosm")
+        );
+
+        let ibc = "1000ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2";
+        let parsed: Coin = ibc.parse().unwrap();
+        assert_eq!(parsed.amount, Uint128::new(1000));
+        assert_eq!(
+            parsed.denom,
+            "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"
+        );
+        assert_eq!(parsed.to_string(), ibc);
+
+        let factory: Coin = "5factory/osmo1abc/foo.bar:baz_qux-1".parse().unwrap();
+        assert_eq!(factory.denom, "factory/osmo1abc/foo.bar:baz_qux-1");
+    }
+
+    #[test]
+    fn coin_from_str_fails_for_invalid_input() {
+        for input in [
+            "",
+            "123",
+            "ucosm",
+            "-1ucosm",
+            "1.5ucosm",
+            "1,000ucosm",
+            " 1ucosm",
+            "1ab",
+            "11ucosm ",
+            "1 2ucosm",
+            "1 1ucosm",
+            "1uco$m",
+            "1ücosm",
+            "1/ucosm",
+            "340282366920938463463374607431768211456ucosm",
+        ] {
+            match input.parse::<Coin>().unwrap_err() {
+                StdError::ParseErr { target_type, .. } => assert_eq!(target_type, "Coin"),
+                err => panic!("Unexpected error for '{}': {:?}", input, err),
+            }
+        }
+
+        let long_denom = format!("1u{}", "a".repeat(128));
+        assert!(long_denom.parse::<Coin>().is_err());
+        let longest_denom = format!("1u{}", "a".repeat(127));
+        assert_eq!(longest_denom.parse::<Coin>().unwrap().denom.len(), 128);
+    }
+
+    #[test]
+    fn coin_from_str_reverses_display() {
+        let original = coin(987654321, "ustake");
+        assert_eq!(original.to_string().parse::<Coin>().unwrap(), original);
+    }
+
     #[test]
     fn coin_works() {
         let a = coin(123, "ucosm");
@@ -1,5 +1,6 @@
 mod recover_pubkey_error;
 mod std_error;
+mod structured_error;
 mod system_error;
 mod verification_error;
 
@@ -8,5 +9,6 @@ pub use std_error::{
     ConversionOverflowError, DivideByZeroError, OverflowError, OverflowOperation, StdError,
     StdResult,
 };
+pub use structured_error::StructuredError;
 pub use system_error::SystemError;
 pub use verification_error::VerificationError;
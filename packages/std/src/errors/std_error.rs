@@ -3,7 +3,7 @@ use std::backtrace::Backtrace;
 use std::fmt;
 use thiserror::Error;
 
-use crate::errors::{RecoverPubkeyError, VerificationError};
+use crate::errors::{RecoverPubkeyError, StructuredError, VerificationError};
 
 /// Structured error type for init, execute and query.
 ///
@@ -102,6 +102,14 @@ pub enum StdError {
         #[cfg(feature = "backtraces")]
         backtrace: Backtrace,
     },
+    /// An error that is passed on to the caller with its structure intact. The message is
+    /// the encoded error, see [`StructuredError`].
+    #[error("{error}")]
+    StructuredErr {
+        error: StructuredError,
+        #[cfg(feature = "backtraces")]
+        backtrace: Backtrace,
+    },
 }
 
 impl StdError {
@@ -196,6 +204,14 @@ impl StdError {
             backtrace: Backtrace::capture(),
         }
     }
+
+    pub fn structured_err(error: StructuredError) -> Self {
+        StdError::StructuredErr {
+            error,
+            #[cfg(feature = "backtraces")]
+            backtrace: Backtrace::capture(),
+        }
+    }
 }
 
 impl PartialEq<StdError> for StdError {
@@ -399,6 +415,22 @@ impl PartialEq<StdError> for StdError {
                     false
                 }
             }
+            StdError::StructuredErr {
+                error,
+                #[cfg(feature = "backtraces")]
+                    backtrace: _,
+            } => {
+                if let StdError::StructuredErr {
+                    error: rhs_error,
+                    #[cfg(feature = "backtraces")]
+                        backtrace: _,
+                } = rhs
+                {
+                    error == rhs_error
+                } else {
+                    false
+                }
+            }
         }
     }
 }
@@ -415,6 +447,12 @@ impl From<std::string::FromUtf8Error> for StdError {
     }
 }
 
+impl From<StructuredError> for StdError {
+    fn from(error: StructuredError) -> Self {
+        Self::structured_err(error)
+    }
+}
+
 impl From<VerificationError> for StdError {
     fn from(source: VerificationError) -> Self {
         Self::verification_err(source)
@@ -708,6 +746,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn structured_err_works() {
+        let error = StdError::structured_err(StructuredError::new(3, "Expired"));
+        match &error {
+            StdError::StructuredErr { error, .. } => {
+                assert_eq!(error.code, 3);
+                assert_eq!(error.message, "Expired");
+            }
+            _ => panic!("expect different error"),
+        }
+        // The message is the encoded error such that it survives `ContractResult::from`
+        assert_eq!(error.to_string(), r#"{"code":3,"message":"Expired"}"#);
+        assert_eq!(StdError::from(StructuredError::new(3, "Expired")), error);
+    }
+
     #[test]
     fn implements_debug() {
         let error: StdError = StdError::from(OverflowError::new(OverflowOperation::Sub, 3, 5));
@@ -0,0 +1,115 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::binary::Binary;
+use crate::serde::{from_slice, to_vec};
+
+/// An error with a machine readable code and optional data that keeps its structure
+/// when crossing contract boundaries, e.g. as the error of a query to another contract.
+///
+/// Errors are passed between contracts as the string in [`ContractResult::Err`](crate::ContractResult::Err).
+/// A structured error is encoded as a JSON object in that string, which is also what its
+/// `Display` implementation produces. Readers that do not know about structured errors
+/// still get a readable message; [`StructuredError::parse`] recovers the structure.
+/// Only errors returned by contracts are structured, a [`SystemError`](crate::SystemError)
+/// is unchanged.
+///
+/// ```
+/// # use cosmwasm_std::{Binary, StructuredError};
+/// let error = StructuredError::new(4, "Insufficient funds").with_data(b"100ucosm".to_vec());
+/// let encoded = error.to_string();
+/// assert_eq!(encoded, r#"{"code":4,"message":"Insufficient funds","data":"MTAwdWNvc20="}"#);
+/// assert_eq!(StructuredError::parse(&encoded), Some(error));
+///
+/// // Plain error messages are not structured
+/// assert_eq!(StructuredError::parse("Generic error: Insufficient funds"), None);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredError {
+    /// An error code defined by the contract that created the error
+    pub code: u32,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Binary>,
+}
+
+impl StructuredError {
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        StructuredError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: impl Into<Binary>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Decodes a structured error from an error string. Returns `None` if the string
+    /// is a plain error message.
+    pub fn parse(error: &str) -> Option<Self> {
+        if !error.starts_with('{') {
+            return None;
+        }
+        from_slice(error.as_bytes()).ok()
+    }
+}
+
+impl fmt::Display for StructuredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Serializing a struct of a number, a string and base64 cannot fail
+        let encoded = to_vec(self).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&encoded))
+    }
+}
+
+impl std::error::Error for StructuredError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_encodes_as_json() {
+        let error = StructuredError::new(7, "Unauthorized");
+        assert_eq!(error.to_string(), r#"{"code":7,"message":"Unauthorized"}"#);
+
+        let error = StructuredError::new(0, "Quote \" and \\").with_data(vec![1, 2, 3]);
+        assert_eq!(
+            error.to_string(),
+            r#"{"code":0,"message":"Quote \" and \\","data":"AQID"}"#
+        );
+    }
+
+    #[test]
+    fn parse_works() {
+        for error in [
+            StructuredError::new(7, "Unauthorized"),
+            StructuredError::new(u32::MAX, "").with_data(vec![0xff; 5]),
+        ] {
+            assert_eq!(StructuredError::parse(&error.to_string()), Some(error));
+        }
+
+        assert_eq!(
+            StructuredError::parse(r#"{"code":1,"message":"m","data":null}"#),
+            Some(StructuredError::new(1, "m"))
+        );
+    }
+
+    #[test]
+    fn parse_ignores_plain_messages() {
+        assert_eq!(StructuredError::parse(""), None);
+        assert_eq!(StructuredError::parse("Unauthorized"), None);
+        assert_eq!(StructuredError::parse("{ not json"), None);
+        // JSON that is not a structured error
+        assert_eq!(StructuredError::parse(r#"{"message":"m"}"#), None);
+        assert_eq!(
+            StructuredError::parse(r#"{"code":1,"message":"m","extra":true}"#),
+            None
+        );
+    }
+}
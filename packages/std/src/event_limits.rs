@@ -0,0 +1,313 @@
+use crate::errors::{StdError, StdResult};
+use crate::results::{Attribute, Event, Response};
+
+/// Appended to attribute values that were cut to fit [`EventLimits::max_value_len`].
+pub const TRUNCATION_MARKER: &str = "...(truncated)";
+
+/// The attribute key that numbers the events an event was split into, e.g. `"2/3"`.
+pub const PART_ATTRIBUTE: &str = "part";
+
+/// Event size limits enforced by a chain.
+///
+/// Chains reject transactions emitting events that are too large, which contracts only
+/// notice as an opaque failure in production. [`EventLimits::split_event`] turns an
+/// event into events of the same type that satisfy the limits. Values that are too long
+/// are cut and end with [`TRUNCATION_MARKER`]. If an event needs to be split, every part
+/// gets a [`PART_ATTRIBUTE`] attribute, so indexers can tell partial events apart.
+///
+/// ```
+/// # use cosmwasm_std::{Event, EventLimits};
+/// let limits = EventLimits {
+///     max_attributes: 3,
+///     max_key_len: 16,
+///     max_value_len: 20,
+///     max_event_size: 1024,
+/// };
+/// let event = Event::new("transfer")
+///     .add_attribute("a", "1")
+///     .add_attribute("b", "2")
+///     .add_attribute("c", "3")
+///     .add_attribute("memo", "a very long memo that does not fit");
+///
+/// let events = limits.split_event(event).unwrap();
+/// assert_eq!(
+///     events,
+///     vec![
+///         Event::new("transfer")
+///             .add_attribute("a", "1")
+///             .add_attribute("b", "2")
+///             .add_attribute("part", "1/2"),
+///         Event::new("transfer")
+///             .add_attribute("c", "3")
+///             .add_attribute("memo", "a very...(truncated)")
+///             .add_attribute("part", "2/2"),
+///     ]
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventLimits {
+    /// Maximum number of attributes per event
+    pub max_attributes: usize,
+    /// Maximum length of an attribute key in bytes. Keys are never truncated.
+    pub max_key_len: usize,
+    /// Maximum length of an attribute value in bytes
+    pub max_value_len: usize,
+    /// Maximum total length of all keys and values of an event in bytes
+    pub max_event_size: usize,
+}
+
+impl EventLimits {
+    /// Cuts `value` to at most `max_value_len` bytes, keeping it valid UTF-8 and ending it
+    /// with [`TRUNCATION_MARKER`] if the marker fits.
+    pub fn truncate_value(&self, value: &str) -> String {
+        if value.len() <= self.max_value_len {
+            return value.to_string();
+        }
+        let (keep, marker) = if self.max_value_len >= TRUNCATION_MARKER.len() {
+            (
+                self.max_value_len - TRUNCATION_MARKER.len(),
+                TRUNCATION_MARKER,
+            )
+        } else {
+            (self.max_value_len, "")
+        };
+        let mut end = keep;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}{}", &value[..end], marker)
+    }
+
+    /// Splits `event` into events of the same type with the attributes in their original
+    /// order. Returns the event unchanged if it is within the limits.
+    ///
+    /// Fails if a key is longer than `max_key_len` or an attribute does not fit into an
+    /// event on its own, since no split can fix that.
+    pub fn split_event(&self, event: Event) -> StdResult<Vec<Event>> {
+        let mut event = event;
+        for attribute in event.attributes.iter_mut() {
+            if attribute.key.len() > self.max_key_len {
+                return Err(StdError::generic_err(format!(
+                    "Attribute key '{}' of event '{}' exceeds the maximum key length of {} bytes",
+                    attribute.key, event.ty, self.max_key_len
+                )));
+            }
+            attribute.value = self.truncate_value(&attribute.value);
+        }
+        let attributes = &event.attributes;
+
+        if let Some(batches) = pack(attributes, self.max_attributes, self.max_event_size) {
+            if batches.len() <= 1 {
+                return Ok(vec![event]);
+            }
+        }
+
+        // Reserve room for the "part" attribute, which can be as long as "n/n"
+        let digits = attributes.len().to_string().len();
+        let reserved_size = PART_ATTRIBUTE.len() + 2 * digits + 1;
+        let batches = pack(
+            attributes,
+            self.max_attributes.saturating_sub(1),
+            self.max_event_size.saturating_sub(reserved_size),
+        )
+        .ok_or_else(|| {
+            StdError::generic_err(format!(
+                "Event '{}' cannot be split to fit the event limits",
+                event.ty
+            ))
+        })?;
+
+        let total = batches.len();
+        let events = batches
+            .into_iter()
+            .enumerate()
+            .map(|(index, batch)| {
+                let mut part = Event::new(event.ty.clone());
+                part.attributes = batch;
+                part.add_attribute(PART_ATTRIBUTE, format!("{}/{}", index + 1, total))
+            })
+            .collect();
+        Ok(events)
+    }
+
+    /// Splits all events of `response` using [`EventLimits::split_event`].
+    ///
+    /// The response's own attributes end up in the `wasm` event, which cannot be split.
+    /// Their values are truncated, but their number and total size are left unchanged.
+    pub fn split_response<T>(&self, mut response: Response<T>) -> StdResult<Response<T>> {
+        let mut events = Vec::with_capacity(response.events.len());
+        for event in response.events {
+            events.extend(self.split_event(event)?);
+        }
+        response.events = events;
+        for attribute in response.attributes.iter_mut() {
+            attribute.value = self.truncate_value(&attribute.value);
+        }
+        Ok(response)
+    }
+}
+
+/// Greedily packs attributes into batches with at most `max_attributes` attributes and
+/// `max_size` bytes each. Returns `None` if an attribute does not fit into a batch on its own.
+fn pack(
+    attributes: &[Attribute],
+    max_attributes: usize,
+    max_size: usize,
+) -> Option<Vec<Vec<Attribute>>> {
+    let mut batches: Vec<Vec<Attribute>> = vec![];
+    let mut current: Vec<Attribute> = vec![];
+    let mut current_size = 0;
+    for attribute in attributes {
+        let size = attribute.key.len() + attribute.value.len();
+        if max_attributes == 0 || size > max_size {
+            return None;
+        }
+        if current.len() == max_attributes || current_size + size > max_size {
+            batches.push(std::mem::take(&mut current));
+            current_size = 0;
+        }
+        current.push(attribute.clone());
+        current_size += size;
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    Some(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: EventLimits = EventLimits {
+        max_attributes: 4,
+        max_key_len: 8,
+        max_value_len: 32,
+        max_event_size: 64,
+    };
+
+    #[test]
+    fn truncate_value_works() {
+        assert_eq!(LIMITS.truncate_value("short"), "short");
+        let exact = "x".repeat(32);
+        assert_eq!(LIMITS.truncate_value(&exact), exact);
+
+        let truncated = LIMITS.truncate_value(&"x".repeat(40));
+        assert_eq!(truncated.len(), 32);
+        assert_eq!(
+            truncated,
+            format!("{}{}", "x".repeat(18), TRUNCATION_MARKER)
+        );
+
+        // cuts at a char boundary
+        let truncated = LIMITS.truncate_value(&"ä".repeat(20));
+        assert_eq!(truncated, format!("{}{}", "ä".repeat(9), TRUNCATION_MARKER));
+
+        // marker does not fit
+        let tiny = EventLimits {
+            max_value_len: 5,
+            ..LIMITS
+        };
+        assert_eq!(tiny.truncate_value("abcdefgh"), "abcde");
+    }
+
+    #[test]
+    fn split_event_keeps_events_within_limits() {
+        let event = Event::new("foo")
+            .add_attribute("a", "1")
+            .add_attribute("b", "2");
+        assert_eq!(LIMITS.split_event(event.clone()).unwrap(), vec![event]);
+
+        let event = Event::new("foo").add_attribute("a", "y".repeat(50));
+        assert_eq!(
+            LIMITS.split_event(event).unwrap(),
+            vec![Event::new("foo")
+                .add_attribute("a", format!("{}{}", "y".repeat(18), TRUNCATION_MARKER))]
+        );
+    }
+
+    #[test]
+    fn split_event_splits_by_attribute_count() {
+        let mut event = Event::new("foo");
+        for i in 0..7 {
+            event = event.add_attribute(format!("k{}", i), i.to_string());
+        }
+
+        let events = LIMITS.split_event(event).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::new("foo")
+                    .add_attribute("k0", "0")
+                    .add_attribute("k1", "1")
+                    .add_attribute("k2", "2")
+                    .add_attribute("part", "1/3"),
+                Event::new("foo")
+                    .add_attribute("k3", "3")
+                    .add_attribute("k4", "4")
+                    .add_attribute("k5", "5")
+                    .add_attribute("part", "2/3"),
+                Event::new("foo")
+                    .add_attribute("k6", "6")
+                    .add_attribute("part", "3/3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_event_splits_by_size() {
+        let event = Event::new("foo")
+            .add_attribute("a", "x".repeat(31))
+            .add_attribute("b", "y".repeat(31))
+            .add_attribute("c", "z");
+
+        let events = LIMITS.split_event(event).unwrap();
+        assert_eq!(events.len(), 2);
+        for event in &events {
+            assert!(event.attributes.len() <= LIMITS.max_attributes);
+            let size: usize = event
+                .attributes
+                .iter()
+                .map(|attr| attr.key.len() + attr.value.len())
+                .sum();
+            assert!(size <= LIMITS.max_event_size);
+        }
+        assert_eq!(events[0].attributes[0].key, "a");
+        assert_eq!(events[1].attributes[0].key, "b");
+        assert_eq!(events[1].attributes[1].key, "c");
+    }
+
+    #[test]
+    fn split_event_fails_for_unsplittable_events() {
+        let event = Event::new("foo").add_attribute("too_long_key", "1");
+        let err = LIMITS.split_event(event).unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum key length"));
+
+        let limits = EventLimits {
+            max_attributes: 1,
+            ..LIMITS
+        };
+        let event = Event::new("foo")
+            .add_attribute("a", "1")
+            .add_attribute("b", "2");
+        let err = limits.split_event(event).unwrap_err();
+        assert!(err.to_string().contains("cannot be split"));
+    }
+
+    #[test]
+    fn split_response_works() {
+        let mut event = Event::new("foo");
+        for i in 0..5 {
+            event = event.add_attribute(format!("k{}", i), i.to_string());
+        }
+        let response: Response = Response::new()
+            .add_attribute("action", "x".repeat(40))
+            .add_event(event)
+            .add_event(Event::new("bar"));
+
+        let response = LIMITS.split_response(response).unwrap();
+        assert_eq!(response.attributes[0].value.len(), 32);
+        let types: Vec<&str> = response.events.iter().map(|e| e.ty.as_str()).collect();
+        assert_eq!(types, ["foo", "foo", "bar"]);
+    }
+}
@@ -38,6 +38,14 @@ extern "C" fn requires_staking() -> () {}
 #[no_mangle]
 extern "C" fn requires_stargate() -> () {}
 
+#[cfg(feature = "gas_remaining")]
+#[no_mangle]
+extern "C" fn requires_gas_remaining() -> () {}
+
+#[cfg(feature = "multisend")]
+#[no_mangle]
+extern "C" fn requires_multisend() -> () {}
+
 /// interface_version_* exports mark which Wasm VM interface level this contract is compiled for.
 /// They can be checked by cosmwasm_vm.
 /// Update this whenever the Wasm VM interface breaks.
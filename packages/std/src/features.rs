@@ -0,0 +1,192 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{StdError, StdResult};
+use crate::query::CustomQuery;
+use crate::serde::{from_slice, to_vec};
+use crate::traits::{QuerierWrapper, Storage};
+
+/// The storage key under which contracts declare their [`ContractFeatures`].
+///
+/// The key is the same for all contracts, so other contracts can read it with a raw
+/// query without knowing anything else about the contract.
+pub const CONTRACT_FEATURES_KEY: &[u8] = b"contract_features";
+
+/// The maximum length of a feature name in bytes
+const MAX_FEATURE_LENGTH: usize = 64;
+
+/// The interfaces a contract declares to support, e.g. "cw20", "receiver-hook" or
+/// "ibc-callbacks", for discovering the capabilities of other contracts on chain.
+///
+/// Contracts store their features with [`ContractFeatures::save`], usually in
+/// `instantiate` and `migrate`. Other contracts read them with
+/// [`ContractFeatures::query`], which is a cheap raw query of
+/// [`CONTRACT_FEATURES_KEY`]. Contracts that did not declare any features have none.
+///
+/// ```
+/// # use cosmwasm_std::{ContractFeatures, StdResult};
+/// # use cosmwasm_std::testing::MockStorage;
+/// # fn main() -> StdResult<()> {
+/// let mut storage = MockStorage::new();
+/// assert!(ContractFeatures::load(&storage)?.features.is_empty());
+///
+/// ContractFeatures::new(["receiver-hook", "cw20"])?.save(&mut storage)?;
+/// let features = ContractFeatures::load(&storage)?;
+/// assert_eq!(features.features, ["cw20", "receiver-hook"]);
+/// assert!(features.supports("cw20"));
+/// assert!(!features.supports("cw721"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ContractFeatures {
+    /// Sorted feature names without duplicates
+    pub features: Vec<String>,
+}
+
+impl ContractFeatures {
+    /// Sorts and deduplicates `features`.
+    ///
+    /// Feature names must be between 1 and 64 bytes long and consist of lowercase
+    /// ASCII letters, digits, `-`, `_`, `.` and `/`, so that names compare reliably.
+    pub fn new<I, S>(features: I) -> StdResult<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut features: Vec<String> = features.into_iter().map(Into::into).collect();
+        for feature in &features {
+            validate_feature(feature)?;
+        }
+        features.sort();
+        features.dedup();
+        Ok(ContractFeatures { features })
+    }
+
+    /// Returns true if `feature` was declared.
+    ///
+    /// This does not rely on the order of `features`, since features queried from other
+    /// contracts were not necessarily stored by this type.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Loads the features of this contract. Returns no features if none were saved.
+    pub fn load(storage: &dyn Storage) -> StdResult<Self> {
+        match storage.get(CONTRACT_FEATURES_KEY) {
+            Some(data) => from_slice(&data),
+            None => Ok(ContractFeatures::default()),
+        }
+    }
+
+    /// Saves the features of this contract, replacing the ones saved before.
+    pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
+        storage.set(CONTRACT_FEATURES_KEY, &to_vec(self)?);
+        Ok(())
+    }
+
+    /// Queries the features of another contract. Returns no features if the contract
+    /// did not declare any.
+    pub fn query<C: CustomQuery>(
+        querier: &QuerierWrapper<C>,
+        contract_addr: impl Into<String>,
+    ) -> StdResult<Self> {
+        match querier.query_wasm_raw(contract_addr, CONTRACT_FEATURES_KEY)? {
+            Some(data) => from_slice(&data),
+            None => Ok(ContractFeatures::default()),
+        }
+    }
+}
+
+fn validate_feature(feature: &str) -> StdResult<()> {
+    if feature.is_empty() || feature.len() > MAX_FEATURE_LENGTH {
+        return Err(StdError::generic_err(format!(
+            "Feature name must be between 1 and {} bytes long: {:?}",
+            MAX_FEATURE_LENGTH, feature
+        )));
+    }
+    let valid = feature
+        .bytes()
+        .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"-_./".contains(&byte));
+    if !valid {
+        return Err(StdError::generic_err(format!(
+            "Feature name contains invalid characters: {:?}",
+            feature
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock::{MockQuerier, MockStorage};
+    use crate::{to_binary, ContractResult, Empty, SystemResult, WasmQuery};
+
+    #[test]
+    fn new_sorts_and_deduplicates() {
+        let features = ContractFeatures::new(["ibc-callbacks", "cw20", "cw20/1", "cw20"]).unwrap();
+        assert_eq!(features.features, ["cw20", "cw20/1", "ibc-callbacks"]);
+        assert!(features.supports("cw20/1"));
+        assert!(!features.supports("cw2"));
+    }
+
+    #[test]
+    fn new_validates_names() {
+        ContractFeatures::new(Vec::<String>::new()).unwrap();
+        ContractFeatures::new(["a".repeat(64)]).unwrap();
+
+        for invalid in ["", "CW20", "cw 20", "cw20!", "émoji", &"a".repeat(65)] {
+            match ContractFeatures::new(["cw20", invalid]).unwrap_err() {
+                StdError::GenericErr { msg, .. } => assert!(msg.starts_with("Feature name")),
+                err => panic!("Unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load_work() {
+        let mut storage = MockStorage::new();
+        assert_eq!(
+            ContractFeatures::load(&storage).unwrap(),
+            ContractFeatures::default()
+        );
+
+        let features = ContractFeatures::new(["cw20", "receiver-hook"]).unwrap();
+        features.save(&mut storage).unwrap();
+        assert_eq!(
+            storage.get(CONTRACT_FEATURES_KEY).unwrap(),
+            br#"{"features":["cw20","receiver-hook"]}"#
+        );
+        assert_eq!(ContractFeatures::load(&storage).unwrap(), features);
+
+        // Saving replaces the previous features
+        let features = ContractFeatures::new(["cw721"]).unwrap();
+        features.save(&mut storage).unwrap();
+        assert_eq!(ContractFeatures::load(&storage).unwrap(), features);
+    }
+
+    #[test]
+    fn query_works() {
+        let mut querier = MockQuerier::<Empty>::new(&[]);
+        querier.update_wasm(|query| match query {
+            WasmQuery::Raw { contract_addr, key } => {
+                assert_eq!(key.as_slice(), CONTRACT_FEATURES_KEY);
+                let value = match contract_addr.as_str() {
+                    "token" => to_binary(&ContractFeatures::new(["cw20"]).unwrap()).unwrap(),
+                    // Raw queries return empty data for missing keys
+                    _ => Default::default(),
+                };
+                SystemResult::Ok(ContractResult::Ok(value))
+            }
+            _ => panic!("Unexpected query: {:?}", query),
+        });
+        let wrapper = QuerierWrapper::<Empty>::new(&querier);
+
+        let features = ContractFeatures::query(&wrapper, "token").unwrap();
+        assert!(features.supports("cw20"));
+        let features = ContractFeatures::query(&wrapper, "other").unwrap();
+        assert_eq!(features, ContractFeatures::default());
+    }
+}
@@ -0,0 +1,217 @@
+//! Helpers to render numbers for humans, e.g. in event attributes or error messages.
+//!
+//! All of them work on the decimal digits of integers and never use floats, so the
+//! output is the same on every node.
+
+use crate::coins::Coin;
+use crate::math::{Decimal, Uint128};
+
+/// Formats `value` with exactly `precision` fractional digits. Digits beyond the
+/// precision are truncated, i.e. the value is rounded towards zero.
+///
+/// ```
+/// # use cosmwasm_std::{format_decimal_with_precision, Decimal};
+/// let value = Decimal::from_ratio(2u128, 3u128);
+/// assert_eq!(format_decimal_with_precision(value, 2), "0.66");
+/// assert_eq!(format_decimal_with_precision(Decimal::percent(150), 3), "1.500");
+/// assert_eq!(format_decimal_with_precision(Decimal::percent(150), 0), "1");
+/// ```
+pub fn format_decimal_with_precision(value: Decimal, precision: u32) -> String {
+    let (whole, fractional) = split_digits(&value.atomics().to_string(), value.decimal_places());
+    let mut fractional: String = fractional.chars().take(precision as usize).collect();
+    while fractional.len() < precision as usize {
+        fractional.push('0');
+    }
+
+    if fractional.is_empty() {
+        whole
+    } else {
+        format!("{}.{}", whole, fractional)
+    }
+}
+
+/// Inserts `separator` between every group of three digits of the integer part of
+/// `number`. Everything after the leading digits (a fractional part, a denom) is
+/// left as it is.
+///
+/// ```
+/// # use cosmwasm_std::{format_amount, format_thousands, Uint128};
+/// assert_eq!(format_thousands("1234567", ','), "1,234,567");
+/// assert_eq!(format_thousands("1234.5678", '\''), "1'234.5678");
+/// assert_eq!(format_thousands(&format_amount(Uint128::new(12345000000), 6), ','), "12,345");
+/// ```
+pub fn format_thousands(number: &str, separator: char) -> String {
+    let digits = number
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(number.len());
+    let (whole, rest) = number.split_at(digits);
+
+    let mut out = String::with_capacity(number.len() + digits / 3);
+    // The first group has between one and three digits.
+    let mut group_left = match digits % 3 {
+        0 => 3,
+        first_group => first_group,
+    };
+    for digit in whole.chars() {
+        if group_left == 0 {
+            out.push(separator);
+            group_left = 3;
+        }
+        out.push(digit);
+        group_left -= 1;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Formats an amount of the smallest unit of a token in a unit that is `decimals`
+/// orders of magnitude larger. Trailing zeros of the fractional part are omitted.
+///
+/// ```
+/// # use cosmwasm_std::{format_amount, Uint128};
+/// assert_eq!(format_amount(Uint128::new(1500000), 6), "1.5");
+/// assert_eq!(format_amount(Uint128::new(1), 6), "0.000001");
+/// assert_eq!(format_amount(Uint128::new(42), 0), "42");
+/// ```
+pub fn format_amount(amount: Uint128, decimals: u32) -> String {
+    let (whole, fractional) = split_digits(&amount.to_string(), decimals);
+    let fractional = fractional.trim_end_matches('0');
+
+    if fractional.is_empty() {
+        whole
+    } else {
+        format!("{}.{}", whole, fractional)
+    }
+}
+
+/// How amounts of a denom are shown to users, like the denom units of the bank
+/// module's denom metadata.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayDenom<'a> {
+    /// The denom used on chain, e.g. `uatom`
+    pub base: &'a str,
+    /// The denom shown to users, e.g. `ATOM`
+    pub display: &'a str,
+    /// The number of decimals of `display` in terms of `base`, e.g. 6
+    pub exponent: u32,
+}
+
+/// Formats a coin using the first matching display denom, e.g. `1.5 ATOM`. Coins of
+/// other denoms are formatted as they are, e.g. `1500000ufoo`.
+///
+/// ```
+/// # use cosmwasm_std::{coin, format_coin, format_thousands, DisplayDenom};
+/// const ATOM: DisplayDenom = DisplayDenom {
+///     base: "uatom",
+///     display: "ATOM",
+///     exponent: 6,
+/// };
+/// assert_eq!(format_coin(&coin(1234500000, "uatom"), &[ATOM]), "1234.5 ATOM");
+/// assert_eq!(format_thousands(&format_coin(&coin(1234500000, "uatom"), &[ATOM]), ','), "1,234.5 ATOM");
+/// assert_eq!(format_coin(&coin(1500000, "ufoo"), &[ATOM]), "1500000ufoo");
+/// ```
+pub fn format_coin(coin: &Coin, denoms: &[DisplayDenom]) -> String {
+    match denoms.iter().find(|denom| denom.base == coin.denom) {
+        Some(denom) => format!(
+            "{} {}",
+            format_amount(coin.amount, denom.exponent),
+            denom.display
+        ),
+        None => coin.to_string(),
+    }
+}
+
+/// Splits the decimal digits of an integer into the whole and fractional part of
+/// the number divided by `10^decimals`. The fractional part has `decimals` digits.
+fn split_digits(digits: &str, decimals: u32) -> (String, String) {
+    let decimals = decimals as usize;
+    if digits.len() > decimals {
+        let (whole, fractional) = digits.split_at(digits.len() - decimals);
+        (whole.to_string(), fractional.to_string())
+    } else {
+        let padding = "0".repeat(decimals - digits.len());
+        ("0".to_string(), padding + digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::coins::coin;
+
+    #[test]
+    fn format_decimal_with_precision_works() {
+        assert_eq!(format_decimal_with_precision(Decimal::zero(), 0), "0");
+        assert_eq!(format_decimal_with_precision(Decimal::zero(), 2), "0.00");
+        assert_eq!(format_decimal_with_precision(Decimal::one(), 1), "1.0");
+        assert_eq!(
+            format_decimal_with_precision(Decimal::permille(1234), 2),
+            "1.23"
+        );
+        // Digits are truncated, not rounded
+        assert_eq!(
+            format_decimal_with_precision(Decimal::permille(1999), 2),
+            "1.99"
+        );
+        // Precision beyond the 18 decimal places of Decimal
+        assert_eq!(
+            format_decimal_with_precision(Decimal::from_ratio(1u128, 3u128), 20),
+            "0.33333333333333333300"
+        );
+        assert_eq!(
+            format_decimal_with_precision(Decimal::MAX, 3),
+            "340282366920938463463.374"
+        );
+    }
+
+    #[test]
+    fn format_thousands_works() {
+        assert_eq!(format_thousands("", ','), "");
+        assert_eq!(format_thousands("1", ','), "1");
+        assert_eq!(format_thousands("123", ','), "123");
+        assert_eq!(format_thousands("1234", ','), "1,234");
+        assert_eq!(format_thousands("123456", ','), "123,456");
+        assert_eq!(format_thousands("1234567890", ' '), "1 234 567 890");
+        assert_eq!(format_thousands("1234.56789", ','), "1,234.56789");
+        assert_eq!(format_thousands("1234567uatom", ','), "1,234,567uatom");
+        assert_eq!(format_thousands("-1234", ','), "-1234");
+    }
+
+    #[test]
+    fn format_amount_works() {
+        assert_eq!(format_amount(Uint128::zero(), 6), "0");
+        assert_eq!(format_amount(Uint128::new(1), 6), "0.000001");
+        assert_eq!(format_amount(Uint128::new(1_000_000), 6), "1");
+        assert_eq!(format_amount(Uint128::new(1_234_560), 6), "1.23456");
+        assert_eq!(format_amount(Uint128::new(1_234_560), 0), "1234560");
+        assert_eq!(
+            format_amount(Uint128::MAX, 18),
+            "340282366920938463463.374607431768211455"
+        );
+    }
+
+    #[test]
+    fn format_coin_works() {
+        let denoms = [
+            DisplayDenom {
+                base: "uatom",
+                display: "ATOM",
+                exponent: 6,
+            },
+            DisplayDenom {
+                base: "aevmos",
+                display: "EVMOS",
+                exponent: 18,
+            },
+        ];
+        assert_eq!(format_coin(&coin(0, "uatom"), &denoms), "0 ATOM");
+        assert_eq!(format_coin(&coin(1_500_000, "uatom"), &denoms), "1.5 ATOM");
+        assert_eq!(
+            format_coin(&coin(25_000_000_000_000_000, "aevmos"), &denoms),
+            "0.025 EVMOS"
+        );
+        assert_eq!(format_coin(&coin(7, "ATOM"), &denoms), "7ATOM");
+        assert_eq!(format_coin(&coin(7, "uatom"), &[]), "7uatom");
+    }
+}
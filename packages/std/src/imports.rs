@@ -72,6 +72,10 @@ extern "C" {
     /// Executes a query on the chain (import). Not to be confused with the
     /// query export, which queries the state of the contract.
     fn query_chain(request: u32) -> u32;
+
+    /// Returns the gas left for the current call.
+    #[cfg(feature = "gas_remaining")]
+    fn gas_remaining() -> u64;
 }
 
 /// A stateless convenience wrapper around database imports provided by the VM.
@@ -360,6 +364,11 @@ impl Api for ExternalApi {
         let region_ptr = region.as_ref() as *const Region as u32;
         unsafe { debug(region_ptr) };
     }
+
+    #[cfg(feature = "gas_remaining")]
+    fn gas_remaining(&self) -> StdResult<u64> {
+        Ok(unsafe { gas_remaining() })
+    }
 }
 
 /// Takes a pointer to a Region and reads the data into a String.
@@ -0,0 +1,378 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::type_name;
+
+use crate::canonical_json::{parse, Value};
+use crate::errors::{StdError, StdResult};
+use crate::serde::{from_slice, to_vec};
+use crate::traits::Storage;
+
+/// Changes stored JSON declaratively, e.g. in a `migrate` entry point when the type the
+/// data was stored with is no longer part of the contract.
+///
+/// Fields are addressed by paths of member names separated by dots, e.g. `"fees.denom"`.
+/// The steps are applied in the order they were added. Renaming or removing a missing
+/// field does nothing, so a migration can run on data that was migrated before.
+///
+/// ```
+/// # use cosmwasm_std::{testing::MockStorage, JsonMigration, Storage, Uint128};
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct Config {
+///     admin: String,
+///     fee: Uint128,
+///     paused: bool,
+/// }
+///
+/// let mut storage = MockStorage::new();
+/// storage.set(b"config", br#"{"owner":"alice","fee":"12","legacy":true}"#);
+///
+/// let config: Config = JsonMigration::new()
+///     .rename("owner", "admin")
+///     .remove("legacy")
+///     .default_value("paused", &false)
+///     .migrate_item(&mut storage, b"config")
+///     .unwrap();
+/// assert_eq!(config.admin, "alice");
+/// assert!(!config.paused);
+/// ```
+#[derive(Debug, Default)]
+pub struct JsonMigration {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Rename {
+        from: Path,
+        to: Vec<u8>,
+    },
+    Remove {
+        path: Path,
+    },
+    Set {
+        path: Path,
+        value: Vec<u8>,
+        overwrite: bool,
+    },
+    /// A value that could not be serialized, reported by `apply`
+    Invalid {
+        path: String,
+        msg: String,
+    },
+}
+
+/// The quoted member names of a path, serialized like by `to_vec` so that they can be
+/// compared to the raw member names of a parsed object.
+#[derive(Debug)]
+struct Path {
+    names: Vec<Vec<u8>>,
+}
+
+impl Path {
+    fn new(path: &str) -> Self {
+        Path {
+            names: path.split('.').map(quote).collect(),
+        }
+    }
+
+    fn parent(&self) -> &[Vec<u8>] {
+        &self.names[..self.names.len() - 1]
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.names[self.names.len() - 1]
+    }
+}
+
+fn quote(name: &str) -> Vec<u8> {
+    // Serializing a string cannot fail
+    to_vec(name).unwrap()
+}
+
+impl JsonMigration {
+    pub fn new() -> Self {
+        JsonMigration::default()
+    }
+
+    /// Renames the field at `from` to `to` within the same object.
+    /// Fails when applied if both fields exist.
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        self.steps.push(Step::Rename {
+            from: Path::new(from),
+            to: quote(to),
+        });
+        self
+    }
+
+    /// Removes the field at `path`.
+    pub fn remove(mut self, path: &str) -> Self {
+        self.steps.push(Step::Remove {
+            path: Path::new(path),
+        });
+        self
+    }
+
+    /// Sets the field at `path` to `value` unless it exists. Missing objects on the
+    /// way are created.
+    pub fn default_value<T: Serialize + ?Sized>(self, path: &str, value: &T) -> Self {
+        self.set_value(path, value, false)
+    }
+
+    /// Sets the field at `path` to `value`, replacing the existing value. Missing
+    /// objects on the way are created.
+    pub fn set<T: Serialize + ?Sized>(self, path: &str, value: &T) -> Self {
+        self.set_value(path, value, true)
+    }
+
+    fn set_value<T: Serialize + ?Sized>(mut self, path: &str, value: &T, overwrite: bool) -> Self {
+        let step = match to_vec(value) {
+            Ok(value) => Step::Set {
+                path: Path::new(path),
+                value,
+                overwrite,
+            },
+            Err(err) => Step::Invalid {
+                path: path.to_string(),
+                msg: err.to_string(),
+            },
+        };
+        self.steps.push(step);
+        self
+    }
+
+    /// Applies all steps to `json`, which must be written by [`to_vec`](crate::to_vec)
+    /// without any whitespace. The members of all objects in the result are sorted
+    /// like by [`to_canonical_json`](crate::to_canonical_json).
+    pub fn apply(&self, json: &[u8]) -> StdResult<Vec<u8>> {
+        let mut value = parse(json).map_err(|msg| StdError::parse_err("JSON", msg))?;
+        for step in &self.steps {
+            apply_step(step, &mut value)
+                .map_err(|msg| StdError::generic_err(format!("JSON migration failed: {}", msg)))?;
+        }
+        let mut out = Vec::with_capacity(json.len());
+        value
+            .write(&mut out)
+            .map_err(|msg| StdError::serialize_err("JSON", msg))?;
+        Ok(out)
+    }
+
+    /// Loads the JSON stored under `key`, applies all steps, checks that the result is a
+    /// valid `T` and stores it again, serialized from `T`. Returns the migrated item.
+    ///
+    /// `key` is the full storage key, e.g. the length prefixed namespace of a
+    /// `cosmwasm_storage::Singleton`.
+    pub fn migrate_item<T: Serialize + DeserializeOwned>(
+        &self,
+        storage: &mut dyn Storage,
+        key: &[u8],
+    ) -> StdResult<T> {
+        let json = storage
+            .get(key)
+            .ok_or_else(|| StdError::not_found(type_name::<T>()))?;
+        let item: T = from_slice(&self.apply(&json)?)?;
+        storage.set(key, &to_vec(&item)?);
+        Ok(item)
+    }
+}
+
+fn apply_step<'a>(step: &'a Step, root: &mut Value<'a>) -> Result<(), String> {
+    match step {
+        Step::Rename { from, to } => {
+            if let Some(members) = object_at(root, from.parent(), false)? {
+                if let Some(index) = position(members, from.name()) {
+                    if position(members, to).is_some() {
+                        return Err(format!(
+                            "Cannot rename {} to {}, both exist",
+                            String::from_utf8_lossy(from.name()),
+                            String::from_utf8_lossy(to)
+                        ));
+                    }
+                    members[index].0 = to;
+                }
+            }
+        }
+        Step::Remove { path } => {
+            if let Some(members) = object_at(root, path.parent(), false)? {
+                members.retain(|(name, _)| *name != path.name());
+            }
+        }
+        Step::Set {
+            path,
+            value,
+            overwrite,
+        } => {
+            let members =
+                object_at(root, path.parent(), true)?.expect("missing objects are created");
+            let value = parse(value)?;
+            match position(members, path.name()) {
+                Some(index) if *overwrite => members[index].1 = value,
+                Some(_) => {}
+                None => members.push((path.name(), value)),
+            }
+        }
+        Step::Invalid { path, msg } => {
+            return Err(format!("Cannot serialize the value of {}: {}", path, msg))
+        }
+    }
+    Ok(())
+}
+
+const NO_OBJECT: &str = "The parent of the field is no object";
+
+type Members<'a> = Vec<(&'a [u8], Value<'a>)>;
+
+/// The members of the object at `path`. Missing objects are created if `create` is set
+/// and make this return `None` otherwise. Fails if a value on the way is no object.
+fn object_at<'v, 'a>(
+    root: &'v mut Value<'a>,
+    path: &'a [Vec<u8>],
+    create: bool,
+) -> Result<Option<&'v mut Members<'a>>, String> {
+    let mut current = root;
+    for name in path {
+        let members = match current {
+            Value::Object(members) => members,
+            _ => return Err(NO_OBJECT.to_string()),
+        };
+        let index = match position(members, name) {
+            Some(index) => index,
+            None if create => {
+                members.push((name, Value::Object(Vec::new())));
+                members.len() - 1
+            }
+            None => return Ok(None),
+        };
+        current = &mut members[index].1;
+    }
+    match current {
+        Value::Object(members) => Ok(Some(members)),
+        _ => Err(NO_OBJECT.to_string()),
+    }
+}
+
+fn position(members: &[(&[u8], Value)], name: &[u8]) -> Option<usize> {
+    members.iter().position(|(member, _)| *member == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{MemoryStorage, Uint128};
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Fees {
+        denom: String,
+        amount: Uint128,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Config {
+        admin: String,
+        fees: Fees,
+        paused: bool,
+    }
+
+    #[test]
+    fn apply_works() {
+        let migration = JsonMigration::new()
+            .rename("owner", "admin")
+            .rename("fees.token", "denom")
+            .remove("legacy")
+            .default_value("paused", &false)
+            .set("fees.amount", &Uint128::new(7))
+            .default_value("limits.max", &3u32);
+        let json =
+            br#"{"owner":"alice","fees":{"token":"ucosm","amount":"1"},"legacy":[1,{"a":2}]}"#;
+        let migrated = migration.apply(json).unwrap();
+        assert_eq!(
+            String::from_utf8(migrated.clone()).unwrap(),
+            r#"{"admin":"alice","fees":{"amount":"7","denom":"ucosm"},"limits":{"max":3},"paused":false}"#
+        );
+
+        // Running it again changes nothing
+        assert_eq!(migration.apply(&migrated).unwrap(), migrated);
+    }
+
+    #[test]
+    fn apply_keeps_existing_values_for_defaults() {
+        let migration = JsonMigration::new().default_value("paused", &false);
+        assert_eq!(
+            migration.apply(br#"{"paused":true}"#).unwrap(),
+            br#"{"paused":true}"#
+        );
+    }
+
+    #[test]
+    fn apply_fails_for_conflicts() {
+        let migration = JsonMigration::new().rename("owner", "admin");
+        match migration
+            .apply(br#"{"owner":"alice","admin":"bob"}"#)
+            .unwrap_err()
+        {
+            StdError::GenericErr { msg, .. } => assert_eq!(
+                msg,
+                r#"JSON migration failed: Cannot rename "owner" to "admin", both exist"#
+            ),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        let migration = JsonMigration::new().set("owner.name", "alice");
+        match migration.apply(br#"{"owner":"alice"}"#).unwrap_err() {
+            StdError::GenericErr { msg, .. } => assert_eq!(
+                msg,
+                "JSON migration failed: The parent of the field is no object"
+            ),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        match JsonMigration::new().apply(br#"{"a":1} "#).unwrap_err() {
+            StdError::ParseErr { target_type, .. } => assert_eq!(target_type, "JSON"),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn migrate_item_works() {
+        let mut storage = MemoryStorage::new();
+        storage.set(
+            b"config",
+            br#"{"owner":"alice","fees":{"denom":"ucosm","amount":"1"}}"#,
+        );
+        let migration = JsonMigration::new()
+            .rename("owner", "admin")
+            .default_value("paused", &false);
+
+        let config: Config = migration.migrate_item(&mut storage, b"config").unwrap();
+        let expected = Config {
+            admin: "alice".to_string(),
+            fees: Fees {
+                denom: "ucosm".to_string(),
+                amount: Uint128::new(1),
+            },
+            paused: false,
+        };
+        assert_eq!(config, expected);
+        assert_eq!(storage.get(b"config").unwrap(), to_vec(&expected).unwrap());
+
+        // Missing items and results that are no valid `T`
+        match migration
+            .migrate_item::<Config>(&mut storage, b"other")
+            .unwrap_err()
+        {
+            StdError::NotFound { kind, .. } => assert!(kind.ends_with("Config")),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        let migration = JsonMigration::new().remove("fees");
+        match migration
+            .migrate_item::<Config>(&mut storage, b"config")
+            .unwrap_err()
+        {
+            StdError::ParseErr { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(storage.get(b"config").unwrap(), to_vec(&expected).unwrap());
+    }
+}
@@ -0,0 +1,207 @@
+//! Hashes for building compact storage keys, e.g. for indexes over long or
+//! composite values.
+//!
+//! Unlike `std::collections::hash_map::DefaultHasher`, whose output may change
+//! between Rust releases, the output of these functions is stable: it only
+//! depends on the input bytes, never on the platform, its endianness or the
+//! compiler version, and it will not change in any future version of this crate.
+//! Keys stored in one version of a contract can therefore be found again after a
+//! migration.
+//!
+//! Composite keys are hashed from a list of parts. Every part is prefixed with
+//! its length as a big-endian `u32`, so that e.g. `["ab", "c"]` and `["a", "bc"]`
+//! get different hashes.
+
+use sha2::{Digest, Sha256};
+
+/// The SHA-256 hash of the length-prefixed `parts`, see the module documentation.
+///
+/// Use this when the hashed values can be chosen by untrusted parties, since
+/// finding collisions is infeasible.
+///
+/// ```
+/// # use cosmwasm_std::sha256_key;
+/// let key = sha256_key(&[b"owner", b"token-1"]);
+/// assert_ne!(key, sha256_key(&[b"owne", b"rtoken-1"]));
+/// ```
+pub fn sha256_key(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u32).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// The first 8 bytes of [`sha256_key`].
+///
+/// With 64 bits, a collision among `n` keys becomes likely around `n = 2^32`, and
+/// an attacker can find one with about as many attempts. Use the full
+/// [`sha256_key`] if a collision could be exploited, e.g. to overwrite the entry
+/// of another user.
+pub fn short_key(parts: &[&[u8]]) -> [u8; 8] {
+    let hash = sha256_key(parts);
+    let mut key = [0u8; 8];
+    key.copy_from_slice(&hash[..8]);
+    key
+}
+
+/// The big-endian [`xxhash64`] of the length-prefixed `parts` with seed 0, see the
+/// module documentation.
+///
+/// This is much cheaper than [`sha256_key`] but not cryptographic: collisions can
+/// be constructed deliberately. Only use it for values that untrusted parties
+/// cannot choose, e.g. keys built from data of the contract itself.
+pub fn xxhash64_key(parts: &[&[u8]]) -> [u8; 8] {
+    let mut data = Vec::with_capacity(parts.iter().map(|part| 4 + part.len()).sum());
+    for part in parts {
+        data.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        data.extend_from_slice(part);
+    }
+    xxhash64(&data, 0).to_be_bytes()
+}
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+/// The 64 bit xxHash (XXH64) of `data`, as specified in
+/// <https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md>. Not
+/// cryptographic, see [`xxhash64_key`].
+///
+/// ```
+/// # use cosmwasm_std::xxhash64;
+/// assert_eq!(xxhash64(b"abc", 0), 0x44BC2CF5AD770999);
+/// ```
+pub fn xxhash64(data: &[u8], seed: u64) -> u64 {
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut accumulators = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            for (index, accumulator) in accumulators.iter_mut().enumerate() {
+                *accumulator = round(*accumulator, read_u64(&rest[index * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let [acc1, acc2, acc3, acc4] = accumulators;
+        let mut hash = acc1
+            .rotate_left(1)
+            .wrapping_add(acc2.rotate_left(7))
+            .wrapping_add(acc3.rotate_left(12))
+            .wrapping_add(acc4.rotate_left(18));
+        for accumulator in accumulators {
+            hash = (hash ^ round(0, accumulator))
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+        }
+        hash
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= round(0, read_u64(rest));
+        hash = hash
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&rest[..4]);
+        hash ^= u64::from(u32::from_le_bytes(bytes)).wrapping_mul(PRIME64_1);
+        hash = hash
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash ^= u64::from(*byte).wrapping_mul(PRIME64_5);
+        hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+fn round(accumulator: u64, input: u64) -> u64 {
+    accumulator
+        .wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+/// Reads the first 8 bytes of `data` as a little-endian `u64`
+fn read_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hex_literal::hex;
+
+    #[test]
+    fn sha256_key_works() {
+        // echo -n "" | sha256sum
+        assert_eq!(
+            sha256_key(&[]),
+            hex!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        // printf '\x00\x00\x00\x03abc' | sha256sum
+        let key = sha256_key(&[b"abc"]);
+        let mut hasher = Sha256::new();
+        hasher.update(b"\x00\x00\x00\x03abc");
+        assert_eq!(key, <[u8; 32]>::from(hasher.finalize()));
+
+        // Parts are length-prefixed
+        assert_ne!(sha256_key(&[b"ab", b"c"]), sha256_key(&[b"a", b"bc"]));
+        assert_ne!(sha256_key(&[b"abc"]), sha256_key(&[b"abc", b""]));
+    }
+
+    #[test]
+    fn short_key_works() {
+        let key = short_key(&[b"owner", b"token-1"]);
+        assert_eq!(key[..], sha256_key(&[b"owner", b"token-1"])[..8]);
+    }
+
+    #[test]
+    fn xxhash64_works() {
+        // Reference values of the xxHash implementation
+        assert_eq!(xxhash64(b"", 0), 0xEF46DB3751D8E999);
+        assert_eq!(xxhash64(b"a", 0), 0xD24EC4F1A98C6E5B);
+        assert_eq!(xxhash64(b"abc", 0), 0x44BC2CF5AD770999);
+        // More than 32 bytes, with 8 byte, 4 byte and single byte tails
+        assert_eq!(
+            xxhash64(b"Nobody inspects the spammish repetition", 0),
+            0xFBCEA83C8A378BF1
+        );
+
+        assert_ne!(xxhash64(b"abc", 1), xxhash64(b"abc", 0));
+    }
+
+    #[test]
+    fn xxhash64_key_works() {
+        assert_eq!(
+            xxhash64_key(&[b"abc"]),
+            xxhash64(b"\x00\x00\x00\x03abc", 0).to_be_bytes()
+        );
+        assert_ne!(xxhash64_key(&[b"ab", b"c"]), xxhash64_key(&[b"a", b"bc"]));
+    }
+}
@@ -2,34 +2,55 @@
 
 // Exposed on all platforms
 
+mod addr_collections;
 mod addresses;
 mod assertions;
+mod balances;
 mod binary;
+mod canonical_json;
 mod coins;
 mod conversion;
 mod deps;
 mod errors;
+mod event_limits;
+mod features;
+mod formatting;
 mod ibc;
 mod import_helpers;
 #[cfg(feature = "iterator")]
 mod iterator;
+mod json_migration;
+mod key_hash;
 mod math;
+mod pagination;
 mod query;
+mod receive;
+mod remote;
 mod results;
+mod sampling;
 mod sections;
 mod serde;
+mod state_events;
 mod storage;
 mod timestamp;
 mod traits;
 mod types;
 
+pub use crate::addr_collections::{AddrMap, AddrSet};
 pub use crate::addresses::{Addr, CanonicalAddr};
+pub use crate::balances::{BalanceDiff, BalanceSnapshot};
 pub use crate::binary::Binary;
+pub use crate::canonical_json::to_canonical_json;
 pub use crate::coins::{coin, coins, has_coins, Coin};
 pub use crate::deps::{Deps, DepsMut, OwnedDeps};
 pub use crate::errors::{
     ConversionOverflowError, DivideByZeroError, OverflowError, OverflowOperation,
-    RecoverPubkeyError, StdError, StdResult, SystemError, VerificationError,
+    RecoverPubkeyError, StdError, StdResult, StructuredError, SystemError, VerificationError,
+};
+pub use crate::event_limits::{EventLimits, PART_ATTRIBUTE, TRUNCATION_MARKER};
+pub use crate::features::{ContractFeatures, CONTRACT_FEATURES_KEY};
+pub use crate::formatting::{
+    format_amount, format_coin, format_decimal_with_precision, format_thousands, DisplayDenom,
 };
 #[cfg(feature = "stargate")]
 pub use crate::ibc::{
@@ -42,10 +63,13 @@ pub use crate::ibc::{
 pub use crate::iterator::Pair;
 #[cfg(feature = "iterator")]
 pub use crate::iterator::{Order, Record};
+pub use crate::json_migration::JsonMigration;
+pub use crate::key_hash::{sha256_key, short_key, xxhash64, xxhash64_key};
 pub use crate::math::{
-    Decimal, Decimal256, Decimal256RangeExceeded, DecimalRangeExceeded, Fraction, Isqrt, Uint128,
-    Uint256, Uint512, Uint64,
+    Decimal, Decimal256, Decimal256RangeExceeded, DecimalRangeExceeded, Fraction, Isqrt, Rounding,
+    Uint128, Uint256, Uint512, Uint64,
 };
+pub use crate::pagination::PageResponse;
 pub use crate::query::{
     AllBalanceResponse, BalanceResponse, BankQuery, ContractInfoResponse, CustomQuery,
     QueryRequest, WasmQuery,
@@ -57,18 +81,24 @@ pub use crate::query::{
 };
 #[cfg(feature = "stargate")]
 pub use crate::query::{ChannelResponse, IbcQuery, ListChannelsResponse, PortIdResponse};
+pub use crate::receive::{NftReceiveMsg, ReceiveHook, TokenReceiveMsg};
+pub use crate::remote::{ContractInterface, Remote};
+#[cfg(feature = "multisend")]
+pub use crate::results::BankOutput;
 pub use crate::results::{
     attr, wasm_execute, wasm_instantiate, Attribute, BankMsg, ContractResult, CosmosMsg, CustomMsg,
-    Empty, Event, QueryResponse, Reply, ReplyOn, Response, SubMsg, SubMsgExecutionResponse,
-    SubMsgResult, SystemResult, WasmMsg,
+    Empty, Event, MultiSend, QueryResponse, Reply, ReplyOn, Response, SubMsg,
+    SubMsgExecutionResponse, SubMsgResult, SystemResult, WasmMsg,
 };
 #[cfg(feature = "staking")]
 pub use crate::results::{DistributionMsg, StakingMsg};
 #[cfg(feature = "stargate")]
 pub use crate::results::{GovMsg, VoteOption};
+pub use crate::sampling::select_weighted;
 pub use crate::serde::{from_binary, from_slice, to_binary, to_vec};
+pub use crate::state_events::StateEvents;
 pub use crate::storage::MemoryStorage;
-pub use crate::timestamp::Timestamp;
+pub use crate::timestamp::{duration_from_proto, duration_to_proto, Timestamp};
 pub use crate::traits::{Api, Querier, QuerierResult, QuerierWrapper, Storage};
 pub use crate::types::{BlockInfo, ContractInfo, Env, MessageInfo, TransactionInfo};
 
@@ -9,11 +9,12 @@ use std::str::FromStr;
 use thiserror::Error;
 
 use crate::errors::StdError;
-use crate::OverflowError;
+use crate::{ConversionOverflowError, OverflowError};
 
 use super::Fraction;
 use super::Isqrt;
-use super::{Uint128, Uint256};
+use super::Rounding;
+use super::{Uint128, Uint256, Uint64};
 
 /// A fixed-point decimal value with 18 fractional digits, i.e. Decimal(1_000_000_000_000_000_000) == 1.0
 ///
@@ -200,6 +201,55 @@ impl Decimal {
         })
     }
 
+    /// Converts the decimal to an integer, rounding as specified.
+    ///
+    /// This cannot overflow, since even `Decimal::MAX` rounded up is much smaller
+    /// than `Uint128::MAX`.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// # use cosmwasm_std::{Decimal, Rounding, Uint128};
+    /// let value = Decimal::percent(250);
+    /// assert_eq!(value.to_uint(Rounding::Floor), Uint128::new(2));
+    /// assert_eq!(value.to_uint(Rounding::Ceil), Uint128::new(3));
+    /// assert_eq!(value.to_uint(Rounding::HalfUp), Uint128::new(3));
+    /// ```
+    pub fn to_uint(self, rounding: Rounding) -> Uint128 {
+        let whole = self.0 / Self::DECIMAL_FRACTIONAL;
+        let fractional = self.0 % Self::DECIMAL_FRACTIONAL;
+        let round_up = match rounding {
+            Rounding::Floor => false,
+            Rounding::Ceil => !fractional.is_zero(),
+            Rounding::HalfUp => fractional >= Self::DECIMAL_FRACTIONAL / Uint128::new(2),
+        };
+        if round_up {
+            whole + Uint128::new(1)
+        } else {
+            whole
+        }
+    }
+
+    /// Converts the decimal to a `Uint64`, rounding as specified. Returns an error
+    /// if the rounded value does not fit.
+    pub fn checked_to_uint64(self, rounding: Rounding) -> Result<Uint64, ConversionOverflowError> {
+        let value = self.to_uint(rounding).u128();
+        if value > u64::MAX as u128 {
+            Err(ConversionOverflowError::new(
+                "Decimal",
+                "Uint64",
+                self.to_string(),
+            ))
+        } else {
+            Ok(Uint64::new(value as u64))
+        }
+    }
+
+    /// Like `checked_to_uint64`, but returns `Uint64::MAX` if the rounded value does not fit.
+    pub fn saturating_to_uint64(self, rounding: Rounding) -> Uint64 {
+        self.checked_to_uint64(rounding).unwrap_or(Uint64::MAX)
+    }
+
     /// Returns the approximate square root as a Decimal.
     ///
     /// This should not overflow or panic.
@@ -1183,6 +1233,59 @@ mod tests {
         dec /= Uint128::new(0);
     }
 
+    #[test]
+    fn decimal_to_uint_works() {
+        let cases = [
+            // value, floor, ceil, half up
+            ("0", 0, 0, 0),
+            ("1", 1, 1, 1),
+            ("1.000000000000000001", 1, 2, 1),
+            ("1.499999999999999999", 1, 2, 1),
+            ("1.5", 1, 2, 2),
+            ("1.999999999999999999", 1, 2, 2),
+            ("0.5", 0, 1, 1),
+            ("42", 42, 42, 42),
+        ];
+        for (value, floor, ceil, half_up) in cases {
+            let value = Decimal::from_str(value).unwrap();
+            assert_eq!(value.to_uint(Rounding::Floor), Uint128::new(floor));
+            assert_eq!(value.to_uint(Rounding::Ceil), Uint128::new(ceil));
+            assert_eq!(value.to_uint(Rounding::HalfUp), Uint128::new(half_up));
+        }
+
+        // Does not overflow
+        assert_eq!(
+            Decimal::MAX.to_uint(Rounding::Floor),
+            Uint128::new(340282366920938463463)
+        );
+        assert_eq!(
+            Decimal::MAX.to_uint(Rounding::Ceil),
+            Uint128::new(340282366920938463464)
+        );
+    }
+
+    #[test]
+    fn decimal_checked_to_uint64_works() {
+        let max = Decimal::from_ratio(u64::MAX, 1u128);
+        assert_eq!(max.checked_to_uint64(Rounding::Ceil), Ok(Uint64::MAX));
+
+        let value = max + Decimal::percent(50);
+        assert_eq!(value.checked_to_uint64(Rounding::Floor), Ok(Uint64::MAX));
+        assert_eq!(
+            value.checked_to_uint64(Rounding::HalfUp),
+            Err(ConversionOverflowError::new(
+                "Decimal",
+                "Uint64",
+                "18446744073709551615.5"
+            ))
+        );
+        assert_eq!(value.saturating_to_uint64(Rounding::Ceil), Uint64::MAX);
+        assert_eq!(
+            Decimal::percent(150).saturating_to_uint64(Rounding::Floor),
+            Uint64::new(1)
+        );
+    }
+
     #[test]
     fn decimal_uint128_sqrt() {
         assert_eq!(Decimal::percent(900).sqrt(), Decimal::percent(300));
@@ -9,10 +9,11 @@ use std::str::FromStr;
 use thiserror::Error;
 
 use crate::errors::StdError;
-use crate::{OverflowError, Uint512};
+use crate::{ConversionOverflowError, OverflowError, Uint128, Uint512};
 
 use super::Fraction;
 use super::Isqrt;
+use super::Rounding;
 use super::Uint256;
 
 /// A fixed-point decimal value with 18 fractional digits, i.e. Decimal256(1_000_000_000_000_000_000) == 1.0
@@ -212,6 +213,51 @@ impl Decimal256 {
         })
     }
 
+    /// Converts the decimal to an integer, rounding as specified.
+    ///
+    /// This cannot overflow, since even `Decimal256::MAX` rounded up is much smaller
+    /// than `Uint256::MAX`.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// # use cosmwasm_std::{Decimal256, Rounding, Uint256};
+    /// let value = Decimal256::percent(249);
+    /// assert_eq!(value.to_uint(Rounding::Floor), Uint256::from(2u128));
+    /// assert_eq!(value.to_uint(Rounding::Ceil), Uint256::from(3u128));
+    /// assert_eq!(value.to_uint(Rounding::HalfUp), Uint256::from(2u128));
+    /// ```
+    pub fn to_uint(self, rounding: Rounding) -> Uint256 {
+        let whole = self.0 / Self::DECIMAL_FRACTIONAL;
+        let fractional = self.0 % Self::DECIMAL_FRACTIONAL;
+        let round_up = match rounding {
+            Rounding::Floor => false,
+            Rounding::Ceil => !fractional.is_zero(),
+            Rounding::HalfUp => fractional >= Self::DECIMAL_FRACTIONAL / Uint256::from(2u8),
+        };
+        if round_up {
+            whole + Uint256::from(1u8)
+        } else {
+            whole
+        }
+    }
+
+    /// Converts the decimal to a `Uint128`, rounding as specified. Returns an error
+    /// if the rounded value does not fit.
+    pub fn checked_to_uint128(
+        self,
+        rounding: Rounding,
+    ) -> Result<Uint128, ConversionOverflowError> {
+        self.to_uint(rounding)
+            .try_into()
+            .map_err(|_| ConversionOverflowError::new("Decimal256", "Uint128", self.to_string()))
+    }
+
+    /// Like `checked_to_uint128`, but returns `Uint128::MAX` if the rounded value does not fit.
+    pub fn saturating_to_uint128(self, rounding: Rounding) -> Uint128 {
+        self.checked_to_uint128(rounding).unwrap_or(Uint128::MAX)
+    }
+
     /// Returns the approximate square root as a Decimal256.
     ///
     /// This should not overflow or panic.
@@ -1270,6 +1316,54 @@ mod tests {
         dec /= Uint256::from(0u128);
     }
 
+    #[test]
+    fn decimal256_to_uint_works() {
+        let cases = [
+            // value, floor, ceil, half up
+            ("0", 0u128, 0u128, 0u128),
+            ("1.000000000000000001", 1, 2, 1),
+            ("1.499999999999999999", 1, 2, 1),
+            ("1.5", 1, 2, 2),
+            ("0.5", 0, 1, 1),
+            ("42", 42, 42, 42),
+        ];
+        for (value, floor, ceil, half_up) in cases {
+            let value = Decimal256::from_str(value).unwrap();
+            assert_eq!(value.to_uint(Rounding::Floor), Uint256::from(floor));
+            assert_eq!(value.to_uint(Rounding::Ceil), Uint256::from(ceil));
+            assert_eq!(value.to_uint(Rounding::HalfUp), Uint256::from(half_up));
+        }
+
+        // Does not overflow
+        assert_eq!(
+            Decimal256::MAX.to_uint(Rounding::Ceil),
+            Uint256::from_str("115792089237316195423570985008687907853269984665640564039458")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn decimal256_checked_to_uint128_works() {
+        let max = Decimal256::from_ratio(u128::MAX, 1u128);
+        assert_eq!(max.checked_to_uint128(Rounding::Ceil), Ok(Uint128::MAX));
+
+        let value = max + Decimal256::percent(50);
+        assert_eq!(value.checked_to_uint128(Rounding::Floor), Ok(Uint128::MAX));
+        assert_eq!(
+            value.checked_to_uint128(Rounding::HalfUp),
+            Err(ConversionOverflowError::new(
+                "Decimal256",
+                "Uint128",
+                "340282366920938463463374607431768211455.5"
+            ))
+        );
+        assert_eq!(value.saturating_to_uint128(Rounding::Ceil), Uint128::MAX);
+        assert_eq!(
+            Decimal256::percent(150).saturating_to_uint128(Rounding::HalfUp),
+            Uint128::new(2)
+        );
+    }
+
     #[test]
     fn decimal256_uint128_sqrt() {
         assert_eq!(Decimal256::percent(900).sqrt(), Decimal256::percent(300));
@@ -2,6 +2,7 @@ mod decimal;
 mod decimal256;
 mod fraction;
 mod isqrt;
+mod rounding;
 mod uint128;
 mod uint256;
 mod uint512;
@@ -11,6 +12,7 @@ pub use decimal::{Decimal, DecimalRangeExceeded};
 pub use decimal256::{Decimal256, Decimal256RangeExceeded};
 pub use fraction::Fraction;
 pub use isqrt::Isqrt;
+pub use rounding::Rounding;
 pub use uint128::Uint128;
 pub use uint256::Uint256;
 pub use uint512::Uint512;
@@ -0,0 +1,14 @@
+/// How to round when converting a decimal to an integer.
+///
+/// All modes are exact, i.e. no floats are involved. The result only differs between
+/// the modes if the decimal has a fractional part.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round towards zero, e.g. 1.9 becomes 1. This is what integer division does.
+    Floor,
+    /// Round away from zero, e.g. 1.1 becomes 2.
+    Ceil,
+    /// Round to the nearest integer. Halfway values (a fractional part of exactly .5)
+    /// are rounded up, e.g. 1.5 becomes 2 and 1.49 becomes 1.
+    HalfUp,
+}
@@ -215,6 +215,44 @@ impl Uint256 {
         Self(res)
     }
 
+    /// Returns the base 2 logarithm of the number, rounded down.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `self` is zero.
+    pub fn ilog2(self) -> u32 {
+        assert!(
+            !self.is_zero(),
+            "argument of integer logarithm must be positive"
+        );
+        255 - self.0.leading_zeros()
+    }
+
+    /// Returns the base 10 logarithm of the number, rounded down.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `self` is zero.
+    pub fn ilog10(self) -> u32 {
+        // 1233 / 4096 is slightly less than log10(2), so this is the result or
+        // one more than that
+        let log = ((self.ilog2() + 1) * 1233) >> 12;
+        if self < Self::from(10u32).pow(log) {
+            log - 1
+        } else {
+            log
+        }
+    }
+
+    /// Returns the smallest power of two greater than or equal to `self`.
+    /// Returns an error if it does not fit into a `Uint256`.
+    pub fn checked_next_power_of_two(self) -> Result<Self, OverflowError> {
+        if self <= Self::from(1u32) {
+            return Ok(Self::from(1u32));
+        }
+        Self::from(2u32).checked_pow((self - Self::from(1u32)).ilog2() + 1)
+    }
+
     pub fn checked_add(self, other: Self) -> Result<Self, OverflowError> {
         self.0
             .checked_add(other.0)
@@ -643,7 +681,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{from_slice, to_vec};
+    use crate::{from_slice, to_vec, Isqrt};
 
     #[test]
     fn uint256_construct() {
@@ -1487,4 +1525,115 @@ mod tests {
         a %= &b;
         assert_eq!(a, Uint256::from(1u32));
     }
+
+    /// All powers of two and ten, their neighbours and the maximum value in
+    /// increasing order
+    fn uint256_boundaries() -> Vec<Uint256> {
+        let one = Uint256::from(1u32);
+        let mut values = vec![Uint256::zero(), Uint256::MAX];
+        for base in [2u32, 10] {
+            let mut power = one;
+            loop {
+                values.extend([power - one, power, power + one]);
+                match power.checked_mul(Uint256::from(base)) {
+                    Ok(next) => power = next,
+                    Err(_) => break,
+                }
+            }
+        }
+        values.sort();
+        values.dedup();
+        values
+    }
+
+    #[test]
+    fn uint256_ilog2_works() {
+        let one = Uint256::from(1u32);
+        for exp in 0..256 {
+            let power = Uint256::from(2u32).pow(exp);
+            assert_eq!(power.ilog2(), exp);
+            // 2^(exp+1) - 1
+            assert_eq!((power + (power - one)).ilog2(), exp);
+            if exp > 0 {
+                assert_eq!((power - one).ilog2(), exp - 1);
+            }
+        }
+        assert_eq!(Uint256::MAX.ilog2(), 255);
+    }
+
+    #[test]
+    #[should_panic(expected = "argument of integer logarithm must be positive")]
+    fn uint256_ilog2_panics_for_zero() {
+        Uint256::zero().ilog2();
+    }
+
+    #[test]
+    fn uint256_ilog10_works() {
+        let one = Uint256::from(1u32);
+        let mut power = one;
+        let mut exp = 0;
+        loop {
+            assert_eq!(power.ilog10(), exp);
+            assert_eq!((power + one).ilog10(), exp);
+            if exp > 0 {
+                assert_eq!((power - one).ilog10(), exp - 1);
+            }
+            match power.checked_mul(Uint256::from(10u32)) {
+                Ok(next) => power = next,
+                Err(_) => break,
+            }
+            exp += 1;
+        }
+        assert_eq!(exp, 77);
+        assert_eq!(Uint256::MAX.ilog10(), 77);
+
+        // The result is one less than the number of decimal digits
+        for value in uint256_boundaries().into_iter().skip(1) {
+            assert_eq!(value.ilog10() as usize, value.to_string().len() - 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "argument of integer logarithm must be positive")]
+    fn uint256_ilog10_panics_for_zero() {
+        Uint256::zero().ilog10();
+    }
+
+    #[test]
+    fn uint256_checked_next_power_of_two_works() {
+        let one = Uint256::from(1u32);
+        assert_eq!(Uint256::zero().checked_next_power_of_two().unwrap(), one);
+        for exp in 0..256 {
+            let power = Uint256::from(2u32).pow(exp);
+            assert_eq!(power.checked_next_power_of_two().unwrap(), power);
+            if exp > 0 {
+                let half = Uint256::from(2u32).pow(exp - 1);
+                assert_eq!((half + one).checked_next_power_of_two().unwrap(), power);
+            }
+        }
+
+        let highest = Uint256::from(2u32).pow(255);
+        let err = (highest + one).checked_next_power_of_two().unwrap_err();
+        assert_eq!(err, OverflowError::new(OverflowOperation::Pow, 2, 256));
+        assert!(matches!(
+            Uint256::MAX.checked_next_power_of_two(),
+            Err(OverflowError { .. })
+        ));
+    }
+
+    #[test]
+    fn uint256_logarithms_and_roots_are_monotonic() {
+        let values = uint256_boundaries();
+        for pair in values.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert!(a.isqrt() <= b.isqrt());
+            if !a.is_zero() {
+                assert!(a.ilog2() <= b.ilog2());
+                assert!(a.ilog10() <= b.ilog10());
+            }
+            if let Ok(b_next) = b.checked_next_power_of_two() {
+                assert!(a.checked_next_power_of_two().unwrap() <= b_next);
+            }
+        }
+    }
 }
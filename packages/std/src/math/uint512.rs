@@ -314,6 +314,44 @@ impl Uint512 {
         Self(res)
     }
 
+    /// Returns the base 2 logarithm of the number, rounded down.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `self` is zero.
+    pub fn ilog2(self) -> u32 {
+        assert!(
+            !self.is_zero(),
+            "argument of integer logarithm must be positive"
+        );
+        511 - self.0.leading_zeros()
+    }
+
+    /// Returns the base 10 logarithm of the number, rounded down.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `self` is zero.
+    pub fn ilog10(self) -> u32 {
+        // 1233 / 4096 is slightly less than log10(2), so this is the result or
+        // one more than that
+        let log = ((self.ilog2() + 1) * 1233) >> 12;
+        if self < Self::from(10u32).pow(log) {
+            log - 1
+        } else {
+            log
+        }
+    }
+
+    /// Returns the smallest power of two greater than or equal to `self`.
+    /// Returns an error if it does not fit into a `Uint512`.
+    pub fn checked_next_power_of_two(self) -> Result<Self, OverflowError> {
+        if self <= Self::from(1u32) {
+            return Ok(Self::from(1u32));
+        }
+        Self::from(2u32).checked_pow((self - Self::from(1u32)).ilog2() + 1)
+    }
+
     pub fn checked_add(self, other: Self) -> Result<Self, OverflowError> {
         self.0
             .checked_add(other.0)
@@ -681,7 +719,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{from_slice, to_vec};
+    use crate::{from_slice, to_vec, Isqrt};
 
     #[test]
     fn uint512_construct() {
@@ -1222,4 +1260,115 @@ mod tests {
         a %= &b;
         assert_eq!(a, Uint512::from(1u32));
     }
+
+    /// All powers of two and ten, their neighbours and the maximum value in
+    /// increasing order
+    fn uint512_boundaries() -> Vec<Uint512> {
+        let one = Uint512::from(1u32);
+        let mut values = vec![Uint512::zero(), Uint512::MAX];
+        for base in [2u32, 10] {
+            let mut power = one;
+            loop {
+                values.extend([power - one, power, power + one]);
+                match power.checked_mul(Uint512::from(base)) {
+                    Ok(next) => power = next,
+                    Err(_) => break,
+                }
+            }
+        }
+        values.sort();
+        values.dedup();
+        values
+    }
+
+    #[test]
+    fn uint512_ilog2_works() {
+        let one = Uint512::from(1u32);
+        for exp in 0..512 {
+            let power = Uint512::from(2u32).pow(exp);
+            assert_eq!(power.ilog2(), exp);
+            // 2^(exp+1) - 1
+            assert_eq!((power + (power - one)).ilog2(), exp);
+            if exp > 0 {
+                assert_eq!((power - one).ilog2(), exp - 1);
+            }
+        }
+        assert_eq!(Uint512::MAX.ilog2(), 511);
+    }
+
+    #[test]
+    #[should_panic(expected = "argument of integer logarithm must be positive")]
+    fn uint512_ilog2_panics_for_zero() {
+        Uint512::zero().ilog2();
+    }
+
+    #[test]
+    fn uint512_ilog10_works() {
+        let one = Uint512::from(1u32);
+        let mut power = one;
+        let mut exp = 0;
+        loop {
+            assert_eq!(power.ilog10(), exp);
+            assert_eq!((power + one).ilog10(), exp);
+            if exp > 0 {
+                assert_eq!((power - one).ilog10(), exp - 1);
+            }
+            match power.checked_mul(Uint512::from(10u32)) {
+                Ok(next) => power = next,
+                Err(_) => break,
+            }
+            exp += 1;
+        }
+        assert_eq!(exp, 154);
+        assert_eq!(Uint512::MAX.ilog10(), 154);
+
+        // The result is one less than the number of decimal digits
+        for value in uint512_boundaries().into_iter().skip(1) {
+            assert_eq!(value.ilog10() as usize, value.to_string().len() - 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "argument of integer logarithm must be positive")]
+    fn uint512_ilog10_panics_for_zero() {
+        Uint512::zero().ilog10();
+    }
+
+    #[test]
+    fn uint512_checked_next_power_of_two_works() {
+        let one = Uint512::from(1u32);
+        assert_eq!(Uint512::zero().checked_next_power_of_two().unwrap(), one);
+        for exp in 0..512 {
+            let power = Uint512::from(2u32).pow(exp);
+            assert_eq!(power.checked_next_power_of_two().unwrap(), power);
+            if exp > 0 {
+                let half = Uint512::from(2u32).pow(exp - 1);
+                assert_eq!((half + one).checked_next_power_of_two().unwrap(), power);
+            }
+        }
+
+        let highest = Uint512::from(2u32).pow(511);
+        let err = (highest + one).checked_next_power_of_two().unwrap_err();
+        assert_eq!(err, OverflowError::new(OverflowOperation::Pow, 2, 512));
+        assert!(matches!(
+            Uint512::MAX.checked_next_power_of_two(),
+            Err(OverflowError { .. })
+        ));
+    }
+
+    #[test]
+    fn uint512_logarithms_and_roots_are_monotonic() {
+        let values = uint512_boundaries();
+        for pair in values.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert!(a.isqrt() <= b.isqrt());
+            if !a.is_zero() {
+                assert!(a.ilog2() <= b.ilog2());
+                assert!(a.ilog10() <= b.ilog10());
+            }
+            if let Ok(b_next) = b.checked_next_power_of_two() {
+                assert!(a.checked_next_power_of_two().unwrap() <= b_next);
+            }
+        }
+    }
 }
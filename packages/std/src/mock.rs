@@ -88,16 +88,27 @@ pub struct MockApi {
     /// Length of canonical addresses created with this API. Contracts should not make any assumtions
     /// what this value is.
     canonical_length: usize,
+    /// Returned by [`Api::gas_remaining`]
+    gas_remaining: u64,
 }
 
 impl Default for MockApi {
     fn default() -> Self {
         MockApi {
             canonical_length: CANONICAL_LENGTH,
+            gas_remaining: u64::MAX,
         }
     }
 }
 
+impl MockApi {
+    /// Sets the gas returned by [`Api::gas_remaining`], which is unlimited by default.
+    pub fn with_gas_remaining(mut self, gas_remaining: u64) -> Self {
+        self.gas_remaining = gas_remaining;
+        self
+    }
+}
+
 impl Api for MockApi {
     fn addr_validate(&self, human: &str) -> StdResult<Addr> {
         self.addr_canonicalize(human).map(|_canonical| ())?;
@@ -204,6 +215,10 @@ impl Api for MockApi {
     fn debug(&self, message: &str) {
         println!("{}", message);
     }
+
+    fn gas_remaining(&self) -> StdResult<u64> {
+        Ok(self.gas_remaining)
+    }
 }
 
 /// Returns a default enviroment with height, time, chain_id, and contract address
@@ -393,13 +408,11 @@ pub fn mock_ibc_packet_timeout(
 pub type MockQuerierCustomHandlerResult = SystemResult<ContractResult<Binary>>;
 
 /// MockQuerier holds an immutable table of bank balances
-/// TODO: also allow querying contracts
 pub struct MockQuerier<C: DeserializeOwned = Empty> {
     bank: BankQuerier,
     #[cfg(feature = "staking")]
     staking: StakingQuerier,
-    // placeholder to add support later
-    wasm: NoWasmQuerier,
+    wasm: WasmQuerier,
     /// A handler to handle custom queries. This is set to a dummy handler that
     /// always errors by default. Update it via `with_custom_handler`.
     ///
@@ -413,7 +426,7 @@ impl<C: DeserializeOwned> MockQuerier<C> {
             bank: BankQuerier::new(balances),
             #[cfg(feature = "staking")]
             staking: StakingQuerier::default(),
-            wasm: NoWasmQuerier {},
+            wasm: WasmQuerier::default(),
             // strange argument notation suggested as a workaround here: https://github.com/rust-lang/rust/issues/41078#issuecomment-294296365
             custom_handler: Box::from(|_: &_| -> MockQuerierCustomHandlerResult {
                 SystemResult::Err(SystemError::UnsupportedRequest {
@@ -442,6 +455,14 @@ impl<C: DeserializeOwned> MockQuerier<C> {
         self.staking = StakingQuerier::new(denom, validators, delegations);
     }
 
+    /// Sets a handler for queries to other contracts. By default, no contract exists.
+    pub fn update_wasm<WH>(&mut self, handler: WH)
+    where
+        WH: Fn(&WasmQuery) -> QuerierResult + 'static,
+    {
+        self.wasm.update_handler(handler)
+    }
+
     pub fn with_custom_handler<CH: 'static>(mut self, handler: CH) -> Self
     where
         CH: Fn(&C) -> MockQuerierCustomHandlerResult,
@@ -492,20 +513,39 @@ impl<C: CustomQuery + DeserializeOwned> MockQuerier<C> {
     }
 }
 
-#[derive(Clone, Default)]
-struct NoWasmQuerier {
-    // FIXME: actually provide a way to call out
+struct WasmQuerier {
+    /// A handler to handle Wasm queries. This is set to a dummy handler that
+    /// always returns "no such contract" by default. Update it via `update_handler`.
+    ///
+    /// Use box to avoid the need of generic type.
+    handler: Box<dyn for<'a> Fn(&'a WasmQuery) -> QuerierResult>,
 }
 
-impl NoWasmQuerier {
+impl WasmQuerier {
+    fn update_handler<WH>(&mut self, handler: WH)
+    where
+        WH: Fn(&WasmQuery) -> QuerierResult + 'static,
+    {
+        self.handler = Box::from(handler)
+    }
+
     fn query(&self, request: &WasmQuery) -> QuerierResult {
-        let addr = match request {
-            WasmQuery::Smart { contract_addr, .. } => contract_addr,
-            WasmQuery::Raw { contract_addr, .. } => contract_addr,
-            WasmQuery::ContractInfo { contract_addr, .. } => contract_addr,
-        }
-        .clone();
-        SystemResult::Err(SystemError::NoSuchContract { addr })
+        (*self.handler)(request)
+    }
+}
+
+impl Default for WasmQuerier {
+    fn default() -> Self {
+        let handler = Box::from(|request: &WasmQuery| -> QuerierResult {
+            let addr = match request {
+                WasmQuery::Smart { contract_addr, .. } => contract_addr,
+                WasmQuery::Raw { contract_addr, .. } => contract_addr,
+                WasmQuery::ContractInfo { contract_addr, .. } => contract_addr,
+            }
+            .clone();
+            SystemResult::Err(SystemError::NoSuchContract { addr })
+        });
+        WasmQuerier { handler }
     }
 }
 
@@ -778,6 +818,15 @@ mod tests {
         api.addr_humanize(&input).unwrap();
     }
 
+    #[test]
+    fn gas_remaining_works() {
+        let api = MockApi::default();
+        assert_eq!(api.gas_remaining().unwrap(), u64::MAX);
+
+        let api = MockApi::default().with_gas_remaining(1234);
+        assert_eq!(api.gas_remaining().unwrap(), 1234);
+    }
+
     // Basic "works" test. Exhaustive tests on VM's side (packages/vm/src/imports.rs)
     #[test]
     fn secp256k1_verify_works() {
@@ -1054,6 +1103,34 @@ mod tests {
         assert_eq!(res.amount, coin(0, "ELF"));
     }
 
+    #[test]
+    fn wasm_querier_works() {
+        let mut querier: MockQuerier = MockQuerier::new(&[]);
+        let request = QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: "contract".to_string(),
+            msg: b"{}".into(),
+        });
+
+        // no contracts by default
+        match querier.handle_query(&request) {
+            SystemResult::Err(SystemError::NoSuchContract { addr }) => {
+                assert_eq!(addr, "contract")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        querier.update_wasm(|request| match request {
+            WasmQuery::Smart { contract_addr, .. } if contract_addr == "contract" => {
+                SystemResult::Ok(ContractResult::Ok(b"\"answer\"".into()))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+        let res = querier.handle_query(&request).unwrap().unwrap();
+        assert_eq!(res, Binary::from(b"\"answer\""));
+    }
+
     #[cfg(feature = "staking")]
     #[test]
     fn staking_querier_all_validators() {
@@ -0,0 +1,169 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::binary::Binary;
+
+/// A page of items returned by a query, plus what a client needs to get the next page.
+///
+/// The common pattern is a query with an optional `start_after` key and a `limit`:
+/// the contract loads one item more than `limit` to find out if there is another page
+/// and returns the key to continue with in `next_key`. [`PageResponse::paginate`]
+/// implements this. Clients repeat the query with `start_after: next_key` until
+/// `next_key` is `None`.
+///
+/// ```
+/// # use cosmwasm_std::{Binary, PageResponse};
+/// let names = vec!["alice", "bob", "carol"];
+///
+/// // The contract loaded limit + 1 items
+/// let page = PageResponse::paginate(names, 2, |name| name.as_bytes().to_vec()).with_total(5);
+/// assert_eq!(page.items, ["alice", "bob"]);
+/// assert_eq!(page.next_key, Some(Binary::from(b"bob")));
+/// assert_eq!(page.total, Some(5));
+/// assert!(!page.is_last_page());
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    /// The key of the last item, to be used as the start of the next page.
+    /// `None` if this is the last page.
+    pub next_key: Option<Binary>,
+    /// The number of items in all pages, if the contract knows it
+    pub total: Option<u64>,
+}
+
+impl<T> PageResponse<T> {
+    /// Creates the last page, i.e. `items` without a next key or total.
+    pub fn new(items: Vec<T>) -> Self {
+        PageResponse {
+            items,
+            next_key: None,
+            total: None,
+        }
+    }
+
+    /// Creates a page of at most `limit` items. If there are more items, the rest is
+    /// dropped and `next_key` is the key of the last item in the page.
+    ///
+    /// Load `limit + 1` items to find out if there is a next page without an extra
+    /// storage read.
+    pub fn paginate(mut items: Vec<T>, limit: usize, key: impl Fn(&T) -> Vec<u8>) -> Self {
+        let next_key = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|item| Binary::from(key(item)))
+        } else {
+            None
+        };
+        PageResponse {
+            items,
+            next_key,
+            total: None,
+        }
+    }
+
+    pub fn with_next_key(mut self, next_key: impl Into<Binary>) -> Self {
+        self.next_key = Some(next_key.into());
+        self
+    }
+
+    pub fn with_total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    /// Returns true if there are no more pages after this one.
+    pub fn is_last_page(&self) -> bool {
+        self.next_key.is_none()
+    }
+
+    /// Converts all items, keeping the next key and total.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> PageResponse<U> {
+        PageResponse {
+            items: self.items.into_iter().map(f).collect(),
+            next_key: self.next_key,
+            total: self.total,
+        }
+    }
+}
+
+impl<T> Default for PageResponse<T> {
+    fn default() -> Self {
+        PageResponse::new(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::serde::{from_slice, to_vec};
+    use crate::{coin, Coin};
+
+    #[test]
+    fn paginate_works() {
+        let key = |n: &u32| n.to_be_bytes().to_vec();
+
+        // More items than the limit
+        let page = PageResponse::paginate(vec![1u32, 2, 3], 2, key);
+        assert_eq!(page.items, [1, 2]);
+        assert_eq!(page.next_key, Some(Binary::from(2u32.to_be_bytes())));
+        assert_eq!(page.total, None);
+
+        // Exactly the limit
+        let page = PageResponse::paginate(vec![1u32, 2], 2, key);
+        assert_eq!(page, PageResponse::new(vec![1, 2]));
+        assert!(page.is_last_page());
+
+        // Limit 0
+        let page = PageResponse::paginate(vec![1u32], 0, key);
+        assert_eq!(page, PageResponse::new(vec![]));
+
+        let page = PageResponse::<u32>::paginate(vec![], 10, key);
+        assert_eq!(page, PageResponse::default());
+    }
+
+    #[test]
+    fn builders_work() {
+        let page = PageResponse::new(vec!["a"])
+            .with_next_key(b"a".to_vec())
+            .with_total(3);
+        assert_eq!(page.next_key, Some(Binary::from(b"a")));
+        assert_eq!(page.total, Some(3));
+        assert!(!page.is_last_page());
+
+        let page = page.map(|s| s.len());
+        assert_eq!(page.items, [1]);
+        assert_eq!(page.next_key, Some(Binary::from(b"a")));
+        assert_eq!(page.total, Some(3));
+    }
+
+    #[test]
+    fn serialization_works() {
+        let page = PageResponse::new(vec![coin(3, "ucosm")])
+            .with_next_key(b"ucosm".to_vec())
+            .with_total(7);
+        let json = to_vec(&page).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&json),
+            r#"{"items":[{"denom":"ucosm","amount":"3"}],"next_key":"dWNvc20=","total":7}"#
+        );
+        assert_eq!(from_slice::<PageResponse<Coin>>(&json).unwrap(), page);
+
+        let json = to_vec(&PageResponse::<Coin>::default()).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&json),
+            r#"{"items":[],"next_key":null,"total":null}"#
+        );
+    }
+
+    #[test]
+    fn schema_works() {
+        let schema = schemars::schema_for!(PageResponse<Coin>);
+        let metadata = schema.schema.metadata.unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("PageResponse_for_Coin"));
+        let object = schema.schema.object.unwrap();
+        assert_eq!(object.required.into_iter().collect::<Vec<_>>(), ["items"]);
+        assert_eq!(object.properties.len(), 3);
+        assert!(schema.definitions.contains_key("Coin"));
+    }
+}
@@ -0,0 +1,166 @@
+use schemars::JsonSchema;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::binary::Binary;
+use crate::errors::StdResult;
+use crate::math::Uint128;
+use crate::results::{CosmosMsg, WasmMsg};
+use crate::serde::to_binary;
+
+/// A message a token contract sends to a recipient contract when tokens are sent to it
+/// along with a message for the recipient.
+///
+/// Implementors are wrapped in an execute message with a single variant named
+/// [`ReceiveHook::VARIANT`], i.e. `{"<variant>":{...}}`. This way the recipient can
+/// accept the hook as one variant of its own `ExecuteMsg` enum.
+pub trait ReceiveHook: Serialize {
+    /// The name of the execute message variant in snake case
+    const VARIANT: &'static str;
+
+    /// Serializes the wrapped execute message.
+    fn into_binary(self) -> StdResult<Binary>
+    where
+        Self: Sized,
+    {
+        to_binary(&Wrapped(&self))
+    }
+
+    /// Creates a message executing the hook on `contract_addr` without funds.
+    fn into_cosmos_msg<C>(self, contract_addr: impl Into<String>) -> StdResult<CosmosMsg<C>>
+    where
+        Self: Sized,
+    {
+        Ok(WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg: self.into_binary()?,
+            funds: vec![],
+        }
+        .into())
+    }
+}
+
+/// Serializes a hook as `{"<variant>":{...}}`. Maps are not supported by serde-json-wasm,
+/// so this is a struct with a single field named after the variant.
+struct Wrapped<'a, H>(&'a H);
+
+impl<H: ReceiveHook> Serialize for Wrapped<'_, H> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut msg = serializer.serialize_struct("ReceiveHookMsg", 1)?;
+        msg.serialize_field(H::VARIANT, self.0)?;
+        msg.end()
+    }
+}
+
+/// The hook for fungible tokens, sent as `{"receive":{...}}`.
+///
+/// This is wire compatible with `Cw20ReceiveMsg` of the cw20 spec, so contracts can
+/// accept cw20 tokens without depending on the cw20 package:
+///
+/// ```
+/// # use cosmwasm_std::{from_slice, Binary, ReceiveHook, TokenReceiveMsg, Uint128};
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// #[serde(rename_all = "snake_case")]
+/// enum ExecuteMsg {
+///     Receive(TokenReceiveMsg),
+/// }
+///
+/// let hook = TokenReceiveMsg {
+///     sender: "alice".to_string(),
+///     amount: Uint128::new(100),
+///     msg: Binary::from(b"{}"),
+/// };
+/// let ExecuteMsg::Receive(received) = from_slice(&hook.clone().into_binary().unwrap()).unwrap();
+/// assert_eq!(received, hook);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenReceiveMsg {
+    /// The account that sent the tokens to the recipient
+    pub sender: String,
+    pub amount: Uint128,
+    /// The message for the recipient
+    pub msg: Binary,
+}
+
+impl ReceiveHook for TokenReceiveMsg {
+    const VARIANT: &'static str = "receive";
+}
+
+/// The hook for non-fungible tokens, sent as `{"receive_nft":{...}}`.
+///
+/// This is wire compatible with `Cw721ReceiveMsg` of the cw721 spec.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NftReceiveMsg {
+    /// The account that sent the token to the recipient
+    pub sender: String,
+    pub token_id: String,
+    /// The message for the recipient
+    pub msg: Binary,
+}
+
+impl ReceiveHook for NftReceiveMsg {
+    const VARIANT: &'static str = "receive_nft";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::results::Empty;
+    use crate::serde::from_slice;
+
+    #[test]
+    fn token_receive_msg_serializes_like_cw20() {
+        let hook = TokenReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::new(123),
+            msg: Binary::from(b"{}"),
+        };
+        let binary = hook.into_binary().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&binary),
+            r#"{"receive":{"sender":"alice","amount":"123","msg":"e30="}}"#
+        );
+    }
+
+    #[test]
+    fn nft_receive_msg_serializes_like_cw721() {
+        let hook = NftReceiveMsg {
+            sender: "alice".to_string(),
+            token_id: "punk".to_string(),
+            msg: Binary::from(b"{}"),
+        };
+        let binary = hook.clone().into_binary().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&binary),
+            r#"{"receive_nft":{"sender":"alice","token_id":"punk","msg":"e30="}}"#
+        );
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename_all = "snake_case")]
+        enum ExecuteMsg {
+            ReceiveNft(NftReceiveMsg),
+        }
+        let parsed: ExecuteMsg = from_slice(&binary).unwrap();
+        assert_eq!(parsed, ExecuteMsg::ReceiveNft(hook));
+    }
+
+    #[test]
+    fn into_cosmos_msg_works() {
+        let hook = TokenReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::new(5),
+            msg: Binary::from(b"{}"),
+        };
+        let msg: CosmosMsg<Empty> = hook.clone().into_cosmos_msg("recipient").unwrap();
+        assert_eq!(
+            msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "recipient".to_string(),
+                msg: hook.into_binary().unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::addresses::Addr;
+use crate::coins::Coin;
+use crate::errors::StdResult;
+use crate::query::CustomQuery;
+use crate::results::WasmMsg;
+use crate::serde::{from_binary, to_binary};
+use crate::traits::QuerierWrapper;
+
+/// The messages another contract accepts, for calling it through a [`Remote`].
+///
+/// This is usually implemented by an empty marker type next to the message types
+/// of the contract, so that callers only need to depend on its API.
+pub trait ContractInterface {
+    type ExecuteMsg: Serialize;
+    type QueryMsg: Serialize;
+}
+
+/// The address of another contract with the interface `T` it implements.
+///
+/// Serializes as the plain address, so it can be stored and used in messages in
+/// place of an [`Addr`].
+///
+/// ```
+/// # use cosmwasm_std::{Addr, ContractInterface, CosmosMsg, Remote, StdResult};
+/// # use serde::Serialize;
+/// #[derive(Serialize)]
+/// #[serde(rename_all = "snake_case")]
+/// enum CounterExecuteMsg {
+///     Increment { by: u32 },
+/// }
+///
+/// #[derive(Serialize)]
+/// #[serde(rename_all = "snake_case")]
+/// enum CounterQueryMsg {
+///     Count {},
+/// }
+///
+/// struct Counter;
+///
+/// impl ContractInterface for Counter {
+///     type ExecuteMsg = CounterExecuteMsg;
+///     type QueryMsg = CounterQueryMsg;
+/// }
+///
+/// # fn main() -> StdResult<()> {
+/// let counter = Remote::<Counter>::new(Addr::unchecked("counter"));
+/// let msg: CosmosMsg = counter.call(&CounterExecuteMsg::Increment { by: 2 })?.into();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(transparent, bound = "")]
+pub struct Remote<T> {
+    address: Addr,
+    #[serde(skip)]
+    interface: PhantomData<fn() -> T>,
+}
+
+impl<T> Remote<T> {
+    pub fn new(address: Addr) -> Self {
+        Remote {
+            address,
+            interface: PhantomData,
+        }
+    }
+
+    pub fn address(&self) -> &Addr {
+        &self.address
+    }
+
+    pub fn into_address(self) -> Addr {
+        self.address
+    }
+}
+
+impl<T: ContractInterface> Remote<T> {
+    /// Creates a message executing `msg` on the contract without funds.
+    pub fn call(&self, msg: &T::ExecuteMsg) -> StdResult<WasmMsg> {
+        self.call_with_funds(msg, vec![])
+    }
+
+    /// Creates a message executing `msg` on the contract and sending `funds` along.
+    pub fn call_with_funds(&self, msg: &T::ExecuteMsg, funds: Vec<Coin>) -> StdResult<WasmMsg> {
+        Ok(WasmMsg::Execute {
+            contract_addr: self.address.to_string(),
+            msg: to_binary(msg)?,
+            funds,
+        })
+    }
+
+    /// Sends the smart query `msg` to the contract and parses the response as `U`.
+    pub fn query<U, C>(&self, querier: &QuerierWrapper<C>, msg: &T::QueryMsg) -> StdResult<U>
+    where
+        U: DeserializeOwned,
+        C: CustomQuery,
+    {
+        querier.query_wasm_smart(&self.address, msg)
+    }
+
+    /// Parses the execute message of `msg` if it executes this contract, e.g. to check the
+    /// messages of a `Response` in tests. Returns `None` for all other messages.
+    pub fn parse_call(&self, msg: &WasmMsg) -> StdResult<Option<T::ExecuteMsg>>
+    where
+        T::ExecuteMsg: DeserializeOwned,
+    {
+        match msg {
+            WasmMsg::Execute {
+                contract_addr, msg, ..
+            } if *contract_addr == self.address => from_binary::<T::ExecuteMsg>(msg).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Implemented by hand since the derives would require `T` to implement the traits as well
+
+impl<T> Clone for Remote<T> {
+    fn clone(&self) -> Self {
+        Remote::new(self.address.clone())
+    }
+}
+
+impl<T> PartialEq for Remote<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
+
+impl<T> Eq for Remote<T> {}
+
+impl<T> fmt::Debug for Remote<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Remote").field(&self.address).finish()
+    }
+}
+
+impl<T> JsonSchema for Remote<T> {
+    fn schema_name() -> String {
+        Addr::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        Addr::json_schema(gen)
+    }
+}
+
+impl<T> From<Remote<T>> for Addr {
+    fn from(remote: Remote<T>) -> Self {
+        remote.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock::MockQuerier;
+    use crate::{
+        coins, from_slice, to_vec, Binary, ContractResult, Empty, SystemResult, WasmQuery,
+    };
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum ExecuteMsg {
+        Increment { by: u32 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum QueryMsg {
+        Count {},
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct CountResponse {
+        count: u32,
+    }
+
+    struct Counter;
+
+    impl ContractInterface for Counter {
+        type ExecuteMsg = ExecuteMsg;
+        type QueryMsg = QueryMsg;
+    }
+
+    #[test]
+    fn call_works() {
+        let counter = Remote::<Counter>::new(Addr::unchecked("counter"));
+
+        let msg = counter.call(&ExecuteMsg::Increment { by: 2 }).unwrap();
+        assert_eq!(
+            msg,
+            WasmMsg::Execute {
+                contract_addr: "counter".to_string(),
+                msg: Binary::from(br#"{"increment":{"by":2}}"#),
+                funds: vec![],
+            }
+        );
+
+        let msg = counter
+            .call_with_funds(&ExecuteMsg::Increment { by: 3 }, coins(12, "ucosm"))
+            .unwrap();
+        match msg {
+            WasmMsg::Execute { funds, .. } => assert_eq!(funds, coins(12, "ucosm")),
+            _ => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn parse_call_works() {
+        let counter = Remote::<Counter>::new(Addr::unchecked("counter"));
+        let msg = counter.call(&ExecuteMsg::Increment { by: 7 }).unwrap();
+        assert_eq!(
+            counter.parse_call(&msg).unwrap(),
+            Some(ExecuteMsg::Increment { by: 7 })
+        );
+
+        // Other contracts and messages are ignored
+        let other = Remote::<Counter>::new(Addr::unchecked("other"));
+        assert_eq!(other.parse_call(&msg).unwrap(), None);
+        let msg = WasmMsg::ClearAdmin {
+            contract_addr: "counter".to_string(),
+        };
+        assert_eq!(counter.parse_call(&msg).unwrap(), None);
+
+        // Messages the interface does not know fail
+        let msg = WasmMsg::Execute {
+            contract_addr: "counter".to_string(),
+            msg: Binary::from(br#"{"reset":{}}"#),
+            funds: vec![],
+        };
+        counter.parse_call(&msg).unwrap_err();
+    }
+
+    #[test]
+    fn query_works() {
+        let mut querier = MockQuerier::<Empty>::new(&[]);
+        querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } => {
+                assert_eq!(contract_addr, "counter");
+                assert_eq!(from_binary::<QueryMsg>(msg).unwrap(), QueryMsg::Count {});
+                let response = to_binary(&CountResponse { count: 42 }).unwrap();
+                SystemResult::Ok(ContractResult::Ok(response))
+            }
+            _ => panic!("Unexpected query: {:?}", query),
+        });
+        let wrapper = QuerierWrapper::<Empty>::new(&querier);
+
+        let counter = Remote::<Counter>::new(Addr::unchecked("counter"));
+        let response: CountResponse = counter.query(&wrapper, &QueryMsg::Count {}).unwrap();
+        assert_eq!(response, CountResponse { count: 42 });
+    }
+
+    #[test]
+    fn remote_serializes_as_address() {
+        let counter = Remote::<Counter>::new(Addr::unchecked("counter"));
+        assert_eq!(to_vec(&counter).unwrap(), br#""counter""#);
+        let parsed: Remote<Counter> = from_slice(br#""counter""#).unwrap();
+        assert_eq!(parsed, counter);
+        assert_eq!(format!("{:?}", parsed), r#"Remote(Addr("counter"))"#);
+        assert_eq!(Addr::from(parsed.clone()), Addr::unchecked("counter"));
+        assert_eq!(parsed.into_address(), Addr::unchecked("counter"));
+    }
+}
@@ -3,8 +3,8 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 
 use crate::binary::Binary;
-use crate::coins::Coin;
-use crate::errors::StdResult;
+use crate::coins::{normalize_coins, Coin};
+use crate::errors::{StdError, StdResult};
 #[cfg(feature = "stargate")]
 use crate::ibc::IbcMsg;
 use crate::serde::to_binary;
@@ -63,6 +63,34 @@ pub enum BankMsg {
     /// There is no Cosmos SDK message that performs this, but it can be done by calling the bank keeper.
     /// Important if a contract controls significant token supply that must be retired.
     Burn { amount: Vec<Coin> },
+    /// Sends native tokens from the contract to several addresses in one message.
+    ///
+    /// This is translated to a [MsgMultiSend](https://github.com/cosmos/cosmos-sdk/blob/v0.40.0/proto/cosmos/bank/v1beta1/tx.proto#L33-L39)
+    /// with the contract as the only input, which pays the sum of all outputs.
+    /// Use [`MultiSend`](crate::MultiSend) to build it.
+    #[cfg(feature = "multisend")]
+    MultiSend { outputs: Vec<BankOutput> },
+}
+
+/// The recipient of a [`BankMsg::MultiSend`] and the coins it receives
+#[cfg(feature = "multisend")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BankOutput {
+    pub address: String,
+    pub coins: Vec<Coin>,
+}
+
+impl BankMsg {
+    /// A [`BankMsg::Burn`] the bank module accepts: coins of the same denom are merged,
+    /// zero amounts are removed and the rest is sorted by denom. Fails if nothing is
+    /// left to burn.
+    pub fn burn(amount: Vec<Coin>) -> StdResult<Self> {
+        let amount = normalize_coins(amount);
+        if amount.is_empty() {
+            return Err(StdError::generic_err("Cannot burn zero coins"));
+        }
+        Ok(BankMsg::Burn { amount })
+    }
 }
 
 /// The message types of the staking module.
@@ -259,7 +287,7 @@ impl<T> From<GovMsg> for CosmosMsg<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::coins;
+    use crate::{coin, coins};
 
     #[test]
     fn from_bank_msg_works() {
@@ -272,4 +300,24 @@ mod tests {
             _ => panic!("must encode in Bank variant"),
         }
     }
+
+    #[test]
+    fn bank_msg_burn_works() {
+        let amount = vec![
+            coin(5, "uosmo"),
+            coin(0, "uatom"),
+            coin(3, "earth"),
+            coin(2, "uosmo"),
+        ];
+        assert_eq!(
+            BankMsg::burn(amount).unwrap(),
+            BankMsg::Burn {
+                amount: vec![coin(3, "earth"), coin(7, "uosmo")]
+            }
+        );
+
+        let err = BankMsg::burn(vec![coin(0, "earth")]).unwrap_err();
+        assert!(matches!(err, StdError::GenericErr { .. }));
+        BankMsg::burn(vec![]).unwrap_err();
+    }
 }
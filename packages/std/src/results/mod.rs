@@ -4,12 +4,15 @@ mod contract_result;
 mod cosmos_msg;
 mod empty;
 mod events;
+mod multisend;
 mod query;
 mod response;
 mod submessages;
 mod system_result;
 
 pub use contract_result::ContractResult;
+#[cfg(feature = "multisend")]
+pub use cosmos_msg::BankOutput;
 pub use cosmos_msg::{wasm_execute, wasm_instantiate, BankMsg, CosmosMsg, CustomMsg, WasmMsg};
 #[cfg(feature = "staking")]
 pub use cosmos_msg::{DistributionMsg, StakingMsg};
@@ -17,6 +20,7 @@ pub use cosmos_msg::{DistributionMsg, StakingMsg};
 pub use cosmos_msg::{GovMsg, VoteOption};
 pub use empty::Empty;
 pub use events::{attr, Attribute, Event};
+pub use multisend::MultiSend;
 pub use query::QueryResponse;
 pub use response::Response;
 pub use submessages::{Reply, ReplyOn, SubMsg, SubMsgExecutionResponse, SubMsgResult};
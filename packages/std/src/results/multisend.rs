@@ -0,0 +1,186 @@
+use crate::coins::{normalize_coins, Coin};
+
+#[cfg(feature = "multisend")]
+use super::BankOutput;
+use super::{BankMsg, CosmosMsg};
+
+/// Collects payouts to many recipients and turns them into bank messages.
+///
+/// With the `multisend` feature, all payouts become a single [`BankMsg::MultiSend`].
+/// Without it, e.g. on chains lacking the `multisend` capability, they are split into
+/// one [`BankMsg::Send`] per recipient. Either way, the contract pays the same coins.
+///
+/// Payouts to the same recipient are merged. The coins of every recipient are
+/// normalized like in [`BankMsg::burn`], and recipients receiving nothing are left out.
+///
+/// ```
+/// # use cosmwasm_std::{coins, MultiSend, Response};
+/// let payouts = MultiSend::new()
+///     .add("alice", coins(100, "ucosm"))
+///     .add("bob", coins(20, "ucosm"))
+///     .add("alice", coins(5, "ucosm"));
+/// assert_eq!(payouts.total(), coins(125, "ucosm"));
+///
+/// let response: Response = Response::new().add_messages(payouts.into_msgs());
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MultiSend {
+    /// The recipients in the order they were first added with their coins
+    outputs: Vec<(String, Vec<Coin>)>,
+}
+
+impl MultiSend {
+    pub fn new() -> Self {
+        MultiSend::default()
+    }
+
+    /// Adds a payout of `amount` to `to_address`.
+    ///
+    /// Panics if the total amount of a denom sent to one recipient overflows.
+    pub fn add(mut self, to_address: impl Into<String>, amount: Vec<Coin>) -> Self {
+        let to_address = to_address.into();
+        match self
+            .outputs
+            .iter_mut()
+            .find(|(address, _)| *address == to_address)
+        {
+            Some((_, coins)) => {
+                let merged = normalize_coins(coins.drain(..).chain(amount));
+                *coins = merged;
+            }
+            None => self.outputs.push((to_address, normalize_coins(amount))),
+        }
+        self
+    }
+
+    /// The number of recipients receiving coins
+    pub fn len(&self) -> usize {
+        self.outputs
+            .iter()
+            .filter(|(_, coins)| !coins.is_empty())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The coins paid to all recipients together, sorted by denom
+    pub fn total(&self) -> Vec<Coin> {
+        normalize_coins(self.outputs.iter().flat_map(|(_, coins)| coins.clone()))
+    }
+
+    /// One [`BankMsg::Send`] per recipient, in the order they were first added
+    pub fn into_sends(self) -> Vec<BankMsg> {
+        self.outputs
+            .into_iter()
+            .filter(|(_, coins)| !coins.is_empty())
+            .map(|(to_address, amount)| BankMsg::Send { to_address, amount })
+            .collect()
+    }
+
+    /// A single [`BankMsg::MultiSend`] paying all recipients, or `None` if there is
+    /// nothing to pay.
+    #[cfg(feature = "multisend")]
+    pub fn into_multi_send(self) -> Option<BankMsg> {
+        let outputs: Vec<BankOutput> = self
+            .outputs
+            .into_iter()
+            .filter(|(_, coins)| !coins.is_empty())
+            .map(|(address, coins)| BankOutput { address, coins })
+            .collect();
+        if outputs.is_empty() {
+            None
+        } else {
+            Some(BankMsg::MultiSend { outputs })
+        }
+    }
+
+    /// The messages paying all recipients: a single [`BankMsg::MultiSend`] with the
+    /// `multisend` feature and one [`BankMsg::Send`] per recipient otherwise. Empty if
+    /// there is nothing to pay.
+    pub fn into_msgs<T>(self) -> Vec<CosmosMsg<T>> {
+        #[cfg(feature = "multisend")]
+        let msgs: Vec<BankMsg> = self.into_multi_send().into_iter().collect();
+        #[cfg(not(feature = "multisend"))]
+        let msgs = self.into_sends();
+        msgs.into_iter().map(CosmosMsg::Bank).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{coin, coins, Empty};
+
+    fn payouts() -> MultiSend {
+        MultiSend::new()
+            .add("alice", vec![coin(100, "ucosm"), coin(1, "earth")])
+            .add("bob", coins(20, "ucosm"))
+            .add("carol", coins(0, "ucosm"))
+            .add("alice", coins(5, "ucosm"))
+    }
+
+    #[test]
+    fn add_merges_recipients() {
+        let payouts = payouts();
+        assert_eq!(payouts.len(), 2);
+        assert!(!payouts.is_empty());
+        assert_eq!(payouts.total(), vec![coin(1, "earth"), coin(125, "ucosm")]);
+        assert!(MultiSend::new().add("carol", vec![]).is_empty());
+    }
+
+    #[test]
+    fn into_sends_works() {
+        assert_eq!(
+            payouts().into_sends(),
+            vec![
+                BankMsg::Send {
+                    to_address: "alice".to_string(),
+                    amount: vec![coin(1, "earth"), coin(105, "ucosm")],
+                },
+                BankMsg::Send {
+                    to_address: "bob".to_string(),
+                    amount: coins(20, "ucosm"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "multisend"))]
+    fn into_msgs_splits_into_sends() {
+        let msgs: Vec<CosmosMsg<Empty>> = payouts().into_msgs();
+        let expected: Vec<CosmosMsg<Empty>> =
+            payouts().into_sends().into_iter().map(Into::into).collect();
+        assert_eq!(msgs, expected);
+        assert!(MultiSend::new().into_msgs::<Empty>().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "multisend")]
+    fn into_msgs_uses_multi_send() {
+        let msgs: Vec<CosmosMsg<Empty>> = payouts().into_msgs();
+        assert_eq!(
+            msgs,
+            vec![CosmosMsg::Bank(BankMsg::MultiSend {
+                outputs: vec![
+                    BankOutput {
+                        address: "alice".to_string(),
+                        coins: vec![coin(1, "earth"), coin(105, "ucosm")],
+                    },
+                    BankOutput {
+                        address: "bob".to_string(),
+                        coins: coins(20, "ucosm"),
+                    },
+                ],
+            })]
+        );
+        assert_eq!(
+            crate::to_vec(&msgs[0]).unwrap(),
+            br#"{"bank":{"multi_send":{"outputs":[{"address":"alice","coins":[{"denom":"earth","amount":"1"},{"denom":"ucosm","amount":"105"}]},{"address":"bob","coins":[{"denom":"ucosm","amount":"20"}]}]}}}"#
+        );
+        assert!(MultiSend::new().into_msgs::<Empty>().is_empty());
+    }
+}
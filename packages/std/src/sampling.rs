@@ -0,0 +1,110 @@
+use crate::errors::{StdError, StdResult};
+use crate::math::{Uint128, Uint256};
+
+/// Selects one of `items` with a probability proportional to its weight.
+///
+/// The selection is fully determined by `seed`, which should be 32 bytes of
+/// uniformly distributed data that no party can choose, e.g. a SHA-256 hash of
+/// a randomness beacon output. Equal seeds and items give equal results on
+/// every machine, since only integer arithmetic is used:
+///
+/// 1. `total` is the sum of all weights.
+/// 2. The seed is read as a big-endian 256 bit integer `r` and reduced to
+///    `r % total`.
+/// 3. The result is the first item whose cumulative weight (the sum of its own
+///    weight and all weights before it) is greater than `r % total`.
+///
+/// Every item is thus selected for `weight` of the `total` possible values. The
+/// modulo bias of step 2 is at most `total / 2^256`, which is below 2^-128 as
+/// long as `total` fits into a `Uint128`. Items with a weight of zero are never
+/// selected. Note that the result depends on the order of `items`.
+///
+/// Returns an error if `items` is empty or all weights are zero.
+///
+/// ```
+/// # use cosmwasm_std::{select_weighted, Uint128};
+/// let validators = [
+///     ("alice", Uint128::new(100)),
+///     ("bob", Uint128::new(300)),
+///     ("carol", Uint128::zero()),
+/// ];
+/// // 0x0190 % 400 = 0 selects alice, values from 100 to 399 select bob
+/// let mut seed = [0u8; 32];
+/// seed[30..].copy_from_slice(&[0x01, 0x90]);
+/// assert_eq!(*select_weighted(seed, &validators).unwrap(), "alice");
+/// seed[31] = 0xf4; // 500 % 400 = 100
+/// assert_eq!(*select_weighted(seed, &validators).unwrap(), "bob");
+/// ```
+pub fn select_weighted<T>(seed: [u8; 32], items: &[(T, Uint128)]) -> StdResult<&T> {
+    let total = items.iter().fold(Uint256::zero(), |sum, (_, weight)| {
+        sum + Uint256::from(*weight)
+    });
+    if total.is_zero() {
+        return Err(StdError::generic_err(
+            "Cannot select from items without weight",
+        ));
+    }
+
+    let target = Uint256::from_be_bytes(seed) % total;
+    let mut cumulative = Uint256::zero();
+    for (item, weight) in items {
+        cumulative += Uint256::from(*weight);
+        if cumulative > target {
+            return Ok(item);
+        }
+    }
+    unreachable!("target is less than the total weight")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(value: u128) -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        seed[16..].copy_from_slice(&value.to_be_bytes());
+        seed
+    }
+
+    #[test]
+    fn select_weighted_works() {
+        let items = [
+            ('a', Uint128::new(2)),
+            ('b', Uint128::zero()),
+            ('c', Uint128::new(3)),
+        ];
+        let selected: String = (0..10)
+            .map(|value| *select_weighted(seed(value), &items).unwrap())
+            .collect();
+        assert_eq!(selected, "aacccaaccc");
+
+        // The whole seed is used
+        let mut high = seed(0);
+        high[0] = 1; // 2^248 % 5 = 1
+        assert_eq!(*select_weighted(high, &items).unwrap(), 'a');
+        high[31] = 2;
+        assert_eq!(*select_weighted(high, &items).unwrap(), 'c');
+    }
+
+    #[test]
+    fn select_weighted_handles_large_weights() {
+        let items = [("first", Uint128::MAX), ("second", Uint128::MAX)];
+        let max = u128::MAX - 1;
+        assert_eq!(*select_weighted(seed(max), &items).unwrap(), "first");
+        assert_eq!(*select_weighted(seed(u128::MAX), &items).unwrap(), "second");
+        assert_eq!(*select_weighted([0xff; 32], &items).unwrap(), "second");
+    }
+
+    #[test]
+    fn select_weighted_errors_without_weight() {
+        let items: [(u8, Uint128); 0] = [];
+        match select_weighted(seed(0), &items).unwrap_err() {
+            StdError::GenericErr { msg, .. } => {
+                assert_eq!(msg, "Cannot select from items without weight")
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        let items = [(1, Uint128::zero()), (2, Uint128::zero())];
+        select_weighted(seed(7), &items).unwrap_err();
+    }
+}
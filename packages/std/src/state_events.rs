@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+
+use crate::binary::Binary;
+#[cfg(feature = "iterator")]
+use crate::iterator::{Order, Record};
+use crate::results::Event;
+use crate::traits::Storage;
+
+/// Declares which storage keys are reported in an event when they are changed and
+/// under which attribute key.
+///
+/// [`StateEvents::mutate`] runs a state mutation and creates the event from the keys
+/// that were actually changed, so events and state cannot get out of sync.
+///
+/// ```
+/// # use cosmwasm_std::{Event, StateEvents, Storage};
+/// # use cosmwasm_std::testing::MockStorage;
+/// let mut storage = MockStorage::new();
+/// storage.set(b"owner", b"alice");
+///
+/// let events = StateEvents::new("config_changed")
+///     .key(b"owner", "owner")
+///     .key(b"paused", "paused");
+/// let ((), event) = events
+///     .mutate(&mut storage, |storage| {
+///         storage.set(b"owner", b"bob");
+///         storage.set(b"paused", b"false"); // not previously set
+///         storage.set(b"unmapped", b"not reported");
+///         Ok::<(), cosmwasm_std::StdError>(())
+///     })
+///     .unwrap();
+/// assert_eq!(
+///     event,
+///     Some(
+///         Event::new("config_changed")
+///             .add_attribute("owner", "bob")
+///             .add_attribute("paused", "false")
+///     )
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateEvents {
+    event_type: String,
+    /// Storage key and attribute key, in the order the attributes are emitted
+    keys: Vec<(Vec<u8>, String)>,
+}
+
+impl StateEvents {
+    pub fn new(event_type: impl Into<String>) -> Self {
+        StateEvents {
+            event_type: event_type.into(),
+            keys: vec![],
+        }
+    }
+
+    /// Reports changes of the value at `storage_key` as an attribute with key `attribute`.
+    pub fn key(mut self, storage_key: impl AsRef<[u8]>, attribute: impl Into<String>) -> Self {
+        self.keys
+            .push((storage_key.as_ref().to_vec(), attribute.into()));
+        self
+    }
+
+    /// Runs `mutation` on `storage` and returns its result along with an event
+    /// describing the declared keys whose value changed.
+    ///
+    /// Every changed key becomes an attribute with the new value. Values are used as
+    /// they are if they are valid UTF-8 (e.g. JSON) and base64 encoded otherwise.
+    /// Removed keys get an empty value. No event is created if none of the declared
+    /// keys changed, since events without attributes are not allowed.
+    ///
+    /// Writes are passed through to `storage` immediately. If `mutation` fails, its
+    /// writes are not rolled back, which is fine when the error is returned from the
+    /// entry point since the whole transaction is reverted in that case.
+    pub fn mutate<T, E>(
+        &self,
+        storage: &mut dyn Storage,
+        mutation: impl FnOnce(&mut dyn Storage) -> Result<T, E>,
+    ) -> Result<(T, Option<Event>), E> {
+        let mut recording = RecordingStorage {
+            inner: storage,
+            original: BTreeMap::new(),
+        };
+        let result = mutation(&mut recording)?;
+        let event = self.event_for(&recording);
+        Ok((result, event))
+    }
+
+    fn event_for(&self, recording: &RecordingStorage) -> Option<Event> {
+        let attributes: Vec<(String, String)> = self
+            .keys
+            .iter()
+            .filter_map(|(key, attribute)| {
+                let original = recording.original.get(key)?;
+                let current = recording.inner.get(key);
+                if *original == current {
+                    return None;
+                }
+                let value = match current {
+                    Some(value) => String::from_utf8(value)
+                        .unwrap_or_else(|err| Binary::from(err.into_bytes()).to_base64()),
+                    None => String::new(),
+                };
+                Some((attribute.clone(), value))
+            })
+            .collect();
+
+        if attributes.is_empty() {
+            None
+        } else {
+            Some(Event::new(self.event_type.clone()).add_attributes(attributes))
+        }
+    }
+}
+
+/// Passes all operations through to `inner` and remembers the value every written
+/// key had before it was first written.
+struct RecordingStorage<'a> {
+    inner: &'a mut dyn Storage,
+    original: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl RecordingStorage<'_> {
+    fn record(&mut self, key: &[u8]) {
+        if !self.original.contains_key(key) {
+            let value = self.inner.get(key);
+            self.original.insert(key.to_vec(), value);
+        }
+    }
+}
+
+impl Storage for RecordingStorage<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key)
+    }
+
+    #[cfg(feature = "iterator")]
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        self.inner.range(start, end, order)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.record(key);
+        self.inner.set(key, value);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.record(key);
+        self.inner.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::errors::{StdError, StdResult};
+    use crate::storage::MemoryStorage;
+
+    fn events() -> StateEvents {
+        StateEvents::new("state")
+            .key(b"count", "count")
+            .key(b"owner", "owner")
+    }
+
+    #[test]
+    fn mutate_reports_changed_keys_in_declared_order() {
+        let mut storage = MemoryStorage::new();
+        storage.set(b"count", b"1");
+
+        let (result, event) = events()
+            .mutate(&mut storage, |storage| -> StdResult<u32> {
+                storage.set(b"owner", b"\"bob\"");
+                storage.set(b"count", b"2");
+                storage.set(b"count", b"3");
+                Ok(42)
+            })
+            .unwrap();
+        assert_eq!(result, 42);
+        assert_eq!(
+            event,
+            Some(
+                Event::new("state")
+                    .add_attribute("count", "3")
+                    .add_attribute("owner", "\"bob\"")
+            )
+        );
+        assert_eq!(storage.get(b"count"), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn mutate_ignores_unchanged_values() {
+        let mut storage = MemoryStorage::new();
+        storage.set(b"count", b"1");
+
+        let ((), event) = events()
+            .mutate(&mut storage, |storage| -> StdResult<()> {
+                storage.set(b"count", b"2");
+                storage.set(b"count", b"1");
+                storage.set(b"other", b"1");
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(event, None);
+        assert_eq!(storage.get(b"other"), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn mutate_reports_removals_and_binary_values() {
+        let mut storage = MemoryStorage::new();
+        storage.set(b"count", b"1");
+
+        let ((), event) = events()
+            .mutate(&mut storage, |storage| -> StdResult<()> {
+                storage.remove(b"count");
+                storage.set(b"owner", &[0xff, 0x00]);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            event,
+            Some(
+                Event::new("state")
+                    .add_attribute("count", "")
+                    .add_attribute("owner", "/wA=")
+            )
+        );
+    }
+
+    #[test]
+    fn mutate_passes_errors_through() {
+        let mut storage = MemoryStorage::new();
+        let err = events()
+            .mutate(&mut storage, |storage| -> StdResult<()> {
+                storage.set(b"count", b"1");
+                Err(StdError::generic_err("nope"))
+            })
+            .unwrap_err();
+        assert_eq!(err, StdError::generic_err("nope"));
+    }
+}
@@ -1,7 +1,10 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::fmt;
+use std::time::Duration;
 
+use crate::errors::ConversionOverflowError;
 use crate::math::Uint64;
 
 /// A point in time in nanosecond precision.
@@ -74,6 +77,85 @@ impl Timestamp {
     pub fn subsec_nanos(&self) -> u64 {
         self.0.u64() % 1_000_000_000
     }
+
+    /// Creates a timestamp from the `seconds` and `nanos` of a protobuf
+    /// `google.protobuf.Timestamp`, e.g. from a Stargate query response.
+    ///
+    /// Fails if `nanos` is not in `0..1_000_000_000` as required by protobuf, or if the
+    /// time is not in the range of this type, e.g. before 1970.
+    ///
+    /// ```
+    /// # use cosmwasm_std::Timestamp;
+    /// let ts = Timestamp::from_proto(1_600_000_000, 202).unwrap();
+    /// assert_eq!(ts.nanos(), 1_600_000_000_000_000_202);
+    /// assert_eq!(ts.to_proto(), (1_600_000_000, 202));
+    /// ```
+    pub fn from_proto(seconds: i64, nanos: i32) -> Result<Self, ConversionOverflowError> {
+        proto_nanos(seconds, nanos)
+            .map(Timestamp::from_nanos)
+            .ok_or_else(|| proto_err("proto Timestamp", "Timestamp", seconds, nanos))
+    }
+
+    /// Returns the `seconds` and `nanos` of a protobuf `google.protobuf.Timestamp`
+    pub fn to_proto(&self) -> (i64, i32) {
+        // Both fit, since the seconds are at most u64::MAX / 10^9
+        (self.seconds() as i64, self.subsec_nanos() as i32)
+    }
+}
+
+/// Creates a [`Duration`] from the `seconds` and `nanos` of a protobuf
+/// `google.protobuf.Duration`, e.g. an unbonding time from a Stargate query response.
+///
+/// Fails for negative durations, which `Duration` cannot represent, and if `nanos` is
+/// not in `0..1_000_000_000`.
+///
+/// ```
+/// # use cosmwasm_std::{duration_from_proto, duration_to_proto};
+/// # use std::time::Duration;
+/// let unbonding_time = duration_from_proto(1_814_400, 0).unwrap();
+/// assert_eq!(unbonding_time, Duration::from_secs(21 * 24 * 3600));
+/// assert_eq!(duration_to_proto(unbonding_time).unwrap(), (1_814_400, 0));
+/// ```
+pub fn duration_from_proto(seconds: i64, nanos: i32) -> Result<Duration, ConversionOverflowError> {
+    match (u64::try_from(seconds), u32::try_from(nanos)) {
+        (Ok(seconds), Ok(nanos)) if nanos < 1_000_000_000 => Ok(Duration::new(seconds, nanos)),
+        _ => Err(proto_err("proto Duration", "Duration", seconds, nanos)),
+    }
+}
+
+/// Returns the `seconds` and `nanos` of a protobuf `google.protobuf.Duration`. Fails if
+/// the seconds exceed `i64::MAX`.
+pub fn duration_to_proto(duration: Duration) -> Result<(i64, i32), ConversionOverflowError> {
+    let seconds = i64::try_from(duration.as_secs()).map_err(|_| {
+        ConversionOverflowError::new("Duration", "proto Duration", format!("{:?}", duration))
+    })?;
+    // Always less than 10^9
+    Ok((seconds, duration.subsec_nanos() as i32))
+}
+
+/// The nanoseconds since epoch of a valid protobuf `google.protobuf.Timestamp` in the
+/// range of [`Timestamp`]
+fn proto_nanos(seconds: i64, nanos: i32) -> Option<u64> {
+    if !(0..1_000_000_000).contains(&nanos) {
+        return None;
+    }
+    u64::try_from(seconds)
+        .ok()?
+        .checked_mul(1_000_000_000)?
+        .checked_add(nanos as u64)
+}
+
+fn proto_err(
+    source_type: &'static str,
+    target_type: &'static str,
+    seconds: i64,
+    nanos: i32,
+) -> ConversionOverflowError {
+    ConversionOverflowError::new(
+        source_type,
+        target_type,
+        format!("seconds: {}, nanos: {}", seconds, nanos),
+    )
 }
 
 impl fmt::Display for Timestamp {
@@ -178,6 +260,80 @@ mod tests {
         assert_eq!(sum.subsec_nanos(), 8765436);
     }
 
+    #[test]
+    fn timestamp_from_proto_works() {
+        assert_eq!(
+            Timestamp::from_proto(0, 0).unwrap(),
+            Timestamp::from_nanos(0)
+        );
+        assert_eq!(
+            Timestamp::from_proto(1_600_000_000, 999_999_999).unwrap(),
+            Timestamp::from_nanos(1_600_000_000_999_999_999)
+        );
+        // The latest representable time
+        let max = Timestamp::from_nanos(u64::MAX);
+        let (seconds, nanos) = max.to_proto();
+        assert_eq!((seconds, nanos), (18_446_744_073, 709_551_615));
+        assert_eq!(Timestamp::from_proto(seconds, nanos).unwrap(), max);
+
+        // Invalid nanos
+        let err = Timestamp::from_proto(1, 1_000_000_000).unwrap_err();
+        assert_eq!(
+            err,
+            ConversionOverflowError::new(
+                "proto Timestamp",
+                "Timestamp",
+                "seconds: 1, nanos: 1000000000"
+            )
+        );
+        Timestamp::from_proto(1, -1).unwrap_err();
+        // Before 1970
+        Timestamp::from_proto(-1, 0).unwrap_err();
+        // After 2554
+        Timestamp::from_proto(seconds, nanos + 1).unwrap_err();
+        Timestamp::from_proto(seconds + 1, 0).unwrap_err();
+        Timestamp::from_proto(i64::MAX, 0).unwrap_err();
+    }
+
+    #[test]
+    fn timestamp_to_proto_works() {
+        assert_eq!(Timestamp::from_nanos(0).to_proto(), (0, 0));
+        assert_eq!(Timestamp::from_nanos(1_000_000_202).to_proto(), (1, 202));
+        assert_eq!(
+            Timestamp::from_nanos(999_999_999).to_proto(),
+            (0, 999_999_999)
+        );
+    }
+
+    #[test]
+    fn duration_from_proto_works() {
+        assert_eq!(duration_from_proto(0, 0).unwrap(), Duration::ZERO);
+        assert_eq!(
+            duration_from_proto(i64::MAX, 999_999_999).unwrap(),
+            Duration::new(i64::MAX as u64, 999_999_999)
+        );
+        let err = duration_from_proto(-1, 0).unwrap_err();
+        assert_eq!(
+            err,
+            ConversionOverflowError::new("proto Duration", "Duration", "seconds: -1, nanos: 0")
+        );
+        // Negative durations have negative nanos
+        duration_from_proto(0, -5).unwrap_err();
+        duration_from_proto(0, 1_000_000_000).unwrap_err();
+    }
+
+    #[test]
+    fn duration_to_proto_works() {
+        assert_eq!(duration_to_proto(Duration::ZERO).unwrap(), (0, 0));
+        assert_eq!(
+            duration_to_proto(Duration::from_nanos(1_000_000_202)).unwrap(),
+            (1, 202)
+        );
+        let max = Duration::new(i64::MAX as u64, 999_999_999);
+        assert_eq!(duration_to_proto(max).unwrap(), (i64::MAX, 999_999_999));
+        duration_to_proto(max + Duration::from_nanos(1)).unwrap_err();
+    }
+
     #[test]
     fn timestamp_implements_display() {
         let embedded = format!("Time: {}", Timestamp::from_nanos(0));
@@ -5,7 +5,7 @@ use std::ops::Deref;
 use crate::addresses::{Addr, CanonicalAddr};
 use crate::binary::Binary;
 use crate::coins::Coin;
-use crate::errors::{RecoverPubkeyError, StdError, StdResult, VerificationError};
+use crate::errors::{RecoverPubkeyError, StdError, StdResult, StructuredError, VerificationError};
 #[cfg(feature = "iterator")]
 use crate::iterator::{Order, Record};
 use crate::query::{
@@ -122,6 +122,18 @@ pub trait Api {
     /// Emits a debugging message that is handled depending on the environment (typically printed to console or ignored).
     /// Those messages are not persisted to chain.
     fn debug(&self, message: &str);
+
+    /// Returns the gas left for the current call, e.g. to process as many items of a
+    /// batch as fit instead of a hardcoded number. The gas is in CosmWasm gas units,
+    /// which the host converts to its own gas with a multiplier.
+    ///
+    /// This needs the `gas_remaining` feature, which requires the host to support the
+    /// `gas_remaining` capability. Without it, an error is returned.
+    fn gas_remaining(&self) -> StdResult<u64> {
+        Err(StdError::generic_err(
+            "Querying the remaining gas requires the gas_remaining feature",
+        ))
+    }
 }
 
 /// A short-hand alias for the two-level query result (1. accessing the contract, 2. executing query in the contract)
@@ -171,6 +183,9 @@ impl<'a, C: CustomQuery> QuerierWrapper<'a, C> {
     /// Any error (System Error, Error or called contract, or Parse Error) are flattened into
     /// one level. Only use this if you don't need to check the SystemError
     /// eg. If you don't differentiate between contract missing and contract returned error
+    ///
+    /// A [`StructuredError`] returned by the called contract becomes a
+    /// [`StdError::StructuredErr`], such that it can be inspected or passed on unchanged.
     pub fn query<U: DeserializeOwned>(&self, request: &QueryRequest<C>) -> StdResult<U> {
         let raw = to_vec(request).map_err(|serialize_err| {
             StdError::generic_err(format!("Serializing QueryRequest: {}", serialize_err))
@@ -180,9 +195,9 @@ impl<'a, C: CustomQuery> QuerierWrapper<'a, C> {
                 "Querier system error: {}",
                 system_err
             ))),
-            SystemResult::Ok(ContractResult::Err(contract_err)) => Err(StdError::generic_err(
-                format!("Querier contract error: {}", contract_err),
-            )),
+            SystemResult::Ok(ContractResult::Err(contract_err)) => {
+                Err(contract_query_error(contract_err))
+            }
             SystemResult::Ok(ContractResult::Ok(value)) => from_binary(&value),
         }
     }
@@ -252,9 +267,9 @@ impl<'a, C: CustomQuery> QuerierWrapper<'a, C> {
                 "Querier system error: {}",
                 system_err
             ))),
-            SystemResult::Ok(ContractResult::Err(contract_err)) => Err(StdError::generic_err(
-                format!("Querier contract error: {}", contract_err),
-            )),
+            SystemResult::Ok(ContractResult::Err(contract_err)) => {
+                Err(contract_query_error(contract_err))
+            }
             SystemResult::Ok(ContractResult::Ok(value)) => {
                 if value.is_empty() {
                     Ok(None)
@@ -318,6 +333,15 @@ impl<'a, C: CustomQuery> QuerierWrapper<'a, C> {
     }
 }
 
+/// Converts the error returned by a queried contract, keeping its structure if it is
+/// a [`StructuredError`].
+fn contract_query_error(contract_err: String) -> StdError {
+    match StructuredError::parse(&contract_err) {
+        Some(error) => StdError::structured_err(error),
+        None => StdError::generic_err(format!("Querier contract error: {}", contract_err)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -361,4 +385,39 @@ mod tests {
         let balance: BalanceResponse = from_slice(&raw).unwrap();
         assert_eq!(balance.amount.amount, Uint128::new(5));
     }
+
+    #[test]
+    fn query_wasm_smart_keeps_structured_errors() {
+        let mut querier: MockQuerier<Empty> = MockQuerier::new(&[]);
+        querier.update_wasm(|request| {
+            let error = match request {
+                WasmQuery::Smart { .. } => {
+                    StructuredError::new(4, "Insufficient funds").with_data(b"100ucosm".to_vec())
+                }
+                _ => return SystemResult::Ok(ContractResult::Err("Unknown query".to_string())),
+            };
+            SystemResult::Ok(ContractResult::Err(error.to_string()))
+        });
+        let wrapper = QuerierWrapper::<Empty>::new(&querier);
+
+        let err = wrapper
+            .query_wasm_smart::<Empty>("contract", &Empty {})
+            .unwrap_err();
+        match err {
+            StdError::StructuredErr { error, .. } => assert_eq!(
+                error,
+                StructuredError::new(4, "Insufficient funds").with_data(b"100ucosm".to_vec())
+            ),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        // plain errors are unchanged
+        let err = wrapper.query_wasm_raw("contract", b"key").unwrap_err();
+        match err {
+            StdError::GenericErr { msg, .. } => {
+                assert_eq!(msg, "Querier contract error: Unknown query")
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
 }
@@ -21,6 +21,8 @@ const DEFAULT_GAS_LIMIT: u64 = 1_000_000_000_000; // ~1ms
 const DEFAULT_INSTANCE_OPTIONS: InstanceOptions = InstanceOptions {
     gas_limit: DEFAULT_GAS_LIMIT,
     print_debug: false,
+    instrumentation: None,
+    wasm_costs: None,
 };
 const HIGH_GAS_LIMIT: u64 = 20_000_000_000_000_000; // ~20s, allows many calls on one instance
 
@@ -126,6 +128,9 @@ fn bench_cache(c: &mut Criterion) {
         supported_features: features_from_csv("iterator,staking"),
         memory_cache_size: MEMORY_CACHE_SIZE,
         instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+        memory_cache_idle_ttl: None,
+        middlewares: vec![],
+        wasm_costs: None,
     };
 
     group.bench_function("save wasm", |b| {
@@ -166,6 +171,9 @@ fn bench_cache(c: &mut Criterion) {
             supported_features: features_from_csv("iterator,staking"),
             memory_cache_size: Size(0),
             instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+            memory_cache_idle_ttl: None,
+            middlewares: vec![],
+            wasm_costs: None,
         };
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
             unsafe { Cache::new(non_memcache).unwrap() };
@@ -232,6 +240,9 @@ pub fn bench_instance_threads(c: &mut Criterion) {
             supported_features: features_from_csv("iterator,staking"),
             memory_cache_size: MEMORY_CACHE_SIZE,
             instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+            memory_cache_idle_ttl: None,
+            middlewares: vec![],
+            wasm_costs: None,
         };
 
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
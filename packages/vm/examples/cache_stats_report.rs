@@ -0,0 +1,37 @@
+use clap::{App, Arg};
+
+use cosmwasm_vm::testing::{MockApi, MockQuerier, MockStorage};
+use cosmwasm_vm::{features_from_csv, Cache, CacheOptions, Size};
+
+pub fn main() {
+    let matches = App::new("Cache stats report")
+        .version("0.1.0")
+        .long_about(
+            "Prints the cumulative statistics of the cache in the given base directory as JSON.",
+        )
+        .arg(
+            Arg::with_name("BASE_DIR")
+                .help("Base directory of the cache")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    let base_dir = matches
+        .value_of("BASE_DIR")
+        .expect("Error parsing base directory");
+    let options = CacheOptions {
+        base_dir: base_dir.into(),
+        supported_features: features_from_csv(""),
+        memory_cache_size: Size::mebi(0),
+        instance_memory_limit: Size::mebi(0),
+        memory_cache_idle_ttl: None,
+        middlewares: vec![],
+        wasm_costs: None,
+    };
+
+    // The cache is only used to read the stats, so the module artifacts are never loaded.
+    let cache: Cache<MockApi, MockStorage, MockQuerier> = unsafe { Cache::new(options).unwrap() };
+    let report = cache.stats_report();
+    println!("{}", serde_json::to_string_pretty(&report.total).unwrap());
+}
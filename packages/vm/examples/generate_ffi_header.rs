@@ -0,0 +1,7 @@
+//! Prints the C header for the types in `cosmwasm_vm::ffi`.
+//!
+//! Usage: `cargo run --example generate_ffi_header > ffi/cosmwasm_vm_ffi.h`
+
+fn main() {
+    print!("{}", cosmwasm_vm::ffi::c_header());
+}
@@ -0,0 +1,66 @@
+/* Generated by cosmwasm_vm::ffi::c_header(). Do not edit. */
+
+#pragma once
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define FFI_LAYOUT_VERSION 3
+
+enum FfiErrorCode {
+  FfiErrorCode_Backend = 1,
+  FfiErrorCode_Cache = 2,
+  FfiErrorCode_Communication = 3,
+  FfiErrorCode_Compile = 4,
+  FfiErrorCode_Conversion = 5,
+  FfiErrorCode_Crypto = 6,
+  FfiErrorCode_GasDepletion = 7,
+  FfiErrorCode_Generic = 8,
+  FfiErrorCode_Instantiation = 9,
+  FfiErrorCode_Integrity = 10,
+  FfiErrorCode_Parse = 11,
+  FfiErrorCode_DeserializationLimitExceeded = 12,
+  FfiErrorCode_Serialize = 13,
+  FfiErrorCode_Resolve = 14,
+  FfiErrorCode_ResultMismatch = 15,
+  FfiErrorCode_Runtime = 16,
+  FfiErrorCode_StaticValidation = 17,
+  FfiErrorCode_UninitializedContextData = 18,
+  FfiErrorCode_WriteAccessDenied = 19,
+};
+typedef uint32_t FfiErrorCode;
+
+typedef struct UnmanagedVector {
+  bool is_none;
+  uint8_t *ptr;
+  size_t len;
+  size_t cap;
+} UnmanagedVector;
+
+typedef struct FfiGasReport {
+  uint32_t layout_version;
+  uint32_t peak_memory_pages;
+  uint64_t limit;
+  uint64_t remaining;
+  uint64_t used_externally;
+  uint64_t used_internally;
+  uint64_t memory_surcharge;
+  uint64_t wasm_gas;
+  uint64_t storage_gas;
+  uint64_t query_gas;
+  uint64_t crypto_gas;
+  uint64_t address_gas;
+} FfiGasReport;
+
+typedef struct FfiVmError {
+  uint32_t layout_version;
+  uint32_t code;
+  UnmanagedVector message;
+} FfiVmError;
+
+typedef struct FfiAnalysisReport {
+  uint32_t layout_version;
+  bool has_ibc_entry_points;
+  UnmanagedVector required_features;
+} FfiAnalysisReport;
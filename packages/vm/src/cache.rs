@@ -1,9 +1,16 @@
 use std::collections::HashSet;
-use std::fs::{create_dir_all, File, OpenOptions};
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
 use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasmer::ModuleMiddleware;
 
 use crate::backend::{Backend, BackendApi, Querier, Storage};
 use crate::checksum::Checksum;
@@ -11,10 +18,10 @@ use crate::compatibility::check_wasm;
 use crate::errors::{VmError, VmResult};
 use crate::features::required_features_from_module;
 use crate::instance::{Instance, InstanceOptions};
-use crate::modules::{FileSystemCache, InMemoryCache, PinnedMemoryCache};
+use crate::modules::{prune_dir, FileSystemCache, InMemoryCache, PinnedMemoryCache};
 use crate::size::Size;
 use crate::static_analysis::{deserialize_wasm, has_ibc_entry_points};
-use crate::wasm_backend::{compile, make_runtime_store};
+use crate::wasm_backend::{compile, compile_with_costs, make_runtime_store, CostTable};
 
 const STATE_DIR: &str = "state";
 // Things related to the state of the blockchain.
@@ -23,13 +30,21 @@ const WASM_DIR: &str = "wasm";
 const CACHE_DIR: &str = "cache";
 // Cacheable things.
 const MODULES_DIR: &str = "modules";
+/// Cumulative statistics of all caches using the same base directory
+const STATS_FILE: &str = "stats.json";
 
+/// Counters of this cache instance. The number and size of the resident modules are
+/// part of the [`Metrics`].
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Stats {
     pub hits_pinned_memory_cache: u32,
     pub hits_memory_cache: u32,
     pub hits_fs_cache: u32,
     pub misses: u32,
+    /// Modules removed from the memory cache, either to stay within its size limit,
+    /// evicting the least recently used ones first, or because they were idle for
+    /// [`CacheOptions::memory_cache_idle_ttl`]
+    pub evictions_memory_cache: u32,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -41,14 +56,122 @@ pub struct Metrics {
     pub size_memory_cache: usize,
 }
 
+/// Metrics of a single module in the pinned memory cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerModuleMetrics {
+    /// Number of loads from the pinned memory cache since the module was pinned
+    pub hits: u32,
+    /// The estimated size of the module in memory, in bytes
+    pub size: usize,
+}
+
+/// Metrics of all modules in the pinned memory cache. See [`Cache::pinned_metrics`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedMetrics {
+    /// Metrics of every pinned module, sorted by checksum
+    pub per_module: Vec<(Checksum, PerModuleMetrics)>,
+}
+
+/// Cache statistics that are accumulated over time. See [`Cache::stats_report`].
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct CumulativeStats {
+    /// Number of Wasm compilations, including the ones in `save_wasm`
+    pub compiles: u64,
+    /// Total time spent compiling Wasm, in nanoseconds
+    pub compile_time_nanos: u64,
+    pub hits_pinned_memory_cache: u64,
+    pub hits_memory_cache: u64,
+    pub hits_fs_cache: u64,
+    pub misses: u64,
+    /// Not recorded by older versions, which count as 0
+    #[serde(default)]
+    pub evictions_memory_cache: u64,
+}
+
+impl CumulativeStats {
+    fn plus(self, other: CumulativeStats) -> Self {
+        CumulativeStats {
+            compiles: self.compiles + other.compiles,
+            compile_time_nanos: self.compile_time_nanos + other.compile_time_nanos,
+            hits_pinned_memory_cache: self.hits_pinned_memory_cache
+                + other.hits_pinned_memory_cache,
+            hits_memory_cache: self.hits_memory_cache + other.hits_memory_cache,
+            hits_fs_cache: self.hits_fs_cache + other.hits_fs_cache,
+            misses: self.misses + other.misses,
+            evictions_memory_cache: self.evictions_memory_cache + other.evictions_memory_cache,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct StatsReport {
+    /// Statistics since this cache instance was created
+    pub session: CumulativeStats,
+    /// Statistics of all cache instances that used the same base directory, including this one
+    pub total: CumulativeStats,
+    pub elements_pinned_memory_cache: usize,
+    pub elements_memory_cache: usize,
+    pub size_pinned_memory_cache: usize,
+    pub size_memory_cache: usize,
+}
+
 #[derive(Clone, Debug)]
 pub struct CacheOptions {
     pub base_dir: PathBuf,
+    /// The capabilities of the chain, e.g. created with [`features_from_csv`](crate::features_from_csv)
+    /// or taken from a bundled [`CapabilityProfile`](crate::CapabilityProfile).
     pub supported_features: HashSet<String>,
     pub memory_cache_size: Size,
     /// Memory limit for instances, in bytes. Use a value that is divisible by the Wasm page size 65536,
     /// e.g. full MiBs.
     pub instance_memory_limit: Size,
+    /// Modules in the memory cache that were not used for this long are removed by a
+    /// background thread, in addition to the size based eviction. `None` disables this.
+    pub memory_cache_idle_ttl: Option<Duration>,
+    /// Extra middlewares for compiling contracts, e.g. custom translation or optimization
+    /// passes of the embedder. They are pushed before the gatekeeper and metering
+    /// middlewares, so that the code they emit is checked and metered like contract code.
+    ///
+    /// Compiled modules are stored on disk in a separate directory for every list of
+    /// [`CacheMiddleware::id`]s and `wasm_costs`.
+    pub middlewares: Vec<CacheMiddleware>,
+    /// The gas costs of Wasm operators, e.g. loaded with [`CostTable::from_json`] at
+    /// node startup. `None` for the default costs.
+    pub wasm_costs: Option<CostTable>,
+}
+
+/// A middleware for [`CacheOptions::middlewares`]
+#[derive(Clone, Debug)]
+pub struct CacheMiddleware {
+    /// Identifies the code the middleware emits. Modules stored on disk are only loaded
+    /// by caches with the same ids, so change it whenever the emitted code changes.
+    pub id: String,
+    pub middleware: Arc<dyn ModuleMiddleware>,
+}
+
+impl CacheMiddleware {
+    pub fn new(id: impl Into<String>, middleware: Arc<dyn ModuleMiddleware>) -> Self {
+        CacheMiddleware {
+            id: id.into(),
+            middleware,
+        }
+    }
+}
+
+/// Identifies modules compiled with `middlewares` and `costs` on disk, or `None` for
+/// modules compiled without middlewares and with the default costs.
+fn compilation_variant(middlewares: &[CacheMiddleware], costs: &CostTable) -> Option<String> {
+    if middlewares.is_empty() && *costs == CostTable::default() {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    for middleware in middlewares {
+        hasher.update((middleware.id.len() as u64).to_be_bytes());
+        hasher.update(&middleware.id);
+    }
+    // The costs are serialized with sorted operators, so equal tables give equal hashes
+    hasher.update(costs.to_json().unwrap_or_default());
+    Some(hex::encode(&hasher.finalize()[..8]))
 }
 
 pub struct CacheInner {
@@ -60,13 +183,99 @@ pub struct CacheInner {
     memory_cache: InMemoryCache,
     fs_cache: FileSystemCache,
     stats: Stats,
+    compiles: u64,
+    compile_time: Duration,
+    stats_path: PathBuf,
+    /// Statistics of previous cache instances, loaded from `stats_path`
+    previous_stats: CumulativeStats,
+}
+
+impl CacheInner {
+    /// Removes the modules that were not used for `ttl` from the memory cache
+    fn evict_idle_modules(&mut self, ttl: Duration) {
+        let evicted = self.memory_cache.evict_idle(ttl);
+        self.stats.evictions_memory_cache += evicted as u32;
+    }
+
+    fn record_compile(&mut self, duration: Duration) {
+        self.compiles += 1;
+        self.compile_time += duration;
+    }
+
+    fn session_stats(&self) -> CumulativeStats {
+        CumulativeStats {
+            compiles: self.compiles,
+            compile_time_nanos: self.compile_time.as_nanos() as u64,
+            hits_pinned_memory_cache: self.stats.hits_pinned_memory_cache.into(),
+            hits_memory_cache: self.stats.hits_memory_cache.into(),
+            hits_fs_cache: self.stats.hits_fs_cache.into(),
+            misses: self.stats.misses.into(),
+            evictions_memory_cache: self.stats.evictions_memory_cache.into(),
+        }
+    }
+
+    fn total_stats(&self) -> CumulativeStats {
+        self.previous_stats.plus(self.session_stats())
+    }
+
+    fn persist_stats(&self) -> VmResult<()> {
+        let data = serde_json::to_vec(&self.total_stats())
+            .map_err(|e| VmError::cache_err(format!("Error serializing stats: {}", e)))?;
+        File::create(&self.stats_path)
+            .and_then(|mut file| file.write_all(&data))
+            .map_err(|e| {
+                VmError::cache_err(format!(
+                    "Error writing stats file {}: {}",
+                    self.stats_path.display(),
+                    e
+                ))
+            })
+    }
+}
+
+/// The background thread evicting idle modules from the memory cache
+struct IdleEviction {
+    /// Dropping this stops the thread
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl IdleEviction {
+    /// Evicts the modules that were not used for `ttl` every `interval`
+    fn start(inner: Arc<Mutex<CacheInner>>, ttl: Duration, interval: Duration) -> VmResult<Self> {
+        let (stop, stopped) = channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("cosmwasm-cache-eviction".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match inner.lock() {
+                        Ok(mut cache) => cache.evict_idle_modules(ttl),
+                        Err(_) => return,
+                    }
+                }
+            })
+            .map_err(|e| VmError::cache_err(format!("Error starting eviction thread: {}", e)))?;
+        Ok(IdleEviction { stop, thread })
+    }
+
+    fn stop(self) {
+        drop(self.stop);
+        // A panic in the thread is not worth propagating while dropping the cache
+        let _ = self.thread.join();
+    }
 }
 
 pub struct Cache<A: BackendApi, S: Storage, Q: Querier> {
     /// Supported features are immutable for the lifetime of the cache,
     /// i.e. any number of read-only references is allowed to access it concurrently.
     supported_features: HashSet<String>,
-    inner: Mutex<CacheInner>,
+    /// Pushed before the default middlewares when compiling
+    middlewares: Vec<Arc<dyn ModuleMiddleware>>,
+    wasm_costs: CostTable,
+    /// Shared with the eviction thread
+    inner: Arc<Mutex<CacheInner>>,
+    /// Only running if `memory_cache_idle_ttl` is set
+    idle_eviction: Option<IdleEviction>,
     // Those two don't store data but only fix type information
     type_api: PhantomData<A>,
     type_storage: PhantomData<S>,
@@ -75,6 +284,46 @@ pub struct Cache<A: BackendApi, S: Storage, Q: Querier> {
     instantiation_lock: Mutex<()>,
 }
 
+/// What [`Cache::migrate_artifacts`] did with one stored Wasm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationOutcome {
+    /// The Wasm was compiled into a new artifact
+    Compiled,
+    /// An artifact for the current version already existed and could be loaded
+    Revalidated,
+    /// The Wasm could not be migrated, see [`MigrationReport::failures`]
+    Failed,
+}
+
+/// Reported by [`Cache::migrate_artifacts`] after each stored Wasm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationProgress<'a> {
+    /// The number of Wasm files processed so far, including this one
+    pub done: usize,
+    /// The number of Wasm files to process
+    pub total: usize,
+    pub path: &'a Path,
+    pub outcome: MigrationOutcome,
+}
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub compiled: usize,
+    pub revalidated: usize,
+    /// The Wasm files that could not be migrated, e.g. because their content does not
+    /// match the checksum in their file name
+    pub failures: Vec<(PathBuf, VmError)>,
+}
+
+/// The files removed by [`Cache::prune`], or the ones that would be removed by
+/// [`Cache::prune_dry_run`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    pub files: Vec<PathBuf>,
+    /// The total size of `files`, i.e. the disk space that is (or can be) reclaimed
+    pub bytes: u64,
+}
+
 #[derive(PartialEq, Debug)]
 pub struct AnalysisReport {
     pub has_ibc_entry_points: bool,
@@ -100,6 +349,9 @@ where
             supported_features,
             memory_cache_size,
             instance_memory_limit,
+            memory_cache_idle_ttl,
+            middlewares,
+            wasm_costs,
         } = options;
 
         let state_path = base_dir.join(STATE_DIR);
@@ -118,18 +370,44 @@ where
             })?;
         }
 
-        let fs_cache = FileSystemCache::new(cache_path.join(MODULES_DIR))
+        let wasm_costs = wasm_costs.unwrap_or_default();
+        let mut fs_cache = FileSystemCache::new(cache_path.join(MODULES_DIR))
             .map_err(|e| VmError::cache_err(format!("Error file system cache: {}", e)))?;
+        if let Some(variant) = compilation_variant(&middlewares, &wasm_costs) {
+            fs_cache = fs_cache.with_variant(variant);
+        }
+        let stats_path = cache_path.join(STATS_FILE);
+        let previous_stats = load_stats_from_disk(&stats_path);
+        let inner = Arc::new(Mutex::new(CacheInner {
+            wasm_path,
+            instance_memory_limit,
+            pinned_memory_cache: PinnedMemoryCache::new(),
+            memory_cache: InMemoryCache::new(memory_cache_size),
+            fs_cache,
+            stats: Stats::default(),
+            compiles: 0,
+            compile_time: Duration::default(),
+            stats_path,
+            previous_stats,
+        }));
+        let idle_eviction = match memory_cache_idle_ttl {
+            // Modules are evicted at most a quarter of the TTL late
+            Some(ttl) => Some(IdleEviction::start(
+                inner.clone(),
+                ttl,
+                (ttl / 4).max(Duration::from_millis(1)),
+            )?),
+            None => None,
+        };
         Ok(Cache {
             supported_features,
-            inner: Mutex::new(CacheInner {
-                wasm_path,
-                instance_memory_limit,
-                pinned_memory_cache: PinnedMemoryCache::new(),
-                memory_cache: InMemoryCache::new(memory_cache_size),
-                fs_cache,
-                stats: Stats::default(),
-            }),
+            middlewares: middlewares
+                .into_iter()
+                .map(|middleware| middleware.middleware)
+                .collect(),
+            wasm_costs,
+            inner,
+            idle_eviction,
             type_storage: PhantomData::<S>,
             type_api: PhantomData::<A>,
             type_querier: PhantomData::<Q>,
@@ -137,6 +415,82 @@ where
         })
     }
 
+    /// Prepares the base directory `new_dir` for this version of the VM from the Wasm stored
+    /// in the base directory `old_dir`, e.g. before a chain upgrade that changes the Wasmer
+    /// version. Without this, every contract is compiled on its first use after the upgrade.
+    ///
+    /// Every stored Wasm is verified against its checksum, copied to `new_dir` and
+    /// compiled into an artifact for the current module version, unless a loadable artifact
+    /// already exists. Both directories can be the same. The Wasm is not checked against the
+    /// capabilities of the chain, since it was accepted when it was stored. Failures of
+    /// single Wasm files do not stop the migration and are listed in the returned report.
+    /// `progress` is called after each file. Artifacts are compiled without
+    /// [`CacheOptions::middlewares`] and with the default costs, so caches with extra
+    /// middlewares or custom [`CacheOptions::wasm_costs`] do not use them.
+    ///
+    /// # Safety
+    ///
+    /// This function is marked unsafe for the same reason as [`Cache::new`]: artifacts
+    /// already stored in `new_dir` are assumed to be correct.
+    pub unsafe fn migrate_artifacts(
+        old_dir: impl AsRef<Path>,
+        new_dir: impl AsRef<Path>,
+        mut progress: impl FnMut(&MigrationProgress),
+    ) -> VmResult<MigrationReport> {
+        let old_wasm_path = old_dir.as_ref().join(STATE_DIR).join(WASM_DIR);
+        let new_wasm_path = new_dir.as_ref().join(STATE_DIR).join(WASM_DIR);
+        create_dir_all(&new_wasm_path).map_err(|e| {
+            VmError::cache_err(format!(
+                "Error creating directory {}: {}",
+                new_wasm_path.display(),
+                e
+            ))
+        })?;
+        let mut fs_cache = FileSystemCache::new(new_dir.as_ref().join(CACHE_DIR).join(MODULES_DIR))
+            .map_err(|e| VmError::cache_err(format!("Error file system cache: {}", e)))?;
+
+        let mut paths = read_dir(&old_wasm_path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                VmError::cache_err(format!(
+                    "Error reading directory {}: {}",
+                    old_wasm_path.display(),
+                    e
+                ))
+            })?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+
+        let store = make_runtime_store(None);
+        let mut report = MigrationReport::default();
+        let total = paths.len();
+        for (index, path) in paths.into_iter().enumerate() {
+            let outcome = match migrate_wasm(&path, &new_wasm_path, &mut fs_cache, &store) {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    report.failures.push((path.clone(), err));
+                    MigrationOutcome::Failed
+                }
+            };
+            match outcome {
+                MigrationOutcome::Compiled => report.compiled += 1,
+                MigrationOutcome::Revalidated => report.revalidated += 1,
+                MigrationOutcome::Failed => {}
+            }
+            progress(&MigrationProgress {
+                done: index + 1,
+                total,
+                path: &path,
+                outcome,
+            });
+        }
+        Ok(report)
+    }
+
     pub fn stats(&self) -> Stats {
         self.inner.lock().unwrap().stats
     }
@@ -152,11 +506,109 @@ where
         }
     }
 
+    /// Returns the hits and sizes of the pinned modules, e.g. to find out which of them
+    /// are worth the memory they occupy.
+    pub fn pinned_metrics(&self) -> PinnedMetrics {
+        let cache = self.inner.lock().unwrap();
+        PinnedMetrics {
+            per_module: cache.pinned_memory_cache.per_module_metrics(),
+        }
+    }
+
+    /// Returns the statistics of this cache instance together with the cumulative
+    /// statistics of all instances that used the same base directory before.
+    ///
+    /// The cumulative statistics are written to disk by [`Cache::persist_stats`] and
+    /// when the cache is dropped.
+    pub fn stats_report(&self) -> StatsReport {
+        let cache = self.inner.lock().unwrap();
+        StatsReport {
+            session: cache.session_stats(),
+            total: cache.total_stats(),
+            elements_pinned_memory_cache: cache.pinned_memory_cache.len(),
+            elements_memory_cache: cache.memory_cache.len(),
+            size_pinned_memory_cache: cache.pinned_memory_cache.size(),
+            size_memory_cache: cache.memory_cache.size(),
+        }
+    }
+
+    /// Writes the cumulative statistics to the base directory, such that the next cache
+    /// instance using it continues counting from there.
+    ///
+    /// Multiple cache instances sharing a base directory at the same time overwrite each
+    /// other's statistics.
+    pub fn persist_stats(&self) -> VmResult<()> {
+        self.inner.lock().unwrap().persist_stats()
+    }
+
+    /// Removes compiled modules and Wasm blobs from disk that were not used for
+    /// `unused_for`, because long-lived nodes accumulate lots of artifacts of contracts
+    /// that are no longer executed. This covers artifacts of all module versions,
+    /// leftovers of interrupted writes, and the stored Wasm.
+    ///
+    /// The checksums in `keep_checksums` and all modules in the memory caches are kept.
+    /// Since modules in the memory cache are not loaded from disk, their files may look
+    /// unused. Removed artifacts are compiled again from Wasm on their next use, but
+    /// removed Wasm has to be stored again with [`Cache::save_wasm`] before it can be
+    /// used, so pass all checksums the chain may still need.
+    ///
+    /// Whether a file was used is decided by its modification and access time, which
+    /// most file systems update at least once a day.
+    pub fn prune(
+        &self,
+        unused_for: Duration,
+        keep_checksums: &[Checksum],
+    ) -> VmResult<PruneReport> {
+        self.prune_files(unused_for, keep_checksums, false)
+    }
+
+    /// Lists the files [`Cache::prune`] would remove, without removing them.
+    pub fn prune_dry_run(
+        &self,
+        unused_for: Duration,
+        keep_checksums: &[Checksum],
+    ) -> VmResult<PruneReport> {
+        self.prune_files(unused_for, keep_checksums, true)
+    }
+
+    fn prune_files(
+        &self,
+        unused_for: Duration,
+        keep_checksums: &[Checksum],
+        dry_run: bool,
+    ) -> VmResult<PruneReport> {
+        let unused_since = SystemTime::now()
+            .checked_sub(unused_for)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let cache = self.inner.lock().unwrap();
+        let keep = |checksum: &Checksum| {
+            keep_checksums.contains(checksum)
+                || cache.pinned_memory_cache.has(checksum)
+                || cache.memory_cache.has(checksum)
+        };
+
+        let mut pruned = cache
+            .fs_cache
+            .prune(unused_since, keep, dry_run)
+            .map_err(|e| VmError::cache_err(format!("Error pruning modules: {}", e)))?;
+        pruned.append(
+            &mut prune_dir(&cache.wasm_path, unused_since, keep, dry_run)
+                .map_err(|e| VmError::cache_err(format!("Error pruning Wasm files: {}", e)))?,
+        );
+        Ok(PruneReport {
+            bytes: pruned.iter().map(|(_, size)| size).sum(),
+            files: pruned.into_iter().map(|(path, _)| path).collect(),
+        })
+    }
+
     pub fn save_wasm(&self, wasm: &[u8]) -> VmResult<Checksum> {
         check_wasm(wasm, &self.supported_features)?;
-        let module = compile(wasm, None, &[])?;
+        let start = Instant::now();
+        let module = compile_with_costs(wasm, None, &self.middlewares, &self.wasm_costs)?;
+        let compile_time = start.elapsed();
 
         let mut cache = self.inner.lock().unwrap();
+        cache.record_compile(compile_time);
         let checksum = save_wasm_to_disk(&cache.wasm_path, wasm)?;
         cache.fs_cache.store(&checksum, &module)?;
         Ok(checksum)
@@ -227,7 +679,14 @@ where
 
         // Re-compile from original Wasm bytecode
         let code = self.load_wasm_with_path(&cache.wasm_path, checksum)?;
-        let module = compile(&code, Some(cache.instance_memory_limit), &[])?;
+        let start = Instant::now();
+        let module = compile_with_costs(
+            &code,
+            Some(cache.instance_memory_limit),
+            &self.middlewares,
+            &self.wasm_costs,
+        )?;
+        cache.record_compile(start.elapsed());
         // Store into the fs cache too
         cache.fs_cache.store(checksum, &module)?;
         let module_size = loupe::size_of_val(&module);
@@ -257,6 +716,16 @@ where
         backend: Backend<A, S, Q>,
         options: InstanceOptions,
     ) -> VmResult<Instance<A, S, Q>> {
+        if options.instrumentation.is_some() {
+            return Err(VmError::instantiation_err(
+                "Instrumentation is not supported for cached modules. Use Instance::from_code instead.",
+            ));
+        }
+        if matches!(&options.wasm_costs, Some(costs) if *costs != self.wasm_costs) {
+            return Err(VmError::instantiation_err(
+                "Cached modules are compiled with the costs of the cache. Use CacheOptions::wasm_costs instead.",
+            ));
+        }
         let module = self.get_module(checksum)?;
         let instance = Instance::from_module(
             &module,
@@ -291,9 +760,10 @@ where
         if let Some(module) = cache.fs_cache.load(checksum, &store)? {
             cache.stats.hits_fs_cache += 1;
             let module_size = loupe::size_of_val(&module);
-            cache
+            let evicted = cache
                 .memory_cache
                 .store(checksum, module.clone(), module_size)?;
+            cache.stats.evictions_memory_cache += evicted as u32;
             return Ok(module);
         }
 
@@ -304,16 +774,36 @@ where
         // stored the old module format.
         let wasm = self.load_wasm_with_path(&cache.wasm_path, checksum)?;
         cache.stats.misses += 1;
-        let module = compile(&wasm, Some(cache.instance_memory_limit), &[])?;
+        let start = Instant::now();
+        let module = compile_with_costs(
+            &wasm,
+            Some(cache.instance_memory_limit),
+            &self.middlewares,
+            &self.wasm_costs,
+        )?;
+        cache.record_compile(start.elapsed());
         cache.fs_cache.store(checksum, &module)?;
         let module_size = loupe::size_of_val(&module);
-        cache
+        let evicted = cache
             .memory_cache
             .store(checksum, module.clone(), module_size)?;
+        cache.stats.evictions_memory_cache += evicted as u32;
         Ok(module)
     }
 }
 
+impl<A: BackendApi, S: Storage, Q: Querier> Drop for Cache<A, S, Q> {
+    fn drop(&mut self) {
+        if let Some(idle_eviction) = self.idle_eviction.take() {
+            idle_eviction.stop();
+        }
+        if let Ok(cache) = self.inner.lock() {
+            // Errors cannot be reported here. Use `persist_stats` to handle them.
+            let _ = cache.persist_stats();
+        }
+    }
+}
+
 unsafe impl<A, S, Q> Sync for Cache<A, S, Q>
 where
     A: BackendApi + 'static,
@@ -333,6 +823,15 @@ where
 /// save stores the wasm code in the given directory and returns an ID for lookup.
 /// It will create the directory if it doesn't exist.
 /// Saving the same byte code multiple times is allowed.
+/// Loads the cumulative statistics from a previous run. Missing or unreadable
+/// statistics are not an error but start counting from zero.
+fn load_stats_from_disk(path: &Path) -> CumulativeStats {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
 fn save_wasm_to_disk(dir: impl Into<PathBuf>, wasm: &[u8]) -> VmResult<Checksum> {
     // calculate filename
     let checksum = Checksum::generate(wasm);
@@ -353,6 +852,30 @@ fn save_wasm_to_disk(dir: impl Into<PathBuf>, wasm: &[u8]) -> VmResult<Checksum>
     Ok(checksum)
 }
 
+/// Migrates a single Wasm file for [`Cache::migrate_artifacts`].
+fn migrate_wasm(
+    path: &Path,
+    new_wasm_path: &Path,
+    fs_cache: &mut FileSystemCache,
+    store: &wasmer::Store,
+) -> VmResult<MigrationOutcome> {
+    let wasm = std::fs::read(path)
+        .map_err(|e| VmError::cache_err(format!("Error reading Wasm file: {}", e)))?;
+    let checksum = Checksum::generate(&wasm);
+    if path.file_name().and_then(|name| name.to_str()) != Some(checksum.to_hex().as_str()) {
+        return Err(VmError::integrity_err());
+    }
+    save_wasm_to_disk(new_wasm_path, &wasm)?;
+
+    // An artifact that fails to load is replaced
+    if let Ok(Some(_)) = fs_cache.load(&checksum, store) {
+        return Ok(MigrationOutcome::Revalidated);
+    }
+    let module = compile(&wasm, None, &[])?;
+    fs_cache.store(&checksum, &module)?;
+    Ok(MigrationOutcome::Compiled)
+}
+
 fn load_wasm_from_disk(dir: impl Into<PathBuf>, checksum: &Checksum) -> VmResult<Vec<u8>> {
     // this requires the directory and file to exist
     let path = dir.into().join(checksum.to_hex());
@@ -383,6 +906,8 @@ mod tests {
     const TESTING_OPTIONS: InstanceOptions = InstanceOptions {
         gas_limit: TESTING_GAS_LIMIT,
         print_debug: false,
+        instrumentation: None,
+        wasm_costs: None,
     };
     const TESTING_MEMORY_CACHE_SIZE: Size = Size::mebi(200);
 
@@ -399,6 +924,9 @@ mod tests {
             supported_features: default_features(),
             memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
             instance_memory_limit: TESTING_MEMORY_LIMIT,
+            memory_cache_idle_ttl: None,
+            middlewares: vec![],
+            wasm_costs: None,
         }
     }
 
@@ -408,6 +936,9 @@ mod tests {
             supported_features: features_from_csv("iterator,staking,stargate"),
             memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
             instance_memory_limit: TESTING_MEMORY_LIMIT,
+            memory_cache_idle_ttl: None,
+            middlewares: vec![],
+            wasm_costs: None,
         }
     }
 
@@ -452,6 +983,201 @@ mod tests {
         }
     }
 
+    #[test]
+    fn save_wasm_compiles_with_middlewares() {
+        use wasmer::wasmparser::Operator;
+        use wasmer::{
+            FunctionMiddleware, LocalFunctionIndex, MiddlewareError, MiddlewareReaderState,
+        };
+
+        /// Rejects `memory.grow`, which every contract uses
+        #[derive(Debug, loupe::MemoryUsage)]
+        struct NoMemoryGrow;
+
+        impl ModuleMiddleware for NoMemoryGrow {
+            fn generate_function_middleware(
+                &self,
+                _: LocalFunctionIndex,
+            ) -> Box<dyn FunctionMiddleware> {
+                Box::new(NoMemoryGrow)
+            }
+        }
+
+        impl FunctionMiddleware for NoMemoryGrow {
+            fn feed<'a>(
+                &mut self,
+                operator: Operator<'a>,
+                state: &mut MiddlewareReaderState<'a>,
+            ) -> Result<(), MiddlewareError> {
+                if let Operator::MemoryGrow { .. } = operator {
+                    return Err(MiddlewareError::new("NoMemoryGrow", "memory.grow"));
+                }
+                state.push_operator(operator);
+                Ok(())
+            }
+        }
+
+        let options = CacheOptions {
+            middlewares: vec![CacheMiddleware::new(
+                "no-memory-grow",
+                Arc::new(NoMemoryGrow),
+            )],
+            wasm_costs: None,
+            ..make_testing_options()
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        match cache.save_wasm(CONTRACT).unwrap_err() {
+            VmError::CompileErr { msg, .. } => {
+                assert!(msg.contains("NoMemoryGrow"), "Unexpected message: {}", msg)
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn modules_are_stored_per_compilation_variant() {
+        use wasmer::{FunctionMiddleware, LocalFunctionIndex};
+
+        /// Keeps the code as it is
+        #[derive(Debug, loupe::MemoryUsage)]
+        struct PassThrough;
+
+        impl ModuleMiddleware for PassThrough {
+            fn generate_function_middleware(
+                &self,
+                _: LocalFunctionIndex,
+            ) -> Box<dyn FunctionMiddleware> {
+                Box::new(PassThrough)
+            }
+        }
+
+        impl FunctionMiddleware for PassThrough {}
+
+        let middleware = |id| CacheMiddleware::new(id, Arc::new(PassThrough));
+        let options = make_testing_options();
+        let modules_path = options.base_dir.join(CACHE_DIR).join(MODULES_DIR);
+        let checksum = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> = unsafe {
+                Cache::new(CacheOptions {
+                    middlewares: vec![middleware("a")],
+                    ..options.clone()
+                })
+                .unwrap()
+            };
+            cache.save_wasm(CONTRACT).unwrap()
+        };
+        let variant = compilation_variant(&[middleware("a")], &CostTable::default()).unwrap();
+        let dirs: Vec<_> = read_dir(&modules_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(dirs.len(), 1);
+        assert!(dirs[0].ends_with(&format!("-{}", variant)), "{:?}", dirs);
+
+        // Not loaded by a cache with other middlewares
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = unsafe {
+            Cache::new(CacheOptions {
+                middlewares: vec![middleware("b")],
+                ..options.clone()
+            })
+            .unwrap()
+        };
+        let _ = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 0);
+        assert_eq!(cache.stats().misses, 1);
+        drop(cache);
+
+        // but by one with the same middlewares
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = unsafe {
+            Cache::new(CacheOptions {
+                middlewares: vec![middleware("a")],
+                ..options
+            })
+            .unwrap()
+        };
+        let _ = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+
+        let costs = CostTable {
+            default: 7,
+            ..CostTable::default()
+        };
+        let default_costs = CostTable::default();
+        assert_eq!(compilation_variant(&[], &default_costs), None);
+        let variants: HashSet<_> = vec![
+            compilation_variant(&[middleware("a")], &default_costs),
+            compilation_variant(&[middleware("b")], &default_costs),
+            compilation_variant(&[middleware("a"), middleware("b")], &default_costs),
+            compilation_variant(&[middleware("ab")], &default_costs),
+            compilation_variant(&[], &costs),
+            compilation_variant(&[middleware("a")], &costs),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(variants.len(), 6);
+    }
+
+    #[test]
+    fn cache_with_wasm_costs_does_not_load_other_modules() {
+        let options = make_testing_options();
+        let checksum = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                unsafe { Cache::new(options.clone()).unwrap() };
+            cache.save_wasm(CONTRACT).unwrap()
+        };
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = unsafe {
+            Cache::new(CacheOptions {
+                wasm_costs: Some(CostTable {
+                    default: 7,
+                    ..CostTable::default()
+                }),
+                ..options
+            })
+            .unwrap()
+        };
+        let _ = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 0);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn get_instance_rejects_other_wasm_costs() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(make_testing_options()).unwrap() };
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        let options = InstanceOptions {
+            wasm_costs: Some(CostTable::default()),
+            ..TESTING_OPTIONS
+        };
+        cache
+            .get_instance(&checksum, mock_backend(&[]), options)
+            .unwrap();
+
+        let options = InstanceOptions {
+            wasm_costs: Some(CostTable {
+                default: 7,
+                ..CostTable::default()
+            }),
+            ..TESTING_OPTIONS
+        };
+        match cache.get_instance(&checksum, mock_backend(&[]), options) {
+            Err(VmError::InstantiationErr { msg, .. }) => {
+                assert!(msg.contains("CacheOptions::wasm_costs"), "{}", msg)
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Expected error"),
+        }
+    }
+
     #[test]
     fn save_wasm_fills_file_system_but_not_memory_cache() {
         // Who knows if and when the uploaded contract will be executed. Don't pollute
@@ -470,6 +1196,106 @@ mod tests {
         assert_eq!(cache.stats().misses, 0);
     }
 
+    #[test]
+    fn idle_modules_are_evicted_from_memory_cache() {
+        let ttl = Duration::from_secs(60);
+        let options = CacheOptions {
+            memory_cache_idle_ttl: Some(ttl),
+            middlewares: vec![],
+            wasm_costs: None,
+            ..make_testing_options()
+        };
+        let mut cache = unsafe { Cache::new(options).unwrap() };
+        let start = Instant::now();
+        let elapsed = Arc::new(Mutex::new(Duration::ZERO));
+        let clock = elapsed.clone();
+        cache.inner.lock().unwrap().memory_cache = InMemoryCache::new(TESTING_MEMORY_CACHE_SIZE)
+            .with_clock(move || start + *clock.lock().unwrap());
+        // Check often instead of every 15 seconds
+        cache.idle_eviction.take().unwrap().stop();
+        cache.idle_eviction =
+            Some(IdleEviction::start(cache.inner.clone(), ttl, Duration::from_millis(1)).unwrap());
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        // Loading from the file system cache stores the module in the memory cache
+        let backend = mock_backend(&[]);
+        let _ = cache
+            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.metrics().elements_memory_cache, 1);
+
+        // Not idle for long enough
+        *elapsed.lock().unwrap() = Duration::from_secs(59);
+        let backend = mock_backend(&[]);
+        let _ = cache
+            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_memory_cache, 1);
+        assert_eq!(cache.stats().evictions_memory_cache, 0);
+
+        // Evicted without any further use of the cache
+        *elapsed.lock().unwrap() = Duration::from_secs(120);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while cache.metrics().elements_memory_cache > 0 {
+            assert!(Instant::now() < deadline, "the idle module was not evicted");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(cache.stats().evictions_memory_cache, 1);
+
+        // The module is loaded from disk again
+        let backend = mock_backend(&[]);
+        let _ = cache
+            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_memory_cache, 1);
+        assert_eq!(cache.stats().hits_fs_cache, 2);
+        assert_eq!(cache.metrics().elements_memory_cache, 1);
+    }
+
+    #[test]
+    fn memory_cache_evicts_least_recently_used_modules() {
+        // The same contract with an empty custom section "x", to get a second checksum
+        let mut contract2 = CONTRACT.to_vec();
+        contract2.extend_from_slice(&[0x00, 0x02, 0x01, b'x']);
+
+        let module_size = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                unsafe { Cache::new(make_testing_options()).unwrap() };
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            let _ = cache
+                .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+                .unwrap();
+            cache.metrics().size_memory_cache
+        };
+
+        // Room for one module only
+        let options = CacheOptions {
+            memory_cache_size: Size(module_size * 3 / 2),
+            ..make_testing_options()
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        let checksum1 = cache.save_wasm(CONTRACT).unwrap();
+        let checksum2 = cache.save_wasm(&contract2).unwrap();
+        for checksum in [checksum1, checksum2, checksum1] {
+            let _ = cache
+                .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+                .unwrap();
+        }
+        let metrics = cache.metrics();
+        assert_eq!(metrics.stats.hits_fs_cache, 3);
+        assert_eq!(metrics.stats.evictions_memory_cache, 2);
+        assert_eq!(metrics.elements_memory_cache, 1);
+        assert!(metrics.size_memory_cache <= module_size * 3 / 2);
+        assert_eq!(cache.stats_report().session.evictions_memory_cache, 2);
+
+        // The most recently used module is resident
+        let _ = cache
+            .get_instance(&checksum1, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_memory_cache, 1);
+    }
+
     #[test]
     fn load_wasm_works() {
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
@@ -491,6 +1317,9 @@ mod tests {
                 supported_features: default_features(),
                 memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
                 instance_memory_limit: TESTING_MEMORY_LIMIT,
+                memory_cache_idle_ttl: None,
+                middlewares: vec![],
+                wasm_costs: None,
             };
             let cache1: Cache<MockApi, MockStorage, MockQuerier> =
                 unsafe { Cache::new(options1).unwrap() };
@@ -503,6 +1332,9 @@ mod tests {
                 supported_features: default_features(),
                 memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
                 instance_memory_limit: TESTING_MEMORY_LIMIT,
+                memory_cache_idle_ttl: None,
+                middlewares: vec![],
+                wasm_costs: None,
             };
             let cache2: Cache<MockApi, MockStorage, MockQuerier> =
                 unsafe { Cache::new(options2).unwrap() };
@@ -537,6 +1369,9 @@ mod tests {
             supported_features: default_features(),
             memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
             instance_memory_limit: TESTING_MEMORY_LIMIT,
+            memory_cache_idle_ttl: None,
+            middlewares: vec![],
+            wasm_costs: None,
         };
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
             unsafe { Cache::new(options).unwrap() };
@@ -896,6 +1731,8 @@ mod tests {
         let options = InstanceOptions {
             gas_limit: 10,
             print_debug: false,
+            instrumentation: None,
+            wasm_costs: None,
         };
         let mut instance1 = cache.get_instance(&checksum, backend1, options).unwrap();
         assert_eq!(cache.stats().hits_fs_cache, 1);
@@ -916,6 +1753,8 @@ mod tests {
         let options = InstanceOptions {
             gas_limit: TESTING_GAS_LIMIT,
             print_debug: false,
+            instrumentation: None,
+            wasm_costs: None,
         };
         let mut instance2 = cache.get_instance(&checksum, backend2, options).unwrap();
         assert_eq!(cache.stats().hits_pinned_memory_cache, 0);
@@ -932,6 +1771,163 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn migrate_artifacts_works() {
+        let old_dir = TempDir::new().unwrap().into_path();
+        let new_dir = TempDir::new().unwrap().into_path();
+        let checksum = {
+            let options = CacheOptions {
+                base_dir: old_dir.clone(),
+                ..make_testing_options()
+            };
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                unsafe { Cache::new(options).unwrap() };
+            cache.save_wasm(CONTRACT).unwrap()
+        };
+        // Does not match the checksum in its name
+        let corrupted = old_dir.join(STATE_DIR).join(WASM_DIR).join("corrupted");
+        std::fs::write(&corrupted, CONTRACT).unwrap();
+
+        let mut progress = Vec::new();
+        let report = unsafe {
+            Cache::<MockApi, MockStorage, MockQuerier>::migrate_artifacts(&old_dir, &new_dir, |p| {
+                progress.push((p.done, p.total, p.path.to_path_buf(), p.outcome))
+            })
+            .unwrap()
+        };
+        assert_eq!(report.compiled, 1);
+        assert_eq!(report.revalidated, 0);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, corrupted);
+        assert!(matches!(report.failures[0].1, VmError::IntegrityErr { .. }));
+        assert_eq!(progress.len(), 2);
+        assert_eq!((progress[0].0, progress[0].1), (1, 2));
+        assert_eq!((progress[1].0, progress[1].1), (2, 2));
+        let corrupted_progress = progress.iter().find(|p| p.2 == corrupted).unwrap();
+        assert_eq!(corrupted_progress.3, MigrationOutcome::Failed);
+
+        // The new cache finds the Wasm and its artifact
+        let options = CacheOptions {
+            base_dir: new_dir.clone(),
+            ..make_testing_options()
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        assert_eq!(cache.load_wasm(&checksum).unwrap(), CONTRACT);
+        let _ = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+        assert_eq!(cache.stats().misses, 0);
+
+        // Existing artifacts are only revalidated
+        let report = unsafe {
+            Cache::<MockApi, MockStorage, MockQuerier>::migrate_artifacts(
+                &old_dir,
+                &new_dir,
+                |_| {},
+            )
+            .unwrap()
+        };
+        assert_eq!((report.compiled, report.revalidated), (0, 1));
+    }
+
+    #[test]
+    fn migrate_artifacts_works_in_place() {
+        let options = make_testing_options();
+        let base_dir = options.base_dir.clone();
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        drop(cache);
+
+        let report = unsafe {
+            Cache::<MockApi, MockStorage, MockQuerier>::migrate_artifacts(
+                &base_dir,
+                &base_dir,
+                |_| {},
+            )
+            .unwrap()
+        };
+        assert_eq!((report.compiled, report.revalidated), (0, 1));
+        assert!(report.failures.is_empty());
+        let wasm = load_wasm_from_disk(base_dir.join(STATE_DIR).join(WASM_DIR), &checksum);
+        assert_eq!(wasm.unwrap(), CONTRACT);
+    }
+
+    #[test]
+    fn prune_works() {
+        let options = make_stargate_testing_options();
+        let base_dir = options.base_dir.clone();
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options.clone()).unwrap() };
+        let checksum1 = cache.save_wasm(CONTRACT).unwrap();
+        let checksum2 = cache.save_wasm(IBC_CONTRACT).unwrap();
+
+        let modules_path = base_dir.join(CACHE_DIR).join(MODULES_DIR);
+        let latest_path = read_dir(&modules_path)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let tmp_file = latest_path.join(".leftover.1-0.tmp");
+        std::fs::write(&tmp_file, b"partial").unwrap();
+        let old_path = modules_path.join("v1-wasmer0");
+        create_dir_all(&old_path).unwrap();
+        let old_artifact = old_path.join(Checksum::generate(b"old").to_hex());
+        std::fs::write(&old_artifact, b"old artifact").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        // Everything was used recently
+        let report = cache.prune_dry_run(Duration::from_secs(3600), &[]).unwrap();
+        assert_eq!(report, PruneReport::default());
+
+        // A dry run removes nothing
+        let report = cache.prune_dry_run(Duration::ZERO, &[checksum1]).unwrap();
+        let mut files = report.files.clone();
+        files.sort();
+        let mut expected = vec![
+            latest_path.join(checksum2.to_hex()),
+            base_dir
+                .join(STATE_DIR)
+                .join(WASM_DIR)
+                .join(checksum2.to_hex()),
+            tmp_file.clone(),
+            old_artifact.clone(),
+        ];
+        expected.sort();
+        assert_eq!(files, expected);
+        let bytes: u64 = files
+            .iter()
+            .map(|file| file.metadata().unwrap().len())
+            .sum();
+        assert_eq!(report.bytes, bytes);
+
+        // Modules in the memory cache are kept
+        let _ = cache
+            .get_instance(&checksum2, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        let report = cache.prune(Duration::ZERO, &[checksum1]).unwrap();
+        let mut files = report.files;
+        files.sort();
+        let mut expected = vec![tmp_file.clone(), old_artifact];
+        expected.sort();
+        assert_eq!(files, expected);
+        assert!(!tmp_file.exists());
+        assert!(!old_path.exists());
+        drop(cache);
+
+        // Removed Wasm cannot be loaded anymore
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        let report = cache.prune(Duration::ZERO, &[]).unwrap();
+        assert_eq!(report.files.len(), 4);
+        assert!(cache.load_wasm(&checksum1).is_err());
+        assert!(cache.load_wasm(&checksum2).is_err());
+        assert!(latest_path.exists());
+    }
+
     #[test]
     fn save_wasm_to_disk_works_for_same_data_multiple_times() {
         let tmp_dir = TempDir::new().unwrap();
@@ -1063,4 +2059,123 @@ mod tests {
         let non_id = Checksum::generate(b"non_existent");
         cache.unpin(&non_id).unwrap();
     }
+
+    #[test]
+    fn pinned_metrics_works() {
+        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        assert_eq!(cache.pinned_metrics().per_module, vec![]);
+
+        cache.pin(&checksum).unwrap();
+        let metrics = cache.pinned_metrics();
+        assert_eq!(metrics.per_module.len(), 1);
+        let (pinned_checksum, module) = metrics.per_module[0];
+        assert_eq!(pinned_checksum, checksum);
+        assert_eq!(module.hits, 0);
+        assert_eq!(module.size, cache.metrics().size_pinned_memory_cache);
+
+        for _ in 0..2 {
+            let backend = mock_backend(&[]);
+            let _instance = cache
+                .get_instance(&checksum, backend, TESTING_OPTIONS)
+                .unwrap();
+        }
+        assert_eq!(cache.pinned_metrics().per_module[0].1.hits, 2);
+
+        // metrics start over when pinning again
+        cache.unpin(&checksum).unwrap();
+        assert_eq!(cache.pinned_metrics().per_module, vec![]);
+        cache.pin(&checksum).unwrap();
+        assert_eq!(cache.pinned_metrics().per_module[0].1.hits, 0);
+    }
+
+    #[test]
+    fn stats_report_works() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(make_testing_options()).unwrap() };
+        let report = cache.stats_report();
+        assert_eq!(report.session, CumulativeStats::default());
+        assert_eq!(report.total, CumulativeStats::default());
+
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        let backend = mock_backend(&[]);
+        let _instance = cache
+            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .unwrap();
+
+        let report = cache.stats_report();
+        assert_eq!(report.session.compiles, 1);
+        assert!(report.session.compile_time_nanos > 0);
+        assert_eq!(report.session.hits_fs_cache, 1);
+        assert_eq!(report.session.misses, 0);
+        assert_eq!(report.total, report.session);
+        assert_eq!(report.elements_memory_cache, 1);
+        assert!(report.size_memory_cache > 0);
+    }
+
+    #[test]
+    fn stats_are_persisted_across_cache_instances() {
+        let options = make_testing_options();
+
+        let checksum = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                unsafe { Cache::new(options.clone()).unwrap() };
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            cache.persist_stats().unwrap();
+            checksum
+        };
+
+        let first_run = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                unsafe { Cache::new(options.clone()).unwrap() };
+            let report = cache.stats_report();
+            assert_eq!(report.session, CumulativeStats::default());
+            assert_eq!(report.total.compiles, 1);
+
+            let backend = mock_backend(&[]);
+            let _instance = cache
+                .get_instance(&checksum, backend, TESTING_OPTIONS)
+                .unwrap();
+            // stats are persisted on drop
+            cache.stats_report().total
+        };
+        assert_eq!(first_run.compiles, 1);
+        assert_eq!(first_run.hits_fs_cache, 1);
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        assert_eq!(cache.stats_report().total, first_run);
+    }
+
+    #[test]
+    fn corrupted_stats_file_is_ignored() {
+        let options = make_testing_options();
+        let stats_path = options.base_dir.join(CACHE_DIR).join(STATS_FILE);
+        create_dir_all(stats_path.parent().unwrap()).unwrap();
+        std::fs::write(&stats_path, b"not json").unwrap();
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            unsafe { Cache::new(options).unwrap() };
+        assert_eq!(cache.stats_report().total, CumulativeStats::default());
+    }
+
+    #[test]
+    fn stats_report_serializes() {
+        let report = StatsReport {
+            session: CumulativeStats {
+                compiles: 1,
+                ..CumulativeStats::default()
+            },
+            total: CumulativeStats::default(),
+            elements_pinned_memory_cache: 0,
+            elements_memory_cache: 1,
+            size_pinned_memory_cache: 0,
+            size_memory_cache: 42,
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"session":{"compiles":1,"compile_time_nanos":0,"hits_pinned_memory_cache":0,"hits_memory_cache":0,"hits_fs_cache":0,"misses":0,"evictions_memory_cache":0},"total":{"compiles":0,"compile_time_nanos":0,"hits_pinned_memory_cache":0,"hits_memory_cache":0,"hits_fs_cache":0,"misses":0,"evictions_memory_cache":0},"elements_pinned_memory_cache":0,"elements_memory_cache":1,"size_pinned_memory_cache":0,"size_memory_cache":42}"#
+        );
+    }
 }
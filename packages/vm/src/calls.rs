@@ -12,6 +12,7 @@ use crate::backend::{BackendApi, Querier, Storage};
 use crate::conversion::ref_to_u32;
 use crate::errors::{VmError, VmResult};
 use crate::instance::Instance;
+use crate::receipt::Receipt;
 use crate::serde::{from_slice, to_vec};
 
 /// The limits in here protect the host from allocating an unreasonable amount of memory
@@ -189,6 +190,83 @@ where
     Ok(result)
 }
 
+/// Like [`call_instantiate`] but also returns a hash of the result, see [`Receipt`].
+pub fn call_instantiate_with_receipt<A, S, Q, U>(
+    instance: &mut Instance<A, S, Q>,
+    env: &Env,
+    info: &MessageInfo,
+    msg: &[u8],
+) -> VmResult<Receipt<U>>
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+    U: DeserializeOwned + CustomMsg,
+{
+    Receipt::new(call_instantiate(instance, env, info, msg)?)
+}
+
+/// Like [`call_execute`] but also returns a hash of the result, see [`Receipt`].
+pub fn call_execute_with_receipt<A, S, Q, U>(
+    instance: &mut Instance<A, S, Q>,
+    env: &Env,
+    info: &MessageInfo,
+    msg: &[u8],
+) -> VmResult<Receipt<U>>
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+    U: DeserializeOwned + CustomMsg,
+{
+    Receipt::new(call_execute(instance, env, info, msg)?)
+}
+
+/// Like [`call_migrate`] but also returns a hash of the result, see [`Receipt`].
+pub fn call_migrate_with_receipt<A, S, Q, U>(
+    instance: &mut Instance<A, S, Q>,
+    env: &Env,
+    msg: &[u8],
+) -> VmResult<Receipt<U>>
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+    U: DeserializeOwned + CustomMsg,
+{
+    Receipt::new(call_migrate(instance, env, msg)?)
+}
+
+/// Like [`call_sudo`] but also returns a hash of the result, see [`Receipt`].
+pub fn call_sudo_with_receipt<A, S, Q, U>(
+    instance: &mut Instance<A, S, Q>,
+    env: &Env,
+    msg: &[u8],
+) -> VmResult<Receipt<U>>
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+    U: DeserializeOwned + CustomMsg,
+{
+    Receipt::new(call_sudo(instance, env, msg)?)
+}
+
+/// Like [`call_reply`] but also returns a hash of the result, see [`Receipt`].
+pub fn call_reply_with_receipt<A, S, Q, U>(
+    instance: &mut Instance<A, S, Q>,
+    env: &Env,
+    msg: &Reply,
+) -> VmResult<Receipt<U>>
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+    U: DeserializeOwned + CustomMsg,
+{
+    Receipt::new(call_reply(instance, env, msg)?)
+}
+
 pub fn call_query<A, S, Q>(
     instance: &mut Instance<A, S, Q>,
     env: &Env,
@@ -437,7 +515,9 @@ where
     Q: Querier + 'static,
 {
     instance.set_storage_readonly(true);
-    call_raw(instance, "query", &[env, msg], read_limits::RESULT_QUERY)
+    instance.with_query_gas_meter(|instance| {
+        call_raw(instance, "query", &[env, msg], read_limits::RESULT_QUERY)
+    })
 }
 
 #[cfg(feature = "stargate")]
@@ -579,7 +659,10 @@ where
         instance.write_memory(region_ptr, arg)?;
         arg_region_ptrs.push(region_ptr.into());
     }
-    let result = instance.call_function1(name, &arg_region_ptrs)?;
+    let result = instance.call_function1(name, &arg_region_ptrs);
+    // Iterators only live for one call, even if the contract keeps their handles
+    instance.end_call();
+    let result = result?;
     let res_region_ptr = ref_to_u32(&result)?;
     let data = instance.read_memory(res_region_ptr, result_max_length)?;
     // free return value in wasm (arguments were freed in wasm code)
@@ -590,8 +673,10 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::receipt::receipt_hash;
     use crate::testing::{mock_env, mock_info, mock_instance};
     use cosmwasm_std::{coins, Empty};
+    use hex_literal::hex;
 
     static CONTRACT: &[u8] = include_bytes!("../testdata/hackatom.wasm");
 
@@ -669,6 +754,31 @@ mod tests {
         assert_eq!(query_response.as_slice(), b"{\"verifier\":\"verifies\"}");
     }
 
+    #[test]
+    fn call_execute_with_receipt_works() {
+        let mut instance = mock_instance(CONTRACT, &[]);
+
+        // init
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+
+        // execute
+        let info = mock_info("verifies", &coins(15, "earth"));
+        let msg = br#"{"release":{}}"#;
+        let receipt =
+            call_execute_with_receipt::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+                .unwrap();
+        assert_eq!(receipt.hash, receipt_hash(&receipt.result).unwrap());
+        assert_eq!(
+            receipt.hash,
+            hex!("c079c3aac6e725c63553a1bbe2de4d9107bd838356e38b799ee05794d34948c0")
+        );
+        receipt.result.unwrap();
+    }
+
     #[cfg(feature = "stargate")]
     mod ibc {
         use super::*;
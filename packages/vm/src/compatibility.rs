@@ -1,4 +1,4 @@
-use parity_wasm::elements::{External, ImportEntry, Module};
+use parity_wasm::elements::{External, ImportEntry, Module, Type};
 use std::collections::BTreeSet;
 use std::collections::HashSet;
 
@@ -22,6 +22,7 @@ const SUPPORTED_IMPORTS: &[&str] = &[
     "env.ed25519_batch_verify",
     "env.debug",
     "env.query_chain",
+    "env.gas_remaining",
     #[cfg(feature = "iterator")]
     "env.db_scan",
     #[cfg(feature = "iterator")]
@@ -55,6 +56,7 @@ pub fn check_wasm(wasm_code: &[u8], supported_features: &HashSet<String>) -> VmR
     check_wasm_exports(&module)?;
     check_wasm_imports(&module, SUPPORTED_IMPORTS)?;
     check_wasm_features(&module, supported_features)?;
+    check_wasm_multi_value(&module)?;
     Ok(())
 }
 
@@ -189,6 +191,30 @@ fn check_wasm_features(module: &Module, supported_features: &HashSet<String>) ->
     Ok(())
 }
 
+/// Checks that function types with more than one result, as emitted when building
+/// contracts with the `multivalue` target feature, can be compiled.
+///
+/// Cranelift supports multi-value returns. Singlepass does not, so we reject such
+/// contracts upfront instead of failing compilation with a Wasmer error.
+fn check_wasm_multi_value(module: &Module) -> VmResult<()> {
+    if cfg!(feature = "cranelift") {
+        return Ok(());
+    }
+
+    let types = module
+        .type_section()
+        .map_or(&[][..], |section| section.types());
+    let multi_value = types.iter().any(|ty| match ty {
+        Type::Function(function) => function.results().len() > 1,
+    });
+    if multi_value {
+        return Err(VmError::static_validation_err(
+            "Wasm contract uses multi-value returns, which are not supported by the singlepass compiler. Enable the cranelift feature of cosmwasm-vm or build the contract without the multivalue target feature.",
+        ));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -707,4 +733,41 @@ mod tests {
             _ => panic!("Got unexpected error"),
         }
     }
+
+    #[test]
+    fn check_wasm_multi_value_works() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (func $pair (result i32 i32)
+                i32.const 1
+                i32.const 2)
+            (func (export "sum") (result i32)
+                call $pair
+                i32.add)
+            )"#,
+        )
+        .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        let result = check_wasm_multi_value(&module);
+        if cfg!(feature = "cranelift") {
+            result.unwrap();
+        } else {
+            match result.unwrap_err() {
+                VmError::StaticValidationErr { msg, .. } => {
+                    assert!(msg.starts_with("Wasm contract uses multi-value returns"))
+                }
+                err => panic!("Unexpected error: {:?}", err),
+            }
+        }
+
+        // A single result is fine
+        let wasm = wat::parse_str(
+            r#"(module
+            (func (export "one") (param i32 i64) (result i32)
+                i32.const 1)
+            )"#,
+        )
+        .unwrap();
+        check_wasm_multi_value(&deserialize_wasm(&wasm).unwrap()).unwrap();
+    }
 }
@@ -3,11 +3,15 @@ use std::borrow::{Borrow, BorrowMut};
 use std::ptr::NonNull;
 use std::sync::{Arc, RwLock};
 
-use wasmer::{HostEnvInitError, Instance as WasmerInstance, Memory, Val, WasmerEnv};
+use wasmer::{HostEnvInitError, Instance as WasmerInstance, Memory, RuntimeError, Val, WasmerEnv};
 use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 use crate::backend::{BackendApi, GasInfo, Querier, Storage};
 use crate::errors::{VmError, VmResult};
+use crate::hooks::{GasWarning, VmHooks};
+use crate::instance::{GasBreakdown, MemorySurcharge, QueryGasReport};
+#[cfg(feature = "iterator")]
+use crate::iterators::IteratorHandles;
 
 /// Never can never be instantiated.
 /// Replace this with the [never primitive type](https://doc.rust-lang.org/std/primitive.never.html) when stable.
@@ -59,15 +63,50 @@ pub struct GasState {
     pub gas_limit: u64,
     /// Tracking the gas used in the Cosmos SDK, in CosmWasm gas units.
     pub externally_used_gas: u64,
+    /// The gas charged for memory beyond the baseline of the [`MemorySurcharge`].
+    /// This is part of the internally used gas.
+    pub memory_surcharge: u64,
+    /// The gas charged by host functions for storage access, metered internally and externally
+    pub storage_gas: u64,
+    /// The gas charged by host functions for querying the chain, metered internally and externally
+    pub query_gas: u64,
+    /// The gas charged by host functions for cryptographic operations
+    pub crypto_gas: u64,
+    /// The gas charged by host functions for validating and converting addresses
+    pub address_gas: u64,
 }
 
 impl GasState {
-    fn with_limit(gas_limit: u64) -> Self {
+    pub(crate) fn with_limit(gas_limit: u64) -> Self {
         Self {
             gas_limit,
             externally_used_gas: 0,
+            memory_surcharge: 0,
+            storage_gas: 0,
+            query_gas: 0,
+            crypto_gas: 0,
+            address_gas: 0,
         }
     }
+
+    fn charge_category(&mut self, category: GasCategory, amount: u64) {
+        let used = match category {
+            GasCategory::Storage => &mut self.storage_gas,
+            GasCategory::Query => &mut self.query_gas,
+            GasCategory::Crypto => &mut self.crypto_gas,
+            GasCategory::Address => &mut self.address_gas,
+        };
+        *used = used.saturating_add(amount);
+    }
+}
+
+/// What a host function charges gas for, see [`GasBreakdown`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GasCategory {
+    Storage,
+    Query,
+    Crypto,
+    Address,
 }
 
 /// A environment that provides access to the ContextData.
@@ -165,16 +204,35 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
             let func = instance.exports.get_function(name)?;
             Ok(func.clone())
         })?;
-        func.call(args).map_err(|runtime_err| -> VmError {
-            self.with_wasmer_instance::<_, Never>(|instance| {
-                let err: VmError = match get_remaining_points(instance) {
-                    MeteringPoints::Remaining(_) => VmError::from(runtime_err),
-                    MeteringPoints::Exhausted => VmError::gas_depletion(),
-                };
-                Err(err)
+        let result = func
+            .call(args)
+            .map_err(|runtime_err| -> VmError {
+                self.with_wasmer_instance::<_, Never>(|instance| {
+                    let err: VmError = match get_remaining_points(instance) {
+                        MeteringPoints::Remaining(_) => match runtime_err.downcast::<VmError>() {
+                            // Host functions fail like this when charging gas, see `process_gas_info`
+                            Ok(err @ VmError::GasDepletion { .. }) => err,
+                            Ok(err) => VmError::from(RuntimeError::user(Box::new(err))),
+                            Err(runtime_err) => VmError::from(runtime_err),
+                        },
+                        MeteringPoints::Exhausted => VmError::gas_depletion(),
+                    };
+                    Err(err)
+                })
+                .unwrap_err() // with_wasmer_instance can only succeed if the callback succeeds
             })
-            .unwrap_err() // with_wasmer_instance can only succeed if the callback succeeds
-        })
+            .and_then(|result| {
+                self.charge_memory_surcharge()?;
+                Ok(result)
+            })
+            .map_err(|mut err| {
+                if let VmError::GasDepletion { breakdown, .. } = &mut err {
+                    breakdown.get_or_insert_with(|| self.gas_breakdown());
+                }
+                err
+            })?;
+        self.check_gas_warning();
+        Ok(result)
     }
 
     pub fn call_function0(&self, name: &str, args: &[Val]) -> VmResult<()> {
@@ -207,6 +265,31 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
         })
     }
 
+    /// Gives access to the handles of the iterators created by the contract.
+    #[cfg(feature = "iterator")]
+    pub fn with_iterators_mut<C, R>(&self, callback: C) -> R
+    where
+        C: FnOnce(&mut IteratorHandles) -> R,
+    {
+        self.with_context_data_mut(|context_data| callback(&mut context_data.iterators))
+    }
+
+    /// Removes the iterators of the current call, see [`IteratorHandles::end_call`], and
+    /// calls [`VmHooks::on_leaked_iterators`] if some were not read to the end.
+    pub fn end_call(&self) {
+        #[cfg(feature = "iterator")]
+        {
+            let leaked = self.with_context_data_mut(|context_data| {
+                let leaked = context_data.iterators.end_call()?;
+                Some((context_data.hooks.clone()?, leaked))
+            });
+            // Not holding the lock, so hooks can do whatever they need to
+            if let Some((hooks, leaked)) = leaked {
+                hooks.on_leaked_iterators(&leaked);
+            }
+        }
+    }
+
     pub fn with_querier_from_context<C, T>(&self, callback: C) -> VmResult<T>
     where
         C: FnOnce(&mut Q) -> VmResult<T>,
@@ -224,6 +307,115 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
         });
     }
 
+    pub fn set_hooks(&self, hooks: Option<Arc<dyn VmHooks>>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.hooks = hooks;
+        })
+    }
+
+    /// Sets the percentage of the gas limit at which [`VmHooks::on_gas_warning`] is called
+    /// and allows the warning to be sent again.
+    pub fn set_gas_warning_threshold(&self, percent: Option<u8>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.gas_warning_percent = percent;
+            context_data.gas_warning_sent = false;
+        })
+    }
+
+    /// Runs calls of `query` against a separate gas meter with `limit` per call, see
+    /// [`Instance::set_query_gas_limit`](crate::Instance::set_query_gas_limit), and
+    /// resets the gas used by queries.
+    pub fn set_query_gas_limit(&self, limit: Option<u64>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.query_gas = limit.map(|limit| QueryGasReport {
+                limit,
+                used: 0,
+                queries: 0,
+            });
+        })
+    }
+
+    pub fn query_gas_report(&self) -> Option<QueryGasReport> {
+        self.with_context_data(|context_data| context_data.query_gas)
+    }
+
+    /// Adds a call of `query` that used `used` gas to the query gas report, if queries are
+    /// metered separately.
+    pub fn record_query_gas(&self, used: u64) {
+        self.with_context_data_mut(|context_data| {
+            if let Some(report) = &mut context_data.query_gas {
+                report.used = report.used.saturating_add(used);
+                report.queries += 1;
+            }
+        })
+    }
+
+    /// Calls [`VmHooks::on_gas_warning`] if the gas used passed the warning threshold
+    /// for the first time. Gas used by Wasm execution is only known when the contract
+    /// calls into the host and when a call into the contract returns, so this is checked
+    /// at these points.
+    pub fn check_gas_warning(&self) {
+        let gas_left = self.get_gas_left();
+        let warning = self.with_context_data_mut(|context_data| {
+            let threshold_percent = context_data.gas_warning_percent?;
+            if context_data.gas_warning_sent {
+                return None;
+            }
+            let hooks = context_data.hooks.clone()?;
+            let limit = context_data.gas_state.gas_limit;
+            // Externally used gas is subtracted from the gas left, see `process_gas_info`
+            let used = limit.saturating_sub(gas_left);
+            if u128::from(used) * 100 < u128::from(limit) * u128::from(threshold_percent) {
+                return None;
+            }
+            context_data.gas_warning_sent = true;
+            let warning = GasWarning {
+                limit,
+                used,
+                threshold_percent,
+            };
+            Some((hooks, warning))
+        });
+        // Not holding the lock, so hooks can do whatever they need to
+        if let Some((hooks, warning)) = warning {
+            hooks.on_gas_warning(&warning);
+        }
+    }
+
+    /// Charges `surcharge.gas_per_page` for every page of linear memory beyond
+    /// `surcharge.baseline_pages`. Pages are only charged once per instance.
+    pub fn set_memory_surcharge(&self, surcharge: Option<MemorySurcharge>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.memory_surcharge = surcharge;
+        })
+    }
+
+    /// Charges the [`MemorySurcharge`] for pages that were not charged before. Like the
+    /// gas warning, this is checked when the contract calls into the host and when a call
+    /// into the contract returns. Linear memory never shrinks, so the size at these points
+    /// is the peak so far.
+    pub fn charge_memory_surcharge(&self) -> VmResult<()> {
+        let pages = self.memory().size().0;
+        let cost = self.with_context_data_mut(|context_data| {
+            let surcharge = context_data.memory_surcharge?;
+            let charged_up_to = surcharge
+                .baseline_pages
+                .max(context_data.memory_surcharged_pages);
+            if pages <= charged_up_to {
+                return None;
+            }
+            context_data.memory_surcharged_pages = pages;
+            let cost = u64::from(pages - charged_up_to).saturating_mul(surcharge.gas_per_page);
+            let gas_state = &mut context_data.gas_state;
+            gas_state.memory_surcharge = gas_state.memory_surcharge.saturating_add(cost);
+            Some(cost)
+        });
+        match cost {
+            Some(cost) => self.decrease_gas_left(cost),
+            None => Ok(()),
+        }
+    }
+
     /// Returns true iff the storage is set to readonly mode
     pub fn is_storage_readonly(&self) -> bool {
         self.with_context_data(|context_data| context_data.storage_readonly)
@@ -235,6 +427,33 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
         })
     }
 
+    /// Attributes the gas used so far to its origins. Everything not charged by host
+    /// functions or for memory was used by executing Wasm.
+    pub fn gas_breakdown(&self) -> GasBreakdown {
+        let gas_left = self.get_gas_left();
+        self.with_gas_state(|state| {
+            // Externally used gas is subtracted from the gas left, see `process_gas_info`
+            let used = state.gas_limit.saturating_sub(gas_left);
+            let host = [
+                state.storage_gas,
+                state.query_gas,
+                state.crypto_gas,
+                state.address_gas,
+                state.memory_surcharge,
+            ]
+            .iter()
+            .fold(0u64, |sum, gas| sum.saturating_add(*gas));
+            GasBreakdown {
+                wasm: used.saturating_sub(host),
+                storage: state.storage_gas,
+                query: state.query_gas,
+                crypto: state.crypto_gas,
+                address: state.address_gas,
+                memory: state.memory_surcharge,
+            }
+        })
+    }
+
     pub fn get_gas_left(&self) -> u64 {
         self.with_wasmer_instance(|instance| {
             Ok(match get_remaining_points(instance) {
@@ -256,7 +475,6 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
     /// Decreases gas left by the given amount.
     /// If the amount exceeds the available gas, the remaining gas is set to 0 and
     /// an VmError::GasDepletion error is returned.
-    #[allow(unused)] // used in tests
     pub fn decrease_gas_left(&self, amount: u64) -> VmResult<()> {
         self.with_wasmer_instance(|instance| {
             let remaining = match get_remaining_points(instance) {
@@ -304,6 +522,7 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
     /// Returns the original storage and querier as owned instances, and closes any remaining
     /// iterators. This is meant to be called when recycling the instance.
     pub fn move_out(&self) -> (Option<S>, Option<Q>) {
+        self.end_call();
         self.with_context_data_mut(|context_data| {
             (context_data.storage.take(), context_data.querier.take())
         })
@@ -315,8 +534,18 @@ pub struct ContextData<S: Storage, Q: Querier> {
     storage: Option<S>,
     storage_readonly: bool,
     querier: Option<Q>,
+    #[cfg(feature = "iterator")]
+    iterators: IteratorHandles,
     /// A non-owning link to the wasmer instance
     wasmer_instance: Option<NonNull<WasmerInstance>>,
+    hooks: Option<Arc<dyn VmHooks>>,
+    gas_warning_percent: Option<u8>,
+    gas_warning_sent: bool,
+    /// Only set if queries are metered separately
+    query_gas: Option<QueryGasReport>,
+    memory_surcharge: Option<MemorySurcharge>,
+    /// The number of pages the memory surcharge was charged for so far
+    memory_surcharged_pages: u32,
 }
 
 impl<S: Storage, Q: Querier> ContextData<S, Q> {
@@ -326,19 +555,32 @@ impl<S: Storage, Q: Querier> ContextData<S, Q> {
             storage: None,
             storage_readonly: true,
             querier: None,
+            #[cfg(feature = "iterator")]
+            iterators: IteratorHandles::new(),
             wasmer_instance: None,
+            hooks: None,
+            gas_warning_percent: None,
+            gas_warning_sent: false,
+            query_gas: None,
+            memory_surcharge: None,
+            memory_surcharged_pages: 0,
         }
     }
 }
 
+/// Charges the gas used by a host function and attributes it to `category`. A charge
+/// exceeding the gas left is attributed with the gas that was left.
 pub fn process_gas_info<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
+    category: GasCategory,
     info: GasInfo,
 ) -> VmResult<()> {
     let gas_left = env.get_gas_left();
 
     let new_limit = env.with_gas_state_mut(|gas_state| {
         gas_state.externally_used_gas += info.externally_used;
+        let charged = info.cost.saturating_add(info.externally_used).min(gas_left);
+        gas_state.charge_category(category, charged);
         // These lines reduce the amount of gas available to wasmer
         // so it can not consume gas that was consumed externally.
         gas_left
@@ -352,6 +594,8 @@ pub fn process_gas_info<A: BackendApi, S: Storage, Q: Querier>(
     if info.externally_used + info.cost > gas_left {
         Err(VmError::gas_depletion())
     } else {
+        env.charge_memory_surcharge()?;
+        env.check_gas_warning();
         Ok(())
     }
 }
@@ -465,17 +709,17 @@ mod tests {
         assert_eq!(env.get_gas_left(), 100);
 
         // Consume all the Gas that we allocated
-        process_gas_info(&env, GasInfo::with_cost(70)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(70)).unwrap();
         assert_eq!(env.get_gas_left(), 30);
-        process_gas_info(&env, GasInfo::with_cost(4)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(4)).unwrap();
         assert_eq!(env.get_gas_left(), 26);
-        process_gas_info(&env, GasInfo::with_cost(6)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(6)).unwrap();
         assert_eq!(env.get_gas_left(), 20);
-        process_gas_info(&env, GasInfo::with_cost(20)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(20)).unwrap();
         assert_eq!(env.get_gas_left(), 0);
 
         // Using one more unit of gas triggers a failure
-        match process_gas_info(&env, GasInfo::with_cost(1)).unwrap_err() {
+        match process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(1)).unwrap_err() {
             VmError::GasDepletion { .. } => {}
             err => panic!("unexpected error: {:?}", err),
         }
@@ -487,22 +731,99 @@ mod tests {
         assert_eq!(env.get_gas_left(), 100);
 
         // Consume all the Gas that we allocated
-        process_gas_info(&env, GasInfo::with_externally_used(70)).unwrap();
+        process_gas_info(
+            &env,
+            GasCategory::Storage,
+            GasInfo::with_externally_used(70),
+        )
+        .unwrap();
         assert_eq!(env.get_gas_left(), 30);
-        process_gas_info(&env, GasInfo::with_externally_used(4)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_externally_used(4)).unwrap();
         assert_eq!(env.get_gas_left(), 26);
-        process_gas_info(&env, GasInfo::with_externally_used(6)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_externally_used(6)).unwrap();
         assert_eq!(env.get_gas_left(), 20);
-        process_gas_info(&env, GasInfo::with_externally_used(20)).unwrap();
+        process_gas_info(
+            &env,
+            GasCategory::Storage,
+            GasInfo::with_externally_used(20),
+        )
+        .unwrap();
         assert_eq!(env.get_gas_left(), 0);
 
         // Using one more unit of gas triggers a failure
-        match process_gas_info(&env, GasInfo::with_externally_used(1)).unwrap_err() {
+        match process_gas_info(&env, GasCategory::Storage, GasInfo::with_externally_used(1))
+            .unwrap_err()
+        {
             VmError::GasDepletion { .. } => {}
             err => panic!("unexpected error: {:?}", err),
         }
     }
 
+    #[derive(Default)]
+    struct RecordingHooks {
+        warnings: std::sync::Mutex<Vec<GasWarning>>,
+    }
+
+    impl VmHooks for RecordingHooks {
+        fn on_gas_warning(&self, warning: &GasWarning) {
+            self.warnings.lock().unwrap().push(*warning);
+        }
+    }
+
+    #[test]
+    fn process_gas_info_sends_gas_warning() {
+        let (env, _instance) = make_instance(100);
+        let hooks = Arc::new(RecordingHooks::default());
+        env.set_hooks(Some(hooks.clone()));
+        env.set_gas_warning_threshold(Some(80));
+
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(50)).unwrap();
+        process_gas_info(
+            &env,
+            GasCategory::Storage,
+            GasInfo::with_externally_used(29),
+        )
+        .unwrap();
+        assert_eq!(hooks.warnings.lock().unwrap().len(), 0);
+
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(1)).unwrap();
+        assert_eq!(
+            *hooks.warnings.lock().unwrap(),
+            [GasWarning {
+                limit: 100,
+                used: 80,
+                threshold_percent: 80,
+            }]
+        );
+
+        // Sent only once
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(10)).unwrap();
+        assert_eq!(hooks.warnings.lock().unwrap().len(), 1);
+
+        // Until the threshold is set again
+        env.set_gas_warning_threshold(Some(90));
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(1)).unwrap();
+        let warnings = hooks.warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!((warnings[1].used, warnings[1].threshold_percent), (91, 90));
+    }
+
+    #[test]
+    fn process_gas_info_without_gas_warning() {
+        let (env, _instance) = make_instance(100);
+        let hooks = Arc::new(RecordingHooks::default());
+
+        // Threshold without hooks
+        env.set_gas_warning_threshold(Some(10));
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(50)).unwrap();
+
+        // Hooks without threshold
+        env.set_gas_warning_threshold(None);
+        env.set_hooks(Some(hooks.clone()));
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(50)).unwrap();
+        assert_eq!(hooks.warnings.lock().unwrap().len(), 0);
+    }
+
     #[test]
     fn process_gas_info_works_for_cost_and_externally_used() {
         let (env, _instance) = make_instance(100);
@@ -511,26 +832,26 @@ mod tests {
         assert_eq!(gas_state.gas_limit, 100);
         assert_eq!(gas_state.externally_used_gas, 0);
 
-        process_gas_info(&env, GasInfo::new(17, 4)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::new(17, 4)).unwrap();
         assert_eq!(env.get_gas_left(), 79);
         let gas_state = env.with_gas_state(|gas_state| gas_state.clone());
         assert_eq!(gas_state.gas_limit, 100);
         assert_eq!(gas_state.externally_used_gas, 4);
 
-        process_gas_info(&env, GasInfo::new(9, 0)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::new(9, 0)).unwrap();
         assert_eq!(env.get_gas_left(), 70);
         let gas_state = env.with_gas_state(|gas_state| gas_state.clone());
         assert_eq!(gas_state.gas_limit, 100);
         assert_eq!(gas_state.externally_used_gas, 4);
 
-        process_gas_info(&env, GasInfo::new(0, 70)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::new(0, 70)).unwrap();
         assert_eq!(env.get_gas_left(), 0);
         let gas_state = env.with_gas_state(|gas_state| gas_state.clone());
         assert_eq!(gas_state.gas_limit, 100);
         assert_eq!(gas_state.externally_used_gas, 74);
 
         // More cost fail but do not change stats
-        match process_gas_info(&env, GasInfo::new(1, 0)).unwrap_err() {
+        match process_gas_info(&env, GasCategory::Storage, GasInfo::new(1, 0)).unwrap_err() {
             VmError::GasDepletion { .. } => {}
             err => panic!("unexpected error: {:?}", err),
         }
@@ -540,7 +861,7 @@ mod tests {
         assert_eq!(gas_state.externally_used_gas, 74);
 
         // More externally used fails and changes stats
-        match process_gas_info(&env, GasInfo::new(0, 1)).unwrap_err() {
+        match process_gas_info(&env, GasCategory::Storage, GasInfo::new(0, 1)).unwrap_err() {
             VmError::GasDepletion { .. } => {}
             err => panic!("unexpected error: {:?}", err),
         }
@@ -555,7 +876,11 @@ mod tests {
         // with_externally_used
         {
             let (env, _instance) = make_instance(100);
-            let result = process_gas_info(&env, GasInfo::with_externally_used(120));
+            let result = process_gas_info(
+                &env,
+                GasCategory::Storage,
+                GasInfo::with_externally_used(120),
+            );
             match result.unwrap_err() {
                 VmError::GasDepletion { .. } => {}
                 err => panic!("unexpected error: {:?}", err),
@@ -569,7 +894,7 @@ mod tests {
         // with_cost
         {
             let (env, _instance) = make_instance(100);
-            let result = process_gas_info(&env, GasInfo::with_cost(120));
+            let result = process_gas_info(&env, GasCategory::Storage, GasInfo::with_cost(120));
             match result.unwrap_err() {
                 VmError::GasDepletion { .. } => {}
                 err => panic!("unexpected error: {:?}", err),
@@ -587,22 +912,34 @@ mod tests {
         assert_eq!(env.get_gas_left(), 100);
 
         // Some gas was consumed externally
-        process_gas_info(&env, GasInfo::with_externally_used(50)).unwrap();
+        process_gas_info(
+            &env,
+            GasCategory::Storage,
+            GasInfo::with_externally_used(50),
+        )
+        .unwrap();
         assert_eq!(env.get_gas_left(), 50);
-        process_gas_info(&env, GasInfo::with_externally_used(4)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_externally_used(4)).unwrap();
         assert_eq!(env.get_gas_left(), 46);
 
         // Consume 20 gas directly in wasmer
         env.decrease_gas_left(20).unwrap();
         assert_eq!(env.get_gas_left(), 26);
 
-        process_gas_info(&env, GasInfo::with_externally_used(6)).unwrap();
+        process_gas_info(&env, GasCategory::Storage, GasInfo::with_externally_used(6)).unwrap();
         assert_eq!(env.get_gas_left(), 20);
-        process_gas_info(&env, GasInfo::with_externally_used(20)).unwrap();
+        process_gas_info(
+            &env,
+            GasCategory::Storage,
+            GasInfo::with_externally_used(20),
+        )
+        .unwrap();
         assert_eq!(env.get_gas_left(), 0);
 
         // Using one more unit of gas triggers a failure
-        match process_gas_info(&env, GasInfo::with_externally_used(1)).unwrap_err() {
+        match process_gas_info(&env, GasCategory::Storage, GasInfo::with_externally_used(1))
+            .unwrap_err()
+        {
             VmError::GasDepletion { .. } => {}
             err => panic!("unexpected error: {:?}", err),
         }
@@ -27,11 +27,16 @@ pub enum CommunicationError {
     /// Whenever UTF-8 bytes cannot be decoded into a unicode string, e.g. in String::from_utf8 or str::from_utf8.
     #[error("Cannot decode UTF8 bytes into string: {}", msg)]
     InvalidUtf8 { msg: String },
+    /// The handle is from an earlier call, whose iterators were removed when it ended.
+    #[error("Iterator {} was not created in the current call", handle)]
+    IteratorExpired { handle: u32 },
     #[error("Region length too big. Got {}, limit {}", length, max_length)]
     // Note: this only checks length, not capacity
     RegionLengthTooBig { length: usize, max_length: usize },
     #[error("Region too small. Got {}, required {}", size, required)]
     RegionTooSmall { size: usize, required: usize },
+    #[error("Too many iterators created in one call. Limit: {}", limit)]
+    TooManyIterators { limit: usize },
     #[error("Got a zero Wasm address")]
     ZeroAddress {},
 }
@@ -56,6 +61,11 @@ impl CommunicationError {
         }
     }
 
+    #[allow(dead_code)]
+    pub(crate) fn iterator_expired(handle: u32) -> Self {
+        CommunicationError::IteratorExpired { handle }
+    }
+
     pub(crate) fn region_length_too_big(length: usize, max_length: usize) -> Self {
         CommunicationError::RegionLengthTooBig { length, max_length }
     }
@@ -64,6 +74,11 @@ impl CommunicationError {
         CommunicationError::RegionTooSmall { size, required }
     }
 
+    #[allow(dead_code)]
+    pub(crate) fn too_many_iterators(limit: usize) -> Self {
+        CommunicationError::TooManyIterators { limit }
+    }
+
     pub(crate) fn zero_address() -> Self {
         CommunicationError::ZeroAddress {}
     }
@@ -105,6 +120,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn iterator_expired() {
+        let error = CommunicationError::iterator_expired(65537);
+        match error {
+            CommunicationError::IteratorExpired { handle, .. } => assert_eq!(handle, 65537),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     #[test]
     fn region_length_too_big_works() {
         let error = CommunicationError::region_length_too_big(50, 20);
@@ -131,6 +155,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn too_many_iterators() {
+        let error = CommunicationError::too_many_iterators(12);
+        match error {
+            CommunicationError::TooManyIterators { limit, .. } => assert_eq!(limit, 12),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     #[test]
     fn zero_address() {
         let error = CommunicationError::zero_address();
@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// An import of a Wasm module that the host cannot satisfy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportIssue {
+    pub module: String,
+    pub name: String,
+    /// The type of the import as declared by the contract
+    pub expected: String,
+    /// The type of the export provided by the host. `None` if the host does not provide it.
+    pub provided: Option<String>,
+    /// The capability that makes the host provide this import, if there is one
+    pub capability: Option<String>,
+}
+
+impl fmt::Display for ImportIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}: expected {}",
+            self.module, self.name, self.expected
+        )?;
+        match &self.provided {
+            Some(provided) => write!(f, ", provided {}", provided)?,
+            None => write!(f, ", not provided")?,
+        }
+        if let Some(capability) = &self.capability {
+            write!(f, " (requires capability '{}')", capability)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_issue_displays() {
+        let issue = ImportIssue {
+            module: "env".to_string(),
+            name: "db_scan".to_string(),
+            expected: "[I32, I32, I32] -> [I32]".to_string(),
+            provided: None,
+            capability: Some("iterator".to_string()),
+        };
+        assert_eq!(
+            issue.to_string(),
+            "env.db_scan: expected [I32, I32, I32] -> [I32], not provided (requires capability 'iterator')"
+        );
+
+        let issue = ImportIssue {
+            module: "env".to_string(),
+            name: "db_read".to_string(),
+            expected: "[] -> []".to_string(),
+            provided: Some("[I32] -> [I32]".to_string()),
+            capability: None,
+        };
+        assert_eq!(
+            issue.to_string(),
+            "env.db_read: expected [] -> [], provided [I32] -> [I32]"
+        );
+    }
+}
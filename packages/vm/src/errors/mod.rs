@@ -1,8 +1,10 @@
 mod communication_error;
+mod import_issue;
 mod region_validation_error;
 mod vm_error;
 
 pub use communication_error::CommunicationError;
+pub use import_issue::ImportIssue;
 pub use region_validation_error::RegionValidationError;
 pub use vm_error::VmError;
 
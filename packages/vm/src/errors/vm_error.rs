@@ -6,7 +6,9 @@ use thiserror::Error;
 use cosmwasm_crypto::CryptoError;
 
 use super::communication_error::CommunicationError;
+use super::import_issue::ImportIssue;
 use crate::backend::BackendError;
+use crate::instance::GasBreakdown;
 
 #[derive(Error, Debug)]
 #[non_exhaustive]
@@ -50,8 +52,13 @@ pub enum VmError {
         #[cfg(feature = "backtraces")]
         backtrace: Backtrace,
     },
-    #[error("Ran out of gas during contract execution")]
+    #[error(
+        "Ran out of gas during contract execution{}",
+        display_breakdown(breakdown)
+    )]
     GasDepletion {
+        /// Where the gas went. This is set for errors returned from calls into the contract.
+        breakdown: Option<GasBreakdown>,
         #[cfg(feature = "backtraces")]
         backtrace: Backtrace,
     },
@@ -68,6 +75,14 @@ pub enum VmError {
         #[cfg(feature = "backtraces")]
         backtrace: Backtrace,
     },
+    /// The module has imports the host cannot satisfy. Unlike [`VmError::InstantiationErr`],
+    /// this lists all offending imports, not only the first one.
+    #[error("Error resolving imports: {}", display_issues(issues))]
+    ImportErr {
+        issues: Vec<ImportIssue>,
+        #[cfg(feature = "backtraces")]
+        backtrace: Backtrace,
+    },
     #[error("Hash doesn't match stored data")]
     IntegrityErr {
         #[cfg(feature = "backtraces")]
@@ -190,6 +205,7 @@ impl VmError {
 
     pub(crate) fn gas_depletion() -> Self {
         VmError::GasDepletion {
+            breakdown: None,
             #[cfg(feature = "backtraces")]
             backtrace: Backtrace::capture(),
         }
@@ -211,6 +227,14 @@ impl VmError {
         }
     }
 
+    pub(crate) fn import_err(issues: Vec<ImportIssue>) -> Self {
+        VmError::ImportErr {
+            issues,
+            #[cfg(feature = "backtraces")]
+            backtrace: Backtrace::capture(),
+        }
+    }
+
     pub(crate) fn integrity_err() -> Self {
         VmError::IntegrityErr {
             #[cfg(feature = "backtraces")]
@@ -302,6 +326,29 @@ impl VmError {
     }
 }
 
+fn display_issues(issues: &[ImportIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn display_breakdown(breakdown: &Option<GasBreakdown>) -> String {
+    match breakdown {
+        Some(breakdown) => format!(
+            " (gas used by wasm: {}, storage: {}, query: {}, crypto: {}, address: {}, memory: {})",
+            breakdown.wasm,
+            breakdown.storage,
+            breakdown.query,
+            breakdown.crypto,
+            breakdown.address,
+            breakdown.memory
+        ),
+        None => String::new(),
+    }
+}
+
 impl From<BackendError> for VmError {
     fn from(original: BackendError) -> Self {
         match original {
@@ -442,9 +489,30 @@ mod tests {
     fn gas_depletion_works() {
         let error = VmError::gas_depletion();
         match error {
-            VmError::GasDepletion { .. } => {}
+            VmError::GasDepletion { breakdown, .. } => assert_eq!(breakdown, None),
             e => panic!("Unexpected error: {:?}", e),
         }
+        assert_eq!(
+            VmError::gas_depletion().to_string(),
+            "Ran out of gas during contract execution"
+        );
+
+        let error = VmError::GasDepletion {
+            breakdown: Some(GasBreakdown {
+                wasm: 1000,
+                storage: 200,
+                query: 30,
+                crypto: 0,
+                address: 4,
+                memory: 5,
+            }),
+            #[cfg(feature = "backtraces")]
+            backtrace: Backtrace::capture(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Ran out of gas during contract execution (gas used by wasm: 1000, storage: 200, query: 30, crypto: 0, address: 4, memory: 5)"
+        );
     }
 
     #[test]
@@ -468,6 +536,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn import_err_works() {
+        let issue = ImportIssue {
+            module: "env".to_string(),
+            name: "foo".to_string(),
+            expected: "[] -> []".to_string(),
+            provided: None,
+            capability: None,
+        };
+        let error = VmError::import_err(vec![issue.clone(), issue.clone()]);
+        assert_eq!(
+            error.to_string(),
+            "Error resolving imports: env.foo: expected [] -> [], not provided; env.foo: expected [] -> [], not provided"
+        );
+        match error {
+            VmError::ImportErr { issues, .. } => assert_eq!(issues, [issue.clone(), issue]),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     #[test]
     fn integrity_err_works() {
         let error = VmError::integrity_err();
@@ -13,6 +13,83 @@ pub fn features_from_csv(csv: &str) -> HashSet<String> {
         .collect()
 }
 
+/// A named set of capabilities a chain enables, to be used as the supported
+/// features of a cache when checking contracts against that chain.
+///
+/// Capabilities that this version of the VM does not know (e.g. `cosmwasm_1_1`)
+/// are part of the profiles anyway, so contracts requiring them are accepted like
+/// on the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityProfile {
+    pub name: &'static str,
+    pub capabilities: &'static [&'static str],
+}
+
+impl CapabilityProfile {
+    /// The capabilities in the form expected by `CacheOptions::supported_features`.
+    pub fn capabilities(&self) -> HashSet<String> {
+        self.capabilities.iter().map(|c| c.to_string()).collect()
+    }
+}
+
+/// The default capabilities of wasmd 0.53 without any chain specific extensions.
+pub const VANILLA_WASMD_0_53: CapabilityProfile = CapabilityProfile {
+    name: "vanilla-wasmd-0.53",
+    capabilities: &[
+        "iterator",
+        "staking",
+        "stargate",
+        "cosmwasm_1_1",
+        "cosmwasm_1_2",
+        "cosmwasm_1_3",
+        "cosmwasm_1_4",
+        "cosmwasm_2_0",
+        "cosmwasm_2_1",
+    ],
+};
+
+/// Osmosis v26, which does not enable the capabilities of CosmWasm 2.1.
+pub const OSMOSIS_V26: CapabilityProfile = CapabilityProfile {
+    name: "osmosis-v26",
+    capabilities: &[
+        "iterator",
+        "staking",
+        "stargate",
+        "cosmwasm_1_1",
+        "cosmwasm_1_2",
+        "cosmwasm_1_3",
+        "cosmwasm_1_4",
+        "cosmwasm_2_0",
+        "osmosis",
+    ],
+};
+
+/// Neutron v4, which has no staking module but supports its custom bindings.
+pub const NEUTRON_V4: CapabilityProfile = CapabilityProfile {
+    name: "neutron-v4",
+    capabilities: &[
+        "iterator",
+        "stargate",
+        "cosmwasm_1_1",
+        "cosmwasm_1_2",
+        "cosmwasm_1_3",
+        "cosmwasm_1_4",
+        "cosmwasm_2_0",
+        "neutron",
+    ],
+};
+
+/// All capability profiles bundled with the VM.
+pub const CAPABILITY_PROFILES: &[CapabilityProfile] =
+    &[VANILLA_WASMD_0_53, OSMOSIS_V26, NEUTRON_V4];
+
+/// Looks up a bundled profile by name, e.g. `osmosis-v26`.
+pub fn capability_profile(name: &str) -> Option<&'static CapabilityProfile> {
+    CAPABILITY_PROFILES
+        .iter()
+        .find(|profile| profile.name == name)
+}
+
 /// Implementation for check_wasm, based on static analysis of the bytecode.
 /// This is used for code upload, to perform check before compiling the Wasm.
 pub fn required_features_from_module(module: &impl ExportInfo) -> HashSet<String> {
@@ -58,6 +135,27 @@ mod tests {
         assert!(set.contains("b"));
     }
 
+    #[test]
+    fn capability_profile_works() {
+        let profile = capability_profile("osmosis-v26").unwrap();
+        assert_eq!(profile, &OSMOSIS_V26);
+        let capabilities = profile.capabilities();
+        assert!(capabilities.contains("iterator"));
+        assert!(capabilities.contains("staking"));
+        assert!(capabilities.contains("stargate"));
+
+        assert!(!NEUTRON_V4.capabilities().contains("staking"));
+        assert_eq!(
+            capability_profile("vanilla-wasmd-0.53"),
+            Some(&VANILLA_WASMD_0_53)
+        );
+        assert_eq!(capability_profile("unknown"), None);
+
+        // Names are unique
+        let names: HashSet<_> = CAPABILITY_PROFILES.iter().map(|p| p.name).collect();
+        assert_eq!(names.len(), CAPABILITY_PROFILES.len());
+    }
+
     #[test]
     fn required_features_from_module_works() {
         let wasm = wat::parse_str(
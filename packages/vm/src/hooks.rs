@@ -0,0 +1,36 @@
+/// Callbacks an embedder can register with [`Instance::set_hooks`](crate::Instance::set_hooks)
+/// to observe the execution of a contract, e.g. to give feedback in a simulation frontend.
+/// All methods have empty default implementations.
+///
+/// Hooks are called synchronously while the contract is executing and must not call
+/// back into the instance.
+pub trait VmHooks: Send + Sync {
+    /// Called once per instance when the gas used passes the threshold set with
+    /// [`Instance::set_gas_warning_threshold`](crate::Instance::set_gas_warning_threshold).
+    fn on_gas_warning(&self, _warning: &GasWarning) {}
+
+    /// Called when a call into the contract ends with iterators created by `db_scan`
+    /// that were not read to the end. They are removed anyway, but leaking them every
+    /// call can hint at a contract reading more than it needs.
+    fn on_leaked_iterators(&self, _leaked: &LeakedIterators) {}
+}
+
+/// Passed to [`VmHooks::on_gas_warning`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GasWarning {
+    /// The gas limit of the instance
+    pub limit: u64,
+    /// The gas used so far, metered internally and externally
+    pub used: u64,
+    /// The threshold in percent of the gas limit that was passed
+    pub threshold_percent: u8,
+}
+
+/// Passed to [`VmHooks::on_leaked_iterators`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LeakedIterators {
+    /// The number of iterators created in the call
+    pub created: usize,
+    /// The number of iterators that were not read to the end
+    pub leaked: usize,
+}
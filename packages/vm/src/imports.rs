@@ -15,7 +15,7 @@ use cosmwasm_std::Order;
 
 use crate::backend::{BackendApi, BackendError, Querier, Storage};
 use crate::conversion::{ref_to_u32, to_u32};
-use crate::environment::{process_gas_info, Environment};
+use crate::environment::{process_gas_info, Environment, GasCategory};
 use crate::errors::{CommunicationError, VmError, VmResult};
 #[cfg(feature = "iterator")]
 use crate::memory::maybe_read_region;
@@ -70,7 +70,7 @@ pub fn do_db_read<A: BackendApi, S: Storage, Q: Querier>(
     let key = read_region(&env.memory(), key_ptr, MAX_LENGTH_DB_KEY)?;
 
     let (result, gas_info) = env.with_storage_from_context::<_, _>(|store| Ok(store.get(&key)))?;
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Storage, gas_info)?;
     let value = result?;
 
     let out_data = match value {
@@ -95,7 +95,7 @@ pub fn do_db_write<A: BackendApi, S: Storage, Q: Querier>(
 
     let (result, gas_info) =
         env.with_storage_from_context::<_, _>(|store| Ok(store.set(&key, &value)))?;
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Storage, gas_info)?;
     result?;
 
     Ok(())
@@ -113,7 +113,7 @@ pub fn do_db_remove<A: BackendApi, S: Storage, Q: Querier>(
 
     let (result, gas_info) =
         env.with_storage_from_context::<_, _>(|store| Ok(store.remove(&key)))?;
-    process_gas_info(env, gas_info)?;
+    process_gas_info(env, GasCategory::Storage, gas_info)?;
     result?;
 
     Ok(())
@@ -134,7 +134,7 @@ pub fn do_addr_validate<A: BackendApi, S: Storage, Q: Querier>(
     };
 
     let (result, gas_info) = env.api.canonical_address(&source_string);
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Address, gas_info)?;
     match result {
         Ok(_canonical) => Ok(0),
         Err(BackendError::UserErr { msg, .. }) => {
@@ -160,7 +160,7 @@ pub fn do_addr_canonicalize<A: BackendApi, S: Storage, Q: Querier>(
     };
 
     let (result, gas_info) = env.api.canonical_address(&source_string);
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Address, gas_info)?;
     match result {
         Ok(canonical) => {
             write_region(&env.memory(), destination_ptr, canonical.as_slice())?;
@@ -181,7 +181,7 @@ pub fn do_addr_humanize<A: BackendApi, S: Storage, Q: Querier>(
     let canonical = read_region(&env.memory(), source_ptr, MAX_LENGTH_CANONICAL_ADDRESS)?;
 
     let (result, gas_info) = env.api.human_address(&canonical);
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Address, gas_info)?;
     match result {
         Ok(human) => {
             write_region(&env.memory(), destination_ptr, human.as_bytes())?;
@@ -206,7 +206,7 @@ pub fn do_secp256k1_verify<A: BackendApi, S: Storage, Q: Querier>(
 
     let result = secp256k1_verify(&hash, &signature, &pubkey);
     let gas_info = GasInfo::with_cost(env.gas_config.secp256k1_verify_cost);
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Crypto, gas_info)?;
     Ok(result.map_or_else(
         |err| match err {
             CryptoError::InvalidHashFormat { .. }
@@ -236,7 +236,7 @@ pub fn do_secp256k1_recover_pubkey<A: BackendApi, S: Storage, Q: Querier>(
 
     let result = secp256k1_recover_pubkey(&hash, &signature, recover_param);
     let gas_info = GasInfo::with_cost(env.gas_config.secp256k1_recover_pubkey_cost);
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Crypto, gas_info)?;
     match result {
         Ok(pubkey) => {
             let pubkey_ptr = write_to_contract::<A, S, Q>(env, pubkey.as_ref())?;
@@ -266,7 +266,7 @@ pub fn do_ed25519_verify<A: BackendApi, S: Storage, Q: Querier>(
 
     let result = ed25519_verify(&message, &signature, &pubkey);
     let gas_info = GasInfo::with_cost(env.gas_config.ed25519_verify_cost);
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Crypto, gas_info)?;
     Ok(result.map_or_else(
         |err| match err {
             CryptoError::InvalidPubkeyFormat { .. }
@@ -315,7 +315,7 @@ pub fn do_ed25519_batch_verify<A: BackendApi, S: Storage, Q: Querier>(
         env.gas_config.ed25519_batch_verify_cost
     } * signatures.len() as u64;
     let gas_info = GasInfo::with_cost(max(gas_cost, env.gas_config.ed25519_verify_cost));
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Crypto, gas_info)?;
     Ok(result.map_or_else(
         |err| match err {
             CryptoError::BatchErr { .. }
@@ -365,15 +365,27 @@ pub fn do_query_chain<A: BackendApi, S: Storage, Q: Querier>(
 ) -> VmResult<u32> {
     let request = read_region(&env.memory(), request_ptr, MAX_LENGTH_QUERY_CHAIN_REQUEST)?;
 
+    // Within a call of `query` with a query gas limit, this is the gas left of the query
     let gas_remaining = env.get_gas_left();
     let (result, gas_info) = env.with_querier_from_context::<_, _>(|querier| {
         Ok(querier.query_raw(&request, gas_remaining))
     })?;
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Query, gas_info)?;
     let serialized = to_vec(&result?)?;
     write_to_contract::<A, S, Q>(env, &serialized)
 }
 
+/// Returns the gas left for the current call in CosmWasm gas, i.e. before the host's
+/// gas multiplier is applied. Hosts limit the gas of a call to the gas left in the
+/// transaction, which makes this an upper bound for the work a contract can still do.
+///
+/// This is only available to contracts with the `gas_remaining` capability.
+pub fn do_gas_remaining<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+) -> VmResult<u64> {
+    Ok(env.get_gas_left())
+}
+
 #[cfg(feature = "iterator")]
 pub fn do_db_scan<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
@@ -387,12 +399,13 @@ pub fn do_db_scan<A: BackendApi, S: Storage, Q: Querier>(
         .try_into()
         .map_err(|_| CommunicationError::invalid_order(order))?;
 
+    env.with_iterators_mut(|iterators| iterators.ensure_capacity())?;
     let (result, gas_info) = env.with_storage_from_context::<_, _>(|store| {
         Ok(store.scan(start.as_deref(), end.as_deref(), order))
     })?;
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Storage, gas_info)?;
     let iterator_id = result?;
-    Ok(iterator_id)
+    env.with_iterators_mut(|iterators| iterators.insert(iterator_id))
 }
 
 #[cfg(feature = "iterator")]
@@ -400,12 +413,17 @@ pub fn do_db_next<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
     iterator_id: u32,
 ) -> VmResult<u32> {
+    let backend_id = env.with_iterators_mut(|iterators| iterators.get(iterator_id))?;
     let (result, gas_info) =
-        env.with_storage_from_context::<_, _>(|store| Ok(store.next(iterator_id)))?;
-    process_gas_info::<A, S, Q>(env, gas_info)?;
+        env.with_storage_from_context::<_, _>(|store| Ok(store.next(backend_id)))?;
+    process_gas_info::<A, S, Q>(env, GasCategory::Storage, gas_info)?;
 
+    let record = result?;
+    if record.is_none() {
+        env.with_iterators_mut(|iterators| iterators.finish(iterator_id))?;
+    }
     // Empty key will later be treated as _no more element_.
-    let (key, value) = result?.unwrap_or_else(|| (Vec::<u8>::new(), Vec::<u8>::new()));
+    let (key, value) = record.unwrap_or_else(|| (Vec::<u8>::new(), Vec::<u8>::new()));
 
     let out_data = encode_sections(&[key, value])?;
     write_to_contract::<A, S, Q>(env, &out_data)
@@ -550,6 +568,16 @@ mod tests {
         read_region(&env.memory(), region_ptr, 5000).unwrap()
     }
 
+    #[test]
+    fn do_gas_remaining_works() {
+        let api = MockApi::default();
+        let (env, _instance) = make_instance(api);
+        assert_eq!(do_gas_remaining(&env).unwrap(), TESTING_GAS_LIMIT);
+
+        env.decrease_gas_left(1234).unwrap();
+        assert_eq!(do_gas_remaining(&env).unwrap(), TESTING_GAS_LIMIT - 1234);
+    }
+
     #[test]
     fn do_db_read_works() {
         let api = MockApi::default();
@@ -1665,6 +1693,31 @@ mod tests {
         assert_eq!(parsed_again.amount, coins(INIT_AMOUNT, INIT_DENOM));
     }
 
+    #[test]
+    fn do_query_chain_charges_instance_with_query_gas_limit() {
+        let api = MockApi::default();
+        let (env, _instance) = make_instance(api);
+        env.set_query_gas_limit(Some(1_000_000_000));
+
+        let request: QueryRequest<Empty> = QueryRequest::Bank(BankQuery::AllBalances {
+            address: INIT_ADDR.to_string(),
+        });
+        let request_data = cosmwasm_std::to_vec(&request).unwrap();
+        let request_ptr = write_data(&env, &request_data);
+
+        leave_default_data(&env);
+
+        // Outside of calls of `query`, e.g. in an execution, queries use the gas of the instance
+        let breakdown_before_query = env.gas_breakdown();
+        let response_ptr = do_query_chain(&env, request_ptr).unwrap();
+        let query_result: cosmwasm_std::QuerierResult =
+            cosmwasm_std::from_slice(&force_read(&env, response_ptr)).unwrap();
+        assert!(query_result.unwrap().is_ok());
+        assert!(env.gas_breakdown().query > breakdown_before_query.query);
+        let report = env.query_gas_report().unwrap();
+        assert_eq!((report.used, report.queries), (0, 0));
+    }
+
     #[test]
     fn do_query_chain_fails_for_broken_request() {
         let api = MockApi::default();
@@ -1905,4 +1958,80 @@ mod tests {
             e => panic!("Unexpected error: {:?}", e),
         }
     }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn do_db_next_fails_for_iterator_of_ended_call() {
+        let api = MockApi::default();
+        let (env, _instance) = make_instance(api);
+        leave_default_data(&env);
+
+        let id = do_db_scan(&env, 0, 0, Order::Ascending.into()).unwrap();
+        env.end_call();
+
+        match do_db_next(&env, id).unwrap_err() {
+            VmError::CommunicationErr {
+                source: CommunicationError::IteratorExpired { handle },
+                ..
+            } => assert_eq!(handle, id),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        // The next call starts with a new generation of handles
+        let id = do_db_scan(&env, 0, 0, Order::Ascending.into()).unwrap();
+        assert_eq!(id, 1 << 16 | 1);
+        let kv_region_ptr = do_db_next(&env, id).unwrap();
+        assert_eq!(
+            force_read(&env, kv_region_ptr),
+            [KEY1, b"\0\0\0\x03", VALUE1, b"\0\0\0\x06"].concat()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn end_call_reports_leaked_iterators() {
+        use crate::hooks::{LeakedIterators, VmHooks};
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct RecordingHooks {
+            leaked: Mutex<Vec<LeakedIterators>>,
+        }
+
+        impl VmHooks for RecordingHooks {
+            fn on_leaked_iterators(&self, leaked: &LeakedIterators) {
+                self.leaked.lock().unwrap().push(*leaked);
+            }
+        }
+
+        let api = MockApi::default();
+        let (env, _instance) = make_instance(api);
+        leave_default_data(&env);
+        let hooks = Arc::new(RecordingHooks::default());
+        env.set_hooks(Some(hooks.clone()));
+
+        // Read to the end
+        let id = do_db_scan(&env, 0, 0, Order::Ascending.into()).unwrap();
+        for _ in 0..3 {
+            do_db_next(&env, id).unwrap();
+        }
+        env.end_call();
+        assert_eq!(hooks.leaked.lock().unwrap().len(), 0);
+
+        // One of two iterators is only read partially
+        let id = do_db_scan(&env, 0, 0, Order::Ascending.into()).unwrap();
+        do_db_next(&env, id).unwrap();
+        let id = do_db_scan(&env, 0, 0, Order::Descending.into()).unwrap();
+        for _ in 0..3 {
+            do_db_next(&env, id).unwrap();
+        }
+        env.end_call();
+        assert_eq!(
+            *hooks.leaked.lock().unwrap(),
+            [LeakedIterators {
+                created: 2,
+                leaked: 1
+            }]
+        );
+    }
 }
@@ -1,24 +1,28 @@
 use std::collections::{HashMap, HashSet};
 use std::ptr::NonNull;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
-use wasmer::{Exports, Function, ImportObject, Instance as WasmerInstance, Module, Val};
+use wasmer::{
+    Exports, ExternType, Function, ImportObject, Instance as WasmerInstance, Module, Val,
+};
 
 use crate::backend::{Backend, BackendApi, Querier, Storage};
 use crate::conversion::{ref_to_u32, to_u32};
-use crate::environment::Environment;
-use crate::errors::{CommunicationError, VmError, VmResult};
+use crate::environment::{Environment, GasState};
+use crate::errors::{CommunicationError, ImportIssue, VmError, VmResult};
 use crate::features::required_features_from_module;
+use crate::hooks::VmHooks;
 use crate::imports::{
     do_addr_canonicalize, do_addr_humanize, do_addr_validate, do_db_read, do_db_remove,
-    do_db_write, do_debug, do_ed25519_batch_verify, do_ed25519_verify, do_query_chain,
-    do_secp256k1_recover_pubkey, do_secp256k1_verify,
+    do_db_write, do_debug, do_ed25519_batch_verify, do_ed25519_verify, do_gas_remaining,
+    do_query_chain, do_secp256k1_recover_pubkey, do_secp256k1_verify,
 };
 #[cfg(feature = "iterator")]
 use crate::imports::{do_db_next, do_db_scan};
+use crate::instrumentation::Instrumentation;
 use crate::memory::{read_region, write_region};
 use crate::size::Size;
-use crate::wasm_backend::compile;
+use crate::wasm_backend::{compile_with_costs, CostTable};
 
 #[derive(Copy, Clone, Debug)]
 pub struct GasReport {
@@ -31,12 +35,71 @@ pub struct GasReport {
     /// The amount of gas that was spend and metered internally (i.e. by executing Wasm and calling
     /// API methods which are not metered externally)
     pub used_internally: u64,
+    /// The largest size of the contract's linear memory so far, in Wasm pages of 64 KiB.
+    /// Linear memory never shrinks, so this is its current size.
+    pub peak_memory_pages: u32,
+    /// The gas charged for memory according to [`Instance::set_memory_surcharge`], part of
+    /// `used_internally`
+    pub memory_surcharge: u64,
+    /// The gas used so far by origin
+    pub breakdown: GasBreakdown,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The gas used by an instance, attributed to what it was used for. Gas metered
+/// externally is attributed to the host function that caused it.
+///
+/// This is also part of the [`VmError::GasDepletion`](crate::VmError::GasDepletion)
+/// errors of calls into the contract, to tell why it ran out of gas.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasBreakdown {
+    /// Executing Wasm code, i.e. all gas not charged by host functions or for memory
+    pub wasm: u64,
+    /// Reading and writing storage, including iterators
+    pub storage: u64,
+    /// Querying the chain
+    pub query: u64,
+    /// Verifying signatures and recovering public keys
+    pub crypto: u64,
+    /// Validating and converting addresses
+    pub address: u64,
+    /// The memory surcharge, see [`Instance::set_memory_surcharge`]
+    pub memory: u64,
+}
+
+/// Additional gas charged for every page of linear memory a contract uses beyond a
+/// baseline, see [`Instance::set_memory_surcharge`]. This makes memory heavy contracts
+/// pay for memory even if they do little computation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemorySurcharge {
+    /// The number of pages of 64 KiB that are free
+    pub baseline_pages: u32,
+    /// The gas charged for every page beyond the baseline
+    pub gas_per_page: u64,
+}
+
+/// The gas used by queries that are metered separately from the other calls of an
+/// instance, see [`Instance::set_query_gas_limit`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QueryGasReport {
+    /// The gas every query can use
+    pub limit: u64,
+    /// The gas used by all queries so far, including the ones that ran out of gas
+    pub used: u64,
+    /// The number of calls of `query` so far
+    pub queries: u64,
+}
+
+#[derive(Clone, Debug)]
 pub struct InstanceOptions {
     pub gas_limit: u64,
     pub print_debug: bool,
+    /// A middleware compiled into the contract along with the host functions it imports,
+    /// e.g. for profiling. Only supported by [`Instance::from_code`].
+    pub instrumentation: Option<Arc<dyn Instrumentation>>,
+    /// The gas costs of Wasm operators, `None` for the default ones. Only used by
+    /// [`Instance::from_code`], which compiles the contract. Caches compile with
+    /// [`CacheOptions::wasm_costs`](crate::CacheOptions::wasm_costs) and reject other costs.
+    pub wasm_costs: Option<CostTable>,
 }
 
 pub struct Instance<A: BackendApi, S: Storage, Q: Querier> {
@@ -63,13 +126,42 @@ where
         options: InstanceOptions,
         memory_limit: Option<Size>,
     ) -> VmResult<Self> {
-        let module = compile(code, memory_limit, &[])?;
+        let default_costs = CostTable::default();
+        let costs = options.wasm_costs.as_ref().unwrap_or(&default_costs);
+        let instrumentation = match &options.instrumentation {
+            Some(instrumentation) => instrumentation,
+            None => {
+                let module = compile_with_costs(code, memory_limit, &[], costs)?;
+                return Instance::from_module(
+                    &module,
+                    backend,
+                    options.gas_limit,
+                    options.print_debug,
+                    None,
+                    None,
+                );
+            }
+        };
+
+        let code = instrumentation
+            .prepare_wasm(code)
+            .map_err(VmError::compile_err)?;
+        let module =
+            compile_with_costs(&code, memory_limit, &[instrumentation.middleware()], costs);
+        instrumentation.end_compilation();
+        let module = module?;
+        let extra_imports = vec![(
+            instrumentation.import_module(),
+            instrumentation.imports(module.store()),
+        )]
+        .into_iter()
+        .collect();
         Instance::from_module(
             &module,
             backend,
             options.gas_limit,
             options.print_debug,
-            None,
+            Some(extra_imports),
             None,
         )
     }
@@ -185,6 +277,13 @@ where
             Function::new_native_with_env(store, env.clone(), do_query_chain),
         );
 
+        // Returns the gas left for the current call, so that contracts can size batches of work.
+        // Only contracts requiring the gas_remaining capability can import this.
+        env_imports.insert(
+            "gas_remaining",
+            Function::new_native_with_env(store, env.clone(), do_gas_remaining),
+        );
+
         // Creates an iterator that will go from start to end.
         // If start_ptr == 0, the start is unbounded.
         // If end_ptr == 0, the end is unbounded.
@@ -208,6 +307,15 @@ where
             Function::new_native_with_env(store, env.clone(), do_db_next),
         );
 
+        let mut namespaces: HashMap<&str, &Exports> = HashMap::new();
+        namespaces.insert("env", &env_imports);
+        if let Some(extra_imports) = &extra_imports {
+            for (namespace, exports_obj) in extra_imports {
+                namespaces.insert(namespace, exports_obj);
+            }
+        }
+        check_imports(module, &namespaces)?;
+
         import_obj.register("env", env_imports);
 
         if let Some(extra_imports) = extra_imports {
@@ -295,9 +403,85 @@ where
                 .gas_limit
                 .saturating_sub(state.externally_used_gas)
                 .saturating_sub(gas_left),
+            peak_memory_pages: self.memory_pages() as u32,
+            memory_surcharge: state.memory_surcharge,
+            breakdown: self.env.gas_breakdown(),
         }
     }
 
+    /// Charges gas for the linear memory used beyond `surcharge.baseline_pages`. Every page
+    /// is charged once per instance, when the memory use is checked after it grew. This
+    /// happens when the contract calls into the host and when a call into the contract
+    /// returns. The call fails with out of gas if the surcharge exceeds the gas left.
+    pub fn set_memory_surcharge(&mut self, surcharge: MemorySurcharge) {
+        self.env.set_memory_surcharge(Some(surcharge));
+    }
+
+    /// Registers callbacks that observe the execution of the contract, see [`VmHooks`].
+    pub fn set_hooks(&mut self, hooks: Arc<dyn VmHooks>) {
+        self.env.set_hooks(Some(hooks));
+    }
+
+    /// Calls [`VmHooks::on_gas_warning`] once the gas used by this instance, metered
+    /// internally and externally, passes `percent` of its gas limit. This lets
+    /// frontends warn that a transaction is close to running out of gas.
+    ///
+    /// Gas used by Wasm execution is only checked when the contract calls into the host
+    /// and when a call into the contract returns. A contract that runs out of gas in
+    /// between fails without a warning.
+    ///
+    /// Panics if `percent` exceeds 100.
+    pub fn set_gas_warning_threshold(&mut self, percent: u8) {
+        assert!(percent <= 100, "Gas warning threshold must not exceed 100%");
+        self.env.set_gas_warning_threshold(Some(percent));
+    }
+
+    /// Meters queries separately from the other calls of this instance: every call of
+    /// [`call_query`](crate::call_query), including the queries the contract makes through
+    /// the querier while answering it, can use up to `limit` gas, no matter how much gas
+    /// the instance has left, and does not use up the gas of the instance. This keeps read
+    /// heavy queries from being starved by the gas limit of transactions, and lets nodes
+    /// cap the cost of public queries. Queries the contract makes in other calls, e.g. in
+    /// `execute`, still use the gas of the instance.
+    ///
+    /// The gas used by queries is reported by [`Instance::query_gas_report`] instead of
+    /// [`Instance::create_gas_report`]. Setting a limit resets the report, `None`
+    /// charges queries to the gas of the instance again.
+    pub fn set_query_gas_limit(&mut self, limit: Option<u64>) {
+        self.env.set_query_gas_limit(limit);
+    }
+
+    /// The gas used by queries since [`Instance::set_query_gas_limit`], or `None` if
+    /// queries are not metered separately.
+    pub fn query_gas_report(&self) -> Option<QueryGasReport> {
+        self.env.query_gas_report()
+    }
+
+    /// Runs `call` against a fresh gas meter with the query gas limit and restores the
+    /// gas of the instance afterwards, if queries are metered separately.
+    pub(crate) fn with_query_gas_meter<R>(
+        &mut self,
+        call: impl FnOnce(&mut Self) -> VmResult<R>,
+    ) -> VmResult<R> {
+        let limit = match self.env.query_gas_report() {
+            Some(report) => report.limit,
+            None => return call(self),
+        };
+        let gas_left = self.env.get_gas_left();
+        let gas_state = self
+            .env
+            .with_gas_state_mut(|state| std::mem::replace(state, GasState::with_limit(limit)));
+        self.env.set_gas_left(limit);
+
+        let result = call(self);
+
+        let used = limit.saturating_sub(self.env.get_gas_left());
+        self.env.set_gas_left(gas_left);
+        self.env.with_gas_state_mut(|state| *state = gas_state);
+        self.env.record_query_gas(used);
+        result
+    }
+
     /// Sets the readonly storage flag on this instance. Since one instance can be used
     /// for multiple calls in integration tests, this should be set to the desired value
     /// right before every call.
@@ -343,6 +527,11 @@ where
         Ok(())
     }
 
+    /// Cleans up after a call into the contract, e.g. removes its iterators.
+    pub(crate) fn end_call(&self) {
+        self.env.end_call()
+    }
+
     /// Calls a function exported by the instance.
     /// The function is expected to return no value. Otherwise this calls errors.
     pub(crate) fn call_function0(&self, name: &str, args: &[Val]) -> VmResult<()> {
@@ -358,6 +547,58 @@ where
 
 /// This exists only to be exported through `internals` for use by crates that are
 /// part of Cosmwasm.
+/// Compares the imports of `module` to the exports provided by the host, grouped by namespace.
+/// Returns an error listing all imports that are missing or have an incompatible type.
+fn check_imports(module: &Module, namespaces: &HashMap<&str, &Exports>) -> VmResult<()> {
+    let issues: Vec<ImportIssue> = module
+        .imports()
+        .filter_map(|import| {
+            let expected = import.ty();
+            let provided = namespaces
+                .get(import.module())
+                .and_then(|exports| exports.get_extern(import.name()))
+                .map(|export| export.ty());
+            if let Some(provided) = &provided {
+                if provided.is_compatible_with(expected) {
+                    return None;
+                }
+            }
+            Some(ImportIssue {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+                expected: describe_extern_type(expected),
+                provided: provided.as_ref().map(describe_extern_type),
+                capability: import_capability(import.module(), import.name()).map(String::from),
+            })
+        })
+        .collect();
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(VmError::import_err(issues))
+    }
+}
+
+fn describe_extern_type(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Function(function) => function.to_string(),
+        ExternType::Global(global) => format!("global {}", global),
+        ExternType::Table(table) => format!("table {}", table),
+        ExternType::Memory(memory) => format!("memory {}", memory),
+    }
+}
+
+/// The capability a host needs to support in order to provide the given import.
+/// Imports that are always provided or unknown to the VM have none.
+fn import_capability(module: &str, name: &str) -> Option<&'static str> {
+    match (module, name) {
+        ("env", "db_scan") | ("env", "db_next") => Some("iterator"),
+        ("env", "gas_remaining") => Some("gas_remaining"),
+        _ => None,
+    }
+}
+
 pub fn instance_from_module<A, S, Q>(
     module: &Module,
     backend: Backend<A, S, Q>,
@@ -382,11 +623,13 @@ mod tests {
     use crate::backend::Storage;
     use crate::calls::{call_execute, call_instantiate, call_query};
     use crate::errors::VmError;
+    use crate::hooks::GasWarning;
     use crate::testing::{
         mock_backend, mock_env, mock_info, mock_instance, mock_instance_options,
         mock_instance_with_balances, mock_instance_with_failing_api, mock_instance_with_gas_limit,
         mock_instance_with_options, MockInstanceOptions,
     };
+    use crate::wasm_backend::compile;
     use cosmwasm_std::{
         coin, coins, from_binary, AllBalanceResponse, BalanceResponse, BankQuery, Empty,
         QueryRequest,
@@ -431,6 +674,40 @@ mod tests {
         assert!(instance.required_features().contains("water"));
     }
 
+    #[test]
+    fn from_code_uses_wasm_costs() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (memory (export "memory") 1)
+            (func (export "main") (result i64)
+                i64.const 7
+                i64.const 2
+                i64.div_u)
+            )"#,
+        )
+        .unwrap();
+        let gas_used = |wasm_costs: Option<CostTable>| {
+            let (instance_options, memory_limit) = mock_instance_options();
+            let instance_options = InstanceOptions {
+                wasm_costs,
+                ..instance_options
+            };
+            let instance =
+                Instance::from_code(&wasm, mock_backend(&[]), instance_options, memory_limit)
+                    .unwrap();
+            let gas_before = instance.get_gas_left();
+            instance.call_function1("main", &[]).unwrap();
+            gas_before - instance.get_gas_left()
+        };
+
+        assert_eq!(gas_used(None), 4 * 150_000);
+        let costs = CostTable {
+            default: 10,
+            operators: vec![("I64DivU".to_string(), 1000)].into_iter().collect(),
+        };
+        assert_eq!(gas_used(Some(costs)), 3 * 10 + 1000);
+    }
+
     #[test]
     fn extra_imports_get_added() {
         let wasm = wat::parse_str(
@@ -479,6 +756,158 @@ mod tests {
         assert!(my_env.called.load(Ordering::Relaxed));
     }
 
+    #[test]
+    fn unsatisfied_imports_are_all_reported() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "db_read" (func $db_read (param i64) (result i32)))
+            (import "env" "do_magic" (func $do_magic))
+            (import "env" "debug" (func $debug (param i32)))
+            (import "foo" "bar" (func $bar))
+            (func (export "main") (call $bar))
+            )"#,
+        )
+        .unwrap();
+
+        let backend = mock_backend(&[]);
+        let (instance_options, memory_limit) = mock_instance_options();
+        let module = compile(&wasm, memory_limit, &[]).unwrap();
+        let result = Instance::from_module(
+            &module,
+            backend,
+            instance_options.gas_limit,
+            false,
+            None,
+            None,
+        );
+        match result.err().unwrap() {
+            VmError::ImportErr { issues, .. } => assert_eq!(
+                issues,
+                [
+                    ImportIssue {
+                        module: "env".to_string(),
+                        name: "db_read".to_string(),
+                        expected: "[I64] -> [I32]".to_string(),
+                        provided: Some("[I32] -> [I32]".to_string()),
+                        capability: None,
+                    },
+                    ImportIssue {
+                        module: "env".to_string(),
+                        name: "do_magic".to_string(),
+                        expected: "[] -> []".to_string(),
+                        provided: None,
+                        capability: None,
+                    },
+                    ImportIssue {
+                        module: "foo".to_string(),
+                        name: "bar".to_string(),
+                        expected: "[] -> []".to_string(),
+                        provided: None,
+                        capability: None,
+                    },
+                ]
+            ),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn gas_remaining_import_works() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "gas_remaining" (func $gas_remaining (result i64)))
+            (memory (export "memory") 1)
+            (func (export "remaining") (result i64) (call $gas_remaining))
+            )"#,
+        )
+        .unwrap();
+
+        let backend = mock_backend(&[]);
+        let (instance_options, memory_limit) = mock_instance_options();
+        let instance = Instance::from_code(&wasm, backend, instance_options, memory_limit).unwrap();
+        let remaining = instance.call_function1("remaining", &[]).unwrap();
+        let remaining = remaining.unwrap_i64() as u64;
+        // The call itself was charged before the import reads the gas left
+        assert!(remaining < instance.create_gas_report().limit);
+        assert!(remaining >= instance.get_gas_left());
+        assert_eq!(
+            import_capability("env", "gas_remaining"),
+            Some("gas_remaining")
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "iterator"))]
+    fn unsatisfied_imports_report_capability() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "db_next" (func $db_next (param i32) (result i32)))
+            )"#,
+        )
+        .unwrap();
+
+        let backend = mock_backend(&[]);
+        let (instance_options, memory_limit) = mock_instance_options();
+        let module = compile(&wasm, memory_limit, &[]).unwrap();
+        let result = Instance::from_module(
+            &module,
+            backend,
+            instance_options.gas_limit,
+            false,
+            None,
+            None,
+        );
+        match result.err().unwrap() {
+            VmError::ImportErr { issues, .. } => {
+                assert_eq!(issues.len(), 1);
+                assert_eq!(issues[0].capability.as_deref(), Some("iterator"));
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cranelift")]
+    fn call_function_works_with_multi_value() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (func $pair (export "pair") (param i32) (result i32 i32)
+                local.get 0
+                local.get 0
+                i32.const 1
+                i32.add)
+            (func (export "sum") (param i32) (result i32)
+                local.get 0
+                call $pair
+                i32.add)
+            )"#,
+        )
+        .unwrap();
+        let backend = mock_backend(&[]);
+        let (instance_options, memory_limit) = mock_instance_options();
+        let instance = Instance::from_code(&wasm, backend, instance_options, memory_limit).unwrap();
+
+        let sum = instance.call_function1("sum", &[20i32.into()]).unwrap();
+        assert_eq!(sum.i32(), Some(41));
+
+        match instance
+            .call_function1("pair", &[20i32.into()])
+            .unwrap_err()
+        {
+            VmError::ResultMismatch {
+                function_name,
+                expected,
+                actual,
+                ..
+            } => {
+                assert_eq!(function_name, "pair");
+                assert_eq!(expected, 1);
+                assert_eq!(actual, 2);
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
     #[test]
     fn call_function0_works() {
         let instance = mock_instance(CONTRACT, &[]);
@@ -689,6 +1118,8 @@ mod tests {
         assert_eq!(report1.used_internally, 0);
         assert_eq!(report1.limit, LIMIT);
         assert_eq!(report1.remaining, LIMIT);
+        assert_eq!(report1.peak_memory_pages, 17);
+        assert_eq!(report1.memory_surcharge, 0);
 
         // init contract
         let info = mock_info("creator", &coins(1000, "earth"));
@@ -705,6 +1136,127 @@ mod tests {
             report2.remaining,
             LIMIT - report2.used_externally - report2.used_internally
         );
+        assert_eq!(report2.peak_memory_pages, 18);
+        assert_eq!(report2.memory_surcharge, 0);
+    }
+
+    #[test]
+    fn set_memory_surcharge_works() {
+        const LIMIT: u64 = 700_000_000_000;
+        let mut instance = mock_instance_with_gas_limit(CONTRACT, LIMIT);
+        instance.set_memory_surcharge(MemorySurcharge {
+            baseline_pages: 18,
+            gas_per_page: 1_000_000,
+        });
+
+        // Within the baseline
+        let region_ptr = instance.allocate(10).unwrap();
+        instance.deallocate(region_ptr).unwrap();
+        let report = instance.create_gas_report();
+        assert_eq!(report.memory_surcharge, 0);
+
+        // 100 KiB require two pages beyond the baseline
+        let gas_before = instance.get_gas_left();
+        let region_ptr = instance.allocate(100 * 1024).unwrap();
+        assert_eq!(instance.memory_pages(), 20);
+        let report = instance.create_gas_report();
+        assert_eq!(report.memory_surcharge, 2_000_000);
+        assert!(gas_before - report.remaining > 2_000_000);
+
+        // Pages are only charged once
+        instance.deallocate(region_ptr).unwrap();
+        let region_ptr = instance.allocate(100 * 1024).unwrap();
+        instance.deallocate(region_ptr).unwrap();
+        assert_eq!(instance.create_gas_report().memory_surcharge, 2_000_000);
+    }
+
+    #[test]
+    fn create_gas_report_breaks_down_gas() {
+        const LIMIT: u64 = 700_000_000_000;
+        let mut instance = mock_instance_with_gas_limit(CONTRACT, LIMIT);
+        assert_eq!(
+            instance.create_gas_report().breakdown,
+            GasBreakdown::default()
+        );
+
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+
+        let report = instance.create_gas_report();
+        let breakdown = report.breakdown;
+        assert!(breakdown.storage >= report.used_externally);
+        assert!(breakdown.address > 0);
+        assert_eq!(
+            (breakdown.query, breakdown.crypto, breakdown.memory),
+            (0, 0, 0)
+        );
+        assert_eq!(
+            breakdown.wasm + breakdown.storage + breakdown.address,
+            report.used_externally + report.used_internally
+        );
+    }
+
+    #[test]
+    fn gas_depletion_in_host_function_has_breakdown() {
+        const LIMIT: u64 = 100_000_000_000;
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "secp256k1_verify" (func $verify (param i32 i32 i32) (result i32)))
+                (memory 1)
+                (export "memory" (memory 0))
+                ;; An empty region at address 0
+                (data (i32.const 0) "\10\00\00\00\00\00\00\00\00\00\00\00")
+
+                (func $nop)
+                (func $verify_empty (param i32) (result i32)
+                    (call $verify (i32.const 0) (i32.const 0) (i32.const 0)))
+                (export "interface_version_8" (func $nop))
+                (export "instantiate" (func $nop))
+                (export "allocate" (func $verify_empty))
+                (export "deallocate" (func $nop))
+            )"#,
+        )
+        .unwrap();
+        let mut instance = mock_instance_with_gas_limit(&wasm, LIMIT);
+
+        // Verification costs more than the gas limit
+        let err = instance.allocate(0).unwrap_err();
+        match &err {
+            VmError::GasDepletion {
+                breakdown: Some(breakdown),
+                ..
+            } => {
+                assert!(breakdown.wasm > 0);
+                assert_eq!(breakdown.crypto, LIMIT - breakdown.wasm);
+                assert_eq!(breakdown.storage, 0);
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("Ran out of gas during contract execution (gas used by wasm: "));
+        assert_eq!(
+            instance.create_gas_report().breakdown.crypto,
+            LIMIT - instance.create_gas_report().breakdown.wasm
+        );
+    }
+
+    #[test]
+    fn memory_surcharge_can_deplete_gas() {
+        let mut instance = mock_instance_with_gas_limit(CONTRACT, 10_000_000_000);
+        instance.set_memory_surcharge(MemorySurcharge {
+            baseline_pages: 0,
+            gas_per_page: 1_000_000_000,
+        });
+
+        match instance.allocate(10).unwrap_err() {
+            VmError::GasDepletion { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(instance.get_gas_left(), 0);
     }
 
     #[test]
@@ -914,6 +1466,54 @@ mod tests {
         assert_eq!(execute_used, 8627053606);
     }
 
+    #[derive(Default)]
+    struct RecordingHooks {
+        warnings: Mutex<Vec<GasWarning>>,
+    }
+
+    impl VmHooks for RecordingHooks {
+        fn on_gas_warning(&self, warning: &GasWarning) {
+            self.warnings.lock().unwrap().push(*warning);
+        }
+    }
+
+    #[test]
+    fn set_gas_warning_threshold_works() {
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+
+        // The limit is not approached
+        let mut instance = mock_instance(CONTRACT, &[]);
+        let hooks = Arc::new(RecordingHooks::default());
+        instance.set_hooks(hooks.clone());
+        instance.set_gas_warning_threshold(100);
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hooks.warnings.lock().unwrap().len(), 0);
+
+        // Warns once, as soon as any gas is used
+        let mut instance = mock_instance(CONTRACT, &[]);
+        let hooks = Arc::new(RecordingHooks::default());
+        instance.set_hooks(hooks.clone());
+        instance.set_gas_warning_threshold(0);
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        let warnings = hooks.warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].limit, instance.create_gas_report().limit);
+        assert_eq!(warnings[0].threshold_percent, 0);
+        assert!(warnings[0].used > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must not exceed 100%")]
+    fn set_gas_warning_threshold_panics_above_100_percent() {
+        let mut instance = mock_instance(CONTRACT, &[]);
+        instance.set_gas_warning_threshold(101);
+    }
+
     #[test]
     fn contract_enforces_gas_limit() {
         let mut instance = mock_instance_with_gas_limit(CONTRACT, 20_000);
@@ -947,4 +1547,86 @@ mod tests {
         let query_used = gas_before_query - instance.get_gas_left();
         assert_eq!(query_used, 4438350006);
     }
+
+    #[test]
+    fn query_gas_limit_works() {
+        let mut instance = mock_instance(CONTRACT, &[]);
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(instance.query_gas_report(), None);
+
+        // Queries don't use the gas of the instance
+        instance.set_query_gas_limit(Some(10_000_000_000));
+        let gas_before_query = instance.get_gas_left();
+        let breakdown_before_query = instance.create_gas_report().breakdown;
+        let msg = br#"{"verifier":{}}"#;
+        call_query(&mut instance, &mock_env(), msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(instance.get_gas_left(), gas_before_query);
+        assert_eq!(
+            instance.create_gas_report().breakdown,
+            breakdown_before_query
+        );
+        assert_eq!(
+            instance.query_gas_report(),
+            Some(QueryGasReport {
+                limit: 10_000_000_000,
+                used: 4438350006,
+                queries: 1,
+            })
+        );
+
+        // Queries exceeding the query gas limit fail, still without using the gas of
+        // the instance
+        instance.set_query_gas_limit(Some(1_000_000));
+        let err = call_query(&mut instance, &mock_env(), msg).unwrap_err();
+        assert!(matches!(err, VmError::GasDepletion { .. }));
+        assert_eq!(instance.get_gas_left(), gas_before_query);
+        let report = instance.query_gas_report().unwrap();
+        assert_eq!((report.used, report.queries), (1_000_000, 1));
+
+        // Without a limit, queries use the gas of the instance again
+        instance.set_query_gas_limit(None);
+        call_query(&mut instance, &mock_env(), msg)
+            .unwrap()
+            .unwrap();
+        assert_eq!(gas_before_query - instance.get_gas_left(), 4438350006);
+        assert_eq!(instance.query_gas_report(), None);
+    }
+
+    #[test]
+    fn query_gas_limit_charges_sub_queries_to_query() {
+        let mut instance = mock_instance(CONTRACT, &[]);
+        let request = cosmwasm_std::to_vec(&QueryRequest::<Empty>::Bank(BankQuery::AllBalances {
+            address: "someone".to_string(),
+        }))
+        .unwrap();
+        let request_ptr = instance.allocate(request.len()).unwrap();
+        instance.write_memory(request_ptr, &request).unwrap();
+        let gas_before_query = instance.get_gas_left();
+
+        // A query the contract makes while answering a query is charged to the query
+        instance.set_query_gas_limit(Some(10_000_000_000));
+        instance
+            .with_query_gas_meter(|instance| do_query_chain(&instance.env, request_ptr))
+            .unwrap();
+        assert_eq!(instance.get_gas_left(), gas_before_query);
+        let report = instance.query_gas_report().unwrap();
+        assert_eq!(report.queries, 1);
+        assert!(report.used > 0);
+
+        // and fails once it exceeds the query gas limit
+        instance.set_query_gas_limit(Some(1_000));
+        let err = instance
+            .with_query_gas_meter(|instance| do_query_chain(&instance.env, request_ptr))
+            .unwrap_err();
+        assert!(matches!(err, VmError::GasDepletion { .. }));
+        assert_eq!(instance.get_gas_left(), gas_before_query);
+        let report = instance.query_gas_report().unwrap();
+        assert_eq!((report.used, report.queries), (1_000, 1));
+    }
 }
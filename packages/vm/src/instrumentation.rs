@@ -0,0 +1,36 @@
+use std::fmt;
+use std::sync::Arc;
+
+use wasmer::{Exports, ModuleMiddleware, Store};
+
+/// A middleware that is compiled into a contract together with the host functions its
+/// instrumentation calls, e.g. the `Profiling` middleware of cosmwasm-profiler.
+///
+/// Set it as [`InstanceOptions::instrumentation`](crate::InstanceOptions::instrumentation)
+/// to run a contract through the regular entry points with the instrumentation in place.
+pub trait Instrumentation: Send + Sync {
+    /// Rewrites the Wasm before it is compiled, e.g. to declare the imports the
+    /// instrumented code calls. Called once per instance.
+    fn prepare_wasm(&self, wasm: &[u8]) -> Result<Vec<u8>, String>;
+
+    /// The middleware added to the compiler when compiling the prepared Wasm
+    fn middleware(&self) -> Arc<dyn ModuleMiddleware>;
+
+    /// Called on the compiling thread once compiling the prepared Wasm returned,
+    /// whether it succeeded or not
+    fn end_compilation(&self) {}
+
+    /// The name of the import module the instrumented code imports from
+    fn import_module(&self) -> &str;
+
+    /// Creates the host functions of [`Instrumentation::import_module`]
+    fn imports(&self, store: &Store) -> Exports;
+}
+
+impl fmt::Debug for dyn Instrumentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Instrumentation")
+            .field("import_module", &self.import_module())
+            .finish()
+    }
+}
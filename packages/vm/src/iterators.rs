@@ -0,0 +1,196 @@
+//! The iterator handles passed to contracts by `db_scan` and `db_next`
+
+use crate::backend::BackendError;
+use crate::errors::{CommunicationError, VmResult};
+use crate::hooks::LeakedIterators;
+
+/// The number of iterators a contract can create in one call. Contracts cannot close
+/// iterators, so every `db_scan` counts until the call ends.
+pub const MAX_ITERATORS_PER_CALL: usize = u16::MAX as usize;
+
+/// Maps the handles given to the contract to the iterator ids of the backend.
+///
+/// A handle consists of the generation of the table in the upper 16 bits and the
+/// position of the iterator plus one in the lower 16 bits, so the first handles of the
+/// first call are 1, 2, 3, … like the ids of the mock storage. Handles are never 0.
+/// Ending the call removes all iterators and starts a new generation, so handles
+/// kept in the contract's memory cannot reach iterators of later calls. Generations
+/// wrap around after 65536 calls.
+#[derive(Debug, Default)]
+pub struct IteratorHandles {
+    generation: u16,
+    backend_ids: Vec<u32>,
+    /// Whether `db_next` reached the end of the iterator at the same position
+    finished: Vec<bool>,
+}
+
+impl IteratorHandles {
+    pub fn new() -> Self {
+        IteratorHandles::default()
+    }
+
+    /// Fails if the contract cannot create another iterator in the current call.
+    /// This is checked before the backend creates the iterator.
+    pub fn ensure_capacity(&self) -> VmResult<()> {
+        if self.backend_ids.len() >= MAX_ITERATORS_PER_CALL {
+            return Err(CommunicationError::too_many_iterators(MAX_ITERATORS_PER_CALL).into());
+        }
+        Ok(())
+    }
+
+    /// Registers an iterator created by the backend and returns its handle.
+    pub fn insert(&mut self, backend_id: u32) -> VmResult<u32> {
+        self.ensure_capacity()?;
+        self.backend_ids.push(backend_id);
+        self.finished.push(false);
+        Ok((self.generation as u32) << 16 | self.backend_ids.len() as u32)
+    }
+
+    /// The backend id of the iterator with the given handle.
+    ///
+    /// Fails with [`CommunicationError::IteratorExpired`] for handles of other generations
+    /// and with [`BackendError::IteratorDoesNotExist`] for unknown handles of the current
+    /// generation.
+    pub fn get(&self, handle: u32) -> VmResult<u32> {
+        let index = self.index(handle)?;
+        Ok(self.backend_ids[index])
+    }
+
+    /// Marks the iterator with the given handle as read to the end. Fails like
+    /// [`IteratorHandles::get`].
+    pub fn finish(&mut self, handle: u32) -> VmResult<()> {
+        let index = self.index(handle)?;
+        self.finished[index] = true;
+        Ok(())
+    }
+
+    /// Removes all iterators at the end of a call and invalidates their handles.
+    ///
+    /// Contracts cannot close iterators, so reading an iterator only partially is allowed.
+    /// Those iterators are returned as leaked, or `None` if all iterators were read to
+    /// the end.
+    pub fn end_call(&mut self) -> Option<LeakedIterators> {
+        let created = self.backend_ids.len();
+        let leaked = self.finished.iter().filter(|finished| !**finished).count();
+        self.backend_ids.clear();
+        self.finished.clear();
+        self.generation = self.generation.wrapping_add(1);
+        if leaked == 0 {
+            return None;
+        }
+        Some(LeakedIterators { created, leaked })
+    }
+
+    fn index(&self, handle: u32) -> VmResult<usize> {
+        if (handle >> 16) as u16 != self.generation {
+            return Err(CommunicationError::iterator_expired(handle).into());
+        }
+        let position = (handle & 0xFFFF) as usize;
+        match position.checked_sub(1) {
+            Some(index) if index < self.backend_ids.len() => Ok(index),
+            _ => Err(BackendError::iterator_does_not_exist(handle).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VmError;
+
+    #[test]
+    fn insert_and_get_work() {
+        let mut handles = IteratorHandles::new();
+
+        let first = handles.insert(17).unwrap();
+        let second = handles.insert(5).unwrap();
+        assert_eq!((first, second), (1, 2));
+        assert_eq!(handles.get(first).unwrap(), 17);
+        assert_eq!(handles.get(second).unwrap(), 5);
+    }
+
+    #[test]
+    fn get_fails_for_unknown_handles() {
+        let mut handles = IteratorHandles::new();
+        handles.insert(17).unwrap();
+
+        for handle in [0, 2, 0xFFFF] {
+            match handles.get(handle).unwrap_err() {
+                VmError::BackendErr {
+                    source: BackendError::IteratorDoesNotExist { id },
+                    ..
+                } => assert_eq!(id, handle),
+                err => panic!("Unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn end_call_invalidates_handles() {
+        let mut handles = IteratorHandles::new();
+        let old = handles.insert(1).unwrap();
+        handles.end_call();
+
+        let new = handles.insert(1).unwrap();
+        assert_eq!(new, 1 << 16 | 1);
+        assert_eq!(handles.get(new).unwrap(), 1);
+        match handles.get(old).unwrap_err() {
+            VmError::CommunicationErr {
+                source: CommunicationError::IteratorExpired { handle },
+                ..
+            } => assert_eq!(handle, old),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn end_call_reports_leaked_iterators() {
+        let mut handles = IteratorHandles::new();
+        assert_eq!(handles.end_call(), None);
+
+        let first = handles.insert(1).unwrap();
+        handles.insert(2).unwrap();
+        handles.insert(3).unwrap();
+        handles.finish(first).unwrap();
+        assert_eq!(
+            handles.end_call(),
+            Some(LeakedIterators {
+                created: 3,
+                leaked: 2
+            })
+        );
+
+        let handle = handles.insert(1).unwrap();
+        handles.finish(handle).unwrap();
+        assert_eq!(handles.end_call(), None);
+        assert!(handles.finish(handle).is_err());
+    }
+
+    #[test]
+    fn end_call_wraps_generations() {
+        let mut handles = IteratorHandles::new();
+        for _ in 0..=u16::MAX {
+            handles.end_call();
+        }
+        assert_eq!(handles.insert(3).unwrap(), 1);
+    }
+
+    #[test]
+    fn insert_fails_beyond_limit() {
+        let mut handles = IteratorHandles::new();
+        for id in 0..MAX_ITERATORS_PER_CALL {
+            handles.insert(id as u32).unwrap();
+        }
+        assert_eq!(handles.get(0xFFFF).unwrap(), u16::MAX as u32 - 1);
+
+        match handles.insert(0).unwrap_err() {
+            VmError::CommunicationErr {
+                source: CommunicationError::TooManyIterators { limit },
+                ..
+            } => assert_eq!(limit, MAX_ITERATORS_PER_CALL),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        handles.end_call();
+        handles.ensure_capacity().unwrap();
+    }
+}
@@ -9,12 +9,19 @@ mod conversion;
 mod environment;
 mod errors;
 mod features;
+pub mod ffi;
+mod hooks;
 mod imports;
 mod instance;
+mod instrumentation;
+#[cfg(feature = "iterator")]
+mod iterators;
 mod limited;
 mod memory;
 mod modules;
+mod receipt;
 mod sections;
+pub mod security_tests;
 mod serde;
 mod size;
 mod static_analysis;
@@ -24,11 +31,16 @@ mod wasm_backend;
 pub use crate::backend::{
     Backend, BackendApi, BackendError, BackendResult, GasInfo, Querier, Storage,
 };
-pub use crate::cache::{AnalysisReport, Cache, CacheOptions, Metrics, Stats};
+pub use crate::cache::{
+    AnalysisReport, Cache, CacheMiddleware, CacheOptions, CumulativeStats, Metrics,
+    MigrationOutcome, MigrationProgress, MigrationReport, PerModuleMetrics, PinnedMetrics,
+    PruneReport, Stats, StatsReport,
+};
 pub use crate::calls::{
-    call_execute, call_execute_raw, call_instantiate, call_instantiate_raw, call_migrate,
-    call_migrate_raw, call_query, call_query_raw, call_reply, call_reply_raw, call_sudo,
-    call_sudo_raw,
+    call_execute, call_execute_raw, call_execute_with_receipt, call_instantiate,
+    call_instantiate_raw, call_instantiate_with_receipt, call_migrate, call_migrate_raw,
+    call_migrate_with_receipt, call_query, call_query_raw, call_reply, call_reply_raw,
+    call_reply_with_receipt, call_sudo, call_sudo_raw, call_sudo_with_receipt,
 };
 #[cfg(feature = "stargate")]
 pub use crate::calls::{
@@ -39,13 +51,24 @@ pub use crate::calls::{
 };
 pub use crate::checksum::Checksum;
 pub use crate::errors::{
-    CommunicationError, CommunicationResult, RegionValidationError, RegionValidationResult,
-    VmError, VmResult,
+    CommunicationError, CommunicationResult, ImportIssue, RegionValidationError,
+    RegionValidationResult, VmError, VmResult,
+};
+pub use crate::features::{
+    capability_profile, features_from_csv, CapabilityProfile, CAPABILITY_PROFILES, NEUTRON_V4,
+    OSMOSIS_V26, VANILLA_WASMD_0_53,
+};
+pub use crate::hooks::{GasWarning, LeakedIterators, VmHooks};
+pub use crate::instance::{
+    GasBreakdown, GasReport, Instance, InstanceOptions, MemorySurcharge, QueryGasReport,
 };
-pub use crate::features::features_from_csv;
-pub use crate::instance::{GasReport, Instance, InstanceOptions};
+pub use crate::instrumentation::Instrumentation;
+#[cfg(feature = "iterator")]
+pub use crate::iterators::MAX_ITERATORS_PER_CALL;
+pub use crate::receipt::{receipt_hash, Receipt};
 pub use crate::serde::{from_slice, to_vec};
 pub use crate::size::Size;
+pub use crate::wasm_backend::CostTable;
 
 #[doc(hidden)]
 pub mod internals {
@@ -56,5 +79,5 @@ pub mod internals {
 
     pub use crate::compatibility::check_wasm;
     pub use crate::instance::instance_from_module;
-    pub use crate::wasm_backend::{compile, make_runtime_store};
+    pub use crate::wasm_backend::{compile, compile_with_costs, make_runtime_store};
 }
@@ -1,6 +1,9 @@
-use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 
 use wasmer::{DeserializeError, Module, Store};
 
@@ -34,12 +37,25 @@ use crate::modules::current_wasmer_module_version;
 ///   Version for Wasmer 2.2.0 which contains a [module breaking change to 2.1.x](https://github.com/wasmerio/wasmer/pull/2747).
 const MODULE_SERIALIZATION_VERSION: &str = "v3";
 
+/// The advisory lock file taken by writers in every versioned directory
+const LOCK_FILE: &str = ".lock";
+
 /// Representation of a directory that contains compiled Wasm artifacts.
+///
+/// Several processes can use the same directory, e.g. a node and a query sidecar.
+/// Modules are loaded by mapping their files into memory, so all processes share the
+/// page cache of an artifact. The Universal engine still copies the compiled code into
+/// executable memory of every process. Modules are written to a temporary file that
+/// replaces the artifact atomically, so readers never see partial files and existing
+/// mappings keep their contents. On Unix, writers hold an advisory lock on the
+/// directory while writing.
 pub struct FileSystemCache {
     /// The base path this cache operates in. Within this path, versioned directories are created.
     /// A sophisticated version of this cache might be able to read multiple input versions in the future.
     base_path: PathBuf,
     wasmer_module_version: u32,
+    /// Tells apart modules compiled differently for the same versions, see [`FileSystemCache::with_variant`]
+    variant: Option<String>,
 }
 
 impl FileSystemCache {
@@ -61,6 +77,7 @@ impl FileSystemCache {
                     Ok(Self {
                         base_path: path,
                         wasmer_module_version,
+                        variant: None,
                     })
                 } else {
                     // This directory is readonly.
@@ -85,10 +102,18 @@ impl FileSystemCache {
             Ok(Self {
                 base_path: path,
                 wasmer_module_version,
+                variant: None,
             })
         }
     }
 
+    /// Stores the modules in a separate directory named after `variant`, e.g. for
+    /// modules compiled with extra middlewares. Modules of other variants are not loaded.
+    pub fn with_variant(mut self, variant: impl Into<String>) -> Self {
+        self.variant = Some(variant.into());
+        self
+    }
+
     /// Loads a serialized module from the file system and returns a module (i.e. artifact + store),
     /// along with the size of the serialized module.
     pub fn load(&self, checksum: &Checksum, store: &Store) -> VmResult<Option<Module>> {
@@ -112,29 +137,177 @@ impl FileSystemCache {
         }
     }
 
-    /// Stores a serialized module to the file system. Returns the size of the serialized module.
+    /// Stores a serialized module to the file system, replacing an existing artifact.
     pub fn store(&mut self, checksum: &Checksum, module: &Module) -> VmResult<()> {
         let modules_dir = self.latest_modules_path();
         fs::create_dir_all(&modules_dir)
             .map_err(|e| VmError::cache_err(format!("Error creating directory: {}", e)))?;
+        let serialized = module
+            .serialize()
+            .map_err(|e| VmError::cache_err(format!("Error serializing module: {}", e)))?;
+
         let filename = checksum.to_hex();
-        let path = modules_dir.join(filename);
-        module
-            .serialize_to_file(path)
+        let _lock = FileLock::exclusive(&modules_dir.join(LOCK_FILE))
+            .map_err(|e| VmError::cache_err(format!("Error locking module directory: {}", e)))?;
+        write_atomically(&modules_dir, &filename, &serialized)
             .map_err(|e| VmError::cache_err(format!("Error writing module to disk: {}", e)))?;
         Ok(())
     }
 
+    /// Removes the artifacts of all module versions that were not used since
+    /// `unused_since`, unless `keep` returns true for their checksum. Leftover temporary
+    /// files of interrupted writes are removed the same way, as well as version
+    /// directories that end up empty. Artifacts of other versions are never loaded, so
+    /// they become unused eventually. With `dry_run`, nothing is removed.
+    ///
+    /// Returns the paths of the (to be) removed files along with their sizes in bytes.
+    pub fn prune(
+        &self,
+        unused_since: SystemTime,
+        keep: impl Fn(&Checksum) -> bool + Copy,
+        dry_run: bool,
+    ) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut pruned = Vec::new();
+        for entry in fs::read_dir(&self.base_path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let dir = entry.path();
+            let _lock = FileLock::exclusive(&dir.join(LOCK_FILE))?;
+            let mut files = prune_dir(&dir, unused_since, keep, dry_run)?;
+            if !dry_run && dir != self.latest_modules_path() && only_lock_file_left(&dir)? {
+                fs::remove_file(dir.join(LOCK_FILE))?;
+                // Fails if another process wrote to the directory in the meantime
+                let _ = fs::remove_dir(&dir);
+            }
+            pruned.append(&mut files);
+        }
+        Ok(pruned)
+    }
+
     /// The path to the latest version of the modules.
     fn latest_modules_path(&self) -> PathBuf {
-        let version = format!(
+        let mut version = format!(
             "{}-wasmer{}",
             MODULE_SERIALIZATION_VERSION, self.wasmer_module_version
         );
+        if let Some(variant) = &self.variant {
+            version.push('-');
+            version.push_str(variant);
+        }
         self.base_path.join(version)
     }
 }
 
+/// Writes `data` to a temporary file in `dir` and renames it to `filename`.
+fn write_atomically(dir: &Path, filename: &str, data: &[u8]) -> io::Result<()> {
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let tmp_path = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        filename,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, dir.join(filename)));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Removes the files in `dir` that were neither modified nor accessed since
+/// `unused_since`, unless `keep` returns true for the checksum in their name. Files
+/// that are not named by a checksum are kept only if they were used, and lock files
+/// are always kept. With `dry_run`, nothing is removed.
+///
+/// Returns the paths of the (to be) removed files along with their sizes in bytes.
+pub(crate) fn prune_dir(
+    dir: &Path,
+    unused_since: SystemTime,
+    keep: impl Fn(&Checksum) -> bool,
+    dry_run: bool,
+) -> io::Result<Vec<(PathBuf, u64)>> {
+    let mut pruned = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || entry.file_name() == LOCK_FILE {
+            continue;
+        }
+        // Access times are not updated on every read with mount options like `relatime`,
+        // but at least once a day.
+        let last_used = match metadata.accessed() {
+            Ok(accessed) => accessed.max(metadata.modified()?),
+            Err(_) => metadata.modified()?,
+        };
+        if last_used >= unused_since {
+            continue;
+        }
+        let checksum = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| hex::decode(name).ok())
+            .and_then(|data| Checksum::try_from(data.as_slice()).ok());
+        if matches!(checksum, Some(checksum) if keep(&checksum)) {
+            continue;
+        }
+        let path = entry.path();
+        if !dry_run {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                // Removed concurrently, e.g. by another process pruning the same directory
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        pruned.push((path, metadata.len()));
+    }
+    Ok(pruned)
+}
+
+/// Returns true if `dir` contains nothing but its lock file.
+fn only_lock_file_left(dir: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        if entry?.file_name() != LOCK_FILE {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// An exclusive advisory lock on a file, released when dropped. Only processes taking
+/// the lock are excluded. Without `flock` on other platforms, this only opens the file.
+struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    fn exclusive(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            // Closing the file releases the lock
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(FileLock { _file: file })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -211,4 +384,103 @@ mod tests {
         );
         let _serialized_module = fs::read(file_path).unwrap();
     }
+
+    #[test]
+    fn file_system_cache_with_variant_uses_separate_path() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            unsafe { FileSystemCache::new(tmp_dir.path()).unwrap() }.with_variant("0123abcd");
+
+        let wasm = wat::parse_str(SOME_WAT).unwrap();
+        let checksum = Checksum::generate(&wasm);
+        let module = compile(&wasm, None, &[]).unwrap();
+        cache.store(&checksum, &module).unwrap();
+
+        let file_path = format!(
+            "{}/v3-wasmer1-0123abcd/{}",
+            tmp_dir.path().to_string_lossy(),
+            checksum
+        );
+        let _serialized_module = fs::read(file_path).unwrap();
+
+        // Not loaded without the variant
+        let cache = unsafe { FileSystemCache::new(tmp_dir.path()).unwrap() };
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum, &store).unwrap().is_none());
+    }
+
+    #[test]
+    fn file_system_cache_store_replaces_atomically() {
+        let tmp_dir = TempDir::new().unwrap();
+        let wasm = wat::parse_str(SOME_WAT).unwrap();
+        let checksum = Checksum::generate(&wasm);
+        let module = compile(&wasm, None, &[]).unwrap();
+
+        let mut cache = unsafe { FileSystemCache::new(tmp_dir.path()).unwrap() };
+        cache.store(&checksum, &module).unwrap();
+        // Mapped by the module, like by another process
+        let loaded = cache
+            .load(&checksum, &make_runtime_store(TESTING_MEMORY_LIMIT))
+            .unwrap()
+            .unwrap();
+
+        // Several caches on the same directory write concurrently while loading
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let path = tmp_dir.path().to_path_buf();
+                let wasm = wasm.clone();
+                std::thread::spawn(move || {
+                    let mut cache = unsafe { FileSystemCache::new(path).unwrap() };
+                    let module = compile(&wasm, None, &[]).unwrap();
+                    for _ in 0..5 {
+                        cache.store(&checksum, &module).unwrap();
+                        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+                        assert!(cache.load(&checksum, &store).unwrap().is_some());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let instance = WasmerInstance::new(&loaded, &imports! {}).unwrap();
+        set_remaining_points(&instance, TESTING_GAS_LIMIT);
+        let add_one = instance.exports.get_function("add_one").unwrap();
+        assert_eq!(add_one.call(&[1.into()]).unwrap()[0].unwrap_i32(), 2);
+
+        // Only the artifact and the lock file are left
+        let mut names: Vec<_> = fs::read_dir(cache.latest_modules_path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, [LOCK_FILE.to_string(), checksum.to_hex()]);
+    }
+
+    #[test]
+    fn file_system_cache_prune_keeps_lock_file_of_used_directories() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache = unsafe { FileSystemCache::new(tmp_dir.path()).unwrap() };
+        let old_path = tmp_dir.path().join("v1-wasmer0");
+        fs::create_dir_all(&old_path).unwrap();
+        let checksum = Checksum::generate(b"old");
+        fs::write(old_path.join(checksum.to_hex()), b"old artifact").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let now = SystemTime::now();
+
+        // A kept artifact keeps the directory locked
+        let pruned = cache.prune(now, |kept| *kept == checksum, false).unwrap();
+        assert!(pruned.is_empty());
+        let mut names: Vec<_> = fs::read_dir(&old_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, [LOCK_FILE.to_string(), checksum.to_hex()]);
+
+        let pruned = cache.prune(now, |_| false, false).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert!(!old_path.exists());
+    }
 }
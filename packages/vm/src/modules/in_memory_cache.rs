@@ -1,6 +1,5 @@
-use clru::{CLruCache, CLruCacheConfig, WeightScale};
-use std::collections::hash_map::RandomState;
-use std::num::NonZeroUsize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use wasmer::Module;
 
 use super::sized_module::SizedModule;
@@ -15,19 +14,32 @@ use crate::{Checksum, Size, VmError, VmResult};
 // Which is a very small percentage (~0.03%) of our typical cache memory budget (2 GB).
 const MINIMUM_MODULE_SIZE: Size = Size::kibi(250);
 
+/// A module in the cache and when it was used
 #[derive(Debug)]
-struct SizeScale;
-
-impl WeightScale<Checksum, SizedModule> for SizeScale {
-    #[inline]
-    fn weight(&self, _key: &Checksum, value: &SizedModule) -> usize {
-        value.size
-    }
+struct Entry {
+    module: SizedModule,
+    /// When the module was last stored or loaded. Used for time based eviction.
+    last_used: Instant,
+    /// The number of the last store or load of the module in the cache. Unlike
+    /// `last_used`, this is unique, so the least recently used module is the one
+    /// with the lowest number.
+    last_use: u64,
 }
 
 /// An in-memory module cache
+///
+/// Everything in it can be sent between threads, so that idle modules can be evicted
+/// in the background.
 pub struct InMemoryCache {
-    modules: Option<CLruCache<Checksum, SizedModule, RandomState, SizeScale>>,
+    modules: HashMap<Checksum, Entry>,
+    /// The maximum cumulative size of all modules. 0 disables the cache.
+    capacity: usize,
+    /// The cumulative size of all modules
+    size: usize,
+    /// The number of stores and loads so far
+    uses: u64,
+    /// The source of `last_used` times
+    clock: Box<dyn Fn() -> Instant + Send>,
 }
 
 impl InMemoryCache {
@@ -37,45 +49,100 @@ impl InMemoryCache {
         let preallocated_entries = size.0 / MINIMUM_MODULE_SIZE.0;
 
         InMemoryCache {
-            modules: if size.0 > 0 {
-                Some(CLruCache::with_config(
-                    CLruCacheConfig::new(NonZeroUsize::new(size.0).unwrap())
-                        .with_memory(preallocated_entries)
-                        .with_scale(SizeScale),
-                ))
-            } else {
-                None
-            },
+            modules: HashMap::with_capacity(preallocated_entries),
+            capacity: size.0,
+            size: 0,
+            uses: 0,
+            clock: Box::new(Instant::now),
         }
     }
 
-    pub fn store(&mut self, checksum: &Checksum, module: Module, size: usize) -> VmResult<()> {
-        if let Some(modules) = &mut self.modules {
-            modules
-                .put_with_weight(*checksum, SizedModule { module, size })
-                .map_err(|e| VmError::cache_err(format!("{:?}", e)))?;
+    /// Uses `clock` instead of [`Instant::now`] to tell when modules were used
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: impl Fn() -> Instant + Send + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// The current time of the clock of this cache
+    pub fn now(&self) -> Instant {
+        (self.clock)()
+    }
+
+    /// Stores a module, evicting the least recently used modules if the cache would
+    /// exceed its size otherwise. Returns the number of evicted modules.
+    pub fn store(&mut self, checksum: &Checksum, module: Module, size: usize) -> VmResult<usize> {
+        if self.capacity == 0 {
+            return Ok(0);
+        }
+        if size > self.capacity {
+            return Err(VmError::cache_err(format!(
+                "Module of size {} exceeds the memory cache size {}",
+                size, self.capacity
+            )));
+        }
+        if let Some(replaced) = self.modules.remove(checksum) {
+            self.size -= replaced.module.size;
         }
-        Ok(())
+        let mut evicted = 0;
+        while self.size + size > self.capacity {
+            let least_recently_used = self
+                .modules
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_use)
+                .map(|(checksum, _)| *checksum)
+                .expect("the size of an empty cache is 0");
+            self.remove(&least_recently_used);
+            evicted += 1;
+        }
+        let entry = Entry {
+            module: SizedModule { module, size },
+            last_used: self.now(),
+            last_use: self.next_use(),
+        };
+        self.modules.insert(*checksum, entry);
+        self.size += size;
+        Ok(evicted)
     }
 
     /// Looks up a module in the cache and creates a new module
     pub fn load(&mut self, checksum: &Checksum) -> VmResult<Option<SizedModule>> {
-        if let Some(modules) = &mut self.modules {
-            match modules.get(checksum) {
-                Some(module) => Ok(Some(module.clone())),
-                None => Ok(None),
+        let now = self.now();
+        let last_use = self.next_use();
+        match self.modules.get_mut(checksum) {
+            Some(entry) => {
+                entry.last_used = now;
+                entry.last_use = last_use;
+                Ok(Some(entry.module.clone()))
             }
-        } else {
-            Ok(None)
+            None => Ok(None),
         }
     }
 
+    /// Returns true if the module is in the cache, without marking it as used.
+    pub fn has(&self, checksum: &Checksum) -> bool {
+        self.modules.contains_key(checksum)
+    }
+
+    /// Removes all modules that were not stored or loaded within the last `ttl`.
+    /// Returns the number of removed modules.
+    pub fn evict_idle(&mut self, ttl: Duration) -> usize {
+        let now = self.now();
+        let idle: Vec<Checksum> = self
+            .modules
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.last_used) >= ttl)
+            .map(|(checksum, _)| *checksum)
+            .collect();
+        for checksum in &idle {
+            self.remove(checksum);
+        }
+        idle.len()
+    }
+
     /// Returns the number of elements in the cache.
     pub fn len(&self) -> usize {
-        self.modules
-            .as_ref()
-            .map(|modules| modules.len())
-            .unwrap_or_default()
+        self.modules.len()
     }
 
     /// Returns cumulative size of all elements in the cache.
@@ -83,10 +150,18 @@ impl InMemoryCache {
     /// This is based on the values provided with `store`. No actual
     /// memory size is measured here.
     pub fn size(&self) -> usize {
-        self.modules
-            .as_ref()
-            .map(|modules| modules.weight())
-            .unwrap_or_default()
+        self.size
+    }
+
+    fn remove(&mut self, checksum: &Checksum) {
+        if let Some(entry) = self.modules.remove(checksum) {
+            self.size -= entry.module.size;
+        }
+    }
+
+    fn next_use(&mut self) -> u64 {
+        self.uses += 1;
+        self.uses
     }
 }
 
@@ -96,6 +171,7 @@ mod tests {
     use crate::size::Size;
     use crate::wasm_backend::compile;
     use std::mem;
+    use std::sync::{Arc, Mutex};
     use wasmer::{imports, Instance as WasmerInstance};
     use wasmer_middlewares::metering::set_remaining_points;
 
@@ -212,24 +288,63 @@ mod tests {
         assert_eq!(cache.len(), 0);
 
         // Add 1
-        cache
+        let evicted = cache
             .store(&checksum1, compile(&wasm1, None, &[]).unwrap(), 900_000)
             .unwrap();
+        assert_eq!(evicted, 0);
         assert_eq!(cache.len(), 1);
 
         // Add 2
-        cache
+        let evicted = cache
             .store(&checksum2, compile(&wasm2, None, &[]).unwrap(), 900_000)
             .unwrap();
+        assert_eq!(evicted, 0);
         assert_eq!(cache.len(), 2);
 
         // Add 3 (pushes out the previous two)
-        cache
+        let evicted = cache
             .store(&checksum3, compile(&wasm3, None, &[]).unwrap(), 1_500_000)
             .unwrap();
+        assert_eq!(evicted, 2);
+        assert_eq!(cache.len(), 1);
+
+        // Replacing is no eviction
+        let evicted = cache
+            .store(&checksum3, compile(&wasm3, None, &[]).unwrap(), 1_500_000)
+            .unwrap();
+        assert_eq!(evicted, 0);
         assert_eq!(cache.len(), 1);
     }
 
+    #[test]
+    fn store_evicts_least_recently_used() {
+        let mut cache = InMemoryCache::new(Size::mebi(2));
+
+        let wasm1 = wat::parse_str(r#"(module (func (export "one")))"#).unwrap();
+        let checksum1 = Checksum::generate(&wasm1);
+        let wasm2 = wat::parse_str(r#"(module (func (export "two")))"#).unwrap();
+        let checksum2 = Checksum::generate(&wasm2);
+        let wasm3 = wat::parse_str(r#"(module (func (export "three")))"#).unwrap();
+        let checksum3 = Checksum::generate(&wasm3);
+
+        cache
+            .store(&checksum1, compile(&wasm1, None, &[]).unwrap(), 800_000)
+            .unwrap();
+        cache
+            .store(&checksum2, compile(&wasm2, None, &[]).unwrap(), 800_000)
+            .unwrap();
+        // Loading makes 1 more recently used than 2
+        cache.load(&checksum1).unwrap().unwrap();
+        let evicted = cache
+            .store(&checksum3, compile(&wasm3, None, &[]).unwrap(), 800_000)
+            .unwrap();
+        assert_eq!(evicted, 1);
+        assert!(cache.has(&checksum1));
+        assert!(!cache.has(&checksum2));
+        assert!(cache.has(&checksum3));
+        assert_eq!(cache.size(), 1_600_000);
+    }
+
     #[test]
     fn size_works() {
         let mut cache = InMemoryCache::new(Size::mebi(2));
@@ -289,4 +404,40 @@ mod tests {
             .unwrap();
         assert_eq!(cache.size(), 1_500_000);
     }
+
+    #[test]
+    fn evict_idle_works() {
+        let start = Instant::now();
+        let elapsed = Arc::new(Mutex::new(Duration::ZERO));
+        let clock = elapsed.clone();
+        let mut cache =
+            InMemoryCache::new(Size::mebi(2)).with_clock(move || start + *clock.lock().unwrap());
+
+        let wasm1 = wat::parse_str(r#"(module (func (export "one")))"#).unwrap();
+        let checksum1 = Checksum::generate(&wasm1);
+        let wasm2 = wat::parse_str(r#"(module (func (export "two")))"#).unwrap();
+        let checksum2 = Checksum::generate(&wasm2);
+
+        cache
+            .store(&checksum1, compile(&wasm1, None, &[]).unwrap(), 100_000)
+            .unwrap();
+        cache
+            .store(&checksum2, compile(&wasm2, None, &[]).unwrap(), 100_000)
+            .unwrap();
+        assert_eq!(cache.evict_idle(Duration::from_secs(60)), 0);
+        assert_eq!(cache.len(), 2);
+
+        *elapsed.lock().unwrap() = Duration::from_millis(80);
+        // Loading counts as usage
+        cache.load(&checksum2).unwrap().unwrap();
+        assert_eq!(cache.evict_idle(Duration::from_millis(40)), 1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.load(&checksum1).unwrap().is_none());
+        assert!(cache.load(&checksum2).unwrap().is_some());
+        assert_eq!(cache.size(), 100_000);
+
+        assert_eq!(cache.evict_idle(Duration::ZERO), 1);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.size(), 0);
+    }
 }
@@ -4,6 +4,7 @@ mod pinned_memory_cache;
 mod sized_module;
 mod versioning;
 
+pub(crate) use file_system_cache::prune_dir;
 pub use file_system_cache::FileSystemCache;
 pub use in_memory_cache::InMemoryCache;
 pub use pinned_memory_cache::PinnedMemoryCache;
@@ -2,11 +2,18 @@ use std::collections::HashMap;
 use wasmer::Module;
 
 use super::sized_module::SizedModule;
+use crate::cache::PerModuleMetrics;
 use crate::{Checksum, VmResult};
 
+struct PinnedModule {
+    module: SizedModule,
+    /// The number of loads since the module was pinned
+    hits: u32,
+}
+
 /// An pinned in memory module cache
 pub struct PinnedMemoryCache {
-    modules: HashMap<Checksum, SizedModule>,
+    modules: HashMap<Checksum, PinnedModule>,
 }
 
 impl PinnedMemoryCache {
@@ -18,7 +25,11 @@ impl PinnedMemoryCache {
     }
 
     pub fn store(&mut self, checksum: &Checksum, module: Module, size: usize) -> VmResult<()> {
-        self.modules.insert(*checksum, SizedModule { module, size });
+        let module = PinnedModule {
+            module: SizedModule { module, size },
+            hits: 0,
+        };
+        self.modules.insert(*checksum, module);
         Ok(())
     }
 
@@ -31,8 +42,11 @@ impl PinnedMemoryCache {
 
     /// Looks up a module in the cache and creates a new module
     pub fn load(&mut self, checksum: &Checksum) -> VmResult<Option<Module>> {
-        match self.modules.get(checksum) {
-            Some(module) => Ok(Some(module.module.clone())),
+        match self.modules.get_mut(checksum) {
+            Some(module) => {
+                module.hits = module.hits.saturating_add(1);
+                Ok(Some(module.module.module.clone()))
+            }
             None => Ok(None),
         }
     }
@@ -52,7 +66,24 @@ impl PinnedMemoryCache {
     /// This is based on the values provided with `store`. No actual
     /// memory size is measured here.
     pub fn size(&self) -> usize {
-        self.modules.iter().map(|(_, module)| module.size).sum()
+        self.modules.values().map(|module| module.module.size).sum()
+    }
+
+    /// Returns the hits and size of every module, sorted by checksum.
+    pub fn per_module_metrics(&self) -> Vec<(Checksum, PerModuleMetrics)> {
+        let mut metrics: Vec<_> = self
+            .modules
+            .iter()
+            .map(|(checksum, module)| {
+                let metrics = PerModuleMetrics {
+                    hits: module.hits,
+                    size: module.module.size,
+                };
+                (*checksum, metrics)
+            })
+            .collect();
+        metrics.sort_by_key(|(checksum, _)| *checksum);
+        metrics
     }
 }
 
@@ -224,4 +255,45 @@ mod tests {
         cache.remove(&checksum2).unwrap();
         assert_eq!(cache.size(), 0);
     }
+
+    #[test]
+    fn per_module_metrics_works() {
+        let mut cache = PinnedMemoryCache::new();
+
+        // Create module
+        let wasm = wat::parse_str(
+            r#"(module
+            (type $t0 (func (param i32) (result i32)))
+            (func $add_one (export "add_one") (type $t0) (param $p0 i32) (result i32)
+                get_local $p0
+                i32.const 1
+                i32.add)
+            )"#,
+        )
+        .unwrap();
+        let checksum = Checksum::generate(&wasm);
+
+        assert_eq!(cache.per_module_metrics(), vec![]);
+
+        // Store
+        let original = compile(&wasm, None, &[]).unwrap();
+        cache.store(&checksum, original, 500).unwrap();
+        assert_eq!(
+            cache.per_module_metrics(),
+            vec![(checksum, PerModuleMetrics { hits: 0, size: 500 })]
+        );
+
+        // Loads count as hits, misses do not
+        cache.load(&checksum).unwrap().unwrap();
+        cache.load(&checksum).unwrap().unwrap();
+        assert!(cache.load(&Checksum::generate(b"other")).unwrap().is_none());
+        assert_eq!(
+            cache.per_module_metrics(),
+            vec![(checksum, PerModuleMetrics { hits: 2, size: 500 })]
+        );
+
+        // Remove
+        cache.remove(&checksum).unwrap();
+        assert_eq!(cache.per_module_metrics(), vec![]);
+    }
 }
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::any::type_name;
+
+use cosmwasm_std::{to_canonical_json, Attribute, Binary, ContractResult, Event, Response, SubMsg};
+
+use crate::errors::{VmError, VmResult};
+
+/// The result of a contract call together with its [`receipt_hash`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receipt<U> {
+    pub result: ContractResult<Response<U>>,
+    pub hash: [u8; 32],
+}
+
+impl<U: Serialize> Receipt<U> {
+    pub fn new(result: ContractResult<Response<U>>) -> VmResult<Self> {
+        let hash = receipt_hash(&result)?;
+        Ok(Receipt { result, hash })
+    }
+}
+
+/// The parts of a call result that affect the chain, in a fixed order
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome<'a, U> {
+    Ok {
+        data: &'a Option<Binary>,
+        attributes: &'a [Attribute],
+        events: &'a [Event],
+        messages: &'a [SubMsg<U>],
+    },
+    Error(&'a str),
+}
+
+/// Returns a SHA-256 hash of the data, attributes, events and messages of a
+/// successful call or of the error message of a failed one.
+///
+/// The hash is computed over the [`to_canonical_json`] serialization, so it does not
+/// depend on the formatting or field order of the JSON emitted by the contract. This allows
+/// replay tooling to compare the outcomes of executions on different nodes without
+/// diffing full responses. Custom messages must serialize deterministically for this
+/// to hold.
+pub fn receipt_hash<U: Serialize>(result: &ContractResult<Response<U>>) -> VmResult<[u8; 32]> {
+    let outcome = match result {
+        ContractResult::Ok(response) => Outcome::Ok {
+            data: &response.data,
+            attributes: &response.attributes,
+            events: &response.events,
+            messages: &response.messages,
+        },
+        ContractResult::Err(err) => Outcome::Error(err),
+    };
+    let json = to_canonical_json(&outcome)
+        .map_err(|err| VmError::serialize_err(type_name::<Outcome<U>>(), err))?;
+    Ok(Sha256::digest(&json).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::{from_slice, to_vec};
+    use cosmwasm_std::{BankMsg, Empty};
+
+    fn response() -> Response {
+        Response::new()
+            .add_attribute("action", "release")
+            .add_event(Event::new("transfer").add_attribute("amount", "15earth"))
+            .add_message(BankMsg::Burn { amount: vec![] })
+            .set_data(b"data")
+    }
+
+    #[test]
+    fn receipt_hash_ignores_json_formatting() {
+        let json = to_vec(&ContractResult::Ok(response())).unwrap();
+        let reformatted = serde_json::to_vec_pretty(&ContractResult::Ok(response())).unwrap();
+        assert_ne!(json, reformatted);
+
+        let a: ContractResult<Response<Empty>> = from_slice(&json, usize::MAX).unwrap();
+        let b: ContractResult<Response<Empty>> = from_slice(&reformatted, usize::MAX).unwrap();
+        assert_eq!(receipt_hash(&a).unwrap(), receipt_hash(&b).unwrap());
+    }
+
+    #[test]
+    fn receipt_hash_covers_outcome() {
+        let hash = receipt_hash(&ContractResult::Ok(response())).unwrap();
+
+        let changes = vec![
+            response().set_data(b"other"),
+            response().add_attribute("extra", "1"),
+            response().add_event(Event::new("extra")),
+            response().add_message(BankMsg::Burn { amount: vec![] }),
+        ];
+        for changed in changes {
+            assert_ne!(receipt_hash(&ContractResult::Ok(changed)).unwrap(), hash);
+        }
+
+        let err: ContractResult<Response> = ContractResult::Err("failed".to_string());
+        let other_err: ContractResult<Response> = ContractResult::Err("other".to_string());
+        assert_ne!(receipt_hash(&err).unwrap(), hash);
+        assert_ne!(
+            receipt_hash(&err).unwrap(),
+            receipt_hash(&other_err).unwrap()
+        );
+    }
+
+    #[test]
+    fn receipt_new_works() {
+        let receipt = Receipt::new(ContractResult::Ok(response())).unwrap();
+        assert_eq!(receipt.result, ContractResult::Ok(response()));
+        assert_eq!(
+            receipt.hash,
+            receipt_hash(&ContractResult::Ok(response())).unwrap()
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Attacks on the VM that every integration has to contain, e.g. to check a custom
+//! [`Backend`] against the same suite this crate is tested with.
+//!
+//! Every check runs a small attack contract (see `testdata/security` for the sources)
+//! in a fresh instance and panics if the attack is not stopped with the expected
+//! error. [`check_all`] runs all of them:
+//!
+//! ```ignore
+//! use cosmwasm_vm::security_tests::check_all;
+//! use cosmwasm_vm::testing::mock_backend;
+//!
+//! check_all(|| mock_backend(&[]));
+//! ```
+
+use crate::backend::{Backend, BackendApi, Querier, Storage};
+use crate::calls::{call_execute_raw, call_query_raw};
+use crate::errors::VmError;
+use crate::instance::{Instance, InstanceOptions};
+use crate::serde::to_vec;
+use crate::size::Size;
+use crate::testing::{mock_env, mock_info};
+
+static HUGE_ALLOCATION: &[u8] = include_bytes!("../testdata/security/huge_allocation.wasm");
+static MALFORMED_REGIONS: &[u8] = include_bytes!("../testdata/security/malformed_regions.wasm");
+static INFINITE_DEALLOCATE: &[u8] = include_bytes!("../testdata/security/infinite_deallocate.wasm");
+static QUERY_BOMB: &[u8] = include_bytes!("../testdata/security/query_bomb.wasm");
+
+/// The gas limit of the attacked instances, ~0.1ms of execution
+pub const GAS_LIMIT: u64 = 100_000_000_000;
+/// The memory limit of the attacked instances
+pub const MEMORY_LIMIT: Size = Size::mebi(16);
+/// The address the query bomb sends its queries to. Queriers that route queries to
+/// contracts can register the bomb under this address to test recursive queries.
+pub const ATTACKER_ADDRESS: &str = "attacker";
+
+/// Runs all checks of this module, each with a backend created by `backend`.
+pub fn check_all<A, S, Q>(mut backend: impl FnMut() -> Backend<A, S, Q>)
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    check_huge_allocation(backend());
+    check_malformed_regions(backend());
+    check_infinite_deallocate(backend());
+    check_query_bomb(backend());
+}
+
+/// Passes a message to the contract that is larger than [`MEMORY_LIMIT`]. Allocating
+/// it must fail without growing the memory beyond the limit.
+pub fn check_huge_allocation<A, S, Q>(backend: Backend<A, S, Q>)
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    let mut instance = attacked_instance(HUGE_ALLOCATION, backend);
+    let msg = vec![b'7'; MEMORY_LIMIT.0 * 2];
+    let result = call_execute_raw(&mut instance, &env(), &info(), &msg);
+    match result {
+        Err(VmError::RuntimeErr { .. }) => {}
+        other => panic!("huge allocation: expected a runtime error, got {:?}", other),
+    }
+    let memory = instance.memory_pages() * 64 * 1024;
+    assert!(
+        memory <= MEMORY_LIMIT.0,
+        "huge allocation: memory grew to {} bytes",
+        memory
+    );
+}
+
+/// Passes regions to the host that point outside of the memory, exceed the address
+/// space or claim more bytes than they can hold. They must be rejected without
+/// touching the storage.
+pub fn check_malformed_regions<A, S, Q>(backend: Backend<A, S, Q>)
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    let mut instance = attacked_instance(MALFORMED_REGIONS, backend);
+    instance.set_storage_readonly(false);
+    for attack in [
+        "read_out_of_bounds",
+        "write_length_exceeds_capacity",
+        "write_exceeding_address_space",
+        "write_out_of_bounds",
+    ] {
+        // Errors of host functions are passed through the Wasm runtime
+        match instance.call_function0(attack, &[]) {
+            Err(VmError::RuntimeErr { msg, .. })
+                if msg.contains("Error in guest/host communication") => {}
+            other => panic!(
+                "malformed regions: expected a communication error for {}, got {:?}",
+                attack, other
+            ),
+        }
+    }
+
+    let value = instance
+        .with_storage(|storage| Ok(storage.get(b"attack").0))
+        .unwrap();
+    assert!(
+        matches!(value, Ok(None)),
+        "malformed regions: the storage was written"
+    );
+}
+
+/// Queries a contract whose `deallocate` never returns. The call must run out of gas.
+pub fn check_infinite_deallocate<A, S, Q>(backend: Backend<A, S, Q>)
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    expect_query_gas_depletion("infinite deallocate", INFINITE_DEALLOCATE, backend);
+}
+
+/// Queries a contract that sends smart queries to [`ATTACKER_ADDRESS`] in an endless
+/// loop. The call must run out of gas, also if the querier executes the queries.
+pub fn check_query_bomb<A, S, Q>(backend: Backend<A, S, Q>)
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    expect_query_gas_depletion("query bomb", QUERY_BOMB, backend);
+}
+
+/// The Wasm code of the query bomb, for queriers that want to execute its queries.
+pub fn query_bomb_code() -> &'static [u8] {
+    QUERY_BOMB
+}
+
+fn expect_query_gas_depletion<A, S, Q>(attack: &str, code: &[u8], backend: Backend<A, S, Q>)
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    let mut instance = attacked_instance(code, backend);
+    match call_query_raw(&mut instance, &env(), b"{}") {
+        Err(VmError::GasDepletion { .. }) => {}
+        other => panic!("{}: expected gas depletion, got {:?}", attack, other),
+    }
+}
+
+fn attacked_instance<A, S, Q>(code: &[u8], backend: Backend<A, S, Q>) -> Instance<A, S, Q>
+where
+    A: BackendApi + 'static,
+    S: Storage + 'static,
+    Q: Querier + 'static,
+{
+    let options = InstanceOptions {
+        gas_limit: GAS_LIMIT,
+        print_debug: false,
+        instrumentation: None,
+        wasm_costs: None,
+    };
+    Instance::from_code(code, backend, options, Some(MEMORY_LIMIT)).unwrap()
+}
+
+fn env() -> Vec<u8> {
+    to_vec(&mock_env()).unwrap()
+}
+
+fn info() -> Vec<u8> {
+    to_vec(&mock_info("attacker", &[])).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::mock_backend;
+
+    #[test]
+    fn check_all_passes_for_mock_backend() {
+        check_all(|| mock_backend(&[]));
+    }
+
+    #[test]
+    #[should_panic(expected = "harmless: expected gas depletion, got Ok")]
+    fn checks_detect_attacks_that_are_not_stopped() {
+        // infinite_deallocate.wat with a deallocate that returns
+        let wat = include_str!("../testdata/security/infinite_deallocate.wat")
+            .replace("(loop $forever\n      br $forever)", "");
+        let code = wat::parse_str(&wat).unwrap();
+        expect_query_gas_depletion("harmless", &code, mock_backend(&[]));
+    }
+}
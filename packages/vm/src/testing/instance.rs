@@ -151,6 +151,8 @@ pub fn mock_instance_with_options(
     let options = InstanceOptions {
         gas_limit: options.gas_limit,
         print_debug: options.print_debug,
+        instrumentation: None,
+        wasm_costs: None,
     };
     Instance::from_code(wasm, backend, options, memory_limit).unwrap()
 }
@@ -161,6 +163,8 @@ pub fn mock_instance_options() -> (InstanceOptions, Option<Size>) {
         InstanceOptions {
             gas_limit: DEFAULT_GAS_LIMIT,
             print_debug: DEFAULT_PRINT_DEBUG,
+            instrumentation: None,
+            wasm_costs: None,
         },
         DEFAULT_MEMORY_LIMIT,
     )
@@ -3,7 +3,7 @@ use serde::de::DeserializeOwned;
 use cosmwasm_std::testing::{MockQuerier as StdMockQuerier, MockQuerierCustomHandlerResult};
 use cosmwasm_std::{
     to_binary, to_vec, Binary, Coin, ContractResult, CustomQuery, Empty, Querier as _,
-    QueryRequest, SystemError, SystemResult,
+    QueryRequest, SystemError, SystemResult, WasmQuery,
 };
 
 use crate::{BackendError, BackendResult, GasInfo, Querier};
@@ -15,7 +15,6 @@ const GAS_COST_QUERY_REQUEST_MULTIPLIER: u64 = 0;
 const GAS_COST_QUERY_RESPONSE_MULTIPLIER: u64 = 100;
 
 /// MockQuerier holds an immutable table of bank balances
-/// TODO: also allow querying contracts
 pub struct MockQuerier<C: CustomQuery + DeserializeOwned = Empty> {
     querier: StdMockQuerier<C>,
 }
@@ -46,6 +45,14 @@ impl<C: CustomQuery + DeserializeOwned> MockQuerier<C> {
         self.querier.update_staking(denom, validators, delegations);
     }
 
+    /// Sets a handler for queries to other contracts. By default, no contract exists.
+    pub fn update_wasm<WH>(&mut self, handler: WH)
+    where
+        WH: Fn(&WasmQuery) -> cosmwasm_std::QuerierResult + 'static,
+    {
+        self.querier.update_wasm(handler)
+    }
+
     pub fn with_custom_handler<CH: 'static>(mut self, handler: CH) -> Self
     where
         CH: Fn(&C) -> MockQuerierCustomHandlerResult,
@@ -5,6 +5,7 @@ use wasmer::{Module, ModuleMiddleware};
 use crate::errors::VmResult;
 use crate::size::Size;
 
+use super::costs::CostTable;
 use super::store::make_compile_time_store;
 
 /// Compiles a given Wasm bytecode into a module.
@@ -16,7 +17,18 @@ pub fn compile(
     memory_limit: Option<Size>,
     middlewares: &[Arc<dyn ModuleMiddleware>],
 ) -> VmResult<Module> {
-    let store = make_compile_time_store(memory_limit, middlewares);
+    compile_with_costs(code, memory_limit, middlewares, &CostTable::default())
+}
+
+/// Like [`compile`], but meters the operators with the given costs instead of the
+/// default ones.
+pub fn compile_with_costs(
+    code: &[u8],
+    memory_limit: Option<Size>,
+    middlewares: &[Arc<dyn ModuleMiddleware>],
+    costs: &CostTable,
+) -> VmResult<Module> {
+    let store = make_compile_time_store(memory_limit, middlewares, costs);
     let module = Module::new(&store, code)?;
     Ok(module)
 }
@@ -32,4 +44,25 @@ mod tests {
         let err = compile(CONTRACT, None, &[]).unwrap_err();
         assert!(err.to_string().contains("Float operator detected:"));
     }
+
+    #[test]
+    fn contract_with_multi_value_compiles_with_cranelift_only() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (func $pair (result i32 i32)
+                i32.const 1
+                i32.const 2)
+            (func (export "sum") (result i32)
+                call $pair
+                i32.add)
+            )"#,
+        )
+        .unwrap();
+        let result = compile(&wasm, None, &[]);
+        if cfg!(feature = "cranelift") {
+            result.unwrap();
+        } else {
+            result.unwrap_err();
+        }
+    }
 }
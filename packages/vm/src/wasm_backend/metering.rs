@@ -0,0 +1,651 @@
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use wasmer::wasmparser::{Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType};
+use wasmer::{
+    ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::{GlobalIndex, ModuleInfo};
+
+/// A gas metering middleware that charges the same total as `wasmer_middlewares::Metering`
+/// with fewer checks.
+///
+/// `Metering` sums up the cost of every basic block and charges it right before the
+/// operator that ends the block. The end of a `block` or `loop` that is not the target of
+/// any branch is only reached by falling through, so its charge is deferred into the
+/// following block and both are charged at once. Deferred costs are charged before the
+/// next `if` at the latest, since the rest of the block may be skipped there, and before
+/// every operator that may trap, so that executions that trap are charged the same as
+/// with `Metering`: all blocks before the one that traps.
+///
+/// The globals are the same as the ones of `Metering`, so
+/// `wasmer_middlewares::metering::{get_remaining_points, set_remaining_points}` work with
+/// both.
+///
+/// Like `Metering`, an instance must only be used for a single module.
+pub struct AdaptiveMetering<F: Fn(&Operator) -> u64 + Send + Sync> {
+    initial_limit: u64,
+    cost_function: Arc<F>,
+    /// The globals for the remaining points and whether they are exhausted
+    global_indexes: Mutex<Option<(GlobalIndex, GlobalIndex)>>,
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync> AdaptiveMetering<F> {
+    pub fn new(initial_limit: u64, cost_function: F) -> Self {
+        Self {
+            initial_limit,
+            cost_function: Arc::new(cost_function),
+            global_indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync> fmt::Debug for AdaptiveMetering<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdaptiveMetering")
+            .field("initial_limit", &self.initial_limit)
+            .field("cost_function", &"<function>")
+            .field("global_indexes", &self.global_indexes)
+            .finish()
+    }
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync> MemoryUsage for AdaptiveMetering<F> {
+    fn size_of_val(&self, _tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync + 'static> ModuleMiddleware for AdaptiveMetering<F> {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let (remaining_points, points_exhausted) = self.global_indexes.lock().unwrap().expect(
+            "AdaptiveMetering::generate_function_middleware: called before transform_module_info",
+        );
+        Box::new(FunctionAdaptiveMetering {
+            cost_function: self.cost_function.clone(),
+            remaining_points,
+            points_exhausted,
+            accumulated_cost: 0,
+            deferred_cost: 0,
+            frames: Vec::new(),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("AdaptiveMetering::transform_module_info: Attempting to use an `AdaptiveMetering` middleware from multiple modules.");
+        }
+
+        let remaining_points = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I64Const(self.initial_limit as i64));
+        module_info.exports.insert(
+            "wasmer_metering_remaining_points".to_string(),
+            ExportIndex::Global(remaining_points),
+        );
+
+        let points_exhausted = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        module_info.exports.insert(
+            "wasmer_metering_points_exhausted".to_string(),
+            ExportIndex::Global(points_exhausted),
+        );
+
+        *global_indexes = Some((remaining_points, points_exhausted));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    Block,
+    Loop,
+    If,
+}
+
+/// A control frame of the function, i.e. a `block`, `loop` or `if` that is not ended yet.
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    /// Whether a branch to the end of the frame was seen. Branches to a frame are
+    /// always inside of it, so this is known when the frame ends.
+    targeted: bool,
+}
+
+struct FunctionAdaptiveMetering<F: Fn(&Operator) -> u64 + Send + Sync> {
+    cost_function: Arc<F>,
+    remaining_points: GlobalIndex,
+    points_exhausted: GlobalIndex,
+    /// The cost of the current basic block
+    accumulated_cost: u64,
+    /// The cost of the blocks before the current one that is not charged yet
+    deferred_cost: u64,
+    frames: Vec<Frame>,
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync> fmt::Debug for FunctionAdaptiveMetering<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FunctionAdaptiveMetering")
+            .field("cost_function", &"<function>")
+            .field("remaining_points", &self.remaining_points)
+            .field("points_exhausted", &self.points_exhausted)
+            .field("accumulated_cost", &self.accumulated_cost)
+            .field("deferred_cost", &self.deferred_cost)
+            .finish()
+    }
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync> FunctionAdaptiveMetering<F> {
+    /// Marks the frame `relative_depth` levels up as the target of a branch. Branches to
+    /// the function body itself are returns.
+    fn mark_target(&mut self, relative_depth: u32) {
+        let len = self.frames.len();
+        if let Some(index) = len.checked_sub(relative_depth as usize + 1) {
+            self.frames[index].targeted = true;
+        }
+    }
+
+    /// Whether the operator ends a frame that is only reached by falling through.
+    fn ends_untargeted_frame(&mut self) -> bool {
+        match self.frames.pop() {
+            Some(Frame { kind, targeted }) => kind != FrameKind::If && !targeted,
+            // The end of the function
+            None => false,
+        }
+    }
+
+    /// Charges the deferred and accumulated cost.
+    fn charge_all<'a>(&mut self, state: &mut MiddlewareReaderState<'a>) {
+        let cost = mem::take(&mut self.deferred_cost) + mem::take(&mut self.accumulated_cost);
+        self.charge(cost, state);
+    }
+
+    /// Charges the deferred cost only, leaving the current block as it is.
+    fn charge_deferred<'a>(&mut self, state: &mut MiddlewareReaderState<'a>) {
+        let cost = mem::take(&mut self.deferred_cost);
+        self.charge(cost, state);
+    }
+
+    /// The same code `Metering` injects.
+    fn charge<'a>(&self, cost: u64, state: &mut MiddlewareReaderState<'a>) {
+        if cost == 0 {
+            return;
+        }
+        let remaining_points = self.remaining_points.as_u32();
+        state.extend(&[
+            // if unsigned(globals[remaining_points]) < unsigned(cost) { throw(); }
+            Operator::GlobalGet {
+                global_index: remaining_points,
+            },
+            Operator::I64Const { value: cost as i64 },
+            Operator::I64LtU,
+            Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::I32Const { value: 1 },
+            Operator::GlobalSet {
+                global_index: self.points_exhausted.as_u32(),
+            },
+            Operator::Unreachable,
+            Operator::End,
+            // globals[remaining_points] -= cost;
+            Operator::GlobalGet {
+                global_index: remaining_points,
+            },
+            Operator::I64Const { value: cost as i64 },
+            Operator::I64Sub,
+            Operator::GlobalSet {
+                global_index: remaining_points,
+            },
+        ]);
+    }
+}
+
+impl<F: Fn(&Operator) -> u64 + Send + Sync> FunctionMiddleware for FunctionAdaptiveMetering<F> {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        // Added before charging, like in `Metering`, so that the operators ending a block
+        // are part of it
+        self.accumulated_cost += (self.cost_function)(&operator);
+
+        match &operator {
+            Operator::Block { .. } => self.frames.push(Frame {
+                kind: FrameKind::Block,
+                targeted: false,
+            }),
+            Operator::Loop { .. } => {
+                self.charge_all(state);
+                self.frames.push(Frame {
+                    kind: FrameKind::Loop,
+                    targeted: false,
+                });
+            }
+            Operator::If { .. } => {
+                self.charge_deferred(state);
+                self.frames.push(Frame {
+                    kind: FrameKind::If,
+                    targeted: false,
+                });
+            }
+            operator if may_trap(operator) => self.charge_deferred(state),
+            Operator::End => {
+                if self.ends_untargeted_frame() {
+                    self.deferred_cost += mem::take(&mut self.accumulated_cost);
+                } else {
+                    self.charge_all(state);
+                }
+            }
+            Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+                self.mark_target(*relative_depth);
+                self.charge_all(state);
+            }
+            Operator::BrTable { table } => {
+                for target in table.targets() {
+                    let (relative_depth, _) = target
+                        .map_err(|err| MiddlewareError::new("AdaptiveMetering", err.to_string()))?;
+                    self.mark_target(relative_depth);
+                }
+                self.charge_all(state);
+            }
+            Operator::Else
+            | Operator::Call { .. }
+            | Operator::CallIndirect { .. }
+            | Operator::Return => self.charge_all(state),
+            _ => {}
+        }
+
+        state.push_operator(operator);
+        Ok(())
+    }
+}
+
+/// Whether the operator may trap, not counting calls and running out of gas. Operators
+/// rejected by the `Gatekeeper`, e.g. atomics and SIMD, are not considered.
+fn may_trap(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::Unreachable
+            | Operator::I32Load { .. }
+            | Operator::I64Load { .. }
+            | Operator::F32Load { .. }
+            | Operator::F64Load { .. }
+            | Operator::I32Load8S { .. }
+            | Operator::I32Load8U { .. }
+            | Operator::I32Load16S { .. }
+            | Operator::I32Load16U { .. }
+            | Operator::I64Load8S { .. }
+            | Operator::I64Load8U { .. }
+            | Operator::I64Load16S { .. }
+            | Operator::I64Load16U { .. }
+            | Operator::I64Load32S { .. }
+            | Operator::I64Load32U { .. }
+            | Operator::I32Store { .. }
+            | Operator::I64Store { .. }
+            | Operator::F32Store { .. }
+            | Operator::F64Store { .. }
+            | Operator::I32Store8 { .. }
+            | Operator::I32Store16 { .. }
+            | Operator::I64Store8 { .. }
+            | Operator::I64Store16 { .. }
+            | Operator::I64Store32 { .. }
+            | Operator::I32DivS
+            | Operator::I32DivU
+            | Operator::I32RemS
+            | Operator::I32RemU
+            | Operator::I64DivS
+            | Operator::I64DivU
+            | Operator::I64RemS
+            | Operator::I64RemU
+            | Operator::I32TruncF32S
+            | Operator::I32TruncF32U
+            | Operator::I32TruncF64S
+            | Operator::I32TruncF64U
+            | Operator::I64TruncF32S
+            | Operator::I64TruncF32U
+            | Operator::I64TruncF64S
+            | Operator::I64TruncF64U
+            | Operator::MemoryInit { .. }
+            | Operator::MemoryCopy { .. }
+            | Operator::MemoryFill { .. }
+            | Operator::TableInit { .. }
+            | Operator::TableCopy { .. }
+            | Operator::TableGet { .. }
+            | Operator::TableSet { .. }
+            | Operator::TableFill { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmer::{imports, CompilerConfig, Cranelift, Instance, Module, Store, Universal, Value};
+    use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
+    use wasmer_middlewares::Metering;
+
+    const LIMIT: u64 = 1_000_000;
+
+    fn cost(operator: &Operator) -> u64 {
+        match operator {
+            Operator::I32Add | Operator::I32Mul => 3,
+            Operator::End => 2,
+            _ => 1,
+        }
+    }
+
+    fn instance(wasm: &[u8], middleware: Arc<dyn ModuleMiddleware>) -> Instance {
+        let mut config = Cranelift::default();
+        config.push_middleware(middleware);
+        let store = Store::new(&Universal::new(config).engine());
+        let module = Module::new(&store, wasm).unwrap();
+        Instance::new(&module, &imports! {}).unwrap()
+    }
+
+    /// Calls `function` with every argument on instances metered with `Metering` and
+    /// `AdaptiveMetering` and checks results and remaining points are the same.
+    fn assert_same_gas(wat: &str, function: &str, args: &[i32]) {
+        let wasm = wat::parse_str(wat).unwrap();
+        for arg in args {
+            let mut outcomes = Vec::new();
+            for middleware in [
+                Arc::new(Metering::new(LIMIT, cost)) as Arc<dyn ModuleMiddleware>,
+                Arc::new(AdaptiveMetering::new(LIMIT, cost)),
+            ] {
+                let instance = instance(&wasm, middleware);
+                let result = instance
+                    .exports
+                    .get_function(function)
+                    .unwrap()
+                    .call(&[Value::I32(*arg)])
+                    .map_err(|err| err.message());
+                outcomes.push((result, get_remaining_points(&instance)));
+            }
+            assert_eq!(outcomes[0], outcomes[1], "{}({})", function, arg);
+            assert_ne!(outcomes[0].1, MeteringPoints::Remaining(LIMIT));
+        }
+    }
+
+    #[test]
+    fn same_gas_for_loops() {
+        let wat = r#"(module
+            (func (export "sum") (param $n i32) (result i32)
+                (local $sum i32)
+                (block $done
+                    (loop $next
+                        local.get $n
+                        i32.eqz
+                        br_if $done
+                        local.get $sum
+                        local.get $n
+                        i32.add
+                        local.set $sum
+                        local.get $n
+                        i32.const 1
+                        i32.sub
+                        local.set $n
+                        br $next))
+                local.get $sum)
+            (func (export "countdown") (param $n i32) (result i32)
+                (loop $next
+                    local.get $n
+                    i32.const 1
+                    i32.sub
+                    local.tee $n
+                    br_if $next)
+                local.get $n))"#;
+        assert_same_gas(wat, "sum", &[0, 1, 10]);
+        assert_same_gas(wat, "countdown", &[1, 10]);
+    }
+
+    #[test]
+    fn same_gas_for_nested_blocks() {
+        let wat = r#"(module
+            (func (export "nested") (param $x i32) (result i32)
+                (block
+                    (block
+                        local.get $x
+                        i32.const 2
+                        i32.mul
+                        local.set $x)
+                    (block $skip
+                        local.get $x
+                        i32.const 10
+                        i32.gt_u
+                        br_if $skip
+                        local.get $x
+                        i32.const 7
+                        i32.add
+                        local.set $x)
+                    local.get $x
+                    i32.const 1
+                    i32.add
+                    local.set $x)
+                local.get $x)
+            (func (export "table") (param $x i32) (result i32)
+                (block $b2
+                    (block $b1
+                        (block $b0
+                            local.get $x
+                            br_table $b0 $b1 $b2)
+                        i32.const 10
+                        return)
+                    i32.const 20
+                    return)
+                i32.const 30))"#;
+        assert_same_gas(wat, "nested", &[0, 3, 20]);
+        assert_same_gas(wat, "table", &[0, 1, 2, 5]);
+    }
+
+    #[test]
+    fn same_gas_for_conditionals_and_calls() {
+        let wat = r#"(module
+            (func $double (param i32) (result i32)
+                (block
+                    local.get 0
+                    local.set 0)
+                local.get 0
+                i32.const 2
+                i32.mul)
+            (func (export "branches") (param $x i32) (result i32)
+                (block
+                    local.get $x
+                    i32.const 3
+                    i32.add
+                    local.set $x)
+                local.get $x
+                i32.const 5
+                i32.gt_u
+                (if
+                    (then
+                        (block
+                            local.get $x
+                            call $double
+                            local.set $x)
+                        local.get $x
+                        i32.const 1
+                        i32.add
+                        local.set $x))
+                (block
+                    local.get $x
+                    local.set $x)
+                local.get $x
+                (if (result i32)
+                    (then
+                        (block
+                            local.get $x
+                            local.set $x)
+                        local.get $x)
+                    (else
+                        i32.const 42)))
+            (func (export "recurse") (param $n i32) (result i32)
+                local.get $n
+                i32.eqz
+                (if (result i32)
+                    (then i32.const 0)
+                    (else
+                        (block
+                            local.get $n
+                            local.set $n)
+                        local.get $n
+                        i32.const 1
+                        i32.sub
+                        call 2
+                        local.get $n
+                        i32.add)))
+            (func (export "trap") (param $x i32) (result i32)
+                (block
+                    local.get $x
+                    local.set $x)
+                local.get $x
+                (if
+                    (then
+                        (block
+                            local.get $x
+                            local.set $x)
+                        unreachable))
+                local.get $x))"#;
+        assert_same_gas(wat, "branches", &[-3, 0, 3, 10]);
+        assert_same_gas(wat, "recurse", &[0, 1, 5]);
+        assert_same_gas(wat, "trap", &[0, 1]);
+    }
+
+    #[test]
+    fn same_gas_when_exhausted() {
+        let wat = r#"(module
+            (func (export "spin") (param $n i32) (result i32)
+                (loop $next
+                    (block
+                        local.get $n
+                        local.set $n)
+                    br $next)
+                i32.const 0))"#;
+        let wasm = wat::parse_str(wat).unwrap();
+        for middleware in [
+            Arc::new(Metering::new(LIMIT, cost)) as Arc<dyn ModuleMiddleware>,
+            Arc::new(AdaptiveMetering::new(LIMIT, cost)),
+        ] {
+            let instance = instance(&wasm, middleware);
+            let spin = instance.exports.get_function("spin").unwrap();
+            spin.call(&[Value::I32(0)]).unwrap_err();
+            assert_eq!(get_remaining_points(&instance), MeteringPoints::Exhausted);
+        }
+    }
+
+    #[test]
+    fn same_gas_for_traps() {
+        let wat = r#"(module
+            (memory 1)
+            (func (export "divide") (param $x i32) (result i32)
+                (block
+                    local.get $x
+                    local.set $x)
+                i32.const 1
+                local.get $x
+                i32.div_u)
+            (func (export "load") (param $x i32) (result i32)
+                (block
+                    local.get $x
+                    local.set $x)
+                local.get $x
+                i32.load)
+            (func (export "store") (param $x i32) (result i32)
+                (block
+                    local.get $x
+                    local.set $x)
+                local.get $x
+                local.get $x
+                i32.store
+                (block
+                    local.get $x
+                    local.set $x)
+                i32.const 7))"#;
+        assert_same_gas(wat, "divide", &[0, 2]);
+        assert_same_gas(wat, "load", &[0, 65536]);
+        assert_same_gas(wat, "store", &[0, 65533]);
+    }
+
+    /// Counts the gas checks injected by a metering middleware that runs before it.
+    #[derive(Debug, Default, MemoryUsage)]
+    struct CountChecks {
+        #[loupe(skip)]
+        checks: Arc<Mutex<usize>>,
+    }
+
+    impl ModuleMiddleware for CountChecks {
+        fn generate_function_middleware(
+            &self,
+            _: LocalFunctionIndex,
+        ) -> Box<dyn FunctionMiddleware> {
+            Box::new(FunctionCountChecks {
+                checks: self.checks.clone(),
+            })
+        }
+    }
+
+    #[derive(Debug)]
+    struct FunctionCountChecks {
+        checks: Arc<Mutex<usize>>,
+    }
+
+    impl FunctionMiddleware for FunctionCountChecks {
+        fn feed<'a>(
+            &mut self,
+            operator: Operator<'a>,
+            state: &mut MiddlewareReaderState<'a>,
+        ) -> Result<(), MiddlewareError> {
+            // Only the gas checks compare unsigned 64 bit integers in the tested code
+            if let Operator::I64LtU = operator {
+                *self.checks.lock().unwrap() += 1;
+            }
+            state.push_operator(operator);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn charges_are_deferred_past_untargeted_ends() {
+        let wat = r#"(module
+            (func (export "add") (param $x i32) (result i32)
+                (block
+                    local.get $x
+                    local.set $x)
+                (block
+                    local.get $x
+                    i32.const 1
+                    i32.add
+                    local.set $x)
+                local.get $x))"#;
+        let wasm = wat::parse_str(wat).unwrap();
+        let count_checks = |metering: Arc<dyn ModuleMiddleware>| {
+            let counter = Arc::new(CountChecks::default());
+            let mut config = Cranelift::default();
+            config.push_middleware(metering);
+            config.push_middleware(counter.clone());
+            let store = Store::new(&Universal::new(config).engine());
+            Module::new(&store, &wasm).unwrap();
+            let checks = *counter.checks.lock().unwrap();
+            checks
+        };
+        // Once at the end of every block and of the function
+        assert_eq!(count_checks(Arc::new(Metering::new(LIMIT, cost))), 3);
+        // Both blocks are charged at the end of the function
+        assert_eq!(
+            count_checks(Arc::new(AdaptiveMetering::new(LIMIT, cost))),
+            1
+        );
+        assert_same_gas(wat, "add", &[1]);
+    }
+}
@@ -1,8 +1,11 @@
 mod compile;
+mod costs;
 mod gatekeeper;
 mod limiting_tunables;
+mod metering;
 mod store;
 
-pub use compile::compile;
+pub use compile::{compile, compile_with_costs};
+pub use costs::CostTable;
 pub use limiting_tunables::LimitingTunables;
 pub use store::make_runtime_store;
@@ -8,12 +8,13 @@ use wasmer::{
     wasmparser::Operator, BaseTunables, CompilerConfig, Engine, ModuleMiddleware, Pages, Store,
     Target, Universal, WASM_PAGE_SIZE,
 };
-use wasmer_middlewares::Metering;
 
 use crate::size::Size;
 
+use super::costs::CostTable;
 use super::gatekeeper::Gatekeeper;
 use super::limiting_tunables::LimitingTunables;
+use super::metering::AdaptiveMetering;
 
 /// WebAssembly linear memory objects have sizes measured in pages. Each page
 /// is 65536 (2^16) bytes. In WebAssembly version 1, a linear memory can have at
@@ -21,25 +22,20 @@ use super::limiting_tunables::LimitingTunables;
 /// https://github.com/WebAssembly/memory64/blob/master/proposals/memory64/Overview.md
 const MAX_WASM_MEMORY: usize = 4 * 1024 * 1024 * 1024;
 
-fn cost(_operator: &Operator) -> u64 {
-    // A flat fee for each operation
-    // The target is 1 Teragas per millisecond (see GAS.md).
-    //
-    // In https://github.com/CosmWasm/cosmwasm/pull/1042 a profiler is developed to
-    // identify runtime differences between different Wasm operation, but this is not yet
-    // precise enough to derive insights from it.
-    150_000
-}
-
 /// Created a store with the default compiler and the given memory limit (in bytes).
-/// If memory_limit is None, no limit is applied.
+/// If memory_limit is None, no limit is applied. Operators are metered with `costs`.
 pub fn make_compile_time_store(
     memory_limit: Option<Size>,
     middlewares: &[Arc<dyn ModuleMiddleware>],
+    costs: &CostTable,
 ) -> Store {
     let gas_limit = 0;
     let deterministic = Arc::new(Gatekeeper::default());
-    let metering = Arc::new(Metering::new(gas_limit, cost));
+    let costs = costs.clone();
+    let metering = Arc::new(AdaptiveMetering::new(
+        gas_limit,
+        move |operator: &Operator| costs.cost(operator),
+    ));
 
     #[cfg(feature = "cranelift")]
     {
@@ -125,7 +121,7 @@ mod tests {
         let wasm = wat::parse_str(EXPORTED_MEMORY_WAT).unwrap();
 
         // No limit
-        let store = make_compile_time_store(None, &[]);
+        let store = make_compile_time_store(None, &[], &CostTable::default());
         let module = Module::new(&store, &wasm).unwrap();
         let module_memory = module.info().memories.last().unwrap();
         assert_eq!(module_memory.minimum, Pages(4));
@@ -142,7 +138,7 @@ mod tests {
         assert_eq!(instance_memory.ty().maximum, None);
 
         // Set limit
-        let store = make_compile_time_store(Some(Size::kibi(23 * 64)), &[]);
+        let store = make_compile_time_store(Some(Size::kibi(23 * 64)), &[], &CostTable::default());
         let module = Module::new(&store, &wasm).unwrap();
         let module_memory = module.info().memories.last().unwrap();
         assert_eq!(module_memory.minimum, Pages(4));
@@ -164,7 +160,7 @@ mod tests {
         // Compile
         let serialized = {
             let wasm = wat::parse_str(EXPORTED_MEMORY_WAT).unwrap();
-            let store = make_compile_time_store(None, &[]);
+            let store = make_compile_time_store(None, &[], &CostTable::default());
             let module = Module::new(&store, &wasm).unwrap();
             module.serialize().unwrap()
         };
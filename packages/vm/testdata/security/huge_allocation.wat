@@ -0,0 +1,36 @@
+;; allocate grows the memory by the requested size and aborts if that fails,
+;; like a Rust contract running out of memory.
+(module
+  (memory (export "memory") 1)
+  (func $allocate (export "allocate") (param $size i32) (result i32)
+    (local $region i32)
+    local.get $size
+    i32.const 65547
+    i32.add
+    i32.const 16
+    i32.shr_u
+    memory.grow
+    local.tee $region
+    i32.const -1
+    i32.eq
+    if
+      unreachable
+    end
+    local.get $region
+    i32.const 16
+    i32.shl
+    local.tee $region
+    local.get $region
+    i32.const 12
+    i32.add
+    i32.store
+    local.get $region
+    local.get $size
+    i32.store offset=4
+    local.get $region
+    i32.const 0
+    i32.store offset=8
+    local.get $region)
+  (func (export "deallocate") (param i32))
+  (func (export "execute") (param i32 i32 i32) (result i32)
+    unreachable))
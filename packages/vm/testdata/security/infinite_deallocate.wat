@@ -0,0 +1,48 @@
+;; query succeeds, but deallocating its result never returns.
+(module
+  (memory (export "memory") 1)
+  ;; A bump allocator that starts over when the page is full. Nothing is ever freed.
+  (global $heap (mut i32) (i32.const 8192))
+  (func $allocate (export "allocate") (param $size i32) (result i32)
+    (local $region i32)
+    global.get $heap
+    local.get $size
+    i32.add
+    i32.const 20
+    i32.add
+    i32.const 65536
+    i32.gt_u
+    if
+      i32.const 8192
+      global.set $heap
+    end
+    global.get $heap
+    local.tee $region
+    local.get $region
+    i32.const 12
+    i32.add
+    i32.store
+    local.get $region
+    local.get $size
+    i32.store offset=4
+    local.get $region
+    i32.const 0
+    i32.store offset=8
+    ;; Regions must be aligned
+    local.get $region
+    local.get $size
+    i32.add
+    i32.const 19
+    i32.add
+    i32.const -8
+    i32.and
+    global.set $heap
+    local.get $region)
+
+  (func (export "deallocate") (param i32)
+    (loop $forever
+      br $forever))
+  ;; The result region
+  (data (i32.const 1024) "\0c\04\00\00\0d\00\00\00\0d\00\00\00{\"ok\":\"e30=\"}")
+  (func (export "query") (param i32 i32) (result i32)
+    i32.const 1024))
@@ -0,0 +1,68 @@
+;; Every export passes a broken region to the host.
+(module
+  (import "env" "db_read" (func $db_read (param i32) (result i32)))
+  (import "env" "db_write" (func $db_write (param i32 i32)))
+  (memory (export "memory") 1)
+  ;; A bump allocator that starts over when the page is full. Nothing is ever freed.
+  (global $heap (mut i32) (i32.const 8192))
+  (func $allocate (export "allocate") (param $size i32) (result i32)
+    (local $region i32)
+    global.get $heap
+    local.get $size
+    i32.add
+    i32.const 20
+    i32.add
+    i32.const 65536
+    i32.gt_u
+    if
+      i32.const 8192
+      global.set $heap
+    end
+    global.get $heap
+    local.tee $region
+    local.get $region
+    i32.const 12
+    i32.add
+    i32.store
+    local.get $region
+    local.get $size
+    i32.store offset=4
+    local.get $region
+    i32.const 0
+    i32.store offset=8
+    ;; Regions must be aligned
+    local.get $region
+    local.get $size
+    i32.add
+    i32.const 19
+    i32.add
+    i32.const -8
+    i32.and
+    global.set $heap
+    local.get $region)
+
+  (func (export "deallocate") (param i32))
+  ;; The key "attack"
+  (data (i32.const 1024) "\0c\04\00\00\06\00\00\00\06\00\00\00attack")
+  ;; A region with a length greater than its capacity
+  (data (i32.const 1056) "\2c\04\00\00\04\00\00\00\08\00\00\00")
+  ;; A region exceeding the 32 bit address space
+  (data (i32.const 1088) "\00\ff\ff\ff\00\10\00\00\00\00\00\00")
+  ;; A region outside of the memory
+  (data (i32.const 1120) "\60\ea\00\00\10\27\00\00\10\27\00\00")
+  (func (export "read_out_of_bounds")
+    i32.const -16
+    call $db_read
+    drop)
+  (func (export "write_length_exceeds_capacity")
+    i32.const 1024
+    i32.const 1056
+    call $db_write)
+  (func (export "write_exceeding_address_space")
+    i32.const 1024
+    i32.const 1088
+    call $db_write)
+  (func (export "write_out_of_bounds")
+    i32.const 1024
+    i32.const 1120
+    call $db_write))
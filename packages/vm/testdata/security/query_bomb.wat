@@ -0,0 +1,53 @@
+;; query sends queries until the gas runs out. Routing the request to this
+;; contract makes every query recurse as well.
+(module
+  (import "env" "query_chain" (func $query_chain (param i32) (result i32)))
+  (memory (export "memory") 1)
+  ;; A bump allocator that starts over when the page is full. Nothing is ever freed.
+  (global $heap (mut i32) (i32.const 8192))
+  (func $allocate (export "allocate") (param $size i32) (result i32)
+    (local $region i32)
+    global.get $heap
+    local.get $size
+    i32.add
+    i32.const 20
+    i32.add
+    i32.const 65536
+    i32.gt_u
+    if
+      i32.const 8192
+      global.set $heap
+    end
+    global.get $heap
+    local.tee $region
+    local.get $region
+    i32.const 12
+    i32.add
+    i32.store
+    local.get $region
+    local.get $size
+    i32.store offset=4
+    local.get $region
+    i32.const 0
+    i32.store offset=8
+    ;; Regions must be aligned
+    local.get $region
+    local.get $size
+    i32.add
+    i32.const 19
+    i32.add
+    i32.const -8
+    i32.and
+    global.set $heap
+    local.get $region)
+
+  (func (export "deallocate") (param i32))
+  ;; The request region
+  (data (i32.const 1024) "\0c\04\00\00\3c\00\00\00\3c\00\00\00{\"wasm\":{\"smart\":{\"contract_addr\":\"attacker\",\"msg\":\"e30=\"}}}")
+  (func (export "query") (param i32 i32) (result i32)
+    (loop $again
+      i32.const 1024
+      call $query_chain
+      drop
+      br $again)
+    i32.const 0))